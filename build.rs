@@ -0,0 +1,6 @@
+fn main() {
+    // The gRPC control service is generated from its protobuf definition,
+    // which needs `protoc`; other builds skip it
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/installer.proto").expect("Failed to compile protobufs");
+}
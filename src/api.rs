@@ -0,0 +1,2036 @@
+use crate::audit::{AuditAction, AuditEntry, AuditLog, AuditQuery, AuditSource, AuditVerification};
+use crate::auth::bans::{Ban, BanList, BanSource};
+use crate::auth::{Authenticator, Principal, Session, TotpEnrollment};
+use crate::capabilities::NodeCapabilities;
+#[cfg(feature = "chaos")]
+use crate::chaos::{self, FaultSpec};
+use crate::config::{ApiConfig, Role};
+use crate::diagnose::{DiagnosticReport, Diagnostics};
+use crate::disk::encryption::EncryptedVolume;
+use crate::disk::inventory::DiskInventory;
+use crate::disk::DiskManager;
+use crate::dryrun::{DryRun, PlannedAction};
+use crate::environment::EnvironmentSnapshot;
+use crate::error::{
+    ApiError, AuthError, DiskError, Error, ErrorMessage, IsoError, MonitoringError, RemoteError,
+    Result, TransferError, UiError,
+};
+use crate::events::{self, EventBus};
+use crate::identify::{Identifier, IdentifyStatus};
+use crate::iso::cache::CacheStats;
+use crate::iso::catalog::{CatalogQuery, IsoCatalogEntry};
+use crate::iso::feeds::FeedRelease;
+use crate::iso::integrity::IntegrityReport;
+use crate::iso::sync::RepoSyncReport;
+use crate::iso::{FeedSyncReport, IsoManager};
+use crate::job::install::{self, InstallJob, InstallJobRunner};
+use crate::job::{JobHistory, JobRecord};
+use crate::monitoring::store::MetricQuery;
+use crate::monitoring::{Alert, Metric, Monitor, SafeMode};
+use crate::network::discovery::{Peer, PeerDiscovery};
+use crate::remote::keys::{AuthorizedKey, AuthorizedKeys};
+use crate::remote::serial::{SerialConsoles, SerialPortStatus};
+use crate::remote::web_vnc::WebVncLogin;
+use crate::remote::{RemoteManager, RemoteServiceStatus};
+use crate::report::{InstallStatistics, ReportFormat, ShiftReport};
+use crate::service::startup::{StartupStatus, SubsystemStatus};
+use crate::service::supervisor::{ProcessCounters, ProcessInfo, ProcessSupervisor};
+use crate::transfer::{self, FileTransfer, SharedFile};
+use crate::ui::access::AccessInfo;
+use crate::ui::installer_gui::InstallerGui;
+use crate::ui::interface::{InterfaceReport, InterfaceStatus};
+use crate::ui::messages;
+use crate::ui::wizard::{WizardAction, WizardState};
+use axum::body::Body;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{oneshot, RwLock};
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiServerState {
+    Stopped,
+    Running,
+    Error(String),
+}
+
+/// Handles shared with request handlers
+#[derive(Clone)]
+pub struct ApiContext {
+    pub disk_manager: Arc<DiskManager>,
+    pub iso_manager: Arc<IsoManager>,
+    pub identifier: Arc<Identifier>,
+    pub startup: StartupStatus,
+    pub job_history: JobHistory,
+    pub install_jobs: InstallJobRunner,
+    /// Report window when no start time is given
+    pub shift: Duration,
+    pub interface: InterfaceStatus,
+    /// Install wizard, shared with the console and touchscreen
+    pub installer_gui: InstallerGui,
+    /// Detected once at startup
+    pub capabilities: NodeCapabilities,
+    /// Self-test run by `/api/v1/diagnose`
+    pub diagnostics: Diagnostics,
+    /// Set when the node was started with `--dry-run`
+    pub dry_run: Option<DryRun>,
+    /// Other nodes on the LAN, found over mDNS
+    pub peers: PeerDiscovery,
+    /// Keys the SSH server accepts
+    pub ssh_keys: Arc<AuthorizedKeys>,
+    /// API tokens, sessions, roles and the TOTP second factor
+    pub auth: Arc<Authenticator>,
+    /// Set when WebVNC connections need a session from the API
+    pub web_vnc_login: Option<WebVncLogin>,
+    /// Addresses refused after failed sign-ins
+    pub bans: Arc<BanList>,
+    /// Serial consoles terminals attach to
+    pub serial: Arc<SerialConsoles>,
+    /// VNC, SSH and WebVNC, started and stopped one at a time
+    pub remote: Arc<RwLock<RemoteManager>>,
+    /// ISO uploads and log downloads
+    pub transfer: Arc<FileTransfer>,
+    /// Events pushed to `/api/v1/events` clients
+    pub events: EventBus,
+    /// Requests are recorded here, reads only when configured
+    pub audit: AuditLog,
+    /// Health checks and the alerts they raise
+    pub monitor: Arc<RwLock<Monitor>>,
+}
+
+pub struct ApiServer {
+    config: Arc<RwLock<ApiConfig>>,
+    state: Arc<RwLock<ApiServerState>>,
+    context: ApiContext,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl ApiServer {
+    pub fn new(config: Arc<RwLock<ApiConfig>>, context: ApiContext) -> Self {
+        Self {
+            config,
+            state: Arc::new(RwLock::new(ApiServerState::Stopped)),
+            context,
+            shutdown_tx: None,
+        }
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        let config = self.config.read().await.clone();
+        if !config.enabled {
+            info!("REST API disabled");
+            return Ok(());
+        }
+
+        let ip: IpAddr = config
+            .bind_address
+            .parse()
+            .map_err(|e| ApiError::BindFailed(format!("Invalid bind address: {}", e)))?;
+        let addr = SocketAddr::new(ip, config.port);
+
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            // `::` also accepts IPv4; fall back where IPv6 is disabled
+            Err(e) if ip == IpAddr::V6(Ipv6Addr::UNSPECIFIED) => {
+                warn!("Cannot listen on {} ({}), using IPv4 only", addr, e);
+                let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), config.port);
+                tokio::net::TcpListener::bind(addr)
+                    .await
+                    .map_err(|e| ApiError::BindFailed(format!("{}: {}", addr, e)))?
+            }
+            Err(e) => return Err(ApiError::BindFailed(format!("{}: {}", addr, e)).into()),
+        };
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let app = router(self.context.clone());
+        let state = self.state.clone();
+
+        tokio::spawn(async move {
+            let result = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(async {
+                let _ = shutdown_rx.await;
+            })
+            .await;
+
+            match result {
+                Ok(_) => *state.write().await = ApiServerState::Stopped,
+                Err(e) => {
+                    error!("REST API server failed: {}", e);
+                    *state.write().await = ApiServerState::Error(e.to_string());
+                }
+            }
+        });
+
+        self.shutdown_tx = Some(shutdown_tx);
+        self.set_state(ApiServerState::Running).await;
+        info!("REST API listening on {}", addr);
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        if let Some(tx) = self.shutdown_tx.take() {
+            info!("Stopping REST API");
+            let _ = tx.send(());
+        }
+        self.set_state(ApiServerState::Stopped).await;
+        Ok(())
+    }
+
+    pub async fn get_state(&self) -> ApiServerState {
+        self.state.read().await.clone()
+    }
+
+    async fn set_state(&self, state: ApiServerState) {
+        *self.state.write().await = state;
+    }
+
+    pub async fn health_check(&self) -> Result<()> {
+        match self.get_state().await {
+            ApiServerState::Error(e) => Err(ApiError::ServerFailed(e).into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn router(context: ApiContext) -> Router {
+    let router = Router::new()
+        .route("/api/v1/status", get(status))
+        .route("/api/v1/ui", get(ui_interface))
+        .route("/api/v1/ui/wizard", get(get_wizard).post(wizard_action))
+        .route("/api/v1/ui/language", get(get_language).put(set_language))
+        .route("/api/v1/ui/access", get(get_access))
+        .route("/api/v1/ui/access/qr.svg", get(access_qr))
+        .route("/api/v1/disks", get(list_disks))
+        .route("/api/v1/disks/:name", get(get_disk))
+        .route(
+            "/api/v1/disks/:name/approve-overwrite",
+            post(approve_overwrite),
+        )
+        .route("/api/v1/isos", get(list_isos))
+        .route("/api/v1/isos/cache", get(iso_cache))
+        .route(
+            "/api/v1/isos/:name",
+            get(get_iso)
+                .put(upload_iso)
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route("/api/v1/isos/:name/check", post(check_iso))
+        .route("/api/v1/isos/:name/pin", post(pin_iso).delete(unpin_iso))
+        .route("/api/v1/releases", get(list_releases))
+        .route("/api/v1/releases/sync", post(sync_releases))
+        .route("/api/v1/repository/sync", post(sync_repository))
+        .route("/api/v1/environment", get(environment))
+        .route("/api/v1/diagnose", get(diagnose))
+        .route("/api/v1/processes", get(list_processes))
+        .route("/api/v1/dry-run", get(dry_run_actions))
+        .route("/api/v1/peers", get(list_peers))
+        .route("/api/v1/reports/shift", get(shift_report))
+        .route(
+            "/api/v1/jobs/install",
+            get(list_install_jobs).post(start_install_job),
+        )
+        .route("/api/v1/jobs/install/queue", get(install_queue))
+        .route("/api/v1/jobs/install/history", get(install_history))
+        .route("/api/v1/jobs/install/stats", get(install_stats))
+        .route("/api/v1/jobs/install/:id", get(get_install_job))
+        .route("/api/v1/jobs/install/:id/cancel", post(cancel_install_job))
+        .route(
+            "/api/v1/jobs/install/:id/priority",
+            post(prioritize_install_job),
+        )
+        .route("/api/v1/jobs/install/:id/resume", post(resume_install_job))
+        .route(
+            "/api/v1/jobs/install/:id/restart",
+            post(restart_install_job),
+        )
+        .route("/api/v1/ssh/keys", get(list_ssh_keys).post(add_ssh_key))
+        .route("/api/v1/ssh/keys/refresh", post(refresh_ssh_keys))
+        .route("/api/v1/ssh/keys/:fingerprint", delete(remove_ssh_key))
+        .route(
+            "/api/v1/auth/session",
+            get(current_session)
+                .post(open_session)
+                .delete(close_session),
+        )
+        .route(
+            "/api/v1/auth/totp/:user",
+            post(enroll_totp).delete(remove_totp),
+        )
+        .route("/api/v1/auth/totp/:user/confirm", post(confirm_totp))
+        .route("/api/v1/auth/bans", get(list_bans))
+        .route("/api/v1/auth/bans/:address", delete(lift_ban))
+        .route("/api/v1/alerts", get(list_alerts))
+        .route("/api/v1/alerts/:id/acknowledge", post(acknowledge_alert))
+        .route("/api/v1/alerts/:id/resolve", post(resolve_alert))
+        .route("/api/v1/safe-mode", get(safe_mode).delete(leave_safe_mode))
+        .route("/api/v1/metrics/history", get(metric_history))
+        .route("/api/v1/audit", get(list_audit))
+        .route("/api/v1/audit/verify", get(verify_audit))
+        .route("/api/v1/webvnc/session", post(open_web_vnc_session))
+        .route("/api/v1/files", get(list_files))
+        .route("/api/v1/files/:share", get(download_file))
+        .route("/api/v1/files/:share/*path", get(download_file))
+        .route("/api/v1/remote/services", get(list_remote_services))
+        .route("/api/v1/remote/services/:name", get(get_remote_service))
+        .route(
+            "/api/v1/remote/services/:name/start",
+            post(start_remote_service),
+        )
+        .route(
+            "/api/v1/remote/services/:name/stop",
+            post(stop_remote_service),
+        )
+        .route(
+            "/api/v1/remote/services/:name/restart",
+            post(restart_remote_service),
+        )
+        .route("/api/v1/serial", get(list_serial_ports))
+        .route("/api/v1/serial/:name", get(serial_terminal))
+        .route("/api/v1/events", get(event_stream))
+        .route(
+            "/api/v1/identify",
+            get(identify_status)
+                .post(start_identify)
+                .delete(stop_identify),
+        );
+    #[cfg(feature = "chaos")]
+    let router = router
+        .route(
+            "/api/v1/chaos",
+            get(list_faults).post(arm_fault).delete(clear_faults),
+        )
+        .route("/api/v1/chaos/:id", delete(disarm_fault));
+    router
+        .layer(middleware::from_fn_with_state(context.clone(), guard_bans))
+        .layer(middleware::from_fn_with_state(
+            context.clone(),
+            audit_requests,
+        ))
+        .with_state(context)
+}
+
+/// Record who called what and how it ended. Outermost, so refused and
+/// banned requests are recorded too.
+async fn audit_requests(
+    State(ctx): State<ApiContext>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let read = request.method() == axum::http::Method::GET;
+    if !ctx.audit.is_enabled() || (read && !ctx.audit.records_reads()) {
+        return next.run(request).await;
+    }
+    let action = format!("{} {}", request.method(), request.uri().path());
+    let actor = ctx
+        .auth
+        .identify(bearer_token(request.headers()))
+        .await
+        .unwrap_or_else(|| "anonymous".to_string());
+    let response = next.run(request).await;
+    let status = response.status();
+    let outcome = if status.is_success() {
+        "ok".to_string()
+    } else {
+        format!("failed: {}", status)
+    };
+    ctx.audit
+        .record(
+            AuditAction::new(AuditSource::Api, actor, action)
+                .with_address(connect_info.map(|ConnectInfo(peer)| peer.ip()))
+                .with_outcome(outcome),
+        )
+        .await;
+    response
+}
+
+/// Refuse banned addresses, and count wrong tokens, passwords and codes
+/// towards a ban of the sender
+async fn guard_bans(
+    State(ctx): State<ApiContext>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(ConnectInfo(peer)) = connect_info else {
+        return next.run(request).await;
+    };
+    if let Err(e) = ctx.bans.check(peer.ip()) {
+        return ApiFailure::from(e).into_response();
+    }
+    let presented_token = !bearer_token(request.headers()).is_empty();
+    let response = next.run(request).await;
+    match response.extensions().get::<SignInFailure>() {
+        Some(SignInFailure::Credentials) => {
+            ctx.bans.record_failure(peer.ip(), BanSource::Api).await
+        }
+        // No token at all is a missing login, not a guess
+        Some(SignInFailure::Token) if presented_token => {
+            ctx.bans.record_failure(peer.ip(), BanSource::Api).await
+        }
+        _ => {}
+    }
+    response
+}
+
+#[derive(Serialize)]
+struct NodeStatus {
+    subsystems: Vec<SubsystemStatus>,
+    capabilities: NodeCapabilities,
+    safe_mode: Option<SafeMode>,
+}
+
+/// Startup state of every subsystem, degraded ones are still coming up,
+/// and what jobs the node can take
+async fn status(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<NodeStatus>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(NodeStatus {
+        subsystems: ctx.startup.snapshot().await,
+        capabilities: ctx.capabilities.clone(),
+        safe_mode: ctx.monitor.read().await.safe_mode(),
+    }))
+}
+
+/// Local interface the node presents; `null` while the UI is stopped
+async fn ui_interface(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Option<InterfaceReport>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.interface.get().await))
+}
+
+async fn get_wizard(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<WizardState>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.installer_gui.wizard_state().await))
+}
+
+/// Step through the same install wizard as the local interface; a
+/// confirmed install is queued like one from the touchscreen
+async fn wizard_action(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+    Json(action): Json<WizardAction>,
+) -> std::result::Result<Json<WizardState>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    Ok(Json(ctx.installer_gui.wizard_action(action).await?))
+}
+
+#[derive(Serialize)]
+struct LanguageStatus {
+    language: String,
+    available: Vec<&'static str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LanguageRequest {
+    language: String,
+}
+
+async fn get_language(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<LanguageStatus>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(LanguageStatus {
+        language: ctx.installer_gui.language().await,
+        available: messages::languages(),
+    }))
+}
+
+/// Switch the local interface and the wizard summaries to another
+/// language, until the UI restarts with `[ui] language`. Operator or above.
+async fn set_language(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+    Json(request): Json<LanguageRequest>,
+) -> std::result::Result<Json<LanguageStatus>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    ctx.installer_gui.set_language(&request.language).await?;
+    Ok(Json(LanguageStatus {
+        language: request.language,
+        available: messages::languages(),
+    }))
+}
+
+async fn known_access(ctx: &ApiContext) -> std::result::Result<AccessInfo, ApiFailure> {
+    ctx.installer_gui
+        .get_access()
+        .await
+        .ok_or_else(|| ApiFailure::new(StatusCode::NOT_FOUND, "The node has no address yet"))
+}
+
+/// Node URL, WebVNC address and SSH fingerprint, as in the QR code
+async fn get_access(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<AccessInfo>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(known_access(&ctx).await?))
+}
+
+/// The access QR code as an image, to show on another screen
+async fn access_qr(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Response, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    let svg = known_access(&ctx).await?.to_svg()?;
+    Ok(([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response())
+}
+
+async fn list_disks(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<DiskInventory>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.disk_manager.list_disks().await?))
+}
+
+async fn get_disk(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<DiskInventory>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.disk_manager.get_disk_inventory(&name).await?))
+}
+
+#[derive(Serialize)]
+struct OverwriteApproval {
+    device: String,
+    encrypted: Vec<EncryptedVolume>,
+    valid_for_secs: u64,
+}
+
+/// Let the next write to a disk go ahead although it holds encrypted data.
+/// Administrator only.
+async fn approve_overwrite(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<OverwriteApproval>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    let disk = ctx.disk_manager.get_disk_inventory(&name).await?;
+    let valid_for = ctx
+        .disk_manager
+        .approve_encrypted_overwrite(&disk.path)
+        .await;
+    Ok(Json(OverwriteApproval {
+        device: disk.path,
+        encrypted: disk.encrypted,
+        valid_for_secs: valid_for.as_secs(),
+    }))
+}
+
+/// Principal behind the request's bearer token, an API token or a
+/// session, when its role is at least `role`
+async fn authorize(
+    ctx: &ApiContext,
+    headers: &HeaderMap,
+    role: Role,
+) -> std::result::Result<Principal, ApiFailure> {
+    Ok(ctx.auth.authorize(bearer_token(headers), role).await?)
+}
+
+fn bearer_token(headers: &HeaderMap) -> &str {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default()
+}
+
+/// Catalogued ISOs, filtered by `distro`, `version`, `arch` and `variant`
+async fn list_isos(
+    State(ctx): State<ApiContext>,
+    Query(query): Query<CatalogQuery>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<IsoCatalogEntry>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.iso_manager.get_catalog(&query).await))
+}
+
+async fn get_iso(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<IsoCatalogEntry>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.iso_manager.get_catalog_entry(&name).await?))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct UploadQuery {
+    /// Replace an ISO of the same name
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(Serialize)]
+struct UploadedIso {
+    file: String,
+    bytes: u64,
+}
+
+/// Store the request body as an ISO in the download directory and catalog
+/// it. Operator or above.
+async fn upload_iso(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> std::result::Result<(StatusCode, Json<UploadedIso>), ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Operator).await?;
+    if !ctx.transfer.is_enabled() {
+        return Err(ApiFailure::new(
+            StatusCode::NOT_FOUND,
+            "File transfer is disabled",
+        ));
+    }
+    let name = transfer::iso_file_name(&name)?;
+    let announced = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if announced.is_some_and(|length| length > ctx.transfer.max_upload_bytes()) {
+        return Err(Error::from(TransferError::TooLarge(
+            ctx.transfer.max_upload_bytes() / (1024 * 1024),
+        ))
+        .into());
+    }
+    let Some(dir) = ctx.iso_manager.upload_dir().await else {
+        return Err(ApiFailure::new(
+            StatusCode::CONFLICT,
+            "No download directory to upload to",
+        ));
+    };
+
+    let path = dir.join(name);
+    let bytes = ctx
+        .transfer
+        .receive(body.into_data_stream(), &path, query.overwrite)
+        .await?;
+    info!(
+        "{} uploaded {} ({} MiB)",
+        principal.name,
+        name,
+        bytes / (1024 * 1024)
+    );
+    ctx.iso_manager.add_iso(path).await;
+    Ok((
+        StatusCode::CREATED,
+        Json(UploadedIso {
+            file: name.to_string(),
+            bytes,
+        }),
+    ))
+}
+
+/// Verify an ISO against its embedded checksums; slow for large images.
+/// Operator or above.
+async fn check_iso(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<IntegrityReport>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    let entry = ctx.iso_manager.get_catalog_entry(&name).await?;
+    Ok(Json(ctx.iso_manager.check_iso(&entry.path).await?))
+}
+
+/// Occupancy, quota, hit counts and contents of the download cache
+async fn iso_cache(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<CacheStats>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.iso_manager.cache_stats().await?))
+}
+
+/// Keep a downloaded ISO from being evicted. Operator or above.
+async fn pin_iso(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<StatusCode, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    let entry = ctx.iso_manager.get_catalog_entry(&name).await?;
+    ctx.iso_manager.pin_iso(&entry.file_name, true).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unpin_iso(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<StatusCode, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    let entry = ctx.iso_manager.get_catalog_entry(&name).await?;
+    ctx.iso_manager.pin_iso(&entry.file_name, false).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Releases from the vendor feeds that are newer than the local ISOs
+async fn list_releases(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<FeedRelease>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.iso_manager.get_releases().await))
+}
+
+/// Check the vendor feeds now. Operator or above.
+async fn sync_releases(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<FeedSyncReport>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    Ok(Json(ctx.iso_manager.sync_feeds().await?))
+}
+
+/// Mirror the central ISO repository now. Operator or above.
+async fn sync_repository(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<RepoSyncReport>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    Ok(Json(ctx.iso_manager.sync_repository().await?))
+}
+
+/// Tool, kernel and node versions as they would be recorded for a new job
+async fn environment(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<EnvironmentSnapshot>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(EnvironmentSnapshot::capture_async().await))
+}
+
+/// Self-test of tools, permissions, storage, network, clock, display and
+/// USB controllers
+async fn diagnose(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<DiagnosticReport>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.diagnostics.run().await))
+}
+
+#[derive(Serialize)]
+struct ProcessList {
+    running: Vec<ProcessInfo>,
+    counters: BTreeMap<String, ProcessCounters>,
+}
+
+/// External programs the node runs, and how those that ended did so
+async fn list_processes(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<ProcessList>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    let supervisor = ProcessSupervisor::global();
+    Ok(Json(ProcessList {
+        running: supervisor.processes(),
+        counters: supervisor.counters(),
+    }))
+}
+
+#[derive(Serialize)]
+struct DryRunStatus {
+    enabled: bool,
+    actions: Vec<PlannedAction>,
+}
+
+/// An injected fault and the id to disarm it by
+#[cfg(feature = "chaos")]
+#[derive(Serialize)]
+struct ArmedFault {
+    id: String,
+    #[serde(flatten)]
+    spec: FaultSpec,
+}
+
+/// Faults armed from `[chaos]` or through the API. Administrator only.
+#[cfg(feature = "chaos")]
+async fn list_faults(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<ArmedFault>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    Ok(Json(
+        chaos::armed()
+            .into_iter()
+            .map(|(id, spec)| ArmedFault { id, spec })
+            .collect(),
+    ))
+}
+
+/// Arm a fault until it is disarmed or the node restarts. Administrator
+/// only.
+#[cfg(feature = "chaos")]
+async fn arm_fault(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+    Json(spec): Json<FaultSpec>,
+) -> std::result::Result<(StatusCode, Json<ArmedFault>), ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Admin).await?;
+    let id = chaos::arm(spec.clone());
+    warn!("{} armed injected fault {}", principal.name, id);
+    Ok((StatusCode::CREATED, Json(ArmedFault { id, spec })))
+}
+
+#[cfg(feature = "chaos")]
+async fn disarm_fault(
+    State(ctx): State<ApiContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<StatusCode, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Admin).await?;
+    if !chaos::disarm(&id) {
+        return Err(ApiFailure::new(
+            StatusCode::NOT_FOUND,
+            format!("No armed fault {}", id),
+        ));
+    }
+    info!("{} disarmed injected fault {}", principal.name, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(feature = "chaos")]
+async fn clear_faults(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<StatusCode, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Admin).await?;
+    chaos::clear();
+    info!("{} disarmed all injected faults", principal.name);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Commands and writes rehearsed so far in dry-run mode
+async fn dry_run_actions(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<DryRunStatus>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(DryRunStatus {
+        enabled: ctx.dry_run.is_some(),
+        actions: ctx
+            .dry_run
+            .as_ref()
+            .map(DryRun::actions)
+            .unwrap_or_default(),
+    }))
+}
+
+/// Other nodes advertising `_usb-installer._tcp`, for fleet views
+async fn list_peers(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<Peer>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.peers.peers().await))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ShiftReportQuery {
+    /// Unix seconds; one shift before `until` when omitted
+    since: Option<u64>,
+    /// Unix seconds; now when omitted
+    until: Option<u64>,
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+/// Summary of the jobs started in the window, as JSON, CSV or HTML
+async fn shift_report(
+    State(ctx): State<ApiContext>,
+    Query(query): Query<ShiftReportQuery>,
+    headers: HeaderMap,
+) -> std::result::Result<Response, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    let until = query
+        .until
+        .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap_or_else(SystemTime::now);
+    let since = match query.since {
+        Some(secs) => SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        None => until
+            .checked_sub(ctx.shift)
+            .unwrap_or(SystemTime::UNIX_EPOCH),
+    };
+    if since > until {
+        return Err(ApiFailure::new(
+            StatusCode::BAD_REQUEST,
+            "since must not be after until",
+        ));
+    }
+
+    let records = ctx.job_history.load(since, until).await?;
+    let report = ShiftReport::build(&records, since, until);
+    let body = match query.format {
+        ReportFormat::Json => return Ok(Json(report).into_response()),
+        ReportFormat::Csv => report.to_csv(),
+        ReportFormat::Html => report.to_html(),
+    };
+    Ok(([(header::CONTENT_TYPE, query.format.content_type())], body).into_response())
+}
+
+async fn list_install_jobs(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<InstallJob>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.install_jobs.store().list().await?))
+}
+
+/// Running jobs, then queued ones in the order they will start
+async fn install_queue(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<InstallJob>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.install_jobs.queue().await))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct HistoryQuery {
+    /// Unix seconds; from the first record when omitted
+    since: Option<u64>,
+    /// Unix seconds; now when omitted
+    until: Option<u64>,
+}
+
+impl HistoryQuery {
+    /// Install records started in the window, oldest first
+    async fn installs(&self, ctx: &ApiContext) -> Result<Vec<JobRecord>> {
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let since = self.since.map_or(SystemTime::UNIX_EPOCH, at);
+        let until = self.until.map_or_else(SystemTime::now, at);
+        let mut records = ctx.job_history.load(since, until).await?;
+        records.retain(|record| record.kind == install::RECORD_KIND);
+        Ok(records)
+    }
+}
+
+/// Finished install runs with ISO, device, duration and failure reason
+async fn install_history(
+    State(ctx): State<ApiContext>,
+    Query(query): Query<HistoryQuery>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<JobRecord>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(query.installs(&ctx).await?))
+}
+
+/// Success rate and mean install time per distribution
+async fn install_stats(
+    State(ctx): State<ApiContext>,
+    Query(query): Query<HistoryQuery>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<InstallStatistics>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    let records = query.installs(&ctx).await?;
+    Ok(Json(InstallStatistics::build(&records)))
+}
+
+async fn get_install_job(
+    State(ctx): State<ApiContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<InstallJob>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.install_jobs.store().load(&id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct InstallJobRequest {
+    /// Catalogued ISO file name
+    iso: String,
+    /// Disk name or path
+    device: String,
+    #[serde(default)]
+    auto_mode: bool,
+    /// Installer to use when the ISO has several
+    installer: Option<String>,
+}
+
+/// Queue an ISO install onto a disk. Operator or above.
+async fn start_install_job(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+    Json(request): Json<InstallJobRequest>,
+) -> std::result::Result<Json<InstallJob>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    let entry = ctx.iso_manager.get_catalog_entry(&request.iso).await?;
+    let disk = ctx.disk_manager.get_disk_inventory(&request.device).await?;
+    let mut job = InstallJob::new(entry.path, &disk.path, request.auto_mode);
+    job.installer = request.installer;
+    Ok(Json(ctx.install_jobs.enqueue(job).await?))
+}
+
+/// Queue a failed, interrupted or cancelled job from its step.
+/// Operator or above.
+async fn resume_install_job(
+    State(ctx): State<ApiContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<InstallJob>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    Ok(Json(ctx.install_jobs.resume(&id).await?))
+}
+
+/// Queue a failed, interrupted or cancelled job from the start.
+/// Operator or above.
+async fn restart_install_job(
+    State(ctx): State<ApiContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<InstallJob>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    Ok(Json(ctx.install_jobs.restart(&id).await?))
+}
+
+/// Drop a queued job or stop a running one. Operator or above.
+async fn cancel_install_job(
+    State(ctx): State<ApiContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<InstallJob>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    Ok(Json(ctx.install_jobs.cancel(&id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct PriorityRequest {
+    priority: i32,
+}
+
+/// Move a queued job ahead of or behind others. Operator or above.
+async fn prioritize_install_job(
+    State(ctx): State<ApiContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<PriorityRequest>,
+) -> std::result::Result<Json<InstallJob>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    Ok(Json(
+        ctx.install_jobs.set_priority(&id, request.priority).await?,
+    ))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct IdentifyRequest {
+    /// Seconds to identify for; the configured default when omitted
+    duration_secs: Option<u64>,
+}
+
+async fn identify_status(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<IdentifyStatus>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.identifier.status().await))
+}
+
+/// Operator or above, like stopping
+async fn start_identify(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+    request: Option<Json<IdentifyRequest>>,
+) -> std::result::Result<Json<IdentifyStatus>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    Ok(Json(ctx.identifier.start(request.duration_secs).await?))
+}
+
+async fn stop_identify(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<IdentifyStatus>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    ctx.identifier.stop().await;
+    Ok(Json(ctx.identifier.status().await))
+}
+
+/// Keys the SSH server accepts and where each came from. Administrator
+/// only.
+async fn list_ssh_keys(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<AuthorizedKey>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    Ok(Json(ctx.ssh_keys.list().await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct SshKeyRequest {
+    /// `authorized_keys` line, e.g. `ssh-ed25519 AAAA... tech@laptop`
+    key: String,
+}
+
+/// Grant a key access; it works from the next login. Administrator only.
+async fn add_ssh_key(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+    Json(request): Json<SshKeyRequest>,
+) -> std::result::Result<Json<AuthorizedKey>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    Ok(Json(ctx.ssh_keys.add(&request.key).await?))
+}
+
+/// Revoke a key by its URL-encoded `SHA256:` fingerprint, even one from
+/// the config or GitHub. Administrator only.
+async fn remove_ssh_key(
+    State(ctx): State<ApiContext>,
+    Path(fingerprint): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<StatusCode, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    ctx.ssh_keys.remove(&fingerprint).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Fetch the key URLs and GitHub users again. Administrator only.
+async fn refresh_ssh_keys(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<AuthorizedKey>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    Ok(Json(ctx.ssh_keys.provision().await?))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SessionRequest {
+    /// Required once the token's user has enrolled TOTP
+    code: Option<String>,
+}
+
+/// Exchange an API token, plus a code from the user's authenticator app
+/// once enrolled, for a session token with the token's role
+async fn open_session(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+    request: Option<Json<SessionRequest>>,
+) -> std::result::Result<Json<Session>, ApiFailure> {
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    Ok(Json(
+        ctx.auth
+            .open_session(bearer_token(&headers), request.code.as_deref())
+            .await?,
+    ))
+}
+
+/// Who the request's token belongs to and its role
+async fn current_session(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Principal>, ApiFailure> {
+    Ok(Json(authorize(&ctx, &headers, Role::Viewer).await?))
+}
+
+/// Sign out; the session token stops working
+async fn close_session(State(ctx): State<ApiContext>, headers: HeaderMap) -> StatusCode {
+    ctx.auth.close_session(bearer_token(&headers)).await;
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Debug, Deserialize)]
+struct CodeRequest {
+    code: String,
+}
+
+/// New TOTP secret for a user, an API token's name or the WebVNC
+/// username, with a QR code for the authenticator app. Administrator only.
+async fn enroll_totp(
+    State(ctx): State<ApiContext>,
+    Path(user): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<TotpEnrollment>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    Ok(Json(ctx.auth.two_factor().enroll(&user).await?))
+}
+
+/// Activate an enrollment with a code from the app; from then on the user
+/// needs a code to sign in. Administrator only.
+async fn confirm_totp(
+    State(ctx): State<ApiContext>,
+    Path(user): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<CodeRequest>,
+) -> std::result::Result<StatusCode, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    ctx.auth.two_factor().confirm(&user, &request.code).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Drop a user's secret, e.g. after a lost phone. Administrator only.
+async fn remove_totp(
+    State(ctx): State<ApiContext>,
+    Path(user): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<StatusCode, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    ctx.auth.two_factor().remove(&user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Addresses banned after failed sign-ins. Administrator only.
+async fn list_bans(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<Ban>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    Ok(Json(ctx.bans.bans()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AlertQuery {
+    resolved: Option<bool>,
+}
+
+/// Alerts, each once with how often it occurred; `resolved=false` lists
+/// the open ones
+async fn list_alerts(
+    State(ctx): State<ApiContext>,
+    Query(query): Query<AlertQuery>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<Alert>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    let monitor = ctx.monitor.read().await;
+    Ok(Json(monitor.get_alerts(query.resolved).await))
+}
+
+/// Stop an open alert from being sent to the notifiers again (operator)
+async fn acknowledge_alert(
+    State(ctx): State<ApiContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Alert>, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Operator).await?;
+    let monitor = ctx.monitor.read().await;
+    Ok(Json(monitor.acknowledge_alert(&id, &principal.name).await?))
+}
+
+/// Close an alert by hand (operator)
+async fn resolve_alert(
+    State(ctx): State<ApiContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Alert>, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Operator).await?;
+    let alert = ctx.monitor.read().await.resolve_alert(&id).await?;
+    info!("{} resolved alert {}", principal.name, id);
+    Ok(Json(alert))
+}
+
+/// Why the node is in safe mode; `null` in normal operation
+async fn safe_mode(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Option<SafeMode>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.monitor.read().await.safe_mode()))
+}
+
+/// Leave safe mode and resume installs (operator). Returns the safe mode
+/// that was left.
+async fn leave_safe_mode(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Option<SafeMode>>, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Operator).await?;
+    let left = ctx.monitor.read().await.leave_safe_mode().await;
+    if left.is_some() {
+        info!("{} left safe mode", principal.name);
+    }
+    Ok(Json(left))
+}
+
+/// Samples of the metric `name` between `since` and `until` (Unix
+/// seconds), optionally only those with `labels=key=value,...`
+async fn metric_history(
+    State(ctx): State<ApiContext>,
+    Query(query): Query<MetricQuery>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<Metric>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    let monitor = ctx.monitor.read().await;
+    Ok(Json(monitor.get_metric_history(&query).await))
+}
+
+/// Audit log entries filtered by `since`, `source`, `actor` and `limit`.
+/// Administrator only.
+async fn list_audit(
+    State(ctx): State<ApiContext>,
+    Query(query): Query<AuditQuery>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<AuditEntry>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    Ok(Json(ctx.audit.entries(&query).await?))
+}
+
+/// Check the audit log's hash chain. Administrator only.
+async fn verify_audit(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<AuditVerification>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Admin).await?;
+    Ok(Json(ctx.audit.verify().await?))
+}
+
+async fn lift_ban(
+    State(ctx): State<ApiContext>,
+    Path(address): Path<IpAddr>,
+    headers: HeaderMap,
+) -> std::result::Result<StatusCode, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Admin).await?;
+    if !ctx.bans.lift(address).await {
+        return Err(ApiFailure::new(
+            StatusCode::NOT_FOUND,
+            format!("{} is not banned", address),
+        ));
+    }
+    info!("{} lifted the ban of {}", principal.name, address);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct WebVncSessionRequest {
+    username: String,
+    password: String,
+    /// Required once the user has enrolled TOTP
+    #[serde(default)]
+    code: Option<String>,
+}
+
+#[derive(Serialize)]
+struct WebVncSession {
+    token: String,
+    /// noVNC page with the token filled in, relative to the WebVNC port
+    path: String,
+    expires_in_secs: u64,
+}
+
+/// WebVNC token for an operator's API token or session, or for the
+/// WebVNC password plus, when enrolled, a TOTP code
+async fn open_web_vnc_session(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+    request: Option<Json<WebVncSessionRequest>>,
+) -> std::result::Result<Json<WebVncSession>, ApiFailure> {
+    let Some(login) = &ctx.web_vnc_login else {
+        return Err(ApiFailure::new(
+            StatusCode::NOT_FOUND,
+            "WebVNC does not require signing in",
+        ));
+    };
+    match request {
+        Some(Json(request)) if bearer_token(&headers).is_empty() => {
+            let two_factor = ctx.auth.two_factor();
+            let user = request.username.as_str();
+            two_factor.check_locked(user).await?;
+            if !login.check_password(user, &request.password) {
+                return Err(Error::from(two_factor.record_failure(user).await).into());
+            }
+            if two_factor.is_required(user).await {
+                let code = request.code.as_deref().unwrap_or_default();
+                two_factor.verify(user, code).await?;
+            }
+        }
+        _ => {
+            authorize(&ctx, &headers, Role::Operator).await?;
+        }
+    }
+
+    let token = login.issue();
+    Ok(Json(WebVncSession {
+        path: format!("vnc.html?path=websockify%3Ftoken%3D{}", token),
+        token,
+        expires_in_secs: login.session_timeout().as_secs(),
+    }))
+}
+
+/// Files in the download shares. Operator or above.
+async fn list_files(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<SharedFile>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Operator).await?;
+    Ok(Json(ctx.transfer.list().await))
+}
+
+#[derive(Debug, Deserialize)]
+struct SharePath {
+    share: String,
+    /// Empty when the share is a single file
+    #[serde(default)]
+    path: String,
+}
+
+/// A file from a share, streamed as an attachment. Operator or above.
+async fn download_file(
+    State(ctx): State<ApiContext>,
+    Path(SharePath { share, path }): Path<SharePath>,
+    headers: HeaderMap,
+) -> std::result::Result<Response, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Operator).await?;
+    let file_path = ctx.transfer.resolve(&share, &path).await?;
+    let file = tokio::fs::File::open(&file_path)
+        .await
+        .map_err(|e| Error::from(TransferError::IoError(e.to_string())))?;
+    let size = file
+        .metadata()
+        .await
+        .map_err(|e| Error::from(TransferError::IoError(e.to_string())))?
+        .len();
+    let name = file_path
+        .file_name()
+        .map(|name| name.to_string_lossy().replace('"', ""))
+        .unwrap_or_default();
+    info!("{} downloads {}", principal.name, file_path.display());
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", name),
+            ),
+        ],
+        Body::from_stream(transfer::read_chunks(file)),
+    )
+        .into_response())
+}
+
+/// VNC, SSH and WebVNC with their ports, running or not
+async fn list_remote_services(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<RemoteServiceStatus>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.remote.read().await.services().await))
+}
+
+async fn get_remote_service(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<RemoteServiceStatus>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.remote.read().await.service(&name).await?))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RemoteServiceRequest {
+    /// Port to listen on from now until the next config reload
+    port: Option<u16>,
+}
+
+/// Start a stopped service, on a new port if given. Administrator only.
+async fn start_remote_service(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    request: Option<Json<RemoteServiceRequest>>,
+) -> std::result::Result<Json<RemoteServiceStatus>, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Admin).await?;
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    let mut remote = ctx.remote.write().await;
+    remote.start_service(&name, request.port).await?;
+    info!("{} started remote service {}", principal.name, name);
+    Ok(Json(remote.service(&name).await?))
+}
+
+/// Administrator only
+async fn stop_remote_service(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<RemoteServiceStatus>, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Admin).await?;
+    let mut remote = ctx.remote.write().await;
+    remote.stop_service(&name).await?;
+    info!("{} stopped remote service {}", principal.name, name);
+    Ok(Json(remote.service(&name).await?))
+}
+
+/// Restart a service, moving it to a new port if given, without touching
+/// the others. Administrator only.
+async fn restart_remote_service(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    request: Option<Json<RemoteServiceRequest>>,
+) -> std::result::Result<Json<RemoteServiceStatus>, ApiFailure> {
+    let principal = authorize(&ctx, &headers, Role::Admin).await?;
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    let mut remote = ctx.remote.write().await;
+    remote.restart_service(&name, request.port).await?;
+    info!("{} restarted remote service {}", principal.name, name);
+    Ok(Json(remote.service(&name).await?))
+}
+
+/// Configured serial consoles, whether they are open and how many
+/// terminals are attached
+async fn list_serial_ports(
+    State(ctx): State<ApiContext>,
+    headers: HeaderMap,
+) -> std::result::Result<Json<Vec<SerialPortStatus>>, ApiFailure> {
+    authorize(&ctx, &headers, Role::Viewer).await?;
+    Ok(Json(ctx.serial.ports().await))
+}
+
+#[derive(Debug, Deserialize)]
+struct SocketQuery {
+    /// Browsers cannot set headers on WebSockets
+    #[serde(default)]
+    token: Option<String>,
+}
+
+impl SocketQuery {
+    /// `?token=`, or the bearer token for clients that can set headers
+    fn token<'a>(&'a self, headers: &'a HeaderMap) -> &'a str {
+        match self.token.as_deref() {
+            Some(token) if !token.is_empty() => token,
+            _ => bearer_token(headers),
+        }
+    }
+}
+
+/// WebSocket terminal on a serial console, for xterm.js and its attach
+/// addon. The port's role and user list decide who may attach.
+async fn serial_terminal(
+    State(ctx): State<ApiContext>,
+    Path(name): Path<String>,
+    Query(query): Query<SocketQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> std::result::Result<Response, ApiFailure> {
+    let principal = ctx
+        .auth
+        .authorize(query.token(&headers), Role::Viewer)
+        .await?;
+    let attachment = ctx.serial.attach(&name, &principal).await?;
+    Ok(ws.on_upgrade(move |socket| attachment.run(socket)))
+}
+
+/// WebSocket stream of node events as JSON text messages, so dashboards
+/// need not poll
+async fn event_stream(
+    State(ctx): State<ApiContext>,
+    Query(query): Query<SocketQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> std::result::Result<Response, ApiFailure> {
+    ctx.auth
+        .authorize(query.token(&headers), Role::Viewer)
+        .await?;
+    let subscription = ctx.events.subscribe();
+    Ok(ws.on_upgrade(move |socket| events::stream(socket, subscription)))
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    /// Message key for client-side localization
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<&'static str>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    params: HashMap<&'static str, String>,
+}
+
+/// Error response carrying an HTTP status and message
+pub struct ApiFailure {
+    status: StatusCode,
+    message: String,
+    user_message: Option<ErrorMessage>,
+    sign_in_failure: Option<SignInFailure>,
+}
+
+/// Marks responses to wrong credentials for `guard_bans`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignInFailure {
+    /// Missing or unknown bearer token
+    Token,
+    /// Wrong password or one-time code
+    Credentials,
+}
+
+impl ApiFailure {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            user_message: None,
+            sign_in_failure: None,
+        }
+    }
+
+    /// Read by the gRPC service, which maps errors the same way
+    #[cfg(feature = "grpc")]
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    #[cfg(feature = "grpc")]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl From<Error> for ApiFailure {
+    fn from(err: Error) -> Self {
+        let status = match &err {
+            Error::Disk(DiskError::DiskNotFound(_))
+            | Error::Iso(IsoError::NotFound(_))
+            | Error::Remote(
+                RemoteError::KeyNotFound(_)
+                | RemoteError::PortNotFound(_)
+                | RemoteError::InvalidService(_),
+            ) => StatusCode::NOT_FOUND,
+            Error::Remote(RemoteError::InvalidKey(_) | RemoteError::InvalidPort(_)) => {
+                StatusCode::BAD_REQUEST
+            }
+            Error::Remote(RemoteError::ServiceRunning(_)) => StatusCode::CONFLICT,
+            Error::Auth(AuthError::NotEnrolled(_))
+            | Error::Monitoring(MonitoringError::AlertNotFound(_)) => StatusCode::NOT_FOUND,
+            Error::Auth(
+                AuthError::InvalidCode(_)
+                | AuthError::Unauthenticated
+                | AuthError::SessionRequired(_),
+            ) => StatusCode::UNAUTHORIZED,
+            Error::Auth(AuthError::LockedOut(_)) => StatusCode::TOO_MANY_REQUESTS,
+            Error::Auth(AuthError::Disabled | AuthError::Forbidden(..) | AuthError::Banned(_)) => {
+                StatusCode::FORBIDDEN
+            }
+            Error::Auth(AuthError::AlreadyEnrolled(_)) => StatusCode::CONFLICT,
+            Error::Transfer(TransferError::InvalidName(_)) => StatusCode::BAD_REQUEST,
+            Error::Transfer(TransferError::NotFound(_)) => StatusCode::NOT_FOUND,
+            Error::Transfer(TransferError::AlreadyExists(_)) => StatusCode::CONFLICT,
+            Error::Transfer(TransferError::TooLarge(_)) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Disk(
+                DiskError::EncryptedTarget(_)
+                | DiskError::EncryptionUnknown(_)
+                | DiskError::SafeMode(_),
+            )
+            | Error::Iso(IsoError::JobConflict(_))
+            | Error::Ui(UiError::WrongWizardStep(_)) => StatusCode::CONFLICT,
+            Error::Ui(
+                UiError::InputError(_)
+                | UiError::ConfirmationMismatch(_)
+                | UiError::UnsupportedLanguage(_),
+            ) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let sign_in_failure = match &err {
+            Error::Auth(AuthError::Unauthenticated) => Some(SignInFailure::Token),
+            Error::Auth(AuthError::InvalidCode(_) | AuthError::LockedOut(_)) => {
+                Some(SignInFailure::Credentials)
+            }
+            _ => None,
+        };
+        Self {
+            user_message: Some(err.user_message()),
+            sign_in_failure,
+            ..Self::new(status, err.to_string())
+        }
+    }
+}
+
+impl IntoResponse for ApiFailure {
+    fn into_response(self) -> Response {
+        let (key, params) = match self.user_message {
+            Some(message) => (Some(message.key), message.params.into_iter().collect()),
+            None => (None, HashMap::new()),
+        };
+
+        let mut response = (
+            self.status,
+            Json(ErrorBody {
+                error: self.message,
+                key,
+                params,
+            }),
+        )
+            .into_response();
+        if let Some(failure) = self.sign_in_failure {
+            response.extensions_mut().insert(failure);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::job::install::InstallJobStore;
+
+    #[test]
+    fn test_not_found_maps_to_404() {
+        let failure: ApiFailure = Error::from(DiskError::DiskNotFound("sdz".to_string())).into();
+        assert_eq!(failure.status, StatusCode::NOT_FOUND);
+        assert_eq!(failure.into_response().status(), StatusCode::NOT_FOUND);
+
+        let failure: ApiFailure = Error::from(IsoError::NotFound("x.iso".to_string())).into();
+        assert_eq!(failure.status, StatusCode::NOT_FOUND);
+
+        let failure: ApiFailure =
+            Error::from(RemoteError::PortNotFound("rack9".to_string())).into();
+        assert_eq!(failure.status, StatusCode::NOT_FOUND);
+
+        let failure: ApiFailure =
+            Error::from(RemoteError::InvalidService("telnet".to_string())).into();
+        assert_eq!(failure.status, StatusCode::NOT_FOUND);
+
+        let failure: ApiFailure =
+            Error::from(MonitoringError::AlertNotFound("a1".to_string())).into();
+        assert_eq!(failure.status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_auth_errors() {
+        let failure: ApiFailure = Error::from(AuthError::InvalidCode("admin".to_string())).into();
+        assert_eq!(failure.status, StatusCode::UNAUTHORIZED);
+        let failure: ApiFailure = Error::from(AuthError::LockedOut(300)).into();
+        assert_eq!(failure.status, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_other_errors_map_to_500() {
+        let failure: ApiFailure = Error::General("boom".to_string()).into();
+        assert_eq!(failure.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let ctx = context(crate::config::AuthConfig {
+            allow_anonymous_read: false,
+            ..Default::default()
+        });
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            authorize(&ctx, &headers, Role::Viewer)
+                .await
+                .unwrap_err()
+                .status,
+            StatusCode::UNAUTHORIZED
+        );
+
+        headers.insert(header::AUTHORIZATION, "Bearer view".parse().unwrap());
+        assert!(authorize(&ctx, &headers, Role::Viewer).await.is_ok());
+        assert_eq!(
+            authorize(&ctx, &headers, Role::Operator)
+                .await
+                .unwrap_err()
+                .status,
+            StatusCode::FORBIDDEN
+        );
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert!(authorize(&ctx, &headers, Role::Admin).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_disabled_server_does_not_bind() {
+        let config = Arc::new(RwLock::new(ApiConfig {
+            enabled: false,
+            ..ApiConfig::default()
+        }));
+        let mut server = ApiServer::new(config, context(Default::default()));
+
+        server.start().await.unwrap();
+        assert_eq!(server.get_state().await, ApiServerState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_failed_sign_ins_ban_the_sender() {
+        use tower::ServiceExt;
+
+        let ctx = context(Default::default());
+        let app = router(ctx.clone());
+        let peer = SocketAddr::from(([192, 168, 1, 50], 40000));
+        let request = |token: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri("/api/v1/auth/bans");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let mut request = request.body(axum::body::Body::empty()).unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer));
+            request
+        };
+
+        // Not signing in at all is not a guess
+        for _ in 0..3 {
+            let response = app.clone().oneshot(request(None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(ctx.bans.bans().is_empty());
+
+        for _ in 0..2 {
+            let response = app.clone().oneshot(request(Some("guess"))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(ctx.bans.bans().len(), 1);
+        let response = app.clone().oneshot(request(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        assert!(ctx.bans.lift(peer.ip()).await);
+        let response = app.oneshot(request(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_audited() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = context(Default::default());
+        ctx.audit = AuditLog::new(crate::config::AuditConfig {
+            enabled: true,
+            path: dir.path().join("audit.jsonl"),
+            record_reads: false,
+        });
+        let app = router(ctx.clone());
+        let peer = SocketAddr::from(([192, 168, 1, 50], 40000));
+        let request = |method: &str, uri: &str| {
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer));
+            request
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/api/v1/auth/bans"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .oneshot(request("DELETE", "/api/v1/auth/bans/10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Reads are left out unless `record_reads` is set
+        let entries = ctx.audit.entries(&AuditQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].actor, "admin");
+        assert_eq!(entries[0].action, "DELETE /api/v1/auth/bans/10.0.0.1");
+        assert_eq!(entries[0].address, Some(peer.ip()));
+        assert_eq!(entries[0].result, "failed: 404 Not Found");
+    }
+
+    #[tokio::test]
+    async fn test_upload_and_download() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = context(Default::default());
+        ctx.iso_manager = Arc::new(IsoManager::new(Arc::new(RwLock::new(
+            crate::config::IsoConfig {
+                search_paths: vec![dir.path().to_path_buf()],
+                catalog: crate::config::CatalogConfig {
+                    path: dir.path().join("catalog.json"),
+                },
+                ..Default::default()
+            },
+        ))));
+        ctx.transfer = Arc::new(FileTransfer::new(crate::config::TransferConfig {
+            shares: [("isos".to_string(), dir.path().to_path_buf())].into(),
+            ..Default::default()
+        }));
+        let app = router(ctx.clone());
+        let request = |method: &str, uri: &str, body: &'static str| {
+            axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request("PUT", "/api/v1/isos/tiny.iso", "ISO"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(std::fs::read(dir.path().join("tiny.iso")).unwrap(), b"ISO");
+        assert!(ctx
+            .iso_manager
+            .get_available_isos()
+            .await
+            .contains(&dir.path().join("tiny.iso")));
+
+        let response = app
+            .clone()
+            .oneshot(request("PUT", "/api/v1/isos/tiny.iso", "ISO2"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = app
+            .clone()
+            .oneshot(request("PUT", "/api/v1/isos/notes.txt", "text"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/api/v1/files/isos/tiny.iso", ""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"tiny.iso\""
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"ISO");
+
+        let response = app
+            .oneshot(request("GET", "/api/v1/files/isos/missing.iso", ""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(feature = "chaos")]
+    #[tokio::test]
+    async fn test_chaos_faults() {
+        use crate::chaos::FaultPoint;
+        use tower::ServiceExt;
+
+        let app = router(context(Default::default()));
+        let request = |method: &str, uri: &str, body: &'static str| {
+            axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/api/v1/chaos",
+                r#"{"point": "command_exec", "target": "test-api", "trigger": "once"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let armed: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(armed["target"], "test-api");
+        let id = armed["id"].as_str().unwrap().to_string();
+        assert!(chaos::inject(FaultPoint::CommandExec, "test-api").is_err());
+
+        let uri = format!("/api/v1/chaos/{}", id);
+        let disarm = |uri: String| {
+            axum::http::Request::builder()
+                .method("DELETE")
+                .uri(uri)
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = app.clone().oneshot(disarm(uri.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app.oneshot(disarm(uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    pub(crate) fn context(auth: crate::config::AuthConfig) -> ApiContext {
+        let disk_manager = Arc::new(DiskManager::new(Arc::new(RwLock::new(
+            crate::config::DiskConfig::default(),
+        ))));
+        let iso_manager = Arc::new(IsoManager::new(Arc::new(RwLock::new(
+            crate::config::IsoConfig::default(),
+        ))));
+        ApiContext {
+            disk_manager: disk_manager.clone(),
+            iso_manager: iso_manager.clone(),
+            identifier: Arc::new(Identifier::new(Arc::new(RwLock::new(
+                crate::config::IdentifyConfig::default(),
+            )))),
+            startup: StartupStatus::default(),
+            job_history: JobHistory::new("/nonexistent/jobs.jsonl"),
+            install_jobs: InstallJobRunner::new(
+                InstallJobStore::new("/nonexistent/install-jobs"),
+                disk_manager,
+                iso_manager,
+            ),
+            shift: Duration::from_secs(8 * 3600),
+            interface: InterfaceStatus::default(),
+            installer_gui: InstallerGui::default(),
+            capabilities: NodeCapabilities::detect(&crate::config::Config::default()),
+            diagnostics: Diagnostics::from_config(&crate::config::Config::default()),
+            dry_run: None,
+            peers: PeerDiscovery::new(crate::config::DiscoveryConfig::default()),
+            ssh_keys: Arc::new(AuthorizedKeys::new(
+                crate::config::SshKeysConfig::default(),
+                "/nonexistent/authorized_keys".into(),
+            )),
+            auth: Arc::new(Authenticator::new(
+                &crate::config::AuthConfig {
+                    tokens: vec![crate::config::ApiToken {
+                        name: "dashboard".to_string(),
+                        token: "view".to_string(),
+                        role: Role::Viewer,
+                    }],
+                    ..auth
+                },
+                Some("secret".to_string()),
+            )),
+            web_vnc_login: None,
+            bans: Arc::new(BanList::new(crate::config::BanConfig {
+                max_failures: 2,
+                ..Default::default()
+            })),
+            serial: Arc::new(SerialConsoles::new()),
+            remote: Arc::new(RwLock::new(RemoteManager::new(Default::default()))),
+            transfer: Arc::new(FileTransfer::new(Default::default())),
+            events: EventBus::new(),
+            audit: AuditLog::new(crate::config::AuditConfig {
+                enabled: false,
+                ..Default::default()
+            }),
+            monitor: Arc::new(RwLock::new(Monitor::new(Arc::new(RwLock::new(
+                crate::config::MonitoringConfig::default(),
+            ))))),
+        }
+    }
+}
@@ -0,0 +1,480 @@
+use crate::config::AuditConfig;
+use crate::error::{Error, Result};
+use crate::monitoring::clock::unix_now;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// `previous_hash` of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Where an audited action came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditSource {
+    Api,
+    Ssh,
+    Config,
+    Disk,
+    Iso,
+    Mqtt,
+    Grpc,
+}
+
+/// One recorded action. `hash` covers every other field, the previous
+/// entry's hash included, so editing or removing an entry breaks the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    /// Unix seconds
+    pub timestamp: u64,
+    pub source: AuditSource,
+    /// API token or SSH user; `node` for the node's own actions
+    pub actor: String,
+    /// Address the actor connected from
+    pub address: Option<IpAddr>,
+    pub action: String,
+    /// Device, ISO or file acted on
+    pub target: Option<String>,
+    /// `ok`, or what went wrong
+    pub result: String,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+/// What to record; the log adds the sequence number, time and hashes
+#[derive(Debug, Clone)]
+pub struct AuditAction {
+    source: AuditSource,
+    actor: String,
+    address: Option<IpAddr>,
+    action: String,
+    target: Option<String>,
+    result: String,
+}
+
+impl AuditAction {
+    pub fn new(source: AuditSource, actor: impl Into<String>, action: impl Into<String>) -> Self {
+        Self {
+            source,
+            actor: actor.into(),
+            address: None,
+            action: action.into(),
+            target: None,
+            result: "ok".to_string(),
+        }
+    }
+
+    pub fn with_address(mut self, address: Option<IpAddr>) -> Self {
+        self.address = address;
+        self
+    }
+
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// `ok` or the error
+    pub fn with_result<T>(mut self, result: &Result<T>) -> Self {
+        if let Err(e) = result {
+            self.result = format!("failed: {}", e);
+        }
+        self
+    }
+
+    pub fn with_outcome(mut self, outcome: impl Into<String>) -> Self {
+        self.result = outcome.into();
+        self
+    }
+}
+
+/// Entries to return, oldest first
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    /// Unix seconds
+    pub since: Option<u64>,
+    pub source: Option<AuditSource>,
+    pub actor: Option<String>,
+    /// Only the newest this many of the matching entries
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.since.map_or(true, |since| entry.timestamp >= since)
+            && self.source.map_or(true, |source| entry.source == source)
+            && self
+                .actor
+                .as_ref()
+                .map_or(true, |actor| &entry.actor == actor)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditVerification {
+    pub entries: u64,
+    pub intact: bool,
+    /// Line of the first entry that was altered, or that follows a removed
+    /// one
+    pub broken_at: Option<u64>,
+}
+
+/// Sequence number and hash the next entry continues from
+#[derive(Debug)]
+struct Chain {
+    next_sequence: u64,
+    last_hash: String,
+}
+
+/// Append-only audit log of remote and destructive actions, a JSON lines
+/// file in which every entry hashes the one before
+#[derive(Clone)]
+pub struct AuditLog {
+    config: AuditConfig,
+    /// Read from the file's last entry on the first append
+    chain: Arc<Mutex<Option<Chain>>>,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig) -> Self {
+        Self {
+            config,
+            chain: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Also record API reads
+    pub fn records_reads(&self) -> bool {
+        self.config.record_reads
+    }
+
+    /// Append an entry. Failures are logged rather than returned so that a
+    /// full disk does not stop the action being audited.
+    pub async fn record(&self, action: AuditAction) {
+        if !self.config.enabled {
+            return;
+        }
+        if let Err(e) = self.append(action).await {
+            warn!(
+                "Failed to write audit log {}: {}",
+                self.config.path.display(),
+                e
+            );
+        }
+    }
+
+    async fn append(&self, action: AuditAction) -> Result<()> {
+        // Held until the line is written so entries stay in chain order.
+        // After a failed write the chain is read from the file again.
+        let mut guard = self.chain.lock().await;
+        let chain = match guard.take() {
+            Some(chain) => chain,
+            None => self.read_chain().await?,
+        };
+
+        let mut entry = AuditEntry {
+            sequence: chain.next_sequence,
+            timestamp: unix_now(),
+            source: action.source,
+            actor: action.actor,
+            address: action.address,
+            action: action.action,
+            target: action.target,
+            result: action.result,
+            previous_hash: chain.last_hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry_hash(&entry);
+
+        let mut line = serde_json::to_string(&entry)
+            .map_err(|e| Error::General(format!("Failed to serialize audit entry: {}", e)))?;
+        line.push('\n');
+        if let Some(parent) = self.config.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.sync_data().await?;
+
+        *guard = Some(Chain {
+            next_sequence: chain.next_sequence + 1,
+            last_hash: entry.hash,
+        });
+        Ok(())
+    }
+
+    async fn read_chain(&self) -> Result<Chain> {
+        let last = self.read_entries().await?.pop();
+        Ok(match last {
+            Some(entry) => Chain {
+                next_sequence: entry.sequence + 1,
+                last_hash: entry.hash,
+            },
+            None => Chain {
+                next_sequence: 0,
+                last_hash: GENESIS_HASH.to_string(),
+            },
+        })
+    }
+
+    /// Entries matching `query`, oldest first
+    pub async fn entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let mut entries: Vec<AuditEntry> = self
+            .read_entries()
+            .await?
+            .into_iter()
+            .filter(|entry| query.matches(entry))
+            .collect();
+        if let Some(limit) = query.limit {
+            let skip = entries.len().saturating_sub(limit);
+            entries.drain(..skip);
+        }
+        Ok(entries)
+    }
+
+    /// Walk the chain and report the first entry whose hash, link to the
+    /// previous entry or sequence number does not match
+    pub async fn verify(&self) -> Result<AuditVerification> {
+        let content = self.read_file().await?;
+        let mut expected_sequence = 0;
+        let mut expected_hash = GENESIS_HASH.to_string();
+        let mut entries = 0;
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let intact = serde_json::from_str::<AuditEntry>(line).is_ok_and(|entry| {
+                let linked = entry.sequence == expected_sequence
+                    && entry.previous_hash == expected_hash
+                    && entry.hash == entry_hash(&entry);
+                expected_hash = entry.hash;
+                linked
+            });
+            if !intact {
+                return Ok(AuditVerification {
+                    entries,
+                    intact: false,
+                    broken_at: Some(number as u64 + 1),
+                });
+            }
+            expected_sequence += 1;
+            entries += 1;
+        }
+        Ok(AuditVerification {
+            entries,
+            intact: true,
+            broken_at: None,
+        })
+    }
+
+    /// Record the configuration file's digest when it differs from the one
+    /// recorded last, so edits between runs show up in the log
+    pub async fn record_config(&self, path: &Path) {
+        if !self.config.enabled {
+            return;
+        }
+        let digest = match tokio::fs::read(path).await {
+            Ok(content) => format!("sha256:{}", hex(&Sha256::digest(content))),
+            Err(e) => {
+                warn!("Cannot read {} for the audit log: {}", path.display(), e);
+                return;
+            }
+        };
+        let query = AuditQuery {
+            source: Some(AuditSource::Config),
+            ..Default::default()
+        };
+        let last = match self.entries(&query).await {
+            Ok(mut entries) => entries.pop(),
+            Err(e) => {
+                warn!("Failed to read audit log: {}", e);
+                return;
+            }
+        };
+        let action = match last {
+            Some(entry) if entry.result == digest => return,
+            Some(_) => "config_changed",
+            None => "config_loaded",
+        };
+        self.record(
+            AuditAction::new(AuditSource::Config, "node", action)
+                .with_target(path.display().to_string())
+                .with_outcome(digest),
+        )
+        .await;
+    }
+
+    async fn read_entries(&self) -> Result<Vec<AuditEntry>> {
+        let content = self.read_file().await?;
+        let mut entries = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<AuditEntry>(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!(
+                    "Skipping malformed line {} of {}: {}",
+                    number + 1,
+                    self.config.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn read_file(&self) -> Result<String> {
+        match tokio::fs::read_to_string(&self.config.path).await {
+            Ok(content) => Ok(content),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// SHA-256 over every field but `hash`, each followed by a NUL
+fn entry_hash(entry: &AuditEntry) -> String {
+    let source = serde_json::to_string(&entry.source).unwrap_or_default();
+    let address = entry.address.map(|a| a.to_string()).unwrap_or_default();
+    let mut hasher = Sha256::new();
+    for field in [
+        entry.sequence.to_string().as_str(),
+        entry.timestamp.to_string().as_str(),
+        source.as_str(),
+        entry.actor.as_str(),
+        address.as_str(),
+        entry.action.as_str(),
+        entry.target.as_deref().unwrap_or_default(),
+        entry.result.as_str(),
+        entry.previous_hash.as_str(),
+    ] {
+        hasher.update(field.as_bytes());
+        hasher.update([0]);
+    }
+    hex(&hasher.finalize())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DiskError;
+
+    fn audit_log(dir: &Path) -> AuditLog {
+        AuditLog::new(AuditConfig {
+            path: dir.join("audit.jsonl"),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_chain_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = audit_log(dir.path());
+        log.record(
+            AuditAction::new(AuditSource::Api, "admin", "POST /api/v1/jobs/install")
+                .with_address(Some("192.168.1.50".parse().unwrap())),
+        )
+        .await;
+
+        // A new handle continues the chain from the file
+        let log = audit_log(dir.path());
+        let failed: Result<()> = Err(DiskError::DiskNotFound("sdz".to_string()).into());
+        log.record(
+            AuditAction::new(AuditSource::Disk, "node", "format")
+                .with_target("/dev/sdz")
+                .with_result(&failed),
+        )
+        .await;
+
+        let entries = log.entries(&AuditQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].previous_hash, GENESIS_HASH);
+        assert_eq!(entries[1].sequence, 1);
+        assert_eq!(entries[1].previous_hash, entries[0].hash);
+        assert!(entries[1].result.starts_with("failed: "));
+        assert_eq!(
+            log.verify().await.unwrap(),
+            AuditVerification {
+                entries: 2,
+                intact: true,
+                broken_at: None,
+            }
+        );
+
+        let query = AuditQuery {
+            source: Some(AuditSource::Disk),
+            ..Default::default()
+        };
+        assert_eq!(log.entries(&query).await.unwrap().len(), 1);
+        let query = AuditQuery {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(log.entries(&query).await.unwrap()[0].action, "format");
+    }
+
+    #[tokio::test]
+    async fn test_tampering_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = audit_log(dir.path());
+        for action in ["start", "stop", "restart"] {
+            log.record(AuditAction::new(AuditSource::Ssh, "tech", action))
+                .await;
+        }
+
+        let path = dir.path().join("audit.jsonl");
+        let original = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, original.replace("\"stop\"", "\"help\"")).unwrap();
+        assert_eq!(log.verify().await.unwrap().broken_at, Some(2));
+
+        // Dropping an entry breaks the link of the one after it
+        let lines: Vec<&str> = original.lines().collect();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let verification = log.verify().await.unwrap();
+        assert!(!verification.intact);
+        assert_eq!(verification.entries, 1);
+        assert_eq!(verification.broken_at, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_config_recorded_when_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = audit_log(dir.path());
+        let config = dir.path().join("config.toml");
+        std::fs::write(&config, "[api]\nport = 8080\n").unwrap();
+
+        log.record_config(&config).await;
+        log.record_config(&config).await;
+        std::fs::write(&config, "[api]\nport = 9090\n").unwrap();
+        log.record_config(&config).await;
+
+        let actions: Vec<String> = log
+            .entries(&AuditQuery::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(actions, ["config_loaded", "config_changed"]);
+    }
+}
@@ -0,0 +1,643 @@
+pub mod bans;
+pub mod totp;
+
+use crate::config::{ApiToken, AuthConfig, Role, TotpConfig};
+use crate::error::{AuthError, Result};
+use crate::monitoring::clock::unix_now;
+use qrcode::render::svg;
+use qrcode::{EcLevel, QrCode};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// User `admin_token` authenticates as
+pub const ADMIN_USER: &str = "admin";
+/// Principal of requests without a token
+const ANONYMOUS_USER: &str = "anonymous";
+/// Smallest edge of the enrollment QR code, in pixels
+const QR_SIZE: u32 = 256;
+
+/// A user's shared secret as stored in the secrets file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Enrollment {
+    /// Base32, as shown to the user
+    secret: String,
+    /// Set once the user has entered a code from their app
+    confirmed: bool,
+    /// Last time step accepted, so each code works only once
+    #[serde(default)]
+    last_step: u64,
+}
+
+#[derive(Debug, Default)]
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// What a user needs to set up an authenticator app
+#[derive(Debug, Clone, Serialize)]
+pub struct TotpEnrollment {
+    pub user: String,
+    pub secret: String,
+    pub uri: String,
+    /// QR code of `uri`, unless it is too long to encode
+    pub qr_svg: Option<String>,
+}
+
+/// Who sent a request and what they may do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Bearer token handed out in exchange for an API token, and a TOTP code
+/// when its user has enrolled
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub token: String,
+    pub user: String,
+    pub role: Role,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Debug)]
+struct OpenSession {
+    principal: Principal,
+    expires: Instant,
+}
+
+/// Resolves bearer tokens, API tokens or sessions, to a principal and
+/// checks its role; shared by the API and the WebVNC sign-in
+pub struct Authenticator {
+    tokens: Vec<ApiToken>,
+    allow_anonymous_read: bool,
+    session_secs: u64,
+    two_factor: TwoFactor,
+    sessions: Mutex<HashMap<String, OpenSession>>,
+}
+
+impl Authenticator {
+    /// `admin_token` from `[api]` is an admin token named `admin`
+    pub fn new(config: &AuthConfig, admin_token: Option<String>) -> Self {
+        let mut tokens = config.tokens.clone();
+        if let Some(token) = admin_token {
+            tokens.push(ApiToken {
+                name: ADMIN_USER.to_string(),
+                token,
+                role: Role::Admin,
+            });
+        }
+        Self {
+            tokens,
+            allow_anonymous_read: config.allow_anonymous_read,
+            session_secs: config.session_secs,
+            two_factor: TwoFactor::new(config.totp.clone()),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn two_factor(&self) -> &TwoFactor {
+        &self.two_factor
+    }
+
+    /// Principal behind a bearer token with at least `role`. Without a
+    /// token, reads are allowed when `allow_anonymous_read` is set.
+    pub async fn authorize(&self, bearer: &str, role: Role) -> Result<Principal> {
+        let principal = match self.authenticate(bearer).await? {
+            Some(principal) => principal,
+            None if role == Role::Viewer && self.allow_anonymous_read => {
+                return Ok(Principal {
+                    name: ANONYMOUS_USER.to_string(),
+                    role: Role::Viewer,
+                })
+            }
+            None => return Err(AuthError::Unauthenticated.into()),
+        };
+        if principal.role < role {
+            return Err(AuthError::Forbidden(principal.name, role).into());
+        }
+        Ok(principal)
+    }
+
+    /// Trade an API token, plus a code when its user has enrolled TOTP,
+    /// for a session token
+    pub async fn open_session(&self, bearer: &str, code: Option<&str>) -> Result<Session> {
+        let principal = self
+            .token_principal(bearer)
+            .ok_or(AuthError::Unauthenticated)?;
+        if self.two_factor.is_required(&principal.name).await {
+            self.two_factor
+                .verify(&principal.name, code.unwrap_or_default())
+                .await?;
+        }
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().await;
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            token.clone(),
+            OpenSession {
+                principal: principal.clone(),
+                expires: now + Duration::from_secs(self.session_secs),
+            },
+        );
+        info!(
+            "Session opened for {} ({:?})",
+            principal.name, principal.role
+        );
+        Ok(Session {
+            token,
+            user: principal.name,
+            role: principal.role,
+            expires_in_secs: self.session_secs,
+        })
+    }
+
+    pub async fn close_session(&self, token: &str) {
+        self.sessions.lock().await.remove(token);
+    }
+
+    /// Name of the user behind a session or API token, without checking
+    /// role or second factor; used to attribute audited requests
+    pub async fn identify(&self, bearer: &str) -> Option<String> {
+        if bearer.is_empty() {
+            return None;
+        }
+        if let Some(principal) = self.session_principal(bearer).await {
+            return Some(principal.name);
+        }
+        self.token_principal(bearer).map(|principal| principal.name)
+    }
+
+    /// `None` for a request without a token
+    async fn authenticate(&self, bearer: &str) -> Result<Option<Principal>> {
+        if bearer.is_empty() {
+            return Ok(None);
+        }
+        if let Some(principal) = self.session_principal(bearer).await {
+            return Ok(Some(principal));
+        }
+        let principal = self
+            .token_principal(bearer)
+            .ok_or(AuthError::Unauthenticated)?;
+        // Enrolled users prove the second factor once per session
+        if self.two_factor.is_required(&principal.name).await {
+            return Err(AuthError::SessionRequired(principal.name).into());
+        }
+        Ok(Some(principal))
+    }
+
+    /// Principal of an open session that has not expired
+    async fn session_principal(&self, token: &str) -> Option<Principal> {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(token)?;
+        (session.expires > Instant::now()).then(|| session.principal.clone())
+    }
+
+    fn token_principal(&self, presented: &str) -> Option<Principal> {
+        self.tokens
+            .iter()
+            .find(|token| constant_time_eq(presented, &token.token))
+            .map(|token| Principal {
+                name: token.name.clone(),
+                role: token.role,
+            })
+    }
+}
+
+/// TOTP second factor shared by the API and WebVNC: enrollment,
+/// verification with replay protection and lockout after repeated failures
+pub struct TwoFactor {
+    config: TotpConfig,
+    enrollments: Mutex<HashMap<String, Enrollment>>,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl TwoFactor {
+    pub fn new(config: TotpConfig) -> Self {
+        let enrollments = if config.enabled {
+            load_enrollments(&config.secrets_path)
+        } else {
+            HashMap::new()
+        };
+        Self {
+            config,
+            enrollments: Mutex::new(enrollments),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Whether `user` has to present a code; users who have not enrolled
+    /// keep signing in with their first factor only
+    pub async fn is_required(&self, user: &str) -> bool {
+        self.config.enabled
+            && self
+                .enrollments
+                .lock()
+                .await
+                .get(user)
+                .is_some_and(|enrollment| enrollment.confirmed)
+    }
+
+    /// New secret for `user`, pending until confirmed with a code. A
+    /// confirmed secret has to be removed before enrolling again.
+    pub async fn enroll(&self, user: &str) -> Result<TotpEnrollment> {
+        self.ensure_enabled()?;
+        let secret = totp::generate_secret();
+        let encoded = totp::base32_encode(&secret);
+        {
+            let mut enrollments = self.enrollments.lock().await;
+            if enrollments.get(user).is_some_and(|e| e.confirmed) {
+                return Err(AuthError::AlreadyEnrolled(user.to_string()).into());
+            }
+            enrollments.insert(
+                user.to_string(),
+                Enrollment {
+                    secret: encoded.clone(),
+                    confirmed: false,
+                    last_step: 0,
+                },
+            );
+            self.save(&enrollments)?;
+        }
+
+        let uri = totp::provisioning_uri(&self.config.issuer, user, &secret);
+        info!("TOTP enrollment started for {}", user);
+        Ok(TotpEnrollment {
+            user: user.to_string(),
+            secret: encoded,
+            qr_svg: qr_svg(&uri),
+            uri,
+        })
+    }
+
+    /// Activate a pending secret once the user shows a code from it
+    pub async fn confirm(&self, user: &str, code: &str) -> Result<()> {
+        self.ensure_enabled()?;
+        self.check(user, code, false).await?;
+        let mut enrollments = self.enrollments.lock().await;
+        if let Some(enrollment) = enrollments.get_mut(user) {
+            enrollment.confirmed = true;
+        }
+        self.save(&enrollments)?;
+        info!("TOTP enrolled for {}", user);
+        Ok(())
+    }
+
+    pub async fn remove(&self, user: &str) -> Result<()> {
+        self.ensure_enabled()?;
+        let mut enrollments = self.enrollments.lock().await;
+        if enrollments.remove(user).is_none() {
+            return Err(AuthError::NotEnrolled(user.to_string()).into());
+        }
+        self.save(&enrollments)?;
+        info!("TOTP secret removed for {}", user);
+        Ok(())
+    }
+
+    /// Check a code from an enrolled user
+    pub async fn verify(&self, user: &str, code: &str) -> Result<()> {
+        self.ensure_enabled()?;
+        self.check(user, code, true).await
+    }
+
+    /// Refuse a locked-out user before their first factor is checked
+    pub async fn check_locked(&self, user: &str) -> Result<()> {
+        let mut failures = self.failures.lock().await;
+        if let Some(locked_until) = failures.get(user).and_then(|f| f.locked_until) {
+            let now = Instant::now();
+            if locked_until > now {
+                let remaining = (locked_until - now).as_secs().max(1);
+                return Err(AuthError::LockedOut(remaining).into());
+            }
+            failures.remove(user);
+        }
+        Ok(())
+    }
+
+    /// Count a failed attempt, by code or by first factor, and lock the
+    /// user out once `max_failures` is reached
+    pub async fn record_failure(&self, user: &str) -> AuthError {
+        let mut failures = self.failures.lock().await;
+        let entry = failures.entry(user.to_string()).or_default();
+        entry.count += 1;
+        if entry.count >= self.config.max_failures.max(1) {
+            warn!(
+                "Locking out {} for {}s after {} failed attempts",
+                user, self.config.lockout_secs, entry.count
+            );
+            entry.count = 0;
+            entry.locked_until =
+                Some(Instant::now() + Duration::from_secs(self.config.lockout_secs));
+            return AuthError::LockedOut(self.config.lockout_secs);
+        }
+        AuthError::InvalidCode(user.to_string())
+    }
+
+    fn ensure_enabled(&self) -> Result<()> {
+        if self.config.enabled {
+            Ok(())
+        } else {
+            Err(AuthError::Disabled.into())
+        }
+    }
+
+    async fn check(&self, user: &str, code: &str, confirmed: bool) -> Result<()> {
+        self.check_locked(user).await?;
+        let mut enrollments = self.enrollments.lock().await;
+        let Some(enrollment) = enrollments
+            .get_mut(user)
+            .filter(|e| e.confirmed == confirmed)
+        else {
+            return Err(AuthError::NotEnrolled(user.to_string()).into());
+        };
+        let secret = totp::base32_decode(&enrollment.secret)
+            .ok_or_else(|| AuthError::StoreFailed(format!("Corrupt secret for {}", user)))?;
+
+        match totp::verify(&secret, code, unix_now()) {
+            Some(step) if step > enrollment.last_step => {
+                enrollment.last_step = step;
+                self.save(&enrollments)?;
+                drop(enrollments);
+                self.failures.lock().await.remove(user);
+                Ok(())
+            }
+            _ => {
+                drop(enrollments);
+                Err(self.record_failure(user).await.into())
+            }
+        }
+    }
+
+    fn save(&self, enrollments: &HashMap<String, Enrollment>) -> Result<()> {
+        let data = serde_json::to_vec_pretty(enrollments)
+            .map_err(|e| AuthError::StoreFailed(e.to_string()))?;
+        write_private(&self.config.secrets_path, &data).map_err(|e| {
+            AuthError::StoreFailed(format!("{}: {}", self.config.secrets_path.display(), e)).into()
+        })
+    }
+}
+
+/// Compare every byte so the time taken does not reveal the prefix
+fn constant_time_eq(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// A missing file means nobody has enrolled yet
+fn load_enrollments(path: &Path) -> HashMap<String, Enrollment> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable TOTP secrets {}: {}", path.display(), e);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+fn qr_svg(uri: &str) -> Option<String> {
+    let code = QrCode::with_error_correction_level(uri, EcLevel::M).ok()?;
+    Some(
+        code.render::<svg::Color>()
+            .min_dimensions(QR_SIZE, QR_SIZE)
+            .dark_color(svg::Color("#000000"))
+            .light_color(svg::Color("#ffffff"))
+            .build(),
+    )
+}
+
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension("tmp");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp)
+        .and_then(|mut file| file.write_all(data))?;
+    std::fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn setup(dir: &TempDir) -> TwoFactor {
+        TwoFactor::new(TotpConfig {
+            enabled: true,
+            secrets_path: dir.path().join("totp.json"),
+            max_failures: 3,
+            ..TotpConfig::default()
+        })
+    }
+
+    async fn current_code(two_factor: &TwoFactor, user: &str, offset: u64) -> String {
+        let enrollments = two_factor.enrollments.lock().await;
+        let secret = totp::base32_decode(&enrollments[user].secret).unwrap();
+        totp::code_at(&secret, unix_now() + offset)
+    }
+
+    #[tokio::test]
+    async fn test_enroll_and_verify() {
+        let dir = TempDir::new().unwrap();
+        let two_factor = setup(&dir);
+
+        let enrollment = two_factor.enroll("admin").await.unwrap();
+        assert!(enrollment.uri.contains(&enrollment.secret));
+        assert!(enrollment.qr_svg.unwrap().starts_with("<?xml"));
+        assert!(!two_factor.is_required("admin").await);
+        // Pending secrets do not count as enrolled
+        let code = current_code(&two_factor, "admin", 0).await;
+        assert!(two_factor.verify("admin", &code).await.is_err());
+
+        two_factor.confirm("admin", &code).await.unwrap();
+        assert!(two_factor.is_required("admin").await);
+        assert!(two_factor.enroll("admin").await.is_err());
+
+        // A code is accepted once, the next step's code still works
+        assert!(two_factor.verify("admin", &code).await.is_err());
+        let next = current_code(&two_factor, "admin", totp::STEP_SECS).await;
+        two_factor.verify("admin", &next).await.unwrap();
+
+        // Enrollment survives a restart
+        let reloaded = setup(&dir);
+        assert!(reloaded.is_required("admin").await);
+        reloaded.remove("admin").await.unwrap();
+        assert!(!reloaded.is_required("admin").await);
+    }
+
+    #[tokio::test]
+    async fn test_lockout() {
+        let dir = TempDir::new().unwrap();
+        let two_factor = setup(&dir);
+        two_factor.enroll("tech").await.unwrap();
+        let code = current_code(&two_factor, "tech", 0).await;
+        two_factor.confirm("tech", &code).await.unwrap();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                two_factor.verify("tech", "000000").await,
+                Err(crate::error::Error::Auth(AuthError::InvalidCode(_)))
+            ));
+        }
+        assert!(matches!(
+            two_factor.verify("tech", "000000").await,
+            Err(crate::error::Error::Auth(AuthError::LockedOut(300)))
+        ));
+        // Even a valid code is refused while locked out
+        let next = current_code(&two_factor, "tech", totp::STEP_SECS).await;
+        assert!(matches!(
+            two_factor.verify("tech", &next).await,
+            Err(crate::error::Error::Auth(AuthError::LockedOut(_)))
+        ));
+        assert!(two_factor.check_locked("other").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let two_factor = TwoFactor::new(TotpConfig::default());
+        assert!(!two_factor.is_required("admin").await);
+        assert!(two_factor.enroll("admin").await.is_err());
+    }
+
+    fn authenticator(dir: &TempDir) -> Authenticator {
+        Authenticator::new(
+            &AuthConfig {
+                tokens: vec![
+                    ApiToken {
+                        name: "dashboard".to_string(),
+                        token: "view-token".to_string(),
+                        role: Role::Viewer,
+                    },
+                    ApiToken {
+                        name: "tech".to_string(),
+                        token: "operate-token".to_string(),
+                        role: Role::Operator,
+                    },
+                ],
+                totp: TotpConfig {
+                    enabled: true,
+                    secrets_path: dir.path().join("totp.json"),
+                    ..TotpConfig::default()
+                },
+                allow_anonymous_read: true,
+                ..AuthConfig::default()
+            },
+            Some("secret".to_string()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_roles() {
+        let dir = TempDir::new().unwrap();
+        let auth = authenticator(&dir);
+
+        assert_eq!(
+            auth.authorize("", Role::Viewer).await.unwrap().name,
+            "anonymous"
+        );
+        assert!(auth.authorize("", Role::Operator).await.is_err());
+        assert!(auth.authorize("view-token", Role::Viewer).await.is_ok());
+        assert!(matches!(
+            auth.authorize("view-token", Role::Operator).await,
+            Err(crate::error::Error::Auth(AuthError::Forbidden(
+                _,
+                Role::Operator
+            )))
+        ));
+        assert!(auth
+            .authorize("operate-token", Role::Operator)
+            .await
+            .is_ok());
+        assert!(auth.authorize("operate-token", Role::Admin).await.is_err());
+        assert!(auth.authorize("secreT", Role::Viewer).await.is_err());
+        let admin = auth.authorize("secret", Role::Admin).await.unwrap();
+        assert_eq!(admin.name, "admin");
+
+        let closed = Authenticator::new(
+            &AuthConfig {
+                allow_anonymous_read: false,
+                ..AuthConfig::default()
+            },
+            None,
+        );
+        assert!(closed.authorize("", Role::Viewer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let dir = TempDir::new().unwrap();
+        let auth = authenticator(&dir);
+
+        let session = auth.open_session("operate-token", None).await.unwrap();
+        assert_eq!(session.token.len(), 64);
+        assert_eq!(session.role, Role::Operator);
+        let principal = auth
+            .authorize(&session.token, Role::Operator)
+            .await
+            .unwrap();
+        assert_eq!(principal.name, "tech");
+        auth.close_session(&session.token).await;
+        assert!(auth.authorize(&session.token, Role::Viewer).await.is_err());
+        assert!(auth.open_session("unknown", None).await.is_err());
+        assert_eq!(auth.identify(&session.token).await, None);
+
+        // Once enrolled, the token alone only opens sessions, with a code
+        let two_factor = auth.two_factor();
+        two_factor.enroll("tech").await.unwrap();
+        let code = current_code(two_factor, "tech", 0).await;
+        two_factor.confirm("tech", &code).await.unwrap();
+        assert!(matches!(
+            auth.authorize("operate-token", Role::Viewer).await,
+            Err(crate::error::Error::Auth(AuthError::SessionRequired(_)))
+        ));
+        assert!(auth.open_session("operate-token", None).await.is_err());
+        let next = current_code(two_factor, "tech", totp::STEP_SECS).await;
+        let session = auth
+            .open_session("operate-token", Some(&next))
+            .await
+            .unwrap();
+        assert!(auth.authorize(&session.token, Role::Operator).await.is_ok());
+        assert_eq!(auth.identify(&session.token).await.as_deref(), Some("tech"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_session() {
+        let auth = Authenticator::new(
+            &AuthConfig {
+                session_secs: 0,
+                ..AuthConfig::default()
+            },
+            Some("secret".to_string()),
+        );
+        let session = auth.open_session("secret", None).await.unwrap();
+
+        assert!(auth.authorize(&session.token, Role::Viewer).await.is_err());
+        assert_eq!(auth.identify(&session.token).await, None);
+        assert_eq!(auth.identify("secret").await.as_deref(), Some("admin"));
+    }
+}
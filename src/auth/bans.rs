@@ -0,0 +1,334 @@
+use crate::config::BanConfig;
+use crate::error::{AuthError, Result};
+use crate::events::{AppEvent, EventBus};
+use crate::network::firewall::Firewall;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Remote service a failed sign-in came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanSource {
+    Ssh,
+    WebVnc,
+    Api,
+}
+
+/// An address currently refused
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ban {
+    pub address: IpAddr,
+    /// Service of the failure that triggered the ban
+    pub source: BanSource,
+    pub failures: u32,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Debug)]
+struct ActiveBan {
+    source: BanSource,
+    failures: u32,
+    until: Instant,
+}
+
+#[derive(Debug, Default)]
+struct BanState {
+    /// Recent failures per address, oldest first
+    failures: HashMap<IpAddr, VecDeque<Instant>>,
+    bans: HashMap<IpAddr, ActiveBan>,
+}
+
+/// Counts failed sign-ins per address across SSH, WebVNC and the API and
+/// bans addresses that keep failing, like fail2ban. Bans are enforced by
+/// the services themselves and, when the firewall is on, by nftables.
+pub struct BanList {
+    config: BanConfig,
+    /// Networks from `ignore` as address and prefix length
+    ignore: Vec<(IpAddr, u8)>,
+    firewall: Option<Firewall>,
+    state: Mutex<BanState>,
+    events: Option<EventBus>,
+}
+
+impl BanList {
+    pub fn new(config: BanConfig) -> Self {
+        let ignore = config
+            .ignore
+            .iter()
+            .filter_map(|network| {
+                let parsed = parse_network(network);
+                if parsed.is_none() {
+                    warn!("Ignoring invalid ban exception: {}", network);
+                }
+                parsed
+            })
+            .collect();
+        Self {
+            config,
+            ignore,
+            firewall: None,
+            state: Mutex::new(BanState::default()),
+            events: None,
+        }
+    }
+
+    /// Also drop banned addresses in the firewall, when it is enabled
+    pub fn with_firewall(mut self, firewall: Firewall) -> Self {
+        if firewall.is_enabled() {
+            self.firewall = Some(firewall);
+        }
+        self
+    }
+
+    /// Publish bans and lifted bans, for alerts
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Refuse an address while it is banned
+    pub fn check(&self, address: IpAddr) -> Result<()> {
+        let address = address.to_canonical();
+        let mut state = self.state.lock().unwrap();
+        match state.bans.get(&address) {
+            Some(ban) if ban.until > Instant::now() => Err(AuthError::Banned(
+                ban.until
+                    .saturating_duration_since(Instant::now())
+                    .as_secs()
+                    + 1,
+            )
+            .into()),
+            Some(_) => {
+                state.bans.remove(&address);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Count a failed sign-in; the one that reaches `max_failures` within
+    /// the window bans the address
+    pub async fn record_failure(&self, address: IpAddr, source: BanSource) {
+        let address = address.to_canonical();
+        if !self.config.enabled || self.is_ignored(address) {
+            return;
+        }
+
+        let ban = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let window = Duration::from_secs(self.config.window_secs);
+            let failures = state.failures.entry(address).or_default();
+            failures.push_back(now);
+            while failures
+                .front()
+                .is_some_and(|first| now.duration_since(*first) > window)
+            {
+                failures.pop_front();
+            }
+            if failures.len() < self.config.max_failures.max(1) as usize {
+                return;
+            }
+
+            let failures = state.failures.remove(&address).unwrap_or_default().len() as u32;
+            state.bans.insert(
+                address,
+                ActiveBan {
+                    source,
+                    failures,
+                    until: now + Duration::from_secs(self.config.ban_secs),
+                },
+            );
+            Ban {
+                address,
+                source,
+                failures,
+                expires_in_secs: self.config.ban_secs,
+            }
+        };
+
+        warn!(
+            "Banned {} for {}s after {} failed {:?} sign-ins",
+            address, ban.expires_in_secs, ban.failures, source
+        );
+        if let Some(firewall) = &self.firewall {
+            if let Err(e) = firewall
+                .ban(address, Duration::from_secs(self.config.ban_secs))
+                .await
+            {
+                warn!("Firewall did not take the ban of {}: {}", address, e);
+            }
+        }
+        self.publish(AppEvent::Banned(ban));
+    }
+
+    /// Addresses banned right now
+    pub fn bans(&self) -> Vec<Ban> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.bans.retain(|_, ban| ban.until > now);
+        let mut bans: Vec<Ban> = state
+            .bans
+            .iter()
+            .map(|(address, ban)| Ban {
+                address: *address,
+                source: ban.source,
+                failures: ban.failures,
+                expires_in_secs: ban.until.saturating_duration_since(now).as_secs(),
+            })
+            .collect();
+        bans.sort_by_key(|ban| ban.address);
+        bans
+    }
+
+    /// End a ban early; false when the address was not banned
+    pub async fn lift(&self, address: IpAddr) -> bool {
+        let address = address.to_canonical();
+        let lifted = {
+            let mut state = self.state.lock().unwrap();
+            state.failures.remove(&address);
+            state.bans.remove(&address).is_some()
+        };
+        if !lifted {
+            return false;
+        }
+        if let Some(firewall) = &self.firewall {
+            if let Err(e) = firewall.unban(address).await {
+                warn!("Firewall did not lift the ban of {}: {}", address, e);
+            }
+        }
+        self.publish(AppEvent::BanLifted { address });
+        true
+    }
+
+    fn publish(&self, event: AppEvent) {
+        if let Some(events) = &self.events {
+            events.publish(event);
+        }
+    }
+
+    fn is_ignored(&self, address: IpAddr) -> bool {
+        address.is_loopback()
+            || self
+                .ignore
+                .iter()
+                .any(|(network, prefix)| in_network(address, *network, *prefix))
+    }
+}
+
+/// `10.0.0.0/8`, `fd00::/8` or a single address
+fn parse_network(text: &str) -> Option<(IpAddr, u8)> {
+    let (address, prefix) = match text.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (text, None),
+    };
+    let address: IpAddr = address.parse().ok()?;
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse().ok().filter(|p| *p <= max)?,
+        None => max,
+    };
+    Some((address, prefix))
+}
+
+fn in_network(address: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (address, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(a) & mask == u32::from(n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(a) & mask == u128::from(n) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn ban_list(ignore: &[&str]) -> BanList {
+        BanList::new(BanConfig {
+            max_failures: 3,
+            ignore: ignore.iter().map(|s| s.to_string()).collect(),
+            ..BanConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_ban_after_failures() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe();
+        let bans = ban_list(&[]).with_events(bus);
+        let address: IpAddr = "192.168.1.50".parse().unwrap();
+
+        bans.record_failure(address, BanSource::Ssh).await;
+        bans.record_failure(address, BanSource::Api).await;
+        assert!(bans.check(address).is_ok());
+        bans.record_failure(address, BanSource::WebVnc).await;
+
+        assert!(matches!(
+            bans.check(address),
+            Err(Error::Auth(AuthError::Banned(_)))
+        ));
+        // IPv4-mapped addresses from dual-stack listeners are the same host
+        assert!(bans.check("::ffff:192.168.1.50".parse().unwrap()).is_err());
+        assert!(bans.check("192.168.1.51".parse().unwrap()).is_ok());
+
+        let listed = bans.bans();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].source, BanSource::WebVnc);
+        assert_eq!(listed[0].failures, 3);
+        assert!(matches!(events.try_recv(), Ok(AppEvent::Banned(ban)) if ban.address == address));
+
+        assert!(bans.lift(address).await);
+        assert!(!bans.lift(address).await);
+        assert!(bans.check(address).is_ok());
+        assert!(matches!(events.try_recv(), Ok(AppEvent::BanLifted { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_ignored_addresses() {
+        let bans = ban_list(&["10.0.0.0/8", "fd00::/8", "not-a-network"]);
+        for address in ["10.1.2.3", "fd00::5", "127.0.0.1", "::1"] {
+            let address: IpAddr = address.parse().unwrap();
+            for _ in 0..5 {
+                bans.record_failure(address, BanSource::Api).await;
+            }
+            assert!(bans.check(address).is_ok());
+        }
+        assert!(bans.bans().is_empty());
+
+        let disabled = BanList::new(BanConfig {
+            enabled: false,
+            max_failures: 1,
+            ..BanConfig::default()
+        });
+        let address: IpAddr = "192.168.1.50".parse().unwrap();
+        disabled.record_failure(address, BanSource::Ssh).await;
+        assert!(disabled.check(address).is_ok());
+    }
+
+    #[test]
+    fn test_in_network() {
+        let (network, prefix) = parse_network("192.168.0.0/16").unwrap();
+        assert!(in_network("192.168.4.1".parse().unwrap(), network, prefix));
+        assert!(!in_network("192.169.0.1".parse().unwrap(), network, prefix));
+        assert!(!in_network("fd00::1".parse().unwrap(), network, prefix));
+
+        let (network, prefix) = parse_network("0.0.0.0/0").unwrap();
+        assert!(in_network("8.8.8.8".parse().unwrap(), network, prefix));
+        assert_eq!(
+            parse_network("fd00::1"),
+            Some(("fd00::1".parse().unwrap(), 128))
+        );
+        assert!(parse_network("10.0.0.0/33").is_none());
+    }
+}
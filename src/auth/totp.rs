@@ -0,0 +1,182 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+/// Seconds each code is valid for, as every authenticator app expects
+pub const STEP_SECS: u64 = 30;
+/// Digits in a code
+pub const DIGITS: u32 = 6;
+/// Steps either side of the current one still accepted, for clock drift
+const SKEW: u64 = 1;
+
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// 160-bit shared secret, the size RFC 4226 recommends
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// HOTP value (RFC 4226) for one counter, truncated to `digits`
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    value % 10u32.pow(digits)
+}
+
+/// Current code for a secret, zero-padded
+pub fn code_at(secret: &[u8], unix_time: u64) -> String {
+    format!(
+        "{:0width$}",
+        hotp(secret, unix_time / STEP_SECS, DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Time step the code belongs to, if it is valid around `unix_time`.
+/// Callers reject steps they have already accepted so a code cannot be
+/// replayed.
+pub fn verify(secret: &[u8], code: &str, unix_time: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let current = unix_time / STEP_SECS;
+    (current.saturating_sub(SKEW)..=current + SKEW).find(|&step| {
+        let expected = format!(
+            "{:0width$}",
+            hotp(secret, step, DIGITS),
+            width = DIGITS as usize
+        );
+        // Compare every byte so the time taken does not reveal the prefix
+        expected
+            .bytes()
+            .zip(code.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    })
+}
+
+/// `otpauth://` URI authenticator apps import, usually from a QR code
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode_uri_component(issuer),
+        encode_uri_component(account),
+        base32_encode(secret),
+        encode_uri_component(issuer),
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+/// Unpadded RFC 4648 base32, the form authenticator apps accept
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// Inverse of [`base32_encode`]; case, spaces and padding are ignored
+pub fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32
+            .iter()
+            .position(|&b| b as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn encode_uri_component(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shared secret of the RFC 6238 SHA-1 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        for (time, expected) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+        ] {
+            assert_eq!(hotp(RFC_SECRET, time / STEP_SECS, 8), expected);
+        }
+        assert_eq!(code_at(RFC_SECRET, 1111111109), "081804");
+    }
+
+    #[test]
+    fn test_verify_window() {
+        let now = 1234567890;
+        let code = code_at(RFC_SECRET, now);
+        assert_eq!(verify(RFC_SECRET, &code, now), Some(now / STEP_SECS));
+        assert!(verify(RFC_SECRET, &code, now + STEP_SECS).is_some());
+        assert!(verify(RFC_SECRET, &code, now + 3 * STEP_SECS).is_none());
+        assert!(verify(RFC_SECRET, "12345", now).is_none());
+        assert!(verify(RFC_SECRET, "abcdef", now).is_none());
+    }
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+        assert!(base32_decode("MZ1W").is_none());
+
+        let secret = generate_secret();
+        assert_eq!(base32_decode(&base32_encode(&secret)).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        let uri = provisioning_uri("USB Installer Node", "admin", b"foobar");
+        assert_eq!(
+            uri,
+            "otpauth://totp/USB%20Installer%20Node:admin?secret=MZXW6YTBOI\
+             &issuer=USB%20Installer%20Node&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,617 @@
+use crate::config::{ButtonConfig, ButtonJob, ButtonSource};
+use crate::disk::inventory::DiskInventory;
+use crate::disk::windows_usb::{is_device_or_partition, DataFilesystem};
+use crate::disk::DiskManager;
+use crate::error::{DiskError, Error, IsoError, Result};
+use crate::identify::Led;
+use crate::iso::ventoy::VentoyStick;
+use crate::iso::IsoManager;
+use crate::job::{JobHistory, JobRecord};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
+use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+
+const EV_KEY: u16 = 0x01;
+
+/// `struct input_event`: a native `timeval` followed by type, code and value
+const TIMEVAL_SIZE: usize = 2 * std::mem::size_of::<usize>();
+const INPUT_EVENT_SIZE: usize = TIMEVAL_SIZE + 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ButtonManagerState {
+    Stopped,
+    Waiting,
+    Running(String),
+    Error(String),
+}
+
+/// Status LED patterns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedPattern {
+    Off,
+    /// Bursts of 1-4 blinks, one per started quarter of progress
+    Working(u8),
+    Success,
+    Failure,
+}
+
+/// Starts the configured job on the inserted USB stick when a hardware
+/// button is pressed, for nodes without keyboard or display
+pub struct ButtonManager {
+    config: Arc<RwLock<ButtonConfig>>,
+    state: Arc<RwLock<ButtonManagerState>>,
+    disk_manager: Arc<DiskManager>,
+    iso_manager: Arc<IsoManager>,
+    last_job: Arc<RwLock<Option<JobRecord>>>,
+    history: Option<JobHistory>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl ButtonManager {
+    pub fn new(
+        config: Arc<RwLock<ButtonConfig>>,
+        disk_manager: Arc<DiskManager>,
+        iso_manager: Arc<IsoManager>,
+    ) -> Self {
+        Self {
+            config,
+            state: Arc::new(RwLock::new(ButtonManagerState::Stopped)),
+            disk_manager,
+            iso_manager,
+            last_job: Arc::new(RwLock::new(None)),
+            history: None,
+            shutdown_tx: None,
+        }
+    }
+
+    /// Also append every finished job to the persistent history
+    pub fn with_history(mut self, history: JobHistory) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        let config = self.config.read().await.clone();
+        if !config.enabled {
+            info!("Button trigger disabled");
+            return Ok(());
+        }
+
+        info!("Waiting for button press ({:?})", config.source);
+
+        let (press_tx, press_rx) = mpsc::channel(4);
+        let (led_tx, led_rx) = watch::channel(LedPattern::Off);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+        let reader = tokio::spawn(read_presses(
+            config.source.clone(),
+            Duration::from_millis(config.long_press_ms),
+            press_tx,
+        ));
+
+        if let Some(name) = &config.led {
+            match Led::find(name) {
+                Some(led) => {
+                    tokio::spawn(drive_led(led, led_rx));
+                }
+                None => warn!("Status LED {} not found", name),
+            }
+        }
+
+        let runner = JobRunner {
+            job: config.job,
+            disk_manager: self.disk_manager.clone(),
+            iso_manager: self.iso_manager.clone(),
+            state: self.state.clone(),
+            last_job: self.last_job.clone(),
+            history: self.history.clone(),
+            led_tx,
+        };
+        tokio::spawn(runner.run(
+            press_rx,
+            shutdown_rx,
+            reader,
+            Duration::from_millis(config.debounce_ms),
+        ));
+
+        self.shutdown_tx = Some(shutdown_tx);
+        self.set_state(ButtonManagerState::Waiting).await;
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        if let Some(tx) = self.shutdown_tx.take() {
+            info!("Stopping button trigger");
+            let _ = tx.send(());
+        }
+        self.set_state(ButtonManagerState::Stopped).await;
+        Ok(())
+    }
+
+    pub async fn get_state(&self) -> ButtonManagerState {
+        self.state.read().await.clone()
+    }
+
+    async fn set_state(&self, state: ButtonManagerState) {
+        *self.state.write().await = state;
+    }
+
+    /// Record of the most recent button-started job
+    pub async fn last_job(&self) -> Option<JobRecord> {
+        self.last_job.read().await.clone()
+    }
+
+    pub async fn reload_config(&self, config: Arc<RwLock<ButtonConfig>>) {
+        *self.config.write().await = config.read().await.clone();
+    }
+}
+
+struct JobRunner {
+    job: ButtonJob,
+    disk_manager: Arc<DiskManager>,
+    iso_manager: Arc<IsoManager>,
+    state: Arc<RwLock<ButtonManagerState>>,
+    last_job: Arc<RwLock<Option<JobRecord>>>,
+    history: Option<JobHistory>,
+    led_tx: watch::Sender<LedPattern>,
+}
+
+impl JobRunner {
+    async fn run(
+        self,
+        mut press_rx: mpsc::Receiver<()>,
+        mut shutdown_rx: oneshot::Receiver<()>,
+        reader: tokio::task::JoinHandle<Result<()>>,
+        debounce: Duration,
+    ) {
+        let mut last_press: Option<Instant> = None;
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => break,
+                press = press_rx.recv() => {
+                    if press.is_none() {
+                        let reason = match reader.await {
+                            Ok(Err(e)) => e.to_string(),
+                            _ => "button input closed".to_string(),
+                        };
+                        error!("Button input failed: {}", reason);
+                        *self.state.write().await = ButtonManagerState::Error(reason);
+                        let _ = self.led_tx.send(LedPattern::Failure);
+                        return;
+                    }
+
+                    if last_press.is_some_and(|t| t.elapsed() < debounce) {
+                        debug!("Ignoring bounced button press");
+                        continue;
+                    }
+
+                    self.run_job().await;
+
+                    // Presses made while the job was running are not queued
+                    while press_rx.try_recv().is_ok() {}
+                    last_press = Some(Instant::now());
+                }
+            }
+        }
+
+        reader.abort();
+        let _ = self.led_tx.send(LedPattern::Off);
+    }
+
+    async fn run_job(&self) {
+        let _ = self.led_tx.send(LedPattern::Working(0));
+
+        let device = match self.disk_manager.list_disks().await.and_then(select_target) {
+            Ok(device) => device,
+            Err(e) => {
+                warn!("Button press ignored: {}", e);
+                let _ = self.led_tx.send(LedPattern::Failure);
+                return;
+            }
+        };
+
+        // A Ventoy stick only needs the ISOs copied, whatever the job
+        let ventoy = self.iso_manager.ventoy_target(&device).await;
+        let name = match &ventoy {
+            Some(_) => "ventoy",
+            None => job_name(self.job),
+        };
+
+        info!("Button pressed, running {} on {}", name, device);
+        *self.state.write().await = ButtonManagerState::Running(device.clone());
+        let mut record = JobRecord::start(name, &device).await;
+
+        let images = match (&ventoy, self.job) {
+            (Some(_), _) => self.iso_manager.get_available_isos().await,
+            (None, ButtonJob::WindowsUsb) => self
+                .iso_manager
+                .get_available_isos()
+                .await
+                .into_iter()
+                .take(1)
+                .collect(),
+            (None, ButtonJob::PrepareDisk | ButtonJob::Bootstrap) => Vec::new(),
+        };
+        record.images = images.iter().map(|i| i.display().to_string()).collect();
+
+        let progress = self.forward_progress(&device);
+        let result = match (ventoy, self.job) {
+            (Some(stick), _) => self.deploy_to_ventoy(&stick, &images).await,
+            (None, ButtonJob::PrepareDisk) => self.disk_manager.prepare_disk(&device).await,
+            (None, ButtonJob::WindowsUsb) => self.write_windows_usb(&device, &images).await,
+            (None, ButtonJob::Bootstrap) => self.disk_manager.bootstrap_target(&device).await,
+        };
+        progress.abort();
+        if result.is_ok() {
+            record.bytes_written = image_bytes(&images).await;
+        }
+        record.finish(&result);
+        if let Some(history) = &self.history {
+            if let Err(e) = history.append(&record).await {
+                warn!("Failed to record job in history: {}", e);
+            }
+        }
+        *self.last_job.write().await = Some(record);
+
+        match result {
+            Ok(_) => {
+                info!("Button job on {} completed", device);
+                let _ = self.led_tx.send(LedPattern::Success);
+            }
+            Err(e) => {
+                error!("Button job on {} failed: {}", device, e);
+                let _ = self.led_tx.send(LedPattern::Failure);
+            }
+        }
+        *self.state.write().await = ButtonManagerState::Waiting;
+    }
+
+    async fn write_windows_usb(&self, device: &str, images: &[PathBuf]) -> Result<()> {
+        let iso = images
+            .first()
+            .ok_or_else(|| Error::from(IsoError::NotFound("No ISO available".to_string())))?;
+
+        let source = self.iso_manager.mount_iso(iso).await?;
+        let result = match self.iso_manager.windows_answer_file(&source).await {
+            Ok(answer_file) => {
+                self.disk_manager
+                    .create_windows_usb(device, &source, DataFilesystem::Ntfs, answer_file)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = self.iso_manager.unmount_current().await {
+            warn!("Failed to unmount {}: {}", iso.display(), e);
+        }
+        result
+    }
+
+    async fn deploy_to_ventoy(&self, stick: &VentoyStick, isos: &[PathBuf]) -> Result<()> {
+        if isos.is_empty() {
+            return Err(IsoError::NotFound("No ISO available".to_string()).into());
+        }
+        self.iso_manager.deploy_to_ventoy(stick, isos).await?;
+        Ok(())
+    }
+
+    fn forward_progress(&self, device: &str) -> tokio::task::JoinHandle<()> {
+        let mut progress_rx = self.disk_manager.subscribe_progress();
+        let mut deploy_rx = self.iso_manager.subscribe_deploys();
+        let led_tx = self.led_tx.clone();
+        let device = device.to_string();
+
+        tokio::spawn(async move {
+            loop {
+                let (target, percentage) = tokio::select! {
+                    Ok(p) = progress_rx.recv() => (p.device, p.percentage),
+                    Ok(p) = deploy_rx.recv() => (p.device, p.percentage),
+                    else => break,
+                };
+                if is_device_or_partition(&target, &device) {
+                    let _ = led_tx.send(LedPattern::Working(percentage));
+                }
+            }
+        })
+    }
+}
+
+/// Data written by a job that copies `images`; `None` when it copies none
+async fn image_bytes(images: &[PathBuf]) -> Option<u64> {
+    if images.is_empty() {
+        return None;
+    }
+    let mut total = 0;
+    for image in images {
+        total += tokio::fs::metadata(image).await.ok()?.len();
+    }
+    Some(total)
+}
+
+fn job_name(job: ButtonJob) -> &'static str {
+    match job {
+        ButtonJob::PrepareDisk => "prepare_disk",
+        ButtonJob::WindowsUsb => "windows_usb",
+        ButtonJob::Bootstrap => "bootstrap",
+    }
+}
+
+/// Pick the job target: the single unmounted removable USB disk. The stick
+/// the node booted from is mounted and therefore skipped.
+fn select_target(disks: Vec<DiskInventory>) -> Result<String> {
+    let mut candidates: Vec<_> = disks
+        .into_iter()
+        .filter(|d| d.removable && d.transport.as_deref() == Some("usb") && !d.mounted)
+        .collect();
+
+    match candidates.len() {
+        1 => Ok(candidates.remove(0).path),
+        0 => Err(DiskError::DiskNotFound("no unmounted USB stick inserted".to_string()).into()),
+        n => Err(DiskError::InvalidLayout(format!(
+            "{} USB sticks inserted, expected exactly one",
+            n
+        ))
+        .into()),
+    }
+}
+
+async fn read_presses(
+    source: ButtonSource,
+    long_press: Duration,
+    press_tx: mpsc::Sender<()>,
+) -> Result<()> {
+    match source {
+        ButtonSource::Evdev { device, key_code } => {
+            read_evdev(&device, key_code, long_press, press_tx).await
+        }
+        ButtonSource::Gpio {
+            chip,
+            line,
+            active_low,
+        } => read_gpio(&chip, line, active_low, press_tx).await,
+    }
+}
+
+async fn read_evdev(
+    device: &Path,
+    key_code: u16,
+    long_press: Duration,
+    press_tx: mpsc::Sender<()>,
+) -> Result<()> {
+    let mut file = tokio::fs::File::open(device).await?;
+    let mut buf = [0u8; INPUT_EVENT_SIZE];
+    let mut detector = PressDetector::new(key_code, long_press);
+
+    loop {
+        file.read_exact(&mut buf).await?;
+        let Some((kind, code, value)) = parse_input_event(&buf) else {
+            continue;
+        };
+        if detector.feed(kind, code, value, Instant::now()) && press_tx.send(()).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn read_gpio(
+    chip: &str,
+    line: u32,
+    active_low: bool,
+    press_tx: mpsc::Sender<()>,
+) -> Result<()> {
+    let edge = if active_low { "falling" } else { "rising" };
+    let mut child = tokio::process::Command::new("gpiomon")
+        .arg(format!("--{}-edge", edge))
+        .arg(chip)
+        .arg(line.to_string())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| Error::General("gpiomon stdout unavailable".to_string()))?;
+    let mut lines = BufReader::new(stdout).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.to_lowercase().contains(edge) && press_tx.send(()).await.is_err() {
+            break;
+        }
+    }
+
+    child.wait().await?;
+    Ok(())
+}
+
+fn parse_input_event(buf: &[u8]) -> Option<(u16, u16, i32)> {
+    let body = buf.get(TIMEVAL_SIZE..TIMEVAL_SIZE + 8)?;
+    Some((
+        u16::from_ne_bytes([body[0], body[1]]),
+        u16::from_ne_bytes([body[2], body[3]]),
+        i32::from_ne_bytes([body[4], body[5], body[6], body[7]]),
+    ))
+}
+
+/// Turns key down/up events into short presses
+struct PressDetector {
+    key_code: u16,
+    long_press: Duration,
+    pressed_at: Option<Instant>,
+}
+
+impl PressDetector {
+    fn new(key_code: u16, long_press: Duration) -> Self {
+        Self {
+            key_code,
+            long_press,
+            pressed_at: None,
+        }
+    }
+
+    /// Returns true when a short press completes
+    fn feed(&mut self, kind: u16, code: u16, value: i32, now: Instant) -> bool {
+        if kind != EV_KEY || code != self.key_code {
+            return false;
+        }
+        match value {
+            1 => {
+                self.pressed_at = Some(now);
+                false
+            }
+            0 => self
+                .pressed_at
+                .take()
+                .is_some_and(|at| now.duration_since(at) < self.long_press),
+            // Autorepeat
+            _ => false,
+        }
+    }
+}
+
+/// On/off steps of a pattern; `None` holds the step until the pattern changes
+fn pattern_steps(pattern: LedPattern) -> Vec<(bool, Option<Duration>)> {
+    let ms = |n| Some(Duration::from_millis(n));
+    match pattern {
+        LedPattern::Off => Vec::new(),
+        LedPattern::Success => vec![(true, None)],
+        LedPattern::Failure => vec![(true, ms(100)), (false, ms(100))],
+        LedPattern::Working(percentage) => {
+            let blinks = 1 + percentage.min(99) / 25;
+            let mut steps = Vec::new();
+            for _ in 0..blinks {
+                steps.push((true, ms(150)));
+                steps.push((false, ms(250)));
+            }
+            steps.push((false, ms(1000)));
+            steps
+        }
+    }
+}
+
+async fn drive_led(led: Led, mut pattern_rx: watch::Receiver<LedPattern>) {
+    led.take_over();
+
+    'pattern: loop {
+        let steps = pattern_steps(*pattern_rx.borrow_and_update());
+        if steps.is_empty() {
+            led.restore();
+        }
+
+        loop {
+            for (on, duration) in &steps {
+                led.set(*on);
+                let changed = match duration {
+                    Some(duration) => tokio::select! {
+                        changed = pattern_rx.changed() => Some(changed),
+                        _ = sleep(*duration) => None,
+                    },
+                    None => Some(pattern_rx.changed().await),
+                };
+                match changed {
+                    Some(Ok(())) => continue 'pattern,
+                    Some(Err(_)) => break 'pattern,
+                    None => {}
+                }
+            }
+
+            if steps.is_empty() {
+                match pattern_rx.changed().await {
+                    Ok(()) => continue 'pattern,
+                    Err(_) => break 'pattern,
+                }
+            }
+        }
+    }
+
+    led.restore();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::inventory::DiskKind;
+
+    fn disk(path: &str, transport: &str, removable: bool, mounted: bool) -> DiskInventory {
+        DiskInventory {
+            name: path.trim_start_matches("/dev/").to_string(),
+            path: path.to_string(),
+            kind: DiskKind::Standard,
+            model: None,
+            serial: None,
+            size_bytes: 16_000_000_000,
+            transport: Some(transport.to_string()),
+            removable,
+            rotational: false,
+            partitions: Vec::new(),
+            mounted,
+            smart: None,
+            special_areas: Vec::new(),
+            encrypted: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_select_target() {
+        let disks = vec![
+            disk("/dev/sda", "sata", false, true),
+            disk("/dev/sdb", "usb", true, true),
+            disk("/dev/sdc", "usb", true, false),
+        ];
+        assert_eq!(select_target(disks).unwrap(), "/dev/sdc");
+
+        assert!(select_target(vec![disk("/dev/sdb", "usb", true, true)]).is_err());
+        assert!(select_target(vec![
+            disk("/dev/sdc", "usb", true, false),
+            disk("/dev/sdd", "usb", true, false),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_press_detector() {
+        let mut detector = PressDetector::new(116, Duration::from_secs(2));
+        let t0 = Instant::now();
+
+        assert!(!detector.feed(EV_KEY, 116, 1, t0));
+        assert!(!detector.feed(EV_KEY, 116, 2, t0));
+        assert!(detector.feed(EV_KEY, 116, 0, t0 + Duration::from_millis(200)));
+
+        // Long press and other keys are ignored
+        assert!(!detector.feed(EV_KEY, 116, 1, t0));
+        assert!(!detector.feed(EV_KEY, 116, 0, t0 + Duration::from_secs(3)));
+        assert!(!detector.feed(EV_KEY, 30, 1, t0));
+        assert!(!detector.feed(EV_KEY, 30, 0, t0));
+    }
+
+    #[test]
+    fn test_parse_input_event() {
+        let mut buf = vec![0u8; TIMEVAL_SIZE];
+        buf.extend_from_slice(&EV_KEY.to_ne_bytes());
+        buf.extend_from_slice(&116u16.to_ne_bytes());
+        buf.extend_from_slice(&1i32.to_ne_bytes());
+
+        assert_eq!(buf.len(), INPUT_EVENT_SIZE);
+        assert_eq!(parse_input_event(&buf), Some((EV_KEY, 116, 1)));
+    }
+
+    #[test]
+    fn test_working_pattern_counts_quarters() {
+        let blinks = |p| {
+            pattern_steps(LedPattern::Working(p))
+                .iter()
+                .filter(|(on, _)| *on)
+                .count()
+        };
+        assert_eq!(blinks(0), 1);
+        assert_eq!(blinks(30), 2);
+        assert_eq!(blinks(100), 4);
+        assert_eq!(pattern_steps(LedPattern::Success), vec![(true, None)]);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::Path;
+
+/// Filesystems the node can create and the mkfs program each needs
+const FILESYSTEMS: &[(&str, &str)] = &[
+    ("ext4", "mkfs.ext4"),
+    ("ext3", "mkfs.ext3"),
+    ("ext2", "mkfs.ext2"),
+    ("xfs", "mkfs.xfs"),
+    ("btrfs", "mkfs.btrfs"),
+    ("vfat", "mkfs.vfat"),
+    ("ntfs", "mkfs.ntfs"),
+    ("f2fs", "mkfs.f2fs"),
+];
+
+/// Tools optional jobs depend on, e.g. wimlib-imagex for Windows sticks
+/// and grub-install for multiboot and bootstrapped targets
+const OPTIONAL_TOOLS: &[&str] = &[
+    "wimlib-imagex",
+    "grub-install",
+    "debootstrap",
+    "pacstrap",
+    "ewfacquire",
+    "isoinfo",
+    "smartctl",
+];
+
+/// mDNS TXT strings are limited to 255 bytes each
+const TXT_MAX_LEN: usize = 255;
+
+/// What this node can do, so controllers only schedule jobs it can run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeCapabilities {
+    pub arch: String,
+    /// Filesystems whose mkfs tool is installed
+    pub filesystems: Vec<String>,
+    /// Installed optional tools from [`OPTIONAL_TOOLS`]
+    pub tools: Vec<String>,
+    /// VNC is enabled and x11vnc or wayvnc is installed
+    pub vnc: bool,
+    /// Network boot is enabled and the iPXE binaries are in place
+    pub pxe: bool,
+    /// Install jobs run at once, from `[jobs] max_concurrent`
+    pub max_concurrent_writes: u32,
+}
+
+impl NodeCapabilities {
+    /// Look up the installed tools on `PATH` and combine them with `config`
+    pub fn detect(config: &Config) -> Self {
+        Self::detect_with(config, |program| find_program(program).is_some())
+    }
+
+    fn detect_with(config: &Config, installed: impl Fn(&str) -> bool) -> Self {
+        let pxe = &config.pxe;
+        Self {
+            arch: env::consts::ARCH.to_string(),
+            filesystems: FILESYSTEMS
+                .iter()
+                .filter(|(_, program)| installed(program))
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            tools: OPTIONAL_TOOLS
+                .iter()
+                .filter(|program| installed(program))
+                .map(|program| program.to_string())
+                .collect(),
+            vnc: (config.remote.vnc.enabled || config.remote.web_vnc.enabled)
+                && (installed("x11vnc") || installed("wayvnc")),
+            pxe: pxe.enabled
+                && (pxe.tftp_root.join(&pxe.bios_boot_file).is_file()
+                    || pxe.tftp_root.join(&pxe.uefi_boot_file).is_file()),
+            max_concurrent_writes: config.jobs.max_concurrent.max(1),
+        }
+    }
+
+    /// `key=value` strings for the mDNS service record. Lists that would
+    /// exceed a TXT string are cut at an item boundary.
+    pub fn txt_records(&self) -> Vec<String> {
+        let flag = |on: bool| if on { "1" } else { "0" };
+        vec![
+            format!("arch={}", self.arch),
+            txt_list("fs", &self.filesystems),
+            txt_list("tools", &self.tools),
+            format!("vnc={}", flag(self.vnc)),
+            format!("pxe={}", flag(self.pxe)),
+            format!("writes={}", self.max_concurrent_writes),
+        ]
+    }
+}
+
+fn txt_list(key: &str, items: &[String]) -> String {
+    let mut record = format!("{}=", key);
+    for (i, item) in items.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        if record.len() + separator.len() + item.len() > TXT_MAX_LEN {
+            break;
+        }
+        record.push_str(separator);
+        record.push_str(item);
+    }
+    record
+}
+
+/// Full path of `program` in the directories on `PATH`
+pub fn find_program(program: &str) -> Option<std::path::PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .chain(["/sbin", "/usr/sbin"].map(Into::into))
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_from_installed_tools() {
+        let mut config = Config::default();
+        config.remote.vnc.enabled = true;
+        config.pxe.enabled = true;
+        config.pxe.tftp_root = "/nonexistent".into();
+        config.jobs.max_concurrent = 2;
+
+        let installed = ["mkfs.ext4", "mkfs.vfat", "x11vnc", "grub-install"];
+        let caps = NodeCapabilities::detect_with(&config, |p| installed.contains(&p));
+
+        assert_eq!(caps.filesystems, ["ext4", "vfat"]);
+        assert_eq!(caps.tools, ["grub-install"]);
+        assert!(caps.vnc);
+        // Enabled, but there is nothing to serve
+        assert!(!caps.pxe);
+        assert_eq!(caps.max_concurrent_writes, 2);
+    }
+
+    #[test]
+    fn test_txt_records() {
+        let caps = NodeCapabilities {
+            arch: "aarch64".to_string(),
+            filesystems: vec!["ext4".to_string(), "vfat".to_string()],
+            tools: (0..40).map(|i| format!("tool-{:02}", i)).collect(),
+            vnc: false,
+            pxe: true,
+            max_concurrent_writes: 1,
+        };
+        let records = caps.txt_records();
+
+        assert_eq!(records[0], "arch=aarch64");
+        assert_eq!(records[1], "fs=ext4,vfat");
+        assert!(records[2].len() <= TXT_MAX_LEN);
+        assert!(records[2].ends_with(",tool-30"));
+        assert_eq!(&records[3..], ["vnc=0", "pxe=1", "writes=1"]);
+    }
+}
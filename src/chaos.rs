@@ -0,0 +1,318 @@
+#[cfg(feature = "chaos")]
+use crate::error::Error;
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+/// Locations in the code base where a failure can be injected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultPoint {
+    /// Before an external command is executed
+    CommandExec,
+    /// Before a monitored service health check runs
+    HealthCheck,
+    /// Before a message is sent on an internal channel
+    ChannelSend,
+    /// While writing data through a [`FaultyWriter`]
+    IoWrite,
+}
+
+/// How often an armed fault fires
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultTrigger {
+    /// Fire on every hit
+    Always,
+    /// Fire on the first hit only
+    Once,
+    /// Fire on the first N hits
+    Times(u32),
+    /// Fire on every Nth hit
+    EveryNth(u32),
+}
+
+/// A single fault definition, loaded from config or armed at runtime
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultSpec {
+    /// Where the fault applies
+    pub point: FaultPoint,
+    /// Optional call-site target (command name, service name, channel name).
+    /// `None` matches every target at the fault point.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Firing policy
+    pub trigger: FaultTrigger,
+    /// For [`FaultPoint::IoWrite`]: number of bytes accepted before writes fail
+    #[serde(default)]
+    pub after_bytes: Option<u64>,
+}
+
+impl FaultSpec {
+    /// Create a fault that fires at `point` for every target
+    pub fn new(point: FaultPoint, trigger: FaultTrigger) -> Self {
+        Self {
+            point,
+            target: None,
+            trigger,
+            after_bytes: None,
+        }
+    }
+
+    /// Restrict the fault to a single call-site target
+    pub fn with_target(mut self, target: String) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Set the byte offset at which an IoWrite fault starts failing
+    pub fn with_after_bytes(mut self, bytes: u64) -> Self {
+        self.after_bytes = Some(bytes);
+        self
+    }
+
+    fn matches(&self, point: FaultPoint, target: &str) -> bool {
+        self.point == point && self.target.as_deref().is_none_or(|t| t == target)
+    }
+}
+
+/// Fault injection settings (`[chaos]` section, `chaos` feature only)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    pub faults: Vec<FaultSpec>,
+}
+
+#[cfg(feature = "chaos")]
+mod registry {
+    use super::{FaultPoint, FaultSpec, FaultTrigger};
+    use std::sync::{Mutex, OnceLock};
+    use tracing::warn;
+
+    pub(super) struct ArmedFault {
+        pub id: String,
+        pub spec: FaultSpec,
+        pub hits: u32,
+        pub fired: u32,
+    }
+
+    impl ArmedFault {
+        fn should_fire(&mut self) -> bool {
+            self.hits += 1;
+            let fire = match self.spec.trigger {
+                FaultTrigger::Always => true,
+                FaultTrigger::Once => self.fired == 0,
+                FaultTrigger::Times(n) => self.fired < n,
+                FaultTrigger::EveryNth(n) => n > 0 && self.hits.is_multiple_of(n),
+            };
+            if fire {
+                self.fired += 1;
+            }
+            fire
+        }
+    }
+
+    pub(super) fn faults() -> &'static Mutex<Vec<ArmedFault>> {
+        static FAULTS: OnceLock<Mutex<Vec<ArmedFault>>> = OnceLock::new();
+        FAULTS.get_or_init(|| Mutex::new(Vec::new()))
+    }
+
+    pub(super) fn arm(spec: FaultSpec) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        warn!(
+            "Arming injected fault {} at {:?} (target: {:?})",
+            id, spec.point, spec.target
+        );
+        if let Ok(mut faults) = faults().lock() {
+            faults.push(ArmedFault {
+                id: id.clone(),
+                spec,
+                hits: 0,
+                fired: 0,
+            });
+        }
+        id
+    }
+
+    /// Returns the matching spec if a fault fires for this hit
+    pub(super) fn hit(point: FaultPoint, target: &str) -> Option<FaultSpec> {
+        let mut faults = faults().lock().ok()?;
+        faults
+            .iter_mut()
+            .filter(|f| f.spec.matches(point, target))
+            .find_map(|f| f.should_fire().then(|| f.spec.clone()))
+    }
+}
+
+/// Arm every fault listed in the configuration
+pub fn install(config: &ChaosConfig) {
+    if !config.enabled {
+        return;
+    }
+    for spec in &config.faults {
+        arm(spec.clone());
+    }
+}
+
+/// Arm a fault at runtime, returning its id for later disarming
+#[cfg(feature = "chaos")]
+pub fn arm(spec: FaultSpec) -> String {
+    registry::arm(spec)
+}
+
+#[cfg(not(feature = "chaos"))]
+pub fn arm(_spec: FaultSpec) -> String {
+    String::new()
+}
+
+/// Remove a previously armed fault
+pub fn disarm(id: &str) -> bool {
+    #[cfg(feature = "chaos")]
+    if let Ok(mut faults) = registry::faults().lock() {
+        let before = faults.len();
+        faults.retain(|f| f.id != id);
+        return faults.len() != before;
+    }
+    let _ = id;
+    false
+}
+
+/// Remove all armed faults
+pub fn clear() {
+    #[cfg(feature = "chaos")]
+    if let Ok(mut faults) = registry::faults().lock() {
+        faults.clear();
+    }
+}
+
+/// List armed faults as `(id, spec)` pairs
+pub fn armed() -> Vec<(String, FaultSpec)> {
+    #[cfg(feature = "chaos")]
+    if let Ok(faults) = registry::faults().lock() {
+        return faults
+            .iter()
+            .map(|f| (f.id.clone(), f.spec.clone()))
+            .collect();
+    }
+    Vec::new()
+}
+
+/// Injection point: returns an error when an armed fault fires for `target`
+#[inline]
+pub fn inject(point: FaultPoint, target: &str) -> Result<()> {
+    #[cfg(feature = "chaos")]
+    if registry::hit(point, target).is_some() {
+        return Err(Error::General(format!(
+            "Injected fault at {:?} ({})",
+            point, target
+        )));
+    }
+    let _ = (point, target);
+    Ok(())
+}
+
+/// Writer wrapper that fails mid-stream when an IoWrite fault is armed
+pub struct FaultyWriter<W: Write> {
+    inner: W,
+    target: String,
+    written: u64,
+    fail_after: Option<u64>,
+}
+
+impl<W: Write> FaultyWriter<W> {
+    /// Wrap `inner`; the fault budget is resolved once at creation
+    pub fn new(inner: W, target: &str) -> Self {
+        #[cfg(feature = "chaos")]
+        let fail_after =
+            registry::hit(FaultPoint::IoWrite, target).map(|spec| spec.after_bytes.unwrap_or(0));
+        #[cfg(not(feature = "chaos"))]
+        let fail_after = None;
+
+        Self {
+            inner,
+            target: target.to_string(),
+            written: 0,
+            fail_after,
+        }
+    }
+
+    /// Unwrap the inner writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for FaultyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let allowed = match self.fail_after {
+            Some(limit) if self.written >= limit => {
+                return Err(io::Error::other(format!(
+                    "Injected write fault on {}",
+                    self.target
+                )));
+            }
+            Some(limit) => buf.len().min((limit - self.written) as usize),
+            None => buf.len(),
+        };
+
+        let n = self.inner.write(&buf[..allowed])?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(all(test, feature = "chaos"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_once_trigger_fires_once() {
+        arm(FaultSpec::new(FaultPoint::CommandExec, FaultTrigger::Once)
+            .with_target("test-once".to_string()));
+
+        assert!(inject(FaultPoint::CommandExec, "test-once").is_err());
+        assert!(inject(FaultPoint::CommandExec, "test-once").is_ok());
+    }
+
+    #[test]
+    fn test_target_filtering() {
+        let id = arm(
+            FaultSpec::new(FaultPoint::HealthCheck, FaultTrigger::Always)
+                .with_target("test-filter".to_string()),
+        );
+
+        assert!(inject(FaultPoint::HealthCheck, "other-service").is_ok());
+        assert!(inject(FaultPoint::HealthCheck, "test-filter").is_err());
+        assert!(disarm(&id));
+        assert!(inject(FaultPoint::HealthCheck, "test-filter").is_ok());
+    }
+
+    #[test]
+    fn test_every_nth_trigger() {
+        arm(
+            FaultSpec::new(FaultPoint::ChannelSend, FaultTrigger::EveryNth(3))
+                .with_target("test-nth".to_string()),
+        );
+
+        let results: Vec<bool> = (0..6)
+            .map(|_| inject(FaultPoint::ChannelSend, "test-nth").is_err())
+            .collect();
+        assert_eq!(results, vec![false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn test_faulty_writer_fails_mid_write() {
+        arm(FaultSpec::new(FaultPoint::IoWrite, FaultTrigger::Once)
+            .with_target("test-writer".to_string())
+            .with_after_bytes(4));
+
+        let mut writer = FaultyWriter::new(Vec::new(), "test-writer");
+        assert_eq!(writer.write(b"abcdef").unwrap(), 4);
+        assert!(writer.write(b"gh").is_err());
+        assert_eq!(writer.into_inner(), b"abcd");
+    }
+}
@@ -1,5 +1,18 @@
+#[cfg(feature = "chaos")]
+use crate::chaos::ChaosConfig;
+use crate::disk::bootstrap::BootstrapTool;
+use crate::disk::checksum::HashAlgorithm;
 use crate::error::{ConfigError, Result};
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcConfig;
+use crate::iso::catalog::CatalogQuery;
+use crate::iso::hooks::PostInstallHook;
+#[cfg(feature = "torrent")]
+use crate::iso::torrent::TorrentConfig;
+use crate::monitoring::notify::NotifierConfig;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use std::{env, fs};
@@ -14,6 +27,40 @@ pub struct Config {
     pub disk: DiskConfig,
     pub service: ServiceConfig,
     pub monitoring: MonitoringConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub identify: IdentifyConfig,
+    #[serde(default)]
+    pub button: ButtonConfig,
+    #[serde(default)]
+    pub target: TargetConfig,
+    #[serde(default)]
+    pub startup: StartupConfig,
+    #[serde(default)]
+    pub reports: ReportsConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub pxe: PxeConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub transfer: TransferConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub diagnose: DiagnoseConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[cfg(feature = "grpc")]
+    #[serde(default)]
+    pub grpc: GrpcConfig,
+    #[cfg(feature = "chaos")]
+    #[serde(default)]
+    pub chaos: ChaosConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,8 +68,157 @@ pub struct NetworkConfig {
     pub interface: Option<String>,
     pub dhcp_timeout: u64,
     pub hostname_prefix: String,
+    /// How the suffix after `hostname_prefix` is chosen
+    #[serde(default)]
+    pub hostname_strategy: HostnameStrategy,
     pub mdns_enabled: bool,
+    /// Air-gapped operation: no tunnel, ISO downloads, feed or repository
+    /// sync and no NTP; the node starts and reports healthy on the local
+    /// network alone
+    #[serde(default)]
+    pub offline: bool,
     pub tunnel: TunnelConfig,
+    #[serde(default)]
+    pub provisioning: ProvisioningConfig,
+    /// Interfaces addressed statically instead of through DHCP
+    #[serde(default)]
+    pub static_addresses: Vec<StaticAddressConfig>,
+    #[serde(default)]
+    pub ipv6: Ipv6Config,
+    #[serde(default)]
+    pub link_local: LinkLocalConfig,
+    #[serde(default)]
+    pub firewall: FirewallConfig,
+    #[serde(default)]
+    pub failover: FailoverConfig,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    /// Tagged sub-interfaces, created before any interface is addressed
+    #[serde(default)]
+    pub vlans: Vec<VlanConfig>,
+    /// Bridges, created after the VLANs so those can be members
+    #[serde(default)]
+    pub bridges: Vec<BridgeConfig>,
+}
+
+/// 802.1Q sub-interface, e.g. `eth0.100` for a tagged provisioning network.
+/// Set `interface` (or a static address) to its name to run DHCP on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VlanConfig {
+    pub parent: String,
+    pub id: u16,
+    /// `<parent>.<id>` when unset
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl VlanConfig {
+    pub fn interface(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{}.{}", self.parent, self.id))
+    }
+}
+
+/// Linux bridge over physical interfaces or VLANs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BridgeConfig {
+    pub name: String,
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub stp: bool,
+}
+
+/// Source of the hostname suffix. All but `random` give a node the same
+/// name on every boot; when the source cannot be read the suffix is random.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostnameStrategy {
+    /// Four random digits, new on every boot
+    #[default]
+    Random,
+    /// Last three octets of the primary interface's MAC address
+    Mac,
+    /// DMI product serial number
+    Serial,
+    /// Hash of `/etc/machine-id`
+    MachineId,
+}
+
+/// Uplinks in order of preference. The default route follows the first one
+/// with carrier, an address and a gateway.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailoverConfig {
+    /// e.g. `["eth0", "wlan0", "usb0"]`; empty leaves routing to the
+    /// primary interface alone
+    pub interfaces: Vec<String>,
+    /// Seconds between link checks
+    pub check_interval: u64,
+    /// Route metric of the first uplink; later ones get higher metrics
+    pub base_metric: u32,
+}
+
+/// Browsing for other nodes advertising `_usb-installer._tcp` over mDNS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    pub enabled: bool,
+    /// Seconds between queries; peers also announce themselves unasked
+    pub query_interval: u64,
+}
+
+/// IPv6 addressing of the primary interface, alongside IPv4
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ipv6Config {
+    /// Accept router advertisements and configure addresses via SLAAC
+    pub enabled: bool,
+    /// Also request an address via DHCPv6, for networks without SLAAC
+    pub dhcpv6: bool,
+    /// Seconds to wait for a global address
+    pub timeout: u64,
+}
+
+/// 169.254.0.0/16 address on the primary interface when DHCP gives up, so
+/// a directly attached laptop can still reach the node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkLocalConfig {
+    pub enabled: bool,
+    /// Seconds between further DHCP attempts while link-local; a lease
+    /// replaces the link-local address
+    pub retry_interval: u64,
+}
+
+/// Input firewall allowing only the node's own services. Network boot and
+/// mDNS stay open to everyone; the management services (SSH, VNC, WebVNC,
+/// API) and the extra ports here can be limited to some source networks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FirewallConfig {
+    pub enabled: bool,
+    /// Addresses or CIDRs, IPv4 or IPv6, the management ports accept
+    /// connections from; empty allows any source
+    pub allowed_sources: Vec<String>,
+    /// Further TCP ports, limited like the management ports
+    pub allowed_tcp: Vec<u16>,
+    /// Further UDP ports, open to every source
+    pub allowed_udp: Vec<u16>,
+}
+
+/// Fixed addressing for one interface
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StaticAddressConfig {
+    pub interface: String,
+    /// Node address and prefix, e.g. `192.168.10.20/24`
+    pub address: String,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub search_domains: Vec<String>,
+    /// Use DHCP on the interface when another host already has `address`
+    pub fallback_to_dhcp: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +227,79 @@ pub struct TunnelConfig {
     pub provider: TunnelProvider,
     pub config_path: Option<PathBuf>,
     pub reconnect_interval: u64,
+    #[serde(default)]
+    pub wireguard: WireguardConfig,
+    #[serde(default)]
+    pub ssh: SshTunnelConfig,
+    #[serde(default)]
+    pub probe: TunnelProbeConfig,
+}
+
+/// Connectivity checks behind the tunnel's health
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TunnelProbeConfig {
+    /// Service reached through the tunnel, e.g. `tcp://10.100.0.1:22` or
+    /// `https://fleet.example.com/health`; its round trip is `tunnel_rtt`
+    pub target: Option<String>,
+    /// Seconds before a probe counts as failed
+    pub timeout: u64,
+    /// Seconds after which a WireGuard peer without a new handshake is
+    /// unreachable
+    pub max_handshake_age: u64,
+}
+
+/// Reverse SSH tunnel publishing node services on a jump host, for nodes
+/// behind NAT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshTunnelConfig {
+    /// `user@host`; reverse forwarding is used when set
+    pub jump_host: Option<String>,
+    pub port: u16,
+    pub identity_file: Option<PathBuf>,
+    /// Node services forwarded from the jump host: `api`, `vnc`, `ssh`.
+    /// The jump host picks the ports.
+    pub publish: Vec<String>,
+    /// Receives the node's hostname and published ports as JSON after
+    /// every (re)connect
+    pub registration_url: Option<String>,
+    /// Seconds between keepalives; the tunnel is rebuilt after three
+    /// unanswered ones
+    pub keepalive_interval: u64,
+}
+
+/// WireGuard interface run in-process instead of through `config_path`
+/// and wg-quick
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WireguardConfig {
+    /// Use the embedded implementation; needs no wireguard-tools
+    pub embedded: bool,
+    pub interface: String,
+    /// Base64, as printed by `wg genkey`
+    pub private_key: Option<String>,
+    /// Node address and prefix on the tunnel, e.g. `10.100.0.5/24`
+    pub address: String,
+    /// Random when unset
+    pub listen_port: Option<u16>,
+    pub mtu: u32,
+    pub peers: Vec<WireguardPeerConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireguardPeerConfig {
+    pub public_key: String,
+    #[serde(default)]
+    pub preshared_key: Option<String>,
+    /// `host:port`; without one the node waits to be contacted
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Networks routed to this peer, e.g. `["10.100.0.0/24"]`
+    pub allowed_ips: Vec<String>,
+    /// Seconds between keepalives, to hold NAT mappings open
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,39 +310,151 @@ pub enum TunnelProvider {
     Ssh,
 }
 
+/// Isolated interface PXE targets are served on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProvisioningConfig {
+    pub enabled: bool,
+    pub interface: Option<String>,
+    /// Node address and prefix on the provisioning subnet
+    pub address: String,
+    pub forwarding: Forwarding,
+    /// Interface NATed traffic leaves through
+    pub uplink: Option<String>,
+    /// Node services targets may reach: DNS, DHCP, TFTP and HTTP, including
+    /// the network boot ports, by default
+    pub allowed_udp: Vec<u16>,
+    pub allowed_tcp: Vec<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Forwarding {
+    /// Targets reach the node only
+    None,
+    /// Targets reach the outside through `uplink`, masqueraded
+    Nat,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RemoteConfig {
     pub vnc: VncConfig,
     pub ssh: SshConfig,
     pub web_vnc: WebVncConfig,
+    #[serde(default)]
+    pub serial: SerialConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct VncConfig {
     pub enabled: bool,
     pub port: u16,
+    /// X display x11vnc serves, and where the virtual display is started
+    /// in headless mode
     pub display: String,
     pub password: Option<String>,
     pub view_only: bool,
+    pub backend: DisplayBackend,
+    /// Xvfb screen, `WIDTHxHEIGHTxDEPTH`, in headless mode
+    pub headless_screen: String,
+}
+
+/// What the VNC server shares
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayBackend {
+    /// Wayland through wayvnc, else X11, else a headless virtual display
+    #[default]
+    Auto,
+    /// x11vnc on `display`
+    X11,
+    /// wayvnc on the running compositor
+    Wayland,
+    /// Xvfb on `display`, shared with x11vnc
+    Headless,
 }
 
+/// Embedded SSH server: key login only, node commands instead of a shell
+/// and SFTP to the ISO directory
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SshConfig {
     pub enabled: bool,
     pub port: u16,
+    /// Host key; an Ed25519 key is generated here when missing
     pub key_path: PathBuf,
     pub authorized_keys_path: PathBuf,
-    pub password_auth: bool,
+    /// Directory SFTP clients see as `/`; the first ISO search path when
+    /// unset
+    #[serde(default)]
+    pub sftp_root: Option<PathBuf>,
+    #[serde(default)]
+    pub keys: SshKeysConfig,
+}
+
+/// Sources `authorized_keys_path` is written from, together with the keys
+/// added and revoked through the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshKeysConfig {
+    /// `authorized_keys` lines
+    pub keys: Vec<String>,
+    /// URLs serving an `authorized_keys` file
+    pub urls: Vec<String>,
+    /// GitHub accounts whose public keys are authorized
+    pub github_users: Vec<String>,
+    /// Hours between fetching `urls` and `github_users` again, so rotated
+    /// keys reach the node; 0 fetches at startup only
+    pub refresh_hours: u64,
+    /// Keys added and revoked through the API
+    pub state_path: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WebVncConfig {
     pub enabled: bool,
     pub port: u16,
     pub https: bool,
     pub cert_path: Option<PathBuf>,
     pub key_path: Option<PathBuf>,
+    /// noVNC client served on `port`; the WebSocket proxy at `/websockify`
+    /// works without it
+    pub assets_dir: PathBuf,
+    /// Connections need a session opened through the API with `username`
+    /// and `password`, plus a TOTP code once the user has enrolled
     pub auth_required: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub session_timeout: u64,
+}
+
+/// Serial consoles of install targets, bridged to WebSocket terminals
+/// through the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialConfig {
+    pub enabled: bool,
+    /// Console output is appended to `<name>.log` here
+    pub log_dir: PathBuf,
+    pub ports: Vec<SerialPortConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialPortConfig {
+    /// Used in the API path, e.g. `rack1`
+    pub name: String,
+    /// `/dev/ttyUSB0`, `/dev/ttyS1`, or a stable `/dev/serial/by-id` link
+    pub device: PathBuf,
+    pub baud: u32,
+    /// Role needed to attach
+    pub role: Role,
+    /// Only watch the output, never type
+    pub read_only: bool,
+    /// Token or user names allowed on this port; empty allows every one
+    /// with `role`
+    pub users: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,6 +464,251 @@ pub struct IsoConfig {
     pub mount_point: PathBuf,
     pub auto_mount: bool,
     pub auto_launch: bool,
+    #[serde(default)]
+    pub windows: WindowsConfig,
+    #[serde(default)]
+    pub download: DownloadConfig,
+    #[serde(default)]
+    pub unattended: UnattendedConfig,
+    #[serde(default)]
+    pub ventoy: VentoyConfig,
+    #[serde(default)]
+    pub catalog: CatalogConfig,
+    #[serde(default)]
+    pub mounts: MountConfig,
+    #[serde(default)]
+    pub feeds: FeedsConfig,
+    #[serde(default)]
+    pub netboot: NetbootConfig,
+    #[serde(default)]
+    pub installer: InstallerConfig,
+    #[serde(default)]
+    pub post_install: PostInstallConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub sync: RepoSyncConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// Mirror base URLs; the fastest responding mirror is tried first
+    pub mirrors: Vec<String>,
+    /// Defaults to the first ISO search path
+    pub target_dir: Option<PathBuf>,
+    /// Bandwidth limit in KiB/s
+    pub rate_limit_kbps: Option<u64>,
+    pub retries: u32,
+    pub connect_timeout: u64,
+    #[cfg(feature = "torrent")]
+    pub torrent: TorrentConfig,
+}
+
+/// Hands-off installations; the answers come from `[target]`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnattendedConfig {
+    pub enabled: bool,
+    /// Partition (labelled OEMDRV or CIDATA) that receives the answer
+    /// files instead of the installer media
+    pub side_partition: Option<String>,
+    /// Directory of `<name>.j2` files replacing the built-in templates
+    /// (`preseed.cfg.j2`, `ks.cfg.j2`, `user-data.j2`, `meta-data.j2`,
+    /// `autounattend.xml.j2`)
+    pub template_dir: Option<PathBuf>,
+}
+
+/// Copying ISOs onto sticks that already run Ventoy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VentoyConfig {
+    /// Copy ISOs instead of repartitioning when the target runs Ventoy
+    pub enabled: bool,
+    /// Directory on the Ventoy data partition; the root when unset
+    pub directory: Option<PathBuf>,
+    /// Check sources against `<iso>.sha256` or `SHA256SUMS` and re-hash
+    /// the copies after remounting
+    pub verify: bool,
+}
+
+/// Identified ISOs, kept across restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CatalogConfig {
+    pub path: PathBuf,
+}
+
+/// Lifetime of loop mounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MountConfig {
+    /// Unmount images nobody has used for this long; 0 keeps them mounted
+    pub idle_unmount_secs: u64,
+}
+
+/// Downloaded ISOs kept on local storage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Size limit of the download directory; least recently used ISOs
+    /// that are not pinned are deleted to stay under it
+    pub quota_mb: Option<u64>,
+    /// File names that are never evicted
+    pub pinned: Vec<String>,
+    /// Last use and pins of the cached ISOs
+    pub index_path: PathBuf,
+}
+
+/// Mirroring the ISO directory from a central repository
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoSyncConfig {
+    /// Base URL of the repository; `<url>/manifest.json` lists its images
+    pub url: Option<String>,
+    /// Defaults to the download directory
+    pub target_dir: Option<PathBuf>,
+    /// Hours between syncs; 0 syncs only on request
+    pub interval_hours: u64,
+    /// Delete local ISOs the repository no longer lists
+    pub delete_removed: bool,
+    pub connect_timeout: u64,
+    /// Sizes and checksums of synced ISOs, so unchanged ones are not
+    /// hashed again
+    pub state_path: PathBuf,
+}
+
+/// Kernels and initrds extracted for network boot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetbootConfig {
+    pub cache_dir: PathBuf,
+}
+
+/// Limits for each stage of an installer process; it is killed when one
+/// is exceeded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstallerConfig {
+    /// Seconds from launch until the installer prints its first line
+    pub start_timeout_secs: u64,
+    /// Seconds from the first line until the installer closes its output
+    pub install_timeout_secs: u64,
+    /// Seconds from closing its output until the installer exits
+    pub finish_timeout_secs: u64,
+    /// Installer media the built-in detection does not know
+    pub detectors: Vec<DetectorConfig>,
+}
+
+/// Media recognized by marker files (`[[iso.installer.detectors]]`), so
+/// appliances such as pfSense or TrueNAS need no code changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectorConfig {
+    pub os_type: String,
+    /// Shown in the installer list
+    pub name: String,
+    /// Paths relative to the media root that must all exist, matched
+    /// ignoring case
+    pub markers: Vec<String>,
+    /// Program and arguments that run the installer, `{path}` standing for
+    /// the mount path. Without one the installer is listed but not started.
+    #[serde(default)]
+    pub command: Vec<String>,
+}
+
+/// Hooks run against the installed system after a successful install
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostInstallConfig {
+    /// Where the target's root filesystem is mounted while hooks run
+    pub mount_dir: PathBuf,
+    pub hooks: Vec<PostInstallHook>,
+}
+
+/// Vendor release feeds checked for new images
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeedsConfig {
+    pub sources: Vec<FeedSource>,
+    /// Products downloaded as soon as a feed lists a newer release
+    pub auto_download: Vec<CatalogQuery>,
+    /// Hours between syncs; 0 syncs only on request
+    pub interval_hours: u64,
+    /// Refuse feeds without a signature and keyring
+    pub require_signatures: bool,
+    /// Where fetched feeds and signatures are kept
+    pub cache_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedSource {
+    pub name: String,
+    pub format: FeedFormat,
+    pub url: String,
+    /// Detached OpenPGP signature of the feed document
+    pub signature: Option<String>,
+    /// Keyring the signature must verify against (gpgv)
+    pub keyring: Option<PathBuf>,
+    /// Distribution for images whose file names do not tell
+    pub distro: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    /// `SHA256SUMS` listing (Debian, Ubuntu)
+    Sha256sums,
+    /// Fedora `releases.json`
+    Fedora,
+    /// `{"releases": [...]}` with distro, version, arch, variant, url, sha256
+    Index,
+}
+
+/// The system to install, shared by every answer file format
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetConfig {
+    pub hostname: String,
+    pub domain: String,
+    pub locale: String,
+    pub keyboard: String,
+    /// IANA zone, mapped to a Windows zone ID for Windows installs
+    pub timezone: String,
+    pub packages: Vec<String>,
+    pub user: TargetUser,
+    pub disk: TargetDisk,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetUser {
+    pub username: String,
+    pub full_name: String,
+    /// crypt(3) hash, e.g. from `mkpasswd -m sha-512`
+    pub password_hash: Option<String>,
+    /// Local account password for Windows, which cannot use crypt hashes
+    pub windows_password: Option<String>,
+    pub ssh_authorized_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetDisk {
+    /// Disk the installer wipes; the installer picks one when unset
+    pub device: Option<String>,
+    /// Disk number Windows Setup wipes
+    pub windows_id: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WindowsConfig {
+    /// Edition index, edition ID or name from install.wim
+    pub edition: Option<String>,
+    pub product_key: Option<String>,
+    /// Activate against KMS, using the edition's client key if no key is set
+    #[serde(default)]
+    pub kms: bool,
+    pub kms_host: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -91,6 +717,21 @@ pub struct UiConfig {
     pub language: String,
     pub fullscreen: bool,
     pub show_logs: bool,
+    #[serde(default)]
+    pub mode: UiMode,
+}
+
+/// Local interface to present
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UiMode {
+    /// Graphical when a display is available, else console, else web-only
+    #[default]
+    Auto,
+    Graphical,
+    Console,
+    /// No local interface; API and WebVNC only
+    Web,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,6 +741,18 @@ pub struct LoggingConfig {
     pub console: bool,
     pub max_file_size: u64,
     pub max_files: u32,
+    #[serde(default)]
+    pub progress: ProgressLogConfig,
+}
+
+/// How often long-running jobs log their progress
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProgressLogConfig {
+    /// Seconds between lines for a job phase that advances slowly
+    pub interval_secs: u64,
+    /// Percent a phase must advance for a line before the interval is up
+    pub percent_step: u8,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -117,6 +770,69 @@ pub struct DiskConfig {
     pub auto_partition: bool,
     pub partition_scheme: PartitionScheme,
     pub default_filesystem: String,
+    #[serde(default)]
+    pub windows_usb: WindowsUsbConfig,
+    #[serde(default)]
+    pub differential: DifferentialConfig,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    /// Digest for write verification and differential manifests
+    #[serde(default)]
+    pub checksum: HashAlgorithm,
+    #[serde(default)]
+    pub bootstrap: BootstrapConfig,
+}
+
+/// Minimal system installed straight onto a target, without a distro installer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BootstrapConfig {
+    pub tool: BootstrapTool,
+    /// Debian release for debootstrap; pacstrap always installs Arch's current state
+    pub suite: String,
+    /// Debian mirror; pacstrap uses the node's pacman mirror list
+    pub mirror: Option<String>,
+    /// Installed on top of the base system, kernel and bootloader
+    pub packages: Vec<String>,
+    pub hostname: String,
+    /// Shell script run inside the new system as the last step
+    pub post_install: Option<PathBuf>,
+    pub esp_size_mb: u64,
+}
+
+/// What to do when a target holds encrypted data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    pub policy: EncryptionPolicy,
+    /// Seconds an administrator's approval to overwrite a device stays valid
+    pub approval_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionPolicy {
+    /// Log a warning and overwrite
+    #[default]
+    Warn,
+    /// Refuse unless an administrator approved the device
+    Block,
+}
+
+/// Skip job phases whose result is already on the target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DifferentialConfig {
+    pub enabled: bool,
+    /// Where the state written to each target is recorded
+    pub state_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowsUsbConfig {
+    pub uefi_ntfs_image: PathBuf,
+    pub esp_size_mb: u64,
+    pub work_dir: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,11 +850,364 @@ pub struct ServiceConfig {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MonitoringConfig {
+    pub enabled: bool,
+    /// Seconds between health checks
+    #[serde(alias = "check_interval")]
     pub watchdog_interval: u64,
+    /// Failed checks in a row before a service counts as down
+    pub max_failures: u32,
+    /// Restart services that are down
+    pub auto_restart: bool,
+    /// Restarts without a passing check in between before giving up
     pub max_restart_attempts: u32,
+    /// Seconds before the second restart, doubled for each one after
     pub restart_delay: u64,
+    /// Enter safe mode once a service is given up on: install jobs and
+    /// disk writes stop, remote access and the API stay up
+    pub safe_mode: bool,
     pub metrics_port: Option<u16>,
+    #[serde(default)]
+    pub traffic: TrafficMetricsConfig,
+    /// Webhooks, mail, Slack and Matrix rooms alerts are sent to
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+    #[serde(default)]
+    pub history: MetricsHistoryConfig,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    #[serde(default)]
+    pub thermal: ThermalConfig,
+}
+
+/// Per-interface byte, packet and error counters, for diagnosing slow
+/// downloads remotely
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrafficMetricsConfig {
+    pub enabled: bool,
+    /// Seconds between samples; throughput is averaged over this window
+    pub interval: u64,
+    /// Every interface but loopback when empty
+    pub interfaces: Vec<String>,
+}
+
+/// Comparison of the system clock against NTP servers or the fleet
+/// controller; a skewed clock breaks TLS for downloads and tunnels
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockConfig {
+    pub enabled: bool,
+    /// NTP servers asked in order, as `host` or `host:port`
+    pub servers: Vec<String>,
+    /// Fall back to the `Date` header of `[heartbeat] url` when no NTP
+    /// server answers
+    pub use_controller: bool,
+    /// Seconds of skew in either direction before an alert is raised
+    pub max_skew_secs: u64,
+    /// Seconds between checks
+    pub interval_secs: u64,
+    /// Seconds to wait for an answer
+    pub timeout_secs: u64,
+}
+
+/// SoC temperature, throttling and undervoltage of single-board computers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalConfig {
+    pub enabled: bool,
+    /// Seconds between readings
+    pub interval_secs: u64,
+    /// Hottest zone in °C above which an alert is raised
+    pub max_temp_c: f64,
+}
+
+/// Metric samples kept for the history graphs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsHistoryConfig {
+    /// Oldest samples are dropped beyond this many
+    pub max_samples: usize,
+    /// Seconds a sample is kept
+    pub retention_secs: u64,
+    /// JSON lines file the samples are appended to and read back from on
+    /// start; memory only when unset
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub bind_address: String,
+    pub port: u16,
+    /// Bearer token with the admin role, named `admin`; `[auth]` has
+    /// tokens for other roles
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+/// Subsystem bring-up at node start
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartupConfig {
+    /// Seconds a subsystem may take to start before it is marked degraded
+    pub timeout_secs: u64,
+    /// Per-subsystem overrides in seconds, e.g. `network = 90`
+    pub timeouts: HashMap<String, u64>,
+    /// Seconds a subsystem may take to stop before shutdown moves on
+    pub stop_timeout_secs: u64,
+    /// Per-subsystem stop timeouts in seconds
+    pub stop_timeouts: HashMap<String, u64>,
+}
+
+/// Network boot of the node's images by machines on the LAN
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PxeConfig {
+    pub enabled: bool,
+    /// Address clients reach the node on; defaults to the provisioning address
+    pub server_address: Ipv4Addr,
+    /// Answer PXE clients alongside the LAN's DHCP server, which keeps
+    /// handing out the addresses
+    pub proxy_dhcp: bool,
+    /// Directory with the iPXE binaries served over TFTP
+    pub tftp_root: PathBuf,
+    pub bios_boot_file: String,
+    pub uefi_boot_file: String,
+    /// Port of the HTTP server for menus, kernels and images
+    pub http_port: u16,
+    /// Seconds the menu waits before booting from the local disk
+    pub menu_timeout_secs: u64,
+}
+
+/// Authentication and roles of API and WebVNC users
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Bearer tokens for API clients; `api.admin_token` is an admin token
+    /// named `admin`
+    pub tokens: Vec<ApiToken>,
+    /// Requests without a token may read status, inventory and reports
+    pub allow_anonymous_read: bool,
+    /// Lifetime of a session token
+    pub session_secs: u64,
+    pub totp: TotpConfig,
+    pub bans: BanConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Shown in logs, and the TOTP account for the token's user
+    pub name: String,
+    pub token: String,
+    pub role: Role,
+}
+
+/// What a token may do; each role includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Status, inventory, jobs and reports
+    Viewer,
+    /// Install jobs, ISO maintenance, identify and WebVNC
+    Operator,
+    /// Disk overwrites, SSH keys and TOTP enrollment
+    Admin,
+}
+
+/// Time-based one-time codes (RFC 6238) as a second factor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TotpConfig {
+    /// Once a user has enrolled, their API token only opens sessions, with
+    /// a code, and the WebVNC password needs a code too
+    pub enabled: bool,
+    /// Name authenticator apps show next to the account
+    pub issuer: String,
+    /// Enrolled secrets, readable by root only
+    pub secrets_path: PathBuf,
+    /// Wrong codes in a row before the user is locked out
+    pub max_failures: u32,
+    pub lockout_secs: u64,
+}
+
+/// Temporary bans of addresses that keep failing to sign in to SSH,
+/// WebVNC or the API, enforced by the node and, when enabled, the firewall
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BanConfig {
+    pub enabled: bool,
+    /// Failed sign-ins from one address within `window_secs` that earn a ban
+    pub max_failures: u32,
+    pub window_secs: u64,
+    pub ban_secs: u64,
+    /// Addresses or CIDRs never banned; loopback never is
+    pub ignore: Vec<String>,
+}
+
+/// ISO uploads and log downloads over the API, and the same shares over
+/// SFTP, so operators need no shell on the node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransferConfig {
+    pub enabled: bool,
+    /// Largest ISO accepted by an upload
+    pub max_upload_mb: u64,
+    /// Files and directories operators may download, by share name; SFTP
+    /// shows them read-only under `/files`
+    pub shares: BTreeMap<String, PathBuf>,
+}
+
+/// MQTT client for fleet infrastructure: status, metrics and alerts are
+/// published under `<topic_prefix>/<node>/`, commands are taken from
+/// `<topic_prefix>/<node>/command/<name>` and `<topic_prefix>/all/command/<name>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub enabled: bool,
+    /// `host:port` of the broker
+    pub broker: String,
+    /// Connect over TLS; off only for a broker on a trusted network
+    pub tls: bool,
+    /// CA certificates (PEM) the broker certificate is checked against;
+    /// the system roots when unset
+    pub ca_file: Option<PathBuf>,
+    /// Topic level naming this node; defaults to the hostname
+    pub node_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic_prefix: String,
+    pub keep_alive_secs: u16,
+    /// Seconds between status and metrics messages
+    pub publish_interval_secs: u64,
+    /// Seconds to wait before connecting again after the broker went away
+    pub reconnect_secs: u64,
+    /// Act on command messages; off, the node only reports
+    pub commands: bool,
+    /// Allow the `reboot` command
+    pub allow_reboot: bool,
+}
+
+/// Periodic POST of node identity, health and jobs to a fleet controller,
+/// for nodes the controller cannot reach
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    pub enabled: bool,
+    /// Controller endpoint the heartbeat is POSTed to
+    pub url: String,
+    /// Name the node reports; defaults to the hostname
+    pub node_id: Option<String>,
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: Option<String>,
+    /// Seconds between heartbeats
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    /// Longest wait between retries of a failed heartbeat
+    pub max_backoff_secs: u64,
+}
+
+/// Thresholds and targets of the `diagnose` self-test
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiagnoseConfig {
+    /// Free space the ISO download directory needs
+    pub min_free_mb: u64,
+    /// `host:port` that must accept a TCP connection; without any, a
+    /// default route is enough
+    pub targets: Vec<String>,
+    /// Seconds to wait for each target
+    pub timeout_secs: u64,
+}
+
+/// Job history and the operator shift reports built from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReportsConfig {
+    /// Append-only JSON lines file every finished job is recorded in
+    pub history_path: PathBuf,
+    /// Length of a shift when a report is requested without a start time
+    pub shift_hours: u64,
+    /// Mail the HTML report of the past shift every `shift_hours`
+    pub mail: bool,
+    /// Email notifiers from `[[monitoring.notifiers]]` that get the
+    /// report; every email notifier when empty
+    pub mail_notifiers: Vec<String>,
+}
+
+/// Tamper-evident record of API calls, SSH sessions, configuration
+/// changes and destructive disk and ISO actions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Append-only JSON lines file; each entry hashes the one before
+    pub path: PathBuf,
+    /// Also record API reads, which dashboards send every few seconds
+    pub record_reads: bool,
+}
+
+/// Install jobs that run an ISO installer onto a target disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// Each job's state is kept here so it survives a crash or power loss
+    pub state_dir: PathBuf,
+    /// Jobs that run at once, each on its own device
+    pub max_concurrent: u32,
+    /// Resume jobs that were interrupted from the step they stopped in;
+    /// otherwise they wait for a resume or restart request
+    pub resume_interrupted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdentifyConfig {
+    /// LED names under /sys/class/leds; empty selects keyboard LEDs
+    pub leds: Vec<String>,
+    pub beep: bool,
+    pub flash_console: bool,
+    pub console: PathBuf,
+    pub default_duration: u64,
+    pub max_duration: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ButtonConfig {
+    pub enabled: bool,
+    pub source: ButtonSource,
+    pub job: ButtonJob,
+    /// LED name under /sys/class/leds used for status patterns
+    pub led: Option<String>,
+    pub debounce_ms: u64,
+    /// Presses held longer than this are ignored
+    pub long_press_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ButtonSource {
+    /// Key on an input device, e.g. the power button or a gpio-keys button
+    Evdev { device: PathBuf, key_code: u16 },
+    /// Raw GPIO line watched with `gpiomon`
+    Gpio {
+        chip: String,
+        line: u32,
+        active_low: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonJob {
+    /// Partition and format the stick per `[disk]`
+    PrepareDisk,
+    /// Write the first available Windows ISO to the stick
+    WindowsUsb,
+    /// Install a minimal system onto the stick per `[disk.bootstrap]`
+    Bootstrap,
 }
 
 impl Config {
@@ -213,43 +1282,233 @@ impl Config {
             .into());
         }
 
-        if self.remote.vnc.port == 0 || self.remote.vnc.port > 65535 {
-            return Err(ConfigError::ValidationFailed("Invalid VNC port".to_string()).into());
+        for (i, entry) in self.network.static_addresses.iter().enumerate() {
+            if entry.interface.is_empty() || !entry.address.contains('/') {
+                return Err(ConfigError::ValidationFailed(
+                    "Static addresses need an interface and an address/prefix".to_string(),
+                )
+                .into());
+            }
+            if self.network.static_addresses[..i]
+                .iter()
+                .any(|other| other.interface == entry.interface)
+            {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "Interface {} has more than one static address",
+                    entry.interface
+                ))
+                .into());
+            }
         }
 
-        if self.remote.ssh.port == 0 || self.remote.ssh.port > 65535 {
-            return Err(ConfigError::ValidationFailed("Invalid SSH port".to_string()).into());
+        let mut links: Vec<String> = Vec::new();
+        for vlan in &self.network.vlans {
+            if vlan.parent.is_empty() || vlan.id == 0 || vlan.id > 4094 {
+                return Err(ConfigError::ValidationFailed(
+                    "VLANs need a parent interface and an ID from 1 to 4094".to_string(),
+                )
+                .into());
+            }
+            links.push(vlan.interface());
         }
-
-        if self.remote.web_vnc.port == 0 || self.remote.web_vnc.port > 65535 {
-            return Err(ConfigError::ValidationFailed("Invalid Web VNC port".to_string()).into());
+        for bridge in &self.network.bridges {
+            if bridge.name.is_empty() {
+                return Err(
+                    ConfigError::ValidationFailed("Bridges need a name".to_string()).into(),
+                );
+            }
+            links.push(bridge.name.clone());
+        }
+        for (i, link) in links.iter().enumerate() {
+            // IFNAMSIZ, including the terminating NUL
+            if link.len() > 15 || links[..i].contains(link) {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "Interface name {} is too long or used twice",
+                    link
+                ))
+                .into());
+            }
         }
 
-        if self.remote.web_vnc.https
-            && (self.remote.web_vnc.cert_path.is_none() || self.remote.web_vnc.key_path.is_none())
-        {
+        if self.network.link_local.enabled && self.network.link_local.retry_interval == 0 {
             return Err(ConfigError::ValidationFailed(
-                "HTTPS requires cert and key paths".to_string(),
+                "Link-local DHCP retry interval must be > 0".to_string(),
             )
             .into());
         }
 
-        if self.iso.search_paths.is_empty() {
+        let failover = &self.network.failover;
+        for (i, interface) in failover.interfaces.iter().enumerate() {
+            if interface.is_empty() || failover.interfaces[..i].contains(interface) {
+                return Err(ConfigError::ValidationFailed(
+                    "Failover interfaces must be named and listed once".to_string(),
+                )
+                .into());
+            }
+        }
+        if !failover.interfaces.is_empty() && failover.check_interval == 0 {
             return Err(ConfigError::ValidationFailed(
-                "ISO search paths cannot be empty".to_string(),
+                "Failover check interval must be > 0".to_string(),
             )
             .into());
         }
-
-        if self.monitoring.watchdog_interval == 0 {
-            return Err(
-                ConfigError::ValidationFailed("Watchdog interval must be > 0".to_string()).into(),
-            );
+        let wireguard = &self.network.tunnel.wireguard;
+        if self.network.tunnel.enabled && wireguard.embedded {
+            if wireguard.private_key.is_none() || !wireguard.address.contains('/') {
+                return Err(ConfigError::ValidationFailed(
+                    "Embedded WireGuard needs a private key and an address/prefix".to_string(),
+                )
+                .into());
+            }
+            if wireguard.peers.is_empty()
+                || wireguard
+                    .peers
+                    .iter()
+                    .any(|peer| peer.public_key.is_empty() || peer.allowed_ips.is_empty())
+            {
+                return Err(ConfigError::ValidationFailed(
+                    "WireGuard peers need a public key and allowed IPs".to_string(),
+                )
+                .into());
+            }
         }
 
-        if self.monitoring.max_restart_attempts == 0 {
+        if self.monitoring.traffic.enabled && self.monitoring.traffic.interval == 0 {
             return Err(ConfigError::ValidationFailed(
-                "Max restart attempts must be > 0".to_string(),
+                "Traffic metrics interval must be > 0".to_string(),
+            )
+            .into());
+        }
+
+        if self.monitoring.clock.enabled && self.monitoring.clock.interval_secs == 0 {
+            return Err(ConfigError::ValidationFailed(
+                "Clock check interval must be > 0".to_string(),
+            )
+            .into());
+        }
+
+        if self.monitoring.thermal.enabled && self.monitoring.thermal.interval_secs == 0 {
+            return Err(ConfigError::ValidationFailed(
+                "Thermal monitoring interval must be > 0".to_string(),
+            )
+            .into());
+        }
+
+        let probe = &self.network.tunnel.probe;
+        if let Some(target) = &probe.target {
+            if !["tcp://", "http://", "https://"]
+                .iter()
+                .any(|scheme| target.starts_with(scheme))
+            {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "Tunnel probe must be tcp://, http:// or https://: {}",
+                    target
+                ))
+                .into());
+            }
+        }
+        if probe.timeout == 0 {
+            return Err(ConfigError::ValidationFailed(
+                "Tunnel probe timeout must be > 0".to_string(),
+            )
+            .into());
+        }
+
+        let ssh = &self.network.tunnel.ssh;
+        if ssh.jump_host.is_some() {
+            if let Some(service) = ssh
+                .publish
+                .iter()
+                .find(|service| !["api", "vnc", "ssh"].contains(&service.as_str()))
+            {
+                return Err(ConfigError::ValidationFailed(format!(
+                    "Unknown service to publish: {}",
+                    service
+                ))
+                .into());
+            }
+            if ssh.keepalive_interval == 0 {
+                return Err(ConfigError::ValidationFailed(
+                    "SSH keepalive interval must be > 0".to_string(),
+                )
+                .into());
+            }
+        }
+
+        if self.network.discovery.enabled && self.network.discovery.query_interval == 0 {
+            return Err(ConfigError::ValidationFailed(
+                "Discovery query interval must be > 0".to_string(),
+            )
+            .into());
+        }
+
+        if self.remote.vnc.port == 0 || self.remote.vnc.port > 65535 {
+            return Err(ConfigError::ValidationFailed("Invalid VNC port".to_string()).into());
+        }
+
+        if self.remote.ssh.port == 0 || self.remote.ssh.port > 65535 {
+            return Err(ConfigError::ValidationFailed("Invalid SSH port".to_string()).into());
+        }
+
+        if self.remote.web_vnc.port == 0 || self.remote.web_vnc.port > 65535 {
+            return Err(ConfigError::ValidationFailed("Invalid Web VNC port".to_string()).into());
+        }
+
+        if self.remote.web_vnc.https
+            && (self.remote.web_vnc.cert_path.is_none() || self.remote.web_vnc.key_path.is_none())
+        {
+            return Err(ConfigError::ValidationFailed(
+                "HTTPS requires cert and key paths".to_string(),
+            )
+            .into());
+        }
+
+        if self.remote.web_vnc.enabled
+            && self.remote.web_vnc.auth_required
+            && (self.remote.web_vnc.username.is_none() || self.remote.web_vnc.password.is_none())
+        {
+            return Err(ConfigError::ValidationFailed(
+                "Web VNC authentication requires a username and password".to_string(),
+            )
+            .into());
+        }
+
+        if self.iso.search_paths.is_empty() {
+            return Err(ConfigError::ValidationFailed(
+                "ISO search paths cannot be empty".to_string(),
+            )
+            .into());
+        }
+
+        if self.api.enabled && self.api.port == 0 {
+            return Err(ConfigError::ValidationFailed("Invalid API port".to_string()).into());
+        }
+
+        if self.pxe.enabled && self.pxe.http_port == 0 {
+            return Err(ConfigError::ValidationFailed(
+                "Invalid network boot HTTP port".to_string(),
+            )
+            .into());
+        }
+
+        if self.identify.default_duration == 0
+            || self.identify.default_duration > self.identify.max_duration
+        {
+            return Err(ConfigError::ValidationFailed(
+                "Identify duration must be > 0 and <= max_duration".to_string(),
+            )
+            .into());
+        }
+
+        if self.monitoring.watchdog_interval == 0 {
+            return Err(
+                ConfigError::ValidationFailed("Watchdog interval must be > 0".to_string()).into(),
+            );
+        }
+
+        if self.monitoring.max_restart_attempts == 0 {
+            return Err(ConfigError::ValidationFailed(
+                "Max restart attempts must be > 0".to_string(),
             )
             .into());
         }
@@ -269,6 +1528,24 @@ impl Default for Config {
             disk: DiskConfig::default(),
             service: ServiceConfig::default(),
             monitoring: MonitoringConfig::default(),
+            api: ApiConfig::default(),
+            identify: IdentifyConfig::default(),
+            button: ButtonConfig::default(),
+            target: TargetConfig::default(),
+            startup: StartupConfig::default(),
+            reports: ReportsConfig::default(),
+            jobs: JobsConfig::default(),
+            pxe: PxeConfig::default(),
+            auth: AuthConfig::default(),
+            transfer: TransferConfig::default(),
+            mqtt: MqttConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            diagnose: DiagnoseConfig::default(),
+            audit: AuditConfig::default(),
+            #[cfg(feature = "grpc")]
+            grpc: GrpcConfig::default(),
+            #[cfg(feature = "chaos")]
+            chaos: ChaosConfig::default(),
         }
     }
 }
@@ -279,8 +1556,95 @@ impl Default for NetworkConfig {
             interface: None,
             dhcp_timeout: 30,
             hostname_prefix: "usb-node".to_string(),
+            hostname_strategy: HostnameStrategy::default(),
             mdns_enabled: true,
+            offline: false,
             tunnel: TunnelConfig::default(),
+            provisioning: ProvisioningConfig::default(),
+            static_addresses: Vec::new(),
+            ipv6: Ipv6Config::default(),
+            link_local: LinkLocalConfig::default(),
+            firewall: FirewallConfig::default(),
+            failover: FailoverConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            vlans: Vec::new(),
+            bridges: Vec::new(),
+        }
+    }
+}
+
+impl Default for LinkLocalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retry_interval: 60,
+        }
+    }
+}
+
+impl Default for FirewallConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allowed_sources: Vec::new(),
+            allowed_tcp: Vec::new(),
+            allowed_udp: Vec::new(),
+        }
+    }
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            interfaces: Vec::new(),
+            check_interval: 5,
+            base_metric: 100,
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            query_interval: 60,
+        }
+    }
+}
+
+impl Default for Ipv6Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dhcpv6: false,
+            timeout: 10,
+        }
+    }
+}
+
+impl Default for StaticAddressConfig {
+    fn default() -> Self {
+        Self {
+            interface: String::new(),
+            address: String::new(),
+            gateway: None,
+            dns_servers: Vec::new(),
+            search_domains: Vec::new(),
+            fallback_to_dhcp: true,
+        }
+    }
+}
+
+impl Default for ProvisioningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interface: None,
+            address: "10.42.0.1/24".to_string(),
+            forwarding: Forwarding::None,
+            uplink: None,
+            allowed_udp: vec![53, 67, 69, 4011],
+            allowed_tcp: vec![80, 8081],
         }
     }
 }
@@ -292,6 +1656,47 @@ impl Default for TunnelConfig {
             provider: TunnelProvider::Tailscale,
             config_path: None,
             reconnect_interval: 60,
+            wireguard: WireguardConfig::default(),
+            ssh: SshTunnelConfig::default(),
+            probe: TunnelProbeConfig::default(),
+        }
+    }
+}
+
+impl Default for TunnelProbeConfig {
+    fn default() -> Self {
+        Self {
+            target: None,
+            timeout: 5,
+            // WireGuard rejects sessions older than this
+            max_handshake_age: 180,
+        }
+    }
+}
+
+impl Default for SshTunnelConfig {
+    fn default() -> Self {
+        Self {
+            jump_host: None,
+            port: 22,
+            identity_file: None,
+            publish: vec!["api".to_string(), "vnc".to_string(), "ssh".to_string()],
+            registration_url: None,
+            keepalive_interval: 30,
+        }
+    }
+}
+
+impl Default for WireguardConfig {
+    fn default() -> Self {
+        Self {
+            embedded: false,
+            interface: "wg-node".to_string(),
+            private_key: None,
+            address: String::new(),
+            listen_port: None,
+            mtu: 1420,
+            peers: Vec::new(),
         }
     }
 }
@@ -302,6 +1707,7 @@ impl Default for RemoteConfig {
             vnc: VncConfig::default(),
             ssh: SshConfig::default(),
             web_vnc: WebVncConfig::default(),
+            serial: SerialConfig::default(),
         }
     }
 }
@@ -314,6 +1720,8 @@ impl Default for VncConfig {
             display: ":0".to_string(),
             password: None,
             view_only: false,
+            backend: DisplayBackend::Auto,
+            headless_screen: "1280x800x24".to_string(),
         }
     }
 }
@@ -323,9 +1731,22 @@ impl Default for SshConfig {
         Self {
             enabled: false,
             port: 22,
-            key_path: PathBuf::from("/etc/ssh/ssh_host_rsa_key"),
+            key_path: PathBuf::from("/var/lib/usb-installer-node/ssh_host_ed25519_key"),
             authorized_keys_path: PathBuf::from("/root/.ssh/authorized_keys"),
-            password_auth: false,
+            sftp_root: None,
+            keys: SshKeysConfig::default(),
+        }
+    }
+}
+
+impl Default for SshKeysConfig {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            urls: Vec::new(),
+            github_users: Vec::new(),
+            refresh_hours: 6,
+            state_path: PathBuf::from("/var/lib/usb-installer-node/ssh-keys.json"),
         }
     }
 }
@@ -338,7 +1759,34 @@ impl Default for WebVncConfig {
             https: false,
             cert_path: None,
             key_path: None,
+            assets_dir: PathBuf::from("/usr/share/novnc"),
             auth_required: true,
+            username: None,
+            password: None,
+            session_timeout: 3600,
+        }
+    }
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            log_dir: PathBuf::from("/var/log/usb-installer-node/serial"),
+            ports: Vec::new(),
+        }
+    }
+}
+
+impl Default for SerialPortConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            device: PathBuf::new(),
+            baud: 115200,
+            role: Role::Operator,
+            read_only: false,
+            users: Vec::new(),
         }
     }
 }
@@ -351,6 +1799,148 @@ impl Default for IsoConfig {
             mount_point: PathBuf::from("/mnt/iso"),
             auto_mount: true,
             auto_launch: false,
+            windows: WindowsConfig::default(),
+            download: DownloadConfig::default(),
+            unattended: UnattendedConfig::default(),
+            ventoy: VentoyConfig::default(),
+            catalog: CatalogConfig::default(),
+            mounts: MountConfig::default(),
+            cache: CacheConfig::default(),
+            sync: RepoSyncConfig::default(),
+            feeds: FeedsConfig::default(),
+            netboot: NetbootConfig::default(),
+            installer: InstallerConfig::default(),
+            post_install: PostInstallConfig::default(),
+        }
+    }
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            mirrors: Vec::new(),
+            target_dir: None,
+            rate_limit_kbps: None,
+            retries: 3,
+            connect_timeout: 15,
+            #[cfg(feature = "torrent")]
+            torrent: TorrentConfig::default(),
+        }
+    }
+}
+
+impl Default for VentoyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: None,
+            verify: true,
+        }
+    }
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("/var/lib/usb-installer-node/iso-catalog.json"),
+        }
+    }
+}
+
+impl Default for MountConfig {
+    fn default() -> Self {
+        Self {
+            idle_unmount_secs: 600,
+        }
+    }
+}
+
+impl Default for RepoSyncConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            target_dir: None,
+            interval_hours: 6,
+            delete_removed: false,
+            connect_timeout: 30,
+            state_path: PathBuf::from("/var/lib/usb-installer-node/repo-sync.json"),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            quota_mb: None,
+            pinned: Vec::new(),
+            index_path: PathBuf::from("/var/lib/usb-installer-node/iso-cache.json"),
+        }
+    }
+}
+
+impl Default for NetbootConfig {
+    fn default() -> Self {
+        Self {
+            cache_dir: PathBuf::from("/var/lib/usb-installer-node/netboot"),
+        }
+    }
+}
+
+impl Default for InstallerConfig {
+    fn default() -> Self {
+        Self {
+            start_timeout_secs: 120,
+            install_timeout_secs: 4 * 3600,
+            finish_timeout_secs: 600,
+            detectors: Vec::new(),
+        }
+    }
+}
+
+impl Default for PostInstallConfig {
+    fn default() -> Self {
+        Self {
+            mount_dir: PathBuf::from("/mnt/usb-installer-hooks"),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl Default for FeedsConfig {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            auto_download: Vec::new(),
+            interval_hours: 24,
+            require_signatures: true,
+            cache_dir: PathBuf::from("/var/lib/usb-installer-node/feeds"),
+        }
+    }
+}
+
+impl Default for TargetConfig {
+    fn default() -> Self {
+        Self {
+            hostname: "node".to_string(),
+            domain: "local".to_string(),
+            locale: "en_US.UTF-8".to_string(),
+            keyboard: "us".to_string(),
+            timezone: "UTC".to_string(),
+            packages: Vec::new(),
+            user: TargetUser::default(),
+            disk: TargetDisk::default(),
+        }
+    }
+}
+
+impl Default for TargetUser {
+    fn default() -> Self {
+        Self {
+            username: "installer".to_string(),
+            full_name: "Installer".to_string(),
+            password_hash: None,
+            windows_password: None,
+            ssh_authorized_keys: Vec::new(),
         }
     }
 }
@@ -362,6 +1952,7 @@ impl Default for UiConfig {
             language: "en".to_string(),
             fullscreen: false,
             show_logs: true,
+            mode: UiMode::Auto,
         }
     }
 }
@@ -374,6 +1965,16 @@ impl Default for LoggingConfig {
             console: true,
             max_file_size: 10 * 1024 * 1024,
             max_files: 5,
+            progress: ProgressLogConfig::default(),
+        }
+    }
+}
+
+impl Default for ProgressLogConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            percent_step: 10,
         }
     }
 }
@@ -384,6 +1985,53 @@ impl Default for DiskConfig {
             auto_partition: false,
             partition_scheme: PartitionScheme::Gpt,
             default_filesystem: "ext4".to_string(),
+            windows_usb: WindowsUsbConfig::default(),
+            differential: DifferentialConfig::default(),
+            encryption: EncryptionConfig::default(),
+            checksum: HashAlgorithm::Sha256,
+            bootstrap: BootstrapConfig::default(),
+        }
+    }
+}
+
+impl Default for BootstrapConfig {
+    fn default() -> Self {
+        Self {
+            tool: BootstrapTool::Debootstrap,
+            suite: "bookworm".to_string(),
+            mirror: None,
+            packages: Vec::new(),
+            hostname: "node".to_string(),
+            post_install: None,
+            esp_size_mb: 512,
+        }
+    }
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            policy: EncryptionPolicy::Warn,
+            approval_secs: 600,
+        }
+    }
+}
+
+impl Default for DifferentialConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            state_dir: PathBuf::from("/var/lib/usb-installer-node/provisioned"),
+        }
+    }
+}
+
+impl Default for WindowsUsbConfig {
+    fn default() -> Self {
+        Self {
+            uefi_ntfs_image: PathBuf::from("/usr/share/usb-installer-node/uefi-ntfs.img"),
+            esp_size_mb: 2,
+            work_dir: PathBuf::from("/mnt/usb-installer-target"),
         }
     }
 }
@@ -401,10 +2049,256 @@ impl Default for ServiceConfig {
 impl Default for MonitoringConfig {
     fn default() -> Self {
         Self {
+            enabled: true,
             watchdog_interval: 30,
+            max_failures: 3,
+            auto_restart: true,
             max_restart_attempts: 3,
             restart_delay: 5,
+            safe_mode: true,
             metrics_port: Some(9090),
+            traffic: TrafficMetricsConfig::default(),
+            notifiers: Vec::new(),
+            history: MetricsHistoryConfig::default(),
+            clock: ClockConfig::default(),
+            thermal: ThermalConfig::default(),
+        }
+    }
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 30,
+            max_temp_c: 80.0,
+        }
+    }
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            servers: vec!["pool.ntp.org".to_string()],
+            use_controller: true,
+            max_skew_secs: 30,
+            interval_secs: 900,
+            timeout_secs: 5,
+        }
+    }
+}
+
+impl Default for TrafficMetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 15,
+            interfaces: Vec::new(),
+        }
+    }
+}
+
+impl Default for MetricsHistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_samples: 50_000,
+            retention_secs: 24 * 60 * 60,
+            path: None,
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            // Dual-stack where IPv6 is available
+            bind_address: "::".to_string(),
+            port: 8080,
+            admin_token: None,
+        }
+    }
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            timeouts: HashMap::new(),
+            stop_timeout_secs: 10,
+            stop_timeouts: HashMap::new(),
+        }
+    }
+}
+
+impl Default for PxeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            server_address: Ipv4Addr::new(10, 42, 0, 1),
+            proxy_dhcp: true,
+            tftp_root: PathBuf::from("/usr/lib/ipxe"),
+            bios_boot_file: "undionly.kpxe".to_string(),
+            uefi_boot_file: "ipxe.efi".to_string(),
+            http_port: 8081,
+            menu_timeout_secs: 10,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            tokens: Vec::new(),
+            allow_anonymous_read: false,
+            session_secs: 3600,
+            totp: TotpConfig::default(),
+            bans: BanConfig::default(),
+        }
+    }
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_upload_mb: 16 * 1024,
+            shares: [
+                ("logs", "/var/log/usb-installer-node"),
+                ("node.log", "/var/log/usb-installer.log"),
+            ]
+            .into_iter()
+            .map(|(name, path)| (name.to_string(), PathBuf::from(path)))
+            .collect(),
+        }
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            broker: "localhost:8883".to_string(),
+            tls: true,
+            ca_file: None,
+            node_id: None,
+            username: None,
+            password: None,
+            topic_prefix: "usb-installer".to_string(),
+            keep_alive_secs: 30,
+            publish_interval_secs: 60,
+            reconnect_secs: 10,
+            commands: true,
+            allow_reboot: false,
+        }
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            node_id: None,
+            token: None,
+            interval_secs: 60,
+            timeout_secs: 10,
+            max_backoff_secs: 300,
+        }
+    }
+}
+
+impl Default for DiagnoseConfig {
+    fn default() -> Self {
+        Self {
+            min_free_mb: 4096,
+            targets: Vec::new(),
+            timeout_secs: 5,
+        }
+    }
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_failures: 5,
+            window_secs: 600,
+            ban_secs: 900,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer: "USB Installer Node".to_string(),
+            secrets_path: PathBuf::from("/var/lib/usb-installer-node/totp.json"),
+            max_failures: 5,
+            lockout_secs: 300,
+        }
+    }
+}
+
+impl Default for ReportsConfig {
+    fn default() -> Self {
+        Self {
+            history_path: PathBuf::from("/var/lib/usb-installer-node/jobs.jsonl"),
+            shift_hours: 8,
+            mail: false,
+            mail_notifiers: Vec::new(),
+        }
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: PathBuf::from("/var/lib/usb-installer-node/audit.jsonl"),
+            record_reads: false,
+        }
+    }
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            state_dir: PathBuf::from("/var/lib/usb-installer-node/install-jobs"),
+            max_concurrent: 1,
+            resume_interrupted: false,
+        }
+    }
+}
+
+impl Default for IdentifyConfig {
+    fn default() -> Self {
+        Self {
+            leds: Vec::new(),
+            beep: true,
+            flash_console: true,
+            console: PathBuf::from("/dev/tty0"),
+            default_duration: 30,
+            max_duration: 300,
+        }
+    }
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: ButtonSource::Evdev {
+                device: PathBuf::from("/dev/input/event0"),
+                key_code: 116, // KEY_POWER
+            },
+            job: ButtonJob::PrepareDisk,
+            led: None,
+            debounce_ms: 500,
+            long_press_ms: 2000,
         }
     }
 }
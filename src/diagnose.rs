@@ -0,0 +1,363 @@
+use crate::capabilities::find_program;
+use crate::config::{Config, UiMode};
+use crate::ui::interface::{detect_display, DisplayServer};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+/// Programs the node does not start without
+pub const REQUIRED_TOOLS: &[&str] = &["mount", "umount", "fdisk", "mkfs.ext4", "x11vnc"];
+
+/// 2025-01-01; a clock before this was reset, and TLS certificates will
+/// not be valid yet
+const EARLIEST_PLAUSIBLE_SECS: u64 = 1_735_689_600;
+
+const USB_DEVICES_DIR: &str = "/sys/bus/usb/devices";
+const ROUTE_TABLE: &str = "/proc/net/route";
+
+/// Result of one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    /// Not needed with this configuration
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiagnosticCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl DiagnosticCheck {
+    fn new(name: &'static str, passed: bool, detail: impl Into<String>) -> Self {
+        let status = if passed {
+            CheckStatus::Pass
+        } else {
+            CheckStatus::Fail
+        };
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    fn skip(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Skip,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Every check, passed when none failed
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticReport {
+    pub passed: bool,
+    pub checks: Vec<DiagnosticCheck>,
+    pub ran_at: SystemTime,
+}
+
+impl DiagnosticReport {
+    fn new(checks: Vec<DiagnosticCheck>) -> Self {
+        Self {
+            passed: checks.iter().all(|c| c.status != CheckStatus::Fail),
+            checks,
+            ran_at: SystemTime::now(),
+        }
+    }
+
+    /// One line per check, for the terminal
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Pass => "PASS",
+                CheckStatus::Fail => "FAIL",
+                CheckStatus::Skip => "SKIP",
+            };
+            text.push_str(&format!(
+                "{}  {:<12} {}\n",
+                status, check.name, check.detail
+            ));
+        }
+        let summary = if self.passed {
+            "All checks passed"
+        } else {
+            "Some checks failed"
+        };
+        text.push_str(summary);
+        text.push('\n');
+        text
+    }
+}
+
+/// Self-test of what the node needs from the machine it runs on, for
+/// debugging nodes in the field. Runs from `diagnose` on the command line
+/// and `GET /api/v1/diagnose`.
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    /// Where ISOs are downloaded to
+    storage: PathBuf,
+    min_free_bytes: u64,
+    /// `host:port` that must accept a TCP connection
+    targets: Vec<String>,
+    timeout: Duration,
+    offline: bool,
+    ui_mode: UiMode,
+}
+
+impl Diagnostics {
+    pub fn from_config(config: &Config) -> Self {
+        let storage = config
+            .iso
+            .download
+            .target_dir
+            .clone()
+            .or_else(|| config.iso.search_paths.first().cloned())
+            .unwrap_or_else(|| PathBuf::from("/"));
+        Self {
+            storage,
+            min_free_bytes: config.diagnose.min_free_mb * 1024 * 1024,
+            targets: config.diagnose.targets.clone(),
+            timeout: Duration::from_secs(config.diagnose.timeout_secs),
+            offline: config.network.offline,
+            ui_mode: config.ui.mode,
+        }
+    }
+
+    pub async fn run(&self) -> DiagnosticReport {
+        DiagnosticReport::new(vec![
+            check_tools(),
+            check_root(),
+            self.check_free_space().await,
+            check_loop_devices(),
+            self.check_network().await,
+            check_clock(SystemTime::now()),
+            self.check_display(),
+            check_usb(Path::new(USB_DEVICES_DIR)),
+        ])
+    }
+
+    async fn check_free_space(&self) -> DiagnosticCheck {
+        let output = Command::new("df")
+            .args(["-B1", "--output=avail"])
+            .arg(&self.storage)
+            .output()
+            .await;
+        let available = output.ok().and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .last()?
+                .trim()
+                .parse::<u64>()
+                .ok()
+        });
+        match available {
+            Some(bytes) => DiagnosticCheck::new(
+                "free_space",
+                bytes >= self.min_free_bytes,
+                format!(
+                    "{} MiB free in {} (need {} MiB)",
+                    bytes / (1024 * 1024),
+                    self.storage.display(),
+                    self.min_free_bytes / (1024 * 1024)
+                ),
+            ),
+            None => DiagnosticCheck::new(
+                "free_space",
+                false,
+                format!("Cannot read free space of {}", self.storage.display()),
+            ),
+        }
+    }
+
+    async fn check_network(&self) -> DiagnosticCheck {
+        if self.offline {
+            return DiagnosticCheck::skip("network", "Offline mode");
+        }
+        if self.targets.is_empty() {
+            let route = std::fs::read_to_string(ROUTE_TABLE).unwrap_or_default();
+            return match default_route(&route) {
+                Some(interface) => DiagnosticCheck::new(
+                    "network",
+                    true,
+                    format!("Default route via {}", interface),
+                ),
+                None => DiagnosticCheck::new("network", false, "No default route"),
+            };
+        }
+
+        let mut unreachable = Vec::new();
+        for target in &self.targets {
+            let connect = TcpStream::connect(target.as_str());
+            match tokio::time::timeout(self.timeout, connect).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => unreachable.push(format!("{} ({})", target, e)),
+                Err(_) => unreachable.push(format!("{} (timed out)", target)),
+            }
+        }
+        if unreachable.is_empty() {
+            DiagnosticCheck::new(
+                "network",
+                true,
+                format!("Reached {}", self.targets.join(", ")),
+            )
+        } else {
+            DiagnosticCheck::new(
+                "network",
+                false,
+                format!("Cannot reach {}", unreachable.join(", ")),
+            )
+        }
+    }
+
+    fn check_display(&self) -> DiagnosticCheck {
+        let display = match detect_display() {
+            Some(DisplayServer::Wayland(socket)) => format!("Wayland at {}", socket.display()),
+            Some(DisplayServer::X11(display)) => format!("X11 display {}", display),
+            None => String::new(),
+        };
+        match self.ui_mode {
+            UiMode::Console | UiMode::Web => {
+                DiagnosticCheck::skip("display", "The local interface is not graphical")
+            }
+            _ if !display.is_empty() => DiagnosticCheck::new("display", true, display),
+            UiMode::Graphical => DiagnosticCheck::new("display", false, "No display found"),
+            UiMode::Auto => DiagnosticCheck::skip(
+                "display",
+                "No display found; the console or web interface is used",
+            ),
+        }
+    }
+}
+
+fn check_tools() -> DiagnosticCheck {
+    let missing: Vec<&str> = REQUIRED_TOOLS
+        .iter()
+        .copied()
+        .filter(|program| find_program(program).is_none())
+        .collect();
+    if missing.is_empty() {
+        DiagnosticCheck::new(
+            "tools",
+            true,
+            format!("Found {}", REQUIRED_TOOLS.join(", ")),
+        )
+    } else {
+        DiagnosticCheck::new("tools", false, format!("Missing {}", missing.join(", ")))
+    }
+}
+
+fn check_root() -> DiagnosticCheck {
+    let uid = nix::unistd::Uid::effective();
+    if uid.is_root() {
+        DiagnosticCheck::new("root", true, "Running as root")
+    } else {
+        DiagnosticCheck::new("root", false, format!("Running as uid {}", uid))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_loop_devices() -> DiagnosticCheck {
+    match crate::iso::loopdev::free_device() {
+        Ok(device) => DiagnosticCheck::new(
+            "loop_devices",
+            true,
+            format!("{} is free", device.display()),
+        ),
+        Err(e) => DiagnosticCheck::new("loop_devices", false, e.to_string()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_loop_devices() -> DiagnosticCheck {
+    DiagnosticCheck::skip("loop_devices", "Only available on Linux")
+}
+
+fn check_clock(now: SystemTime) -> DiagnosticCheck {
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    if secs >= EARLIEST_PLAUSIBLE_SECS {
+        DiagnosticCheck::new("clock", true, format!("{} seconds since the epoch", secs))
+    } else {
+        DiagnosticCheck::new(
+            "clock",
+            false,
+            format!("Clock reads {} seconds since the epoch; it was reset", secs),
+        )
+    }
+}
+
+/// Host controllers show up as their root hubs, `usb1`, `usb2`, ...
+fn check_usb(devices_dir: &Path) -> DiagnosticCheck {
+    let controllers = std::fs::read_dir(devices_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("usb"))
+                .count()
+        })
+        .unwrap_or(0);
+    DiagnosticCheck::new(
+        "usb",
+        controllers > 0,
+        format!("{} USB host controllers", controllers),
+    )
+}
+
+/// Interface of the default route in `/proc/net/route`
+fn default_route(table: &str) -> Option<&str> {
+    table.lines().skip(1).find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let interface = fields.next()?;
+        (fields.next()? == "00000000").then_some(interface)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks() {
+        assert_eq!(check_clock(UNIX_EPOCH).status, CheckStatus::Fail);
+        assert_eq!(check_clock(SystemTime::now()).status, CheckStatus::Pass);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(check_usb(dir.path()).status, CheckStatus::Fail);
+        std::fs::create_dir(dir.path().join("usb1")).unwrap();
+        std::fs::create_dir(dir.path().join("1-1")).unwrap();
+        let usb = check_usb(dir.path());
+        assert_eq!(usb.status, CheckStatus::Pass);
+        assert_eq!(usb.detail, "1 USB host controllers");
+
+        let table = "Iface\tDestination\tGateway\n\
+                     eth0\t0001A8C0\t00000000\n\
+                     wlan0\t00000000\t0101A8C0\n";
+        assert_eq!(default_route(table), Some("wlan0"));
+        assert_eq!(default_route("Iface\tDestination\tGateway\n"), None);
+    }
+
+    #[test]
+    fn test_report_fails_on_any_failure() {
+        let report = DiagnosticReport::new(vec![
+            DiagnosticCheck::new("root", true, "Running as root"),
+            DiagnosticCheck::skip("display", "The local interface is not graphical"),
+        ]);
+        assert!(report.passed);
+
+        let report = DiagnosticReport::new(vec![DiagnosticCheck::new("usb", false, "")]);
+        assert!(!report.passed);
+        assert!(report.to_text().contains("FAIL  usb"));
+    }
+}
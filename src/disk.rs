@@ -1,13 +1,39 @@
+pub mod bootstrap;
+pub mod checksum;
+pub mod cloud;
+pub mod encryption;
+pub mod expand;
+pub mod fingerprint;
 pub mod format;
+pub mod imaging;
+pub mod inventory;
+pub mod multiboot;
 pub mod partition;
+pub mod relabel;
+pub mod shrink;
+pub mod windows_usb;
 
-use crate::config::DiskConfig;
+use crate::audit::{AuditAction, AuditLog, AuditSource};
+use crate::config::{DifferentialConfig, DiskConfig, EncryptionPolicy};
+use crate::dryrun::DryRun;
 use crate::error::{DiskError, Result};
+use bootstrap::{BootstrapParams, Bootstrapper};
+use checksum::HashAlgorithm;
+use fingerprint::{FingerprintStore, PlannedPartition, ProvisionRecord, TargetFingerprint};
 use format::{DiskFormatter, FormatParams};
+use imaging::{CaptureParams, CaptureReport, DiskImager, WriteParams, WriteReport};
+use inventory::{DiskInventory, SpecialArea};
+use multiboot::{MultibootEntry, MultibootParams, MultibootWriter};
 use partition::{DiskPartitioner, PartitionParams};
+use serde::Serialize;
+use shrink::ShrinkPlan;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, RwLock};
 use tracing::{debug, error, info, warn};
+use windows_usb::{partition_path, DataFilesystem, WindowsUsbParams, WindowsUsbWriter};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DiskManagerState {
@@ -18,23 +44,132 @@ pub enum DiskManagerState {
     Error(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskOperation {
+    Partition,
+    Format,
+    WindowsMedia,
+    Imaging,
+    Multiboot,
+    Bootstrap,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskProgress {
+    pub device: String,
+    pub operation: DiskOperation,
+    pub step: u32,
+    pub total_steps: u32,
+    pub percentage: u8,
+    pub message: String,
+}
+
 pub struct DiskManager {
     config: Arc<RwLock<DiskConfig>>,
     state: Arc<RwLock<DiskManagerState>>,
     partitioner: DiskPartitioner,
     formatter: DiskFormatter,
+    windows_usb: WindowsUsbWriter,
+    multiboot: MultibootWriter,
+    bootstrapper: Bootstrapper,
+    imager: DiskImager,
+    progress_tx: broadcast::Sender<DiskProgress>,
+    /// Devices an administrator allowed to be overwritten despite holding
+    /// encrypted data, with the approval's expiry
+    approvals: Arc<RwLock<HashMap<String, Instant>>>,
+    /// Why destructive operations are refused, while the node is in safe
+    /// mode
+    held: Arc<RwLock<Option<String>>>,
+    /// Set with `--dry-run`; destructive steps are recorded instead of run
+    dry_run: Option<DryRun>,
+    audit: Option<AuditLog>,
 }
 
 impl DiskManager {
     pub fn new(config: Arc<RwLock<DiskConfig>>) -> Self {
+        let (progress_tx, _) = broadcast::channel(100);
+
         Self {
             config,
             state: Arc::new(RwLock::new(DiskManagerState::Idle)),
             partitioner: DiskPartitioner::new(),
-            formatter: DiskFormatter::new(),
+            formatter: DiskFormatter::new().with_progress(progress_tx.clone()),
+            windows_usb: WindowsUsbWriter::new().with_progress(progress_tx.clone()),
+            multiboot: MultibootWriter::new().with_progress(progress_tx.clone()),
+            bootstrapper: Bootstrapper::new().with_progress(progress_tx.clone()),
+            imager: DiskImager::new().with_progress(progress_tx.clone()),
+            progress_tx,
+            approvals: Arc::new(RwLock::new(HashMap::new())),
+            held: Arc::new(RwLock::new(None)),
+            dry_run: None,
+            audit: None,
+        }
+    }
+
+    /// Rehearse partitioning, formatting and image writes: the commands
+    /// and writes they would perform are logged and recorded in `dry_run`
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.formatter = DiskFormatter::new()
+            .with_progress(self.progress_tx.clone())
+            .with_dry_run(dry_run.clone());
+        self.imager = DiskImager::new()
+            .with_progress(self.progress_tx.clone())
+            .with_dry_run(dry_run.clone());
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Record partitioning, formatting and writes that were carried out
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.is_some()
+    }
+
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<DiskProgress> {
+        self.progress_tx.subscribe()
+    }
+
+    async fn audit<T>(&self, action: &str, device: &str, result: &Result<T>) {
+        if let Some(audit) = &self.audit {
+            audit
+                .record(
+                    AuditAction::new(AuditSource::Disk, "node", action)
+                        .with_target(device)
+                        .with_result(result),
+                )
+                .await;
         }
     }
 
+    fn report_progress(
+        &self,
+        device: &str,
+        operation: DiskOperation,
+        step: u32,
+        total_steps: u32,
+        message: String,
+    ) {
+        let percentage = if total_steps == 0 {
+            0
+        } else {
+            ((step * 100) / total_steps).min(100) as u8
+        };
+
+        let _ = self.progress_tx.send(DiskProgress {
+            device: device.to_string(),
+            operation,
+            step,
+            total_steps,
+            percentage,
+            message,
+        });
+    }
+
     pub async fn prepare_disk(&self, device: &str) -> Result<()> {
         info!("Starting disk preparation for {}", device);
 
@@ -43,6 +178,9 @@ impl DiskManager {
             info!("Disk management disabled");
             return Ok(());
         }
+        self.check_held().await?;
+        inventory::check_target(device)?;
+        self.check_encryption(device, config.encryption.policy).await?;
 
         self.set_state(DiskManagerState::Busy).await;
 
@@ -58,25 +196,65 @@ impl DiskManager {
                 self.set_state(DiskManagerState::Error(e.to_string())).await;
             }
         }
+        if !self.is_dry_run() {
+            self.audit("prepare_disk", device, &result).await;
+        }
 
         result
     }
 
     async fn prepare_disk_internal(&self, device: &str, config: &DiskConfig) -> Result<()> {
+        let mut current = if config.differential.enabled {
+            self.fingerprint(device).await
+        } else {
+            None
+        };
+
         if config.auto_partition {
             self.set_state(DiskManagerState::Partitioning).await;
-            self.auto_partition_disk(device, config)?;
+            if self
+                .auto_partition_disk(device, config, current.as_ref())
+                .await?
+            {
+                // Freshly created partitions have nothing worth keeping
+                current = None;
+            }
         }
 
         if config.auto_format {
             self.set_state(DiskManagerState::Formatting).await;
-            self.auto_format_partitions(device, config)?;
+            self.auto_format_partitions(device, config, current.as_ref())
+                .await?;
         }
 
         Ok(())
     }
 
-    fn auto_partition_disk(&self, device: &str, config: &DiskConfig) -> Result<()> {
+    /// Fingerprint the target for differential runs. On failure every phase
+    /// runs as usual.
+    async fn fingerprint(&self, device: &str) -> Option<TargetFingerprint> {
+        let target = device.to_string();
+        match tokio::task::spawn_blocking(move || fingerprint::capture(&target)).await {
+            Ok(Ok(current)) => Some(current),
+            Ok(Err(e)) => {
+                warn!("Failed to fingerprint {}: {}", device, e);
+                None
+            }
+            Err(e) => {
+                warn!("Fingerprint task for {} failed: {}", device, e);
+                None
+            }
+        }
+    }
+
+    /// Create the configured layout. Returns false when `current` already
+    /// has it and nothing was changed.
+    async fn auto_partition_disk(
+        &self,
+        device: &str,
+        config: &DiskConfig,
+        current: Option<&TargetFingerprint>,
+    ) -> Result<bool> {
         info!("Auto-partitioning disk {}", device);
 
         let layout = config.partition_layout.as_ref().ok_or_else(|| {
@@ -84,30 +262,89 @@ impl DiskManager {
         })?;
 
         let table_type = partition::PartitionTableType::from_str(&layout.table_type)?;
+        let total_steps = layout.partitions.len() as u32 + 1;
+
+        let mut planned = Vec::new();
+        let mut start_sector = 2048;
+        for partition_config in &layout.partitions {
+            let size_sectors = self.calculate_size_sectors(&partition_config.size, device)?;
+            planned.push(PlannedPartition {
+                start_sector,
+                size_sectors,
+                type_id: partition_config.type_guid.clone(),
+                name: partition_config.name.clone(),
+            });
+            start_sector += size_sectors;
+        }
+
+        if current.is_some_and(|c| fingerprint::layout_matches(c, &planned)) {
+            info!("Partition table on {} already matches, skipping", device);
+            self.report_progress(
+                device,
+                DiskOperation::Partition,
+                total_steps,
+                total_steps,
+                "Partition table already matches".to_string(),
+            );
+            return Ok(false);
+        }
 
+        if let Some(dry_run) = &self.dry_run {
+            rehearse_partitioning(dry_run, device, &layout.table_type, &planned);
+            return Ok(true);
+        }
+
+        self.report_progress(
+            device,
+            DiskOperation::Partition,
+            0,
+            total_steps,
+            "Creating partition table".to_string(),
+        );
         self.partitioner
             .create_partition_table(device, table_type)?;
 
-        let mut start_sector = 2048;
+        for (i, (partition_config, planned)) in layout.partitions.iter().zip(&planned).enumerate()
+        {
+            self.report_progress(
+                device,
+                DiskOperation::Partition,
+                i as u32 + 1,
+                total_steps,
+                format!("Creating partition {}", i + 1),
+            );
 
-        for (i, partition_config) in layout.partitions.iter().enumerate() {
-            let size_sectors = self.calculate_size_sectors(&partition_config.size, device)?;
-
-            let params =
-                PartitionParams::new(device.to_string(), i as u32 + 1, start_sector, size_sectors)
-                    .with_type_guid(partition_config.type_guid.clone())
-                    .with_name(partition_config.name.clone())
-                    .with_flags(partition_config.flags.clone());
+            let params = PartitionParams::new(
+                device.to_string(),
+                i as u32 + 1,
+                planned.start_sector,
+                planned.size_sectors,
+            )
+            .with_type_guid(partition_config.type_guid.clone())
+            .with_name(partition_config.name.clone())
+            .with_flags(partition_config.flags.clone());
 
             self.partitioner.create_partition(&params)?;
-
-            start_sector += size_sectors;
         }
 
-        Ok(())
+        self.report_progress(
+            device,
+            DiskOperation::Partition,
+            total_steps,
+            total_steps,
+            "Partitioning complete".to_string(),
+        );
+        Ok(true)
     }
 
-    fn auto_format_partitions(&self, device: &str, config: &DiskConfig) -> Result<()> {
+    /// Format the configured partitions, skipping those `current` shows
+    /// already carry the configured filesystem and label
+    async fn auto_format_partitions(
+        &self,
+        device: &str,
+        config: &DiskConfig,
+        current: Option<&TargetFingerprint>,
+    ) -> Result<()> {
         info!("Auto-formatting partitions on {}", device);
 
         let layout = config.partition_layout.as_ref().ok_or_else(|| {
@@ -116,19 +353,18 @@ impl DiskManager {
 
         for (i, partition_config) in layout.partitions.iter().enumerate() {
             if let Some(fs_type_str) = &partition_config.filesystem {
-                let partition_device = format!(
-                    "{}{}",
-                    device,
-                    if device.ends_with(char::is_numeric) {
-                        "p"
-                    } else {
-                        ""
-                    },
-                    i + 1
-                );
+                let partition_device = partition_path(device, i as u32 + 1);
 
                 let fs_type = format::FileSystemType::from_str(fs_type_str)?;
 
+                let existing = current.and_then(|c| c.partition(i as u32 + 1));
+                if existing
+                    .is_some_and(|p| p.has_filesystem(fs_type, partition_config.label.as_deref()))
+                {
+                    info!("{} is already formatted as configured, skipping", partition_device);
+                    continue;
+                }
+
                 let mut params = FormatParams::new(partition_device.clone(), fs_type);
 
                 if let Some(label) = &partition_config.label {
@@ -139,7 +375,7 @@ impl DiskManager {
                     params = params.force();
                 }
 
-                self.formatter.format(&params)?;
+                self.formatter.format(&params).await?;
             }
         }
 
@@ -198,21 +434,419 @@ impl DiskManager {
     }
 
     pub async fn partition_disk(&self, params: &PartitionParams) -> Result<()> {
+        self.check_held().await?;
+        inventory::check_target(&params.device)?;
+        let policy = self.config.read().await.encryption.policy;
+        self.check_encryption(&params.device, policy).await?;
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.action(format!("Create a partition on {}", params.device));
+            return Ok(());
+        }
         self.set_state(DiskManagerState::Partitioning).await;
+        self.report_progress(
+            &params.device,
+            DiskOperation::Partition,
+            0,
+            1,
+            "Creating partition".to_string(),
+        );
         let result = self.partitioner.create_partition(params);
+        if result.is_ok() {
+            self.report_progress(
+                &params.device,
+                DiskOperation::Partition,
+                1,
+                1,
+                "Partition created".to_string(),
+            );
+        }
         self.set_state(DiskManagerState::Idle).await;
+        self.audit("partition", &params.device, &result).await;
         result
     }
 
     pub async fn format_partition(&self, params: &FormatParams) -> Result<()> {
+        self.check_held().await?;
+        inventory::check_target(&params.device)?;
+        let policy = self.config.read().await.encryption.policy;
+        self.check_encryption(&params.device, policy).await?;
         self.set_state(DiskManagerState::Formatting).await;
-        let result = self.formatter.format(params);
+        let result = self.formatter.format(params).await;
         self.set_state(DiskManagerState::Idle).await;
+        if !self.is_dry_run() {
+            self.audit("format", &params.device, &result).await;
+        }
         result
     }
 
-    pub async fn list_disks(&self) -> Result<Vec<String>> {
-        self.partitioner.list_disks()
+    pub async fn create_windows_usb(
+        &self,
+        device: &str,
+        source: &Path,
+        filesystem: DataFilesystem,
+        answer_file: Option<String>,
+    ) -> Result<()> {
+        self.check_held().await?;
+        inventory::check_target(device)?;
+        let policy = self.config.read().await.encryption.policy;
+        self.check_encryption(device, policy).await?;
+        let config = self.config.read().await.windows_usb.clone();
+        let params = WindowsUsbParams::new(
+            device.to_string(),
+            source.to_path_buf(),
+            config.uefi_ntfs_image,
+        )
+        .with_filesystem(filesystem)
+        .with_esp_size(config.esp_size_mb)
+        .with_work_dir(config.work_dir)
+        .with_answer_file(answer_file);
+
+        let (differential, algorithm) = {
+            let config = self.config.read().await;
+            (config.differential.clone(), config.checksum)
+        };
+        if differential.enabled && is_provisioned(device, source, &differential, algorithm).await {
+            info!(
+                "{} already holds {}, skipping write",
+                device,
+                source.display()
+            );
+            return Ok(());
+        }
+
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.action(format!(
+                "Write Windows installer from {} to {} ({:?} data partition, {} MiB ESP)",
+                source.display(),
+                device,
+                filesystem,
+                config.esp_size_mb
+            ));
+            return Ok(());
+        }
+
+        self.set_state(DiskManagerState::Busy).await;
+        let result = self.windows_usb.create(&params).await;
+        if result.is_ok() && differential.enabled {
+            record_provisioned(device, source, &differential, algorithm).await;
+        }
+        match &result {
+            Ok(_) => self.set_state(DiskManagerState::Idle).await,
+            Err(e) => {
+                error!("Windows installer creation failed: {}", e);
+                self.set_state(DiskManagerState::Error(e.to_string())).await;
+            }
+        }
+        self.audit("create_windows_usb", device, &result).await;
+        result
+    }
+
+    /// Build a stick that offers every recognised ISO in `isos` from a GRUB
+    /// menu. Returns the entries written to grub.cfg.
+    pub async fn create_multiboot_usb(
+        &self,
+        device: &str,
+        isos: Vec<PathBuf>,
+    ) -> Result<Vec<MultibootEntry>> {
+        self.check_held().await?;
+        inventory::check_target(device)?;
+        let policy = self.config.read().await.encryption.policy;
+        self.check_encryption(device, policy).await?;
+        let work_dir = self.config.read().await.windows_usb.work_dir.clone();
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.action(format!(
+                "Write a multiboot stick with {} ISOs to {}",
+                isos.len(),
+                device
+            ));
+            return Ok(Vec::new());
+        }
+        let params = MultibootParams::new(device.to_string(), isos).with_work_dir(work_dir);
+
+        self.set_state(DiskManagerState::Busy).await;
+        let result = self.multiboot.create(&params).await;
+        match &result {
+            Ok(_) => self.set_state(DiskManagerState::Idle).await,
+            Err(e) => {
+                error!("Multiboot stick creation failed: {}", e);
+                self.set_state(DiskManagerState::Error(e.to_string())).await;
+            }
+        }
+        self.audit("create_multiboot_usb", device, &result).await;
+        result
+    }
+
+    /// Install a minimal system onto `device` per `[disk.bootstrap]`
+    /// instead of writing an installer
+    pub async fn bootstrap_target(&self, device: &str) -> Result<()> {
+        self.check_held().await?;
+        inventory::check_target(device)?;
+        let (policy, config, work_dir) = {
+            let config = self.config.read().await;
+            (
+                config.encryption.policy,
+                config.bootstrap.clone(),
+                config.windows_usb.work_dir.clone(),
+            )
+        };
+        self.check_encryption(device, policy).await?;
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.action(format!(
+                "Bootstrap {} {:?} onto {} ({} MiB ESP)",
+                config.suite, config.tool, device, config.esp_size_mb
+            ));
+            return Ok(());
+        }
+        let params = BootstrapParams::new(device.to_string(), config.tool, config.suite)
+            .with_mirror(config.mirror)
+            .with_packages(config.packages)
+            .with_hostname(config.hostname)
+            .with_post_install(config.post_install)
+            .with_esp_size(config.esp_size_mb)
+            .with_work_dir(work_dir);
+
+        self.set_state(DiskManagerState::Busy).await;
+        let result = self.bootstrapper.create(&params).await;
+        match &result {
+            Ok(_) => self.set_state(DiskManagerState::Idle).await,
+            Err(e) => {
+                error!("Bootstrapping {} failed: {}", device, e);
+                self.set_state(DiskManagerState::Error(e.to_string())).await;
+            }
+        }
+        self.audit("bootstrap", device, &result).await;
+        result
+    }
+
+    /// Shrink a partition together with its filesystem. With `dry_run` only
+    /// the plan, including the minimum achievable size, is returned.
+    pub async fn shrink_partition(
+        &self,
+        device: &str,
+        partition_number: u32,
+        new_size_mb: u64,
+        dry_run: bool,
+    ) -> Result<ShrinkPlan> {
+        self.check_held().await?;
+        inventory::check_target(device)?;
+        // The table scheme is only used when creating a new table
+        let manager = partition::PartitionManager::new(
+            device.to_string(),
+            partition::PartitionScheme::Gpt,
+        )
+        .with_progress(self.progress_tx.clone());
+
+        if dry_run || self.dry_run.is_some() {
+            return manager
+                .shrink_partition(partition_number, new_size_mb, true)
+                .await;
+        }
+        let policy = self.config.read().await.encryption.policy;
+        self.check_encryption(device, policy).await?;
+
+        self.set_state(DiskManagerState::Partitioning).await;
+        let result = manager
+            .shrink_partition(partition_number, new_size_mb, false)
+            .await;
+        match &result {
+            Ok(_) => self.set_state(DiskManagerState::Idle).await,
+            Err(e) => {
+                error!("Shrinking {} failed: {}", device, e);
+                self.set_state(DiskManagerState::Error(e.to_string())).await;
+            }
+        }
+        self.audit("shrink_partition", device, &result).await;
+        result
+    }
+
+    /// Capture a device to an image file. In forensic mode the source is
+    /// made read-only first and a chain-of-custody report is written next
+    /// to the image.
+    pub async fn capture_image(&self, params: CaptureParams) -> Result<CaptureReport> {
+        let policy = self.config.read().await.encryption.policy;
+        self.check_encryption(&params.device, policy).await?;
+        let imager = self.imager.clone();
+        let device = params.device.clone();
+
+        self.set_state(DiskManagerState::Busy).await;
+        let result = match tokio::task::spawn_blocking(move || imager.capture(&params)).await {
+            Ok(result) => result,
+            Err(e) => Err(DiskError::ImagingFailed(format!("Imaging task failed: {}", e)).into()),
+        };
+        match &result {
+            Ok(_) => self.set_state(DiskManagerState::Idle).await,
+            Err(e) => {
+                error!("Imaging {} failed: {}", device, e);
+                self.set_state(DiskManagerState::Error(e.to_string())).await;
+            }
+        }
+        self.audit("capture_image", &device, &result).await;
+        result
+    }
+
+    /// Write a raw, compressed or qcow2 image to a device. With
+    /// `params.expand` the last partition and its filesystem are grown to
+    /// fill a device larger than the image; `params.seed` adds a cloud-init
+    /// seed partition behind it.
+    pub async fn write_image(&self, mut params: WriteParams) -> Result<WriteReport> {
+        self.check_held().await?;
+        inventory::check_target(&params.device)?;
+        let (policy, algorithm) = {
+            let config = self.config.read().await;
+            (config.encryption.policy, config.checksum)
+        };
+        self.check_encryption(&params.device, policy).await?;
+        params.hash.get_or_insert(algorithm);
+        let imager = self.imager.clone();
+        let device = params.device.clone();
+
+        self.set_state(DiskManagerState::Busy).await;
+        let result = match tokio::task::spawn_blocking(move || imager.write(&params)).await {
+            Ok(result) => result,
+            Err(e) => Err(DiskError::ImagingFailed(format!("Imaging task failed: {}", e)).into()),
+        };
+        match &result {
+            Ok(_) => self.set_state(DiskManagerState::Idle).await,
+            Err(e) => {
+                error!("Writing image to {} failed: {}", device, e);
+                self.set_state(DiskManagerState::Error(e.to_string())).await;
+            }
+        }
+        if !self.is_dry_run() {
+            self.audit("write_image", &device, &result).await;
+        }
+        result
+    }
+
+    /// Rename an existing filesystem without reformatting it
+    pub async fn set_label(&self, device: &str, label: &str) -> Result<()> {
+        self.check_held().await?;
+        inventory::check_target(device)?;
+        let target = device.to_string();
+        let label = label.to_string();
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.action(format!("Set the label of {} to {}", target, label));
+            return Ok(());
+        }
+        let result = self
+            .run_relabel(move || relabel::set_label(&target, &label))
+            .await;
+        self.audit("set_label", device, &result).await;
+        result
+    }
+
+    /// Assign a new UUID (or FAT/NTFS serial) to an existing filesystem
+    pub async fn set_uuid(&self, device: &str, uuid: &str) -> Result<()> {
+        self.check_held().await?;
+        inventory::check_target(device)?;
+        let target = device.to_string();
+        let uuid = uuid.to_string();
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.action(format!("Set the UUID of {} to {}", target, uuid));
+            return Ok(());
+        }
+        let result = self
+            .run_relabel(move || relabel::set_uuid(&target, &uuid))
+            .await;
+        self.audit("set_uuid", device, &result).await;
+        result
+    }
+
+    async fn run_relabel<F>(&self, op: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()> + Send + 'static,
+    {
+        if self.dry_run.is_some() {
+            return Ok(());
+        }
+        self.set_state(DiskManagerState::Busy).await;
+        let result = match tokio::task::spawn_blocking(op).await {
+            Ok(result) => result,
+            Err(e) => Err(DiskError::RelabelFailed(format!("Relabel task failed: {}", e)).into()),
+        };
+        self.set_state(DiskManagerState::Idle).await;
+        result
+    }
+
+    pub async fn list_disks(&self) -> Result<Vec<DiskInventory>> {
+        tokio::task::spawn_blocking(inventory::scan)
+            .await
+            .map_err(|e| DiskError::DiskNotFound(format!("Disk scan task failed: {}", e)))?
+    }
+
+    pub async fn get_disk_inventory(&self, device: &str) -> Result<DiskInventory> {
+        let device = device.to_string();
+        tokio::task::spawn_blocking(move || inventory::inspect(&device))
+            .await
+            .map_err(|e| DiskError::DiskNotFound(format!("Disk scan task failed: {}", e)))?
+    }
+
+    /// eMMC boot and RPMB areas of `device`. These are skipped by every
+    /// write path; capture them explicitly with `capture_image`.
+    pub async fn special_areas(&self, device: &str) -> Result<Vec<SpecialArea>> {
+        Ok(self.get_disk_inventory(device).await?.special_areas)
+    }
+
+    /// Allow the next write to `device` or one of its partitions although it
+    /// holds encrypted data. Returns how long the approval stays valid.
+    pub async fn approve_encrypted_overwrite(&self, device: &str) -> Duration {
+        let valid_for = Duration::from_secs(self.config.read().await.encryption.approval_secs);
+        self.approvals
+            .write()
+            .await
+            .insert(device.to_string(), Instant::now() + valid_for);
+        warn!(
+            "Overwriting encrypted data on {} approved for {}s",
+            device,
+            valid_for.as_secs()
+        );
+        valid_for
+    }
+
+    /// Refuse to overwrite encrypted data unless policy allows it or an
+    /// administrator approved the device. An approval covers one write.
+    /// A volume that cannot be read counts as encrypted.
+    async fn check_encryption(&self, device: &str, policy: EncryptionPolicy) -> Result<()> {
+        let path = device.to_string();
+        let inspection = tokio::task::spawn_blocking(move || encryption::inspect(&path))
+            .await
+            .map_err(|e| DiskError::EncryptionUnknown(format!("{}: {}", device, e)))?;
+        if inspection.encrypted.is_empty() && inspection.unreadable.is_empty() {
+            return Ok(());
+        }
+        let summary = inspection
+            .encrypted
+            .iter()
+            .map(|v| format!("{} ({})", v.path, v.kind))
+            .chain(
+                inspection
+                    .unreadable
+                    .iter()
+                    .map(|path| format!("{} (unreadable)", path)),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if policy == EncryptionPolicy::Warn {
+            warn!("Overwriting possibly encrypted data on {}", summary);
+            return Ok(());
+        }
+
+        let mut approvals = self.approvals.write().await;
+        let now = Instant::now();
+        approvals.retain(|_, expiry| *expiry > now);
+        let approved = approvals.keys().find(|disk| covers(disk, device)).cloned();
+        match approved {
+            Some(disk) => {
+                approvals.remove(&disk);
+                warn!("Overwriting encrypted data on {} with approval", summary);
+                Ok(())
+            }
+            None if inspection.encrypted.is_empty() => {
+                Err(DiskError::EncryptionUnknown(summary).into())
+            }
+            None => Err(DiskError::EncryptedTarget(summary).into()),
+        }
     }
 
     pub async fn get_disk_info(&self, device: &str) -> Result<partition::DiskInfo> {
@@ -242,6 +876,141 @@ impl DiskManager {
             _ => Ok(()),
         }
     }
+
+    /// Refuse partitioning, formatting and writes for `reason`, or allow
+    /// them again with `None`. Operations already running finish.
+    pub async fn hold(&self, reason: Option<String>) {
+        match &reason {
+            Some(reason) => warn!("Holding disk operations: {}", reason),
+            None => info!("Disk operations allowed again"),
+        }
+        *self.held.write().await = reason;
+    }
+
+    async fn check_held(&self) -> Result<()> {
+        match &*self.held.read().await {
+            Some(reason) => Err(DiskError::SafeMode(reason.clone()).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Leave the error state a failed operation left behind, after it was
+    /// reported, so the manager counts as ready for the next job
+    pub async fn clear_error(&self) {
+        let mut state = self.state.write().await;
+        if let DiskManagerState::Error(e) = &*state {
+            info!("Clearing disk error: {}", e);
+            *state = DiskManagerState::Idle;
+        }
+    }
+}
+
+/// Whether an approval for `disk` extends to `device`: the disk itself or
+/// one of its partitions (`sdb1`, `nvme0n1p1`)
+fn covers(disk: &str, device: &str) -> bool {
+    let Some(suffix) = device.strip_prefix(disk) else {
+        return false;
+    };
+    let number = suffix.strip_prefix('p').unwrap_or(suffix);
+    suffix.is_empty() || (!number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Record the parted commands that would create `planned` on `device`
+fn rehearse_partitioning(
+    dry_run: &DryRun,
+    device: &str,
+    table_type: &str,
+    planned: &[PlannedPartition],
+) {
+    let label = match table_type.to_ascii_lowercase().as_str() {
+        "mbr" | "dos" | "msdos" => "msdos",
+        _ => "gpt",
+    };
+    let mut cmd = std::process::Command::new("parted");
+    cmd.args(["-s", device, "mklabel", label]);
+    dry_run.command(
+        format!("Create {} partition table on {}", label, device),
+        &cmd,
+    );
+
+    for (i, partition) in planned.iter().enumerate() {
+        let mut cmd = std::process::Command::new("parted");
+        cmd.args(["-s", device, "mkpart"]);
+        if label == "gpt" {
+            cmd.arg(partition.name.as_deref().unwrap_or(""));
+        } else {
+            cmd.arg("primary");
+        }
+        cmd.arg(format!("{}s", partition.start_sector)).arg(format!(
+            "{}s",
+            partition.start_sector + partition.size_sectors - 1
+        ));
+        dry_run.command(
+            format!(
+                "Create partition {} on {} ({} bytes)",
+                i + 1,
+                device,
+                partition.size_sectors * 512
+            ),
+            &cmd,
+        );
+    }
+}
+
+/// Whether `device` still holds what was last written from `source`
+async fn is_provisioned(
+    device: &str,
+    source: &Path,
+    config: &DifferentialConfig,
+    algorithm: HashAlgorithm,
+) -> bool {
+    let device = device.to_string();
+    let source = source.to_path_buf();
+    let store = FingerprintStore::new(config.state_dir.clone());
+
+    tokio::task::spawn_blocking(move || {
+        match (
+            fingerprint::capture(&device),
+            fingerprint::manifest(&source, algorithm),
+        ) {
+            (Ok(current), Ok(manifest)) => store.is_provisioned(&current, &manifest),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Cannot fingerprint {}: {}", device, e);
+                false
+            }
+        }
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Remember what was written to `device` so a repeat run can skip it
+async fn record_provisioned(
+    device: &str,
+    source: &Path,
+    config: &DifferentialConfig,
+    algorithm: HashAlgorithm,
+) {
+    let device = device.to_string();
+    let source = source.to_path_buf();
+    let store = FingerprintStore::new(config.state_dir.clone());
+
+    let result = tokio::task::spawn_blocking(move || -> Result<()> {
+        let manifest = fingerprint::manifest(&source, algorithm)?;
+        store.save(&ProvisionRecord {
+            fingerprint: fingerprint::capture(&device)?,
+            manifest: manifest.value,
+            algorithm: manifest.algorithm,
+            recorded_at: std::time::SystemTime::now(),
+        })
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("Failed to record provisioned state: {}", e),
+        Err(e) => warn!("Provision record task failed: {}", e),
+    }
 }
 
 #[cfg(test)]
@@ -255,6 +1024,92 @@ mod tests {
         assert_eq!(manager.get_state().await, DiskManagerState::Idle);
     }
 
+    #[tokio::test]
+    async fn test_progress_subscription() {
+        let config = Arc::new(RwLock::new(DiskConfig::default()));
+        let manager = DiskManager::new(config);
+        let mut rx = manager.subscribe_progress();
+
+        manager.report_progress(
+            "/dev/sdb",
+            DiskOperation::Partition,
+            1,
+            4,
+            "Creating partition 1".to_string(),
+        );
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.device, "/dev/sdb");
+        assert_eq!(event.operation, DiskOperation::Partition);
+        assert_eq!(event.percentage, 25);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_target_is_blocked() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing").to_string_lossy().into_owned();
+        let manager = DiskManager::new(Arc::new(RwLock::new(DiskConfig::default())));
+
+        assert!(manager
+            .check_encryption(&missing, EncryptionPolicy::Warn)
+            .await
+            .is_ok());
+        assert!(matches!(
+            manager
+                .check_encryption(&missing, EncryptionPolicy::Block)
+                .await,
+            Err(crate::error::Error::Disk(DiskError::EncryptionUnknown(_)))
+        ));
+
+        manager.approve_encrypted_overwrite(&missing).await;
+        assert!(manager
+            .check_encryption(&missing, EncryptionPolicy::Block)
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_approval_covers_partitions() {
+        assert!(covers("/dev/sdb", "/dev/sdb"));
+        assert!(covers("/dev/sdb", "/dev/sdb2"));
+        assert!(covers("/dev/nvme0n1", "/dev/nvme0n1p1"));
+        assert!(!covers("/dev/sdb", "/dev/sdba"));
+        assert!(!covers("/dev/sd", "/dev/sdb"));
+        assert!(!covers("/dev/sdb", "/dev/sdbp"));
+    }
+
+    #[test]
+    fn test_rehearse_partitioning() {
+        let dry_run = DryRun::new();
+        let planned = [
+            PlannedPartition {
+                start_sector: 2048,
+                size_sectors: 1048576,
+                type_id: None,
+                name: Some("EFI".to_string()),
+            },
+            PlannedPartition {
+                start_sector: 1050624,
+                size_sectors: 2048,
+                type_id: None,
+                name: None,
+            },
+        ];
+        rehearse_partitioning(&dry_run, "/dev/sdb", "GPT", &planned);
+
+        let actions = dry_run.actions();
+        assert_eq!(actions.len(), 3);
+        assert_eq!(actions[0].command_line(), "parted -s /dev/sdb mklabel gpt");
+        assert_eq!(
+            actions[1].command_line(),
+            "parted -s /dev/sdb mkpart EFI 2048s 1050623s"
+        );
+        assert_eq!(
+            actions[2].command_line(),
+            "parted -s /dev/sdb mkpart '' 1050624s 1052671s"
+        );
+    }
+
     #[test]
     fn test_parse_size_to_sectors() {
         let config = Arc::new(RwLock::new(DiskConfig::default()));
@@ -0,0 +1,527 @@
+use super::format::{DiskFormatter, FileSystemType, FormatParams};
+use super::windows_usb::{self, partition_path};
+use super::{DiskOperation, DiskProgress};
+use crate::error::{DiskError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Smallest root partition a base system with kernel and GRUB fits in, in MiB
+const MIN_ROOT_MB: u64 = 2048;
+
+const TOTAL_STEPS: u32 = 6;
+
+/// Tool that installs the base system into the mounted root
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BootstrapTool {
+    /// Debian and derivatives
+    #[default]
+    Debootstrap,
+    /// Arch Linux, from arch-install-scripts
+    Pacstrap,
+}
+
+/// Parameters for installing a system straight onto a target
+#[derive(Debug, Clone)]
+pub struct BootstrapParams {
+    /// Target whole-disk device (e.g., /dev/sdb)
+    pub device: String,
+    pub tool: BootstrapTool,
+    /// Debian release, ignored by pacstrap
+    pub suite: String,
+    pub mirror: Option<String>,
+    /// Extra packages on top of the base system, kernel and GRUB
+    pub packages: Vec<String>,
+    pub hostname: String,
+    /// Script copied into the new system and run there with /bin/sh
+    pub post_install: Option<PathBuf>,
+    /// Directory under which the new root is mounted while installing
+    pub work_dir: PathBuf,
+    /// EFI system partition size in MiB
+    pub esp_size_mb: u64,
+}
+
+impl BootstrapParams {
+    pub fn new(device: String, tool: BootstrapTool, suite: String) -> Self {
+        Self {
+            device,
+            tool,
+            suite,
+            mirror: None,
+            packages: Vec::new(),
+            hostname: "node".to_string(),
+            post_install: None,
+            work_dir: PathBuf::from("/mnt/usb-installer-target"),
+            esp_size_mb: 512,
+        }
+    }
+
+    /// Set package mirror
+    pub fn with_mirror(mut self, mirror: Option<String>) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// Set extra packages
+    pub fn with_packages(mut self, packages: Vec<String>) -> Self {
+        self.packages = packages;
+        self
+    }
+
+    /// Set host name of the new system
+    pub fn with_hostname(mut self, hostname: String) -> Self {
+        self.hostname = hostname;
+        self
+    }
+
+    /// Set post-install script
+    pub fn with_post_install(mut self, script: Option<PathBuf>) -> Self {
+        self.post_install = script;
+        self
+    }
+
+    /// Set mount directory
+    pub fn with_work_dir(mut self, work_dir: PathBuf) -> Self {
+        self.work_dir = work_dir;
+        self
+    }
+
+    /// Set EFI system partition size
+    pub fn with_esp_size(mut self, size_mb: u64) -> Self {
+        self.esp_size_mb = size_mb;
+        self
+    }
+}
+
+/// Installs a minimal system onto a target with debootstrap or pacstrap,
+/// for headless images that have no installer to boot
+pub struct Bootstrapper {
+    formatter: DiskFormatter,
+    progress_tx: Option<broadcast::Sender<DiskProgress>>,
+}
+
+impl Bootstrapper {
+    pub fn new() -> Self {
+        Self {
+            formatter: DiskFormatter::new(),
+            progress_tx: None,
+        }
+    }
+
+    /// Publish progress events on the given channel
+    pub fn with_progress(mut self, tx: broadcast::Sender<DiskProgress>) -> Self {
+        self.formatter = DiskFormatter::new().with_progress(tx.clone());
+        self.progress_tx = Some(tx);
+        self
+    }
+
+    /// Partition and format `params.device`, install the base system, kernel
+    /// and GRUB into it and run the post-install script.
+    ///
+    /// Layout is GPT: a BIOS boot partition for GRUB's core image, a FAT32
+    /// ESP mounted at `/boot/efi`, and an ext4 root filling the rest. GRUB is
+    /// installed for BIOS and as the removable-media UEFI loader, so the
+    /// target boots on whichever machine it ends up in.
+    pub async fn create(&self, params: &BootstrapParams) -> Result<()> {
+        info!(
+            "Bootstrapping {} with {:?} ({})",
+            params.device, params.tool, params.suite
+        );
+        let arch = std::env::consts::ARCH;
+        self.validate(params, arch)?;
+
+        report(&self.progress_tx, &params.device, 1, 5, "Partitioning");
+        windows_usb::unmount_device(&params.device);
+        self.partition(params)?;
+
+        let esp_part = partition_path(&params.device, 2);
+        let root_part = partition_path(&params.device, 3);
+
+        report(
+            &self.progress_tx,
+            &params.device,
+            2,
+            10,
+            "Formatting partitions",
+        );
+        let esp = FormatParams::new(esp_part.clone(), FileSystemType::Vfat)
+            .with_label("ESP".to_string())
+            .force();
+        self.formatter.format(&esp).await?;
+        let root = FormatParams::new(root_part.clone(), FileSystemType::Ext4)
+            .with_label("rootfs".to_string())
+            .force();
+        self.formatter.format(&root).await?;
+
+        let owned = params.clone();
+        let progress = self.progress_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            install(
+                &owned,
+                arch,
+                &root_part,
+                &esp_part,
+                |step, percentage, message| {
+                    report(&progress, &owned.device, step, percentage, message)
+                },
+            )
+        })
+        .await
+        .map_err(|e| DiskError::BootstrapFailed(format!("install task failed: {}", e)))??;
+
+        report(
+            &self.progress_tx,
+            &params.device,
+            TOTAL_STEPS,
+            100,
+            "System installed",
+        );
+        info!("Bootstrapped {}", params.device);
+        Ok(())
+    }
+
+    fn validate(&self, params: &BootstrapParams, arch: &str) -> Result<()> {
+        if !Path::new(&params.device).exists() {
+            return Err(DiskError::DiskNotFound(params.device.clone()).into());
+        }
+        if grub_targets(arch).is_empty() {
+            return Err(
+                DiskError::BootstrapFailed(format!("unsupported architecture {}", arch)).into(),
+            );
+        }
+        if let Some(script) = &params.post_install {
+            if !script.is_file() {
+                return Err(DiskError::BootstrapFailed(format!(
+                    "post-install script {} not found",
+                    script.display()
+                ))
+                .into());
+            }
+        }
+
+        let needed = (params.esp_size_mb + 2 + MIN_ROOT_MB) * 1024 * 1024;
+        let available = windows_usb::device_size(&params.device)?;
+        if needed > available {
+            return Err(DiskError::InsufficientSpace(needed, available).into());
+        }
+        Ok(())
+    }
+
+    fn partition(&self, params: &BootstrapParams) -> Result<()> {
+        windows_usb::run(
+            Command::new("wipefs").args(["-a", &params.device]),
+            "wipefs",
+        )
+        .map_err(|e| DiskError::PartitionFailed(e.to_string()))?;
+
+        let esp_end = format!("{}MiB", params.esp_size_mb + 2);
+        windows_usb::run(
+            Command::new("parted").args([
+                "-s",
+                "-a",
+                "optimal",
+                &params.device,
+                "mklabel",
+                "gpt",
+                "mkpart",
+                "BIOS",
+                "1MiB",
+                "2MiB",
+                "set",
+                "1",
+                "bios_grub",
+                "on",
+                "mkpart",
+                "ESP",
+                "fat32",
+                "2MiB",
+                &esp_end,
+                "set",
+                "2",
+                "esp",
+                "on",
+                "mkpart",
+                "root",
+                "ext4",
+                &esp_end,
+                "100%",
+            ]),
+            "parted",
+        )
+        .map_err(|e| DiskError::PartitionFailed(e.to_string()))?;
+
+        let _ = Command::new("partprobe").arg(&params.device).status();
+        let _ = Command::new("udevadm").arg("settle").status();
+        Ok(())
+    }
+}
+
+impl Default for Bootstrapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Mount the new root, populate it and unmount everything again, also
+/// when a step fails
+fn install(
+    params: &BootstrapParams,
+    arch: &str,
+    root_part: &str,
+    esp_part: &str,
+    report: impl Fn(u32, u8, &str),
+) -> Result<()> {
+    let root = params.work_dir.join("root");
+    let mut mounted = Vec::new();
+    let result = populate(
+        params,
+        arch,
+        &root,
+        root_part,
+        esp_part,
+        &report,
+        &mut mounted,
+    );
+
+    let mut unmounted = Ok(());
+    for target in mounted.iter().rev() {
+        if let Err(e) = windows_usb::umount(target) {
+            warn!("Failed to unmount {}: {}", target.display(), e);
+            if unmounted.is_ok() {
+                unmounted = Err(e);
+            }
+        }
+    }
+    result?;
+    unmounted
+}
+
+fn populate(
+    params: &BootstrapParams,
+    arch: &str,
+    root: &Path,
+    root_part: &str,
+    esp_part: &str,
+    report: &impl Fn(u32, u8, &str),
+    mounted: &mut Vec<PathBuf>,
+) -> Result<()> {
+    mount(&[root_part], root, mounted)?;
+    mount(&[esp_part], &root.join("boot/efi"), mounted)?;
+
+    report(3, 15, "Installing base system");
+    let (program, args) = bootstrap_command(params, arch, root);
+    windows_usb::run(Command::new(program).args(&args), program).map_err(bootstrap_error)?;
+
+    report(4, 70, "Configuring system");
+    let fstab = fstab(&fs_uuid(root_part)?, &fs_uuid(esp_part)?);
+    fs::write(root.join("etc/fstab"), fstab)?;
+    fs::write(root.join("etc/hostname"), format!("{}\n", params.hostname))?;
+    fs::write(
+        root.join("etc/hosts"),
+        format!("127.0.0.1\tlocalhost\n127.0.1.1\t{}\n", params.hostname),
+    )?;
+    for dir in ["/dev", "/proc", "/sys"] {
+        mount(&["--bind", dir], &root.join(&dir[1..]), mounted)?;
+    }
+
+    report(5, 80, "Installing bootloader");
+    for target in grub_targets(arch) {
+        let target = format!("--target={}", target);
+        if target.ends_with("-efi") {
+            chroot(
+                root,
+                &[
+                    "grub-install",
+                    &target,
+                    "--efi-directory=/boot/efi",
+                    "--removable",
+                    "--no-nvram",
+                ],
+            )?;
+        } else {
+            chroot(root, &["grub-install", &target, &params.device])?;
+        }
+    }
+    chroot(root, &["grub-mkconfig", "-o", "/boot/grub/grub.cfg"])?;
+
+    if let Some(script) = &params.post_install {
+        report(6, 90, "Running post-install script");
+        fs::copy(script, root.join("root/post-install.sh"))?;
+        let result = chroot(root, &["/bin/sh", "/root/post-install.sh"]);
+        let _ = fs::remove_file(root.join("root/post-install.sh"));
+        result?;
+    }
+    Ok(())
+}
+
+/// Program and arguments installing the base system, kernel and GRUB for
+/// `arch` plus the configured packages into `root`
+fn bootstrap_command(
+    params: &BootstrapParams,
+    arch: &str,
+    root: &Path,
+) -> (&'static str, Vec<String>) {
+    let root = root.display().to_string();
+    match params.tool {
+        BootstrapTool::Debootstrap => {
+            let base: &[&str] = match arch {
+                "aarch64" => &["linux-image-arm64", "grub-efi-arm64-bin", "grub2-common"],
+                _ => &[
+                    "linux-image-amd64",
+                    "grub-pc-bin",
+                    "grub-efi-amd64-bin",
+                    "grub2-common",
+                ],
+            };
+            let include: Vec<&str> = base
+                .iter()
+                .copied()
+                .chain(params.packages.iter().map(String::as_str))
+                .collect();
+            let mut args = vec![
+                format!("--include={}", include.join(",")),
+                params.suite.clone(),
+                root,
+            ];
+            args.extend(params.mirror.clone());
+            ("debootstrap", args)
+        }
+        BootstrapTool::Pacstrap => {
+            // -K initialises an empty keyring in the target
+            let mut args = vec!["-K".to_string(), root];
+            args.extend(["base", "linux", "grub"].map(String::from));
+            args.extend(params.packages.iter().cloned());
+            ("pacstrap", args)
+        }
+    }
+}
+
+/// GRUB platforms installed on `arch`; empty when unsupported
+fn grub_targets(arch: &str) -> &'static [&'static str] {
+    match arch {
+        "x86_64" => &["i386-pc", "x86_64-efi"],
+        "aarch64" => &["arm64-efi"],
+        _ => &[],
+    }
+}
+
+/// fstab mounting the root and ESP by filesystem UUID, which survives the
+/// target showing up under a different device name
+pub fn fstab(root_uuid: &str, esp_uuid: &str) -> String {
+    format!(
+        "UUID={}\t/\text4\terrors=remount-ro\t0\t1\n\
+         UUID={}\t/boot/efi\tvfat\tumask=0077\t0\t2\n",
+        root_uuid, esp_uuid
+    )
+}
+
+fn fs_uuid(device: &str) -> Result<String> {
+    let output = Command::new("blkid")
+        .args(["-s", "UUID", "-o", "value", device])
+        .output()
+        .map_err(bootstrap_error)?;
+    let uuid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || uuid.is_empty() {
+        return Err(DiskError::BootstrapFailed(format!("no filesystem UUID on {}", device)).into());
+    }
+    Ok(uuid)
+}
+
+fn mount(args: &[&str], target: &Path, mounted: &mut Vec<PathBuf>) -> Result<()> {
+    fs::create_dir_all(target)?;
+    windows_usb::run(Command::new("mount").args(args).arg(target), "mount")
+        .map_err(bootstrap_error)?;
+    mounted.push(target.to_path_buf());
+    Ok(())
+}
+
+fn chroot(root: &Path, command: &[&str]) -> Result<()> {
+    windows_usb::run(Command::new("chroot").arg(root).args(command), command[0])
+        .map_err(bootstrap_error)?;
+    Ok(())
+}
+
+fn bootstrap_error(e: io::Error) -> crate::error::Error {
+    DiskError::BootstrapFailed(e.to_string()).into()
+}
+
+fn report(
+    progress_tx: &Option<broadcast::Sender<DiskProgress>>,
+    device: &str,
+    step: u32,
+    percentage: u8,
+    message: &str,
+) {
+    if let Some(tx) = progress_tx {
+        let _ = tx.send(DiskProgress {
+            device: device.to_string(),
+            operation: DiskOperation::Bootstrap,
+            step,
+            total_steps: TOTAL_STEPS,
+            percentage,
+            message: message.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bootstrap_command() {
+        let root = Path::new("/mnt/target/root");
+        let params = BootstrapParams::new(
+            "/dev/sdb".to_string(),
+            BootstrapTool::Debootstrap,
+            "bookworm".to_string(),
+        )
+        .with_packages(vec!["openssh-server".to_string()])
+        .with_mirror(Some("http://deb.debian.org/debian".to_string()));
+
+        let (program, args) = bootstrap_command(&params, "x86_64", root);
+        assert_eq!(program, "debootstrap");
+        assert_eq!(
+            args,
+            [
+                "--include=linux-image-amd64,grub-pc-bin,grub-efi-amd64-bin,grub2-common,openssh-server",
+                "bookworm",
+                "/mnt/target/root",
+                "http://deb.debian.org/debian",
+            ]
+        );
+
+        let params = BootstrapParams {
+            tool: BootstrapTool::Pacstrap,
+            ..params
+        }
+        .with_packages(vec!["openssh".to_string()]);
+        let (program, args) = bootstrap_command(&params, "x86_64", root);
+        assert_eq!(program, "pacstrap");
+        assert_eq!(
+            args,
+            ["-K", "/mnt/target/root", "base", "linux", "grub", "openssh"]
+        );
+
+        assert_eq!(grub_targets("aarch64"), ["arm64-efi"]);
+        assert!(grub_targets("riscv64").is_empty());
+    }
+
+    #[test]
+    fn test_fstab() {
+        let fstab = fstab("0b6c1a2e-5f7d-4d1e-9a63-2f0c8d1b7e44", "4A1F-90C2");
+        let lines: Vec<&str> = fstab.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "UUID=0b6c1a2e-5f7d-4d1e-9a63-2f0c8d1b7e44\t/\text4\terrors=remount-ro\t0\t1",
+                "UUID=4A1F-90C2\t/boot/efi\tvfat\tumask=0077\t0\t2",
+            ]
+        );
+    }
+}
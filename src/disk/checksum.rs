@@ -0,0 +1,164 @@
+use crate::error::{DiskError, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::Read;
+use xxhash_rust::xxh3::Xxh3;
+
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Digest used to verify written data and to fingerprint sources
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    /// Cryptographic and several times faster than SHA-256 on ARM cores
+    /// without SHA extensions
+    Blake3,
+    /// Non-cryptographic; detects corruption but not tampering
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn is_cryptographic(&self) -> bool {
+        !matches!(self, Self::Xxh3)
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256 => write!(f, "SHA-256"),
+            Self::Blake3 => write!(f, "BLAKE3"),
+            Self::Xxh3 => write!(f, "XXH3"),
+        }
+    }
+}
+
+/// A digest together with the algorithm that produced it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    pub algorithm: HashAlgorithm,
+    pub value: String,
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.algorithm, self.value)
+    }
+}
+
+/// Incremental hashing, fed chunk by chunk alongside a copy
+pub struct Hasher {
+    state: HasherState,
+}
+
+enum HasherState {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        let state = match algorithm {
+            HashAlgorithm::Sha256 => HasherState::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => HasherState::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => HasherState::Xxh3(Box::new(Xxh3::new())),
+        };
+        Self { state }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.state {
+            HasherState::Sha256(hasher) => hasher.update(data),
+            HasherState::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            HasherState::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Lowercase hex, as `sha256sum`, `b3sum` and `xxhsum -H3` print it
+    pub fn finish(self) -> Checksum {
+        let (algorithm, value) = match self.state {
+            HasherState::Sha256(hasher) => (HashAlgorithm::Sha256, hex(&hasher.finalize())),
+            HasherState::Blake3(hasher) => (
+                HashAlgorithm::Blake3,
+                hasher.finalize().to_hex().to_string(),
+            ),
+            HasherState::Xxh3(hasher) => (HashAlgorithm::Xxh3, format!("{:016x}", hasher.digest())),
+        };
+        Checksum { algorithm, value }
+    }
+}
+
+/// Hash everything `reader` yields
+pub fn hash_reader(algorithm: HashAlgorithm, mut reader: impl Read) -> Result<Checksum> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .map_err(|e| checksum_error(format!("Read failed while hashing: {}", e)))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+pub fn hash_bytes(algorithm: HashAlgorithm, data: &[u8]) -> Checksum {
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update(data);
+    hasher.finish()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn checksum_error(message: String) -> crate::error::Error {
+    DiskError::ChecksumFailed(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_bytes() {
+        let checksum = hash_bytes(HashAlgorithm::Sha256, b"");
+        assert_eq!(checksum.algorithm, HashAlgorithm::Sha256);
+        assert_eq!(
+            checksum.value,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hash_bytes(HashAlgorithm::Blake3, b"").value,
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(
+            hash_bytes(HashAlgorithm::Xxh3, b"").value,
+            "2d06800538d394c2"
+        );
+        assert!(!HashAlgorithm::Xxh3.is_cryptographic());
+    }
+
+    #[test]
+    fn test_hash_reader_matches_hash_bytes() {
+        let data: Vec<u8> = (0..CHUNK_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+        for algorithm in [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Xxh3,
+        ] {
+            assert_eq!(
+                hash_reader(algorithm, &data[..]).unwrap(),
+                hash_bytes(algorithm, &data)
+            );
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use super::expand;
+use crate::chaos::{self, FaultPoint};
+use crate::error::{DiskError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tracing::info;
+
+/// Size of the NoCloud seed partition, in MiB
+pub const SEED_SIZE_MB: u64 = 16;
+
+const QCOW2_MAGIC: &[u8] = b"QFI\xfb";
+
+/// Compression wrapped around a raw disk image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Xz,
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    fn program(&self) -> &'static str {
+        match self {
+            Self::Xz => "xz",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        }
+    }
+}
+
+/// Layout of an image file, recognised from its leading bytes rather than
+/// its name: Ubuntu's `.img` cloud images are qcow2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "compression", rename_all = "lowercase")]
+pub enum ImageKind {
+    Raw,
+    Compressed(Compression),
+    /// QEMU copy-on-write image, converted onto the device by `qemu-img`
+    Qcow2,
+}
+
+impl ImageKind {
+    pub fn detect(image: &Path) -> Result<Self> {
+        let mut header = Vec::with_capacity(8);
+        File::open(image)
+            .and_then(|file| file.take(8).read_to_end(&mut header))
+            .map_err(|e| cloud_error(format!("{}: {}", image.display(), e)))?;
+        Ok(Self::from_header(&header))
+    }
+
+    fn from_header(header: &[u8]) -> Self {
+        if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Compressed(Compression::Xz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Compressed(Compression::Zstd)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Self::Compressed(Compression::Gzip)
+        } else if header.starts_with(QCOW2_MAGIC) {
+            Self::Qcow2
+        } else {
+            Self::Raw
+        }
+    }
+}
+
+/// cloud-init NoCloud seed, written to a `CIDATA` partition after the image
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CloudSeed {
+    pub user_data: String,
+    /// Generated with a random instance-id when unset, so every target
+    /// runs first-boot configuration
+    pub meta_data: Option<String>,
+    pub network_config: Option<String>,
+}
+
+/// Decompressed image data from `xz`, `zstd` or `gzip -dc`. The compressed
+/// file is fed to the tool from a thread that counts the bytes consumed,
+/// which drives progress since the decompressed size is not known up front.
+pub struct Decompressor {
+    child: Option<Child>,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<io::Result<()>>>,
+    consumed: Arc<AtomicU64>,
+    program: &'static str,
+}
+
+impl Decompressor {
+    pub fn spawn(image: &Path, compression: Compression) -> Result<Self> {
+        let program = compression.program();
+        chaos::inject(FaultPoint::CommandExec, program)?;
+
+        let mut source =
+            File::open(image).map_err(|e| cloud_error(format!("{}: {}", image.display(), e)))?;
+        let mut child = Command::new(program)
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| cloud_error(format!("Failed to run {}: {}", program, e)))?;
+        let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(cloud_error(format!("{} has no pipes", program)));
+        };
+
+        let consumed = Arc::new(AtomicU64::new(0));
+        let counter = consumed.clone();
+        let feeder = thread::spawn(move || -> io::Result<()> {
+            let mut buf = vec![0u8; 1024 * 1024];
+            loop {
+                let n = source.read(&mut buf)?;
+                if n == 0 {
+                    return Ok(());
+                }
+                stdin.write_all(&buf[..n])?;
+                counter.fetch_add(n as u64, Ordering::Relaxed);
+            }
+        });
+
+        Ok(Self {
+            child: Some(child),
+            stdout,
+            feeder: Some(feeder),
+            consumed,
+            program,
+        })
+    }
+
+    /// Compressed bytes handed to the tool so far
+    pub fn consumed(&self) -> Arc<AtomicU64> {
+        self.consumed.clone()
+    }
+
+    /// Wait for the tool once its output was read to the end; fails when the
+    /// compressed data was corrupt or truncated
+    pub fn finish(mut self) -> Result<()> {
+        let fed = self.feeder.take().map(|feeder| feeder.join());
+        let Some(child) = self.child.take() else {
+            return Ok(());
+        };
+        let output = child
+            .wait_with_output()
+            .map_err(|e| cloud_error(format!("{} failed: {}", self.program, e)))?;
+        if !output.status.success() {
+            return Err(cloud_error(format!(
+                "{} exited with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        match fed {
+            Some(Ok(Err(e))) => Err(cloud_error(format!("Reading image failed: {}", e))),
+            Some(Err(_)) => Err(cloud_error("Image reader panicked".to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for Decompressor {
+    fn drop(&mut self) {
+        // Abandoned mid-stream, e.g. after a write error on the target
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Size of the disk a qcow2 image describes
+pub fn qcow2_size(image: &Path) -> Result<u64> {
+    let output = Command::new("qemu-img")
+        .args(["info", "--output=json", "-f", "qcow2"])
+        .arg(image)
+        .output()
+        .map_err(|e| cloud_error(format!("Failed to run qemu-img: {}", e)))?;
+    if !output.status.success() {
+        return Err(cloud_error(format!(
+            "qemu-img info failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| cloud_error(format!("Invalid qemu-img output: {}", e)))?;
+    info["virtual-size"]
+        .as_u64()
+        .ok_or_else(|| cloud_error("qemu-img reported no virtual size".to_string()))
+}
+
+/// Convert a qcow2 image onto `device`, reporting percent done
+pub fn write_qcow2(image: &Path, device: &str, mut progress: impl FnMut(u8)) -> Result<()> {
+    chaos::inject(FaultPoint::CommandExec, "qemu-img")?;
+
+    // -n: the device exists and must not be created
+    let mut child = Command::new("qemu-img")
+        .args(["convert", "-p", "-n", "-f", "qcow2", "-O", "raw"])
+        .arg(image)
+        .arg(device)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| cloud_error(format!("Failed to run qemu-img: {}", e)))?;
+
+    if let Some(mut stdout) = child.stdout.take() {
+        // Progress lines are separated by carriage returns
+        let mut buf = [0u8; 256];
+        let mut line = Vec::new();
+        while let Ok(n) = stdout.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            for &byte in &buf[..n] {
+                if byte == b'\r' || byte == b'\n' {
+                    if let Some(percentage) = parse_qemu_progress(&String::from_utf8_lossy(&line)) {
+                        progress(percentage);
+                    }
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+        }
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| cloud_error(format!("qemu-img failed: {}", e)))?;
+    if !output.status.success() {
+        return Err(cloud_error(format!(
+            "qemu-img convert failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Compare a qcow2 image with the first `size` bytes of `device`. The raw
+/// driver's size option keeps qemu-img from comparing the rest of a
+/// device larger than the image.
+pub fn compare_qcow2(image: &Path, device: &str, size: u64) -> Result<bool> {
+    let target = format!(
+        "json:{}",
+        serde_json::json!({
+            "driver": "raw",
+            "size": size,
+            "file": { "driver": "host_device", "filename": device },
+        })
+    );
+    let output = Command::new("qemu-img")
+        .args(["compare", "-q", "-f", "qcow2"])
+        .arg(image)
+        .arg(target)
+        .output()
+        .map_err(|e| cloud_error(format!("Failed to run qemu-img: {}", e)))?;
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(cloud_error(format!(
+            "qemu-img compare failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+/// `    (42.17/100%)` -> 42
+fn parse_qemu_progress(line: &str) -> Option<u8> {
+    let done = line.trim().strip_prefix('(')?.split('/').next()?;
+    done.parse::<f64>().ok().map(|p| p.clamp(0.0, 100.0) as u8)
+}
+
+/// Append a `CIDATA` partition to `device` holding the NoCloud seed files
+/// and return its path. Files are copied with mtools, so nothing is mounted.
+pub fn write_seed(device: &str, seed: &CloudSeed) -> Result<String> {
+    let partition = expand::append_partition(device, SEED_SIZE_MB)?;
+
+    let mkfs = Command::new("mkfs.vfat")
+        .args(["-n", "CIDATA", &partition])
+        .output()
+        .map_err(|e| cloud_error(format!("Failed to run mkfs.vfat: {}", e)))?;
+    if !mkfs.status.success() {
+        return Err(DiskError::FormatFailed(format!(
+            "{}: {}",
+            partition,
+            String::from_utf8_lossy(&mkfs.stderr).trim()
+        ))
+        .into());
+    }
+
+    let dir = std::env::temp_dir().join(format!("cidata-{}", uuid::Uuid::new_v4()));
+    let result = copy_seed_files(&dir, &partition, seed);
+    let _ = fs::remove_dir_all(&dir);
+    result?;
+
+    info!("cloud-init seed written to {}", partition);
+    Ok(partition)
+}
+
+fn copy_seed_files(dir: &Path, partition: &str, seed: &CloudSeed) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut files = vec![
+        ("user-data", seed.user_data.clone()),
+        (
+            "meta-data",
+            seed.meta_data
+                .clone()
+                .unwrap_or_else(|| format!("instance-id: iid-{}\n", uuid::Uuid::new_v4())),
+        ),
+    ];
+    if let Some(network_config) = &seed.network_config {
+        files.push(("network-config", network_config.clone()));
+    }
+
+    let mut mcopy = Command::new("mcopy");
+    mcopy.args(["-i", partition]);
+    for (name, contents) in &files {
+        let path = dir.join(name);
+        fs::write(&path, contents)?;
+        mcopy.arg(path);
+    }
+    let output = mcopy
+        .arg("::")
+        .output()
+        .map_err(|e| cloud_error(format!("Failed to run mcopy: {}", e)))?;
+    if !output.status.success() {
+        return Err(cloud_error(format!(
+            "mcopy failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+fn cloud_error(message: String) -> crate::error::Error {
+    DiskError::ImagingFailed(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_image_kind() {
+        assert_eq!(
+            ImageKind::from_header(b"\xfd7zXZ\x00\x00\x04"),
+            ImageKind::Compressed(Compression::Xz)
+        );
+        assert_eq!(
+            ImageKind::from_header(&[0x28, 0xb5, 0x2f, 0xfd, 0x04]),
+            ImageKind::Compressed(Compression::Zstd)
+        );
+        assert_eq!(
+            ImageKind::from_header(&[0x1f, 0x8b, 0x08]),
+            ImageKind::Compressed(Compression::Gzip)
+        );
+        assert_eq!(
+            ImageKind::from_header(b"QFI\xfb\x00\x00\x00\x03"),
+            ImageKind::Qcow2
+        );
+        assert_eq!(ImageKind::from_header(&[0xeb, 0x63, 0x90]), ImageKind::Raw);
+        assert_eq!(ImageKind::from_header(&[]), ImageKind::Raw);
+
+        assert_eq!(parse_qemu_progress("    (42.17/100%)"), Some(42));
+        assert_eq!(parse_qemu_progress("    (100.00/100%)"), Some(100));
+        assert_eq!(parse_qemu_progress("qemu-img: error"), None);
+    }
+
+    #[test]
+    fn test_decompressor_streams_gzip() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        fs::write(&image, vec![7u8; 3 * 1024 * 1024]).unwrap();
+        let status = Command::new("gzip").arg("-k").arg(&image).status().unwrap();
+        assert!(status.success());
+
+        let compressed = dir.path().join("disk.img.gz");
+        assert_eq!(
+            ImageKind::detect(&compressed).unwrap(),
+            ImageKind::Compressed(Compression::Gzip)
+        );
+        let mut decompressor = Decompressor::spawn(&compressed, Compression::Gzip).unwrap();
+        let consumed = decompressor.consumed();
+        let mut data = Vec::new();
+        decompressor.read_to_end(&mut data).unwrap();
+        decompressor.finish().unwrap();
+        assert_eq!(data, fs::read(&image).unwrap());
+        assert_eq!(
+            consumed.load(Ordering::Relaxed),
+            fs::metadata(&compressed).unwrap().len()
+        );
+
+        // A truncated download is reported once the stream ends
+        let truncated = dir.path().join("truncated.img.gz");
+        let bytes = fs::read(&compressed).unwrap();
+        fs::File::create(&truncated)
+            .unwrap()
+            .write_all(&bytes[..bytes.len() / 2])
+            .unwrap();
+        let mut decompressor = Decompressor::spawn(&truncated, Compression::Gzip).unwrap();
+        let _ = decompressor.read_to_end(&mut Vec::new());
+        assert!(decompressor.finish().is_err());
+    }
+}
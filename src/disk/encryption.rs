@@ -0,0 +1,180 @@
+use serde::Serialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use tracing::debug;
+
+/// Enough of each volume to reach the APFS keylocker field
+const HEADER_SIZE: usize = 4096;
+const LUKS_MAGIC: &[u8] = b"LUKS\xba\xbe";
+/// OEM ID of a BitLocker (and BitLocker To Go) boot sector
+const BITLOCKER_OEM_ID: &[u8] = b"-FVE-FS-";
+const APFS_MAGIC: &[u8] = b"NXSB";
+/// `nx_keylocker.pr_block_count`; only containers with an encrypted
+/// volume have a keybag
+const APFS_KEYLOCKER_COUNT: usize = 1304;
+const CORE_STORAGE_MAGIC: &[u8] = b"CS";
+
+/// Full-disk encryption scheme found on a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptionKind {
+    Luks,
+    BitLocker,
+    /// Encrypted APFS container or Core Storage volume
+    FileVault,
+}
+
+impl fmt::Display for EncryptionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionKind::Luks => write!(f, "LUKS"),
+            EncryptionKind::BitLocker => write!(f, "BitLocker"),
+            EncryptionKind::FileVault => write!(f, "FileVault"),
+        }
+    }
+}
+
+/// A volume on a target that holds encrypted data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EncryptedVolume {
+    pub path: String,
+    pub kind: EncryptionKind,
+}
+
+/// Encryption scheme of a volume from its first bytes
+pub fn identify(header: &[u8]) -> Option<EncryptionKind> {
+    if header.starts_with(LUKS_MAGIC) {
+        return Some(EncryptionKind::Luks);
+    }
+    if header.get(3..11) == Some(BITLOCKER_OEM_ID) {
+        return Some(EncryptionKind::BitLocker);
+    }
+    if header.get(32..36) == Some(APFS_MAGIC) {
+        let keylocker = header.get(APFS_KEYLOCKER_COUNT..APFS_KEYLOCKER_COUNT + 8)?;
+        return (u64::from_le_bytes(keylocker.try_into().ok()?) > 0)
+            .then_some(EncryptionKind::FileVault);
+    }
+    // Core Storage physical volume, format version 1
+    if header.get(88..90) == Some(CORE_STORAGE_MAGIC) && header.get(8..10) == Some(&[1, 0]) {
+        return Some(EncryptionKind::FileVault);
+    }
+    None
+}
+
+/// What a scan of a target found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inspection {
+    pub encrypted: Vec<EncryptedVolume>,
+    /// Volumes that could not be read, so may hold encrypted data
+    pub unreadable: Vec<String>,
+}
+
+/// Encrypted volumes on `device`: the device itself and, for a whole
+/// disk, each of its partitions. Unreadable volumes are skipped.
+pub fn scan(device: &str) -> Vec<EncryptedVolume> {
+    inspect(device).encrypted
+}
+
+/// Like [`scan`], but lists the volumes that could not be read
+pub fn inspect(device: &str) -> Inspection {
+    let mut volumes = vec![device.to_string()];
+    volumes.extend(partitions(device));
+
+    let mut inspection = Inspection::default();
+    for path in volumes {
+        match read_header(&path) {
+            Some(header) => {
+                if let Some(kind) = identify(&header) {
+                    inspection.encrypted.push(EncryptedVolume { path, kind });
+                }
+            }
+            None => inspection.unreadable.push(path),
+        }
+    }
+    inspection
+}
+
+fn read_header(path: &str) -> Option<Vec<u8>> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    match File::open(path).and_then(|f| f.take(HEADER_SIZE as u64).read_to_end(&mut header)) {
+        Ok(_) => Some(header),
+        Err(e) => {
+            debug!("Cannot read {} for encryption check: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Partition device paths of a whole disk, from sysfs
+fn partitions(device: &str) -> Vec<String> {
+    let Some(name) = device.strip_prefix("/dev/") else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(Path::new("/sys/class/block").join(name)) else {
+        return Vec::new();
+    };
+    let mut partitions: Vec<String> = entries
+        .flatten()
+        .filter(|e| e.path().join("partition").exists())
+        .map(|e| format!("/dev/{}", e.file_name().to_string_lossy()))
+        .collect();
+    partitions.sort();
+    partitions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_identify() {
+        let mut luks = vec![0u8; 512];
+        luks[..6].copy_from_slice(LUKS_MAGIC);
+        assert_eq!(identify(&luks), Some(EncryptionKind::Luks));
+
+        let mut bitlocker = vec![0u8; 512];
+        bitlocker[..11].copy_from_slice(b"\xeb\x58\x90-FVE-FS-");
+        assert_eq!(identify(&bitlocker), Some(EncryptionKind::BitLocker));
+
+        let mut apfs = vec![0u8; HEADER_SIZE];
+        apfs[32..36].copy_from_slice(APFS_MAGIC);
+        assert_eq!(identify(&apfs), None);
+        apfs[APFS_KEYLOCKER_COUNT] = 1;
+        assert_eq!(identify(&apfs), Some(EncryptionKind::FileVault));
+
+        let mut core_storage = vec![0u8; 512];
+        core_storage[8] = 1;
+        core_storage[88..90].copy_from_slice(CORE_STORAGE_MAGIC);
+        assert_eq!(identify(&core_storage), Some(EncryptionKind::FileVault));
+
+        // Plain NTFS and an empty device
+        let mut ntfs = vec![0u8; 512];
+        ntfs[3..11].copy_from_slice(b"NTFS    ");
+        assert_eq!(identify(&ntfs), None);
+        assert_eq!(identify(&[]), None);
+    }
+
+    #[test]
+    fn test_scan_image_file() {
+        let dir = TempDir::new().unwrap();
+        let image = dir.path().join("disk.img");
+        let mut header = vec![0u8; 8192];
+        header[..6].copy_from_slice(LUKS_MAGIC);
+        fs::write(&image, header).unwrap();
+
+        let path = image.to_string_lossy().into_owned();
+        assert_eq!(
+            scan(&path),
+            vec![EncryptedVolume {
+                path: path.clone(),
+                kind: EncryptionKind::Luks
+            }]
+        );
+        let missing = dir.path().join("missing").to_string_lossy().into_owned();
+        assert!(scan(&missing).is_empty());
+        assert_eq!(inspect(&missing).unreadable, vec![missing]);
+    }
+}
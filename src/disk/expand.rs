@@ -0,0 +1,302 @@
+use super::fingerprint::{self, PartitionFingerprint};
+use super::format::FileSystemType;
+use super::relabel::parse_blkid_type;
+use super::shrink::{self, ShrinkableFs};
+use super::windows_usb::partition_path;
+use crate::error::{DiskError, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tracing::{info, warn};
+
+/// Result of growing the last partition of a freshly written image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Expansion {
+    pub partition: String,
+    pub old_size_sectors: u64,
+    pub new_size_sectors: u64,
+    pub filesystem: Option<String>,
+    /// False when the filesystem type cannot be grown offline; the partition
+    /// is still enlarged and the filesystem can be grown on first boot
+    pub filesystem_grown: bool,
+}
+
+/// Grow the last partition on `device` to the end of the disk, less
+/// `reserve_bytes` kept free for a partition appended afterwards, then the
+/// filesystem inside it. Moves the backup GPT to the real end of the disk
+/// first, since the image left it at the end of the image.
+pub fn expand_last_partition(device: &str, reserve_bytes: u64) -> Result<Expansion> {
+    let (table_type, partitions) = read_table(device)?;
+    let last = last_partition(&partitions)
+        .ok_or_else(|| resize_error(format!("{} has no partitions to expand", device)))?
+        .clone();
+
+    let gpt = table_type.as_deref() == Some("gpt");
+    if gpt {
+        relocate_backup_gpt(device)?;
+    }
+
+    info!("Growing partition {} on {}", last.number, device);
+    let input = if reserve_bytes == 0 {
+        ", +\n".to_string()
+    } else {
+        let geometry = Geometry::read(device, gpt)?;
+        let size = geometry
+            .usable_end(geometry.sectors(reserve_bytes))
+            .saturating_sub(last.start_sector);
+        if size <= last.size_sectors {
+            return Err(resize_error(format!(
+                "no room to grow partition {} on {}",
+                last.number, device
+            )));
+        }
+        format!(", {}\n", size)
+    };
+    run(
+        Command::new("sfdisk").args(["--no-reread", "-N", &last.number.to_string(), device]),
+        Some(input.as_bytes()),
+    )?;
+    run(Command::new("partprobe").arg(device), None)?;
+
+    let (_, partitions) = read_table(device)?;
+    let new_size_sectors = partitions
+        .iter()
+        .find(|p| p.number == last.number)
+        .map(|p| p.size_sectors)
+        .unwrap_or(last.size_sectors);
+
+    let partition = partition_path(device, last.number);
+    let blkid = run(
+        Command::new("blkid").args(["-p", "-o", "value", "-s", "TYPE", &partition]),
+        None,
+    )
+    .unwrap_or_default();
+    let fs_type = parse_blkid_type(&blkid);
+    let filesystem_grown = match fs_type {
+        Some(fs_type) => grow_filesystem(&partition, fs_type)?,
+        None => false,
+    };
+    if !filesystem_grown {
+        warn!(
+            "Filesystem '{}' on {} was not grown",
+            blkid.trim(),
+            partition
+        );
+    }
+
+    Ok(Expansion {
+        partition,
+        old_size_sectors: last.size_sectors,
+        new_size_sectors,
+        filesystem: fs_type.map(|_| blkid.trim().to_string()),
+        filesystem_grown,
+    })
+}
+
+/// Grow the filesystem to fill its partition. Returns false for types that
+/// can only be grown while mounted (xfs, btrfs) or not at all (FAT).
+fn grow_filesystem(partition: &str, fs_type: FileSystemType) -> Result<bool> {
+    match fs_type {
+        FileSystemType::Ext4 | FileSystemType::Ext3 | FileSystemType::Ext2 => {
+            shrink::check(partition, ShrinkableFs::Ext)?;
+            run(Command::new("resize2fs").arg(partition), None)?;
+        }
+        FileSystemType::Ntfs => {
+            run(
+                Command::new("ntfsresize").args(["--force", "--no-progress-bar", partition]),
+                Some(b"y\n"),
+            )?;
+        }
+        FileSystemType::F2fs => {
+            run(Command::new("resize.f2fs").arg(partition), None)?;
+        }
+        _ => return Ok(false),
+    }
+    info!("Grew {:?} filesystem on {}", fs_type, partition);
+    Ok(true)
+}
+
+/// Append a FAT partition of `size_mb` MiB after the last one, 1 MiB
+/// aligned, and return its path
+pub fn append_partition(device: &str, size_mb: u64) -> Result<String> {
+    let (table_type, partitions) = read_table(device)?;
+    let gpt = table_type.as_deref() == Some("gpt");
+    if gpt {
+        relocate_backup_gpt(device)?;
+    }
+
+    let geometry = Geometry::read(device, gpt)?;
+    let end = last_partition(&partitions)
+        .map(|p| p.start_sector + p.size_sectors)
+        .unwrap_or(0);
+    let mib = geometry.sectors(1024 * 1024);
+    let start = end.div_ceil(mib).max(1) * mib;
+    let size = geometry.sectors(size_mb * 1024 * 1024);
+    if start + size > geometry.usable_end(0) {
+        return Err(DiskError::PartitionFailed(format!(
+            "no room for a {} MiB partition at the end of {}",
+            size_mb, device
+        ))
+        .into());
+    }
+
+    // Microsoft basic data on GPT, FAT32 LBA on MBR
+    let type_id = if gpt {
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7"
+    } else {
+        "c"
+    };
+    run(
+        Command::new("sfdisk").args(["--no-reread", "--append", device]),
+        Some(format!("start={}, size={}, type={}\n", start, size, type_id).as_bytes()),
+    )
+    .map_err(|e| DiskError::PartitionFailed(e.to_string()))?;
+    run(Command::new("partprobe").arg(device), None)?;
+    let _ = Command::new("udevadm").arg("settle").status();
+
+    let (_, partitions) = read_table(device)?;
+    partitions
+        .iter()
+        .find(|p| p.start_sector == start)
+        .map(|p| partition_path(device, p.number))
+        .ok_or_else(|| {
+            DiskError::PartitionFailed(format!("appended partition missing on {}", device)).into()
+        })
+}
+
+/// Sector size and count of a device, for placing partitions at its end
+struct Geometry {
+    sector_size: u64,
+    total_sectors: u64,
+    gpt: bool,
+}
+
+impl Geometry {
+    fn read(device: &str, gpt: bool) -> Result<Self> {
+        let query = |flag: &str| -> Result<u64> {
+            run(Command::new("blockdev").args([flag, device]), None)?
+                .trim()
+                .parse()
+                .map_err(|_| resize_error(format!("blockdev {} {} failed", flag, device)))
+        };
+        let sector_size = query("--getss")?;
+        Ok(Self {
+            sector_size,
+            total_sectors: query("--getsize64")? / sector_size,
+            gpt,
+        })
+    }
+
+    fn sectors(&self, bytes: u64) -> u64 {
+        bytes.div_ceil(self.sector_size)
+    }
+
+    /// First sector past the space partitions may use, leaving `reserve`
+    /// sectors and the backup GPT (header plus 16 KiB of entries) free,
+    /// rounded down to a MiB boundary
+    fn usable_end(&self, reserve: u64) -> u64 {
+        let backup = if self.gpt {
+            1 + self.sectors(16 * 1024)
+        } else {
+            0
+        };
+        let mib = self.sectors(1024 * 1024);
+        let end = self.total_sectors.saturating_sub(backup + reserve);
+        end / mib * mib
+    }
+}
+
+fn relocate_backup_gpt(device: &str) -> Result<()> {
+    run(
+        Command::new("sfdisk").args(["--relocate", "gpt-bak-std", device]),
+        None,
+    )?;
+    Ok(())
+}
+
+fn read_table(device: &str) -> Result<(Option<String>, Vec<PartitionFingerprint>)> {
+    let json = run(Command::new("sfdisk").args(["--json", device]), None)?;
+    let (table_type, partitions, _) = fingerprint::parse_sfdisk(&json)?;
+    Ok((table_type, partitions))
+}
+
+/// The partition that ends last; only it can grow into the free space
+fn last_partition(partitions: &[PartitionFingerprint]) -> Option<&PartitionFingerprint> {
+    partitions
+        .iter()
+        .max_by_key(|p| p.start_sector + p.size_sectors)
+}
+
+fn run(cmd: &mut Command, input: Option<&[u8]>) -> Result<String> {
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| resize_error(format!("Failed to run {}: {}", program, e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(input.unwrap_or_default());
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| resize_error(format!("{} failed: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(resize_error(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn resize_error(message: String) -> crate::error::Error {
+    DiskError::ResizeFailed(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_partition() {
+        let (_, partitions, _) = fingerprint::parse_sfdisk(
+            r#"{"partitiontable": {"label": "dos", "id": "0x8a2f1c3d", "partitions": [
+                {"node": "/dev/sdb2", "start": 532480, "size": 3907584, "type": "83"},
+                {"node": "/dev/sdb1", "start": 8192, "size": 524288, "type": "c"}
+            ]}}"#,
+        )
+        .unwrap();
+
+        let last = last_partition(&partitions).unwrap();
+        assert_eq!(last.number, 2);
+        assert_eq!(last.start_sector + last.size_sectors, 4440064);
+        assert!(last_partition(&[]).is_none());
+    }
+
+    #[test]
+    fn test_usable_end() {
+        // 8 GiB stick with 512-byte sectors
+        let geometry = Geometry {
+            sector_size: 512,
+            total_sectors: 16_777_216,
+            gpt: true,
+        };
+        // The backup GPT takes the last 33 sectors, so the last full MiB goes
+        assert_eq!(geometry.usable_end(0), 16_775_168);
+        assert_eq!(
+            geometry.usable_end(geometry.sectors(16 * 1024 * 1024)),
+            16_742_400
+        );
+
+        let geometry = Geometry {
+            sector_size: 4096,
+            total_sectors: 2_097_152,
+            gpt: false,
+        };
+        assert_eq!(geometry.sectors(1024 * 1024), 256);
+        assert_eq!(geometry.usable_end(0), 2_097_152);
+    }
+}
@@ -0,0 +1,405 @@
+use super::checksum::{self, Checksum, HashAlgorithm};
+use super::format::FileSystemType;
+use super::inventory;
+use super::relabel::parse_blkid_type;
+use super::windows_usb::partition_path;
+use crate::error::{DiskError, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+use tracing::debug;
+
+/// Planned partitions may come out up to this many sectors smaller, since
+/// parted aligns the end and GPT reserves its backup table
+const SIZE_TOLERANCE_SECTORS: u64 = 2048;
+
+/// Current state of one partition on the target
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionFingerprint {
+    pub number: u32,
+    pub start_sector: u64,
+    pub size_sectors: u64,
+    /// GPT type GUID or MBR type byte
+    pub type_id: String,
+    pub name: Option<String>,
+    pub filesystem: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+}
+
+/// Identity of the target's partition table and filesystems
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetFingerprint {
+    pub device: String,
+    pub serial: Option<String>,
+    pub table_type: Option<String>,
+    /// SHA-256 over the table layout, including partition GUIDs
+    pub table_hash: String,
+    pub partitions: Vec<PartitionFingerprint>,
+}
+
+impl TargetFingerprint {
+    /// Same table and filesystems, regardless of the device node it is on
+    pub fn same_state(&self, other: &TargetFingerprint) -> bool {
+        self.table_hash == other.table_hash && self.partitions == other.partitions
+    }
+
+    pub fn partition(&self, number: u32) -> Option<&PartitionFingerprint> {
+        self.partitions.iter().find(|p| p.number == number)
+    }
+}
+
+impl PartitionFingerprint {
+    /// Whether the partition already carries `fs_type`, and `label` if given
+    pub fn has_filesystem(&self, fs_type: FileSystemType, label: Option<&str>) -> bool {
+        self.filesystem.as_deref().and_then(parse_blkid_type) == Some(fs_type)
+            && label.is_none_or(|l| self.label.as_deref() == Some(l))
+    }
+}
+
+/// A partition as the job would create it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedPartition {
+    pub start_sector: u64,
+    pub size_sectors: u64,
+    pub type_id: Option<String>,
+    pub name: Option<String>,
+}
+
+/// What was written to a target, stored on the node after a successful job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProvisionRecord {
+    pub fingerprint: TargetFingerprint,
+    /// Manifest hash of the written source
+    pub manifest: String,
+    /// Algorithm of `manifest`; records from before it was recorded are SHA-256
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+    pub recorded_at: SystemTime,
+}
+
+/// Provision records keyed by disk serial (device name when unknown)
+pub struct FingerprintStore {
+    dir: PathBuf,
+}
+
+impl FingerprintStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn load(&self, fingerprint: &TargetFingerprint) -> Option<ProvisionRecord> {
+        let content = fs::read_to_string(self.path(fingerprint)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, record: &ProvisionRecord) -> Result<()> {
+        fs::create_dir_all(&self.dir).map_err(|e| store_error(&self.dir, e))?;
+        let path = self.path(&record.fingerprint);
+        let json = serde_json::to_vec_pretty(record)
+            .map_err(|e| DiskError::InvalidLayout(format!("Invalid provision record: {}", e)))?;
+        fs::write(&path, json).map_err(|e| store_error(&path, e))?;
+        Ok(())
+    }
+
+    /// True when the target still holds exactly what was last written from
+    /// a source with this manifest. A manifest hashed with another algorithm
+    /// never matches.
+    pub fn is_provisioned(&self, current: &TargetFingerprint, manifest: &Checksum) -> bool {
+        self.load(current).is_some_and(|r| {
+            r.algorithm == manifest.algorithm
+                && r.manifest == manifest.value
+                && r.fingerprint.same_state(current)
+        })
+    }
+
+    fn path(&self, fingerprint: &TargetFingerprint) -> PathBuf {
+        let key = fingerprint.serial.clone().unwrap_or_else(|| {
+            fingerprint
+                .device
+                .trim_start_matches("/dev/")
+                .replace('/', "_")
+        });
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+/// Fingerprint the partition table and filesystems of `device`
+pub fn capture(device: &str) -> Result<TargetFingerprint> {
+    let disk = inventory::inspect(device)?;
+
+    let output = Command::new("sfdisk")
+        .args(["--json", &disk.path])
+        .output()
+        .map_err(|e| DiskError::PartitionFailed(format!("Failed to run sfdisk: {}", e)))?;
+
+    // sfdisk fails on disks without a partition table
+    let (table_type, mut partitions, layout) = if output.status.success() {
+        parse_sfdisk(&String::from_utf8_lossy(&output.stdout))?
+    } else {
+        debug!("{} has no partition table", disk.path);
+        (None, Vec::new(), String::new())
+    };
+
+    for partition in &mut partitions {
+        let path = partition_path(&disk.path, partition.number);
+        if let Some(entry) = disk.partitions.iter().find(|p| p.path == path) {
+            partition.filesystem = entry.filesystem.clone();
+            partition.label = entry.label.clone();
+            partition.uuid = entry.uuid.clone();
+        }
+    }
+
+    let table_hash = checksum::hash_bytes(HashAlgorithm::Sha256, layout.as_bytes()).value;
+    Ok(TargetFingerprint {
+        device: disk.path,
+        serial: disk.serial,
+        table_type,
+        table_hash,
+        partitions,
+    })
+}
+
+/// Manifest hash of a source: relative paths and sizes of every file for a
+/// directory (mounted ISO), or name, size and modification time for an image
+pub fn manifest(source: &Path, algorithm: HashAlgorithm) -> Result<Checksum> {
+    let mut entries = Vec::new();
+    let meta = fs::metadata(source).map_err(|e| store_error(source, e))?;
+    if meta.is_dir() {
+        collect_manifest(source, source, &mut entries)?;
+        entries.sort();
+    } else {
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        entries.push(format!(
+            "{} {} {}",
+            source.file_name().unwrap_or_default().to_string_lossy(),
+            meta.len(),
+            modified
+        ));
+    }
+    Ok(checksum::hash_bytes(
+        algorithm,
+        entries.join("\n").as_bytes(),
+    ))
+}
+
+fn collect_manifest(root: &Path, dir: &Path, entries: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)
+        .map_err(|e| store_error(dir, e))?
+        .flatten()
+    {
+        let path = entry.path();
+        let meta = entry.metadata().map_err(|e| store_error(&path, e))?;
+        if meta.is_dir() {
+            collect_manifest(root, &path, entries)?;
+        } else {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            entries.push(format!("{} {}", relative.display(), meta.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Parse `sfdisk --json` into the table type, partition geometry and a
+/// canonical layout string for hashing. Device node names are left out of
+/// the layout so the hash survives the stick moving from sdb to sdc.
+pub fn parse_sfdisk(json: &str) -> Result<(Option<String>, Vec<PartitionFingerprint>, String)> {
+    let root: Value = serde_json::from_str(json)
+        .map_err(|e| DiskError::InvalidLayout(format!("Invalid sfdisk output: {}", e)))?;
+    let table = &root["partitiontable"];
+
+    let mut layout = format!(
+        "{} {}\n",
+        table["label"].as_str().unwrap_or_default(),
+        table["id"].as_str().unwrap_or_default()
+    );
+    for p in table["partitions"].as_array().into_iter().flatten() {
+        layout.push_str(&format!(
+            "{} {} {} {} {}\n",
+            p["start"],
+            p["size"],
+            p["type"].as_str().unwrap_or_default().to_uppercase(),
+            p["uuid"].as_str().unwrap_or_default(),
+            p["name"].as_str().unwrap_or_default()
+        ));
+    }
+
+    let partitions = table["partitions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| {
+            let node = p["node"].as_str()?;
+            Some(PartitionFingerprint {
+                number: trailing_number(node)?,
+                start_sector: p["start"].as_u64()?,
+                size_sectors: p["size"].as_u64()?,
+                type_id: p["type"].as_str().unwrap_or_default().to_uppercase(),
+                name: p["name"].as_str().map(str::to_string),
+                filesystem: None,
+                label: None,
+                uuid: None,
+            })
+        })
+        .collect();
+
+    Ok((
+        table["label"].as_str().map(str::to_string),
+        partitions,
+        layout,
+    ))
+}
+
+/// Whether the partition table already has the planned geometry, types and names
+pub fn layout_matches(current: &TargetFingerprint, planned: &[PlannedPartition]) -> bool {
+    current.partitions.len() == planned.len()
+        && current.partitions.iter().zip(planned).all(|(c, p)| {
+            c.start_sector == p.start_sector
+                && c.size_sectors <= p.size_sectors
+                && c.size_sectors + SIZE_TOLERANCE_SECTORS >= p.size_sectors
+                && p.type_id
+                    .as_ref()
+                    .is_none_or(|t| t.eq_ignore_ascii_case(&c.type_id))
+                && p.name.as_ref().is_none_or(|n| c.name.as_ref() == Some(n))
+        })
+}
+
+fn trailing_number(node: &str) -> Option<u32> {
+    let digits = node.len() - node.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    node[node.len() - digits..].parse().ok()
+}
+
+fn store_error(path: &Path, e: std::io::Error) -> crate::error::Error {
+    DiskError::InvalidLayout(format!("{}: {}", path.display(), e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SFDISK: &str = r#"{
+        "partitiontable": {
+            "label": "gpt",
+            "id": "6B1D7F2E-3C4A-4E1B-9F0D-2A5C8E7B1D30",
+            "device": "/dev/nvme0n1",
+            "unit": "sectors",
+            "partitions": [
+                {"node": "/dev/nvme0n1p1", "start": 2048, "size": 1048576,
+                 "type": "c12a7328-f81f-11d2-ba4b-00a0c93ec93b", "name": "EFI"},
+                {"node": "/dev/nvme0n1p2", "start": 1050624, "size": 29360128,
+                 "type": "0FC63DAF-8483-4772-8E79-3D69D8477DE4", "name": "data"}
+            ]
+        }
+    }"#;
+
+    fn fingerprint() -> TargetFingerprint {
+        let (table_type, mut partitions, _) = parse_sfdisk(SFDISK).unwrap();
+        partitions[0].filesystem = Some("vfat".to_string());
+        partitions[1].filesystem = Some("ext4".to_string());
+        partitions[1].label = Some("DATA".to_string());
+        TargetFingerprint {
+            device: "/dev/nvme0n1".to_string(),
+            serial: Some("S4EVNX0N".to_string()),
+            table_type,
+            table_hash: "abc".to_string(),
+            partitions,
+        }
+    }
+
+    fn planned() -> Vec<PlannedPartition> {
+        vec![
+            PlannedPartition {
+                start_sector: 2048,
+                size_sectors: 1048576,
+                type_id: Some("C12A7328-F81F-11D2-BA4B-00A0C93EC93B".to_string()),
+                name: Some("EFI".to_string()),
+            },
+            PlannedPartition {
+                start_sector: 1050624,
+                // "100%" of the disk, before GPT backup and alignment
+                size_sectors: 29360128 + 1024,
+                type_id: None,
+                name: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_parse_sfdisk() {
+        let fp = fingerprint();
+        assert_eq!(fp.table_type.as_deref(), Some("gpt"));
+        assert_eq!(fp.partitions.len(), 2);
+        assert_eq!(fp.partitions[1].number, 2);
+        assert_eq!(
+            fp.partitions[0].type_id,
+            "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
+        );
+        let (_, _, layout) = parse_sfdisk(SFDISK).unwrap();
+        assert!(layout.starts_with("gpt 6B1D7F2E"));
+        assert!(!layout.contains("nvme0n1"));
+    }
+
+    #[test]
+    fn test_layout_and_filesystem_match() {
+        let fp = fingerprint();
+        let mut planned = planned();
+        assert!(layout_matches(&fp, &planned));
+
+        let data = fp.partition(2).unwrap();
+        assert!(data.has_filesystem(FileSystemType::Ext4, Some("DATA")));
+        assert!(data.has_filesystem(FileSystemType::Ext4, None));
+        assert!(!data.has_filesystem(FileSystemType::Ext4, Some("OTHER")));
+        assert!(!data.has_filesystem(FileSystemType::Xfs, None));
+
+        planned[1].size_sectors = 8192;
+        assert!(!layout_matches(&fp, &planned));
+        planned.pop();
+        assert!(!layout_matches(&fp, &planned));
+    }
+
+    #[test]
+    fn test_store_and_manifest() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let source = dir.path().join("iso");
+        fs::create_dir_all(source.join("sources")).unwrap();
+        fs::write(source.join("sources/boot.wim"), b"wim").unwrap();
+        let manifest = manifest(&source, HashAlgorithm::Sha256).unwrap();
+
+        let store = FingerprintStore::new(dir.path().join("state"));
+        let fp = fingerprint();
+        assert!(!store.is_provisioned(&fp, &manifest));
+
+        store
+            .save(&ProvisionRecord {
+                fingerprint: fp.clone(),
+                manifest: manifest.value.clone(),
+                algorithm: manifest.algorithm,
+                recorded_at: SystemTime::now(),
+            })
+            .unwrap();
+
+        let moved = TargetFingerprint {
+            device: "/dev/nvme1n1".to_string(),
+            ..fp.clone()
+        };
+        assert!(store.is_provisioned(&moved, &manifest));
+
+        let rehashed = Checksum {
+            algorithm: HashAlgorithm::Blake3,
+            ..manifest.clone()
+        };
+        assert!(!store.is_provisioned(&fp, &rehashed));
+
+        fs::write(source.join("sources/boot.wim"), b"newer wim").unwrap();
+        let changed = super::manifest(&source, HashAlgorithm::Sha256).unwrap();
+        assert!(!store.is_provisioned(&fp, &changed));
+    }
+}
@@ -1,8 +1,16 @@
+use super::{DiskOperation, DiskProgress};
+use crate::chaos::{self, FaultPoint};
+use crate::dryrun::DryRun;
 use crate::error::{DiskError, Result};
 use std::collections::HashMap;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use tokio::io::AsyncReadExt;
+use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 
+/// Number of progress steps reported for a single format operation
+const FORMAT_STEPS: u32 = 4;
+
 /// Supported file system types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileSystemType {
@@ -103,24 +111,47 @@ impl FormatParams {
 }
 
 /// Disk formatter implementation
-pub struct DiskFormatter;
+pub struct DiskFormatter {
+    progress_tx: Option<broadcast::Sender<DiskProgress>>,
+    dry_run: Option<DryRun>,
+}
 
 impl DiskFormatter {
     /// Create a new disk formatter
     pub fn new() -> Self {
-        Self
+        Self {
+            progress_tx: None,
+            dry_run: None,
+        }
+    }
+
+    /// Publish progress events on the given channel
+    pub fn with_progress(mut self, tx: broadcast::Sender<DiskProgress>) -> Self {
+        self.progress_tx = Some(tx);
+        self
+    }
+
+    /// Record mkfs commands instead of running them
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.dry_run = Some(dry_run);
+        self
     }
 
     /// Format a partition with the specified parameters
-    pub fn format(&self, params: &FormatParams) -> Result<()> {
+    pub async fn format(&self, params: &FormatParams) -> Result<()> {
         info!("Formatting {} as {:?}", params.device, params.fs_type);
+        self.report(params, 1, 0, "Validating device");
 
-        // Validate device exists
-        self.validate_device(&params.device)?;
+        // A rehearsed partitioning run leaves the partitions uncreated
+        let rehearsed = self.dry_run.is_some() && !std::path::Path::new(&params.device).exists();
+        if !rehearsed {
+            // Validate device exists
+            self.validate_device(&params.device)?;
 
-        // Check if device is mounted
-        if self.is_mounted(&params.device)? {
-            return Err(DiskError::DeviceMounted(params.device.clone()));
+            // Check if device is mounted
+            if self.is_mounted(&params.device)? {
+                return Err(DiskError::DeviceMounted(params.device.clone()));
+            }
         }
 
         // Build mkfs command
@@ -166,38 +197,39 @@ impl DiskFormatter {
         // Add device path
         cmd.arg(&params.device);
 
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.command(
+                format!("Format {} as {:?}", params.device, params.fs_type),
+                &cmd,
+            );
+            return Ok(());
+        }
+
         debug!("Executing format command: {:?}", cmd);
 
-        // Execute format command
-        let output = cmd.output().map_err(|e| {
-            error!("Failed to execute mkfs command: {}", e);
-            DiskError::CommandFailed(format!("mkfs execution failed: {}", e))
-        })?;
+        chaos::inject(FaultPoint::CommandExec, params.fs_type.mkfs_command())?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            error!("Format failed: {}", stderr);
-            return Err(DiskError::FormatFailed(
-                params.device.clone(),
-                stderr.to_string(),
-            ));
-        }
+        // Execute format command, streaming its output for progress
+        self.report(params, 2, 0, "Creating file system");
+        self.run_mkfs(cmd, params).await?;
 
         info!("Successfully formatted {}", params.device);
 
         // Verify format
+        self.report(params, 3, 100, "Verifying file system");
         self.verify_format(&params.device, params.fs_type)?;
 
+        self.report(params, FORMAT_STEPS, 100, "Format complete");
         Ok(())
     }
 
     /// Format multiple partitions
-    pub fn format_batch(&self, partitions: &[FormatParams]) -> Result<Vec<Result<()>>> {
+    pub async fn format_batch(&self, partitions: &[FormatParams]) -> Result<Vec<Result<()>>> {
         let mut results = Vec::new();
 
         for params in partitions {
             info!("Batch formatting: {}", params.device);
-            let result = self.format(params);
+            let result = self.format(params).await;
 
             if let Err(ref e) = result {
                 warn!("Failed to format {}: {}", params.device, e);
@@ -209,6 +241,84 @@ impl DiskFormatter {
         Ok(results)
     }
 
+    /// Spawn mkfs and translate its progress output into events
+    async fn run_mkfs(&self, cmd: Command, params: &FormatParams) -> Result<()> {
+        let mut cmd = tokio::process::Command::from(cmd);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        let mut child = cmd.spawn().map_err(|e| {
+            error!("Failed to execute mkfs command: {}", e);
+            DiskError::FormatFailed(format!("mkfs execution failed: {}", e))
+        })?;
+
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+
+        let stderr_task = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            if let Some(mut stderr) = stderr {
+                let _ = stderr.read_to_end(&mut buf).await;
+            }
+            String::from_utf8_lossy(&buf).to_string()
+        });
+
+        if let Some(mut stdout) = stdout {
+            // mkfs tools redraw progress with \r or backspaces, so split on
+            // any of them rather than reading whole lines
+            let mut chunk = [0u8; 1024];
+            let mut pending = String::new();
+            let mut last_percentage = 0u8;
+
+            while let Ok(n) = stdout.read(&mut chunk).await {
+                if n == 0 {
+                    break;
+                }
+                pending.push_str(&String::from_utf8_lossy(&chunk[..n]));
+
+                while let Some(pos) = pending.find(['\n', '\r', '\u{8}']) {
+                    let segment: String = pending.drain(..=pos).collect();
+                    if let Some(percentage) = parse_progress_line(&segment) {
+                        if percentage != last_percentage {
+                            last_percentage = percentage;
+                            self.report(params, 2, percentage, "Creating file system");
+                        }
+                    }
+                }
+            }
+        }
+
+        let status = child
+            .wait()
+            .await
+            .map_err(|e| DiskError::FormatFailed(format!("mkfs wait failed: {}", e)))?;
+        let stderr = stderr_task.await.unwrap_or_default();
+
+        if !status.success() {
+            error!("Format failed: {}", stderr);
+            return Err(DiskError::FormatFailed(format!(
+                "{}: {}",
+                params.device,
+                stderr.trim()
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn report(&self, params: &FormatParams, step: u32, percentage: u8, message: &str) {
+        if let Some(tx) = &self.progress_tx {
+            let _ = tx.send(DiskProgress {
+                device: params.device.clone(),
+                operation: DiskOperation::Format,
+                step,
+                total_steps: FORMAT_STEPS,
+                percentage,
+                message: message.to_string(),
+            });
+        }
+    }
+
     /// Validate device exists and is a block device
     fn validate_device(&self, device: &str) -> Result<()> {
         use std::path::Path;
@@ -396,6 +506,26 @@ impl DiskFormatter {
     }
 }
 
+/// Extract a completion percentage from a chunk of mkfs output.
+///
+/// Understands `mkfs.ntfs` ("42.50 percent completed") and mke2fs
+/// ("Writing inode tables: 12/64") style progress.
+fn parse_progress_line(line: &str) -> Option<u8> {
+    if let Some(idx) = line.find("percent completed") {
+        let value: f64 = line[..idx].split_whitespace().last()?.parse().ok()?;
+        return Some(value.clamp(0.0, 100.0) as u8);
+    }
+
+    let (_, counter) = line.rsplit_once(':')?;
+    let (done, total) = counter.trim().split_once('/')?;
+    let done: u64 = done.trim().parse().ok()?;
+    let total: u64 = total.trim().parse().ok()?;
+    if total == 0 {
+        return None;
+    }
+    Some(((done * 100) / total).min(100) as u8)
+}
+
 impl Default for DiskFormatter {
     fn default() -> Self {
         Self::new()
@@ -447,6 +577,30 @@ mod tests {
         assert!(params.force);
     }
 
+    #[test]
+    fn test_parse_progress_line() {
+        assert_eq!(parse_progress_line("42.37 percent completed"), Some(42));
+        assert_eq!(parse_progress_line("100.00 percent completed"), Some(100));
+        assert_eq!(parse_progress_line("Writing inode tables: 16/64"), Some(25));
+        assert_eq!(parse_progress_line("Allocating group tables: 0/0"), None);
+        assert_eq!(parse_progress_line("Creating journal (16384 blocks): done"), None);
+    }
+
+    #[tokio::test]
+    async fn test_progress_reporting() {
+        let (tx, mut rx) = broadcast::channel(4);
+        let formatter = DiskFormatter::new().with_progress(tx);
+        let params = FormatParams::new("/dev/sda1".to_string(), FileSystemType::Ntfs);
+
+        formatter.report(&params, 2, 50, "Creating file system");
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.device, "/dev/sda1");
+        assert_eq!(event.operation, DiskOperation::Format);
+        assert_eq!(event.percentage, 50);
+        assert_eq!(event.total_steps, FORMAT_STEPS);
+    }
+
     #[test]
     fn test_uuid_validation() {
         let formatter = DiskFormatter::new();
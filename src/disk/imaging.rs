@@ -0,0 +1,988 @@
+use super::checksum::{self, Checksum, HashAlgorithm};
+use super::cloud::{self, CloudSeed, Decompressor, ImageKind};
+use super::expand::{self, Expansion};
+use super::inventory;
+use super::{DiskOperation, DiskProgress};
+use crate::chaos::{self, FaultPoint, FaultyWriter};
+use crate::dryrun::DryRun;
+use crate::environment::EnvironmentSnapshot;
+use crate::error::{DiskError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+/// Read size for raw captures
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+const TOTAL_STEPS: u32 = 4;
+
+/// On-disk image format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// Plain dd-style image
+    Raw,
+    /// EnCase Expert Witness format via `ewfacquire`
+    E01,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Raw => "img",
+            Self::E01 => "E01",
+        }
+    }
+}
+
+/// Case metadata recorded in forensic images and custody reports
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaseInfo {
+    pub case_number: String,
+    pub evidence_number: String,
+    pub examiner: String,
+    pub description: String,
+    pub notes: String,
+}
+
+/// Parameters for capturing a device to an image file
+#[derive(Debug, Clone)]
+pub struct CaptureParams {
+    /// Source whole-disk device (e.g., /dev/sdb)
+    pub device: String,
+    /// Image path; E01 segments are named after it
+    pub output: PathBuf,
+    pub format: ImageFormat,
+    /// Forensic mode: enforce read-only access and write a custody report
+    pub forensic: bool,
+    /// Refuse to capture unless the device already reports read-only,
+    /// as it does behind a hardware write blocker
+    pub require_write_blocker: bool,
+    /// Re-hash the finished image and compare with the acquisition hashes
+    pub verify: bool,
+    pub case: CaseInfo,
+}
+
+impl CaptureParams {
+    /// Create parameters for a verified raw capture
+    pub fn new(device: String, output: PathBuf) -> Self {
+        Self {
+            device,
+            output,
+            format: ImageFormat::Raw,
+            forensic: false,
+            require_write_blocker: false,
+            verify: true,
+            case: CaseInfo::default(),
+        }
+    }
+
+    /// Set image format
+    pub fn with_format(mut self, format: ImageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Enable forensic mode with the given case metadata
+    pub fn forensic(mut self, case: CaseInfo) -> Self {
+        self.forensic = true;
+        self.case = case;
+        self
+    }
+
+    /// Require a write blocker on the source
+    pub fn with_write_blocker(mut self, required: bool) -> Self {
+        self.require_write_blocker = required;
+        self
+    }
+
+    /// Enable or disable verification of the finished image
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+}
+
+/// Parameters for writing an image onto a device
+#[derive(Debug, Clone)]
+pub struct WriteParams {
+    /// Raw image, optionally xz, zstd or gzip compressed, or qcow2
+    pub image: PathBuf,
+    /// Target whole-disk device (e.g., /dev/sdb)
+    pub device: String,
+    /// Read the written range back and compare it with the image
+    pub verify: bool,
+    /// Grow the last partition and its filesystem to the end of the device
+    pub expand: bool,
+    /// Digest for verification; the configured one when unset
+    pub hash: Option<HashAlgorithm>,
+    /// cloud-init NoCloud seed written to a partition after the image
+    pub seed: Option<CloudSeed>,
+}
+
+impl WriteParams {
+    /// Create parameters for a verified write without expansion
+    pub fn new(image: PathBuf, device: String) -> Self {
+        Self {
+            image,
+            device,
+            verify: true,
+            expand: false,
+            hash: None,
+            seed: None,
+        }
+    }
+
+    /// Enable or disable read-back verification
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Enable or disable growing the last partition after the write
+    pub fn with_expand(mut self, expand: bool) -> Self {
+        self.expand = expand;
+        self
+    }
+
+    /// Set the digest used to verify the write
+    pub fn with_hash(mut self, hash: HashAlgorithm) -> Self {
+        self.hash = Some(hash);
+        self
+    }
+
+    /// Add a cloud-init seed partition after writing
+    pub fn with_seed(mut self, seed: CloudSeed) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// Hashes of the acquired data
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImageHashes {
+    pub md5: String,
+    pub sha256: String,
+}
+
+/// A timestamped entry in the chain of custody
+#[derive(Debug, Clone, Serialize)]
+pub struct CustodyEvent {
+    pub timestamp: SystemTime,
+    pub action: String,
+}
+
+/// Capture result; serialized as the chain-of-custody report in forensic mode
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureReport {
+    pub id: String,
+    pub device: String,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub size_bytes: u64,
+    pub image: PathBuf,
+    pub format: ImageFormat,
+    pub hashes: ImageHashes,
+    /// `None` when verification was skipped
+    pub verified: Option<bool>,
+    pub forensic: bool,
+    pub read_only_enforced: bool,
+    pub write_blocker_detected: bool,
+    pub case: CaseInfo,
+    pub started_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+    pub environment: EnvironmentSnapshot,
+    pub events: Vec<CustodyEvent>,
+}
+
+impl CaptureReport {
+    fn log(&mut self, action: impl Into<String>) {
+        let action = action.into();
+        info!("{}: {}", self.device, action);
+        self.events.push(CustodyEvent {
+            timestamp: SystemTime::now(),
+            action,
+        });
+    }
+
+    /// Path of the custody report written next to the image
+    pub fn report_path(&self) -> PathBuf {
+        let mut name = self.image.clone().into_os_string();
+        name.push(".custody.json");
+        PathBuf::from(name)
+    }
+}
+
+/// Result of writing an image to a device
+#[derive(Debug, Clone, Serialize)]
+pub struct WriteReport {
+    pub device: String,
+    pub image: PathBuf,
+    pub kind: ImageKind,
+    /// Uncompressed bytes put on the device
+    pub bytes_written: u64,
+    /// Digest of the uncompressed image, with the algorithm used
+    pub checksum: Checksum,
+    /// `None` when verification was skipped
+    pub verified: Option<bool>,
+    /// `None` when expansion was not requested
+    pub expansion: Option<Expansion>,
+    /// Partition holding the cloud-init seed, if one was written
+    pub seed_partition: Option<String>,
+}
+
+/// Captures whole devices to raw or E01 images while hashing the source data
+#[derive(Clone)]
+pub struct DiskImager {
+    progress_tx: Option<broadcast::Sender<DiskProgress>>,
+    dry_run: Option<DryRun>,
+}
+
+impl DiskImager {
+    pub fn new() -> Self {
+        Self {
+            progress_tx: None,
+            dry_run: None,
+        }
+    }
+
+    /// Publish progress events on the given channel
+    pub fn with_progress(mut self, tx: broadcast::Sender<DiskProgress>) -> Self {
+        self.progress_tx = Some(tx);
+        self
+    }
+
+    /// Record writes instead of performing them. Captures only read the
+    /// source and are not affected.
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Capture `params.device`. Blocking; run from a blocking task.
+    pub fn capture(&self, params: &CaptureParams) -> Result<CaptureReport> {
+        let disk = inventory::inspect(&params.device)?;
+        if disk.mounted {
+            // Even read-only mounts may replay a journal onto the device
+            return Err(imaging_error(format!(
+                "{} has mounted filesystems",
+                params.device
+            )));
+        }
+
+        let mut report = CaptureReport {
+            id: uuid::Uuid::new_v4().to_string(),
+            device: params.device.clone(),
+            model: disk.model.clone(),
+            serial: disk.serial.clone(),
+            size_bytes: disk.size_bytes,
+            image: image_path(&params.output, params.format),
+            format: params.format,
+            hashes: ImageHashes::default(),
+            verified: None,
+            forensic: params.forensic,
+            read_only_enforced: false,
+            write_blocker_detected: is_read_only(&disk.name),
+            case: params.case.clone(),
+            started_at: SystemTime::now(),
+            finished_at: None,
+            environment: EnvironmentSnapshot::capture(),
+            events: Vec::new(),
+        };
+        report.log(format!(
+            "Capture of {} ({} bytes, serial {}) to {} started",
+            params.device,
+            disk.size_bytes,
+            disk.serial.as_deref().unwrap_or("unknown"),
+            report.image.display()
+        ));
+
+        self.report(&params.device, 1, 0, "Protecting source device");
+        if params.require_write_blocker && !report.write_blocker_detected {
+            return Err(imaging_error(format!(
+                "{} does not report read-only; connect it through a write blocker",
+                params.device
+            )));
+        }
+        if report.write_blocker_detected {
+            report.log("Source reports read-only (write blocker detected)");
+        }
+        if params.forensic {
+            let mut devices = vec![disk.path.clone()];
+            devices.extend(disk.partitions.iter().map(|p| p.path.clone()));
+            for device in &devices {
+                set_read_only(device)?;
+            }
+            report.read_only_enforced = true;
+            report.log(format!(
+                "Kernel read-only flag set on {}",
+                devices.join(", ")
+            ));
+        }
+
+        let result = self.acquire(params, &mut report);
+        if let Err(e) = result {
+            report.log(format!("Capture failed: {}", e));
+            self.write_report(params, &mut report);
+            return Err(e);
+        }
+
+        self.report(&params.device, 4, 100, "Capture complete");
+        self.write_report(params, &mut report);
+        Ok(report)
+    }
+
+    fn acquire(&self, params: &CaptureParams, report: &mut CaptureReport) -> Result<()> {
+        if let Some(parent) = report.image.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| imaging_error(format!("{}: {}", parent.display(), e)))?;
+        }
+
+        report.hashes = match params.format {
+            ImageFormat::Raw => self.acquire_raw(params, &report.image, report.size_bytes)?,
+            ImageFormat::E01 => self.acquire_e01(params, &report.image)?,
+        };
+        report.log(format!(
+            "Acquisition finished: MD5 {}, SHA-256 {}",
+            report.hashes.md5, report.hashes.sha256
+        ));
+
+        if params.verify {
+            self.report(&params.device, 3, 0, "Verifying image");
+            let image_hashes = match params.format {
+                ImageFormat::Raw => hash_file(&report.image)?,
+                ImageFormat::E01 => verify_e01(&report.image)?,
+            };
+            let verified = image_hashes == report.hashes;
+            report.verified = Some(verified);
+            if !verified {
+                error!(
+                    "Image {} does not match the source: MD5 {}, SHA-256 {}",
+                    report.image.display(),
+                    image_hashes.md5,
+                    image_hashes.sha256
+                );
+                return Err(imaging_error(format!(
+                    "{} failed verification",
+                    report.image.display()
+                )));
+            }
+            report.log("Image verified against acquisition hashes");
+        }
+        Ok(())
+    }
+
+    fn acquire_raw(&self, params: &CaptureParams, image: &Path, size: u64) -> Result<ImageHashes> {
+        // File::open is O_RDONLY; the source is never opened for writing
+        let mut source = File::open(&params.device)
+            .map_err(|e| imaging_error(format!("{}: {}", params.device, e)))?;
+        let output = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(image)
+            .map_err(|e| imaging_error(format!("{}: {}", image.display(), e)))?;
+        let mut output = FaultyWriter::new(output, "imaging");
+        let mut hasher = StreamHasher::new()?;
+
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        let mut copied = 0u64;
+        let mut last_percentage = 0u8;
+        loop {
+            let n = source.read(&mut buf).map_err(|e| {
+                imaging_error(format!(
+                    "Read error on {} at byte {}: {}",
+                    params.device, copied, e
+                ))
+            })?;
+            if n == 0 {
+                break;
+            }
+            output
+                .write_all(&buf[..n])
+                .map_err(|e| imaging_error(format!("{}: {}", image.display(), e)))?;
+            hasher.update(&buf[..n])?;
+            copied += n as u64;
+
+            let percentage = ((copied * 100) / size.max(1)).min(100) as u8;
+            if percentage != last_percentage {
+                last_percentage = percentage;
+                self.report(&params.device, 2, percentage, "Acquiring image");
+            }
+        }
+
+        output
+            .flush()
+            .and_then(|_| output.into_inner().sync_all())
+            .map_err(|e| imaging_error(format!("{}: {}", image.display(), e)))?;
+
+        if copied != size {
+            warn!(
+                "Read {} bytes from {}, expected {}",
+                copied, params.device, size
+            );
+        }
+        hasher.finish()
+    }
+
+    fn acquire_e01(&self, params: &CaptureParams, image: &Path) -> Result<ImageHashes> {
+        chaos::inject(FaultPoint::CommandExec, "ewfacquire")?;
+
+        let mut child = Command::new("ewfacquire")
+            .args(ewfacquire_args(params, image))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| imaging_error(format!("Failed to run ewfacquire: {}", e)))?;
+
+        let mut hashes = ImageHashes::default();
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines().map_while(|l| l.ok()) {
+                if let Some(percentage) = parse_ewf_status(&line) {
+                    self.report(&params.device, 2, percentage, "Acquiring image");
+                }
+                parse_ewf_hash(&line, &mut hashes);
+            }
+        }
+
+        let output = child
+            .wait_with_output()
+            .map_err(|e| imaging_error(format!("ewfacquire failed: {}", e)))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(imaging_error(format!(
+                "ewfacquire exited with {}: {}",
+                output.status,
+                stderr.trim()
+            )));
+        }
+        if hashes.md5.is_empty() || hashes.sha256.is_empty() {
+            return Err(imaging_error(
+                "ewfacquire did not report hashes".to_string(),
+            ));
+        }
+        Ok(hashes)
+    }
+
+    /// Write an image to `params.device`, optionally verifying it, growing
+    /// the last partition into the rest of the device and adding a
+    /// cloud-init seed partition. Compressed images are decompressed on the
+    /// fly. Blocking; run from a blocking task.
+    pub fn write(&self, params: &WriteParams) -> Result<WriteReport> {
+        let disk = inventory::inspect(&params.device)?;
+        if disk.mounted {
+            return Err(imaging_error(format!(
+                "{} has mounted filesystems",
+                params.device
+            )));
+        }
+        let kind = ImageKind::detect(&params.image)?;
+        let file_size = fs::metadata(&params.image)
+            .map_err(|e| imaging_error(format!("{}: {}", params.image.display(), e)))?
+            .len();
+        // The decompressed size of compressed images is unknown until written
+        let size = match kind {
+            ImageKind::Raw => Some(file_size),
+            ImageKind::Qcow2 => Some(cloud::qcow2_size(&params.image)?),
+            ImageKind::Compressed(_) => None,
+        };
+        if let Some(size) = size.filter(|&size| size > disk.size_bytes) {
+            return Err(DiskError::InsufficientSpace(size, disk.size_bytes).into());
+        }
+
+        let algorithm = params.hash.unwrap_or_default();
+        if let Some(dry_run) = &self.dry_run {
+            return Ok(rehearse_write(
+                dry_run,
+                params,
+                kind,
+                size,
+                disk.size_bytes,
+                algorithm,
+            ));
+        }
+
+        info!(
+            "Writing {} ({:?}, {} bytes) to {}",
+            params.image.display(),
+            kind,
+            file_size,
+            params.device
+        );
+        self.report(&params.device, 1, 0, "Writing image");
+        let (written, checksum) = match kind {
+            ImageKind::Raw => {
+                let source = File::open(&params.image)
+                    .map_err(|e| imaging_error(format!("{}: {}", params.image.display(), e)))?;
+                self.write_stream(params, source, file_size, algorithm, |written| written)?
+            }
+            ImageKind::Compressed(compression) => {
+                let mut source = Decompressor::spawn(&params.image, compression)?;
+                let consumed = source.consumed();
+                // Progress follows the compressed bytes read
+                let result = self.write_stream(params, &mut source, file_size, algorithm, |_| {
+                    consumed.load(Ordering::Relaxed)
+                });
+                let finished = source.finish();
+                let written = result?;
+                finished?;
+                written
+            }
+            ImageKind::Qcow2 => {
+                let size = size.unwrap_or_default();
+                cloud::write_qcow2(&params.image, &params.device, |percentage| {
+                    self.report(&params.device, 1, percentage, "Writing image")
+                })?;
+                let device = File::open(&params.device)
+                    .map_err(|e| imaging_error(format!("{}: {}", params.device, e)))?;
+                (size, checksum::hash_reader(algorithm, device.take(size))?)
+            }
+        };
+        let mut report = WriteReport {
+            device: params.device.clone(),
+            image: params.image.clone(),
+            kind,
+            bytes_written: written,
+            checksum,
+            verified: None,
+            expansion: None,
+            seed_partition: None,
+        };
+
+        if params.verify {
+            self.report(&params.device, 2, 0, "Verifying written data");
+            let verified = if kind == ImageKind::Qcow2 {
+                cloud::compare_qcow2(&params.image, &params.device, written)?
+            } else {
+                let device = File::open(&params.device)
+                    .map_err(|e| imaging_error(format!("{}: {}", params.device, e)))?;
+                checksum::hash_reader(algorithm, device.take(written))? == report.checksum
+            };
+            report.verified = Some(verified);
+            if !verified {
+                return Err(imaging_error(format!(
+                    "{} does not match {} after writing",
+                    params.device,
+                    params.image.display()
+                )));
+            }
+        }
+
+        // Skip expansion when the image already fills the device
+        if params.expand && written < disk.size_bytes {
+            self.report(&params.device, 3, 0, "Expanding last partition");
+            let reserve = match params.seed {
+                Some(_) => cloud::SEED_SIZE_MB * 1024 * 1024,
+                None => 0,
+            };
+            report.expansion = Some(expand::expand_last_partition(&params.device, reserve)?);
+        }
+
+        if let Some(seed) = &params.seed {
+            self.report(&params.device, 3, 50, "Writing cloud-init seed");
+            report.seed_partition = Some(cloud::write_seed(&params.device, seed)?);
+        }
+
+        self.report(&params.device, 4, 100, "Write complete");
+        Ok(report)
+    }
+
+    /// Copy `source` to the device while hashing it. `position` maps the
+    /// bytes written to progress through `total`. Returns the bytes written.
+    fn write_stream(
+        &self,
+        params: &WriteParams,
+        mut source: impl Read,
+        total: u64,
+        algorithm: HashAlgorithm,
+        position: impl Fn(u64) -> u64,
+    ) -> Result<(u64, Checksum)> {
+        let target = fs::OpenOptions::new()
+            .write(true)
+            .open(&params.device)
+            .map_err(|e| imaging_error(format!("{}: {}", params.device, e)))?;
+        let mut target = FaultyWriter::new(target, "imaging");
+        let mut hasher = checksum::Hasher::new(algorithm);
+
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        let mut written = 0u64;
+        let mut last_percentage = 0u8;
+        loop {
+            let n = source
+                .read(&mut buf)
+                .map_err(|e| imaging_error(format!("{}: {}", params.image.display(), e)))?;
+            if n == 0 {
+                break;
+            }
+            target.write_all(&buf[..n]).map_err(|e| {
+                imaging_error(format!(
+                    "Write error on {} at byte {}: {}",
+                    params.device, written, e
+                ))
+            })?;
+            hasher.update(&buf[..n]);
+            written += n as u64;
+
+            let percentage = ((position(written) * 100) / total.max(1)).min(100) as u8;
+            if percentage != last_percentage {
+                last_percentage = percentage;
+                self.report(&params.device, 1, percentage, "Writing image");
+            }
+        }
+
+        target
+            .flush()
+            .and_then(|_| target.into_inner().sync_all())
+            .map_err(|e| imaging_error(format!("{}: {}", params.device, e)))?;
+        Ok((written, hasher.finish()))
+    }
+
+    fn write_report(&self, params: &CaptureParams, report: &mut CaptureReport) {
+        report.finished_at = Some(SystemTime::now());
+        if !params.forensic {
+            return;
+        }
+
+        let path = report.report_path();
+        let result = serde_json::to_vec_pretty(report)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&path, json).map_err(|e| e.to_string()));
+        match result {
+            Ok(_) => info!("Chain-of-custody report written to {}", path.display()),
+            Err(e) => error!("Failed to write {}: {}", path.display(), e),
+        }
+    }
+
+    fn report(&self, device: &str, step: u32, percentage: u8, message: &str) {
+        if let Some(tx) = &self.progress_tx {
+            let _ = tx.send(DiskProgress {
+                device: device.to_string(),
+                operation: DiskOperation::Imaging,
+                step,
+                total_steps: TOTAL_STEPS,
+                percentage,
+                message: message.to_string(),
+            });
+        }
+    }
+}
+
+impl Default for DiskImager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Feeds data to `md5sum` and `sha256sum` so both hashes are computed in
+/// the same pass as the copy
+struct StreamHasher {
+    md5: Child,
+    sha256: Child,
+}
+
+impl StreamHasher {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            md5: spawn_hasher("md5sum")?,
+            sha256: spawn_hasher("sha256sum")?,
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        for child in [&mut self.md5, &mut self.sha256] {
+            if let Some(stdin) = child.stdin.as_mut() {
+                stdin
+                    .write_all(data)
+                    .map_err(|e| imaging_error(format!("Hashing failed: {}", e)))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<ImageHashes> {
+        drop(self.md5.stdin.take());
+        drop(self.sha256.stdin.take());
+        Ok(ImageHashes {
+            md5: hasher_output(self.md5)?,
+            sha256: hasher_output(self.sha256)?,
+        })
+    }
+}
+
+fn spawn_hasher(program: &str) -> Result<Child> {
+    Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| imaging_error(format!("Failed to run {}: {}", program, e)))
+}
+
+fn hasher_output(child: Child) -> Result<String> {
+    let output = child
+        .wait_with_output()
+        .map_err(|e| imaging_error(format!("Hashing failed: {}", e)))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.split_whitespace().next() {
+        Some(hash) if output.status.success() => Ok(hash.to_lowercase()),
+        _ => Err(imaging_error(format!(
+            "Hashing exited with {}",
+            output.status
+        ))),
+    }
+}
+
+fn hash_file(path: &Path) -> Result<ImageHashes> {
+    hash_prefix(path, u64::MAX)
+}
+
+/// Hash the first `len` bytes of a file or device
+fn hash_prefix(path: &Path, len: u64) -> Result<ImageHashes> {
+    let mut file = File::open(path)
+        .map_err(|e| imaging_error(format!("{}: {}", path.display(), e)))?
+        .take(len);
+    let mut hasher = StreamHasher::new()?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| imaging_error(format!("{}: {}", path.display(), e)))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n])?;
+    }
+    hasher.finish()
+}
+
+fn verify_e01(image: &Path) -> Result<ImageHashes> {
+    let output = Command::new("ewfverify")
+        .args(["-q", "-d", "sha256"])
+        .arg(image)
+        .output()
+        .map_err(|e| imaging_error(format!("Failed to run ewfverify: {}", e)))?;
+
+    let mut hashes = ImageHashes::default();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        parse_ewf_hash(line, &mut hashes);
+    }
+    if !output.status.success() {
+        return Err(imaging_error(format!(
+            "ewfverify exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(hashes)
+}
+
+fn ewfacquire_args(params: &CaptureParams, image: &Path) -> Vec<String> {
+    // ewfacquire appends the segment extension itself
+    let target = image.with_extension("");
+    let case = &params.case;
+    vec![
+        "-u".to_string(),
+        "-t".to_string(),
+        target.display().to_string(),
+        "-f".to_string(),
+        "encase6".to_string(),
+        "-c".to_string(),
+        "deflate:fast".to_string(),
+        "-d".to_string(),
+        "sha256".to_string(),
+        "-C".to_string(),
+        case.case_number.clone(),
+        "-E".to_string(),
+        case.evidence_number.clone(),
+        "-e".to_string(),
+        case.examiner.clone(),
+        "-D".to_string(),
+        case.description.clone(),
+        "-N".to_string(),
+        case.notes.clone(),
+        params.device.clone(),
+    ]
+}
+
+/// `Status: at 42%.`
+fn parse_ewf_status(line: &str) -> Option<u8> {
+    let rest = line.trim().strip_prefix("Status: at ")?;
+    rest.split('%').next()?.trim().parse().ok()
+}
+
+/// `MD5 hash calculated over data:\t\t<hex>` and the SHA256 equivalent
+fn parse_ewf_hash(line: &str, hashes: &mut ImageHashes) {
+    let Some((label, value)) = line.split_once(':') else {
+        return;
+    };
+    let value = value.trim().to_lowercase();
+    match label.trim() {
+        "MD5 hash calculated over data" => hashes.md5 = value,
+        "SHA256 hash calculated over data" => hashes.sha256 = value,
+        _ => {}
+    }
+}
+
+fn image_path(output: &Path, format: ImageFormat) -> PathBuf {
+    match format {
+        ImageFormat::Raw => output.to_path_buf(),
+        ImageFormat::E01 => output.with_extension(format.extension()),
+    }
+}
+
+/// The kernel's read-only flag, set by write blockers that report it
+fn is_read_only(name: &str) -> bool {
+    fs::read_to_string(Path::new("/sys/class/block").join(name).join("ro"))
+        .map(|ro| ro.trim() == "1")
+        .unwrap_or(false)
+}
+
+fn set_read_only(device: &str) -> Result<()> {
+    let status = Command::new("blockdev")
+        .args(["--setro", device])
+        .status()
+        .map_err(|e| imaging_error(format!("Failed to run blockdev: {}", e)))?;
+
+    let output = Command::new("blockdev")
+        .args(["--getro", device])
+        .output()
+        .map_err(|e| imaging_error(format!("Failed to run blockdev: {}", e)))?;
+    if !status.success() || String::from_utf8_lossy(&output.stdout).trim() != "1" {
+        return Err(imaging_error(format!(
+            "Could not make {} read-only",
+            device
+        )));
+    }
+    Ok(())
+}
+
+/// Record the steps `DiskImager::write` would take. The report carries
+/// the image size but no digest, as nothing was read.
+fn rehearse_write(
+    dry_run: &DryRun,
+    params: &WriteParams,
+    kind: ImageKind,
+    size: Option<u64>,
+    device_size: u64,
+    algorithm: HashAlgorithm,
+) -> WriteReport {
+    let size_text = match size {
+        Some(size) => format!("{} bytes", size),
+        None => "size known after decompression".to_string(),
+    };
+    dry_run.action(format!(
+        "Write {} ({:?}, {}) to {} ({} bytes)",
+        params.image.display(),
+        kind,
+        size_text,
+        params.device,
+        device_size
+    ));
+    if params.verify {
+        dry_run.action(format!(
+            "Verify {} against its {} digest",
+            params.device, algorithm
+        ));
+    }
+    if params.expand && size.is_none_or(|size| size < device_size) {
+        dry_run.action(format!(
+            "Grow the last partition on {} and its filesystem to the end of the device",
+            params.device
+        ));
+    }
+    if params.seed.is_some() {
+        dry_run.action(format!(
+            "Write a {} MiB cloud-init seed partition to {}",
+            cloud::SEED_SIZE_MB,
+            params.device
+        ));
+    }
+
+    WriteReport {
+        device: params.device.clone(),
+        image: params.image.clone(),
+        kind,
+        bytes_written: 0,
+        checksum: Checksum {
+            algorithm,
+            value: String::new(),
+        },
+        verified: None,
+        expansion: None,
+        seed_partition: None,
+    }
+}
+
+fn imaging_error(message: String) -> crate::error::Error {
+    DiskError::ImagingFailed(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ewf_output() {
+        let mut hashes = ImageHashes::default();
+        for line in [
+            "Status: at 42%.",
+            "MD5 hash calculated over data:\t\t2A3B0C9F1E8D7C6B5A4F3E2D1C0B9A8F",
+            "SHA256 hash calculated over data:\te3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ] {
+            parse_ewf_hash(line, &mut hashes);
+        }
+
+        assert_eq!(parse_ewf_status("Status: at 42%."), Some(42));
+        assert_eq!(parse_ewf_status("Acquiry started at: Jun 01 2024"), None);
+        assert_eq!(hashes.md5, "2a3b0c9f1e8d7c6b5a4f3e2d1c0b9a8f");
+        assert_eq!(
+            hashes.sha256,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("empty.img");
+        fs::write(&path, b"").unwrap();
+
+        let hashes = hash_file(&path).unwrap();
+        assert_eq!(hashes.md5, "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            hashes.sha256,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        // A device is longer than the image written to it
+        fs::write(&path, b"imagetrailing").unwrap();
+        let prefix = dir.path().join("image.img");
+        fs::write(&prefix, b"image").unwrap();
+        assert_eq!(hash_prefix(&path, 5).unwrap(), hash_file(&prefix).unwrap());
+    }
+
+    #[test]
+    fn test_ewfacquire_args_and_paths() {
+        let params = CaptureParams::new("/dev/sdb".to_string(), PathBuf::from("/images/case7"))
+            .with_format(ImageFormat::E01)
+            .forensic(CaseInfo {
+                case_number: "2024-007".to_string(),
+                examiner: "J. Doe".to_string(),
+                ..CaseInfo::default()
+            });
+        let image = image_path(&params.output, params.format);
+        assert_eq!(image, PathBuf::from("/images/case7.E01"));
+
+        let args = ewfacquire_args(&params, &image);
+        assert_eq!(&args[..3], ["-u", "-t", "/images/case7"]);
+        assert!(args.windows(2).any(|w| w == ["-C", "2024-007"]));
+        assert_eq!(args.last().map(String::as_str), Some("/dev/sdb"));
+        assert!(params.forensic && params.verify);
+    }
+}
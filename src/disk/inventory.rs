@@ -0,0 +1,476 @@
+use super::encryption::{self, EncryptedVolume};
+use crate::error::{DiskError, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+/// Columns requested from lsblk, in bytes (`-b`) and as JSON (`-J`)
+const LSBLK_COLUMNS: &str =
+    "NAME,PATH,TYPE,MODEL,SERIAL,SIZE,TRAN,RM,ROTA,RO,FSTYPE,LABEL,UUID,MOUNTPOINT";
+
+/// What kind of block device a disk entry is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DiskKind {
+    /// SATA, SCSI, USB and virtio disks
+    Standard,
+    /// One namespace of an NVMe controller (`/dev/nvme0n2` is namespace 2 of `/dev/nvme0`)
+    NvmeNamespace { controller: String, namespace: u32 },
+    /// User data area of an eMMC or SD card
+    Mmc,
+}
+
+/// Hardware area of an eMMC device outside its user data area
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpecialAreaKind {
+    Boot0,
+    Boot1,
+    /// Replay-protected memory block; only reachable through authenticated frames
+    Rpmb,
+}
+
+/// An eMMC boot or RPMB area. These are never partitioned, formatted or
+/// wiped as part of normal operations.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpecialArea {
+    pub kind: SpecialAreaKind,
+    pub path: String,
+    /// 0 when the kernel exposes the area as a character device only
+    pub size_bytes: u64,
+    pub read_only: bool,
+}
+
+/// Health summary from `smartctl`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SmartSummary {
+    pub passed: bool,
+    pub temperature_celsius: Option<i64>,
+    pub power_on_hours: Option<u64>,
+    pub reallocated_sectors: Option<u64>,
+}
+
+/// A partition on an inventoried disk
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PartitionEntry {
+    pub path: String,
+    pub size_bytes: u64,
+    pub filesystem: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    pub mount_point: Option<String>,
+}
+
+/// Whole-disk metadata for device selection
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiskInventory {
+    pub name: String,
+    pub path: String,
+    pub kind: DiskKind,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub size_bytes: u64,
+    pub transport: Option<String>,
+    pub removable: bool,
+    pub rotational: bool,
+    pub partitions: Vec<PartitionEntry>,
+    pub mounted: bool,
+    pub smart: Option<SmartSummary>,
+    /// eMMC boot and RPMB areas belonging to this device
+    pub special_areas: Vec<SpecialArea>,
+    /// Volumes holding LUKS, BitLocker or FileVault data
+    pub encrypted: Vec<EncryptedVolume>,
+}
+
+impl DiskInventory {
+    /// Human readable label, e.g. "SanDisk Ultra (usb, 15.4 GB)"
+    pub fn display_name(&self) -> String {
+        let size = format!("{:.1} GB", self.size_bytes as f64 / 1_000_000_000.0);
+        let model = match &self.kind {
+            DiskKind::NvmeNamespace { namespace, .. } if *namespace > 1 => format!(
+                "{} ns{}",
+                self.model.as_deref().unwrap_or(&self.name),
+                namespace
+            ),
+            _ => self.model.clone().unwrap_or_else(|| self.name.clone()),
+        };
+        match &self.transport {
+            Some(transport) => format!("{} ({}, {})", model, transport, size),
+            None => format!("{} ({})", model, size),
+        }
+    }
+
+    /// A boot area of this device as a standalone entry, by path or name.
+    /// RPMB is left out since it cannot be read as a block device.
+    fn area(&self, device: &str) -> Option<DiskInventory> {
+        let area = self.special_areas.iter().find(|a| {
+            a.kind != SpecialAreaKind::Rpmb
+                && (a.path == device || a.path.trim_start_matches("/dev/") == device)
+        })?;
+        Some(DiskInventory {
+            name: area.path.trim_start_matches("/dev/").to_string(),
+            path: area.path.clone(),
+            size_bytes: area.size_bytes,
+            partitions: Vec::new(),
+            mounted: false,
+            smart: None,
+            special_areas: Vec::new(),
+            encrypted: encryption::scan(&area.path),
+            ..self.clone()
+        })
+    }
+}
+
+/// Inventory every disk on the system
+pub fn scan() -> Result<Vec<DiskInventory>> {
+    let output = Command::new("lsblk")
+        .args(["-J", "-b", "-o", LSBLK_COLUMNS])
+        .output()
+        .map_err(|e| DiskError::DiskNotFound(format!("Failed to run lsblk: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(DiskError::DiskNotFound(format!(
+            "lsblk failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+
+    let mut disks = parse_lsblk(&String::from_utf8_lossy(&output.stdout))?;
+    for disk in &mut disks {
+        disk.smart = smart_summary(&disk.path);
+        disk.encrypted = encryption::scan(&disk.path);
+        add_char_rpmb(disk);
+    }
+    Ok(disks)
+}
+
+/// Inventory a single disk by path (`/dev/sdb`) or name (`sdb`). An eMMC
+/// boot area resolves to an entry of its own so it can be captured.
+pub fn inspect(device: &str) -> Result<DiskInventory> {
+    let disks = scan()?;
+    if let Some(disk) = disks.iter().find(|d| d.path == device || d.name == device) {
+        return Ok(disk.clone());
+    }
+    disks
+        .iter()
+        .find_map(|d| d.area(device))
+        .ok_or_else(|| DiskError::DiskNotFound(device.to_string()).into())
+}
+
+/// Refuse targets that are eMMC boot or RPMB areas, or partitions on them.
+/// Called before any operation that partitions, formats or overwrites.
+pub fn check_target(device: &str) -> Result<()> {
+    let name = device.trim_start_matches("/dev/");
+    match parse_special(name) {
+        Some((_, kind)) => {
+            Err(DiskError::ProtectedArea(format!("{} is the eMMC {:?} area", device, kind)).into())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Parse `lsblk -J -b` output into disk entries, without SMART data.
+/// eMMC boot areas are attached to their device instead of listed as disks.
+pub fn parse_lsblk(json: &str) -> Result<Vec<DiskInventory>> {
+    let root: Value = serde_json::from_str(json)
+        .map_err(|e| DiskError::InvalidLayout(format!("Invalid lsblk output: {}", e)))?;
+
+    let devices = root["blockdevices"].as_array().cloned().unwrap_or_default();
+
+    let mut disks = Vec::new();
+    let mut areas = Vec::new();
+    for d in devices
+        .iter()
+        .filter(|d| d["type"].as_str() == Some("disk"))
+    {
+        let name = string(&d["name"]).unwrap_or_default();
+        let path = string(&d["path"]).unwrap_or_else(|| format!("/dev/{}", name));
+
+        if let Some((parent, kind)) = parse_special(&name) {
+            areas.push((
+                parent.to_string(),
+                SpecialArea {
+                    kind,
+                    path,
+                    size_bytes: number(&d["size"]),
+                    read_only: flag(&d["ro"]),
+                },
+            ));
+            continue;
+        }
+
+        let mut partitions = Vec::new();
+        collect_partitions(d, &mut partitions);
+
+        let mounted = string(&d["mountpoint"]).is_some()
+            || partitions.iter().any(|p| p.mount_point.is_some());
+
+        disks.push(DiskInventory {
+            kind: classify(&name),
+            name,
+            path,
+            model: string(&d["model"]),
+            serial: string(&d["serial"]),
+            size_bytes: number(&d["size"]),
+            transport: string(&d["tran"]),
+            removable: flag(&d["rm"]),
+            rotational: flag(&d["rota"]),
+            partitions,
+            mounted,
+            smart: None,
+            special_areas: Vec::new(),
+            encrypted: Vec::new(),
+        });
+    }
+
+    for (parent, area) in areas {
+        if let Some(disk) = disks.iter_mut().find(|d| d.name == parent) {
+            disk.special_areas.push(area);
+        }
+    }
+    Ok(disks)
+}
+
+/// Classify a whole-disk kernel name (`sda`, `nvme0n1`, `mmcblk0`)
+pub fn classify(name: &str) -> DiskKind {
+    if let Some((controller, namespace)) = name
+        .strip_prefix("nvme")
+        .and_then(|rest| rest.split_once('n'))
+    {
+        if let (Ok(_), Ok(namespace)) = (controller.parse::<u32>(), namespace.parse()) {
+            return DiskKind::NvmeNamespace {
+                controller: format!("/dev/nvme{}", controller),
+                namespace,
+            };
+        }
+    }
+    if name.starts_with("mmcblk") {
+        return DiskKind::Mmc;
+    }
+    DiskKind::Standard
+}
+
+/// Split an eMMC area name such as `mmcblk0boot1` or `mmcblk0boot1p1` into
+/// the owning device name and the area
+fn parse_special(name: &str) -> Option<(&str, SpecialAreaKind)> {
+    let rest = name.strip_prefix("mmcblk")?;
+    let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    let parent = &name[..6 + digits];
+    let suffix = &rest[digits..];
+    let kind = if suffix.starts_with("boot0") {
+        SpecialAreaKind::Boot0
+    } else if suffix.starts_with("boot1") {
+        SpecialAreaKind::Boot1
+    } else if suffix.starts_with("rpmb") {
+        SpecialAreaKind::Rpmb
+    } else {
+        return None;
+    };
+    Some((parent, kind))
+}
+
+/// Newer kernels expose RPMB as a character device, which lsblk does not list
+fn add_char_rpmb(disk: &mut DiskInventory) {
+    if disk.kind != DiskKind::Mmc
+        || disk
+            .special_areas
+            .iter()
+            .any(|a| a.kind == SpecialAreaKind::Rpmb)
+    {
+        return;
+    }
+    let path = format!("{}rpmb", disk.path);
+    if Path::new(&path).exists() {
+        disk.special_areas.push(SpecialArea {
+            kind: SpecialAreaKind::Rpmb,
+            path,
+            size_bytes: 0,
+            read_only: false,
+        });
+    }
+}
+
+fn collect_partitions(device: &Value, partitions: &mut Vec<PartitionEntry>) {
+    for child in device["children"].as_array().into_iter().flatten() {
+        if child["type"].as_str() == Some("part") {
+            partitions.push(PartitionEntry {
+                path: string(&child["path"]).unwrap_or_default(),
+                size_bytes: number(&child["size"]),
+                filesystem: string(&child["fstype"]),
+                label: string(&child["label"]),
+                uuid: string(&child["uuid"]),
+                mount_point: string(&child["mountpoint"]),
+            });
+        }
+        collect_partitions(child, partitions);
+    }
+}
+
+/// Query `smartctl`; `None` when unavailable or unsupported (most USB bridges)
+fn smart_summary(device: &str) -> Option<SmartSummary> {
+    let output = Command::new("smartctl")
+        .args(["-j", "-H", "-A", device])
+        .output()
+        .ok()?;
+
+    let summary = parse_smartctl(&String::from_utf8_lossy(&output.stdout));
+    if summary.is_none() {
+        debug!("No SMART data for {}", device);
+    }
+    summary
+}
+
+/// Parse `smartctl -j -H -A` output
+pub fn parse_smartctl(json: &str) -> Option<SmartSummary> {
+    let root: Value = serde_json::from_str(json).ok()?;
+    let passed = root["smart_status"]["passed"].as_bool()?;
+
+    let reallocated_sectors = root["ata_smart_attributes"]["table"]
+        .as_array()
+        .and_then(|table| table.iter().find(|attr| attr["id"].as_u64() == Some(5)))
+        .and_then(|attr| attr["raw"]["value"].as_u64());
+
+    Some(SmartSummary {
+        passed,
+        temperature_celsius: root["temperature"]["current"].as_i64(),
+        power_on_hours: root["power_on_time"]["hours"].as_u64(),
+        reallocated_sectors,
+    })
+}
+
+fn string(value: &Value) -> Option<String> {
+    value
+        .as_str()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+// Older util-linux prints numbers and flags as strings ("1", "0")
+fn number(value: &Value) -> u64 {
+    value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+        .unwrap_or(0)
+}
+
+fn flag(value: &Value) -> bool {
+    value.as_bool().unwrap_or_else(|| number(value) != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LSBLK: &str = r#"{
+       "blockdevices": [
+          {"name":"sda", "path":"/dev/sda", "type":"disk", "model":"Samsung SSD 870", "serial":"S5Y1NX0R", "size":500107862016, "tran":"sata", "rm":false, "rota":false, "fstype":null, "label":null, "uuid":null, "mountpoint":null,
+             "children": [
+                {"name":"sda1", "path":"/dev/sda1", "type":"part", "model":null, "serial":null, "size":536870912, "tran":null, "rm":false, "rota":false, "fstype":"vfat", "label":"EFI", "uuid":"1234-ABCD", "mountpoint":"/boot/efi"}
+             ]
+          },
+          {"name":"sdb", "path":"/dev/sdb", "type":"disk", "model":"Ultra           ", "serial":"4C530001", "size":"15376318464", "tran":"usb", "rm":"1", "rota":"0", "fstype":null, "label":null, "uuid":null, "mountpoint":null},
+          {"name":"loop0", "path":"/dev/loop0", "type":"loop", "size":4096, "rm":false, "rota":false}
+       ]
+    }"#;
+
+    #[test]
+    fn test_parse_lsblk() {
+        let disks = parse_lsblk(LSBLK).unwrap();
+        assert_eq!(disks.len(), 2);
+
+        assert_eq!(disks[0].path, "/dev/sda");
+        assert!(disks[0].mounted);
+        assert_eq!(disks[0].partitions.len(), 1);
+        assert_eq!(
+            disks[0].partitions[0].mount_point.as_deref(),
+            Some("/boot/efi")
+        );
+
+        assert_eq!(disks[1].model.as_deref(), Some("Ultra"));
+        assert_eq!(disks[1].size_bytes, 15_376_318_464);
+        assert!(disks[1].removable);
+        assert!(!disks[1].rotational);
+        assert!(!disks[1].mounted);
+        assert_eq!(disks[1].display_name(), "Ultra (usb, 15.4 GB)");
+    }
+
+    #[test]
+    fn test_nvme_and_emmc() {
+        let json = r#"{
+           "blockdevices": [
+              {"name":"nvme0n1", "path":"/dev/nvme0n1", "type":"disk", "model":"WD SN740", "size":256060514304, "tran":"nvme", "rm":false, "rota":false, "ro":false},
+              {"name":"nvme0n2", "path":"/dev/nvme0n2", "type":"disk", "model":"WD SN740", "size":1073741824, "tran":"nvme", "rm":false, "rota":false, "ro":false},
+              {"name":"mmcblk0", "path":"/dev/mmcblk0", "type":"disk", "size":31268536320, "rm":false, "rota":false, "ro":false,
+                 "children": [
+                    {"name":"mmcblk0p1", "path":"/dev/mmcblk0p1", "type":"part", "size":31267487744, "fstype":"ext4", "mountpoint":null}
+                 ]
+              },
+              {"name":"mmcblk0boot0", "path":"/dev/mmcblk0boot0", "type":"disk", "size":4194304, "rm":false, "rota":false, "ro":true},
+              {"name":"mmcblk0boot1", "path":"/dev/mmcblk0boot1", "type":"disk", "size":4194304, "rm":false, "rota":false, "ro":"1"}
+           ]
+        }"#;
+
+        let disks = parse_lsblk(json).unwrap();
+        assert_eq!(disks.len(), 3);
+        assert_eq!(
+            disks[1].kind,
+            DiskKind::NvmeNamespace {
+                controller: "/dev/nvme0".to_string(),
+                namespace: 2
+            }
+        );
+        assert_eq!(disks[1].display_name(), "WD SN740 ns2 (nvme, 1.1 GB)");
+        assert_eq!(disks[0].display_name(), "WD SN740 (nvme, 256.1 GB)");
+
+        let emmc = &disks[2];
+        assert_eq!(emmc.kind, DiskKind::Mmc);
+        assert_eq!(emmc.partitions.len(), 1);
+        assert_eq!(emmc.special_areas.len(), 2);
+        assert_eq!(emmc.special_areas[0].kind, SpecialAreaKind::Boot0);
+        assert!(emmc.special_areas[1].read_only);
+
+        let boot1 = emmc.area("mmcblk0boot1").unwrap();
+        assert_eq!(boot1.path, "/dev/mmcblk0boot1");
+        assert_eq!(boot1.size_bytes, 4_194_304);
+        assert!(emmc.area("/dev/mmcblk0p1").is_none());
+    }
+
+    #[test]
+    fn test_check_target() {
+        assert!(check_target("/dev/sdb").is_ok());
+        assert!(check_target("/dev/mmcblk0").is_ok());
+        assert!(check_target("/dev/mmcblk0p2").is_ok());
+        assert!(check_target("/dev/nvme0n2").is_ok());
+        assert!(check_target("/dev/mmcblk0boot0").is_err());
+        assert!(check_target("/dev/mmcblk1boot1p1").is_err());
+        assert!(check_target("mmcblk0rpmb").is_err());
+        assert_eq!(classify("nvme0c0n1"), DiskKind::Standard);
+    }
+
+    #[test]
+    fn test_parse_smartctl() {
+        let json = r#"{
+            "smart_status": {"passed": true},
+            "temperature": {"current": 34},
+            "power_on_time": {"hours": 1200},
+            "ata_smart_attributes": {"table": [
+                {"id": 5, "name": "Reallocated_Sector_Ct", "raw": {"value": 2}}
+            ]}
+        }"#;
+
+        let smart = parse_smartctl(json).unwrap();
+        assert!(smart.passed);
+        assert_eq!(smart.temperature_celsius, Some(34));
+        assert_eq!(smart.power_on_hours, Some(1200));
+        assert_eq!(smart.reallocated_sectors, Some(2));
+
+        assert!(parse_smartctl(r#"{"smartctl": {"exit_status": 1}}"#).is_none());
+    }
+}
@@ -0,0 +1,573 @@
+use super::format::{DiskFormatter, FileSystemType, FormatParams};
+use super::windows_usb::{self, partition_path, DataFilesystem};
+use super::{DiskOperation, DiskProgress};
+use crate::error::{DiskError, Result};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Size of the FAT32 partition holding both GRUB installations, in MiB
+const ESP_SIZE_MB: u64 = 128;
+
+const TOTAL_STEPS: u32 = 6;
+
+/// Live or installer system recognised inside an ISO
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "family", rename_all = "snake_case")]
+pub enum Distro {
+    /// Ubuntu and derivatives booting through casper
+    Ubuntu { kernel: String, initrd: String },
+    /// Debian live images
+    DebianLive { kernel: String, initrd: String },
+    /// Debian installer media (netinst, DVD)
+    DebianInstaller,
+    /// Fedora, RHEL and rebuilds (Anaconda)
+    Anaconda,
+    /// Arch Linux and archiso-based images
+    Arch,
+}
+
+/// An ISO copied to the stick, with the distro it boots
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MultibootEntry {
+    pub file_name: String,
+    pub volume_id: String,
+    pub distro: Distro,
+}
+
+/// Parameters for building a multiboot stick
+#[derive(Debug, Clone)]
+pub struct MultibootParams {
+    /// Target whole-disk device (e.g., /dev/sdb)
+    pub device: String,
+    /// ISO files copied to `isos/` on the data partition
+    pub isos: Vec<PathBuf>,
+    /// Directory under which the partitions are mounted while writing
+    pub work_dir: PathBuf,
+    /// Data partition file system
+    pub filesystem: DataFilesystem,
+    /// Data partition volume label, used by GRUB and the kernels to find the ISOs
+    pub label: String,
+}
+
+impl MultibootParams {
+    /// Create parameters with an NTFS data partition, which holds ISOs over 4 GiB
+    pub fn new(device: String, isos: Vec<PathBuf>) -> Self {
+        Self {
+            device,
+            isos,
+            work_dir: PathBuf::from("/mnt/usb-installer-target"),
+            filesystem: DataFilesystem::Ntfs,
+            label: "MULTIBOOT".to_string(),
+        }
+    }
+
+    /// Set data partition file system
+    pub fn with_filesystem(mut self, filesystem: DataFilesystem) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+
+    /// Set data partition label
+    pub fn with_label(mut self, label: String) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Set mount directory
+    pub fn with_work_dir(mut self, work_dir: PathBuf) -> Self {
+        self.work_dir = work_dir;
+        self
+    }
+}
+
+/// Builds sticks that boot several ISOs through GRUB loopback entries
+pub struct MultibootWriter {
+    formatter: DiskFormatter,
+    progress_tx: Option<broadcast::Sender<DiskProgress>>,
+}
+
+impl MultibootWriter {
+    pub fn new() -> Self {
+        Self {
+            formatter: DiskFormatter::new(),
+            progress_tx: None,
+        }
+    }
+
+    /// Publish progress events on the given channel
+    pub fn with_progress(mut self, tx: broadcast::Sender<DiskProgress>) -> Self {
+        self.formatter = DiskFormatter::new().with_progress(tx.clone());
+        self.progress_tx = Some(tx);
+        self
+    }
+
+    /// Partition `params.device`, install GRUB for BIOS and UEFI, copy the
+    /// ISOs and write a menu entry for each recognised one.
+    ///
+    /// Layout is GPT: a BIOS boot partition for GRUB's core image, a FAT32
+    /// ESP holding `/boot/grub` and `/EFI/BOOT`, and the data partition.
+    pub async fn create(&self, params: &MultibootParams) -> Result<Vec<MultibootEntry>> {
+        info!(
+            "Creating multiboot stick on {} with {} ISOs",
+            params.device,
+            params.isos.len()
+        );
+
+        self.report(params, 0, 0, "Detecting distributions");
+        let mut entries = Vec::new();
+        for iso in &params.isos {
+            match inspect_iso(iso)? {
+                Some(entry) => entries.push(entry),
+                None => warn!("{}: no bootable distribution recognised", iso.display()),
+            }
+        }
+        if entries.is_empty() {
+            return Err(DiskError::InvalidLayout("no bootable ISOs recognised".to_string()).into());
+        }
+        self.validate(params)?;
+
+        self.report(params, 1, 5, "Partitioning");
+        windows_usb::unmount_device(&params.device);
+        self.partition(params)?;
+
+        let esp_part = partition_path(&params.device, 2);
+        let data_part = partition_path(&params.device, 3);
+
+        self.report(params, 2, 10, "Formatting partitions");
+        let esp = FormatParams::new(esp_part.clone(), FileSystemType::Vfat)
+            .with_label("MBOOT_ESP".to_string())
+            .force();
+        self.formatter.format(&esp).await?;
+        let fs_type = match params.filesystem {
+            DataFilesystem::Ntfs => FileSystemType::Ntfs,
+            DataFilesystem::Fat32 => FileSystemType::Vfat,
+        };
+        let mut data = FormatParams::new(data_part.clone(), fs_type)
+            .with_label(params.label.clone())
+            .force();
+        if params.filesystem == DataFilesystem::Ntfs {
+            data = data.add_option("--quick".to_string());
+        }
+        self.formatter.format(&data).await?;
+
+        self.report(params, 3, 15, "Installing GRUB");
+        let esp_dir = params.work_dir.join("esp");
+        windows_usb::mount(&esp_part, &esp_dir, DataFilesystem::Fat32)?;
+        let result = install_grub(&params.device, &esp_dir).and_then(|_| {
+            let config = grub_config(&params.label, params.filesystem, &entries);
+            let path = esp_dir.join("boot/grub/grub.cfg");
+            fs::write(&path, config)
+                .map_err(|e| DiskError::WriteFailed(format!("{}: {}", path.display(), e)).into())
+        });
+        let unmounted = windows_usb::umount(&esp_dir);
+        result?;
+        unmounted?;
+
+        self.report(params, 4, 20, "Copying ISOs");
+        let data_dir = params.work_dir.join("data");
+        windows_usb::mount(&data_part, &data_dir, params.filesystem)?;
+        let result = self.copy_isos(params, &data_dir).await;
+        let unmounted = windows_usb::umount(&data_dir);
+        result?;
+        unmounted?;
+
+        self.report(params, TOTAL_STEPS, 100, "Multiboot stick created");
+        info!(
+            "Multiboot stick created on {} with {} entries",
+            params.device,
+            entries.len()
+        );
+        Ok(entries)
+    }
+
+    fn validate(&self, params: &MultibootParams) -> Result<()> {
+        if !Path::new(&params.device).exists() {
+            return Err(DiskError::DiskNotFound(params.device.clone()).into());
+        }
+
+        let mut needed = (ESP_SIZE_MB + 2) * 1024 * 1024;
+        for iso in &params.isos {
+            let size = fs::metadata(iso)?.len();
+            if params.filesystem == DataFilesystem::Fat32 && size > u32::MAX as u64 {
+                return Err(DiskError::InvalidLayout(format!(
+                    "{} is larger than 4 GiB and cannot be stored on FAT32",
+                    iso.display()
+                ))
+                .into());
+            }
+            needed += size;
+        }
+
+        let available = windows_usb::device_size(&params.device)?;
+        if needed > available {
+            return Err(DiskError::InsufficientSpace(needed, available).into());
+        }
+        Ok(())
+    }
+
+    fn partition(&self, params: &MultibootParams) -> Result<()> {
+        windows_usb::run(
+            Command::new("wipefs").args(["-a", &params.device]),
+            "wipefs",
+        )
+        .map_err(|e| DiskError::PartitionFailed(e.to_string()))?;
+
+        let esp_end = format!("{}MiB", ESP_SIZE_MB + 2);
+        let data_fs = match params.filesystem {
+            DataFilesystem::Ntfs => "ntfs",
+            DataFilesystem::Fat32 => "fat32",
+        };
+
+        windows_usb::run(
+            Command::new("parted").args([
+                "-s",
+                "-a",
+                "optimal",
+                &params.device,
+                "mklabel",
+                "gpt",
+                "mkpart",
+                "BIOS",
+                "1MiB",
+                "2MiB",
+                "set",
+                "1",
+                "bios_grub",
+                "on",
+                "mkpart",
+                "ESP",
+                "fat32",
+                "2MiB",
+                &esp_end,
+                "set",
+                "2",
+                "esp",
+                "on",
+                "mkpart",
+                "Data",
+                data_fs,
+                &esp_end,
+                "100%",
+                "set",
+                "3",
+                "msftdata",
+                "on",
+                // Some BIOSes only boot GPT disks with the protective MBR marked active
+                "disk_set",
+                "pmbr_boot",
+                "on",
+            ]),
+            "parted",
+        )
+        .map_err(|e| DiskError::PartitionFailed(e.to_string()))?;
+
+        let _ = Command::new("partprobe").arg(&params.device).status();
+        let _ = Command::new("udevadm").arg("settle").status();
+        Ok(())
+    }
+
+    async fn copy_isos(&self, params: &MultibootParams, data_dir: &Path) -> Result<()> {
+        let isos = params.isos.clone();
+        let target = data_dir.join("isos");
+        let total: u64 = isos
+            .iter()
+            .filter_map(|iso| fs::metadata(iso).ok())
+            .map(|m| m.len())
+            .sum();
+        let progress = self.progress_tx.clone();
+        let device = params.device.clone();
+
+        tokio::task::spawn_blocking(move || -> io::Result<()> {
+            fs::create_dir_all(&target)?;
+            let mut copied = 0u64;
+            let mut last_percentage = 0u8;
+            let mut buf = vec![0u8; 4 * 1024 * 1024];
+            for iso in &isos {
+                let name = iso.file_name().unwrap_or_default();
+                let mut reader = fs::File::open(iso)?;
+                let mut writer = fs::File::create(target.join(name))?;
+                loop {
+                    let n = reader.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    writer.write_all(&buf[..n])?;
+                    copied += n as u64;
+
+                    // Copying spans 20%..95% of the overall operation
+                    let percentage = 20 + ((copied * 75) / total.max(1)).min(75) as u8;
+                    if percentage != last_percentage {
+                        last_percentage = percentage;
+                        if let Some(tx) = &progress {
+                            let _ = tx.send(DiskProgress {
+                                device: device.clone(),
+                                operation: DiskOperation::Multiboot,
+                                step: 5,
+                                total_steps: TOTAL_STEPS,
+                                percentage,
+                                message: "Copying ISOs".to_string(),
+                            });
+                        }
+                    }
+                }
+                writer.sync_all()?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| DiskError::WriteFailed(format!("copy task failed: {}", e)))?
+        .map_err(|e| DiskError::WriteFailed(format!("copy failed: {}", e)))?;
+        Ok(())
+    }
+
+    fn report(&self, params: &MultibootParams, step: u32, percentage: u8, message: &str) {
+        if let Some(tx) = &self.progress_tx {
+            let _ = tx.send(DiskProgress {
+                device: params.device.clone(),
+                operation: DiskOperation::Multiboot,
+                step,
+                total_steps: TOTAL_STEPS,
+                percentage,
+                message: message.to_string(),
+            });
+        }
+    }
+}
+
+impl Default for MultibootWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// List the ISO with `isoinfo` and recognise the distribution it boots
+pub fn inspect_iso(iso: &Path) -> Result<Option<MultibootEntry>> {
+    let listing = isoinfo(&["-R", "-f", "-i"], iso)?;
+    let files: Vec<&str> = listing.lines().map(str::trim).collect();
+    let Some(distro) = detect_distro(&files) else {
+        return Ok(None);
+    };
+
+    let volume_id = isoinfo(&["-d", "-i"], iso)?
+        .lines()
+        .find_map(|line| line.strip_prefix("Volume id:"))
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default();
+
+    Ok(Some(MultibootEntry {
+        file_name: iso
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        volume_id,
+        distro,
+    }))
+}
+
+fn isoinfo(args: &[&str], iso: &Path) -> Result<String> {
+    let output = Command::new("isoinfo")
+        .args(args)
+        .arg(iso)
+        .output()
+        .map_err(|e| DiskError::InvalidLayout(format!("Failed to run isoinfo: {}", e)))?;
+    if !output.status.success() {
+        return Err(DiskError::InvalidLayout(format!(
+            "{}: isoinfo failed: {}",
+            iso.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Recognise a distribution from the ISO's file list (absolute paths)
+pub fn detect_distro(files: &[&str]) -> Option<Distro> {
+    let find = |prefix: &str| {
+        files
+            .iter()
+            .find(|f| f.starts_with(prefix))
+            .map(|f| f.to_string())
+    };
+
+    if let (Some(kernel), Some(initrd)) = (find("/casper/vmlinuz"), find("/casper/initrd")) {
+        return Some(Distro::Ubuntu { kernel, initrd });
+    }
+    if let (Some(kernel), Some(initrd)) = (find("/live/vmlinuz"), find("/live/initrd")) {
+        return Some(Distro::DebianLive { kernel, initrd });
+    }
+    if files.contains(&"/install.amd/vmlinuz") && files.contains(&"/install.amd/initrd.gz") {
+        return Some(Distro::DebianInstaller);
+    }
+    if files.contains(&"/images/pxeboot/vmlinuz") && files.contains(&"/images/pxeboot/initrd.img") {
+        return Some(Distro::Anaconda);
+    }
+    if files.contains(&"/arch/boot/x86_64/vmlinuz-linux") {
+        return Some(Distro::Arch);
+    }
+    None
+}
+
+/// grub.cfg with a loopback entry per ISO. The data partition is found by
+/// label, and each kernel is told where the ISO lives so the live or
+/// installer system can mount it after boot.
+pub fn grub_config(label: &str, filesystem: DataFilesystem, entries: &[MultibootEntry]) -> String {
+    let fs_module = match filesystem {
+        DataFilesystem::Ntfs => "ntfs",
+        DataFilesystem::Fat32 => "fat",
+    };
+    let mut out = format!(
+        "insmod part_gpt\ninsmod {}\ninsmod iso9660\ninsmod loopback\ninsmod all_video\n\
+         search --no-floppy --set=isopart --label {}\n\
+         set timeout=10\n\n",
+        fs_module, label
+    );
+
+    for entry in entries {
+        let iso = format!("/isos/{}", entry.file_name);
+        let title = if entry.volume_id.is_empty() {
+            entry.file_name.clone()
+        } else {
+            entry.volume_id.clone()
+        };
+        let (kernel, initrd, args) = match &entry.distro {
+            Distro::Ubuntu { kernel, initrd } => (
+                kernel.clone(),
+                initrd.clone(),
+                format!("boot=casper iso-scan/filename={} quiet splash ---", iso),
+            ),
+            Distro::DebianLive { kernel, initrd } => (
+                kernel.clone(),
+                initrd.clone(),
+                format!("boot=live components findiso={}", iso),
+            ),
+            Distro::DebianInstaller => (
+                "/install.amd/vmlinuz".to_string(),
+                "/install.amd/initrd.gz".to_string(),
+                format!("iso-scan/ask_second_pass=true iso-scan/filename={}", iso),
+            ),
+            Distro::Anaconda => (
+                "/images/pxeboot/vmlinuz".to_string(),
+                "/images/pxeboot/initrd.img".to_string(),
+                format!("inst.stage2=hd:LABEL={}:{}", label, iso),
+            ),
+            Distro::Arch => (
+                "/arch/boot/x86_64/vmlinuz-linux".to_string(),
+                "/arch/boot/x86_64/initramfs-linux.img".to_string(),
+                format!(
+                    "img_dev=/dev/disk/by-label/{} img_loop={} archisolabel={}",
+                    label, iso, entry.volume_id
+                ),
+            ),
+        };
+
+        out.push_str(&format!(
+            "menuentry \"{title}\" {{\n\
+             \tset isofile=\"{iso}\"\n\
+             \tloopback loop (${{isopart}})$isofile\n\
+             \tlinux (loop){kernel} {args}\n\
+             \tinitrd (loop){initrd}\n\
+             }}\n\n",
+            title = title.replace('"', "'"),
+            iso = iso,
+            kernel = kernel,
+            args = args,
+            initrd = initrd
+        ));
+    }
+    out
+}
+
+/// Install GRUB for BIOS (core image in the BIOS boot partition) and as the
+/// removable-media UEFI loader, sharing `/boot/grub` on the ESP
+fn install_grub(device: &str, esp_dir: &Path) -> Result<()> {
+    let boot_dir = esp_dir.join("boot");
+    let boot_arg = format!("--boot-directory={}", boot_dir.display());
+    let efi_arg = format!("--efi-directory={}", esp_dir.display());
+
+    windows_usb::run(
+        Command::new("grub-install").args(["--target=i386-pc", &boot_arg, device]),
+        "grub-install",
+    )
+    .and_then(|_| {
+        windows_usb::run(
+            Command::new("grub-install").args([
+                "--target=x86_64-efi",
+                &efi_arg,
+                &boot_arg,
+                "--removable",
+                "--no-nvram",
+            ]),
+            "grub-install",
+        )
+    })
+    .map_err(|e| DiskError::WriteFailed(e.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_distro() {
+        let ubuntu = [
+            "/casper",
+            "/casper/initrd",
+            "/casper/vmlinuz",
+            "/boot/grub/grub.cfg",
+        ];
+        assert_eq!(
+            detect_distro(&ubuntu),
+            Some(Distro::Ubuntu {
+                kernel: "/casper/vmlinuz".to_string(),
+                initrd: "/casper/initrd".to_string(),
+            })
+        );
+
+        let debian = ["/install.amd/vmlinuz", "/install.amd/initrd.gz", "/pool"];
+        assert_eq!(detect_distro(&debian), Some(Distro::DebianInstaller));
+
+        let fedora = ["/images/pxeboot/vmlinuz", "/images/pxeboot/initrd.img"];
+        assert_eq!(detect_distro(&fedora), Some(Distro::Anaconda));
+
+        let windows = ["/bootmgr", "/sources/install.wim"];
+        assert_eq!(detect_distro(&windows), None);
+    }
+
+    #[test]
+    fn test_grub_config() {
+        let entries = vec![
+            MultibootEntry {
+                file_name: "ubuntu-24.04-desktop-amd64.iso".to_string(),
+                volume_id: "Ubuntu 24.04 LTS amd64".to_string(),
+                distro: Distro::Ubuntu {
+                    kernel: "/casper/vmlinuz".to_string(),
+                    initrd: "/casper/initrd".to_string(),
+                },
+            },
+            MultibootEntry {
+                file_name: "archlinux-x86_64.iso".to_string(),
+                volume_id: "ARCH_202405".to_string(),
+                distro: Distro::Arch,
+            },
+        ];
+        let config = grub_config("MULTIBOOT", DataFilesystem::Ntfs, &entries);
+
+        assert!(config.contains("insmod ntfs\n"));
+        assert!(config.contains("search --no-floppy --set=isopart --label MULTIBOOT\n"));
+        assert!(config.contains("menuentry \"Ubuntu 24.04 LTS amd64\" {\n"));
+        assert!(config.contains("\tloopback loop (${isopart})$isofile\n"));
+        assert!(config.contains(
+            "\tlinux (loop)/casper/vmlinuz boot=casper iso-scan/filename=/isos/ubuntu-24.04-desktop-amd64.iso quiet splash ---\n"
+        ));
+        assert!(config.contains(
+            "img_dev=/dev/disk/by-label/MULTIBOOT img_loop=/isos/archlinux-x86_64.iso archisolabel=ARCH_202405"
+        ));
+    }
+}
@@ -1,7 +1,11 @@
+use super::shrink::{self, ShrinkPlan};
+use super::{DiskOperation, DiskProgress};
 use crate::error::{Result, UsbNodeError};
 use log::{debug, error, info, warn};
+use std::io::Write;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use tokio::sync::broadcast;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum PartitionType {
@@ -40,11 +44,34 @@ pub struct PartitionInfo {
 pub struct PartitionManager {
     device: String,
     scheme: PartitionScheme,
+    progress_tx: Option<broadcast::Sender<DiskProgress>>,
 }
 
 impl PartitionManager {
     pub fn new(device: String, scheme: PartitionScheme) -> Self {
-        Self { device, scheme }
+        Self {
+            device,
+            scheme,
+            progress_tx: None,
+        }
+    }
+
+    pub fn with_progress(mut self, tx: broadcast::Sender<DiskProgress>) -> Self {
+        self.progress_tx = Some(tx);
+        self
+    }
+
+    fn report(&self, step: u32, total_steps: u32, message: &str) {
+        if let Some(tx) = &self.progress_tx {
+            let _ = tx.send(DiskProgress {
+                device: self.device.clone(),
+                operation: DiskOperation::Partition,
+                step,
+                total_steps,
+                percentage: ((step * 100) / total_steps.max(1)).min(100) as u8,
+                message: message.to_string(),
+            });
+        }
     }
 
     pub async fn create_partition_table(&self) -> Result<()> {
@@ -57,9 +84,12 @@ impl PartitionManager {
             self.device
         );
 
+        self.report(0, 3, "Validating device");
         self.validate_device()?;
+        self.report(1, 3, "Unmounting partitions");
         self.unmount_all_partitions().await?;
 
+        self.report(2, 3, "Writing partition table");
         let label_type = match self.scheme {
             PartitionScheme::Mbr => "msdos",
             PartitionScheme::Gpt => "gpt",
@@ -82,11 +112,13 @@ impl PartitionManager {
         }
 
         info!("Partition table created successfully");
+        self.report(3, 3, "Partition table created");
         Ok(())
     }
 
     pub async fn create_partition(&self, spec: &PartitionSpec) -> Result<u32> {
         info!("Creating partition: {} MB", spec.size_mb);
+        self.report(0, 2, "Creating partition");
 
         let partitions = self.list_partitions().await?;
         let partition_number = partitions.len() as u32 + 1;
@@ -141,10 +173,12 @@ impl PartitionManager {
         }
 
         if spec.bootable {
+            self.report(1, 2, "Setting boot flag");
             self.set_bootable(partition_number).await?;
         }
 
         info!("Partition {} created successfully", partition_number);
+        self.report(2, 2, "Partition created");
         Ok(partition_number)
     }
 
@@ -152,8 +186,10 @@ impl PartitionManager {
         info!("Deleting partition {}", partition_number);
 
         let partition_device = format!("{}{}", self.device, partition_number);
+        self.report(0, 2, "Unmounting partition");
         self.unmount_partition(&partition_device).await?;
 
+        self.report(1, 2, "Deleting partition");
         let output = Command::new("parted")
             .arg("-s")
             .arg(&self.device)
@@ -171,6 +207,7 @@ impl PartitionManager {
         }
 
         info!("Partition {} deleted successfully", partition_number);
+        self.report(2, 2, "Partition deleted");
         Ok(())
     }
 
@@ -194,22 +231,120 @@ impl PartitionManager {
         self.parse_partition_list(&stdout)
     }
 
+    /// Resize a partition. Shrinking goes through `shrink_partition` so the
+    /// filesystem is shrunk first.
     pub async fn resize_partition(&self, partition_number: u32, new_size_mb: u64) -> Result<()> {
         info!(
             "Resizing partition {} to {} MB",
             partition_number, new_size_mb
         );
 
+        let (sector_size, start, end) = self.partition_geometry(partition_number)?;
+        let current_size_mb = (end - start + 1) * sector_size / (1024 * 1024);
+        if new_size_mb < current_size_mb {
+            self.shrink_partition(partition_number, new_size_mb, false).await?;
+            return Ok(());
+        }
+
         let partition_device = format!("{}{}", self.device, partition_number);
+        self.report(0, 2, "Unmounting partition");
         self.unmount_partition(&partition_device).await?;
 
-        let output = Command::new("parted")
-            .arg("-s")
+        self.report(1, 2, "Resizing partition");
+        self.set_partition_end(partition_number, sector_size, start, new_size_mb)?;
+
+        info!("Partition {} resized successfully", partition_number);
+        self.report(2, 2, "Partition resized");
+        Ok(())
+    }
+
+    /// Shrink the filesystem and then the partition to `new_size_mb`.
+    ///
+    /// With `dry_run` nothing is modified; the returned plan reports the
+    /// minimum achievable size.
+    pub async fn shrink_partition(
+        &self,
+        partition_number: u32,
+        new_size_mb: u64,
+        dry_run: bool,
+    ) -> Result<ShrinkPlan> {
+        let partition_device = format!("{}{}", self.device, partition_number);
+        self.report(0, 5, "Inspecting filesystem");
+
+        let (sector_size, start, end) = self.partition_geometry(partition_number)?;
+        let fs = shrink::detect(&partition_device)?;
+        let plan = ShrinkPlan {
+            device: partition_device.clone(),
+            filesystem: fs.name().to_string(),
+            current_size_mb: (end - start + 1) * sector_size / (1024 * 1024),
+            minimum_size_mb: shrink::minimum_size_mb(&partition_device, fs)?,
+            target_size_mb: new_size_mb,
+            dry_run,
+        };
+
+        info!(
+            "Shrink plan for {}: {} MB -> {} MB (minimum {} MB)",
+            partition_device, plan.current_size_mb, plan.target_size_mb, plan.minimum_size_mb
+        );
+
+        if dry_run {
+            self.report(5, 5, "Dry run complete");
+            return Ok(plan);
+        }
+
+        if !plan.is_feasible() {
+            return Err(UsbNodeError::Disk(format!(
+                "Cannot shrink {} from {} MB to {} MB (minimum {} MB)",
+                partition_device, plan.current_size_mb, plan.target_size_mb, plan.minimum_size_mb
+            )));
+        }
+
+        self.report(1, 5, "Unmounting partition");
+        self.unmount_partition(&partition_device).await?;
+
+        self.report(2, 5, "Checking filesystem");
+        shrink::check(&partition_device, fs)?;
+
+        self.report(3, 5, "Shrinking filesystem");
+        shrink::shrink_filesystem(&partition_device, fs, new_size_mb)?;
+
+        self.report(4, 5, "Shrinking partition");
+        self.set_partition_end(partition_number, sector_size, start, new_size_mb)?;
+
+        info!("Partition {} shrunk to {} MB", partition_number, new_size_mb);
+        self.report(5, 5, "Partition shrunk");
+        Ok(plan)
+    }
+
+    /// Move the end of a partition so it spans `size_mb` from `start`
+    fn set_partition_end(
+        &self,
+        partition_number: u32,
+        sector_size: u64,
+        start: u64,
+        size_mb: u64,
+    ) -> Result<()> {
+        let end = start + size_mb * 1024 * 1024 / sector_size - 1;
+
+        // parted only asks for shrink confirmation on a tty, so pretend one
+        let mut child = Command::new("parted")
+            .arg("---pretend-input-tty")
             .arg(&self.device)
             .arg("resizepart")
             .arg(partition_number.to_string())
-            .arg(format!("{}MiB", new_size_mb))
-            .output()
+            .arg(format!("{}s", end))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| UsbNodeError::Disk(format!("Failed to execute parted: {}", e)))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(b"Yes\n");
+        }
+
+        let output = child
+            .wait_with_output()
             .map_err(|e| UsbNodeError::Disk(format!("Failed to execute parted: {}", e)))?;
 
         if !output.status.success() {
@@ -220,10 +355,62 @@ impl PartitionManager {
             )));
         }
 
-        info!("Partition {} resized successfully", partition_number);
         Ok(())
     }
 
+    /// Logical sector size, start and end sector of a partition
+    fn partition_geometry(&self, partition_number: u32) -> Result<(u64, u64, u64)> {
+        let output = Command::new("parted")
+            .arg("-m")
+            .arg("-s")
+            .arg(&self.device)
+            .arg("unit")
+            .arg("s")
+            .arg("print")
+            .output()
+            .map_err(|e| UsbNodeError::Disk(format!("Failed to execute parted: {}", e)))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(UsbNodeError::Disk(format!(
+                "Failed to read partition table: {}",
+                stderr
+            )));
+        }
+
+        self.parse_machine_geometry(&String::from_utf8_lossy(&output.stdout), partition_number)
+            .ok_or_else(|| {
+                UsbNodeError::Disk(format!(
+                    "Partition {} not found on {}",
+                    partition_number, self.device
+                ))
+            })
+    }
+
+    /// Parse `parted -m unit s print` output
+    fn parse_machine_geometry(
+        &self,
+        output: &str,
+        partition_number: u32,
+    ) -> Option<(u64, u64, u64)> {
+        let mut lines = output.lines().filter(|l| !l.trim().is_empty());
+        if lines.next()?.trim() != "BYT;" {
+            return None;
+        }
+
+        let sector_size: u64 = lines.next()?.split(':').nth(3)?.parse().ok()?;
+
+        lines.find_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 4 || fields[0].parse::<u32>().ok()? != partition_number {
+                return None;
+            }
+            let start = fields[1].trim_end_matches('s').parse().ok()?;
+            let end = fields[2].trim_end_matches('s').parse().ok()?;
+            Some((sector_size, start, end))
+        })
+    }
+
     async fn set_bootable(&self, partition_number: u32) -> Result<()> {
         let output = Command::new("parted")
             .arg("-s")
@@ -443,6 +630,20 @@ mod tests {
         assert_eq!(manager.scheme, PartitionScheme::Gpt);
     }
 
+    #[tokio::test]
+    async fn test_progress_reporting() {
+        let (tx, mut rx) = broadcast::channel(4);
+        let manager =
+            PartitionManager::new("/dev/sdb".to_string(), PartitionScheme::Gpt).with_progress(tx);
+
+        manager.report(1, 2, "Setting boot flag");
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.device, "/dev/sdb");
+        assert_eq!(event.operation, DiskOperation::Partition);
+        assert_eq!(event.percentage, 50);
+    }
+
     #[test]
     fn test_parse_size_to_bytes() {
         let manager = PartitionManager::new("/dev/sdb".to_string(), PartitionScheme::Gpt);
@@ -465,6 +666,25 @@ mod tests {
         assert_eq!(manager.parse_size_to_mb("1GB").unwrap(), 1024);
     }
 
+    #[test]
+    fn test_parse_machine_geometry() {
+        let manager = PartitionManager::new("/dev/sdb".to_string(), PartitionScheme::Gpt);
+        let output = "BYT;\n\
+            /dev/sdb:30031872s:scsi:512:512:gpt:SanDisk Ultra:;\n\
+            1:2048s:1050623s:1048576s:fat32:EFI:boot, esp;\n\
+            2:1050624s:30029823s:28979200s:ext4:root:;\n";
+
+        assert_eq!(
+            manager.parse_machine_geometry(output, 2),
+            Some((512, 1_050_624, 30_029_823))
+        );
+        assert_eq!(manager.parse_machine_geometry(output, 3), None);
+        assert_eq!(
+            manager.parse_machine_geometry("Error: unrecognised disk label", 1),
+            None
+        );
+    }
+
     #[test]
     fn test_parse_size_to_sectors() {
         let manager = PartitionManager::new("/dev/sdb".to_string(), PartitionScheme::Gpt);
@@ -0,0 +1,316 @@
+use super::format::FileSystemType;
+use crate::error::{DiskError, Result};
+use std::process::Command;
+use tracing::{error, info};
+
+/// Change the volume label of an existing filesystem without reformatting
+pub fn set_label(device: &str, label: &str) -> Result<()> {
+    let fs_type = detect_filesystem(device)?;
+    ensure_unmounted(device)?;
+
+    info!("Setting label of {} ({:?}) to '{}'", device, fs_type, label);
+    run(label_command(fs_type, device, label)?, device)
+}
+
+/// Change the UUID (or FAT/NTFS volume serial) of an existing filesystem
+pub fn set_uuid(device: &str, uuid: &str) -> Result<()> {
+    let fs_type = detect_filesystem(device)?;
+    ensure_unmounted(device)?;
+
+    info!("Setting UUID of {} ({:?}) to {}", device, fs_type, uuid);
+    run(uuid_command(fs_type, device, uuid)?, device)
+}
+
+/// Build the relabel command for a filesystem type
+pub fn label_command(fs_type: FileSystemType, device: &str, label: &str) -> Result<Command> {
+    validate_label(fs_type, label)?;
+
+    let (program, args) = match fs_type {
+        FileSystemType::Ext4 | FileSystemType::Ext3 | FileSystemType::Ext2 => {
+            ("e2label", vec![device.to_string(), label.to_string()])
+        }
+        FileSystemType::Xfs => (
+            "xfs_admin",
+            vec!["-L".to_string(), label.to_string(), device.to_string()],
+        ),
+        FileSystemType::Btrfs => (
+            "btrfs",
+            vec![
+                "filesystem".to_string(),
+                "label".to_string(),
+                device.to_string(),
+                label.to_string(),
+            ],
+        ),
+        // FAT labels are stored upper case; fatlabel warns otherwise
+        FileSystemType::Vfat => ("fatlabel", vec![device.to_string(), label.to_uppercase()]),
+        FileSystemType::Ntfs => ("ntfslabel", vec![device.to_string(), label.to_string()]),
+        FileSystemType::F2fs => return Err(unsupported(fs_type, "label")),
+    };
+
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    Ok(cmd)
+}
+
+/// Build the UUID change command for a filesystem type
+///
+/// ext, xfs and btrfs take a standard UUID. FAT takes an 8 digit hex volume
+/// ID (`ABCD-1234`) and NTFS a 16 digit hex serial number.
+pub fn uuid_command(fs_type: FileSystemType, device: &str, uuid: &str) -> Result<Command> {
+    let (program, args) = match fs_type {
+        FileSystemType::Ext4 | FileSystemType::Ext3 | FileSystemType::Ext2 => (
+            "tune2fs",
+            vec!["-U".to_string(), parse_uuid(uuid)?, device.to_string()],
+        ),
+        FileSystemType::Xfs => (
+            "xfs_admin",
+            vec!["-U".to_string(), parse_uuid(uuid)?, device.to_string()],
+        ),
+        FileSystemType::Btrfs => (
+            "btrfstune",
+            vec![
+                "-f".to_string(),
+                "-U".to_string(),
+                parse_uuid(uuid)?,
+                device.to_string(),
+            ],
+        ),
+        FileSystemType::Vfat => (
+            "fatlabel",
+            vec!["-i".to_string(), device.to_string(), parse_serial(uuid, 8)?],
+        ),
+        FileSystemType::Ntfs => (
+            "ntfslabel",
+            vec![
+                format!("--new-serial={}", parse_serial(uuid, 16)?),
+                device.to_string(),
+            ],
+        ),
+        FileSystemType::F2fs => return Err(unsupported(fs_type, "UUID")),
+    };
+
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    Ok(cmd)
+}
+
+/// Map a `blkid` TYPE value to a filesystem type
+pub fn parse_blkid_type(value: &str) -> Option<FileSystemType> {
+    match value.trim() {
+        "ext4" => Some(FileSystemType::Ext4),
+        "ext3" => Some(FileSystemType::Ext3),
+        "ext2" => Some(FileSystemType::Ext2),
+        "xfs" => Some(FileSystemType::Xfs),
+        "btrfs" => Some(FileSystemType::Btrfs),
+        "vfat" => Some(FileSystemType::Vfat),
+        "ntfs" => Some(FileSystemType::Ntfs),
+        "f2fs" => Some(FileSystemType::F2fs),
+        _ => None,
+    }
+}
+
+fn detect_filesystem(device: &str) -> Result<FileSystemType> {
+    let output = Command::new("blkid")
+        .args(["-p", "-o", "value", "-s", "TYPE", device])
+        .output()
+        .map_err(|e| DiskError::RelabelFailed(format!("Failed to run blkid: {}", e)))?;
+
+    let value = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || value.trim().is_empty() {
+        return Err(DiskError::RelabelFailed(format!("{}: no filesystem detected", device)).into());
+    }
+
+    parse_blkid_type(&value).ok_or_else(|| {
+        DiskError::RelabelFailed(format!(
+            "{}: unsupported filesystem '{}'",
+            device,
+            value.trim()
+        ))
+        .into()
+    })
+}
+
+fn ensure_unmounted(device: &str) -> Result<()> {
+    let output = Command::new("findmnt")
+        .args(["-n", "-o", "TARGET", "--source", device])
+        .output()
+        .map_err(|e| DiskError::RelabelFailed(format!("Failed to run findmnt: {}", e)))?;
+
+    if output.status.success() && !output.stdout.is_empty() {
+        return Err(DiskError::RelabelFailed(format!(
+            "{} is mounted at {}",
+            device,
+            String::from_utf8_lossy(&output.stdout).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn run(mut cmd: Command, device: &str) -> Result<()> {
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let output = cmd
+        .output()
+        .map_err(|e| DiskError::RelabelFailed(format!("Failed to run {}: {}", program, e)))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!("{} failed on {}: {}", program, device, stderr.trim());
+        return Err(DiskError::RelabelFailed(format!(
+            "{}: {}: {}",
+            device,
+            program,
+            stderr.trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Per-filesystem label limits, in bytes (characters for NTFS)
+fn validate_label(fs_type: FileSystemType, label: &str) -> Result<()> {
+    let (max, len) = match fs_type {
+        FileSystemType::Ext4 | FileSystemType::Ext3 | FileSystemType::Ext2 => (16, label.len()),
+        FileSystemType::Xfs => (12, label.len()),
+        FileSystemType::Btrfs => (255, label.len()),
+        FileSystemType::Vfat => (11, label.len()),
+        FileSystemType::Ntfs => (128, label.chars().count()),
+        FileSystemType::F2fs => (512, label.chars().count()),
+    };
+
+    if len > max {
+        return Err(DiskError::RelabelFailed(format!(
+            "Label '{}' exceeds {} limit of {}",
+            label,
+            fs_name(fs_type),
+            max
+        ))
+        .into());
+    }
+
+    if fs_type == FileSystemType::Vfat
+        && label
+            .chars()
+            .any(|c| !c.is_ascii() || "\"*+,./:;<=>?[\\]|".contains(c))
+    {
+        return Err(DiskError::RelabelFailed(format!(
+            "Label '{}' contains characters not allowed on FAT",
+            label
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+fn parse_uuid(value: &str) -> Result<String> {
+    uuid::Uuid::parse_str(value)
+        .map(|u| u.hyphenated().to_string())
+        .map_err(|_| DiskError::RelabelFailed(format!("Invalid UUID: {}", value)).into())
+}
+
+/// Normalize a hex volume serial such as `ABCD-1234`
+fn parse_serial(value: &str, digits: usize) -> Result<String> {
+    let serial: String = value.chars().filter(|c| *c != '-').collect();
+    if serial.len() != digits || !serial.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(DiskError::RelabelFailed(format!(
+            "Invalid volume serial '{}': expected {} hex digits",
+            value, digits
+        ))
+        .into());
+    }
+    Ok(serial.to_uppercase())
+}
+
+fn fs_name(fs_type: FileSystemType) -> &'static str {
+    match fs_type {
+        FileSystemType::Ext4 => "ext4",
+        FileSystemType::Ext3 => "ext3",
+        FileSystemType::Ext2 => "ext2",
+        FileSystemType::Xfs => "xfs",
+        FileSystemType::Btrfs => "btrfs",
+        FileSystemType::Vfat => "vfat",
+        FileSystemType::Ntfs => "ntfs",
+        FileSystemType::F2fs => "f2fs",
+    }
+}
+
+fn unsupported(fs_type: FileSystemType, what: &str) -> crate::error::Error {
+    DiskError::RelabelFailed(format!(
+        "Changing the {} of {} is not supported",
+        what,
+        fs_name(fs_type)
+    ))
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|a| a.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_label_commands() {
+        let cmd = label_command(FileSystemType::Ext4, "/dev/sdb1", "INV-0042").unwrap();
+        assert_eq!(args(&cmd), ["e2label", "/dev/sdb1", "INV-0042"]);
+
+        let cmd = label_command(FileSystemType::Vfat, "/dev/sdb1", "inv0042").unwrap();
+        assert_eq!(args(&cmd), ["fatlabel", "/dev/sdb1", "INV0042"]);
+
+        let cmd = label_command(FileSystemType::Btrfs, "/dev/sdb2", "data").unwrap();
+        assert_eq!(
+            args(&cmd),
+            ["btrfs", "filesystem", "label", "/dev/sdb2", "data"]
+        );
+
+        assert!(label_command(FileSystemType::Vfat, "/dev/sdb1", "TOO-LONG-LABEL").is_err());
+        assert!(label_command(FileSystemType::Vfat, "/dev/sdb1", "A/B").is_err());
+        assert!(label_command(FileSystemType::Xfs, "/dev/sdb1", "thirteen-char").is_err());
+        assert!(label_command(FileSystemType::F2fs, "/dev/sdb1", "data").is_err());
+    }
+
+    #[test]
+    fn test_uuid_commands() {
+        let cmd = uuid_command(
+            FileSystemType::Xfs,
+            "/dev/sdb1",
+            "1B4E28BA-2FA1-11D2-883F-0016D3CCA427",
+        )
+        .unwrap();
+        assert_eq!(
+            args(&cmd),
+            [
+                "xfs_admin",
+                "-U",
+                "1b4e28ba-2fa1-11d2-883f-0016d3cca427",
+                "/dev/sdb1"
+            ]
+        );
+
+        let cmd = uuid_command(FileSystemType::Vfat, "/dev/sdb1", "abcd-1234").unwrap();
+        assert_eq!(args(&cmd), ["fatlabel", "-i", "/dev/sdb1", "ABCD1234"]);
+
+        let cmd = uuid_command(FileSystemType::Ntfs, "/dev/sdb1", "0123456789abcdef").unwrap();
+        assert_eq!(
+            args(&cmd),
+            ["ntfslabel", "--new-serial=0123456789ABCDEF", "/dev/sdb1"]
+        );
+
+        assert!(uuid_command(FileSystemType::Ext4, "/dev/sdb1", "not-a-uuid").is_err());
+        assert!(uuid_command(FileSystemType::Vfat, "/dev/sdb1", "ABCD").is_err());
+    }
+
+    #[test]
+    fn test_parse_blkid_type() {
+        assert_eq!(parse_blkid_type("ntfs\n"), Some(FileSystemType::Ntfs));
+        assert_eq!(parse_blkid_type("vfat"), Some(FileSystemType::Vfat));
+        assert_eq!(parse_blkid_type("swap"), None);
+    }
+}
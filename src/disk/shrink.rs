@@ -0,0 +1,260 @@
+use super::format::FileSystemType;
+use super::relabel::parse_blkid_type;
+use crate::error::{DiskError, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tracing::{debug, error, info};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Filesystems that can be shrunk offline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShrinkableFs {
+    /// ext2/3/4 via resize2fs
+    Ext,
+    /// NTFS via ntfsresize
+    Ntfs,
+}
+
+impl ShrinkableFs {
+    pub fn from_fs_type(fs_type: FileSystemType) -> Result<Self> {
+        match fs_type {
+            FileSystemType::Ext4 | FileSystemType::Ext3 | FileSystemType::Ext2 => Ok(Self::Ext),
+            FileSystemType::Ntfs => Ok(Self::Ntfs),
+            other => Err(DiskError::ResizeFailed(format!(
+                "Shrinking {:?} filesystems is not supported",
+                other
+            ))
+            .into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ext => "ext",
+            Self::Ntfs => "ntfs",
+        }
+    }
+}
+
+/// Outcome of planning (or performing) a partition shrink
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShrinkPlan {
+    pub device: String,
+    pub filesystem: String,
+    pub current_size_mb: u64,
+    pub minimum_size_mb: u64,
+    pub target_size_mb: u64,
+    pub dry_run: bool,
+}
+
+impl ShrinkPlan {
+    pub fn is_feasible(&self) -> bool {
+        self.target_size_mb >= self.minimum_size_mb && self.target_size_mb < self.current_size_mb
+    }
+}
+
+/// Detect the filesystem on `device` and check it can be shrunk
+pub fn detect(device: &str) -> Result<ShrinkableFs> {
+    let output = Command::new("blkid")
+        .args(["-p", "-o", "value", "-s", "TYPE", device])
+        .output()
+        .map_err(|e| DiskError::ResizeFailed(format!("Failed to run blkid: {}", e)))?;
+
+    let value = String::from_utf8_lossy(&output.stdout);
+    let fs_type = parse_blkid_type(&value).ok_or_else(|| {
+        DiskError::ResizeFailed(format!(
+            "{}: unknown filesystem '{}', refusing to shrink",
+            device,
+            value.trim()
+        ))
+    })?;
+    ShrinkableFs::from_fs_type(fs_type)
+}
+
+/// Smallest size the filesystem can be shrunk to, rounded up to whole MiB
+pub fn minimum_size_mb(device: &str, fs: ShrinkableFs) -> Result<u64> {
+    let bytes = match fs {
+        ShrinkableFs::Ext => {
+            let blocks = parse_resize2fs_minimum(&run(
+                Command::new("resize2fs").args(["-P", device]),
+                device,
+            )?)
+            .ok_or_else(|| parse_error(device, "resize2fs -P"))?;
+            let block_size =
+                parse_block_size(&run(Command::new("dumpe2fs").args(["-h", device]), device)?)
+                    .ok_or_else(|| parse_error(device, "dumpe2fs"))?;
+            blocks * block_size
+        }
+        ShrinkableFs::Ntfs => parse_ntfsresize_minimum(&run(
+            Command::new("ntfsresize").args(["--info", "--force", "--no-progress-bar", device]),
+            device,
+        )?)
+        .ok_or_else(|| parse_error(device, "ntfsresize --info"))?,
+    };
+    Ok(bytes.div_ceil(MIB))
+}
+
+/// Check the filesystem before resizing; resize2fs refuses unchecked filesystems
+pub fn check(device: &str, fs: ShrinkableFs) -> Result<()> {
+    if fs != ShrinkableFs::Ext {
+        return Ok(());
+    }
+
+    debug!("Checking filesystem on {}", device);
+    let status = Command::new("e2fsck")
+        .args(["-f", "-y", device])
+        .status()
+        .map_err(|e| DiskError::ResizeFailed(format!("Failed to run e2fsck: {}", e)))?;
+
+    // 0: clean, 1: errors corrected
+    match status.code() {
+        Some(0) | Some(1) => Ok(()),
+        code => Err(
+            DiskError::ResizeFailed(format!("{}: e2fsck exited with {:?}", device, code)).into(),
+        ),
+    }
+}
+
+/// Shrink the filesystem on `device` to `size_mb`
+pub fn shrink_filesystem(device: &str, fs: ShrinkableFs, size_mb: u64) -> Result<()> {
+    info!(
+        "Shrinking {} filesystem on {} to {} MiB",
+        fs.name(),
+        device,
+        size_mb
+    );
+
+    match fs {
+        ShrinkableFs::Ext => {
+            run(
+                Command::new("resize2fs").args([device, &format!("{}M", size_mb)]),
+                device,
+            )?;
+        }
+        ShrinkableFs::Ntfs => {
+            // ntfsresize size suffixes are decimal, so pass bytes
+            let mut child = Command::new("ntfsresize")
+                .args(["--force", "--no-progress-bar", "--size"])
+                .arg((size_mb * MIB).to_string())
+                .arg(device)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| DiskError::ResizeFailed(format!("Failed to run ntfsresize: {}", e)))?;
+
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(b"y\n");
+            }
+            let output = child
+                .wait_with_output()
+                .map_err(|e| DiskError::ResizeFailed(format!("ntfsresize failed: {}", e)))?;
+            if !output.status.success() {
+                return Err(command_error(device, "ntfsresize", &output.stderr));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run(cmd: &mut Command, device: &str) -> Result<String> {
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let output = cmd
+        .output()
+        .map_err(|e| DiskError::ResizeFailed(format!("Failed to run {}: {}", program, e)))?;
+
+    if !output.status.success() {
+        return Err(command_error(device, &program, &output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn command_error(device: &str, program: &str, stderr: &[u8]) -> crate::error::Error {
+    let stderr = String::from_utf8_lossy(stderr);
+    error!("{} failed on {}: {}", program, device, stderr.trim());
+    DiskError::ResizeFailed(format!("{}: {}: {}", device, program, stderr.trim())).into()
+}
+
+fn parse_error(device: &str, tool: &str) -> DiskError {
+    DiskError::ResizeFailed(format!("{}: unexpected {} output", device, tool))
+}
+
+/// `Estimated minimum size of the filesystem: 123456` (in filesystem blocks)
+fn parse_resize2fs_minimum(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("Estimated minimum size of the filesystem:"))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn parse_block_size(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("Block size:"))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// `You might resize at 5234688000 bytes or 5235 MB (freeing 10764 MB).`
+fn parse_ntfsresize_minimum(output: &str) -> Option<u64> {
+    output.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("You might resize at ")?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ext_minimum() {
+        let resize2fs =
+            "resize2fs 1.47.0 (5-Feb-2023)\nEstimated minimum size of the filesystem: 262144\n";
+        let dumpe2fs = "Filesystem volume name:   root\nBlock count:              3932160\nBlock size:               4096\n";
+
+        assert_eq!(parse_resize2fs_minimum(resize2fs), Some(262144));
+        assert_eq!(parse_block_size(dumpe2fs), Some(4096));
+        assert_eq!(parse_resize2fs_minimum("resize2fs: Bad magic number"), None);
+    }
+
+    #[test]
+    fn test_parse_ntfs_minimum() {
+        let output = "ntfsresize v2022.10.3 (libntfs-3g)\n\
+            Device name        : /dev/sdb2\n\
+            Current volume size: 16000000000 bytes (16000 MB)\n\
+            You might resize at 5234688000 bytes or 5235 MB (freeing 10765 MB).\n";
+
+        assert_eq!(parse_ntfsresize_minimum(output), Some(5_234_688_000));
+        assert_eq!(parse_ntfsresize_minimum("ERROR: volume is dirty"), None);
+    }
+
+    #[test]
+    fn test_shrink_plan_feasibility() {
+        let plan = ShrinkPlan {
+            device: "/dev/sdb2".to_string(),
+            filesystem: "ext".to_string(),
+            current_size_mb: 16000,
+            minimum_size_mb: 1024,
+            target_size_mb: 4096,
+            dry_run: true,
+        };
+        assert!(plan.is_feasible());
+        assert!(!ShrinkPlan {
+            target_size_mb: 512,
+            ..plan.clone()
+        }
+        .is_feasible());
+        assert!(!ShrinkPlan {
+            target_size_mb: 16000,
+            ..plan
+        }
+        .is_feasible());
+        assert!(ShrinkableFs::from_fs_type(FileSystemType::Xfs).is_err());
+    }
+}
@@ -0,0 +1,578 @@
+use super::format::{DiskFormatter, FileSystemType, FormatParams};
+use super::{DiskOperation, DiskProgress};
+use crate::chaos::{self, FaultPoint};
+use crate::error::{DiskError, Result};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+/// Largest file FAT32 can hold
+const FAT32_MAX_FILE: u64 = 4 * 1024 * 1024 * 1024 - 1;
+
+/// Part size passed to `wimlib-imagex split`, in MiB
+const WIM_SPLIT_MB: u64 = 3800;
+
+const TOTAL_STEPS: u32 = 6;
+
+/// File system used for the partition holding the Windows setup files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFilesystem {
+    /// NTFS data partition, booted on UEFI through UEFI:NTFS
+    Ntfs,
+    /// FAT32 data partition, install.wim split to fit
+    Fat32,
+}
+
+/// Parameters for building a Windows installer stick
+#[derive(Debug, Clone)]
+pub struct WindowsUsbParams {
+    /// Target whole-disk device (e.g., /dev/sdb)
+    pub device: String,
+    /// Root of the mounted Windows ISO
+    pub source: PathBuf,
+    /// Directory used to mount the data partition while copying
+    pub work_dir: PathBuf,
+    /// UEFI:NTFS FAT image written to the ESP
+    pub uefi_ntfs_image: PathBuf,
+    /// Size of the ESP in MiB
+    pub esp_size_mb: u64,
+    /// Data partition file system
+    pub filesystem: DataFilesystem,
+    /// Data partition volume label
+    pub label: String,
+    /// autounattend.xml placed at the root of the data partition
+    pub answer_file: Option<String>,
+}
+
+impl WindowsUsbParams {
+    /// Create parameters with an NTFS data partition and a 2 MiB ESP
+    pub fn new(device: String, source: PathBuf, uefi_ntfs_image: PathBuf) -> Self {
+        Self {
+            device,
+            source,
+            work_dir: PathBuf::from("/mnt/usb-installer-target"),
+            uefi_ntfs_image,
+            esp_size_mb: 2,
+            filesystem: DataFilesystem::Ntfs,
+            label: "WINSETUP".to_string(),
+            answer_file: None,
+        }
+    }
+
+    /// Set data partition file system
+    pub fn with_filesystem(mut self, filesystem: DataFilesystem) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+
+    /// Set data partition label
+    pub fn with_label(mut self, label: String) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Set mount directory for the data partition
+    pub fn with_work_dir(mut self, work_dir: PathBuf) -> Self {
+        self.work_dir = work_dir;
+        self
+    }
+
+    /// Set the autounattend.xml contents for a hands-off installation
+    pub fn with_answer_file(mut self, answer_file: Option<String>) -> Self {
+        self.answer_file = answer_file;
+        self
+    }
+
+    /// Set ESP size in MiB
+    pub fn with_esp_size(mut self, size_mb: u64) -> Self {
+        self.esp_size_mb = size_mb;
+        self
+    }
+}
+
+/// Produces Windows installer sticks from a mounted Windows ISO
+pub struct WindowsUsbWriter {
+    formatter: DiskFormatter,
+    progress_tx: Option<broadcast::Sender<DiskProgress>>,
+}
+
+impl WindowsUsbWriter {
+    /// Create a new writer
+    pub fn new() -> Self {
+        Self {
+            formatter: DiskFormatter::new(),
+            progress_tx: None,
+        }
+    }
+
+    /// Publish progress events on the given channel
+    pub fn with_progress(mut self, tx: broadcast::Sender<DiskProgress>) -> Self {
+        self.formatter = DiskFormatter::new().with_progress(tx.clone());
+        self.progress_tx = Some(tx);
+        self
+    }
+
+    /// Partition, format and populate `params.device` as a bootable Windows installer.
+    ///
+    /// Layout is GPT with the data partition first and a small ESP at the end of the
+    /// disk. With an NTFS data partition the ESP carries UEFI:NTFS, which chain-loads
+    /// the Windows boot manager from NTFS on firmware that only reads FAT.
+    pub async fn create(&self, params: &WindowsUsbParams) -> Result<()> {
+        info!(
+            "Creating Windows installer on {} from {}",
+            params.device,
+            params.source.display()
+        );
+
+        self.report(params, 0, 0, "Validating source and target");
+        self.validate(params)?;
+
+        self.report(params, 1, 5, "Partitioning");
+        unmount_device(&params.device);
+        self.partition(params)?;
+
+        let data_part = partition_path(&params.device, 1);
+        let esp_part = partition_path(&params.device, 2);
+
+        self.report(params, 2, 10, "Formatting data partition");
+        let fs_type = match params.filesystem {
+            DataFilesystem::Ntfs => FileSystemType::Ntfs,
+            DataFilesystem::Fat32 => FileSystemType::Vfat,
+        };
+        let mut format = FormatParams::new(data_part.clone(), fs_type)
+            .with_label(params.label.clone())
+            .force();
+        if params.filesystem == DataFilesystem::Ntfs {
+            format = format.add_option("--quick".to_string());
+        }
+        self.formatter.format(&format).await?;
+
+        self.report(params, 3, 15, "Installing UEFI boot loader");
+        match params.filesystem {
+            DataFilesystem::Ntfs => self.install_uefi_ntfs(params, &esp_part)?,
+            DataFilesystem::Fat32 => {
+                let esp = FormatParams::new(esp_part.clone(), FileSystemType::Vfat)
+                    .with_label("ESP".to_string());
+                self.formatter.format(&esp).await?;
+            }
+        }
+
+        self.report(params, 4, 20, "Copying installation files");
+        mount(&data_part, &params.work_dir, params.filesystem)?;
+        let result = self.populate(params).await;
+        let unmounted = umount(&params.work_dir);
+        result?;
+        unmounted?;
+
+        self.report(params, TOTAL_STEPS, 100, "Windows installer created");
+        info!("Windows installer created on {}", params.device);
+        Ok(())
+    }
+
+    fn validate(&self, params: &WindowsUsbParams) -> Result<()> {
+        if !Path::new(&params.device).exists() {
+            return Err(DiskError::DiskNotFound(params.device.clone()).into());
+        }
+
+        if !params.source.join("sources").is_dir() || !params.source.join("bootmgr").exists() {
+            return Err(DiskError::InvalidLayout(format!(
+                "{} does not look like Windows installation media",
+                params.source.display()
+            ))
+            .into());
+        }
+
+        if params.filesystem == DataFilesystem::Ntfs {
+            let image_size = fs::metadata(&params.uefi_ntfs_image)
+                .map_err(|e| {
+                    DiskError::WriteFailed(format!(
+                        "UEFI:NTFS image {}: {}",
+                        params.uefi_ntfs_image.display(),
+                        e
+                    ))
+                })?
+                .len();
+            // parted loses part of the last MiB to the backup GPT header
+            let esp_usable = params.esp_size_mb.saturating_sub(1) * 1024 * 1024;
+            if image_size > esp_usable {
+                return Err(DiskError::InsufficientSpace(image_size, esp_usable).into());
+            }
+        }
+
+        let needed = dir_size(&params.source)?;
+        let available = device_size(&params.device)?;
+        if needed + params.esp_size_mb * 1024 * 1024 > available {
+            return Err(DiskError::InsufficientSpace(needed, available).into());
+        }
+
+        Ok(())
+    }
+
+    fn partition(&self, params: &WindowsUsbParams) -> Result<()> {
+        run(
+            Command::new("wipefs").args(["-a", &params.device]),
+            "wipefs",
+        )
+        .map_err(|e| DiskError::PartitionFailed(e.to_string()))?;
+
+        let esp_start = format!("-{}MiB", params.esp_size_mb);
+        let data_fs = match params.filesystem {
+            DataFilesystem::Ntfs => "ntfs",
+            DataFilesystem::Fat32 => "fat32",
+        };
+
+        run(
+            Command::new("parted").args([
+                "-s",
+                "-a",
+                "optimal",
+                &params.device,
+                "mklabel",
+                "gpt",
+                "mkpart",
+                "Windows",
+                data_fs,
+                "1MiB",
+                &esp_start,
+                "set",
+                "1",
+                "msftdata",
+                "on",
+                "mkpart",
+                "UEFI_NTFS",
+                "fat16",
+                &esp_start,
+                "100%",
+                "set",
+                "2",
+                "esp",
+                "on",
+            ]),
+            "parted",
+        )
+        .map_err(|e| DiskError::PartitionFailed(e.to_string()))?;
+
+        let _ = Command::new("partprobe").arg(&params.device).status();
+        let _ = Command::new("udevadm").arg("settle").status();
+        Ok(())
+    }
+
+    fn install_uefi_ntfs(&self, params: &WindowsUsbParams, esp_part: &str) -> Result<()> {
+        debug!(
+            "Writing {} to {}",
+            params.uefi_ntfs_image.display(),
+            esp_part
+        );
+        run(
+            Command::new("dd").args([
+                &format!("if={}", params.uefi_ntfs_image.display()),
+                &format!("of={}", esp_part),
+                "bs=1M",
+                "conv=fsync",
+            ]),
+            "dd",
+        )
+        .map_err(|e| DiskError::WriteFailed(e.to_string()).into())
+    }
+
+    async fn populate(&self, params: &WindowsUsbParams) -> Result<()> {
+        let split_wim = params.filesystem == DataFilesystem::Fat32
+            && fs::metadata(params.source.join("sources/install.wim"))
+                .map(|m| m.len() > FAT32_MAX_FILE)
+                .unwrap_or(false);
+
+        let source = params.source.clone();
+        let target = params.work_dir.clone();
+        let total = dir_size(&source)?;
+        let progress = self.progress_tx.clone();
+        let device = params.device.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut copied = 0u64;
+            let mut last_percentage = 0u8;
+            copy_tree(&source, &target, split_wim, &mut |bytes| {
+                copied += bytes;
+                // Copying spans 20%..90% of the overall operation
+                let percentage = 20 + ((copied * 70) / total.max(1)).min(70) as u8;
+                if percentage != last_percentage {
+                    last_percentage = percentage;
+                    if let Some(tx) = &progress {
+                        let _ = tx.send(DiskProgress {
+                            device: device.clone(),
+                            operation: DiskOperation::WindowsMedia,
+                            step: 4,
+                            total_steps: TOTAL_STEPS,
+                            percentage,
+                            message: "Copying installation files".to_string(),
+                        });
+                    }
+                }
+            })
+        })
+        .await
+        .map_err(|e| DiskError::WriteFailed(format!("copy task failed: {}", e)))?
+        .map_err(|e| DiskError::WriteFailed(format!("copy failed: {}", e)))?;
+
+        if split_wim {
+            self.report(params, 5, 90, "Splitting install.wim");
+            split_install_wim(&params.source, &params.work_dir)?;
+        }
+
+        if let Some(answer_file) = &params.answer_file {
+            let path = params.work_dir.join("autounattend.xml");
+            fs::write(&path, answer_file)
+                .map_err(|e| DiskError::WriteFailed(format!("{}: {}", path.display(), e)))?;
+        }
+
+        let _ = Command::new("sync").status();
+        Ok(())
+    }
+
+    fn report(&self, params: &WindowsUsbParams, step: u32, percentage: u8, message: &str) {
+        if let Some(tx) = &self.progress_tx {
+            let _ = tx.send(DiskProgress {
+                device: params.device.clone(),
+                operation: DiskOperation::WindowsMedia,
+                step,
+                total_steps: TOTAL_STEPS,
+                percentage,
+                message: message.to_string(),
+            });
+        }
+    }
+}
+
+impl Default for WindowsUsbWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Path of partition `number` on `device`, handling nvme/mmcblk `p` separators
+pub fn partition_path(device: &str, number: u32) -> String {
+    if device.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", device, number)
+    } else {
+        format!("{}{}", device, number)
+    }
+}
+
+pub(super) fn run(cmd: &mut Command, name: &str) -> io::Result<()> {
+    chaos::inject(FaultPoint::CommandExec, name).map_err(|e| io::Error::other(e.to_string()))?;
+
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+pub(super) fn unmount_device(device: &str) {
+    let Ok(mounts) = fs::read_to_string("/proc/mounts") else {
+        return;
+    };
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        if let (Some(source), Some(target)) = (fields.next(), fields.next()) {
+            if is_device_or_partition(source, device) {
+                debug!("Unmounting {} from {}", source, target);
+                if let Err(e) = umount(Path::new(target)) {
+                    warn!("Failed to unmount {}: {}", target, e);
+                }
+            }
+        }
+    }
+}
+
+/// Whether `source` is `device` or one of its partitions as named by
+/// `partition_path`; `/dev/sdab1` is not a partition of `/dev/sda`
+pub fn is_device_or_partition(source: &str, device: &str) -> bool {
+    let Some(rest) = source.strip_prefix(device) else {
+        return false;
+    };
+    if rest.is_empty() {
+        return true;
+    }
+    let number = if device.ends_with(|c: char| c.is_ascii_digit()) {
+        rest.strip_prefix('p')
+    } else {
+        Some(rest)
+    };
+    number.is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+pub(super) fn mount(device: &str, target: &Path, filesystem: DataFilesystem) -> Result<()> {
+    fs::create_dir_all(target)?;
+
+    let fs_types: &[&str] = match filesystem {
+        // Prefer the in-kernel driver and fall back to FUSE
+        DataFilesystem::Ntfs => &["ntfs3", "ntfs-3g"],
+        DataFilesystem::Fat32 => &["vfat"],
+    };
+
+    let mut last_error = None;
+    for fs_type in fs_types {
+        let mount_path = target.to_string_lossy();
+        match run(
+            Command::new("mount").args(["-t", fs_type, device, mount_path.as_ref()]),
+            "mount",
+        ) {
+            Ok(()) => return Ok(()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(DiskError::WriteFailed(format!(
+        "Failed to mount {}: {}",
+        device,
+        last_error.map(|e| e.to_string()).unwrap_or_default()
+    ))
+    .into())
+}
+
+pub(super) fn umount(target: &Path) -> Result<()> {
+    run(Command::new("umount").arg(target), "umount")
+        .map_err(|e| DiskError::WriteFailed(e.to_string()).into())
+}
+
+fn split_install_wim(source: &Path, target: &Path) -> Result<()> {
+    info!("Splitting install.wim into {} MiB parts", WIM_SPLIT_MB);
+    run(
+        Command::new("wimlib-imagex")
+            .arg("split")
+            .arg(source.join("sources/install.wim"))
+            .arg(target.join("sources/install.swm"))
+            .arg(WIM_SPLIT_MB.to_string()),
+        "wimlib-imagex",
+    )
+    .map_err(|e| DiskError::WriteFailed(e.to_string()).into())
+}
+
+pub(super) fn device_size(device: &str) -> Result<u64> {
+    let output = Command::new("blockdev")
+        .args(["--getsize64", device])
+        .output()?;
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| DiskError::DiskNotFound(device.to_string()).into())
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        total += if meta.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            meta.len()
+        };
+    }
+    Ok(total)
+}
+
+/// Recursively copy `source` into `target`, reporting bytes written.
+/// When `skip_wim` is set, sources/install.wim is left for the splitter.
+fn copy_tree(
+    source: &Path,
+    target: &Path,
+    skip_wim: bool,
+    on_progress: &mut dyn FnMut(u64),
+) -> io::Result<()> {
+    fs::create_dir_all(target)?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let from = entry.path();
+        let to = target.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_tree(&from, &to, skip_wim, on_progress)?;
+            continue;
+        }
+
+        if skip_wim
+            && entry.file_name().eq_ignore_ascii_case("install.wim")
+            && source
+                .file_name()
+                .is_some_and(|d| d.eq_ignore_ascii_case("sources"))
+        {
+            continue;
+        }
+
+        let mut reader = fs::File::open(&from)?;
+        let mut writer = fs::File::create(&to)?;
+        let mut buf = vec![0u8; 4 * 1024 * 1024];
+        loop {
+            let n = io::Read::read(&mut reader, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            io::Write::write_all(&mut writer, &buf[..n])?;
+            on_progress(n as u64);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_partition_path() {
+        assert_eq!(partition_path("/dev/sdb", 1), "/dev/sdb1");
+        assert_eq!(partition_path("/dev/nvme0n1", 2), "/dev/nvme0n1p2");
+        assert_eq!(partition_path("/dev/mmcblk0", 1), "/dev/mmcblk0p1");
+    }
+
+    #[test]
+    fn test_is_device_or_partition() {
+        assert!(is_device_or_partition("/dev/sda", "/dev/sda"));
+        assert!(is_device_or_partition("/dev/sda12", "/dev/sda"));
+        assert!(!is_device_or_partition("/dev/sdab1", "/dev/sda"));
+        assert!(is_device_or_partition("/dev/nvme0n1p2", "/dev/nvme0n1"));
+        assert!(!is_device_or_partition("/dev/nvme0n12", "/dev/nvme0n1"));
+        assert!(!is_device_or_partition("/dev/mmcblk0boot0", "/dev/mmcblk0"));
+        assert!(!is_device_or_partition("tmpfs", "/dev/sda"));
+    }
+
+    #[test]
+    fn test_copy_tree_skips_install_wim() {
+        let source = TempDir::new().unwrap();
+        let target = TempDir::new().unwrap();
+        fs::create_dir_all(source.path().join("sources")).unwrap();
+        fs::write(source.path().join("bootmgr"), b"boot").unwrap();
+        fs::write(source.path().join("sources/install.wim"), b"image").unwrap();
+        fs::write(source.path().join("sources/boot.wim"), b"pe").unwrap();
+
+        let mut copied = 0;
+        copy_tree(source.path(), target.path(), true, &mut |n| copied += n).unwrap();
+
+        assert_eq!(copied, 6);
+        assert!(target.path().join("sources/boot.wim").exists());
+        assert!(!target.path().join("sources/install.wim").exists());
+    }
+
+    #[test]
+    fn test_validate_rejects_non_windows_source() {
+        let source = TempDir::new().unwrap();
+        let params = WindowsUsbParams::new(
+            "/dev/null".to_string(),
+            source.path().to_path_buf(),
+            PathBuf::from("/nonexistent/uefi-ntfs.img"),
+        );
+
+        assert!(WindowsUsbWriter::new().validate(&params).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use serde::Serialize;
+use std::process::Command;
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::info;
+
+/// A destructive step that was rehearsed instead of carried out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedAction {
+    pub description: String,
+    /// Program and arguments; empty for steps done without an external
+    /// command, such as writing an image to a device
+    pub command: Vec<String>,
+}
+
+impl PlannedAction {
+    /// The command as it would be typed in a shell
+    pub fn command_line(&self) -> String {
+        self.command
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Collects the commands and writes destructive operations would perform
+/// when the node runs with `--dry-run`. Components holding one log and
+/// record each step and return without touching the device.
+#[derive(Debug, Clone, Default)]
+pub struct DryRun {
+    actions: Arc<Mutex<Vec<PlannedAction>>>,
+}
+
+impl DryRun {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `cmd` instead of running it
+    pub fn command(&self, description: impl Into<String>, cmd: &Command) {
+        let command = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        self.record(PlannedAction {
+            description: description.into(),
+            command,
+        });
+    }
+
+    /// Record a step that does not run an external command
+    pub fn action(&self, description: impl Into<String>) {
+        self.record(PlannedAction {
+            description: description.into(),
+            command: Vec::new(),
+        });
+    }
+
+    /// Everything recorded so far, oldest first
+    pub fn actions(&self) -> Vec<PlannedAction> {
+        self.actions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn record(&self, action: PlannedAction) {
+        if action.command.is_empty() {
+            info!("[dry-run] {}", action.description);
+        } else {
+            info!("[dry-run] {}: {}", action.description, action.command_line());
+        }
+        self.actions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(action);
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,%+@".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_commands_and_actions() {
+        let dry_run = DryRun::new();
+        let mut cmd = Command::new("mkfs.ext4");
+        cmd.args(["-L", "Data disk", "/dev/sdb2"]);
+        dry_run.command("Format /dev/sdb2 as ext4", &cmd);
+        dry_run.clone().action("Write debian.img (4096 bytes) to /dev/sdb");
+
+        let actions = dry_run.actions();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(
+            actions[0].command,
+            ["mkfs.ext4", "-L", "Data disk", "/dev/sdb2"]
+        );
+        assert_eq!(
+            actions[0].command_line(),
+            "mkfs.ext4 -L 'Data disk' /dev/sdb2"
+        );
+        assert!(actions[1].command.is_empty());
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("2048s"), "2048s");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
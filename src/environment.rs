@@ -0,0 +1,142 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use tracing::debug;
+
+const KERNEL_RELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+
+/// Tools whose version is recorded with every job: name, program, arguments.
+/// The noVNC client is plain files, so its package version is used instead.
+const TRACKED_TOOLS: &[(&str, &str, &[&str])] = &[
+    ("parted", "parted", &["--version"]),
+    ("mkfs.ext4", "mkfs.ext4", &["-V"]),
+    ("mkfs.fat", "mkfs.fat", &["--help"]),
+    ("mkfs.ntfs", "mkfs.ntfs", &["--version"]),
+    ("mkfs.xfs", "mkfs.xfs", &["-V"]),
+    ("mkfs.btrfs", "mkfs.btrfs", &["--version"]),
+    ("mkfs.f2fs", "mkfs.f2fs", &["-V"]),
+    ("x11vnc", "x11vnc", &["-version"]),
+    ("novnc", "dpkg-query", &["-W", "-f", "${Version}", "novnc"]),
+];
+
+/// Software environment a job ran in, so fleet-wide failures can be
+/// correlated with tool or kernel upgrades
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvironmentSnapshot {
+    pub node_version: String,
+    pub kernel: Option<String>,
+    /// Tool name to version; `None` when the tool is not installed or
+    /// reported no recognisable version
+    pub tools: BTreeMap<String, Option<String>>,
+    pub captured_at: SystemTime,
+}
+
+impl EnvironmentSnapshot {
+    /// Query the kernel and tool versions. Runs the tracked tools, so call it
+    /// from a blocking context.
+    pub fn capture() -> Self {
+        let tools = TRACKED_TOOLS
+            .iter()
+            .map(|(name, program, args)| (name.to_string(), tool_version(program, args)))
+            .collect();
+
+        Self {
+            node_version: env!("CARGO_PKG_VERSION").to_string(),
+            kernel: fs::read_to_string(KERNEL_RELEASE_PATH)
+                .ok()
+                .map(|release| release.trim().to_string()),
+            tools,
+            captured_at: SystemTime::now(),
+        }
+    }
+
+    /// Capture on the blocking thread pool
+    pub async fn capture_async() -> Self {
+        tokio::task::spawn_blocking(Self::capture)
+            .await
+            .unwrap_or_else(|_| Self::empty())
+    }
+
+    fn empty() -> Self {
+        Self {
+            node_version: env!("CARGO_PKG_VERSION").to_string(),
+            kernel: None,
+            tools: BTreeMap::new(),
+            captured_at: SystemTime::now(),
+        }
+    }
+}
+
+fn tool_version(program: &str, args: &[&str]) -> Option<String> {
+    let output = match Command::new(program).args(args).output() {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("{} not available: {}", program, e);
+            return None;
+        }
+    };
+
+    // Several mkfs tools print their banner on stderr and exit non-zero
+    // for version or help flags, so only the text is checked
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    parse_version(&text)
+}
+
+/// First dotted version number in tool output, e.g.
+/// `mkfs.btrfs, part of btrfs-progs v6.2` -> `6.2`
+fn parse_version(output: &str) -> Option<String> {
+    static VERSION: OnceLock<Regex> = OnceLock::new();
+    let re = VERSION.get_or_init(|| Regex::new(r"\bv?(\d+(?:\.\d+)+(?:[-+~][\w.+~-]+)?)").unwrap());
+
+    output
+        .lines()
+        .find_map(|line| re.captures(line))
+        .map(|caps| caps[1].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tool_versions() {
+        assert_eq!(
+            parse_version("parted (GNU parted) 3.6\nCopyright (C) 2023"),
+            Some("3.6".to_string())
+        );
+        assert_eq!(
+            parse_version("mke2fs 1.47.0 (5-Feb-2023)\n\tUsing EXT2FS Library version 1.47.0"),
+            Some("1.47.0".to_string())
+        );
+        assert_eq!(
+            parse_version("mkfs.btrfs, part of btrfs-progs v6.2"),
+            Some("6.2".to_string())
+        );
+        assert_eq!(
+            parse_version("x11vnc: 0.9.16 lastmod: 2019-01-05"),
+            Some("0.9.16".to_string())
+        );
+        assert_eq!(
+            parse_version("1.0.0+dfsg1-4"),
+            Some("1.0.0+dfsg1-4".to_string())
+        );
+        assert_eq!(parse_version("usage: websockify [options]"), None);
+    }
+
+    #[test]
+    fn test_missing_tool_has_no_version() {
+        assert_eq!(tool_version("usb-node-no-such-tool", &["--version"]), None);
+
+        let snapshot = EnvironmentSnapshot::capture();
+        assert_eq!(snapshot.node_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(snapshot.tools.len(), TRACKED_TOOLS.len());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::Role;
 use std::fmt;
 use std::io;
 
@@ -20,6 +21,16 @@ pub enum Error {
     Ui(UiError),
     /// Monitoring errors
     Monitoring(MonitoringError),
+    /// REST API errors
+    Api(ApiError),
+    /// Network boot server errors
+    Pxe(PxeError),
+    /// Second-factor authentication errors
+    Auth(AuthError),
+    /// File upload and download errors
+    Transfer(TransferError),
+    /// MQTT fleet client errors
+    Mqtt(MqttError),
     /// I/O errors
     Io(io::Error),
     /// General errors
@@ -54,6 +65,20 @@ pub enum NetworkError {
     StateTransitionError(String),
     /// Link down
     LinkDown(String),
+    /// Provisioning interface could not be isolated
+    IsolationFailed(String),
+    /// Static address could not be applied
+    StaticAddressFailed(String),
+    /// Another host already answers for the static address
+    AddressConflict(String),
+    /// Default route or routing policy for an uplink could not be set
+    RouteFailed(String),
+    /// VLAN or bridge could not be created
+    LinkSetupFailed(String),
+    /// Firewall rules could not be loaded or removed
+    FirewallFailed(String),
+    /// The fleet controller did not accept a heartbeat
+    HeartbeatFailed(String),
 }
 
 #[derive(Debug)]
@@ -70,6 +95,26 @@ pub enum DiskError {
     InsufficientSpace(u64, u64),
     /// Operation not atomic
     NonAtomicOperation(String),
+    /// Writing data to the target failed
+    WriteFailed(String),
+    /// Changing a filesystem label or UUID failed
+    RelabelFailed(String),
+    /// Filesystem or partition resize failed
+    ResizeFailed(String),
+    /// Capturing an image of a device failed
+    ImagingFailed(String),
+    /// Target is an eMMC boot or RPMB area
+    ProtectedArea(String),
+    /// Target holds encrypted data and policy forbids overwriting it
+    EncryptedTarget(String),
+    /// Target could not be checked for encrypted data
+    EncryptionUnknown(String),
+    /// Hashing data for verification failed
+    ChecksumFailed(String),
+    /// Installing a system with debootstrap or pacstrap failed
+    BootstrapFailed(String),
+    /// The node is in safe mode and holds destructive operations
+    SafeMode(String),
 }
 
 #[derive(Debug)]
@@ -86,6 +131,26 @@ pub enum IsoError {
     InstallerNotFound(String),
     /// Installer execution failed
     InstallerFailed(String),
+    /// Windows product key rejected
+    InvalidProductKey(String),
+    /// ISO download failed on every mirror
+    DownloadFailed(String),
+    /// Downloaded file does not match the expected checksum
+    ChecksumMismatch(String),
+    /// Answer file could not be generated or placed
+    UnattendedFailed(String),
+    /// Copying ISOs onto a Ventoy stick failed
+    DeployFailed(String),
+    /// Release feed could not be fetched, verified or read
+    FeedFailed(String),
+    /// Installer exceeded the time allowed for a stage
+    InstallerTimedOut(String),
+    /// Post-install hook failed or timed out
+    HookFailed(String),
+    /// Install job cannot be started, resumed or restarted in its state
+    JobConflict(String),
+    /// Central ISO repository could not be mirrored
+    SyncFailed(String),
 }
 
 #[derive(Debug)]
@@ -104,6 +169,26 @@ pub enum RemoteError {
     KeyGenerationFailed(String),
     /// Certificate error
     CertificateError(String),
+    /// Not a valid `authorized_keys` line
+    InvalidKey(String),
+    /// No authorized key has this fingerprint
+    KeyNotFound(String),
+    /// Serial console error
+    SerialError(String),
+    /// No serial console has this name
+    PortNotFound(String),
+    /// Remote services failed to start
+    StartFailed(String),
+    /// Remote services failed to stop
+    StopFailed(String),
+    /// A running remote service is down
+    HealthCheckFailed(String),
+    /// Not one of `vnc`, `ssh` and `web_vnc`
+    InvalidService(String),
+    /// Service is already running
+    ServiceRunning(String),
+    /// Port zero or one taken by another remote service
+    InvalidPort(u16),
 }
 
 #[derive(Debug)]
@@ -132,6 +217,12 @@ pub enum UiError {
     StateSyncError(String),
     /// GUI crash
     GuiCrash(String),
+    /// Install wizard action not allowed in the current step
+    WrongWizardStep(String),
+    /// Typed confirmation does not match the device name
+    ConfirmationMismatch(String),
+    /// No translations for this language
+    UnsupportedLanguage(String),
 }
 
 #[derive(Debug)]
@@ -144,6 +235,264 @@ pub enum MonitoringError {
     AlertError(String),
     /// Recovery failed
     RecoveryFailed(String),
+    /// No alert with this ID
+    AlertNotFound(String),
+    /// No NTP server or controller gave the time
+    ClockCheckFailed(String),
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// Listener could not be bound
+    BindFailed(String),
+    /// Server stopped unexpectedly
+    ServerFailed(String),
+}
+
+#[derive(Debug)]
+pub enum PxeError {
+    /// ProxyDHCP, TFTP or HTTP socket could not be bound
+    BindFailed(String),
+    /// A boot service stopped unexpectedly
+    ServerFailed(String),
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// Two-factor authentication is turned off
+    Disabled,
+    /// The user has no confirmed TOTP secret
+    NotEnrolled(String),
+    /// The user already has a confirmed TOTP secret
+    AlreadyEnrolled(String),
+    /// Wrong, expired or reused code, or wrong password
+    InvalidCode(String),
+    /// Too many failures; seconds until the user may try again
+    LockedOut(u64),
+    /// Secrets file could not be read or written
+    StoreFailed(String),
+    /// Missing or unknown bearer token
+    Unauthenticated,
+    /// The token's user has enrolled TOTP and must open a session
+    SessionRequired(String),
+    /// The user's role is below the one required
+    Forbidden(String, Role),
+    /// Too many failed sign-ins from this address; seconds until the ban
+    /// is lifted
+    Banned(u64),
+}
+
+#[derive(Debug)]
+pub enum TransferError {
+    /// Not a plain file name, or not an ISO
+    InvalidName(String),
+    /// No such share or file in it
+    NotFound(String),
+    /// Upload would replace a file without `overwrite`
+    AlreadyExists(String),
+    /// Upload is larger than the limit, in MiB
+    TooLarge(u64),
+    /// Reading or writing the file failed
+    IoError(String),
+}
+
+#[derive(Debug)]
+pub enum MqttError {
+    /// Broker could not be reached or the connection dropped
+    ConnectionFailed(String),
+    /// Broker refused the connection with this CONNACK return code
+    Refused(u8),
+    /// Command message that cannot be carried out
+    InvalidCommand(String),
+}
+
+/// Stable message key plus parameters for rendering an error in the UI
+/// language. Free-form details (tool output) stay in `Display` for logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorMessage {
+    pub key: &'static str,
+    pub params: Vec<(&'static str, String)>,
+}
+
+impl ErrorMessage {
+    fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            params: Vec::new(),
+        }
+    }
+
+    fn with(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.params.push((name, value.to_string()));
+        self
+    }
+}
+
+impl Error {
+    /// Message key and parameters for user-facing display
+    pub fn user_message(&self) -> ErrorMessage {
+        match self {
+            Error::Config(e) => match e {
+                ConfigError::MissingField(field) => {
+                    ErrorMessage::new("error.config.missing_field").with("field", field)
+                }
+                _ => ErrorMessage::new("error.config.invalid"),
+            },
+            Error::Network(e) => match e {
+                NetworkError::DhcpFailed(_) => ErrorMessage::new("error.network.dhcp_failed"),
+                NetworkError::InterfaceNotFound(iface) => {
+                    ErrorMessage::new("error.network.interface_not_found").with("interface", iface)
+                }
+                NetworkError::LinkDown(iface) => {
+                    ErrorMessage::new("error.network.link_down").with("interface", iface)
+                }
+                NetworkError::TunnelFailed(_) => ErrorMessage::new("error.network.tunnel_failed"),
+                NetworkError::IsolationFailed(_) => {
+                    ErrorMessage::new("error.network.isolation_failed")
+                }
+                NetworkError::StaticAddressFailed(_) => {
+                    ErrorMessage::new("error.network.static_address_failed")
+                }
+                NetworkError::AddressConflict(address) => {
+                    ErrorMessage::new("error.network.address_conflict").with("address", address)
+                }
+                NetworkError::RouteFailed(_) => ErrorMessage::new("error.network.route_failed"),
+                NetworkError::LinkSetupFailed(_) => {
+                    ErrorMessage::new("error.network.link_setup_failed")
+                }
+                NetworkError::FirewallFailed(_) => {
+                    ErrorMessage::new("error.network.firewall_failed")
+                }
+                _ => ErrorMessage::new("error.network.failed"),
+            },
+            Error::Disk(e) => match e {
+                DiskError::PartitionFailed(_) => ErrorMessage::new("error.disk.partition_failed"),
+                DiskError::FormatFailed(_) => ErrorMessage::new("error.disk.format_failed"),
+                DiskError::DiskNotFound(disk) => {
+                    ErrorMessage::new("error.disk.not_found").with("device", disk)
+                }
+                DiskError::InsufficientSpace(need, have) => {
+                    ErrorMessage::new("error.disk.insufficient_space")
+                        .with("required_mb", need / (1024 * 1024))
+                        .with("available_mb", have / (1024 * 1024))
+                }
+                DiskError::WriteFailed(_) => ErrorMessage::new("error.disk.write_failed"),
+                DiskError::RelabelFailed(_) => ErrorMessage::new("error.disk.relabel_failed"),
+                DiskError::ResizeFailed(_) => ErrorMessage::new("error.disk.resize_failed"),
+                DiskError::ImagingFailed(_) => ErrorMessage::new("error.disk.imaging_failed"),
+                DiskError::ProtectedArea(_) => ErrorMessage::new("error.disk.protected_area"),
+                DiskError::EncryptedTarget(_) => ErrorMessage::new("error.disk.encrypted"),
+                DiskError::EncryptionUnknown(_) => {
+                    ErrorMessage::new("error.disk.encryption_unknown")
+                }
+                DiskError::ChecksumFailed(_) => ErrorMessage::new("error.disk.checksum_failed"),
+                DiskError::BootstrapFailed(_) => ErrorMessage::new("error.disk.bootstrap_failed"),
+                DiskError::SafeMode(_) => ErrorMessage::new("error.disk.safe_mode"),
+                _ => ErrorMessage::new("error.disk.failed"),
+            },
+            Error::Iso(e) => match e {
+                IsoError::NotFound(path) => {
+                    ErrorMessage::new("error.iso.not_found").with("path", path)
+                }
+                IsoError::MountFailed(_) | IsoError::UnmountFailed(_) => {
+                    ErrorMessage::new("error.iso.mount_failed")
+                }
+                IsoError::InvalidFormat(_) => ErrorMessage::new("error.iso.invalid_format"),
+                IsoError::InstallerNotFound(_) => ErrorMessage::new("error.iso.no_installer"),
+                IsoError::InstallerFailed(_) => ErrorMessage::new("error.iso.installer_failed"),
+                IsoError::InvalidProductKey(_) => {
+                    ErrorMessage::new("error.iso.invalid_product_key")
+                }
+                IsoError::DownloadFailed(_) => ErrorMessage::new("error.iso.download_failed"),
+                IsoError::ChecksumMismatch(file) => {
+                    ErrorMessage::new("error.iso.checksum_mismatch").with("file", file)
+                }
+                IsoError::UnattendedFailed(_) => ErrorMessage::new("error.iso.unattended_failed"),
+                IsoError::DeployFailed(_) => ErrorMessage::new("error.iso.deploy_failed"),
+                IsoError::FeedFailed(_) => ErrorMessage::new("error.iso.feed_failed"),
+                IsoError::InstallerTimedOut(_) => ErrorMessage::new("error.iso.installer_timeout"),
+                IsoError::HookFailed(_) => ErrorMessage::new("error.iso.hook_failed"),
+                IsoError::JobConflict(_) => ErrorMessage::new("error.iso.job_conflict"),
+                IsoError::SyncFailed(_) => ErrorMessage::new("error.iso.sync_failed"),
+            },
+            Error::Remote(e) => match e {
+                RemoteError::AuthFailed(_) => ErrorMessage::new("error.remote.auth_failed"),
+                RemoteError::InvalidKey(_) => ErrorMessage::new("error.remote.invalid_key"),
+                RemoteError::KeyNotFound(_) => ErrorMessage::new("error.remote.key_not_found"),
+                RemoteError::PortNotFound(name) => {
+                    ErrorMessage::new("error.remote.port_not_found").with("name", name)
+                }
+                RemoteError::InvalidService(name) => {
+                    ErrorMessage::new("error.remote.invalid_service").with("name", name)
+                }
+                RemoteError::ServiceRunning(name) => {
+                    ErrorMessage::new("error.remote.service_running").with("name", name)
+                }
+                RemoteError::InvalidPort(port) => {
+                    ErrorMessage::new("error.remote.invalid_port").with("port", port)
+                }
+                _ => ErrorMessage::new("error.remote.failed"),
+            },
+            Error::Service(_) => ErrorMessage::new("error.service.failed"),
+            Error::Ui(UiError::WrongWizardStep(_)) => ErrorMessage::new("error.ui.wizard_step"),
+            Error::Ui(UiError::ConfirmationMismatch(name)) => {
+                ErrorMessage::new("error.ui.confirmation_mismatch").with("name", name)
+            }
+            Error::Ui(UiError::UnsupportedLanguage(language)) => {
+                ErrorMessage::new("error.ui.unsupported_language").with("language", language)
+            }
+            Error::Ui(_) => ErrorMessage::new("error.ui.failed"),
+            Error::Monitoring(MonitoringError::AlertNotFound(id)) => {
+                ErrorMessage::new("error.monitoring.alert_not_found").with("id", id)
+            }
+            Error::Monitoring(_) => ErrorMessage::new("error.monitoring.failed"),
+            Error::Api(_) => ErrorMessage::new("error.api.failed"),
+            Error::Pxe(_) => ErrorMessage::new("error.pxe.failed"),
+            Error::Auth(e) => match e {
+                AuthError::InvalidCode(_) => ErrorMessage::new("error.auth.invalid_code"),
+                AuthError::LockedOut(seconds) => {
+                    ErrorMessage::new("error.auth.locked_out").with("seconds", seconds)
+                }
+                AuthError::NotEnrolled(_) => ErrorMessage::new("error.auth.not_enrolled"),
+                AuthError::Unauthenticated | AuthError::SessionRequired(_) => {
+                    ErrorMessage::new("error.auth.unauthenticated")
+                }
+                AuthError::Forbidden(..) => ErrorMessage::new("error.auth.forbidden"),
+                AuthError::Banned(seconds) => {
+                    ErrorMessage::new("error.auth.banned").with("seconds", seconds)
+                }
+                _ => ErrorMessage::new("error.auth.failed"),
+            },
+            Error::Transfer(e) => match e {
+                TransferError::InvalidName(name) => {
+                    ErrorMessage::new("error.transfer.invalid_name").with("name", name)
+                }
+                TransferError::NotFound(name) => {
+                    ErrorMessage::new("error.transfer.not_found").with("name", name)
+                }
+                TransferError::AlreadyExists(name) => {
+                    ErrorMessage::new("error.transfer.exists").with("name", name)
+                }
+                TransferError::TooLarge(limit_mb) => {
+                    ErrorMessage::new("error.transfer.too_large").with("limit_mb", limit_mb)
+                }
+                TransferError::IoError(_) => ErrorMessage::new("error.transfer.failed"),
+            },
+            Error::Mqtt(e) => match e {
+                MqttError::Refused(code) => {
+                    ErrorMessage::new("error.mqtt.refused").with("code", code)
+                }
+                MqttError::InvalidCommand(command) => {
+                    ErrorMessage::new("error.mqtt.invalid_command").with("command", command)
+                }
+                _ => ErrorMessage::new("error.mqtt.connection_failed"),
+            },
+            Error::Io(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                ErrorMessage::new("error.permission_denied")
+            }
+            Error::Io(_) | Error::General(_) => ErrorMessage::new("error.general"),
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -157,6 +506,11 @@ impl fmt::Display for Error {
             Error::Service(e) => write!(f, "Service error: {e}"),
             Error::Ui(e) => write!(f, "UI error: {e}"),
             Error::Monitoring(e) => write!(f, "Monitoring error: {e}"),
+            Error::Api(e) => write!(f, "API error: {e}"),
+            Error::Pxe(e) => write!(f, "Network boot error: {e}"),
+            Error::Auth(e) => write!(f, "Authentication error: {e}"),
+            Error::Transfer(e) => write!(f, "Transfer error: {e}"),
+            Error::Mqtt(e) => write!(f, "MQTT error: {e}"),
             Error::Io(e) => write!(f, "I/O error: {e}"),
             Error::General(msg) => write!(f, "General error: {msg}"),
         }
@@ -184,6 +538,17 @@ impl fmt::Display for NetworkError {
             NetworkError::InterfaceNotFound(iface) => write!(f, "Interface not found: {iface}"),
             NetworkError::StateTransitionError(msg) => write!(f, "State transition error: {msg}"),
             NetworkError::LinkDown(iface) => write!(f, "Link down: {iface}"),
+            NetworkError::IsolationFailed(msg) => write!(f, "Network isolation failed: {msg}"),
+            NetworkError::StaticAddressFailed(msg) => {
+                write!(f, "Static address configuration failed: {msg}")
+            }
+            NetworkError::AddressConflict(address) => {
+                write!(f, "Address {address} is already in use")
+            }
+            NetworkError::RouteFailed(msg) => write!(f, "Route configuration failed: {msg}"),
+            NetworkError::LinkSetupFailed(msg) => write!(f, "VLAN or bridge setup failed: {msg}"),
+            NetworkError::FirewallFailed(msg) => write!(f, "Firewall setup failed: {msg}"),
+            NetworkError::HeartbeatFailed(msg) => write!(f, "Heartbeat not delivered: {msg}"),
         }
     }
 }
@@ -202,6 +567,20 @@ impl fmt::Display for DiskError {
                 )
             }
             DiskError::NonAtomicOperation(msg) => write!(f, "Non-atomic operation: {msg}"),
+            DiskError::WriteFailed(msg) => write!(f, "Write failed: {msg}"),
+            DiskError::RelabelFailed(msg) => write!(f, "Relabel failed: {msg}"),
+            DiskError::ResizeFailed(msg) => write!(f, "Resize failed: {msg}"),
+            DiskError::ImagingFailed(msg) => write!(f, "Imaging failed: {msg}"),
+            DiskError::ProtectedArea(msg) => write!(f, "Protected area: {msg}"),
+            DiskError::EncryptedTarget(msg) => {
+                write!(f, "Target holds encrypted data: {msg}")
+            }
+            DiskError::EncryptionUnknown(msg) => {
+                write!(f, "Cannot check target for encrypted data: {msg}")
+            }
+            DiskError::ChecksumFailed(msg) => write!(f, "Checksum failed: {msg}"),
+            DiskError::BootstrapFailed(msg) => write!(f, "Bootstrap failed: {msg}"),
+            DiskError::SafeMode(reason) => write!(f, "Node is in safe mode: {reason}"),
         }
     }
 }
@@ -215,6 +594,16 @@ impl fmt::Display for IsoError {
             IsoError::InvalidFormat(msg) => write!(f, "Invalid ISO format: {msg}"),
             IsoError::InstallerNotFound(msg) => write!(f, "Installer not found: {msg}"),
             IsoError::InstallerFailed(msg) => write!(f, "Installer failed: {msg}"),
+            IsoError::InvalidProductKey(msg) => write!(f, "Invalid product key: {msg}"),
+            IsoError::DownloadFailed(msg) => write!(f, "Download failed: {msg}"),
+            IsoError::ChecksumMismatch(msg) => write!(f, "Checksum mismatch: {msg}"),
+            IsoError::UnattendedFailed(msg) => write!(f, "Unattended setup failed: {msg}"),
+            IsoError::DeployFailed(msg) => write!(f, "Ventoy deployment failed: {msg}"),
+            IsoError::FeedFailed(msg) => write!(f, "Release feed failed: {msg}"),
+            IsoError::InstallerTimedOut(msg) => write!(f, "Installer timed out: {msg}"),
+            IsoError::HookFailed(msg) => write!(f, "Post-install hook failed: {msg}"),
+            IsoError::JobConflict(msg) => write!(f, "Install job conflict: {msg}"),
+            IsoError::SyncFailed(msg) => write!(f, "Repository sync failed: {msg}"),
         }
     }
 }
@@ -229,6 +618,16 @@ impl fmt::Display for RemoteError {
             RemoteError::ProcessFailed(msg) => write!(f, "Process failed: {msg}"),
             RemoteError::KeyGenerationFailed(msg) => write!(f, "Key generation failed: {msg}"),
             RemoteError::CertificateError(msg) => write!(f, "Certificate error: {msg}"),
+            RemoteError::InvalidKey(msg) => write!(f, "Invalid SSH public key: {msg}"),
+            RemoteError::KeyNotFound(msg) => write!(f, "SSH key not found: {msg}"),
+            RemoteError::SerialError(msg) => write!(f, "Serial console error: {msg}"),
+            RemoteError::PortNotFound(name) => write!(f, "No serial console named {name}"),
+            RemoteError::StartFailed(msg) => write!(f, "Start failed: {msg}"),
+            RemoteError::StopFailed(msg) => write!(f, "Stop failed: {msg}"),
+            RemoteError::HealthCheckFailed(msg) => write!(f, "Health check failed: {msg}"),
+            RemoteError::InvalidService(name) => write!(f, "No remote service named {name}"),
+            RemoteError::ServiceRunning(name) => write!(f, "{name} is already running"),
+            RemoteError::InvalidPort(port) => write!(f, "Port {port} cannot be used"),
         }
     }
 }
@@ -255,6 +654,13 @@ impl fmt::Display for UiError {
             UiError::InputError(msg) => write!(f, "Input error: {msg}"),
             UiError::StateSyncError(msg) => write!(f, "State sync error: {msg}"),
             UiError::GuiCrash(msg) => write!(f, "GUI crash: {msg}"),
+            UiError::WrongWizardStep(step) => write!(f, "Not allowed in wizard step {step}"),
+            UiError::ConfirmationMismatch(name) => {
+                write!(f, "Confirmation does not match {name}")
+            }
+            UiError::UnsupportedLanguage(language) => {
+                write!(f, "Unsupported language: {language}")
+            }
         }
     }
 }
@@ -266,6 +672,75 @@ impl fmt::Display for MonitoringError {
             MonitoringError::MetricsError(msg) => write!(f, "Metrics error: {msg}"),
             MonitoringError::AlertError(msg) => write!(f, "Alert error: {msg}"),
             MonitoringError::RecoveryFailed(msg) => write!(f, "Recovery failed: {msg}"),
+            MonitoringError::AlertNotFound(id) => write!(f, "Alert not found: {id}"),
+            MonitoringError::ClockCheckFailed(msg) => write!(f, "Clock check failed: {msg}"),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BindFailed(msg) => write!(f, "Failed to bind listener: {msg}"),
+            ApiError::ServerFailed(msg) => write!(f, "Server failed: {msg}"),
+        }
+    }
+}
+
+impl fmt::Display for PxeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PxeError::BindFailed(msg) => write!(f, "Failed to bind boot service: {msg}"),
+            PxeError::ServerFailed(msg) => write!(f, "Boot service failed: {msg}"),
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Disabled => write!(f, "Two-factor authentication is disabled"),
+            AuthError::NotEnrolled(user) => write!(f, "{user} has not enrolled a TOTP secret"),
+            AuthError::AlreadyEnrolled(user) => {
+                write!(f, "{user} already has a TOTP secret")
+            }
+            AuthError::InvalidCode(user) => write!(f, "Invalid credentials for {user}"),
+            AuthError::LockedOut(seconds) => {
+                write!(f, "Too many failed attempts, locked for {seconds}s")
+            }
+            AuthError::StoreFailed(msg) => write!(f, "TOTP secrets unavailable: {msg}"),
+            AuthError::Unauthenticated => write!(f, "Missing or invalid token"),
+            AuthError::SessionRequired(user) => {
+                write!(f, "{user} must open a session with a one-time code")
+            }
+            AuthError::Forbidden(user, role) => write!(f, "{user} lacks the {role:?} role"),
+            AuthError::Banned(seconds) => {
+                write!(f, "Address banned after failed sign-ins, {seconds}s left")
+            }
+        }
+    }
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::InvalidName(name) => write!(f, "Invalid file name: {name}"),
+            TransferError::NotFound(name) => write!(f, "File not found: {name}"),
+            TransferError::AlreadyExists(name) => write!(f, "{name} already exists"),
+            TransferError::TooLarge(limit_mb) => {
+                write!(f, "Upload exceeds the limit of {limit_mb} MiB")
+            }
+            TransferError::IoError(msg) => write!(f, "I/O error: {msg}"),
+        }
+    }
+}
+
+impl fmt::Display for MqttError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MqttError::ConnectionFailed(msg) => write!(f, "Connection failed: {msg}"),
+            MqttError::Refused(code) => write!(f, "Broker refused the connection (code {code})"),
+            MqttError::InvalidCommand(msg) => write!(f, "Invalid command: {msg}"),
         }
     }
 }
@@ -287,6 +762,11 @@ impl std::error::Error for RemoteError {}
 impl std::error::Error for ServiceError {}
 impl std::error::Error for UiError {}
 impl std::error::Error for MonitoringError {}
+impl std::error::Error for ApiError {}
+impl std::error::Error for PxeError {}
+impl std::error::Error for AuthError {}
+impl std::error::Error for TransferError {}
+impl std::error::Error for MqttError {}
 
 // From implementations
 impl From<io::Error> for Error {
@@ -343,4 +823,64 @@ impl From<MonitoringError> for Error {
     }
 }
 
+impl From<ApiError> for Error {
+    fn from(err: ApiError) -> Self {
+        Error::Api(err)
+    }
+}
+
+impl From<PxeError> for Error {
+    fn from(err: PxeError) -> Self {
+        Error::Pxe(err)
+    }
+}
+
+impl From<AuthError> for Error {
+    fn from(err: AuthError) -> Self {
+        Error::Auth(err)
+    }
+}
+
+impl From<TransferError> for Error {
+    fn from(err: TransferError) -> Self {
+        Error::Transfer(err)
+    }
+}
+
+impl From<MqttError> for Error {
+    fn from(err: MqttError) -> Self {
+        Error::Mqtt(err)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_message_omits_raw_detail() {
+        let err: Error =
+            DiskError::FormatFailed("/dev/sdb1: mkfs.ntfs: bad sector".to_string()).into();
+        let message = err.user_message();
+        assert_eq!(message.key, "error.disk.format_failed");
+        assert!(message.params.is_empty());
+        assert!(err.to_string().contains("bad sector"));
+    }
+
+    #[test]
+    fn test_user_message_params() {
+        let err: Error =
+            DiskError::InsufficientSpace(8 * 1024 * 1024 * 1024, 4 * 1024 * 1024 * 1024).into();
+        let message = err.user_message();
+        assert_eq!(message.key, "error.disk.insufficient_space");
+        assert_eq!(
+            message.params,
+            vec![
+                ("required_mb", "8192".to_string()),
+                ("available_mb", "4096".to_string())
+            ]
+        );
+    }
+}
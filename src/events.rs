@@ -0,0 +1,387 @@
+use crate::auth::bans::Ban;
+use crate::disk::inventory::DiskInventory;
+use crate::disk::{DiskManager, DiskProgress};
+use crate::iso::catalog::{CatalogQuery, IsoCatalogEntry};
+use crate::iso::downloader::DownloadProgress;
+use crate::iso::IsoManager;
+use crate::job::install::{InstallJob, InstallJobRunner};
+use crate::monitoring::{Alert, SafeMode};
+use crate::network::NetworkState;
+use crate::remote::{RemoteManagerState, RemoteServiceStatus};
+use crate::service::startup::SubsystemStatus;
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::debug;
+
+/// Events buffered per subscriber before the slowest one misses some
+const BUS_CAPACITY: usize = 256;
+/// How often `/sys/block` and the ISO catalog are checked for additions
+/// while someone is subscribed
+const INVENTORY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Something that happened on the node. Subsystems publish these on the
+/// [`EventBus`]; the UI, alerting, MQTT and event stream clients subscribe.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AppEvent {
+    /// A subsystem started, degraded, failed or was skipped
+    Subsystem(SubsystemStatus),
+    /// An install job was queued or changed step, status or priority
+    InstallJob(InstallJob),
+    DiskProgress(DiskProgress),
+    DownloadProgress(DownloadProgress),
+    /// An alert was raised or resolved
+    Alert(Alert),
+    Network {
+        state: NetworkState,
+    },
+    /// VNC, SSH and WebVNC as a whole
+    Remote {
+        state: RemoteManagerState,
+    },
+    /// One remote service was started or stopped on its own
+    RemoteService(RemoteServiceStatus),
+    /// An address was banned after failed sign-ins
+    Banned(Ban),
+    /// A ban was lifted by an administrator before it expired
+    BanLifted {
+        address: IpAddr,
+    },
+    DeviceAdded(DiskInventory),
+    DeviceRemoved {
+        name: String,
+    },
+    /// An ISO was catalogued after a scan, download or upload
+    IsoDiscovered(IsoCatalogEntry),
+    /// Safe mode was entered, or left when `safe_mode` is `None`
+    SafeMode {
+        safe_mode: Option<SafeMode>,
+    },
+    /// Sent to an event stream client that fell behind and missed
+    /// `skipped` events; it should refetch state from the REST API
+    Lagged {
+        skipped: u64,
+    },
+}
+
+/// Central fan-out of node events between subsystems
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<AppEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(BUS_CAPACITY);
+        Self { tx }
+    }
+
+    pub fn publish(&self, event: AppEvent) {
+        // No subscribers is not an error
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AppEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Publish disk and download progress, whose writers report on their
+    /// manager's own channel, install job changes, and devices and ISOs as
+    /// they come and go
+    pub fn forward_from(
+        &self,
+        disk_manager: Arc<DiskManager>,
+        iso_manager: Arc<IsoManager>,
+        install_jobs: InstallJobRunner,
+    ) {
+        self.relay(disk_manager.subscribe_progress(), AppEvent::DiskProgress);
+        self.relay(
+            iso_manager.subscribe_downloads(),
+            AppEvent::DownloadProgress,
+        );
+        tokio::spawn(forward_jobs(self.clone(), install_jobs));
+        tokio::spawn(watch_inventory(self.clone(), disk_manager, iso_manager));
+    }
+
+    fn relay<T>(&self, mut rx: broadcast::Receiver<T>, event: fn(T) -> AppEvent)
+    where
+        T: Clone + Send + 'static,
+    {
+        let bus = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(item) => bus.publish(event(item)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Send events to a WebSocket client as JSON text messages until it goes
+/// away. Messages from the client are ignored.
+pub async fn stream(socket: WebSocket, mut events: broadcast::Receiver<AppEvent>) {
+    let (mut sink, mut incoming) = socket.split();
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => AppEvent::Lagged { skipped },
+                    Err(RecvError::Closed) => break,
+                };
+                let text = match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        debug!("Failed to encode event: {}", e);
+                        continue;
+                    }
+                };
+                if sink.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = incoming.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = sink.close().await;
+}
+
+/// Publish queued jobs and their transitions. Jobs that leave the queue
+/// are published once more in their final state from the store.
+async fn forward_jobs(bus: EventBus, install_jobs: InstallJobRunner) {
+    let mut queue = install_jobs.subscribe_queue();
+    let mut previous = queue.borrow_and_update().clone();
+    while queue.changed().await.is_ok() {
+        let current = queue.borrow_and_update().clone();
+        let (changed, departed) = queue_changes(&previous, &current);
+        for job in changed {
+            bus.publish(AppEvent::InstallJob(job.clone()));
+        }
+        for id in departed {
+            match install_jobs.store().load(&id).await {
+                Ok(job) => bus.publish(AppEvent::InstallJob(job)),
+                Err(e) => debug!("Failed to load install job {}: {}", id, e),
+            }
+        }
+        previous = current;
+    }
+}
+
+/// Jobs that are new in `current` or moved on since `previous`, and the
+/// ids of those no longer queued
+fn queue_changes<'a>(
+    previous: &[InstallJob],
+    current: &'a [InstallJob],
+) -> (Vec<&'a InstallJob>, Vec<String>) {
+    let key = |job: &InstallJob| (job.status, job.step, job.priority, job.updated_at);
+    let before: HashMap<&str, _> = previous
+        .iter()
+        .map(|job| (job.id.as_str(), key(job)))
+        .collect();
+    let changed = current
+        .iter()
+        .filter(|job| before.get(job.id.as_str()) != Some(&key(job)))
+        .collect();
+    let departed = previous
+        .iter()
+        .filter(|job| !current.iter().any(|queued| queued.id == job.id))
+        .map(|job| job.id.clone())
+        .collect();
+    (changed, departed)
+}
+
+/// Report disks and ISOs that appear or disappear. Only block device names
+/// are read on every tick; the full inventory is scanned when they change.
+/// With nobody subscribed nothing is checked, and the next subscriber
+/// starts from what is there then.
+async fn watch_inventory(
+    bus: EventBus,
+    disk_manager: Arc<DiskManager>,
+    iso_manager: Arc<IsoManager>,
+) {
+    let mut block_devices = HashSet::new();
+    let mut disks: Option<HashSet<String>> = None;
+    let mut isos: Option<HashSet<PathBuf>> = None;
+    let mut interval = tokio::time::interval(INVENTORY_INTERVAL);
+    loop {
+        interval.tick().await;
+        if bus.tx.receiver_count() == 0 {
+            disks = None;
+            isos = None;
+            continue;
+        }
+
+        let devices = block_device_names().await;
+        if disks.is_none() || devices != block_devices {
+            match disk_manager.list_disks().await {
+                Ok(current) => {
+                    if let Some(known) = &disks {
+                        for event in device_changes(known, &current) {
+                            bus.publish(event);
+                        }
+                    }
+                    disks = Some(current.into_iter().map(|disk| disk.name).collect());
+                    block_devices = devices;
+                }
+                Err(e) => debug!("Failed to list disks for events: {}", e),
+            }
+        }
+
+        let catalog = iso_manager.get_catalog(&CatalogQuery::default()).await;
+        if let Some(known) = &isos {
+            for entry in catalog.iter().filter(|entry| !known.contains(&entry.path)) {
+                bus.publish(AppEvent::IsoDiscovered(entry.clone()));
+            }
+        }
+        isos = Some(catalog.into_iter().map(|entry| entry.path).collect());
+    }
+}
+
+fn device_changes(known: &HashSet<String>, current: &[DiskInventory]) -> Vec<AppEvent> {
+    let mut events: Vec<AppEvent> = current
+        .iter()
+        .filter(|disk| !known.contains(&disk.name))
+        .map(|disk| AppEvent::DeviceAdded(disk.clone()))
+        .collect();
+    let present: HashSet<&str> = current.iter().map(|disk| disk.name.as_str()).collect();
+    let mut removed: Vec<&String> = known
+        .iter()
+        .filter(|name| !present.contains(name.as_str()))
+        .collect();
+    removed.sort();
+    events.extend(
+        removed
+            .into_iter()
+            .map(|name| AppEvent::DeviceRemoved { name: name.clone() }),
+    );
+    events
+}
+
+async fn block_device_names() -> HashSet<String> {
+    let mut names = HashSet::new();
+    let Ok(mut entries) = tokio::fs::read_dir("/sys/block").await else {
+        return names;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        names.insert(entry.file_name().to_string_lossy().to_string());
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::inventory::DiskKind;
+    use crate::job::install::InstallJobStatus;
+    use crate::service::startup::StartOutcome;
+
+    fn disk(name: &str) -> DiskInventory {
+        DiskInventory {
+            name: name.to_string(),
+            path: format!("/dev/{}", name),
+            kind: DiskKind::Standard,
+            model: None,
+            serial: None,
+            size_bytes: 16_000_000_000,
+            transport: Some("usb".to_string()),
+            removable: true,
+            rotational: false,
+            partitions: Vec::new(),
+            mounted: false,
+            smart: None,
+            special_areas: Vec::new(),
+            encrypted: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = AppEvent::Subsystem(SubsystemStatus {
+            name: "api",
+            outcome: StartOutcome::Ready { took_ms: 12 },
+        });
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"type": "subsystem", "name": "api", "state": "ready", "took_ms": 12})
+        );
+        let event = AppEvent::DeviceRemoved {
+            name: "sdb".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"type": "device_removed", "name": "sdb"})
+        );
+        let value = serde_json::to_value(AppEvent::DeviceAdded(disk("sdc"))).unwrap();
+        assert_eq!(value["type"], "device_added");
+        assert_eq!(value["path"], "/dev/sdc");
+    }
+
+    #[test]
+    fn test_queue_changes() {
+        let queued = InstallJob::new("/isos/a.iso", "/dev/sdb", false);
+        let mut running = InstallJob::new("/isos/b.iso", "/dev/sdc", false);
+
+        let previous = vec![queued.clone(), running.clone()];
+        running.status = InstallJobStatus::Running;
+        let new = InstallJob::new("/isos/c.iso", "/dev/sdd", false);
+        let current = vec![running.clone(), new.clone()];
+
+        let (changed, departed) = queue_changes(&previous, &current);
+        let changed: Vec<&str> = changed.iter().map(|job| job.id.as_str()).collect();
+        assert_eq!(changed, [running.id.as_str(), new.id.as_str()]);
+        assert_eq!(departed, [queued.id.clone()]);
+
+        let (changed, departed) = queue_changes(&current, &current);
+        assert!(changed.is_empty() && departed.is_empty());
+    }
+
+    #[test]
+    fn test_device_changes() {
+        let known: HashSet<String> = ["sda".to_string(), "sdb".to_string()].into();
+        let events = device_changes(&known, &[disk("sda"), disk("sdc")]);
+        let events: Vec<serde_json::Value> = events
+            .iter()
+            .map(|event| serde_json::to_value(event).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["type"], "device_added");
+        assert_eq!(events[0]["name"], "sdc");
+        assert_eq!(events[1]["type"], "device_removed");
+        assert_eq!(events[1]["name"], "sdb");
+    }
+
+    #[tokio::test]
+    async fn test_bus_fan_out() {
+        let bus = EventBus::new();
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+        bus.publish(AppEvent::DeviceRemoved {
+            name: "sdb".to_string(),
+        });
+        for rx in [&mut first, &mut second] {
+            assert!(matches!(
+                rx.recv().await.unwrap(),
+                AppEvent::DeviceRemoved { name } if name == "sdb"
+            ));
+        }
+    }
+}
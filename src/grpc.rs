@@ -0,0 +1,689 @@
+use crate::api::{ApiContext, ApiFailure};
+use crate::audit::{AuditAction, AuditSource};
+use crate::auth::bans::BanSource;
+use crate::config::Role;
+use crate::disk::inventory::{DiskInventory, DiskKind};
+use crate::disk::{DiskOperation, DiskProgress};
+use crate::error::{ApiError, AuthError, Error, Result};
+use crate::iso::catalog::{Arch, CatalogQuery, IsoCatalogEntry};
+use crate::job::install::{InstallJob, InstallJobStatus, InstallStep};
+use axum::http::StatusCode;
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Code, Request, Response, Status};
+use tracing::{debug, error, info, warn};
+
+pub mod proto {
+    tonic::include_proto!("usb_installer.v1");
+}
+
+use proto::installer_control_server::{InstallerControl, InstallerControlServer};
+
+/// Updates buffered per watcher before the sender waits for the client
+const WATCH_BUFFER: usize = 16;
+
+/// Typed control service for provisioning pipelines (`[grpc]` section,
+/// `grpc` cargo feature)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GrpcConfig {
+    pub enabled: bool,
+    pub bind_address: String,
+    pub port: u16,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_address: "::".to_string(),
+            port: 50051,
+        }
+    }
+}
+
+/// gRPC counterpart of the REST API's disk, ISO and install endpoints,
+/// sharing its tokens, roles and ban list
+pub struct GrpcServer {
+    config: GrpcConfig,
+    context: ApiContext,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl GrpcServer {
+    pub fn new(config: GrpcConfig, context: ApiContext) -> Self {
+        Self {
+            config,
+            context,
+            shutdown_tx: None,
+        }
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        if !self.config.enabled {
+            info!("gRPC control service disabled");
+            return Ok(());
+        }
+
+        let ip: IpAddr = self
+            .config
+            .bind_address
+            .parse()
+            .map_err(|e| ApiError::BindFailed(format!("Invalid bind address: {}", e)))?;
+        let addr = SocketAddr::new(ip, self.config.port);
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            // `::` also accepts IPv4; fall back where IPv6 is disabled
+            Err(e) if ip == IpAddr::V6(Ipv6Addr::UNSPECIFIED) => {
+                warn!("Cannot listen on {} ({}), using IPv4 only", addr, e);
+                let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), self.config.port);
+                tokio::net::TcpListener::bind(addr)
+                    .await
+                    .map_err(|e| ApiError::BindFailed(format!("{}: {}", addr, e)))?
+            }
+            Err(e) => return Err(ApiError::BindFailed(format!("{}: {}", addr, e)).into()),
+        };
+        let incoming = TcpIncoming::from_listener(listener, true, None)
+            .map_err(|e| ApiError::BindFailed(format!("{}: {}", addr, e)))?;
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let service = InstallerControlServer::new(ControlService {
+            ctx: self.context.clone(),
+        });
+        tokio::spawn(async move {
+            let result = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming_shutdown(incoming, async {
+                    let _ = shutdown_rx.await;
+                })
+                .await;
+            if let Err(e) = result {
+                error!("gRPC control service failed: {}", e);
+            }
+        });
+
+        self.shutdown_tx = Some(shutdown_tx);
+        info!("gRPC control service listening on {}", addr);
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        if let Some(tx) = self.shutdown_tx.take() {
+            info!("Stopping gRPC control service");
+            let _ = tx.send(());
+        }
+        Ok(())
+    }
+}
+
+type WatchStream<T> = Pin<Box<dyn Stream<Item = std::result::Result<T, Status>> + Send>>;
+
+struct ControlService {
+    ctx: ApiContext,
+}
+
+/// Who made a call, for the audit log
+struct Caller {
+    actor: String,
+    address: Option<IpAddr>,
+    method: &'static str,
+    /// Viewer calls, recorded only with `record_reads`
+    read: bool,
+}
+
+impl ControlService {
+    /// Check the bearer token like the REST API does, counting wrong
+    /// tokens towards a ban of the caller. Refused calls are audited here,
+    /// the others by the handler through [`Self::record`].
+    async fn authorize<T>(
+        &self,
+        request: &Request<T>,
+        method: &'static str,
+        role: Role,
+    ) -> std::result::Result<Caller, Status> {
+        let token = bearer_token(request.metadata());
+        let mut caller = Caller {
+            actor: String::new(),
+            address: request.remote_addr().map(|addr| addr.ip()),
+            method,
+            read: role == Role::Viewer,
+        };
+        let result = match caller.address.map(|peer| self.ctx.bans.check(peer)) {
+            Some(Err(e)) => Err(e),
+            _ => self.ctx.auth.authorize(token, role).await,
+        };
+        match result {
+            Ok(principal) => {
+                caller.actor = principal.name;
+                Ok(caller)
+            }
+            Err(e) => {
+                // No token at all is a missing login, not a guess
+                if let (Some(peer), Error::Auth(AuthError::Unauthenticated)) = (caller.address, &e)
+                {
+                    if !token.is_empty() {
+                        self.ctx.bans.record_failure(peer, BanSource::Api).await;
+                    }
+                }
+                caller.actor = self
+                    .ctx
+                    .auth
+                    .identify(token)
+                    .await
+                    .unwrap_or_else(|| "anonymous".to_string());
+                let result = Err(status(e));
+                self.record(&caller, None, &result).await;
+                result
+            }
+        }
+    }
+
+    /// Record a call and how it ended, like the REST API records requests
+    async fn record<R>(
+        &self,
+        caller: &Caller,
+        target: Option<&str>,
+        result: &std::result::Result<R, Status>,
+    ) {
+        let audit = &self.ctx.audit;
+        if !audit.is_enabled() || (caller.read && !audit.records_reads()) {
+            return;
+        }
+        let mut action = AuditAction::new(AuditSource::Grpc, &caller.actor, caller.method)
+            .with_address(caller.address);
+        if let Some(target) = target {
+            action = action.with_target(target);
+        }
+        if let Err(e) = result {
+            action = action.with_outcome(format!("failed: {:?}", e.code()));
+        }
+        audit.record(action).await;
+    }
+
+    async fn catalog(&self, query: &CatalogQuery) -> proto::ListIsosResponse {
+        let entries = self.ctx.iso_manager.get_catalog(query).await;
+        proto::ListIsosResponse {
+            isos: entries.iter().map(proto::Iso::from).collect(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl InstallerControl for ControlService {
+    type WatchInstallJobStream = WatchStream<proto::InstallJob>;
+    type WatchDiskProgressStream = WatchStream<proto::DiskProgress>;
+
+    async fn list_disks(
+        &self,
+        request: Request<proto::ListDisksRequest>,
+    ) -> std::result::Result<Response<proto::ListDisksResponse>, Status> {
+        let caller = self.authorize(&request, "ListDisks", Role::Viewer).await?;
+        let result = self.ctx.disk_manager.list_disks().await.map_err(status);
+        self.record(&caller, None, &result).await;
+        Ok(Response::new(proto::ListDisksResponse {
+            disks: result?.iter().map(proto::Disk::from).collect(),
+        }))
+    }
+
+    async fn get_disk(
+        &self,
+        request: Request<proto::GetDiskRequest>,
+    ) -> std::result::Result<Response<proto::Disk>, Status> {
+        let caller = self.authorize(&request, "GetDisk", Role::Viewer).await?;
+        let name = &request.get_ref().name;
+        let result = self
+            .ctx
+            .disk_manager
+            .get_disk_inventory(name)
+            .await
+            .map_err(status);
+        self.record(&caller, Some(name.as_str()), &result).await;
+        Ok(Response::new(proto::Disk::from(&result?)))
+    }
+
+    async fn list_isos(
+        &self,
+        request: Request<proto::ListIsosRequest>,
+    ) -> std::result::Result<Response<proto::ListIsosResponse>, Status> {
+        let caller = self.authorize(&request, "ListIsos", Role::Viewer).await?;
+        let result = catalog_query(request.into_inner());
+        self.record(&caller, None, &result).await;
+        Ok(Response::new(self.catalog(&result?).await))
+    }
+
+    async fn rescan_isos(
+        &self,
+        request: Request<proto::RescanIsosRequest>,
+    ) -> std::result::Result<Response<proto::ListIsosResponse>, Status> {
+        let caller = self
+            .authorize(&request, "RescanIsos", Role::Operator)
+            .await?;
+        let result = self.ctx.iso_manager.rescan().await.map_err(status);
+        self.record(&caller, None, &result).await;
+        result?;
+        Ok(Response::new(self.catalog(&CatalogQuery::default()).await))
+    }
+
+    async fn start_install(
+        &self,
+        request: Request<proto::StartInstallRequest>,
+    ) -> std::result::Result<Response<proto::InstallJob>, Status> {
+        let caller = self
+            .authorize(&request, "StartInstall", Role::Operator)
+            .await?;
+        let request = request.into_inner();
+        let result = async {
+            let entry = self.ctx.iso_manager.get_catalog_entry(&request.iso).await?;
+            let disk = self
+                .ctx
+                .disk_manager
+                .get_disk_inventory(&request.device)
+                .await?;
+            let mut job = InstallJob::new(entry.path, &disk.path, request.auto_mode);
+            job.installer = request.installer.clone();
+            self.ctx.install_jobs.enqueue(job).await
+        }
+        .await
+        .map_err(status);
+        self.record(&caller, Some(request.device.as_str()), &result)
+            .await;
+        Ok(Response::new(proto::InstallJob::from(&result?)))
+    }
+
+    async fn get_install_job(
+        &self,
+        request: Request<proto::GetInstallJobRequest>,
+    ) -> std::result::Result<Response<proto::InstallJob>, Status> {
+        let caller = self
+            .authorize(&request, "GetInstallJob", Role::Viewer)
+            .await?;
+        let id = &request.get_ref().id;
+        let result = self.ctx.install_jobs.store().load(id).await.map_err(status);
+        self.record(&caller, Some(id.as_str()), &result).await;
+        Ok(Response::new(proto::InstallJob::from(&result?)))
+    }
+
+    async fn list_install_jobs(
+        &self,
+        request: Request<proto::ListInstallJobsRequest>,
+    ) -> std::result::Result<Response<proto::ListInstallJobsResponse>, Status> {
+        let caller = self
+            .authorize(&request, "ListInstallJobs", Role::Viewer)
+            .await?;
+        let result = self.ctx.install_jobs.store().list().await.map_err(status);
+        self.record(&caller, None, &result).await;
+        Ok(Response::new(proto::ListInstallJobsResponse {
+            jobs: result?.iter().map(proto::InstallJob::from).collect(),
+        }))
+    }
+
+    async fn cancel_install_job(
+        &self,
+        request: Request<proto::CancelInstallJobRequest>,
+    ) -> std::result::Result<Response<proto::InstallJob>, Status> {
+        let caller = self
+            .authorize(&request, "CancelInstallJob", Role::Operator)
+            .await?;
+        let id = &request.get_ref().id;
+        let result = self.ctx.install_jobs.cancel(id).await.map_err(status);
+        self.record(&caller, Some(id.as_str()), &result).await;
+        Ok(Response::new(proto::InstallJob::from(&result?)))
+    }
+
+    async fn watch_install_job(
+        &self,
+        request: Request<proto::WatchInstallJobRequest>,
+    ) -> std::result::Result<Response<Self::WatchInstallJobStream>, Status> {
+        let caller = self
+            .authorize(&request, "WatchInstallJob", Role::Viewer)
+            .await?;
+        let id = request.into_inner().id;
+        let jobs = self.ctx.install_jobs.clone();
+        // Unknown jobs fail the call rather than end an empty stream
+        let result = jobs.store().load(&id).await.map_err(status);
+        self.record(&caller, Some(id.as_str()), &result).await;
+        result?;
+
+        let mut queue = jobs.subscribe_queue();
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let queued = queue
+                    .borrow_and_update()
+                    .iter()
+                    .find(|job| job.id == id)
+                    .cloned();
+                // The queue only holds running and waiting jobs; once the
+                // job leaves it, its final state is in the store
+                let (job, finished) = match queued {
+                    Some(job) => (job, false),
+                    None => match jobs.store().load(&id).await {
+                        Ok(job) => (job, true),
+                        Err(e) => {
+                            let _ = tx.send(Err(status(e))).await;
+                            return;
+                        }
+                    },
+                };
+                let job = proto::InstallJob::from(&job);
+                if last.as_ref() != Some(&job) {
+                    if tx.send(Ok(job.clone())).await.is_err() {
+                        return;
+                    }
+                    last = Some(job);
+                }
+                if finished {
+                    return;
+                }
+                tokio::select! {
+                    changed = queue.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                    _ = tx.closed() => return,
+                }
+            }
+        });
+
+        let updates = futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|update| (update, rx))
+        });
+        Ok(Response::new(Box::pin(updates)))
+    }
+
+    async fn watch_disk_progress(
+        &self,
+        request: Request<proto::WatchDiskProgressRequest>,
+    ) -> std::result::Result<Response<Self::WatchDiskProgressStream>, Status> {
+        let caller = self
+            .authorize(&request, "WatchDiskProgress", Role::Viewer)
+            .await?;
+        let device = request.into_inner().device;
+        self.record(&caller, device.as_deref(), &Ok::<_, Status>(()))
+            .await;
+        let progress = self.ctx.disk_manager.subscribe_progress();
+
+        let updates = futures_util::stream::unfold(progress, move |mut progress| {
+            let device = device.clone();
+            async move {
+                loop {
+                    match progress.recv().await {
+                        Ok(update) if device.as_ref().is_none_or(|d| *d == update.device) => {
+                            return Some((Ok(proto::DiskProgress::from(&update)), progress));
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("gRPC progress watcher skipped {} updates", skipped)
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(updates)))
+    }
+}
+
+fn catalog_query(request: proto::ListIsosRequest) -> std::result::Result<CatalogQuery, Status> {
+    let arch = request
+        .arch
+        .map(|arch| {
+            Arch::parse(&arch)
+                .ok_or_else(|| Status::invalid_argument(format!("Unknown architecture: {}", arch)))
+        })
+        .transpose()?;
+    Ok(CatalogQuery {
+        distro: request.distro,
+        version: request.version,
+        arch,
+        variant: request.variant,
+    })
+}
+
+fn bearer_token(metadata: &MetadataMap) -> &str {
+    metadata
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default()
+}
+
+/// The REST API's error mapping, in gRPC codes
+fn status(err: Error) -> Status {
+    let failure = ApiFailure::from(err);
+    let code = match failure.status() {
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::CONFLICT => Code::FailedPrecondition,
+        StatusCode::PAYLOAD_TOO_LARGE => Code::OutOfRange,
+        _ => Code::Internal,
+    };
+    Status::new(code, failure.message())
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|age| age.as_secs())
+        .unwrap_or_default()
+}
+
+impl From<&DiskInventory> for proto::Disk {
+    fn from(disk: &DiskInventory) -> Self {
+        let kind = match disk.kind {
+            DiskKind::Standard => "standard",
+            DiskKind::NvmeNamespace { .. } => "nvme_namespace",
+            DiskKind::Mmc => "mmc",
+        };
+        Self {
+            name: disk.name.clone(),
+            path: disk.path.clone(),
+            kind: kind.to_string(),
+            model: disk.model.clone(),
+            serial: disk.serial.clone(),
+            size_bytes: disk.size_bytes,
+            transport: disk.transport.clone(),
+            removable: disk.removable,
+            rotational: disk.rotational,
+            mounted: disk.mounted,
+            partitions: disk.partitions.len() as u32,
+            encrypted: !disk.encrypted.is_empty(),
+            display_name: disk.display_name(),
+        }
+    }
+}
+
+impl From<&IsoCatalogEntry> for proto::Iso {
+    fn from(entry: &IsoCatalogEntry) -> Self {
+        Self {
+            file_name: entry.file_name.clone(),
+            path: entry.path.to_string_lossy().to_string(),
+            volume_id: entry.volume_id.clone(),
+            distro: entry.distro.clone(),
+            version: entry.version.clone(),
+            arch: entry.arch.map(|arch| arch.to_string()),
+            variant: entry.variant.clone(),
+            size: entry.size,
+            checksum: entry.checksum.clone(),
+            bios: entry.boot.bios,
+            uefi: entry.boot.uefi,
+            display_name: entry.display_name(),
+        }
+    }
+}
+
+impl From<&InstallJob> for proto::InstallJob {
+    fn from(job: &InstallJob) -> Self {
+        let step = match job.step {
+            InstallStep::SelectIso => proto::InstallStep::SelectIso,
+            InstallStep::PrepareDisk => proto::InstallStep::PrepareDisk,
+            InstallStep::Install => proto::InstallStep::Install,
+            InstallStep::Verify => proto::InstallStep::Verify,
+            InstallStep::PostHooks => proto::InstallStep::PostHooks,
+        };
+        let status = match job.status {
+            InstallJobStatus::Queued => proto::InstallJobStatus::Queued,
+            InstallJobStatus::Running => proto::InstallJobStatus::Running,
+            InstallJobStatus::Failed => proto::InstallJobStatus::Failed,
+            InstallJobStatus::Interrupted => proto::InstallJobStatus::Interrupted,
+            InstallJobStatus::Cancelled => proto::InstallJobStatus::Cancelled,
+            InstallJobStatus::Completed => proto::InstallJobStatus::Completed,
+        };
+        Self {
+            id: job.id.clone(),
+            iso: job.iso.to_string_lossy().to_string(),
+            device: job.device.clone(),
+            auto_mode: job.auto_mode,
+            installer: job.installer.clone(),
+            priority: job.priority,
+            step: step.into(),
+            status: status.into(),
+            error: job.error.clone(),
+            created_at: unix_secs(job.created_at),
+            updated_at: unix_secs(job.updated_at),
+        }
+    }
+}
+
+impl From<&DiskProgress> for proto::DiskProgress {
+    fn from(progress: &DiskProgress) -> Self {
+        let operation = match progress.operation {
+            DiskOperation::Partition => proto::DiskOperation::Partition,
+            DiskOperation::Format => proto::DiskOperation::Format,
+            DiskOperation::WindowsMedia => proto::DiskOperation::WindowsMedia,
+            DiskOperation::Imaging => proto::DiskOperation::Imaging,
+            DiskOperation::Multiboot => proto::DiskOperation::Multiboot,
+            DiskOperation::Bootstrap => proto::DiskOperation::Bootstrap,
+        };
+        Self {
+            device: progress.device.clone(),
+            operation: operation.into(),
+            step: progress.step,
+            total_steps: progress.total_steps,
+            percentage: progress.percentage.into(),
+            message: progress.message.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::IsoError;
+
+    #[test]
+    fn test_install_job_message() {
+        let mut job = InstallJob::new("/srv/isos/debian-12.iso", "/dev/sdb", true);
+        job.step = InstallStep::Verify;
+        job.status = InstallJobStatus::Failed;
+        job.error = Some("No bootloader".to_string());
+        job.created_at = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        let message = proto::InstallJob::from(&job);
+        assert_eq!(message.id, job.id);
+        assert_eq!(message.iso, "/srv/isos/debian-12.iso");
+        assert_eq!(message.step(), proto::InstallStep::Verify);
+        assert_eq!(message.status(), proto::InstallJobStatus::Failed);
+        assert_eq!(message.error.as_deref(), Some("No bootloader"));
+        assert_eq!(message.created_at, 1_700_000_000);
+    }
+
+    #[test]
+    fn test_catalog_query() {
+        let query = catalog_query(proto::ListIsosRequest {
+            distro: Some("ubuntu".to_string()),
+            arch: Some("x86_64".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(query.distro.as_deref(), Some("ubuntu"));
+        assert_eq!(query.arch, Some(Arch::X86_64));
+
+        let err = catalog_query(proto::ListIsosRequest {
+            arch: Some("sparc".to_string()),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_status_codes() {
+        let code = |err: Error| status(err).code();
+        assert_eq!(
+            code(IsoError::NotFound("x.iso".to_string()).into()),
+            Code::NotFound
+        );
+        assert_eq!(
+            code(AuthError::Unauthenticated.into()),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            code(IsoError::JobConflict("/dev/sdb".to_string()).into()),
+            Code::FailedPrecondition
+        );
+        assert_eq!(
+            code(ApiError::ServerFailed("x".to_string()).into()),
+            Code::Internal
+        );
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(bearer_token(&metadata), "");
+        metadata.insert("authorization", "Bearer s3cret".parse().unwrap());
+        assert_eq!(bearer_token(&metadata), "s3cret");
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_audited() {
+        use crate::audit::{AuditLog, AuditQuery};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = crate::api::tests::context(Default::default());
+        ctx.audit = AuditLog::new(crate::config::AuditConfig {
+            enabled: true,
+            path: dir.path().join("audit.jsonl"),
+            record_reads: false,
+        });
+        let service = ControlService { ctx: ctx.clone() };
+        fn authorized<T>(message: T) -> Request<T> {
+            let mut request = Request::new(message);
+            request
+                .metadata_mut()
+                .insert("authorization", "Bearer view".parse().unwrap());
+            request
+        }
+
+        let _ = service
+            .list_disks(authorized(proto::ListDisksRequest {}))
+            .await;
+        let err = service
+            .cancel_install_job(authorized(proto::CancelInstallJobRequest {
+                id: "job-1".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied);
+
+        // Reads are left out unless `record_reads` is set
+        let entries = ctx.audit.entries(&AuditQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, AuditSource::Grpc);
+        assert_eq!(entries[0].actor, "dashboard");
+        assert_eq!(entries[0].action, "CancelInstallJob");
+        assert_eq!(entries[0].result, "failed: PermissionDenied");
+    }
+}
@@ -0,0 +1,297 @@
+use crate::capabilities::NodeCapabilities;
+use crate::config::HeartbeatConfig;
+use crate::error::{NetworkError, Result};
+use crate::job::install::{InstallJob, InstallJobRunner};
+use crate::monitoring::clock::unix_now;
+use crate::monitoring::exporter::{HealthReport, ServiceProbe};
+use crate::monitoring::Monitor;
+use crate::network::curl;
+use crate::service::startup::{StartupStatus, SubsystemStatus};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// First retry after a failed heartbeat, doubled for each failure after it
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// What the controller learns about the node with every heartbeat
+#[derive(Debug, Clone, Serialize)]
+pub struct Heartbeat {
+    pub node: String,
+    pub version: &'static str,
+    /// Unix seconds
+    pub sent_at: u64,
+    /// Every monitored service passed its last check
+    pub healthy: bool,
+    pub services: BTreeMap<String, ServiceProbe>,
+    pub subsystems: Vec<SubsystemStatus>,
+    /// Queued and running install jobs
+    pub jobs: Vec<InstallJob>,
+    /// What the node can run, so the controller schedules jobs it can
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<NodeCapabilities>,
+}
+
+/// Pushes a heartbeat to `[heartbeat] url` every `interval_secs`, so a
+/// fleet controller learns about nodes behind NAT without polling them.
+/// Failed heartbeats are retried with backoff.
+pub struct HeartbeatPublisher {
+    config: HeartbeatConfig,
+    monitor: Arc<RwLock<Monitor>>,
+    startup: StartupStatus,
+    install_jobs: InstallJobRunner,
+    capabilities: Option<NodeCapabilities>,
+    /// Air-gapped node; there is no controller to reach
+    offline: bool,
+    stop_tx: Mutex<Option<watch::Sender<bool>>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl HeartbeatPublisher {
+    pub fn new(
+        config: HeartbeatConfig,
+        monitor: Arc<RwLock<Monitor>>,
+        startup: StartupStatus,
+        install_jobs: InstallJobRunner,
+    ) -> Self {
+        Self {
+            config,
+            monitor,
+            startup,
+            install_jobs,
+            capabilities: None,
+            offline: false,
+            stop_tx: Mutex::new(None),
+            task: Mutex::new(None),
+        }
+    }
+
+    /// Report what the node can run with every heartbeat
+    pub fn with_capabilities(mut self, capabilities: NodeCapabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    /// Send nothing, rather than retry a controller that cannot be reached
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        if !self.config.enabled {
+            debug!("Heartbeat disabled");
+            return Ok(());
+        }
+        if self.offline {
+            info!("Offline mode: no heartbeats");
+            return Ok(());
+        }
+
+        let node = self.config.node_id.clone().unwrap_or_else(|| {
+            std::fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|_| "usb-installer-node".to_string())
+        });
+        info!("Sending heartbeats to {} as {}", self.config.url, node);
+
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let sender = Sender {
+            node,
+            config: self.config.clone(),
+            monitor: self.monitor.clone(),
+            startup: self.startup.clone(),
+            install_jobs: self.install_jobs.clone(),
+            capabilities: self.capabilities.clone(),
+        };
+        let task = tokio::spawn(sender.run(stop_rx));
+        *self.stop_tx.lock().unwrap() = Some(stop_tx);
+        *self.task.lock().unwrap() = Some(task);
+        Ok(())
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        let stop_tx = self.stop_tx.lock().unwrap().take();
+        let task = self.task.lock().unwrap().take();
+        if let (Some(stop_tx), Some(task)) = (stop_tx, task) {
+            let _ = stop_tx.send(true);
+            let _ = task.await;
+        }
+        Ok(())
+    }
+}
+
+/// Everything the send loop needs, moved into its task
+struct Sender {
+    node: String,
+    config: HeartbeatConfig,
+    monitor: Arc<RwLock<Monitor>>,
+    startup: StartupStatus,
+    install_jobs: InstallJobRunner,
+    capabilities: Option<NodeCapabilities>,
+}
+
+impl Sender {
+    async fn run(self, mut stop_rx: watch::Receiver<bool>) {
+        let interval = Duration::from_secs(self.config.interval_secs.max(1));
+        let max_backoff = Duration::from_secs(self.config.max_backoff_secs);
+        let mut failures = 0;
+        loop {
+            let delay = match self.send().await {
+                Ok(()) => {
+                    if failures > 0 {
+                        info!("Heartbeat delivered after {} failed attempts", failures);
+                    }
+                    failures = 0;
+                    interval
+                }
+                Err(e) => {
+                    failures += 1;
+                    let delay = retry_delay(failures, max_backoff);
+                    warn!("Heartbeat failed, retrying in {:?}: {}", delay, e);
+                    delay
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = stop_rx.changed() => break,
+            }
+        }
+        debug!("Heartbeat stopped");
+    }
+
+    async fn heartbeat(&self) -> Heartbeat {
+        let status = self.monitor.read().await.get_health_status().await;
+        let health = HealthReport::from_status(&status);
+        Heartbeat {
+            node: self.node.clone(),
+            version: env!("CARGO_PKG_VERSION"),
+            sent_at: unix_now(),
+            healthy: health.healthy,
+            services: health.services,
+            subsystems: self.startup.snapshot().await,
+            jobs: self.install_jobs.queue().await,
+            capabilities: self.capabilities.clone(),
+        }
+    }
+
+    async fn send(&self) -> Result<()> {
+        let body = serde_json::to_string(&self.heartbeat().await)
+            .map_err(|e| NetworkError::HeartbeatFailed(e.to_string()))?;
+
+        // The token and body go to curl on stdin, out of the process list
+        let mut input = String::new();
+        if let Some(token) = &self.config.token {
+            input.push_str(&curl::config_line(
+                "header",
+                &format!("Authorization: Bearer {}", token),
+            ));
+        }
+        input.push_str(&curl::config_line("data-raw", &body));
+
+        let mut cmd = Command::new("curl");
+        cmd.args(["-fsS", "--max-time"])
+            .arg(self.config.timeout_secs.to_string())
+            .args(["-X", "POST", "-H", "Content-Type: application/json"])
+            .args(["--config", "-", &self.config.url]);
+
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| NetworkError::HeartbeatFailed(format!("Failed to run curl: {}", e)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes()).await?;
+        }
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            return Err(NetworkError::HeartbeatFailed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Wait after `failures` failed heartbeats in a row
+fn retry_delay(failures: u32, max_backoff: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    RETRY_DELAY.saturating_mul(factor).min(max_backoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let max = Duration::from_secs(300);
+        assert_eq!(retry_delay(1, max), Duration::from_secs(5));
+        assert_eq!(retry_delay(3, max), Duration::from_secs(20));
+        assert_eq!(retry_delay(50, max), max);
+    }
+
+    fn publisher(dir: &std::path::Path) -> HeartbeatPublisher {
+        let install_jobs = InstallJobRunner::new(
+            crate::job::install::InstallJobStore::new(dir.to_path_buf()),
+            Arc::new(crate::disk::DiskManager::new(Arc::new(RwLock::new(
+                Default::default(),
+            )))),
+            Arc::new(crate::iso::IsoManager::new(Arc::new(RwLock::new(
+                Default::default(),
+            )))),
+        );
+        HeartbeatPublisher::new(
+            HeartbeatConfig {
+                enabled: true,
+                url: "https://controller.example/heartbeat".to_string(),
+                ..Default::default()
+            },
+            Arc::new(RwLock::new(Monitor::new(Arc::new(RwLock::new(
+                Default::default(),
+            ))))),
+            StartupStatus::default(),
+            install_jobs,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_offline_sends_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let publisher = publisher(dir.path()).with_offline(true);
+
+        publisher.start().await.unwrap();
+        assert!(publisher.task.lock().unwrap().is_none());
+        publisher.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_capabilities() {
+        let dir = tempfile::tempdir().unwrap();
+        let capabilities = NodeCapabilities::detect(&crate::config::Config::default());
+        let publisher = publisher(dir.path()).with_capabilities(capabilities.clone());
+        let sender = Sender {
+            node: "node1".to_string(),
+            config: publisher.config.clone(),
+            monitor: publisher.monitor.clone(),
+            startup: publisher.startup.clone(),
+            install_jobs: publisher.install_jobs.clone(),
+            capabilities: publisher.capabilities.clone(),
+        };
+
+        let body = serde_json::to_value(sender.heartbeat().await).unwrap();
+        assert_eq!(
+            body["capabilities"],
+            serde_json::to_value(&capabilities).unwrap()
+        );
+    }
+}
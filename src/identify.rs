@@ -0,0 +1,391 @@
+use crate::config::IdentifyConfig;
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, RwLock};
+use tokio::time::{interval, sleep_until};
+use tracing::{debug, info, warn};
+
+const LEDS_PATH: &str = "/sys/class/leds";
+
+/// Blink half-period
+const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// DEC reverse-video mode, flips the whole Linux console
+const CONSOLE_REVERSE_ON: &[u8] = b"\x1b[?5h";
+const CONSOLE_REVERSE_OFF: &[u8] = b"\x1b[?5l";
+
+/// Identify status reported to the fleet controller
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IdentifyStatus {
+    pub active: bool,
+    pub remaining_secs: u64,
+    pub leds: Vec<String>,
+    pub beep: bool,
+    pub flash_console: bool,
+}
+
+struct Session {
+    id: u64,
+    until: Instant,
+    leds: Vec<String>,
+    beep: bool,
+    flash_console: bool,
+    cancel_tx: Option<oneshot::Sender<()>>,
+}
+
+/// An LED under /sys/class/leds and the state to restore afterwards
+pub(crate) struct Led {
+    name: String,
+    dir: PathBuf,
+    max_brightness: String,
+    brightness: String,
+    trigger: Option<String>,
+}
+
+impl Led {
+    /// Look up an LED by its /sys/class/leds name
+    pub(crate) fn find(name: &str) -> Option<Self> {
+        Self::open(&Path::new(LEDS_PATH).join(name))
+    }
+
+    fn open(dir: &Path) -> Option<Self> {
+        let read = |file: &str| fs::read_to_string(dir.join(file)).ok();
+        Some(Self {
+            name: dir.file_name()?.to_string_lossy().to_string(),
+            dir: dir.to_path_buf(),
+            max_brightness: read("max_brightness")?.trim().to_string(),
+            brightness: read("brightness")?.trim().to_string(),
+            trigger: read("trigger").and_then(|t| parse_trigger(&t)),
+        })
+    }
+
+    pub(crate) fn set(&self, on: bool) {
+        let value = if on { &self.max_brightness } else { "0" };
+        self.write("brightness", value);
+    }
+
+    pub(crate) fn take_over(&self) {
+        // Keyboard and heartbeat triggers would immediately overwrite us
+        if self.trigger.is_some() {
+            self.write("trigger", "none");
+        }
+    }
+
+    pub(crate) fn restore(&self) {
+        self.write("brightness", &self.brightness);
+        if let Some(trigger) = &self.trigger {
+            self.write("trigger", trigger);
+        }
+    }
+
+    fn write(&self, file: &str, value: &str) {
+        if let Err(e) = fs::write(self.dir.join(file), value) {
+            debug!("Failed to write {} for LED {}: {}", file, self.name, e);
+        }
+    }
+}
+
+/// Makes the node physically findable: blinks LEDs, beeps and flashes the
+/// console for a limited time
+pub struct Identifier {
+    config: Arc<RwLock<IdentifyConfig>>,
+    leds_path: PathBuf,
+    session: Arc<RwLock<Option<Session>>>,
+    next_id: AtomicU64,
+}
+
+impl Identifier {
+    pub fn new(config: Arc<RwLock<IdentifyConfig>>) -> Self {
+        Self::with_leds_path(config, PathBuf::from(LEDS_PATH))
+    }
+
+    fn with_leds_path(config: Arc<RwLock<IdentifyConfig>>, leds_path: PathBuf) -> Self {
+        Self {
+            config,
+            leds_path,
+            session: Arc::new(RwLock::new(None)),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Start identifying for `duration` seconds (config default when `None`),
+    /// replacing any running session
+    pub async fn start(&self, duration: Option<u64>) -> Result<IdentifyStatus> {
+        let config = self.config.read().await.clone();
+        let duration = duration
+            .unwrap_or(config.default_duration)
+            .clamp(1, config.max_duration);
+
+        self.stop().await;
+
+        let leds = discover_leds(&self.leds_path, &config.leds);
+        let console = if config.beep || config.flash_console {
+            open_console(&config.console)
+        } else {
+            None
+        };
+
+        if leds.is_empty() && console.is_none() {
+            return Err(Error::General(
+                "No LED or console available to identify this node".to_string(),
+            ));
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let until = Instant::now() + Duration::from_secs(duration);
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+
+        *self.session.write().await = Some(Session {
+            id,
+            until,
+            leds: leds.iter().map(|l| l.name.clone()).collect(),
+            beep: console.is_some() && config.beep,
+            flash_console: console.is_some() && config.flash_console,
+            cancel_tx: Some(cancel_tx),
+        });
+
+        info!(
+            "Identifying node for {}s ({} LEDs, console: {})",
+            duration,
+            leds.len(),
+            console.is_some()
+        );
+
+        let session = self.session.clone();
+        tokio::spawn(async move {
+            blink(leds, console, &config, until.into(), cancel_rx).await;
+
+            let mut session = session.write().await;
+            if session.as_ref().is_some_and(|s| s.id == id) {
+                *session = None;
+            }
+            info!("Identify finished");
+        });
+
+        Ok(self.status().await)
+    }
+
+    /// Stop a running identify session and restore LED state
+    pub async fn stop(&self) {
+        if let Some(mut session) = self.session.write().await.take() {
+            if let Some(tx) = session.cancel_tx.take() {
+                let _ = tx.send(());
+            }
+        }
+    }
+
+    pub async fn status(&self) -> IdentifyStatus {
+        match self.session.read().await.as_ref() {
+            Some(s) => IdentifyStatus {
+                active: true,
+                remaining_secs: s.until.saturating_duration_since(Instant::now()).as_secs(),
+                leds: s.leds.clone(),
+                beep: s.beep,
+                flash_console: s.flash_console,
+            },
+            None => IdentifyStatus {
+                active: false,
+                remaining_secs: 0,
+                leds: Vec::new(),
+                beep: false,
+                flash_console: false,
+            },
+        }
+    }
+
+    pub async fn reload_config(&self, config: Arc<RwLock<IdentifyConfig>>) {
+        *self.config.write().await = config.read().await.clone();
+    }
+}
+
+async fn blink(
+    leds: Vec<Led>,
+    mut console: Option<fs::File>,
+    config: &IdentifyConfig,
+    until: tokio::time::Instant,
+    mut cancel_rx: oneshot::Receiver<()>,
+) {
+    for led in &leds {
+        led.take_over();
+    }
+
+    let mut ticker = interval(BLINK_INTERVAL);
+    let mut on = false;
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = sleep_until(until) => break,
+            _ = &mut cancel_rx => break,
+        }
+
+        on = !on;
+        for led in &leds {
+            led.set(on);
+        }
+
+        if let Some(tty) = console.as_mut() {
+            let mut out = Vec::new();
+            if config.flash_console {
+                out.extend_from_slice(if on {
+                    CONSOLE_REVERSE_ON
+                } else {
+                    CONSOLE_REVERSE_OFF
+                });
+            }
+            if config.beep && on {
+                out.push(0x07);
+            }
+            let _ = tty.write_all(&out);
+        }
+    }
+
+    for led in &leds {
+        led.restore();
+    }
+    if let Some(tty) = console.as_mut() {
+        let _ = tty.write_all(CONSOLE_REVERSE_OFF);
+    }
+}
+
+/// Find LEDs to blink. Configured names are used as given; otherwise the
+/// keyboard lock LEDs are picked.
+fn discover_leds(root: &Path, names: &[String]) -> Vec<Led> {
+    if !names.is_empty() {
+        return names
+            .iter()
+            .filter_map(|name| {
+                let led = Led::open(&root.join(name));
+                if led.is_none() {
+                    warn!("LED {} not found under {}", name, root.display());
+                }
+                led
+            })
+            .collect();
+    }
+
+    let mut leds: Vec<Led> = fs::read_dir(root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| is_keyboard_led(&entry.file_name().to_string_lossy()))
+        .filter_map(|entry| Led::open(&entry.path()))
+        .collect();
+    leds.sort_by(|a, b| a.name.cmp(&b.name));
+    leds
+}
+
+fn is_keyboard_led(name: &str) -> bool {
+    ["::capslock", "::numlock", "::scrolllock"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
+/// Extract the active trigger from a sysfs trigger list, e.g.
+/// `none [kbd-capslock] timer` -> `kbd-capslock`
+fn parse_trigger(triggers: &str) -> Option<String> {
+    let start = triggers.find('[')? + 1;
+    let end = start + triggers[start..].find(']')?;
+    let active = &triggers[start..end];
+    (active != "none").then(|| active.to_string())
+}
+
+fn open_console(path: &Path) -> Option<fs::File> {
+    match fs::OpenOptions::new().write(true).open(path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            debug!("Console {} not writable: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn fake_led(root: &Path, name: &str, trigger: &str) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("max_brightness"), "1\n").unwrap();
+        fs::write(dir.join("brightness"), "0\n").unwrap();
+        fs::write(dir.join("trigger"), trigger).unwrap();
+    }
+
+    #[test]
+    fn test_parse_trigger() {
+        assert_eq!(
+            parse_trigger("none [kbd-capslock] timer"),
+            Some("kbd-capslock".to_string())
+        );
+        assert_eq!(parse_trigger("[none] timer heartbeat"), None);
+        assert_eq!(parse_trigger("none timer"), None);
+    }
+
+    #[test]
+    fn test_discover_keyboard_leds() {
+        let root = TempDir::new().unwrap();
+        fake_led(root.path(), "input3::capslock", "none [kbd-capslock]");
+        fake_led(root.path(), "input3::numlock", "[none] kbd-numlock");
+        fake_led(root.path(), "mmc0::", "[mmc0] none");
+
+        let leds = discover_leds(root.path(), &[]);
+        let names: Vec<_> = leds.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["input3::capslock", "input3::numlock"]);
+
+        let leds = discover_leds(root.path(), &["mmc0::".to_string()]);
+        assert_eq!(leds.len(), 1);
+        assert_eq!(leds[0].trigger.as_deref(), Some("mmc0"));
+    }
+
+    #[tokio::test]
+    async fn test_identify_restores_leds() {
+        let root = TempDir::new().unwrap();
+        fake_led(root.path(), "input3::capslock", "none [kbd-capslock]");
+
+        let config = IdentifyConfig {
+            beep: false,
+            flash_console: false,
+            ..IdentifyConfig::default()
+        };
+        let identifier =
+            Identifier::with_leds_path(Arc::new(RwLock::new(config)), root.path().to_path_buf());
+
+        let status = identifier.start(Some(60)).await.unwrap();
+        assert!(status.active);
+        assert_eq!(status.leds, ["input3::capslock"]);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        identifier.stop().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let led = root.path().join("input3::capslock");
+        assert_eq!(fs::read_to_string(led.join("brightness")).unwrap(), "0");
+        assert_eq!(
+            fs::read_to_string(led.join("trigger")).unwrap(),
+            "kbd-capslock"
+        );
+        assert!(!identifier.status().await.active);
+    }
+
+    #[tokio::test]
+    async fn test_identify_without_outputs_fails() {
+        let root = TempDir::new().unwrap();
+        let config = IdentifyConfig {
+            beep: false,
+            flash_console: false,
+            ..IdentifyConfig::default()
+        };
+        let identifier =
+            Identifier::with_leds_path(Arc::new(RwLock::new(config)), root.path().to_path_buf());
+
+        assert!(identifier.start(None).await.is_err());
+    }
+}
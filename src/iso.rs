@@ -1,46 +1,165 @@
+pub mod cache;
+pub mod catalog;
+pub mod detector;
+pub mod downloader;
+pub mod feeds;
+pub mod hooks;
 pub mod installer;
+pub mod integrity;
+pub mod live;
+#[cfg(target_os = "linux")]
+pub mod loopdev;
 pub mod mounter;
+pub mod netboot;
+pub mod sync;
+pub mod template;
+#[cfg(feature = "torrent")]
+pub mod torrent;
+pub mod unattended;
+pub mod ventoy;
+pub mod windows;
 
-use crate::config::IsoConfig;
+use crate::audit::{AuditAction, AuditLog, AuditSource};
+use crate::config::{DownloadConfig, IsoConfig, TargetConfig};
+use crate::dryrun::DryRun;
 use crate::error::{IsoError, Result};
+use cache::{CacheStats, IsoCache};
+use catalog::{CatalogQuery, IsoCatalog, IsoCatalogEntry};
+use detector::{ConfigDetector, InstallerDetector};
+use downloader::{DownloadProgress, DownloadRequest, IsoDownloader};
+use feeds::FeedRelease;
 use installer::{InstallerInfo, InstallerProgress, IsoInstaller};
-use mounter::{IsoMounter, MountPoint};
+use integrity::IntegrityReport;
+use mounter::{IsoMounter, MountGuard, MountPoint};
+use netboot::NetbootImage;
+use serde::Serialize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
+use std::time::Duration;
+use sync::RepoSyncReport;
+use template::TemplateEngine;
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
 use tracing::{debug, error, info, warn};
+use unattended::{AnswerFormat, UnattendedFiles};
+use ventoy::{DeployProgress, VentoyDeployer, VentoyDeployment, VentoyStick};
+use windows::WindowsSetup;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum IsoManagerState {
     Idle,
     Scanning,
     Mounting,
+    Downloading,
     Ready,
     Installing,
     Error(String),
 }
 
+/// Outcome of one release feed sync
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FeedSyncReport {
+    /// Images the feeds announced
+    pub releases: usize,
+    /// Releases newer than the local images
+    pub updates: Vec<FeedRelease>,
+    /// Products fetched automatically
+    pub downloaded: Vec<PathBuf>,
+    pub errors: Vec<String>,
+}
+
 pub struct IsoManager {
     config: Arc<RwLock<IsoConfig>>,
+    target: Arc<RwLock<TargetConfig>>,
     state: Arc<RwLock<IsoManagerState>>,
     mounter: Arc<IsoMounter>,
     installer: Arc<IsoInstaller>,
     available_isos: Arc<RwLock<Vec<PathBuf>>>,
     active_iso: Arc<RwLock<Option<PathBuf>>>,
+    /// Keeps the active ISO mounted while it is selected
+    active_mount: Arc<RwLock<Option<MountGuard>>>,
+    idle_reaper: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
+    catalog: Arc<RwLock<IsoCatalog>>,
+    /// Downloaded ISOs, opened on start
+    cache: Arc<RwLock<IsoCache>>,
+    /// Held while the central repository is mirrored
+    sync_lock: Arc<Mutex<()>>,
+    netboot: Arc<RwLock<Vec<NetbootImage>>>,
+    downloader: IsoDownloader,
+    download_tx: broadcast::Sender<DownloadProgress>,
+    deployer: VentoyDeployer,
+    deploy_tx: broadcast::Sender<DeployProgress>,
+    /// Set with `--dry-run`; installers, answer files and hooks are
+    /// recorded instead of run
+    dry_run: Option<DryRun>,
+    /// Air-gapped node; nothing is fetched from the network
+    offline: bool,
+    audit: Option<AuditLog>,
 }
 
 impl IsoManager {
     pub fn new(config: Arc<RwLock<IsoConfig>>) -> Self {
+        let (download_tx, _) = broadcast::channel(100);
+        let (deploy_tx, _) = broadcast::channel(100);
+
         Self {
             config,
+            target: Arc::new(RwLock::new(TargetConfig::default())),
             state: Arc::new(RwLock::new(IsoManagerState::Idle)),
             mounter: Arc::new(IsoMounter::new()),
             installer: Arc::new(IsoInstaller::new()),
             available_isos: Arc::new(RwLock::new(Vec::new())),
             active_iso: Arc::new(RwLock::new(None)),
+            active_mount: Arc::new(RwLock::new(None)),
+            idle_reaper: Arc::new(RwLock::new(None)),
+            catalog: Arc::new(RwLock::new(IsoCatalog::default())),
+            cache: Arc::new(RwLock::new(IsoCache::new(
+                PathBuf::new(),
+                Default::default(),
+            ))),
+            sync_lock: Arc::new(Mutex::new(())),
+            netboot: Arc::new(RwLock::new(Vec::new())),
+            downloader: IsoDownloader::new().with_progress(download_tx.clone()),
+            download_tx,
+            deployer: VentoyDeployer::new().with_progress(deploy_tx.clone()),
+            deploy_tx,
+            dry_run: None,
+            offline: false,
+            audit: None,
+        }
+    }
+
+    /// Refuse downloads, feed checks and repository syncs
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Rehearse installations without starting installers or writing to
+    /// the media and the target
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.installer = Arc::new(IsoInstaller::new().with_dry_run(dry_run.clone()));
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Record installs, Ventoy deployments and cache evictions
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    async fn audit(&self, action: AuditAction) {
+        if let Some(audit) = &self.audit {
+            audit.record(action).await;
         }
     }
 
+    /// Answers used for unattended installs
+    pub fn with_target(mut self, target: Arc<RwLock<TargetConfig>>) -> Self {
+        self.target = target;
+        self
+    }
+
     pub async fn start(&self) -> Result<()> {
         info!("Starting ISO manager");
 
@@ -50,18 +169,56 @@ impl IsoManager {
             return Ok(());
         }
 
+        for detector in &config.installer.detectors {
+            self.register_detector(Arc::new(ConfigDetector::new(detector.clone())))
+                .await;
+        }
+
         if config.auto_scan {
             self.scan_for_isos(&config.iso_paths).await?;
         }
 
+        if let Some(dir) = download_dir(&config) {
+            *self.cache.write().await = IsoCache::load(dir, config.cache.clone()).await;
+            if let Err(e) = self.enforce_cache_quota().await {
+                warn!("ISO cache eviction failed: {}", e);
+            }
+        }
+
         if config.auto_mount && !self.available_isos.read().await.is_empty() {
             let iso = self.available_isos.read().await[0].clone();
             self.mount_iso(&iso).await?;
         }
 
+        if config.mounts.idle_unmount_secs > 0 {
+            let idle = Duration::from_secs(config.mounts.idle_unmount_secs);
+            *self.idle_reaper.write().await = Some(self.spawn_idle_reaper(idle));
+        }
+
         Ok(())
     }
 
+    /// Periodically unmount images no one has used for `idle`
+    fn spawn_idle_reaper(&self, idle: Duration) -> tokio::task::JoinHandle<()> {
+        let mounter = self.mounter.clone();
+        let period = (idle / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let mounter = mounter.clone();
+                match tokio::task::spawn_blocking(move || mounter.unmount_idle(idle)).await {
+                    Ok(Ok(unmounted)) if !unmounted.is_empty() => {
+                        debug!("Unmounted {} idle ISOs", unmounted.len());
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("Idle unmount failed: {}", e),
+                    Err(e) => warn!("Idle unmount task failed: {}", e),
+                }
+            }
+        })
+    }
+
     pub async fn stop(&self) -> Result<()> {
         info!("Stopping ISO manager");
 
@@ -69,6 +226,11 @@ impl IsoManager {
             warn!("Failed to cancel installer: {}", e);
         }
 
+        if let Some(reaper) = self.idle_reaper.write().await.take() {
+            reaper.abort();
+        }
+        self.active_mount.write().await.take();
+
         let results = self.mounter.unmount_all()?;
         for result in results {
             if let Err(e) = result {
@@ -96,6 +258,7 @@ impl IsoManager {
 
         info!("Found {} ISO files", isos.len());
         *self.available_isos.write().await = isos.clone();
+        self.update_catalog().await;
         self.set_state(IsoManagerState::Idle).await;
 
         Ok(isos)
@@ -131,23 +294,141 @@ impl IsoManager {
                 .ok_or_else(|| IsoError::InvalidIsoFile(iso_path.to_string_lossy().to_string()))?,
         );
 
-        self.mounter
-            .mount(iso_path, &mount_point, vec!["ro".to_string()])?;
+        let guard = self
+            .mounter
+            .acquire(iso_path, &mount_point, vec!["ro".to_string()])?;
+        let mount_point = guard.target().to_path_buf();
+        self.touch_cached(iso_path).await;
 
+        *self.active_mount.write().await = Some(guard);
         *self.active_iso.write().await = Some(iso_path.to_path_buf());
         self.set_state(IsoManagerState::Ready).await;
 
         Ok(mount_point)
     }
 
+    /// Deselect the active ISO. It stays mounted while other consumers
+    /// still use it and is unmounted once they are done and it is idle.
     pub async fn unmount_current(&self) -> Result<()> {
         if let Some(iso) = self.active_iso.write().await.take() {
-            self.mounter.unmount(&iso)?;
+            self.active_mount.write().await.take();
+            if self.mounter.ref_count(&iso)? == 0 {
+                self.mounter.unmount(&iso)?;
+            } else {
+                debug!("{} is still in use, leaving it mounted", iso.display());
+            }
             self.set_state(IsoManagerState::Idle).await;
         }
         Ok(())
     }
 
+    /// Verify an ISO against the checksums embedded in it. An image that is
+    /// not mounted yet is mounted for the check only.
+    pub async fn check_iso(&self, iso: &Path) -> Result<IntegrityReport> {
+        let temporary = !self.mounter.is_mounted(iso)?;
+        let stem = iso
+            .file_stem()
+            .ok_or_else(|| IsoError::NotFound(iso.display().to_string()))?;
+        let mount_point = self.config.read().await.mount_point.clone();
+        let target = mount_point.join(".check").join(stem);
+        let guard = self.mounter.acquire(iso, &target, vec!["ro".to_string()])?;
+
+        let iso_path = iso.to_path_buf();
+        let root = guard.target().to_path_buf();
+        let result = tokio::task::spawn_blocking(move || integrity::check(&iso_path, &root))
+            .await
+            .map_err(|e| IsoError::InvalidFormat(e.to_string()));
+
+        drop(guard);
+        if temporary && self.mounter.ref_count(iso)? == 0 {
+            if let Err(e) = self.mounter.unmount(iso) {
+                warn!("Failed to unmount {}: {}", iso.display(), e);
+            }
+        }
+
+        let report = result??;
+        if report.passed() {
+            info!("{}: {} files intact", iso.display(), report.checked);
+        } else {
+            error!(
+                "{}: {} of {} files damaged",
+                iso.display(),
+                report.mismatches.len(),
+                report.checked
+            );
+        }
+        Ok(report)
+    }
+
+    /// Extract the kernel and initrd of `iso` for network boot. An image
+    /// that is not mounted yet is mounted for the extraction only.
+    pub async fn prepare_netboot(&self, iso: &Path) -> Result<NetbootImage> {
+        let temporary = !self.mounter.is_mounted(iso)?;
+        let stem = iso
+            .file_stem()
+            .ok_or_else(|| IsoError::NotFound(iso.display().to_string()))?;
+        let (mount_point, cache_dir) = {
+            let config = self.config.read().await;
+            (config.mount_point.clone(), config.netboot.cache_dir.clone())
+        };
+        let target = mount_point.join(".netboot").join(stem);
+        let guard = self.mounter.acquire(iso, &target, vec!["ro".to_string()])?;
+
+        let iso_path = iso.to_path_buf();
+        let root = guard.target().to_path_buf();
+        let result =
+            tokio::task::spawn_blocking(move || netboot::extract(&iso_path, &root, &cache_dir))
+                .await
+                .map_err(|e| IsoError::InvalidFormat(e.to_string()));
+
+        drop(guard);
+        if temporary && self.mounter.ref_count(iso)? == 0 {
+            if let Err(e) = self.mounter.unmount(iso) {
+                warn!("Failed to unmount {}: {}", iso.display(), e);
+            }
+        }
+
+        let image = result??;
+        let mut images = self.netboot.write().await;
+        images.retain(|i| i.iso != image.iso);
+        images.push(image.clone());
+        Ok(image)
+    }
+
+    /// Prepare every available ISO that can boot over the network; others
+    /// are skipped and images that disappeared are dropped
+    pub async fn prepare_all_netboot(&self) -> Vec<NetbootImage> {
+        let isos = self.get_available_isos().await;
+        for iso in &isos {
+            if let Err(e) = self.prepare_netboot(iso).await {
+                debug!("{} is not served for network boot: {}", iso.display(), e);
+            }
+        }
+        let mut images = self.netboot.write().await;
+        images.retain(|i| isos.contains(&i.iso));
+        images.clone()
+    }
+
+    pub async fn get_netboot_images(&self) -> Vec<NetbootImage> {
+        self.netboot.read().await.clone()
+    }
+
+    /// Keep `iso` mounted for as long as the guard lives, so its contents
+    /// can be published to network boot clients
+    pub async fn hold_netboot_mount(&self, iso: &Path) -> Result<MountGuard> {
+        let stem = iso
+            .file_stem()
+            .ok_or_else(|| IsoError::NotFound(iso.display().to_string()))?;
+        let target = self
+            .config
+            .read()
+            .await
+            .mount_point
+            .join(".netboot")
+            .join(stem);
+        self.mounter.acquire(iso, &target, vec!["ro".to_string()])
+    }
+
     pub async fn discover_installers(&self) -> Result<Vec<InstallerInfo>> {
         let iso = self
             .active_iso
@@ -161,6 +442,10 @@ impl IsoManager {
             .get_mount_point(&iso)?
             .ok_or_else(|| IsoError::NotMounted(iso.to_string_lossy().to_string()))?
             .target;
+        // Held so the mount outlives discovery even if the ISO is deselected
+        let _guard = self
+            .mounter
+            .acquire(&iso, &mount_point, vec!["ro".to_string()])?;
 
         self.installer.discover_installer(&mount_point).await
     }
@@ -170,29 +455,542 @@ impl IsoManager {
         installer: &InstallerInfo,
         auto_mode: bool,
     ) -> Result<mpsc::Receiver<InstallerProgress>> {
-        info!("Starting installation with {}", installer.name);
-        self.set_state(IsoManagerState::Installing).await;
+        let prepared = self.prepare_installation(installer, auto_mode).await;
+        if self.dry_run.is_none() {
+            self.audit(
+                AuditAction::new(AuditSource::Iso, "node", "install")
+                    .with_target(installer.path.display().to_string())
+                    .with_result(&prepared),
+            )
+            .await;
+        }
+        prepared?;
 
         let (tx, rx) = mpsc::channel(100);
+        let hooks_tx = tx.clone();
         let installer_clone = self.installer.clone();
         let installer_info = installer.clone();
+        let config = self.config.read().await.clone();
+        let target = self.target.read().await.clone();
+        let dry_run = self.dry_run.clone();
 
         tokio::spawn(async move {
             if let Err(e) = installer_clone
-                .start_installer(&installer_info, auto_mode, rx)
+                .start_installer(&installer_info, auto_mode, &config.installer, tx)
                 .await
             {
                 error!("Installation failed: {}", e);
+                return;
+            }
+            match dry_run {
+                Some(dry_run) => hooks::rehearse_hooks(&config.post_install, &target, &dry_run),
+                None => {
+                    hooks::run_hooks(&config.post_install, &target, &hooks_tx).await;
+                }
             }
         });
 
-        Ok(tx)
+        Ok(rx)
+    }
+
+    /// Recognize installer media the built-in detection does not know
+    pub async fn register_detector(&self, detector: Arc<dyn InstallerDetector>) {
+        self.installer.register_detector(detector).await;
+    }
+
+    /// Run the installer to completion without its post-install hooks, for
+    /// callers that drive the steps themselves
+    pub async fn run_installation(
+        &self,
+        installer: &InstallerInfo,
+        auto_mode: bool,
+        progress_tx: mpsc::Sender<InstallerProgress>,
+    ) -> Result<()> {
+        self.prepare_installation(installer, auto_mode).await?;
+        let limits = self.config.read().await.installer.clone();
+        let result = self
+            .installer
+            .start_installer(installer, auto_mode, &limits, progress_tx)
+            .await;
+        self.set_state(match &result {
+            Ok(_) => IsoManagerState::Ready,
+            Err(e) => IsoManagerState::Error(e.to_string()),
+        })
+        .await;
+        result
+    }
+
+    /// Check that `device` now holds an installed Linux root. Other systems,
+    /// including those of registered detectors, are not inspected.
+    pub async fn verify_installation(&self, installer: &InstallerInfo, device: &str) -> Result<()> {
+        if matches!(installer.os_type.as_str(), "windows" | "bsd")
+            || self.installer.has_detector(&installer.os_type).await
+        {
+            info!("Not verifying {} install on {}", installer.os_type, device);
+            return Ok(());
+        }
+        let mount_dir = self.config.read().await.post_install.mount_dir.clone();
+        let device = device.to_string();
+        tokio::task::spawn_blocking(move || hooks::verify_root(&device, &mount_dir))
+            .await
+            .map_err(|e| IsoError::HookFailed(format!("verify task failed: {}", e)))?
+    }
+
+    /// Run the post-install hooks against `device`
+    pub async fn run_post_install(
+        &self,
+        device: &str,
+        progress_tx: &mpsc::Sender<InstallerProgress>,
+    ) -> Vec<hooks::HookResult> {
+        let config = self.config.read().await.post_install.clone();
+        let mut target = self.target.read().await.clone();
+        target.disk.device = Some(device.to_string());
+        if let Some(dry_run) = &self.dry_run {
+            hooks::rehearse_hooks(&config, &target, dry_run);
+            return Vec::new();
+        }
+        hooks::run_hooks(&config, &target, progress_tx).await
+    }
+
+    async fn prepare_installation(&self, installer: &InstallerInfo, auto_mode: bool) -> Result<()> {
+        info!("Starting installation with {}", installer.name);
+
+        if installer.os_type == "windows" {
+            let config = self.config.read().await.windows.clone();
+            self.installer
+                .prepare_windows_setup(installer, &config)
+                .await?;
+        }
+
+        if auto_mode {
+            self.prepare_unattended(installer).await?;
+        }
+
+        self.set_state(IsoManagerState::Installing).await;
+        Ok(())
+    }
+
+    /// Place answer files for the installer, on the configured side partition
+    /// or on the installer media, which must then be writable
+    async fn prepare_unattended(&self, installer: &InstallerInfo) -> Result<()> {
+        let config = self.config.read().await.clone();
+        if !config.unattended.enabled {
+            return Ok(());
+        }
+        let Some(format) = AnswerFormat::for_os_type(&installer.os_type) else {
+            debug!("No answer file format for {}", installer.os_type);
+            return Ok(());
+        };
+
+        let target = self.target.read().await.clone();
+        let templates = TemplateEngine::new(config.unattended.template_dir.as_deref())?;
+        let side_partition = config.unattended.side_partition.clone();
+        let files = match format {
+            AnswerFormat::Autounattend => {
+                let setup = self.installer.get_windows_setup().await.ok_or_else(|| {
+                    IsoError::UnattendedFailed("Windows edition has not been selected".to_string())
+                })?;
+                UnattendedFiles::render_windows(&target, &templates, &setup)?
+            }
+            _ => UnattendedFiles::render(format, &target, &templates, side_partition.is_some())?,
+        };
+        let media_root = match &side_partition {
+            Some(_) => None,
+            None => {
+                let iso = self.active_iso.read().await.clone();
+                let mount_point = match iso {
+                    Some(iso) => self.mounter.get_mount_point(&iso)?,
+                    None => None,
+                };
+                let root = mount_point.map(|m| m.target).ok_or_else(|| {
+                    IsoError::UnattendedFailed("no installer media is mounted".to_string())
+                })?;
+                Some(root)
+            }
+        };
+        let mount_dir = config.mount_point.join(".unattended");
+
+        if let Some(dry_run) = &self.dry_run {
+            let destination = match (&side_partition, &media_root) {
+                (Some(partition), _) => format!("side partition {}", partition),
+                (None, Some(root)) => format!("installer media at {}", root.display()),
+                (None, None) => return Ok(()),
+            };
+            dry_run.action(format!(
+                "Write {:?} answer files to {}",
+                format, destination
+            ));
+            return Ok(());
+        }
+
+        tokio::task::spawn_blocking(move || match (side_partition, media_root) {
+            (Some(partition), _) => files.inject_side_partition(&partition, &mount_dir),
+            (None, Some(root)) => files.inject_media(&root),
+            (None, None) => Ok(()),
+        })
+        .await
+        .map_err(|e| IsoError::UnattendedFailed(e.to_string()))?
+    }
+
+    /// autounattend.xml for Windows media mounted at `media_root`, or `None`
+    /// when unattended installs are disabled
+    pub async fn windows_answer_file(&self, media_root: &Path) -> Result<Option<String>> {
+        let config = self.config.read().await.clone();
+        if !config.unattended.enabled {
+            return Ok(None);
+        }
+
+        let image = windows::find_install_image(media_root).ok_or_else(|| {
+            IsoError::InstallerNotFound(format!(
+                "install.wim or install.esd under {}",
+                media_root.display()
+            ))
+        })?;
+        let editions = windows::list_editions(&image)?;
+        let setup = WindowsSetup::from_config(&config.windows, image, &editions)?;
+        let target = self.target.read().await.clone();
+        let templates = TemplateEngine::new(config.unattended.template_dir.as_deref())?;
+        let files = UnattendedFiles::render_windows(&target, &templates, &setup)?;
+        Ok(files.files.into_iter().next().map(|f| f.contents))
+    }
+
+    pub fn subscribe_downloads(&self) -> broadcast::Receiver<DownloadProgress> {
+        self.download_tx.subscribe()
+    }
+
+    /// Fetch an ISO from the configured mirrors into the download directory
+    /// and make it available for mounting
+    pub async fn download_iso(&self, request: &DownloadRequest) -> Result<PathBuf> {
+        let download = self.config.read().await.download.clone();
+        self.download_with(request, &download).await
+    }
+
+    async fn download_with(
+        &self,
+        request: &DownloadRequest,
+        download: &DownloadConfig,
+    ) -> Result<PathBuf> {
+        let config = self.config.read().await.clone();
+        let target_dir = download_dir(&config)
+            .ok_or_else(|| IsoError::DownloadFailed("No download directory".to_string()))?;
+        let file_name = request.file_name()?;
+        let cached = target_dir.join(&file_name).exists();
+        // A cached copy is only verified, which needs no network
+        if self.offline && !cached {
+            return Err(IsoError::DownloadFailed(format!(
+                "{} is not cached and the node is offline",
+                file_name
+            ))
+            .into());
+        }
+
+        self.set_state(IsoManagerState::Downloading).await;
+        let result = self
+            .downloader
+            .download(request, download, &target_dir)
+            .await;
+
+        match &result {
+            Ok(path) => {
+                let mut isos = self.available_isos.write().await;
+                if !isos.contains(path) {
+                    isos.push(path.clone());
+                }
+                drop(isos);
+                self.cache
+                    .write()
+                    .await
+                    .record_lookup(&file_name, cached)
+                    .await;
+                if let Err(e) = self.enforce_cache_quota().await {
+                    warn!("ISO cache eviction failed: {}", e);
+                }
+                self.update_catalog().await;
+            }
+            Err(e) => error!("ISO download failed: {}", e),
+        }
+        self.set_state(IsoManagerState::Idle).await;
+        result
+    }
+
+    /// Directory uploaded ISOs are written to, the download directory
+    pub async fn upload_dir(&self) -> Option<PathBuf> {
+        download_dir(&*self.config.read().await)
+    }
+
+    /// Make an ISO copied onto the node, by upload or SFTP, available and
+    /// catalog it
+    pub async fn add_iso(&self, path: PathBuf) {
+        let mut isos = self.available_isos.write().await;
+        if !isos.contains(&path) {
+            isos.push(path);
+        }
+        drop(isos);
+        self.update_catalog().await;
+    }
+
+    /// Scan the configured ISO paths again, for media added behind the
+    /// node's back
+    pub async fn rescan(&self) -> Result<Vec<PathBuf>> {
+        let paths = self.config.read().await.iso_paths.clone();
+        self.scan_for_isos(&paths).await
+    }
+
+    /// Size, quota, hit rate and contents of the download cache
+    pub async fn cache_stats(&self) -> Result<CacheStats> {
+        self.cache.read().await.stats().await
+    }
+
+    /// Keep a downloaded ISO from being evicted, or allow it again
+    pub async fn pin_iso(&self, file_name: &str, pinned: bool) -> Result<()> {
+        self.cache.write().await.set_pinned(file_name, pinned).await
+    }
+
+    /// Evict least recently used ISOs until the cache fits its quota. The
+    /// active ISO and mounted ones are kept.
+    pub async fn enforce_cache_quota(&self) -> Result<Vec<PathBuf>> {
+        let in_use = self.isos_in_use().await?;
+        let evicted = self.cache.write().await.enforce(&in_use).await?;
+        for iso in &evicted {
+            self.audit(
+                AuditAction::new(AuditSource::Iso, "node", "evict")
+                    .with_target(iso.display().to_string()),
+            )
+            .await;
+        }
+        if !evicted.is_empty() {
+            self.available_isos
+                .write()
+                .await
+                .retain(|iso| !evicted.contains(iso));
+        }
+        Ok(evicted)
+    }
+
+    /// The selected ISO and every mounted one
+    async fn isos_in_use(&self) -> Result<Vec<PathBuf>> {
+        let mut in_use: Vec<PathBuf> = self.active_iso.read().await.iter().cloned().collect();
+        for iso in self.available_isos.read().await.iter() {
+            if !in_use.contains(iso) && self.mounter.get_mount_point(iso)?.is_some() {
+                in_use.push(iso.clone());
+            }
+        }
+        Ok(in_use)
+    }
+
+    /// Mirror the central ISO repository, fetching only the changed blocks
+    /// of each image. ISOs in use are left for the next sync.
+    pub async fn sync_repository(&self) -> Result<RepoSyncReport> {
+        if self.offline {
+            return Err(IsoError::SyncFailed("node is offline".to_string()).into());
+        }
+        let _sync = self.sync_lock.lock().await;
+        let config = self.config.read().await.clone();
+        let dir = config
+            .sync
+            .target_dir
+            .clone()
+            .or_else(|| download_dir(&config))
+            .ok_or_else(|| IsoError::SyncFailed("No target directory".to_string()))?;
+
+        let in_use = self.isos_in_use().await?;
+        let report = sync::sync(&config.sync, &dir, &in_use).await?;
+
+        let changed: Vec<PathBuf> = report
+            .added
+            .iter()
+            .chain(&report.updated)
+            .map(|name| dir.join(name))
+            .collect();
+        let removed: Vec<PathBuf> = report.removed.iter().map(|name| dir.join(name)).collect();
+        if !changed.is_empty() || !removed.is_empty() {
+            let mut isos = self.available_isos.write().await;
+            isos.retain(|iso| !removed.contains(iso));
+            for path in changed {
+                if !isos.contains(&path) {
+                    isos.push(path);
+                }
+            }
+            drop(isos);
+            self.update_catalog().await;
+        }
+        Ok(report)
+    }
+
+    /// Record a use of `iso` if it is in the download cache
+    async fn touch_cached(&self, iso: &Path) {
+        let mut cache = self.cache.write().await;
+        if iso.parent() == Some(cache.dir()) {
+            if let Some(name) = iso.file_name() {
+                cache.touch(&name.to_string_lossy()).await;
+            }
+        }
+    }
+
+    /// Fetch every release feed, record what they announce in the catalog
+    /// and download configured products that have a newer release
+    pub async fn sync_feeds(&self) -> Result<FeedSyncReport> {
+        if self.offline {
+            return Err(IsoError::FeedFailed("node is offline".to_string()).into());
+        }
+        let config = self.config.read().await.feeds.clone();
+        let mut report = FeedSyncReport::default();
+        let mut releases = Vec::new();
+        for source in &config.sources {
+            match feeds::fetch(source, &config.cache_dir, config.require_signatures).await {
+                Ok(found) => releases.extend(found),
+                Err(e) => {
+                    warn!("Release feed sync failed: {}", e);
+                    report.errors.push(e.to_string());
+                }
+            }
+        }
+        report.releases = releases.len();
+
+        let catalog_path = self.config.read().await.catalog.path.clone();
+        let updates = {
+            let mut catalog = self.catalog.write().await;
+            // Keep the previous releases when every feed failed
+            if report.errors.is_empty() || !releases.is_empty() {
+                catalog.releases = releases;
+            }
+            if let Err(e) = catalog.save(&catalog_path).await {
+                warn!("Failed to save ISO catalog: {}", e);
+            }
+            catalog.updates()
+        };
+        info!("Release feeds list {} newer images", updates.len());
+
+        for release in feeds::wanted(&updates, &config.auto_download) {
+            match self.download_release(release).await {
+                Ok(path) => report.downloaded.push(path),
+                Err(e) => report.errors.push(e.to_string()),
+            }
+        }
+        report.updates = updates;
+        Ok(report)
+    }
+
+    /// Announced releases newer than the local images
+    pub async fn get_releases(&self) -> Vec<FeedRelease> {
+        self.catalog.read().await.updates()
+    }
+
+    /// Download an announced release from where its feed says it is
+    pub async fn download_release(&self, release: &FeedRelease) -> Result<PathBuf> {
+        info!(
+            "Downloading {} {} from release feed {}",
+            release.distro, release.version, release.source
+        );
+        let mut request = DownloadRequest::new(release.file_name().to_string());
+        if let Some(sha256) = &release.sha256 {
+            request = request.with_sha256(sha256.clone());
+        }
+        let download = DownloadConfig {
+            mirrors: vec![release.base_url().to_string()],
+            ..self.config.read().await.download.clone()
+        };
+        self.download_with(&request, &download).await
+    }
+
+    pub fn subscribe_deploys(&self) -> broadcast::Receiver<DeployProgress> {
+        self.deploy_tx.subscribe()
+    }
+
+    /// Ventoy installation on `device`, if there is one
+    pub async fn detect_ventoy(&self, device: &str) -> Result<Option<VentoyStick>> {
+        let device = device.to_string();
+        let disk = tokio::task::spawn_blocking(move || crate::disk::inventory::inspect(&device))
+            .await
+            .map_err(|e| IsoError::DeployFailed(e.to_string()))??;
+        Ok(ventoy::detect(&disk))
+    }
+
+    /// The Ventoy stick on `device` when ISOs should be copied onto it
+    /// instead of repartitioning. `None` unless Ventoy mode is enabled.
+    pub async fn ventoy_target(&self, device: &str) -> Option<VentoyStick> {
+        if !self.config.read().await.ventoy.enabled {
+            return None;
+        }
+        match self.detect_ventoy(device).await {
+            Ok(stick) => stick,
+            Err(e) => {
+                warn!("Could not check {} for Ventoy: {}", device, e);
+                None
+            }
+        }
+    }
+
+    /// Copy `isos` onto the data partition of a Ventoy stick
+    pub async fn deploy_to_ventoy(
+        &self,
+        stick: &VentoyStick,
+        isos: &[PathBuf],
+    ) -> Result<VentoyDeployment> {
+        let config = self.config.read().await.clone();
+        let mount_dir = config.mount_point.join(".ventoy");
+        if let Some(dry_run) = &self.dry_run {
+            return Ok(self
+                .deployer
+                .rehearse(stick, isos, &config.ventoy, &mount_dir, dry_run));
+        }
+
+        self.set_state(IsoManagerState::Installing).await;
+        let result = self
+            .deployer
+            .deploy(stick, isos, &config.ventoy, &mount_dir)
+            .await;
+        if let Err(e) = &result {
+            error!("Ventoy deployment to {} failed: {}", stick.device, e);
+        }
+        self.audit(
+            AuditAction::new(AuditSource::Iso, "node", "deploy_ventoy")
+                .with_target(stick.device.clone())
+                .with_result(&result),
+        )
+        .await;
+        self.set_state(IsoManagerState::Idle).await;
+        result
+    }
+
+    pub async fn get_windows_setup(&self) -> Option<WindowsSetup> {
+        self.installer.get_windows_setup().await
     }
 
     pub async fn get_available_isos(&self) -> Vec<PathBuf> {
         self.available_isos.read().await.clone()
     }
 
+    /// Catalog entries matching `query`
+    pub async fn get_catalog(&self, query: &CatalogQuery) -> Vec<IsoCatalogEntry> {
+        self.catalog.read().await.query(query)
+    }
+
+    pub async fn get_catalog_entry(&self, file_name: &str) -> Result<IsoCatalogEntry> {
+        self.catalog
+            .read()
+            .await
+            .get(file_name)
+            .cloned()
+            .ok_or_else(|| IsoError::NotFound(file_name.to_string()).into())
+    }
+
+    /// Identify the available ISOs, reading only new or changed images, and
+    /// persist the catalog
+    async fn update_catalog(&self) {
+        let path = self.config.read().await.catalog.path.clone();
+        let isos = self.available_isos.read().await.clone();
+
+        let mut catalog = self.catalog.write().await;
+        if catalog.entries.is_empty() {
+            *catalog = IsoCatalog::load(&path).await;
+        }
+        catalog.refresh(&isos).await;
+        if let Err(e) = catalog.save(&path).await {
+            warn!("Failed to save ISO catalog: {}", e);
+        }
+    }
+
     pub async fn get_active_iso(&self) -> Option<PathBuf> {
         self.active_iso.read().await.clone()
     }
@@ -209,13 +1007,72 @@ impl IsoManager {
         *self.config.write().await = config.read().await.clone();
     }
 
+    /// Also verifies every mount is still in place and no loop device was
+    /// left attached to an image without a mount
     pub async fn health_check(&self) -> Result<()> {
         let state = self.get_state().await;
-        match state {
-            IsoManagerState::Error(e) => Err(IsoError::HealthCheckFailed(e)),
-            _ => Ok(()),
+        if let IsoManagerState::Error(e) = state {
+            return Err(IsoError::HealthCheckFailed(e).into());
+        }
+
+        let lost = self.mounter.lost_mounts()?;
+        if !lost.is_empty() {
+            return Err(IsoError::MountFailed(format!(
+                "No longer mounted: {}",
+                display_paths(&lost)
+            ))
+            .into());
         }
+
+        let leaked = self.mounter.leaked_loop_devices()?;
+        if !leaked.is_empty() {
+            return Err(IsoError::MountFailed(format!(
+                "Loop devices left attached: {}",
+                display_paths(&leaked)
+            ))
+            .into());
+        }
+        Ok(())
     }
+
+    /// Repair what `health_check` reports: lost mounts are dropped and the
+    /// active ISO mounted again, leaked loop devices detached. Running
+    /// installers are left alone.
+    pub async fn recover(&self) -> Result<()> {
+        for source in self.mounter.lost_mounts()? {
+            warn!("{} was unmounted elsewhere", source.display());
+            self.mounter.forget(&source)?;
+        }
+        self.mounter.detach_leaked()?;
+
+        let active = self.active_iso.read().await.clone();
+        match active {
+            Some(iso) if !self.mounter.is_mounted(&iso)? => {
+                self.active_mount.write().await.take();
+                self.mount_iso(&iso).await?;
+            }
+            Some(_) => self.set_state(IsoManagerState::Ready).await,
+            None => self.set_state(IsoManagerState::Idle).await,
+        }
+        Ok(())
+    }
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Where downloads go and the ISO cache lives
+pub fn download_dir(config: &IsoConfig) -> Option<PathBuf> {
+    config
+        .download
+        .target_dir
+        .clone()
+        .or_else(|| config.search_paths.first().cloned())
 }
 
 #[cfg(test)]
@@ -231,6 +1088,23 @@ mod tests {
         assert!(manager.get_active_iso().await.is_none());
     }
 
+    #[tokio::test]
+    async fn test_offline_fetches_nothing() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = Arc::new(RwLock::new(IsoConfig {
+            search_paths: vec![temp_dir.path().to_path_buf()],
+            ..IsoConfig::default()
+        }));
+        let manager = IsoManager::new(config).with_offline(true);
+
+        let request = DownloadRequest::new("debian-cd/debian-12.5.0-amd64-netinst.iso".to_string());
+        let err = manager.download_iso(&request).await.unwrap_err();
+        assert!(err.to_string().contains("offline"), "{}", err);
+        assert_eq!(manager.get_state().await, IsoManagerState::Idle);
+        assert!(manager.sync_repository().await.is_err());
+        assert!(manager.sync_feeds().await.is_err());
+    }
+
     #[tokio::test]
     async fn test_scan_empty_directory() {
         let config = Arc::new(RwLock::new(IsoConfig::default()));
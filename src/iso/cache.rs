@@ -0,0 +1,345 @@
+use crate::config::CacheConfig;
+use crate::error::{Error, IsoError, Result};
+use crate::monitoring::clock::unix_now;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tracing::{info, warn};
+
+/// Use of one cached ISO, keyed by file name in the index
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CacheRecord {
+    /// Unix seconds
+    last_used: u64,
+    #[serde(default)]
+    pinned: bool,
+}
+
+/// An ISO in the download directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CachedIso {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Unix seconds; the modification time until the ISO is first used
+    pub last_used: u64,
+    pub pinned: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub used_bytes: u64,
+    pub quota_bytes: Option<u64>,
+    pub pinned: usize,
+    /// Requested ISOs that were already downloaded
+    pub hits: u64,
+    /// Requested ISOs that had to be downloaded
+    pub misses: u64,
+    pub isos: Vec<CachedIso>,
+}
+
+/// Downloaded ISOs under a size quota. The least recently used ISOs are
+/// evicted first; pinned ones and ISOs in use are kept.
+#[derive(Debug)]
+pub struct IsoCache {
+    dir: PathBuf,
+    config: CacheConfig,
+    records: HashMap<String, CacheRecord>,
+    hits: u64,
+    misses: u64,
+}
+
+impl IsoCache {
+    pub fn new(dir: impl Into<PathBuf>, config: CacheConfig) -> Self {
+        Self {
+            dir: dir.into(),
+            config,
+            records: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Open the cache of `dir`, reading last use and pins from the index
+    pub async fn load(dir: impl Into<PathBuf>, config: CacheConfig) -> Self {
+        let mut cache = Self::new(dir, config);
+        match fs::read(&cache.config.index_path).await {
+            Ok(content) => match serde_json::from_slice(&content) {
+                Ok(records) => cache.records = records,
+                Err(e) => warn!(
+                    "Ignoring unreadable ISO cache index {}: {}",
+                    cache.config.index_path.display(),
+                    e
+                ),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!(
+                "Failed to read ISO cache index {}: {}",
+                cache.config.index_path.display(),
+                e
+            ),
+        }
+        cache
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn quota_bytes(&self) -> Option<u64> {
+        self.config.quota_mb.map(|mb| mb * 1024 * 1024)
+    }
+
+    /// Count a request for `file_name` as a hit when it was already cached
+    pub async fn record_lookup(&mut self, file_name: &str, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        self.touch(file_name).await;
+    }
+
+    /// Mark `file_name` as just used
+    pub async fn touch(&mut self, file_name: &str) {
+        self.records
+            .entry(file_name.to_string())
+            .or_default()
+            .last_used = unix_now();
+        self.save().await;
+    }
+
+    pub fn is_pinned(&self, file_name: &str) -> bool {
+        self.config.pinned.iter().any(|name| name == file_name)
+            || self.records.get(file_name).is_some_and(|r| r.pinned)
+    }
+
+    /// Pin or unpin a cached ISO. ISOs pinned in the configuration stay
+    /// pinned.
+    pub async fn set_pinned(&mut self, file_name: &str, pinned: bool) -> Result<()> {
+        if !self.dir.join(file_name).is_file() {
+            return Err(IsoError::NotFound(file_name.to_string()).into());
+        }
+        if !pinned && self.config.pinned.iter().any(|name| name == file_name) {
+            return Err(Error::General(format!(
+                "{} is pinned in [iso.cache] pinned",
+                file_name
+            )));
+        }
+        self.records
+            .entry(file_name.to_string())
+            .or_default()
+            .pinned = pinned;
+        self.save().await;
+        Ok(())
+    }
+
+    /// ISOs in the cache directory
+    pub async fn scan(&self) -> Result<Vec<CachedIso>> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut isos = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let is_iso = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("iso"));
+            let metadata = entry.metadata().await?;
+            if !is_iso || !metadata.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            isos.push(CachedIso {
+                size: metadata.len(),
+                last_used: self
+                    .records
+                    .get(&file_name)
+                    .map_or(modified, |r| r.last_used.max(modified)),
+                pinned: self.is_pinned(&file_name),
+                path,
+            });
+        }
+        isos.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(isos)
+    }
+
+    pub async fn stats(&self) -> Result<CacheStats> {
+        let isos = self.scan().await?;
+        Ok(CacheStats {
+            used_bytes: isos.iter().map(|iso| iso.size).sum(),
+            quota_bytes: self.quota_bytes(),
+            pinned: isos.iter().filter(|iso| iso.pinned).count(),
+            hits: self.hits,
+            misses: self.misses,
+            isos,
+        })
+    }
+
+    /// Delete ISOs until the cache fits its quota, skipping `in_use`.
+    /// Returns the deleted ISOs.
+    pub async fn enforce(&mut self, in_use: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let Some(quota) = self.quota_bytes() else {
+            return Ok(Vec::new());
+        };
+        let isos = self.scan().await?;
+        let evict = plan_eviction(&isos, quota, in_use);
+
+        let mut evicted = Vec::new();
+        for path in evict {
+            if let Err(e) = fs::remove_file(&path).await {
+                warn!("Failed to evict {}: {}", path.display(), e);
+                continue;
+            }
+            // Published checksum the catalog reads next to the ISO
+            let _ = fs::remove_file(sidecar(&path)).await;
+            if let Some(name) = path.file_name() {
+                self.records.remove(&*name.to_string_lossy());
+            }
+            info!("Evicted {} from the ISO cache", path.display());
+            evicted.push(path);
+        }
+
+        let used: u64 = isos
+            .iter()
+            .filter(|iso| !evicted.contains(&iso.path))
+            .map(|iso| iso.size)
+            .sum();
+        if used > quota {
+            warn!(
+                "ISO cache holds {} MiB, over its {} MiB quota; the rest is pinned or in use",
+                used / (1024 * 1024),
+                quota / (1024 * 1024)
+            );
+        }
+        if !evicted.is_empty() {
+            self.save().await;
+        }
+        Ok(evicted)
+    }
+
+    async fn save(&self) {
+        let result = async {
+            if let Some(parent) = self.config.index_path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            let json = serde_json::to_vec_pretty(&self.records)
+                .map_err(|e| Error::General(e.to_string()))?;
+            fs::write(&self.config.index_path, json).await?;
+            Ok::<_, Error>(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to save ISO cache index: {}", e);
+        }
+    }
+}
+
+/// ISOs to delete, least recently used first, so the rest fits in `quota`
+fn plan_eviction(isos: &[CachedIso], quota: u64, in_use: &[PathBuf]) -> Vec<PathBuf> {
+    let mut used: u64 = isos.iter().map(|iso| iso.size).sum();
+    let mut candidates: Vec<&CachedIso> = isos
+        .iter()
+        .filter(|iso| !iso.pinned && !in_use.contains(&iso.path))
+        .collect();
+    candidates.sort_by_key(|iso| iso.last_used);
+
+    let mut evict = Vec::new();
+    for iso in candidates {
+        if used <= quota {
+            break;
+        }
+        used -= iso.size;
+        evict.push(iso.path.clone());
+    }
+    evict
+}
+
+fn sidecar(iso: &Path) -> PathBuf {
+    let mut name = iso.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iso(name: &str, size: u64, last_used: u64, pinned: bool) -> CachedIso {
+        CachedIso {
+            path: PathBuf::from("/installers").join(name),
+            size,
+            last_used,
+            pinned,
+        }
+    }
+
+    #[test]
+    fn test_plan_eviction() {
+        let isos = [
+            iso("debian-11.iso", 400, 100, false),
+            iso("debian-12.iso", 600, 300, false),
+            iso("ubuntu-22.04.iso", 500, 50, true),
+            iso("ubuntu-24.04.iso", 700, 200, false),
+            iso("fedora-40.iso", 300, 10, false),
+        ];
+        let in_use = [PathBuf::from("/installers/fedora-40.iso")];
+
+        // 2500 bytes used: the oldest unpinned ISOs not in use go first
+        assert_eq!(
+            plan_eviction(&isos, 1500, &in_use),
+            [
+                PathBuf::from("/installers/debian-11.iso"),
+                PathBuf::from("/installers/ubuntu-24.04.iso"),
+            ]
+        );
+        assert!(plan_eviction(&isos, 2500, &in_use).is_empty());
+        // Pinned and in-use ISOs are kept even over the quota
+        assert_eq!(plan_eviction(&isos, 0, &in_use).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_enforce_quota_and_pins() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = CacheConfig {
+            quota_mb: Some(0),
+            pinned: vec!["keep.iso".to_string()],
+            index_path: dir.path().join("state/iso-cache.json"),
+        };
+        let isos = dir.path().join("isos");
+        std::fs::create_dir_all(&isos).unwrap();
+        for name in ["keep.iso", "pinned.iso", "old.iso"] {
+            std::fs::write(isos.join(name), b"iso").unwrap();
+        }
+        std::fs::write(isos.join("old.iso.sha256"), b"sum").unwrap();
+
+        let mut cache = IsoCache::load(&isos, config.clone()).await;
+        cache.set_pinned("pinned.iso", true).await.unwrap();
+        assert!(cache.set_pinned("keep.iso", false).await.is_err());
+        assert!(cache.set_pinned("missing.iso", true).await.is_err());
+        cache.record_lookup("old.iso", true).await;
+        cache.record_lookup("pinned.iso", false).await;
+
+        let evicted = cache.enforce(&[]).await.unwrap();
+        assert_eq!(evicted, [isos.join("old.iso")]);
+        assert!(!isos.join("old.iso.sha256").exists());
+
+        // Pins survive a restart
+        let cache = IsoCache::load(&isos, config).await;
+        let stats = cache.stats().await.unwrap();
+        assert_eq!(stats.isos.len(), 2);
+        assert_eq!(stats.pinned, 2);
+        assert_eq!(stats.used_bytes, 6);
+        assert_eq!(stats.quota_bytes, Some(0));
+    }
+}
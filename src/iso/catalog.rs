@@ -0,0 +1,585 @@
+use super::feeds::{self, FeedRelease};
+use super::ventoy;
+use crate::disk::multiboot::{self, Distro};
+use crate::error::{IsoError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// CPU architecture an image boots on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+    I386,
+}
+
+impl Arch {
+    /// Recognise the names distributions use in labels and file names
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "amd64" | "x86_64" | "x64" => Some(Self::X86_64),
+            "arm64" | "aarch64" | "aa64" | "a64" => Some(Self::Aarch64),
+            "i386" | "i686" | "x86" | "ia32" => Some(Self::I386),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::X86_64 => write!(f, "x86_64"),
+            Self::Aarch64 => write!(f, "aarch64"),
+            Self::I386 => write!(f, "i386"),
+        }
+    }
+}
+
+/// Firmware the image can boot from, read from its El Torito boot catalog
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootSupport {
+    pub bios: bool,
+    pub uefi: bool,
+}
+
+/// What an ISO contains, as far as its metadata tells
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IsoCatalogEntry {
+    pub path: PathBuf,
+    pub file_name: String,
+    pub volume_id: String,
+    pub distro: Option<String>,
+    pub version: Option<String>,
+    pub arch: Option<Arch>,
+    /// Edition or media type, e.g. "server", "netinst", "live"
+    pub variant: Option<String>,
+    pub size: u64,
+    /// Published SHA-256 from `<iso>.sha256` or `SHA256SUMS`
+    pub checksum: Option<String>,
+    pub boot: BootSupport,
+    /// Modification time (Unix seconds) the entry was read at
+    pub modified: u64,
+}
+
+impl IsoCatalogEntry {
+    /// Human readable name, e.g. "Ubuntu 24.04 server (x86_64)"
+    pub fn display_name(&self) -> String {
+        let Some(distro) = &self.distro else {
+            return self.file_name.clone();
+        };
+        let mut name = distro.clone();
+        for part in [&self.version, &self.variant].into_iter().flatten() {
+            name.push(' ');
+            name.push_str(part);
+        }
+        if let Some(arch) = self.arch {
+            name.push_str(&format!(" ({})", arch));
+        }
+        name
+    }
+
+    fn unidentified(path: &Path, size: u64, modified: u64) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            file_name: file_name(path),
+            volume_id: String::new(),
+            distro: None,
+            version: None,
+            arch: None,
+            variant: None,
+            size,
+            checksum: None,
+            boot: BootSupport::default(),
+            modified,
+        }
+    }
+}
+
+/// Filters for catalog queries; unset fields match everything
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CatalogQuery {
+    pub distro: Option<String>,
+    pub version: Option<String>,
+    pub arch: Option<Arch>,
+    pub variant: Option<String>,
+}
+
+impl CatalogQuery {
+    fn matches(&self, entry: &IsoCatalogEntry) -> bool {
+        let text = |wanted: &Option<String>, value: &Option<String>| match (wanted, value) {
+            (None, _) => true,
+            (Some(wanted), Some(value)) => value.eq_ignore_ascii_case(wanted),
+            (Some(_), None) => false,
+        };
+        text(&self.distro, &entry.distro)
+            && text(&self.variant, &entry.variant)
+            && self.version.as_ref().is_none_or(|v| {
+                entry
+                    .version
+                    .as_ref()
+                    .is_some_and(|e| e.starts_with(v.as_str()))
+            })
+            && self.arch.is_none_or(|a| entry.arch == Some(a))
+    }
+}
+
+/// Known ISOs, persisted as JSON so unchanged images are not re-read
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IsoCatalog {
+    pub entries: Vec<IsoCatalogEntry>,
+    /// Images the release feeds announced at the last sync
+    #[serde(default)]
+    pub releases: Vec<FeedRelease>,
+}
+
+impl IsoCatalog {
+    /// Read the catalog file; a missing or unreadable file gives an empty
+    /// catalog that is rebuilt on the next scan
+    pub async fn load(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) => {
+                debug!("No ISO catalog at {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable ISO catalog {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    /// Write through a temporary file so a crash never leaves half a catalog
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .await
+                .map_err(|e| catalog_error(dir, e))?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| IsoError::InvalidFormat(format!("ISO catalog: {}", e)))?;
+        let partial = path.with_extension("json.tmp");
+        fs::write(&partial, json)
+            .await
+            .map_err(|e| catalog_error(&partial, e))?;
+        fs::rename(&partial, path)
+            .await
+            .map_err(|e| catalog_error(path, e))
+    }
+
+    /// Rebuild the catalog for `isos`, reusing entries whose size and
+    /// modification time are unchanged
+    pub async fn refresh(&mut self, isos: &[PathBuf]) {
+        let mut entries = Vec::with_capacity(isos.len());
+        for iso in isos {
+            let (size, modified) = match fs::metadata(iso).await {
+                Ok(meta) => (meta.len(), modified_secs(&meta)),
+                Err(e) => {
+                    warn!("Skipping {}: {}", iso.display(), e);
+                    continue;
+                }
+            };
+            let cached = self
+                .entries
+                .iter()
+                .find(|e| &e.path == iso && e.size == size && e.modified == modified);
+            let entry = match cached {
+                Some(entry) => entry.clone(),
+                None => match inspect(iso, size, modified).await {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        warn!("Could not identify {}: {}", iso.display(), e);
+                        IsoCatalogEntry::unidentified(iso, size, modified)
+                    }
+                },
+            };
+            entries.push(entry);
+        }
+        info!(
+            "ISO catalog has {} entries ({} identified)",
+            entries.len(),
+            entries.iter().filter(|e| e.distro.is_some()).count()
+        );
+        self.entries = entries;
+    }
+
+    /// Look an ISO up by file name
+    pub fn get(&self, file_name: &str) -> Option<&IsoCatalogEntry> {
+        self.entries.iter().find(|e| e.file_name == file_name)
+    }
+
+    /// Announced releases newer than the local images
+    pub fn updates(&self) -> Vec<FeedRelease> {
+        feeds::updates(&self.releases, &self.entries)
+    }
+
+    pub fn query(&self, query: &CatalogQuery) -> Vec<IsoCatalogEntry> {
+        self.entries
+            .iter()
+            .filter(|e| query.matches(e))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Identification gathered from one metadata source
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(super) struct Identity {
+    pub(super) distro: Option<String>,
+    pub(super) version: Option<String>,
+    pub(super) arch: Option<Arch>,
+    pub(super) variant: Option<String>,
+}
+
+impl Identity {
+    /// Fill the gaps from a less reliable source
+    fn or(self, other: Identity) -> Identity {
+        Identity {
+            distro: self.distro.or(other.distro),
+            version: self.version.or(other.version),
+            arch: self.arch.or(other.arch),
+            variant: self.variant.or(other.variant),
+        }
+    }
+}
+
+/// Read an ISO's metadata with `isoinfo`. `.disk/info` is the most precise
+/// source, then the volume label, then the file layout.
+async fn inspect(iso: &Path, size: u64, modified: u64) -> Result<IsoCatalogEntry> {
+    let details = isoinfo(&["-d", "-i"], iso).await?;
+    let listing = isoinfo(&["-R", "-f", "-i"], iso).await?;
+    let files: Vec<&str> = listing.lines().map(str::trim).collect();
+
+    let volume_id = details
+        .lines()
+        .find_map(|line| line.strip_prefix("Volume id:"))
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default();
+    let disk_info = if files.contains(&"/.disk/info") {
+        isoinfo(&["-R", "-x", "/.disk/info", "-i"], iso).await?
+    } else {
+        String::new()
+    };
+
+    let identity = parse_disk_info(&disk_info)
+        .or(parse_volume_id(&volume_id))
+        .or(parse_listing(&files));
+
+    Ok(IsoCatalogEntry {
+        path: iso.to_path_buf(),
+        file_name: file_name(iso),
+        volume_id,
+        distro: identity.distro,
+        version: identity.version,
+        arch: identity.arch,
+        variant: identity.variant,
+        size,
+        checksum: ventoy::published_checksum(iso).await,
+        boot: parse_boot(&details, &files),
+        modified,
+    })
+}
+
+/// `.disk/info` of Debian and Ubuntu media, e.g.
+/// `Debian GNU/Linux 12.5.0 "Bookworm" - Official amd64 NETINST with firmware`
+fn parse_disk_info(info: &str) -> Identity {
+    let mut identity = Identity::default();
+    let mut tokens = info.lines().next().unwrap_or_default().split_whitespace();
+    let Some(name) = tokens.next() else {
+        return identity;
+    };
+
+    // Ubuntu flavours name the edition in the first word: "Ubuntu-Server"
+    let (name, edition) = match name.split_once('-') {
+        Some((name, edition)) => (name, Some(edition.to_lowercase())),
+        None => (name, None),
+    };
+    identity.distro = Some(distro_name(name).unwrap_or_else(|| name.to_string()));
+    identity.variant = edition;
+
+    let mut after_arch = false;
+    for token in tokens {
+        if identity.version.is_none() && is_version(token) {
+            identity.version = Some(token.to_string());
+        } else if identity.arch.is_none() && Arch::parse(token).is_some() {
+            identity.arch = Arch::parse(token);
+            after_arch = true;
+            continue;
+        } else if after_arch
+            && identity.variant.is_none()
+            && token.chars().all(|c| c.is_ascii_uppercase())
+        {
+            identity.variant = Some(token.to_lowercase());
+        }
+        after_arch = false;
+    }
+    identity
+}
+
+/// Volume labels such as `Ubuntu-Server 24.04 LTS amd64`,
+/// `Fedora-WS-Live-40-1-14` or `CCCOMA_X64FRE_EN-US_DV9`
+fn parse_volume_id(volume_id: &str) -> Identity {
+    let label = volume_id
+        .replace("x86_64", "amd64")
+        .replace("X86_64", "amd64");
+    let tokens: Vec<&str> = label
+        .split([' ', '_', '-'])
+        .filter(|t| !t.is_empty())
+        .collect();
+    let Some(distro) = tokens.first().and_then(|t| distro_name(t)) else {
+        return Identity::default();
+    };
+
+    let mut identity = Identity {
+        distro: Some(distro),
+        ..Identity::default()
+    };
+    if identity.distro.as_deref() == Some("Windows") {
+        // Architecture and build type share a token: X64FRE, A64FRE
+        identity.arch = tokens.iter().find_map(|t| t.get(..3).and_then(Arch::parse));
+        return identity;
+    }
+
+    for token in &tokens[1..] {
+        if identity.version.is_none() && is_version(token) {
+            identity.version = Some(token.to_string());
+        } else if identity.arch.is_none() && Arch::parse(token).is_some() {
+            identity.arch = Arch::parse(token);
+        } else if identity.variant.is_none() {
+            identity.variant = variant_name(token);
+        }
+    }
+    identity
+}
+
+/// Image file names follow the same pattern as volume labels, e.g.
+/// `debian-12.5.0-amd64-netinst.iso`
+pub(super) fn parse_file_name(name: &str) -> Identity {
+    let stem = name
+        .strip_suffix(".iso")
+        .or_else(|| name.strip_suffix(".ISO"))
+        .unwrap_or(name);
+    parse_volume_id(stem)
+}
+
+/// Last resort: the boot files the multiboot builder also looks for
+fn parse_listing(files: &[&str]) -> Identity {
+    let (distro, variant) = match multiboot::detect_distro(files) {
+        Some(Distro::Ubuntu { .. }) => (Some("Ubuntu"), None),
+        Some(Distro::DebianLive { .. }) => (Some("Debian"), Some("live")),
+        Some(Distro::DebianInstaller) => (Some("Debian"), None),
+        Some(Distro::Arch) => (Some("Arch Linux"), None),
+        Some(Distro::Anaconda) | None => {
+            let windows = files
+                .iter()
+                .any(|f| f.eq_ignore_ascii_case("/sources/install.wim"))
+                || files
+                    .iter()
+                    .any(|f| f.eq_ignore_ascii_case("/sources/install.esd"));
+            (windows.then_some("Windows"), None)
+        }
+    };
+
+    Identity {
+        distro: distro.map(str::to_string),
+        version: None,
+        arch: files.iter().find_map(|f| efi_arch(f)),
+        variant: variant.map(str::to_string),
+    }
+}
+
+/// BIOS boot needs an x86 El Torito entry; UEFI boot is recognised by the
+/// removable-media loader the firmware looks for
+fn parse_boot(details: &str, files: &[&str]) -> BootSupport {
+    let el_torito = details.contains("El Torito");
+    BootSupport {
+        bios: el_torito && details.contains("Arch 0 (x86)"),
+        uefi: files.iter().any(|f| efi_arch(f).is_some()),
+    }
+}
+
+/// Architecture of a removable-media UEFI loader, e.g. `/EFI/BOOT/BOOTX64.EFI`
+fn efi_arch(file: &str) -> Option<Arch> {
+    let file = file.to_ascii_lowercase();
+    let loader = file.strip_prefix("/efi/boot/boot")?.strip_suffix(".efi")?;
+    Arch::parse(loader)
+}
+
+pub(super) fn distro_name(token: &str) -> Option<String> {
+    let name = match token.to_ascii_lowercase().as_str() {
+        "ubuntu" => "Ubuntu",
+        "kubuntu" => "Kubuntu",
+        "xubuntu" => "Xubuntu",
+        "debian" => "Debian",
+        "fedora" => "Fedora",
+        "arch" | "archlinux" => "Arch Linux",
+        "rocky" => "Rocky Linux",
+        "almalinux" => "AlmaLinux",
+        "centos" => "CentOS",
+        "rhel" => "RHEL",
+        "opensuse" => "openSUSE",
+        "linuxmint" | "mint" => "Linux Mint",
+        "kali" => "Kali Linux",
+        "cccoma" | "ccsa" => "Windows",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+fn variant_name(token: &str) -> Option<String> {
+    let variant = match token.to_ascii_lowercase().as_str() {
+        "ws" | "workstation" => "workstation",
+        "server" => "server",
+        "desktop" => "desktop",
+        "live" => "live",
+        "dvd" => "dvd",
+        "netinst" => "netinst",
+        "minimal" => "minimal",
+        "boot" => "boot",
+        _ => return None,
+    };
+    Some(variant.to_string())
+}
+
+pub(super) fn is_version(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_digit())
+        && token.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn modified_secs(meta: &std::fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+async fn isoinfo(args: &[&str], iso: &Path) -> Result<String> {
+    let output = Command::new("isoinfo")
+        .args(args)
+        .arg(iso)
+        .output()
+        .await
+        .map_err(|e| IsoError::InvalidFormat(format!("Failed to run isoinfo: {}", e)))?;
+    if !output.status.success() {
+        return Err(IsoError::InvalidFormat(format!(
+            "{}: {}",
+            iso.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn catalog_error(path: &Path, e: std::io::Error) -> crate::error::Error {
+    IsoError::InvalidFormat(format!("ISO catalog {}: {}", path.display(), e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_disk_info() {
+        assert_eq!(
+            parse_disk_info(
+                "Debian GNU/Linux 12.5.0 \"Bookworm\" - Official amd64 NETINST with firmware 20240210-11:27"
+            ),
+            Identity {
+                distro: Some("Debian".to_string()),
+                version: Some("12.5.0".to_string()),
+                arch: Some(Arch::X86_64),
+                variant: Some("netinst".to_string()),
+            }
+        );
+        assert_eq!(
+            parse_disk_info("Ubuntu-Server 24.04 LTS \"Noble Numbat\" - Release amd64 (20240423)"),
+            Identity {
+                distro: Some("Ubuntu".to_string()),
+                version: Some("24.04".to_string()),
+                arch: Some(Arch::X86_64),
+                variant: Some("server".to_string()),
+            }
+        );
+        assert_eq!(parse_disk_info(""), Identity::default());
+    }
+
+    #[test]
+    fn test_parse_volume_id_and_listing() {
+        let fedora = parse_volume_id("Fedora-WS-Live-40-1-14");
+        assert_eq!(fedora.distro.as_deref(), Some("Fedora"));
+        assert_eq!(fedora.version.as_deref(), Some("40"));
+        assert_eq!(fedora.variant.as_deref(), Some("workstation"));
+
+        let rocky = parse_volume_id("Rocky-9-4-x86_64-dvd");
+        assert_eq!(rocky.arch, Some(Arch::X86_64));
+        assert_eq!(rocky.variant.as_deref(), Some("dvd"));
+
+        let windows = parse_volume_id("CCCOMA_X64FRE_EN-US_DV9");
+        assert_eq!(windows.distro.as_deref(), Some("Windows"));
+        assert_eq!(windows.arch, Some(Arch::X86_64));
+
+        assert_eq!(parse_volume_id("CDROM"), Identity::default());
+
+        let files = [
+            "/EFI/BOOT/BOOTAA64.EFI",
+            "/casper/vmlinuz",
+            "/casper/initrd",
+        ];
+        let ubuntu = parse_listing(&files);
+        assert_eq!(ubuntu.distro.as_deref(), Some("Ubuntu"));
+        assert_eq!(ubuntu.arch, Some(Arch::Aarch64));
+
+        let details =
+            "El Torito VD version 1 found, boot catalog is in sector 33\n    Arch 0 (x86)\n";
+        assert_eq!(
+            parse_boot(details, &files),
+            BootSupport {
+                bios: true,
+                uefi: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_query_and_persistence() {
+        let mut ubuntu = IsoCatalogEntry::unidentified(Path::new("/installers/u.iso"), 10, 1);
+        ubuntu.distro = Some("Ubuntu".to_string());
+        ubuntu.version = Some("24.04".to_string());
+        ubuntu.arch = Some(Arch::X86_64);
+        ubuntu.variant = Some("server".to_string());
+        let other = IsoCatalogEntry::unidentified(Path::new("/installers/x.iso"), 20, 1);
+        let catalog = IsoCatalog {
+            entries: vec![ubuntu.clone(), other],
+            ..IsoCatalog::default()
+        };
+
+        assert_eq!(ubuntu.display_name(), "Ubuntu 24.04 server (x86_64)");
+        assert_eq!(catalog.get("x.iso").unwrap().display_name(), "x.iso");
+
+        let query = CatalogQuery {
+            distro: Some("ubuntu".to_string()),
+            version: Some("24".to_string()),
+            ..CatalogQuery::default()
+        };
+        assert_eq!(catalog.query(&query), vec![ubuntu]);
+        assert_eq!(catalog.query(&CatalogQuery::default()).len(), 2);
+
+        let json = serde_json::to_string(&catalog).unwrap();
+        assert_eq!(serde_json::from_str::<IsoCatalog>(&json).unwrap(), catalog);
+    }
+}
@@ -0,0 +1,240 @@
+use super::installer::InstallerInfo;
+use crate::config::DetectorConfig;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Recognizes installer media the built-in detection does not know, e.g.
+/// ESXi, pfSense or TrueNAS. Detectors are built in or configured with
+/// `[[iso.installer.detectors]]`, registered with the `IsoInstaller` and
+/// run after the built-in checks on a blocking thread, so they may read
+/// the media synchronously.
+pub trait InstallerDetector: Send + Sync {
+    /// `os_type` of the installers this detector reports; also selects it
+    /// for validating and launching them
+    fn os_type(&self) -> &str;
+
+    /// The installer on the media mounted at `mount_path`, if recognized
+    fn detect(&self, mount_path: &Path) -> Option<InstallerInfo>;
+
+    /// Whether a detected installer is still usable
+    fn validate(&self, installer: &InstallerInfo) -> bool {
+        installer.path.exists()
+    }
+
+    /// Process that runs the installer. Without one the installer can be
+    /// listed but not started.
+    fn command(&self, _installer: &InstallerInfo, _auto_mode: bool) -> Option<Command> {
+        None
+    }
+}
+
+/// VMware ESXi installer media, recognized by its `VMKBOOT.B00` module
+pub struct EsxiDetector;
+
+impl InstallerDetector for EsxiDetector {
+    fn os_type(&self) -> &str {
+        "esxi"
+    }
+
+    fn detect(&self, mount_path: &Path) -> Option<InstallerInfo> {
+        find_file(mount_path, "vmkboot.b00")?;
+        let version = find_file(mount_path, "boot.cfg")
+            .and_then(|cfg| std::fs::read_to_string(cfg).ok())
+            .and_then(|cfg| parse_esxi_build(&cfg));
+        Some(InstallerInfo {
+            name: "VMware ESXi Installer".to_string(),
+            path: mount_path.to_path_buf(),
+            os_type: self.os_type().to_string(),
+            version,
+            // The installer only runs by booting the media
+            auto_installable: false,
+            live: None,
+        })
+    }
+
+    fn validate(&self, installer: &InstallerInfo) -> bool {
+        find_file(&installer.path, "vmkboot.b00").is_some()
+    }
+}
+
+/// A detector from the configuration: media with every marker file
+pub struct ConfigDetector {
+    config: DetectorConfig,
+}
+
+impl ConfigDetector {
+    pub fn new(config: DetectorConfig) -> Self {
+        Self { config }
+    }
+
+    fn validate_markers(&self, root: &Path) -> bool {
+        self.config.markers.iter().all(|marker| {
+            marker
+                .split('/')
+                .filter(|part| !part.is_empty())
+                .try_fold(root.to_path_buf(), |dir, part| find_file(&dir, part))
+                .is_some()
+        })
+    }
+}
+
+impl InstallerDetector for ConfigDetector {
+    fn os_type(&self) -> &str {
+        &self.config.os_type
+    }
+
+    fn detect(&self, mount_path: &Path) -> Option<InstallerInfo> {
+        if self.config.markers.is_empty() || !self.validate_markers(mount_path) {
+            return None;
+        }
+        Some(InstallerInfo {
+            name: self.config.name.clone(),
+            path: mount_path.to_path_buf(),
+            os_type: self.config.os_type.clone(),
+            version: None,
+            auto_installable: !self.config.command.is_empty(),
+            live: None,
+        })
+    }
+
+    fn validate(&self, installer: &InstallerInfo) -> bool {
+        self.validate_markers(&installer.path)
+    }
+
+    fn command(&self, installer: &InstallerInfo, _auto_mode: bool) -> Option<Command> {
+        let path = installer.path.to_string_lossy();
+        let mut args = self
+            .config
+            .command
+            .iter()
+            .map(|arg| arg.replace("{path}", &path));
+        let mut cmd = Command::new(args.next()?);
+        cmd.args(args);
+        Some(cmd)
+    }
+}
+
+/// `build=7.0.3-0.0.21424296` from `boot.cfg` → 7.0.3
+fn parse_esxi_build(cfg: &str) -> Option<String> {
+    cfg.lines().find_map(|line| {
+        let build = line.trim().strip_prefix("build=")?;
+        let version = build.split('-').next()?;
+        (!version.is_empty()).then(|| version.to_string())
+    })
+}
+
+/// `name` in `dir`, ignoring case; ISO 9660 media often use upper case
+fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .eq_ignore_ascii_case(name)
+        })
+        .map(|entry| entry.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iso::installer::IsoInstaller;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_esxi_detector() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(EsxiDetector.detect(temp_dir.path()).is_none());
+
+        std::fs::write(temp_dir.path().join("VMKBOOT.B00"), b"").unwrap();
+        std::fs::write(
+            temp_dir.path().join("BOOT.CFG"),
+            "bootstate=0\ntitle=Loading ESXi installer\nbuild=8.0.2-0.0.22380479\n",
+        )
+        .unwrap();
+        let info = EsxiDetector.detect(temp_dir.path()).unwrap();
+        assert_eq!(info.os_type, "esxi");
+        assert_eq!(info.version.as_deref(), Some("8.0.2"));
+        assert!(EsxiDetector.validate(&info));
+        // Nothing can start it from the node, so it is not offered
+        assert!(!info.auto_installable);
+        assert!(EsxiDetector.command(&info, true).is_none());
+    }
+
+    #[test]
+    fn test_config_detector() {
+        let temp_dir = TempDir::new().unwrap();
+        let detector = ConfigDetector::new(DetectorConfig {
+            os_type: "truenas".to_string(),
+            name: "TrueNAS Installer".to_string(),
+            markers: vec![
+                "TrueNAS-SCALE.update".to_string(),
+                "live/squashfs".to_string(),
+            ],
+            command: vec!["truenas-install".to_string(), "--media={path}".to_string()],
+        });
+        assert!(detector.detect(temp_dir.path()).is_none());
+
+        std::fs::write(temp_dir.path().join("TrueNAS-SCALE.update"), b"").unwrap();
+        assert!(detector.detect(temp_dir.path()).is_none());
+        std::fs::create_dir_all(temp_dir.path().join("LIVE")).unwrap();
+        std::fs::write(temp_dir.path().join("LIVE/SQUASHFS"), b"").unwrap();
+
+        let info = detector.detect(temp_dir.path()).unwrap();
+        assert_eq!(info.os_type, "truenas");
+        assert!(info.auto_installable);
+        assert!(detector.validate(&info));
+        let cmd = detector.command(&info, false).unwrap();
+        assert_eq!(cmd.as_std().get_program(), "truenas-install");
+        assert_eq!(
+            cmd.as_std().get_args().collect::<Vec<_>>(),
+            [format!("--media={}", temp_dir.path().display()).as_str()]
+        );
+    }
+
+    struct PfSenseDetector;
+
+    impl InstallerDetector for PfSenseDetector {
+        fn os_type(&self) -> &str {
+            "pfsense"
+        }
+
+        fn detect(&self, mount_path: &Path) -> Option<InstallerInfo> {
+            let marker = mount_path.join("etc/platform");
+            std::fs::read_to_string(&marker)
+                .ok()
+                .filter(|platform| platform.trim() == "pfSense")
+                .map(|_| InstallerInfo {
+                    name: "pfSense Installer".to_string(),
+                    path: marker,
+                    os_type: self.os_type().to_string(),
+                    version: None,
+                    auto_installable: false,
+                    live: None,
+                })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_detector() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("etc")).unwrap();
+        std::fs::write(temp_dir.path().join("etc/platform"), "pfSense\n").unwrap();
+
+        let installer = IsoInstaller::new();
+        assert!(installer
+            .discover_installer(temp_dir.path())
+            .await
+            .unwrap()
+            .is_empty());
+
+        installer.register_detector(Arc::new(PfSenseDetector)).await;
+        let found = installer.discover_installer(temp_dir.path()).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].os_type, "pfsense");
+        assert!(installer.validate_installer(&found[0]).await.unwrap());
+    }
+}
@@ -0,0 +1,502 @@
+use crate::config::DownloadConfig;
+use crate::error::{IsoError, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::broadcast;
+use tokio::time::interval;
+use tracing::{debug, error, info, warn};
+
+/// How often the partial file is polled for progress
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Suffix of in-progress downloads; kept between attempts for resuming
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// curl exit code when the server ignores the range request
+const CURL_RANGE_ERROR: i32 = 33;
+
+/// ISO download progress
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadProgress {
+    pub file: String,
+    pub mirror: String,
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    pub percentage: u8,
+    pub bytes_per_sec: u64,
+}
+
+/// An ISO to fetch, relative to the mirror base URL
+#[derive(Debug, Clone)]
+pub struct DownloadRequest {
+    /// Path on the mirror, e.g. `debian-cd/12.5.0/amd64/iso-cd/debian-12.5.0-amd64-netinst.iso`
+    pub path: String,
+    /// Expected SHA-256 of the complete file
+    pub sha256: Option<String>,
+    /// .torrent URL, local .torrent file or magnet link, tried before the
+    /// mirrors when built with the `torrent` feature
+    pub torrent: Option<String>,
+}
+
+impl DownloadRequest {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            sha256: None,
+            torrent: None,
+        }
+    }
+
+    pub fn with_sha256(mut self, sha256: String) -> Self {
+        self.sha256 = Some(sha256.to_lowercase());
+        self
+    }
+
+    pub fn with_torrent(mut self, torrent: String) -> Self {
+        self.torrent = Some(torrent);
+        self
+    }
+
+    /// Local file name, the last path segment
+    pub fn file_name(&self) -> Result<String> {
+        let name = self.path.rsplit('/').next().unwrap_or_default();
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(
+                IsoError::DownloadFailed(format!("Invalid download path: {}", self.path)).into(),
+            );
+        }
+        Ok(name.to_string())
+    }
+}
+
+/// A mirror that answered the HEAD probe
+#[derive(Debug, Clone, PartialEq)]
+struct MirrorProbe {
+    url: String,
+    latency: Duration,
+    size: Option<u64>,
+}
+
+/// Fetches ISOs over HTTP(S) using curl
+pub struct IsoDownloader {
+    progress_tx: Option<broadcast::Sender<DownloadProgress>>,
+}
+
+impl IsoDownloader {
+    pub fn new() -> Self {
+        Self { progress_tx: None }
+    }
+
+    /// Publish progress events on the given channel
+    pub fn with_progress(mut self, tx: broadcast::Sender<DownloadProgress>) -> Self {
+        self.progress_tx = Some(tx);
+        self
+    }
+
+    /// Download `request` into `target_dir`, resuming a previous partial
+    /// download and trying mirrors from fastest to slowest
+    pub async fn download(
+        &self,
+        request: &DownloadRequest,
+        config: &DownloadConfig,
+        target_dir: &Path,
+    ) -> Result<PathBuf> {
+        let file_name = request.file_name()?;
+        let target = target_dir.join(&file_name);
+        let partial = target_dir.join(format!("{}{}", file_name, PARTIAL_SUFFIX));
+
+        if target.exists() {
+            info!("{} already present, verifying", target.display());
+            self.verify(&target, request).await?;
+            return Ok(target);
+        }
+
+        if let Some(source) = &request.torrent {
+            #[cfg(feature = "torrent")]
+            if config.torrent.enabled {
+                match self
+                    .fetch_torrent(source, &file_name, target_dir, config)
+                    .await
+                {
+                    Ok(payload) => return self.finish(&payload, &target, request).await,
+                    Err(e) if !config.mirrors.is_empty() => {
+                        warn!(
+                            "Torrent download of {} failed, using mirrors: {}",
+                            file_name, e
+                        )
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            #[cfg(not(feature = "torrent"))]
+            warn!(
+                "Built without torrent support, ignoring {} for {}",
+                source, file_name
+            );
+        }
+
+        if config.mirrors.is_empty() {
+            return Err(IsoError::DownloadFailed("No mirrors configured".to_string()).into());
+        }
+
+        tokio::fs::create_dir_all(target_dir)
+            .await
+            .map_err(|e| IsoError::DownloadFailed(format!("{}: {}", target_dir.display(), e)))?;
+
+        let mirrors = self.rank_mirrors(request, config).await;
+        if mirrors.is_empty() {
+            return Err(
+                IsoError::DownloadFailed(format!("No mirror serves {}", request.path)).into(),
+            );
+        }
+
+        let mut last_error = None;
+        'mirrors: for mirror in &mirrors {
+            for attempt in 1..=config.retries.max(1) {
+                info!(
+                    "Downloading {} from {} (attempt {})",
+                    file_name, mirror.url, attempt
+                );
+                match self.fetch(mirror, &file_name, &partial, config).await {
+                    Ok(()) => {
+                        last_error = None;
+                        break 'mirrors;
+                    }
+                    Err(e) => {
+                        warn!("Download from {} failed: {}", mirror.url, e);
+                        last_error = Some(e);
+                    }
+                }
+            }
+        }
+
+        if let Some(e) = last_error {
+            return Err(IsoError::DownloadFailed(format!("{}: {}", file_name, e)).into());
+        }
+
+        self.finish(&partial, &target, request).await
+    }
+
+    /// Verify a completed download and move it into place
+    async fn finish(
+        &self,
+        download: &Path,
+        target: &Path,
+        request: &DownloadRequest,
+    ) -> Result<PathBuf> {
+        if let Err(e) = self.verify(download, request).await {
+            // A corrupt partial file would poison every later resume
+            let _ = tokio::fs::remove_file(download).await;
+            return Err(e);
+        }
+
+        tokio::fs::rename(download, target)
+            .await
+            .map_err(|e| IsoError::DownloadFailed(format!("{}: {}", target.display(), e)))?;
+
+        info!("Downloaded {}", target.display());
+        Ok(target.to_path_buf())
+    }
+
+    /// Fetch via BitTorrent into a staging directory under `target_dir`
+    #[cfg(feature = "torrent")]
+    async fn fetch_torrent(
+        &self,
+        source: &str,
+        file_name: &str,
+        target_dir: &Path,
+        config: &DownloadConfig,
+    ) -> Result<PathBuf> {
+        let staging = target_dir.join(format!(".{}.torrent.d", file_name));
+        let payload = super::torrent::fetch(source, file_name, &staging, config, |status| {
+            if let Some(tx) = &self.progress_tx {
+                let _ = tx.send(DownloadProgress {
+                    file: file_name.to_string(),
+                    mirror: source.to_string(),
+                    downloaded_bytes: status.downloaded_bytes,
+                    total_bytes: Some(status.total_bytes),
+                    percentage: status.percentage,
+                    bytes_per_sec: status.bytes_per_sec,
+                });
+            }
+        })
+        .await?;
+
+        // Keep the staging directory (and aria2c's resume state) until the
+        // payload has been moved out
+        let moved = target_dir.join(format!("{}{}", file_name, PARTIAL_SUFFIX));
+        tokio::fs::rename(&payload, &moved)
+            .await
+            .map_err(|e| IsoError::DownloadFailed(format!("{}: {}", moved.display(), e)))?;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        Ok(moved)
+    }
+
+    /// HEAD every mirror and order the responding ones by latency
+    async fn rank_mirrors(
+        &self,
+        request: &DownloadRequest,
+        config: &DownloadConfig,
+    ) -> Vec<MirrorProbe> {
+        let mut probes = Vec::new();
+
+        for base in &config.mirrors {
+            let url = mirror_url(base, &request.path);
+            let started = Instant::now();
+            let output = Command::new("curl")
+                .args(["-sfIL", "--max-time"])
+                .arg(config.connect_timeout.to_string())
+                .arg(&url)
+                .output()
+                .await;
+
+            match output {
+                Ok(output) if output.status.success() => probes.push(MirrorProbe {
+                    url,
+                    latency: started.elapsed(),
+                    size: parse_content_length(&String::from_utf8_lossy(&output.stdout)),
+                }),
+                Ok(_) => debug!("Mirror {} does not serve {}", base, request.path),
+                Err(e) => debug!("Failed to probe {}: {}", base, e),
+            }
+        }
+
+        probes.sort_by_key(|p| p.latency);
+        probes
+    }
+
+    /// Fetch into `partial`, resuming where the server supports it
+    async fn fetch(
+        &self,
+        mirror: &MirrorProbe,
+        file_name: &str,
+        partial: &Path,
+        config: &DownloadConfig,
+    ) -> std::result::Result<(), String> {
+        let existing = tokio::fs::metadata(partial)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        if mirror.size.is_some_and(|size| existing == size) {
+            // Already complete; a ranged request would fail with 416
+            return Ok(());
+        }
+
+        match self
+            .transfer(mirror, file_name, partial, config, existing)
+            .await
+        {
+            Err((Some(CURL_RANGE_ERROR), _)) if existing > 0 => {
+                warn!(
+                    "{} does not support resuming, restarting {}",
+                    mirror.url, file_name
+                );
+                let _ = tokio::fs::remove_file(partial).await;
+                self.transfer(mirror, file_name, partial, config, 0)
+                    .await
+                    .map_err(|(_, e)| e)
+            }
+            result => result.map_err(|(_, e)| e),
+        }
+    }
+
+    /// Run one curl transfer while polling the partial file for progress.
+    /// Errors carry the curl exit code.
+    async fn transfer(
+        &self,
+        mirror: &MirrorProbe,
+        file_name: &str,
+        partial: &Path,
+        config: &DownloadConfig,
+        existing: u64,
+    ) -> std::result::Result<(), (Option<i32>, String)> {
+        let mut cmd = Command::new("curl");
+        cmd.args([
+            "-fL",
+            "--silent",
+            "--show-error",
+            "-C",
+            "-",
+            "--connect-timeout",
+        ])
+        .arg(config.connect_timeout.to_string())
+        .arg("-o")
+        .arg(partial)
+        .arg(&mirror.url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+        if let Some(limit) = config.rate_limit_kbps {
+            cmd.arg("--limit-rate").arg(format!("{}k", limit));
+        }
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| (None, format!("Failed to run curl: {}", e)))?;
+
+        let mut ticker = interval(PROGRESS_INTERVAL);
+        let mut last = (Instant::now(), existing);
+        let status = loop {
+            tokio::select! {
+                status = child.wait() => break status.map_err(|e| (None, e.to_string()))?,
+                _ = ticker.tick() => {
+                    let downloaded = tokio::fs::metadata(partial).await.map(|m| m.len()).unwrap_or(0);
+                    let elapsed = last.0.elapsed().as_secs_f64();
+                    let rate = if elapsed > 0.0 {
+                        (downloaded.saturating_sub(last.1) as f64 / elapsed) as u64
+                    } else {
+                        0
+                    };
+                    last = (Instant::now(), downloaded);
+                    self.report(file_name, mirror, downloaded, rate);
+                }
+            }
+        };
+
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr).await;
+            }
+            return Err((
+                status.code(),
+                format!("curl exited with {}: {}", status, stderr.trim()),
+            ));
+        }
+
+        let downloaded = tokio::fs::metadata(partial)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        self.report(file_name, mirror, downloaded, 0);
+        Ok(())
+    }
+
+    async fn verify(&self, file: &Path, request: &DownloadRequest) -> Result<()> {
+        let Some(expected) = &request.sha256 else {
+            return Ok(());
+        };
+
+        debug!("Verifying SHA-256 of {}", file.display());
+        let output = Command::new("sha256sum")
+            .arg(file)
+            .output()
+            .await
+            .map_err(|e| IsoError::DownloadFailed(format!("Failed to run sha256sum: {}", e)))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let actual = stdout.split_whitespace().next().unwrap_or_default();
+        if !output.status.success() || !actual.eq_ignore_ascii_case(expected) {
+            error!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                file.display(),
+                expected,
+                actual
+            );
+            return Err(IsoError::ChecksumMismatch(request.file_name()?).into());
+        }
+        Ok(())
+    }
+
+    fn report(&self, file_name: &str, mirror: &MirrorProbe, downloaded: u64, bytes_per_sec: u64) {
+        if let Some(tx) = &self.progress_tx {
+            let percentage = match mirror.size {
+                Some(total) if total > 0 => ((downloaded * 100) / total).min(100) as u8,
+                _ => 0,
+            };
+            let _ = tx.send(DownloadProgress {
+                file: file_name.to_string(),
+                mirror: mirror.url.clone(),
+                downloaded_bytes: downloaded,
+                total_bytes: mirror.size,
+                percentage,
+                bytes_per_sec,
+            });
+        }
+    }
+}
+
+impl Default for IsoDownloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn mirror_url(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// Content-Length of the final response in `curl -I -L` output
+fn parse_content_length(headers: &str) -> Option<u64> {
+    headers.lines().rev().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("content-length")
+            .then(|| value.trim().parse().ok())?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_url() {
+        assert_eq!(
+            mirror_url("https://mirror.example/debian/", "/cd/netinst.iso"),
+            "https://mirror.example/debian/cd/netinst.iso"
+        );
+        assert_eq!(
+            mirror_url("http://10.0.0.1", "a.iso"),
+            "http://10.0.0.1/a.iso"
+        );
+    }
+
+    #[test]
+    fn test_parse_content_length_after_redirect() {
+        let headers = "HTTP/1.1 302 Found\r\nLocation: https://cdn.example/a.iso\r\nContent-Length: 0\r\n\r\n\
+                       HTTP/2 200\r\ncontent-length: 658505728\r\naccept-ranges: bytes\r\n\r\n";
+        assert_eq!(parse_content_length(headers), Some(658_505_728));
+        assert_eq!(parse_content_length("HTTP/2 200\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn test_request_file_name() {
+        let request = DownloadRequest::new("debian/12/debian-12.5.0-amd64-netinst.iso".to_string())
+            .with_sha256("ABCDEF".to_string());
+        assert_eq!(
+            request.file_name().unwrap(),
+            "debian-12.5.0-amd64-netinst.iso"
+        );
+        assert_eq!(request.sha256.as_deref(), Some("abcdef"));
+
+        assert!(DownloadRequest::new("images/".to_string())
+            .file_name()
+            .is_err());
+        assert!(DownloadRequest::new("images/..".to_string())
+            .file_name()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_existing_file_is_verified() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a.iso"), b"iso").unwrap();
+
+        let downloader = IsoDownloader::new();
+        let request = DownloadRequest::new("a.iso".to_string()).with_sha256("0".repeat(64));
+
+        let result = downloader
+            .download(&request, &DownloadConfig::default(), dir.path())
+            .await;
+        assert!(result.is_err());
+    }
+}
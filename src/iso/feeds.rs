@@ -0,0 +1,411 @@
+use super::catalog::{self, Arch, CatalogQuery, IsoCatalogEntry};
+use crate::config::{FeedFormat, FeedSource};
+use crate::error::{IsoError, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::Path;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+/// Longest a feed or signature download may take
+const FETCH_TIMEOUT_SECS: u64 = 60;
+
+/// An image a vendor feed announces
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedRelease {
+    /// Feed it came from
+    #[serde(default)]
+    pub source: String,
+    pub distro: String,
+    pub version: String,
+    pub arch: Option<Arch>,
+    pub variant: Option<String>,
+    pub url: String,
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+}
+
+impl FeedRelease {
+    pub fn file_name(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Directory URL the image is served from
+    pub fn base_url(&self) -> &str {
+        self.url
+            .rsplit_once('/')
+            .map_or(self.url.as_str(), |(base, _)| base)
+    }
+
+    /// Whether `product` asks for this image; versions are not compared
+    pub fn matches(&self, product: &CatalogQuery) -> bool {
+        let text = |wanted: &Option<String>, value: Option<&str>| match (wanted, value) {
+            (None, _) => true,
+            (Some(wanted), Some(value)) => value.eq_ignore_ascii_case(wanted),
+            (Some(_), None) => false,
+        };
+        text(&product.distro, Some(&self.distro))
+            && text(&product.variant, self.variant.as_deref())
+            && product.arch.is_none_or(|a| self.arch == Some(a))
+    }
+
+    /// Same distribution, architecture and edition as a local image
+    fn same_product(&self, entry: &IsoCatalogEntry) -> bool {
+        let variants_agree = match (&self.variant, &entry.variant) {
+            (Some(ours), Some(theirs)) => ours.eq_ignore_ascii_case(theirs),
+            _ => true,
+        };
+        entry
+            .distro
+            .as_deref()
+            .is_some_and(|d| d.eq_ignore_ascii_case(&self.distro))
+            && entry.arch == self.arch
+            && variants_agree
+    }
+}
+
+/// Custom feed format: `{"releases": [...]}`
+#[derive(Debug, Deserialize)]
+struct Index {
+    releases: Vec<FeedRelease>,
+}
+
+/// One entry of Fedora's `releases.json`
+#[derive(Debug, Deserialize)]
+struct FedoraRelease {
+    version: String,
+    arch: String,
+    link: String,
+    #[serde(default)]
+    subvariant: Option<String>,
+    #[serde(default)]
+    sha256: Option<String>,
+    #[serde(default)]
+    size: Option<String>,
+}
+
+/// Download `source`, check its signature and list the images it announces.
+/// Feeds without a verifiable signature are refused when `require_signature`.
+pub async fn fetch(
+    source: &FeedSource,
+    cache_dir: &Path,
+    require_signature: bool,
+) -> Result<Vec<FeedRelease>> {
+    tokio::fs::create_dir_all(cache_dir)
+        .await
+        .map_err(|e| feed_error(source, format!("{}: {}", cache_dir.display(), e)))?;
+
+    let document = cache_dir.join(format!("{}.feed", source.name));
+    download(source, &source.url, &document).await?;
+
+    match (&source.signature, &source.keyring) {
+        (Some(signature_url), Some(keyring)) => {
+            let signature = cache_dir.join(format!("{}.sig", source.name));
+            download(source, signature_url, &signature).await?;
+            verify_signature(source, keyring, &signature, &document).await?;
+        }
+        (Some(_), None) => {
+            return Err(feed_error(
+                source,
+                "signature given without a keyring".to_string(),
+            ));
+        }
+        (None, _) if require_signature => {
+            return Err(feed_error(source, "feed is not signed".to_string()));
+        }
+        (None, _) => debug!("Feed {} is not signed", source.name),
+    }
+
+    let text = tokio::fs::read_to_string(&document)
+        .await
+        .map_err(|e| feed_error(source, format!("{}: {}", document.display(), e)))?;
+    let mut releases = parse(source, &text)?;
+    for release in &mut releases {
+        release.source = source.name.clone();
+    }
+    info!("Feed {} lists {} images", source.name, releases.len());
+    Ok(releases)
+}
+
+fn parse(source: &FeedSource, text: &str) -> Result<Vec<FeedRelease>> {
+    match source.format {
+        FeedFormat::Sha256sums => {
+            let base = source
+                .url
+                .rsplit_once('/')
+                .map_or(source.url.as_str(), |(base, _)| base);
+            Ok(parse_sha256sums(text, base, source.distro.as_deref()))
+        }
+        FeedFormat::Fedora => {
+            let releases: Vec<FedoraRelease> = serde_json::from_str(text)
+                .map_err(|e| feed_error(source, format!("invalid releases.json: {}", e)))?;
+            Ok(parse_fedora(releases))
+        }
+        FeedFormat::Index => serde_json::from_str::<Index>(text)
+            .map(|index| index.releases)
+            .map_err(|e| feed_error(source, format!("invalid index: {}", e))),
+    }
+}
+
+/// `SHA256SUMS` as published next to Debian and Ubuntu images. Names that
+/// do not identify a distribution fall back to `distro`.
+fn parse_sha256sums(text: &str, base_url: &str, distro: Option<&str>) -> Vec<FeedRelease> {
+    text.lines()
+        .filter_map(|line| {
+            let (hash, name) = line.split_once(char::is_whitespace)?;
+            let name = name.trim().trim_start_matches('*');
+            if !name.to_ascii_lowercase().ends_with(".iso") || name.contains('/') {
+                return None;
+            }
+            let identity = catalog::parse_file_name(name);
+            Some(FeedRelease {
+                source: String::new(),
+                distro: identity.distro.or_else(|| distro.map(str::to_string))?,
+                version: identity.version?,
+                arch: identity.arch,
+                variant: identity.variant,
+                url: format!("{}/{}", base_url.trim_end_matches('/'), name),
+                sha256: Some(hash.to_lowercase()),
+                size: None,
+            })
+        })
+        .collect()
+}
+
+/// ISO images from Fedora's `releases.json`; pre-releases are skipped
+fn parse_fedora(releases: Vec<FedoraRelease>) -> Vec<FeedRelease> {
+    releases
+        .into_iter()
+        .filter(|r| r.link.ends_with(".iso") && catalog::is_version(&r.version))
+        .map(|r| FeedRelease {
+            source: String::new(),
+            distro: "Fedora".to_string(),
+            version: r.version,
+            arch: Arch::parse(&r.arch),
+            variant: r.subvariant.map(|v| v.to_lowercase()),
+            url: r.link,
+            sha256: r.sha256.map(|h| h.to_lowercase()),
+            size: r.size.and_then(|s| s.parse().ok()),
+        })
+        .collect()
+}
+
+/// Releases newer than every local image of the same product, newest first
+pub fn updates(releases: &[FeedRelease], entries: &[IsoCatalogEntry]) -> Vec<FeedRelease> {
+    let mut updates: Vec<FeedRelease> = releases
+        .iter()
+        .filter(|release| {
+            !entries.iter().any(|entry| {
+                entry.file_name == release.file_name()
+                    || (release.same_product(entry)
+                        && entry.version.as_deref().is_some_and(|v| {
+                            compare_versions(v, &release.version) != Ordering::Less
+                        }))
+            })
+        })
+        .cloned()
+        .collect();
+    updates.sort_by(|a, b| {
+        a.distro
+            .cmp(&b.distro)
+            .then_with(|| compare_versions(&b.version, &a.version))
+    });
+    updates
+}
+
+/// Newest release matching each product, for automatic download
+pub fn wanted<'a>(updates: &'a [FeedRelease], products: &[CatalogQuery]) -> Vec<&'a FeedRelease> {
+    let mut wanted: Vec<&FeedRelease> = Vec::new();
+    for product in products {
+        let newest = updates
+            .iter()
+            .filter(|release| release.matches(product))
+            .max_by(|a, b| compare_versions(&a.version, &b.version));
+        if let Some(release) = newest {
+            if !wanted.iter().any(|w| w.url == release.url) {
+                wanted.push(release);
+            }
+        }
+    }
+    wanted
+}
+
+/// Compare dotted versions numerically: 12.10 is newer than 12.9
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |v: &str| -> Vec<u64> {
+        v.split(|c: char| !c.is_ascii_digit())
+            .filter(|p| !p.is_empty())
+            .map(|p| p.parse().unwrap_or(0))
+            .collect()
+    };
+    parts(a).cmp(&parts(b))
+}
+
+async fn download(source: &FeedSource, url: &str, target: &Path) -> Result<()> {
+    debug!("Fetching {} into {}", url, target.display());
+    let output = Command::new("curl")
+        .args(["-sfL", "--max-time"])
+        .arg(FETCH_TIMEOUT_SECS.to_string())
+        .arg("-o")
+        .arg(target)
+        .arg(url)
+        .output()
+        .await
+        .map_err(|e| feed_error(source, format!("Failed to run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(feed_error(
+            source,
+            format!(
+                "{}: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Detached OpenPGP signature check against a fixed keyring
+async fn verify_signature(
+    source: &FeedSource,
+    keyring: &Path,
+    signature: &Path,
+    document: &Path,
+) -> Result<()> {
+    let output = Command::new("gpgv")
+        .arg("--keyring")
+        .arg(keyring)
+        .arg(signature)
+        .arg(document)
+        .output()
+        .await
+        .map_err(|e| feed_error(source, format!("Failed to run gpgv: {}", e)))?;
+    if !output.status.success() {
+        return Err(feed_error(
+            source,
+            format!(
+                "bad signature: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    debug!("Feed {} signature verified", source.name);
+    Ok(())
+}
+
+fn feed_error(source: &FeedSource, message: String) -> crate::error::Error {
+    IsoError::FeedFailed(format!("{}: {}", source.name, message)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn entry(file_name: &str, version: &str, variant: &str) -> IsoCatalogEntry {
+        IsoCatalogEntry {
+            path: PathBuf::from("/installers").join(file_name),
+            file_name: file_name.to_string(),
+            volume_id: String::new(),
+            distro: Some("Debian".to_string()),
+            version: Some(version.to_string()),
+            arch: Some(Arch::X86_64),
+            variant: Some(variant.to_string()),
+            size: 0,
+            checksum: None,
+            boot: Default::default(),
+            modified: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse_sha256sums() {
+        let sums = "\
+013f5b44670d81280b5b1bc02455842b250df2f0c6763398feb69af1a805a14f  debian-12.5.0-amd64-netinst.iso
+7a2b9a5d15ff3ee3ed1e4d8e5bb4ef1e0f3cc07e1d14bd2a1fa5b3e6efc5a1a3  debian-edu-12.5.0-amd64-netinst.iso
+1bd2b5bd9f3b0e2a9e6f4c62a3c0d1f5e4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9 *debian-12.5.0-amd64-DVD-1.iso
+ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff  debian-12.5.0-amd64-netinst.iso.torrent
+";
+        let releases = parse_sha256sums(
+            sums,
+            "https://cdimage.debian.org/debian-cd/current/amd64/iso-cd/",
+            Some("Debian"),
+        );
+        assert_eq!(releases.len(), 3);
+        assert_eq!(releases[0].distro, "Debian");
+        assert_eq!(releases[0].version, "12.5.0");
+        assert_eq!(releases[0].arch, Some(Arch::X86_64));
+        assert_eq!(releases[0].variant.as_deref(), Some("netinst"));
+        assert_eq!(
+            releases[0].url,
+            "https://cdimage.debian.org/debian-cd/current/amd64/iso-cd/debian-12.5.0-amd64-netinst.iso"
+        );
+        assert_eq!(
+            releases[0].base_url(),
+            "https://cdimage.debian.org/debian-cd/current/amd64/iso-cd"
+        );
+        assert_eq!(releases[2].file_name(), "debian-12.5.0-amd64-DVD-1.iso");
+    }
+
+    #[test]
+    fn test_parse_fedora() {
+        let json = r#"[
+            {"version": "40", "arch": "x86_64", "link": "https://download.fedoraproject.org/pub/fedora/linux/releases/40/Workstation/x86_64/iso/Fedora-Workstation-Live-x86_64-40-1.14.iso", "variant": "Workstation", "subvariant": "Workstation", "sha256": "DD1FACA950D1A8C3D169ADF2DF4C3644EBB62F8AAC04C401F2393E521395D613", "size": "2295853056"},
+            {"version": "40", "arch": "x86_64", "link": "https://download.fedoraproject.org/pub/fedora/linux/releases/40/Cloud/x86_64/images/Fedora-Cloud-Base-Generic.x86_64-40-1.14.qcow2", "variant": "Cloud", "subvariant": "Cloud_Base"},
+            {"version": "41 Beta", "arch": "aarch64", "link": "https://download.fedoraproject.org/pub/fedora/linux/releases/test/41_Beta/Server/aarch64/iso/Fedora-Server-dvd-aarch64-41_Beta-1.2.iso", "variant": "Server", "subvariant": "Server"}
+        ]"#;
+        let releases = parse_fedora(serde_json::from_str(json).unwrap());
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].variant.as_deref(), Some("workstation"));
+        assert_eq!(releases[0].size, Some(2295853056));
+        assert!(releases[0]
+            .sha256
+            .as_deref()
+            .unwrap()
+            .starts_with("dd1faca9"));
+    }
+
+    #[test]
+    fn test_updates() {
+        assert_eq!(compare_versions("12.10.0", "12.9.1"), Ordering::Greater);
+        assert_eq!(compare_versions("24.04", "24.04"), Ordering::Equal);
+
+        let release = |version: &str, variant: &str| FeedRelease {
+            source: "debian".to_string(),
+            distro: "Debian".to_string(),
+            version: version.to_string(),
+            arch: Some(Arch::X86_64),
+            variant: Some(variant.to_string()),
+            url: format!("https://cdimage.debian.org/debian-{version}-amd64-{variant}.iso"),
+            sha256: None,
+            size: None,
+        };
+        let releases = [
+            release("12.4.0", "netinst"),
+            release("12.5.0", "netinst"),
+            release("12.6.0", "netinst"),
+            release("12.6.0", "dvd"),
+        ];
+        let local = [entry(
+            "debian-12.5.0-amd64-netinst.iso",
+            "12.5.0",
+            "netinst",
+        )];
+
+        let updates = updates(&releases, &local);
+        let versions: Vec<_> = updates
+            .iter()
+            .map(|r| (r.version.as_str(), r.variant.as_deref().unwrap()))
+            .collect();
+        assert_eq!(versions, [("12.6.0", "netinst"), ("12.6.0", "dvd")]);
+
+        let products = [CatalogQuery {
+            distro: Some("debian".to_string()),
+            variant: Some("netinst".to_string()),
+            ..CatalogQuery::default()
+        }];
+        let wanted = wanted(&updates, &products);
+        assert_eq!(wanted.len(), 1);
+        assert_eq!(wanted[0].version, "12.6.0");
+    }
+}
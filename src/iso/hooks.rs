@@ -0,0 +1,459 @@
+use super::installer::InstallerProgress;
+use crate::config::{PostInstallConfig, TargetConfig};
+use crate::dryrun::DryRun;
+use crate::error::{IsoError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command as StdCommand, Stdio};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// Filesystems an installed Linux root may use
+const ROOT_FILESYSTEMS: &[&str] = &["ext4", "ext3", "xfs", "btrfs", "f2fs"];
+
+/// A step run against the installed system once the installer completed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PostInstallHook {
+    /// Shown in logs; defaults to the action type
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub action: HookAction,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HookAction {
+    /// Shell script run on the node with `TARGET_ROOT`, `TARGET_DEVICE` and
+    /// `TARGET_HOSTNAME` set
+    Script { path: PathBuf },
+    /// Install `[target.user] ssh_authorized_keys` for the target user
+    SshKeys,
+    /// Write `[target] hostname` to /etc/hostname and /etc/hosts
+    Hostname,
+    /// Enable a systemd unit so it runs on first boot, copying the unit
+    /// file from `source` first when given
+    FirstBootService {
+        unit: String,
+        #[serde(default)]
+        source: Option<PathBuf>,
+    },
+}
+
+impl PostInstallHook {
+    fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| match &self.action {
+            HookAction::Script { path } => format!("script {}", path.display()),
+            HookAction::SshKeys => "ssh_keys".to_string(),
+            HookAction::Hostname => "hostname".to_string(),
+            HookAction::FirstBootService { unit, .. } => format!("first_boot_service {}", unit),
+        })
+    }
+}
+
+/// Outcome of one hook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HookResult {
+    pub name: String,
+    pub success: bool,
+    pub message: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Record the hooks `run_hooks` would run instead of mounting the target
+pub fn rehearse_hooks(config: &PostInstallConfig, target: &TargetConfig, dry_run: &DryRun) {
+    let device = target
+        .disk
+        .device
+        .as_deref()
+        .unwrap_or("[target.disk] device");
+    for hook in &config.hooks {
+        dry_run.action(format!(
+            "Run post-install hook {} on {} mounted at {}",
+            hook.name(),
+            device,
+            config.mount_dir.display()
+        ));
+    }
+}
+
+/// Mount the installed root from `[target.disk] device` and run every hook
+/// in order. A failing hook is logged and the next one still runs.
+pub async fn run_hooks(
+    config: &PostInstallConfig,
+    target: &TargetConfig,
+    progress_tx: &mpsc::Sender<InstallerProgress>,
+) -> Vec<HookResult> {
+    if config.hooks.is_empty() {
+        return Vec::new();
+    }
+    let Some(device) = target.disk.device.clone() else {
+        warn!("Post-install hooks skipped: [target.disk] device is not set");
+        return Vec::new();
+    };
+
+    let mount_dir = config.mount_dir.clone();
+    let mounted = {
+        let device = device.clone();
+        let mount_dir = mount_dir.clone();
+        tokio::task::spawn_blocking(move || mount_root(&device, &mount_dir)).await
+    };
+    let root = match mounted {
+        Ok(Ok(root)) => root,
+        Ok(Err(e)) => {
+            error!("Post-install hooks skipped: {}", e);
+            return Vec::new();
+        }
+        Err(e) => {
+            error!("Post-install hooks skipped: mount task failed: {}", e);
+            return Vec::new();
+        }
+    };
+    info!(
+        "Running {} post-install hooks on {}",
+        config.hooks.len(),
+        root
+    );
+
+    let mut results = Vec::new();
+    for (i, hook) in config.hooks.iter().enumerate() {
+        let name = hook.name();
+        let _ = progress_tx
+            .send(InstallerProgress {
+                percentage: ((i * 100) / config.hooks.len()) as u8,
+                message: format!("Running hook {}", name),
+                stage: "post_install".to_string(),
+            })
+            .await;
+
+        let started = Instant::now();
+        let limit = Duration::from_secs(hook.timeout_secs);
+        let outcome = match tokio::time::timeout(limit, run_hook(hook, &mount_dir, &device, target))
+            .await
+        {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                Err(IsoError::HookFailed(format!("timed out after {}s", hook.timeout_secs)).into())
+            }
+        };
+        let result = HookResult {
+            name,
+            success: outcome.is_ok(),
+            message: outcome.err().map(|e| e.to_string()),
+            duration_ms: started.elapsed().as_millis() as u64,
+        };
+        match &result.message {
+            None => info!(hook = %result.name, duration_ms = result.duration_ms, "Hook completed"),
+            Some(e) => error!(hook = %result.name, "Hook failed: {}", e),
+        }
+        results.push(result);
+    }
+
+    let _ = StdCommand::new("sync").status();
+    if let Err(e) = umount(&mount_dir) {
+        warn!("Failed to unmount {}: {}", mount_dir.display(), e);
+    }
+    results
+}
+
+async fn run_hook(
+    hook: &PostInstallHook,
+    root: &Path,
+    device: &str,
+    target: &TargetConfig,
+) -> Result<()> {
+    match &hook.action {
+        HookAction::Script { path } => run_script(path, root, device, &target.hostname).await,
+        action => {
+            let action = action.clone();
+            let root = root.to_path_buf();
+            let target = target.clone();
+            tokio::task::spawn_blocking(move || match action {
+                HookAction::SshKeys => install_ssh_keys(&root, &target),
+                HookAction::Hostname => set_hostname(&root, &target.hostname),
+                HookAction::FirstBootService { unit, source } => {
+                    enable_service(&root, &unit, source.as_deref())
+                }
+                HookAction::Script { .. } => unreachable!(),
+            })
+            .await
+            .map_err(|e| IsoError::HookFailed(format!("hook task failed: {}", e)))?
+        }
+    }
+}
+
+/// Run a hook script, logging its output. Dropping the future on timeout
+/// kills the script.
+async fn run_script(path: &Path, root: &Path, device: &str, hostname: &str) -> Result<()> {
+    let output = Command::new("/bin/sh")
+        .arg(path)
+        .env("TARGET_ROOT", root)
+        .env("TARGET_DEVICE", device)
+        .env("TARGET_HOSTNAME", hostname)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| IsoError::HookFailed(format!("{}: {}", path.display(), e)))?;
+
+    let name = path.display().to_string();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        info!(hook = %name, "{}", line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        warn!(hook = %name, "{}", line);
+    }
+    if !output.status.success() {
+        return Err(IsoError::HookFailed(format!("{} exited with {}", name, output.status)).into());
+    }
+    Ok(())
+}
+
+/// Append the configured keys to the target user's authorized_keys, owned
+/// by the user as listed in the installed system's /etc/passwd
+fn install_ssh_keys(root: &Path, target: &TargetConfig) -> Result<()> {
+    let user = &target.user;
+    if user.ssh_authorized_keys.is_empty() {
+        return Ok(());
+    }
+    let passwd = fs::read_to_string(root.join("etc/passwd")).unwrap_or_default();
+    let (uid, gid, home) = passwd_entry(&passwd, &user.username).ok_or_else(|| {
+        IsoError::HookFailed(format!("user {} not found in the target", user.username))
+    })?;
+
+    let ssh_dir = root.join(home.trim_start_matches('/')).join(".ssh");
+    fs::create_dir_all(&ssh_dir)?;
+    fs::set_permissions(&ssh_dir, fs::Permissions::from_mode(0o700))?;
+    let keys_path = ssh_dir.join("authorized_keys");
+    let existing = fs::read_to_string(&keys_path).unwrap_or_default();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&keys_path)?;
+    for key in &user.ssh_authorized_keys {
+        if !existing.lines().any(|line| line.trim() == key.trim()) {
+            writeln!(file, "{}", key.trim())?;
+        }
+    }
+    fs::set_permissions(&keys_path, fs::Permissions::from_mode(0o600))?;
+
+    for path in [&ssh_dir, &keys_path] {
+        std::os::unix::fs::chown(path, Some(uid), Some(gid))?;
+    }
+    info!(
+        "Installed {} SSH keys for {}",
+        user.ssh_authorized_keys.len(),
+        user.username
+    );
+    Ok(())
+}
+
+/// UID, GID and home directory of `user` in passwd(5) content
+fn passwd_entry(passwd: &str, user: &str) -> Option<(u32, u32, String)> {
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 7 || fields[0] != user {
+            return None;
+        }
+        Some((
+            fields[2].parse().ok()?,
+            fields[3].parse().ok()?,
+            fields[5].to_string(),
+        ))
+    })
+}
+
+fn set_hostname(root: &Path, hostname: &str) -> Result<()> {
+    fs::write(root.join("etc/hostname"), format!("{}\n", hostname))?;
+
+    // Point the Debian-style 127.0.1.1 entry at the new name
+    let hosts_path = root.join("etc/hosts");
+    let hosts = fs::read_to_string(&hosts_path).unwrap_or_default();
+    let mut lines: Vec<String> = hosts
+        .lines()
+        .filter(|line| !line.starts_with("127.0.1.1"))
+        .map(str::to_string)
+        .collect();
+    lines.push(format!("127.0.1.1\t{}", hostname));
+    fs::write(&hosts_path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Enable `unit` the way `systemctl enable` would for `WantedBy=multi-user.target`
+fn enable_service(root: &Path, unit: &str, source: Option<&Path>) -> Result<()> {
+    let system_dir = root.join("etc/systemd/system");
+    if let Some(source) = source {
+        fs::create_dir_all(&system_dir)?;
+        fs::copy(source, system_dir.join(unit))?;
+    }
+
+    let unit_path = [
+        "etc/systemd/system",
+        "usr/lib/systemd/system",
+        "lib/systemd/system",
+    ]
+    .iter()
+    .map(|dir| Path::new("/").join(dir).join(unit))
+    .find(|path| root.join(path.strip_prefix("/").unwrap_or(path)).is_file())
+    .ok_or_else(|| IsoError::HookFailed(format!("unit {} not found in the target", unit)))?;
+
+    let wants = system_dir.join("multi-user.target.wants");
+    fs::create_dir_all(&wants)?;
+    let link = wants.join(unit);
+    if link.symlink_metadata().is_ok() {
+        fs::remove_file(&link)?;
+    }
+    // Absolute inside the target, so it resolves once the target boots
+    symlink(&unit_path, &link)?;
+    Ok(())
+}
+
+/// Check that `device` carries a Linux root filesystem
+pub fn verify_root(device: &str, mount_dir: &Path) -> Result<()> {
+    let partition = mount_root(device, mount_dir)?;
+    info!("Found installed root on {}", partition);
+    umount(mount_dir)
+}
+
+/// Mount the partition of `device` that holds a Linux root filesystem
+fn mount_root(device: &str, mount_dir: &Path) -> Result<String> {
+    let output = StdCommand::new("lsblk")
+        .args(["-lnpo", "NAME,FSTYPE", device])
+        .output()
+        .map_err(|e| IsoError::HookFailed(format!("Failed to run lsblk: {}", e)))?;
+    fs::create_dir_all(mount_dir)?;
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(partition), Some(fs_type)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        if partition == device || !ROOT_FILESYSTEMS.contains(&fs_type) {
+            continue;
+        }
+        let status = StdCommand::new("mount")
+            .arg(partition)
+            .arg(mount_dir)
+            .status()?;
+        if !status.success() {
+            continue;
+        }
+        if mount_dir.join("etc/os-release").exists() {
+            return Ok(partition.to_string());
+        }
+        let _ = umount(mount_dir);
+    }
+    Err(IsoError::HookFailed(format!("no Linux root filesystem found on {}", device)).into())
+}
+
+fn umount(target: &Path) -> Result<()> {
+    let output = StdCommand::new("umount").arg(target).output()?;
+    if !output.status.success() {
+        return Err(IsoError::HookFailed(format!(
+            "umount failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_builtin_actions() {
+        let root = tempfile::tempdir().unwrap();
+        let meta = fs::metadata(root.path()).unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(
+            root.path().join("etc/passwd"),
+            format!(
+                "root:x:0:0:root:/root:/bin/bash\nadmin:x:{}:{}:Admin:/home/admin:/bin/bash\n",
+                meta.uid(),
+                meta.gid()
+            ),
+        )
+        .unwrap();
+        fs::write(
+            root.path().join("etc/hosts"),
+            "127.0.0.1\tlocalhost\n127.0.1.1\tdebian\n",
+        )
+        .unwrap();
+
+        let mut target = TargetConfig::default();
+        target.user.username = "admin".to_string();
+        target.user.ssh_authorized_keys = vec!["ssh-ed25519 AAAAC3Nz admin@ops".to_string()];
+        install_ssh_keys(root.path(), &target).unwrap();
+        // Running the hook again does not duplicate keys
+        install_ssh_keys(root.path(), &target).unwrap();
+        let keys_path = root.path().join("home/admin/.ssh/authorized_keys");
+        assert_eq!(
+            fs::read_to_string(&keys_path).unwrap(),
+            "ssh-ed25519 AAAAC3Nz admin@ops\n"
+        );
+        assert_eq!(fs::metadata(&keys_path).unwrap().mode() & 0o777, 0o600);
+
+        set_hostname(root.path(), "lab-07").unwrap();
+        assert_eq!(
+            fs::read_to_string(root.path().join("etc/hostname")).unwrap(),
+            "lab-07\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.path().join("etc/hosts")).unwrap(),
+            "127.0.0.1\tlocalhost\n127.0.1.1\tlab-07\n"
+        );
+
+        let unit = root.path().join("firstboot.service");
+        fs::write(&unit, "[Service]\nType=oneshot\n").unwrap();
+        enable_service(root.path(), "firstboot.service", Some(&unit)).unwrap();
+        assert_eq!(
+            fs::read_link(
+                root.path()
+                    .join("etc/systemd/system/multi-user.target.wants/firstboot.service")
+            )
+            .unwrap(),
+            Path::new("/etc/systemd/system/firstboot.service")
+        );
+        assert!(enable_service(root.path(), "missing.service", None).is_err());
+    }
+
+    #[test]
+    fn test_hook_config() {
+        let hooks: Vec<PostInstallHook> = toml::from_str::<toml::Value>(
+            r#"
+            [[hooks]]
+            type = "ssh_keys"
+
+            [[hooks]]
+            name = "site"
+            type = "script"
+            path = "/etc/usb-installer-node/hooks/site.sh"
+            timeout_secs = 900
+            "#,
+        )
+        .unwrap()["hooks"]
+            .clone()
+            .try_into()
+            .unwrap();
+
+        assert_eq!(hooks[0].action, HookAction::SshKeys);
+        assert_eq!(hooks[0].timeout_secs, 300);
+        assert_eq!(hooks[0].name(), "ssh_keys");
+        assert_eq!(hooks[1].name(), "site");
+        assert_eq!(hooks[1].timeout_secs, 900);
+    }
+}
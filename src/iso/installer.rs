@@ -1,9 +1,20 @@
+use super::detector::{EsxiDetector, InstallerDetector};
+use super::feeds::compare_versions;
+use super::live::{self, LiveImage};
+use super::windows::{self, WindowsSetup};
+use crate::config::{InstallerConfig, WindowsConfig};
+use crate::dryrun::DryRun;
 use crate::error::{IsoError, Result};
+use crate::service::supervisor::{ProcessSupervisor, STOP_TIMEOUT};
+use std::cmp::Ordering;
+use std::fmt;
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::sync::{mpsc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::time::{sleep_until, Instant};
 use tracing::{debug, error, info, warn};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -24,6 +35,9 @@ pub struct InstallerInfo {
     pub os_type: String,
     pub version: Option<String>,
     pub auto_installable: bool,
+    /// Root filesystem of live media, so its contents can be shown before
+    /// installing
+    pub live: Option<LiveImage>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,11 +47,47 @@ pub struct InstallerProgress {
     pub stage: String,
 }
 
+/// Phase of a running installer process, each with its own time limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallStage {
+    /// Launched, no output yet
+    Starting,
+    /// Printing output
+    Installing,
+    /// Output closed, waiting for the process to exit
+    Finishing,
+}
+
+impl InstallStage {
+    fn timeout(&self, config: &InstallerConfig) -> Duration {
+        Duration::from_secs(match self {
+            Self::Starting => config.start_timeout_secs,
+            Self::Installing => config.install_timeout_secs,
+            Self::Finishing => config.finish_timeout_secs,
+        })
+    }
+}
+
+impl fmt::Display for InstallStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Starting => write!(f, "starting"),
+            Self::Installing => write!(f, "installing"),
+            Self::Finishing => write!(f, "finishing"),
+        }
+    }
+}
+
 pub struct IsoInstaller {
     state: Arc<RwLock<InstallerState>>,
     current_installer: Arc<RwLock<Option<InstallerInfo>>>,
-    process: Arc<RwLock<Option<Child>>>,
+    /// Stops the running process
+    cancel_tx: Arc<RwLock<Option<oneshot::Sender<()>>>>,
     progress_tx: Arc<RwLock<Option<mpsc::Sender<InstallerProgress>>>>,
+    windows_setup: Arc<RwLock<Option<WindowsSetup>>>,
+    /// Consulted after the built-in detection
+    detectors: Arc<RwLock<Vec<Arc<dyn InstallerDetector>>>>,
+    dry_run: Option<DryRun>,
 }
 
 impl IsoInstaller {
@@ -45,11 +95,43 @@ impl IsoInstaller {
         Self {
             state: Arc::new(RwLock::new(InstallerState::Idle)),
             current_installer: Arc::new(RwLock::new(None)),
-            process: Arc::new(RwLock::new(None)),
+            cancel_tx: Arc::new(RwLock::new(None)),
             progress_tx: Arc::new(RwLock::new(None)),
+            windows_setup: Arc::new(RwLock::new(None)),
+            detectors: Arc::new(RwLock::new(vec![Arc::new(EsxiDetector)])),
+            dry_run: None,
         }
     }
 
+    /// Record installer commands instead of running them
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Recognize more installer media. Replaces a detector registered for
+    /// the same `os_type`.
+    pub async fn register_detector(&self, detector: Arc<dyn InstallerDetector>) {
+        let mut detectors = self.detectors.write().await;
+        detectors.retain(|d| d.os_type() != detector.os_type());
+        info!("Registered installer detector {}", detector.os_type());
+        detectors.push(detector);
+    }
+
+    /// Whether `os_type` comes from a registered detector
+    pub async fn has_detector(&self, os_type: &str) -> bool {
+        self.detector_for(os_type).await.is_some()
+    }
+
+    async fn detector_for(&self, os_type: &str) -> Option<Arc<dyn InstallerDetector>> {
+        self.detectors
+            .read()
+            .await
+            .iter()
+            .find(|d| d.os_type() == os_type)
+            .cloned()
+    }
+
     pub async fn discover_installer(&self, mount_path: &Path) -> Result<Vec<InstallerInfo>> {
         info!("Discovering installers in {}", mount_path.display());
         self.set_state(InstallerState::Discovering).await;
@@ -70,6 +152,7 @@ impl IsoInstaller {
 
     async fn scan_for_installers(&self, mount_path: &Path) -> Result<Vec<InstallerInfo>> {
         let mut installers = Vec::new();
+        let live = self.inspect_live_image(mount_path).await;
 
         let debian_installer = mount_path.join("install.amd");
         if debian_installer.exists() {
@@ -79,6 +162,7 @@ impl IsoInstaller {
                 os_type: "debian".to_string(),
                 version: self.detect_debian_version(mount_path).await,
                 auto_installable: true,
+                live: live.clone(),
             });
         }
 
@@ -90,6 +174,7 @@ impl IsoInstaller {
                 os_type: "ubuntu".to_string(),
                 version: self.detect_ubuntu_version(mount_path).await,
                 auto_installable: true,
+                live: live.clone(),
             });
         }
 
@@ -100,7 +185,66 @@ impl IsoInstaller {
                 path: windows_installer,
                 os_type: "windows".to_string(),
                 version: self.detect_windows_version(mount_path).await,
-                auto_installable: false,
+                auto_installable: true,
+                live: None,
+            });
+        }
+
+        let arch_root = mount_path.join("arch");
+        if has_airootfs(&arch_root).await {
+            installers.push(InstallerInfo {
+                name: "Arch Linux Installer".to_string(),
+                path: arch_root.clone(),
+                os_type: "arch".to_string(),
+                version: read_first_line(&arch_root.join("version")).await,
+                // Only archinstall takes a configuration for unattended runs
+                auto_installable: has_archinstall(&arch_root).await,
+                live: live.clone(),
+            });
+        }
+
+        if let Some(release) = self.detect_anaconda_release(mount_path).await {
+            installers.push(InstallerInfo {
+                name: format!("{} Installer", release.name),
+                path: mount_path.to_path_buf(),
+                os_type: release.os_type,
+                version: release.version,
+                // Kickstart needs the full Anaconda stage 2, not a live image
+                auto_installable: mount_path.join("images/install.img").exists(),
+                live: live.clone(),
+            });
+        }
+
+        let suse_products = mount_path.join("media.1/products");
+        if suse_products.exists() {
+            let products = tokio::fs::read_to_string(&suse_products)
+                .await
+                .unwrap_or_default();
+            installers.push(InstallerInfo {
+                name: "openSUSE Installer".to_string(),
+                path: mount_path.to_path_buf(),
+                os_type: "opensuse".to_string(),
+                version: parse_suse_version(&products),
+                // AutoYaST profiles are read by every YaST installation
+                auto_installable: true,
+                live: live.clone(),
+            });
+        }
+
+        let proxmox_info = mount_path.join(".cd-info");
+        if proxmox_info.exists() {
+            let info = tokio::fs::read_to_string(&proxmox_info)
+                .await
+                .unwrap_or_default();
+            let product = shell_value(&info, "PRODUCTLONG").unwrap_or("Proxmox");
+            let version = shell_value(&info, "RELEASE").map(str::to_string);
+            installers.push(InstallerInfo {
+                name: format!("{} Installer", product),
+                path: mount_path.to_path_buf(),
+                os_type: "proxmox".to_string(),
+                auto_installable: proxmox_auto_installable(&info),
+                version,
+                live: None,
             });
         }
 
@@ -112,43 +256,85 @@ impl IsoInstaller {
                 os_type: "bsd".to_string(),
                 version: None,
                 auto_installable: true,
+                live: None,
             });
         }
 
+        let detectors = self.detectors.read().await.clone();
+        let root = mount_path.to_path_buf();
+        let detected = tokio::task::spawn_blocking(move || {
+            detectors
+                .iter()
+                .filter_map(|detector| {
+                    let found = detector.detect(&root);
+                    if let Some(info) = &found {
+                        debug!("Detector {} found {}", detector.os_type(), info.name);
+                    }
+                    found
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .map_err(|e| IsoError::InstallerFailed(format!("Installer detection failed: {}", e)))?;
+        installers.extend(detected);
+
         Ok(installers)
     }
 
+    /// Run the installer to completion, streaming its output as progress.
+    /// Each stage is bounded by `limits`.
     pub async fn start_installer(
         &self,
         installer: &InstallerInfo,
         auto_mode: bool,
-        progress_rx: mpsc::Receiver<InstallerProgress>,
+        limits: &InstallerConfig,
+        progress_tx: mpsc::Sender<InstallerProgress>,
     ) -> Result<()> {
         info!("Starting installer: {}", installer.name);
 
         if self.get_state().await != InstallerState::Ready {
-            return Err(IsoError::InvalidState("Installer not ready".to_string()));
+            return Err(IsoError::InstallerFailed("Installer not ready".to_string()).into());
         }
 
         self.set_state(InstallerState::Running).await;
+        if installer.os_type != "windows" {
+            *self.windows_setup.write().await = None;
+        }
         *self.current_installer.write().await = Some(installer.clone());
-
-        let (tx, mut rx) = mpsc::channel(100);
-        *self.progress_tx.write().await = Some(tx);
+        *self.progress_tx.write().await = Some(progress_tx);
 
         let result = match installer.os_type.as_str() {
-            "debian" => self.run_debian_installer(installer, auto_mode).await,
-            "ubuntu" => self.run_ubuntu_installer(installer, auto_mode).await,
-            "windows" => self.run_windows_installer(installer).await,
-            "bsd" => self.run_bsd_installer(installer, auto_mode).await,
-            _ => Err(IsoError::UnsupportedInstaller(installer.os_type.clone())),
+            "debian" => {
+                self.run_debian_installer(installer, auto_mode, limits)
+                    .await
+            }
+            "ubuntu" => {
+                self.run_ubuntu_installer(installer, auto_mode, limits)
+                    .await
+            }
+            "windows" => self.run_windows_installer(installer, limits).await,
+            "bsd" => self.run_bsd_installer(installer, auto_mode, limits).await,
+            os_type => match self
+                .detector_for(os_type)
+                .await
+                .and_then(|d| d.command(installer, auto_mode))
+            {
+                Some(cmd) => self.run_process(cmd, &installer.name, limits).await,
+                None => Err(IsoError::InstallerFailed(format!(
+                    "Unsupported installer type {}",
+                    installer.os_type
+                ))
+                .into()),
+            },
         };
+        *self.progress_tx.write().await = None;
 
-        match result {
+        match &result {
             Ok(_) => {
                 self.set_state(InstallerState::Completed).await;
                 info!("Installer completed successfully");
             }
+            Err(_) if self.get_state().await == InstallerState::Cancelled => {}
             Err(e) => {
                 self.set_state(InstallerState::Failed(e.to_string())).await;
                 error!("Installer failed: {}", e);
@@ -158,7 +344,12 @@ impl IsoInstaller {
         result
     }
 
-    async fn run_debian_installer(&self, installer: &InstallerInfo, auto_mode: bool) -> Result<()> {
+    async fn run_debian_installer(
+        &self,
+        installer: &InstallerInfo,
+        auto_mode: bool,
+        limits: &InstallerConfig,
+    ) -> Result<()> {
         let mut cmd = Command::new("debian-installer");
         cmd.current_dir(&installer.path);
 
@@ -167,18 +358,15 @@ impl IsoInstaller {
             cmd.arg("--priority=critical");
         }
 
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-
-        let mut child = cmd.spawn().map_err(|e| {
-            IsoError::InstallerFailed(format!("Failed to start Debian installer: {}", e))
-        })?;
-
-        self.monitor_process(&mut child).await?;
-        Ok(())
+        self.run_process(cmd, "Debian installer", limits).await
     }
 
-    async fn run_ubuntu_installer(&self, installer: &InstallerInfo, auto_mode: bool) -> Result<()> {
+    async fn run_ubuntu_installer(
+        &self,
+        installer: &InstallerInfo,
+        auto_mode: bool,
+        limits: &InstallerConfig,
+    ) -> Result<()> {
         let mut cmd = Command::new("ubiquity");
         cmd.current_dir(&installer.path);
 
@@ -186,72 +374,160 @@ impl IsoInstaller {
             cmd.arg("--automatic");
         }
 
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
+        self.run_process(cmd, "Ubuntu installer", limits).await
+    }
 
-        let mut child = cmd.spawn().map_err(|e| {
-            IsoError::InstallerFailed(format!("Failed to start Ubuntu installer: {}", e))
+    pub async fn prepare_windows_setup(
+        &self,
+        installer: &InstallerInfo,
+        config: &WindowsConfig,
+    ) -> Result<WindowsSetup> {
+        let media_root = installer.path.parent().unwrap_or(&installer.path);
+        let image = windows::find_install_image(media_root).ok_or_else(|| {
+            IsoError::InstallerNotFound(format!(
+                "install.wim or install.esd under {}",
+                media_root.display()
+            ))
         })?;
 
-        self.monitor_process(&mut child).await?;
-        Ok(())
+        let editions = windows::list_editions(&image)?;
+        let setup = WindowsSetup::from_config(config, image, &editions)?;
+
+        info!(
+            "Selected Windows edition {} (index {}), activation: {:?}",
+            setup.edition.name, setup.edition.index, setup.activation
+        );
+        *self.windows_setup.write().await = Some(setup.clone());
+        Ok(setup)
     }
 
-    async fn run_windows_installer(&self, installer: &InstallerInfo) -> Result<()> {
-        let mut cmd = Command::new(&installer.path);
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
+    pub async fn get_windows_setup(&self) -> Option<WindowsSetup> {
+        self.windows_setup.read().await.clone()
+    }
 
-        let mut child = cmd.spawn().map_err(|e| {
-            IsoError::InstallerFailed(format!("Failed to start Windows installer: {}", e))
-        })?;
+    async fn run_windows_installer(
+        &self,
+        installer: &InstallerInfo,
+        limits: &InstallerConfig,
+    ) -> Result<()> {
+        if self.windows_setup.read().await.is_none() {
+            return Err(IsoError::InstallerFailed(
+                "Windows edition has not been selected".to_string(),
+            )
+            .into());
+        }
 
-        self.monitor_process(&mut child).await?;
-        Ok(())
+        let cmd = Command::new(&installer.path);
+        self.run_process(cmd, "Windows installer", limits).await
     }
 
-    async fn run_bsd_installer(&self, installer: &InstallerInfo, auto_mode: bool) -> Result<()> {
+    async fn run_bsd_installer(
+        &self,
+        installer: &InstallerInfo,
+        auto_mode: bool,
+        limits: &InstallerConfig,
+    ) -> Result<()> {
         let mut cmd = Command::new(&installer.path);
 
         if auto_mode {
             cmd.arg("-s");
         }
 
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
+        self.run_process(cmd, "BSD installer", limits).await
+    }
 
-        let mut child = cmd.spawn().map_err(|e| {
-            IsoError::InstallerFailed(format!("Failed to start BSD installer: {}", e))
-        })?;
+    /// Run `cmd`, forwarding each output line as progress, until it exits,
+    /// a stage times out or the installer is cancelled. The process is
+    /// stopped on timeout and cancellation.
+    async fn run_process(&self, cmd: Command, name: &str, limits: &InstallerConfig) -> Result<()> {
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.command(format!("Run {}", name), cmd.as_std());
+            return Ok(());
+        }
 
-        self.monitor_process(&mut child).await?;
-        Ok(())
-    }
+        let mut process = ProcessSupervisor::global()
+            .spawn_piped(name, cmd)
+            .map_err(|e| IsoError::InstallerFailed(format!("Failed to start {}: {}", name, e)))?;
 
-    async fn monitor_process(&self, child: &mut Child) -> Result<()> {
-        *self.process.write().await = Some(child.try_into().map_err(|_| IsoError::ProcessError)?);
+        let (cancel_tx, mut cancel_rx) = oneshot::channel();
+        *self.cancel_tx.write().await = Some(cancel_tx);
 
-        let status = child
-            .wait()
-            .map_err(|e| IsoError::InstallerFailed(format!("Process wait failed: {}", e)))?;
+        let (line_tx, mut line_rx) = mpsc::channel(100);
+        if let Some(stdout) = process.take_stdout() {
+            tokio::spawn(forward_lines(stdout, line_tx.clone()));
+        }
+        if let Some(stderr) = process.take_stderr() {
+            tokio::spawn(forward_lines(stderr, line_tx));
+        }
 
-        *self.process.write().await = None;
+        let mut stage = InstallStage::Starting;
+        let mut deadline = Instant::now() + stage.timeout(limits);
+        let mut percentage = 0;
+        let result = loop {
+            tokio::select! {
+                line = line_rx.recv(), if stage != InstallStage::Finishing => match line {
+                    Some(line) => {
+                        if stage == InstallStage::Starting {
+                            stage = InstallStage::Installing;
+                            deadline = Instant::now() + stage.timeout(limits);
+                        }
+                        debug!("{}: {}", name, line);
+                        percentage = parse_percentage(&line).unwrap_or(percentage);
+                        self.report(percentage, line, stage).await;
+                    }
+                    None => {
+                        stage = InstallStage::Finishing;
+                        deadline = Instant::now() + stage.timeout(limits);
+                    }
+                },
+                status = process.wait(), if stage == InstallStage::Finishing => {
+                    break match status {
+                        Ok(status) if status.success() => Ok(()),
+                        Ok(status) => Err(IsoError::InstallerFailed(format!(
+                            "{} exited with status: {}",
+                            name, status
+                        ))),
+                        Err(e) => Err(IsoError::InstallerFailed(format!(
+                            "Waiting for {} failed: {}",
+                            name, e
+                        ))),
+                    };
+                }
+                _ = sleep_until(deadline) => {
+                    warn!("{} exceeded the {} stage limit, killing it", name, stage);
+                    break Err(IsoError::InstallerTimedOut(format!("{} while {}", name, stage)));
+                }
+                Ok(()) = &mut cancel_rx => {
+                    break Err(IsoError::InstallerFailed(format!("{} was cancelled", name)));
+                }
+            }
+        };
 
-        if !status.success() {
-            return Err(IsoError::InstallerFailed(format!(
-                "Installer exited with status: {}",
-                status
-            )));
+        *self.cancel_tx.write().await = None;
+        if result.is_err() {
+            if let Err(e) = process.stop(STOP_TIMEOUT).await {
+                warn!("Failed to stop {}: {}", name, e);
+            }
         }
+        result.map_err(Into::into)
+    }
 
-        Ok(())
+    async fn report(&self, percentage: u8, message: String, stage: InstallStage) {
+        if let Some(tx) = self.progress_tx.read().await.as_ref() {
+            let _ = tx
+                .send(InstallerProgress {
+                    percentage,
+                    message,
+                    stage: stage.to_string(),
+                })
+                .await;
+        }
     }
 
     pub async fn cancel_installer(&self) -> Result<()> {
-        if let Some(mut process) = self.process.write().await.take() {
-            process.kill().map_err(|e| IsoError::ProcessError)?;
-
+        if let Some(cancel_tx) = self.cancel_tx.write().await.take() {
             self.set_state(InstallerState::Cancelled).await;
+            let _ = cancel_tx.send(());
             info!("Installer cancelled");
         }
         Ok(())
@@ -287,6 +563,41 @@ impl IsoInstaller {
         }
     }
 
+    /// Distribution and version of Anaconda media, from `.treeinfo` or,
+    /// on older media, `.discinfo`
+    async fn detect_anaconda_release(&self, mount_path: &Path) -> Option<AnacondaRelease> {
+        if let Ok(content) = tokio::fs::read_to_string(mount_path.join(".treeinfo")).await {
+            return parse_treeinfo(&content);
+        }
+        if !mount_path.join("images/install.img").exists() {
+            return None;
+        }
+        // Timestamp, release, architecture
+        let content = tokio::fs::read_to_string(mount_path.join(".discinfo"))
+            .await
+            .ok()?;
+        Some(AnacondaRelease {
+            name: "Fedora".to_string(),
+            os_type: "fedora".to_string(),
+            version: content.lines().nth(1).map(|v| v.trim().to_string()),
+        })
+    }
+
+    async fn inspect_live_image(&self, mount_path: &Path) -> Option<LiveImage> {
+        let root = mount_path.to_path_buf();
+        let live = tokio::task::spawn_blocking(move || live::inspect(&root))
+            .await
+            .ok()
+            .flatten()?;
+        info!(
+            "Live image {}: kernel {}, {} packages",
+            live.squashfs.display(),
+            live.kernel_version.as_deref().unwrap_or("unknown"),
+            live.packages.len()
+        );
+        Some(live)
+    }
+
     async fn detect_windows_version(&self, mount_path: &Path) -> Option<String> {
         let sources = mount_path.join("sources");
         if sources.exists() {
@@ -311,10 +622,158 @@ impl IsoInstaller {
                 let parent = installer.path.parent().unwrap_or(&installer.path);
                 Ok(parent.join("sources").exists() && parent.join("boot").exists())
             }
+            "arch" => Ok(has_airootfs(&installer.path).await),
+            "fedora" | "rhel" | "centos" | "rocky" | "alma" => {
+                Ok(installer.path.join(".treeinfo").exists()
+                    || installer.path.join("images/install.img").exists())
+            }
+            "opensuse" => Ok(installer.path.join("media.1/products").exists()),
+            "proxmox" => Ok(installer.path.join(".cd-info").exists()),
             "bsd" => Ok(true),
-            _ => Ok(false),
+            os_type => Ok(self
+                .detector_for(os_type)
+                .await
+                .is_some_and(|d| d.validate(installer))),
+        }
+    }
+}
+
+/// Distribution named by Anaconda media
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AnacondaRelease {
+    name: String,
+    /// Key for the answer file format, e.g. `rhel`
+    os_type: String,
+    version: Option<String>,
+}
+
+/// `[release]` (or the older `[general]`) section of a `.treeinfo`
+fn parse_treeinfo(content: &str) -> Option<AnacondaRelease> {
+    let mut section = "";
+    let mut values: Vec<(&str, &str, &str)> = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name;
+        } else if let Some((key, value)) = line.split_once('=') {
+            values.push((section, key.trim(), value.trim()));
+        }
+    }
+    let value = |key: &str| {
+        ["release", "general"].iter().find_map(|wanted| {
+            values
+                .iter()
+                .find(|(section, k, _)| section == wanted && *k == key)
+                .map(|(_, _, value)| value.to_string())
+        })
+    };
+
+    let name = value("name").or_else(|| value("family"))?;
+    let lower = name.to_ascii_lowercase();
+    let os_type = if lower.contains("red hat") {
+        "rhel"
+    } else if lower.contains("centos") {
+        "centos"
+    } else if lower.contains("rocky") {
+        "rocky"
+    } else if lower.contains("alma") {
+        "alma"
+    } else {
+        "fedora"
+    };
+    Some(AnacondaRelease {
+        name,
+        os_type: os_type.to_string(),
+        version: value("version"),
+    })
+}
+
+/// Version from `media.1/products`, e.g. `/ openSUSE-Leap 15.5-1` → 15.5
+fn parse_suse_version(products: &str) -> Option<String> {
+    let version = products.lines().next()?.split_whitespace().nth(2)?;
+    Some(
+        version
+            .rsplit_once('-')
+            .map_or(version, |(v, _)| v)
+            .to_string(),
+    )
+}
+
+/// `KEY='value'` from Proxmox's `.cd-info`
+fn shell_value<'a>(content: &'a str, key: &str) -> Option<&'a str> {
+    content.lines().find_map(|line| {
+        let value = line.trim().strip_prefix(key)?.strip_prefix('=')?;
+        Some(value.trim_matches(|c| c == '\'' || c == '"'))
+    })
+}
+
+/// Proxmox VE reads an answer file from 8.2 on
+fn proxmox_auto_installable(info: &str) -> bool {
+    shell_value(info, "PRODUCT") == Some("pve")
+        && shell_value(info, "RELEASE")
+            .is_some_and(|v| compare_versions(v, "8.2") != Ordering::Less)
+}
+
+/// Root filesystem image of archiso media, e.g. `arch/x86_64/airootfs.sfs`
+async fn has_airootfs(arch_root: &Path) -> bool {
+    let Ok(mut entries) = tokio::fs::read_dir(arch_root).await else {
+        return false;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let dir = entry.path();
+        if dir.join("airootfs.sfs").exists() || dir.join("airootfs.erofs").exists() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether a `pkglist.<arch>.txt` of archiso media lists archinstall
+async fn has_archinstall(arch_root: &Path) -> bool {
+    let Ok(mut entries) = tokio::fs::read_dir(arch_root).await else {
+        return false;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !(name.starts_with("pkglist.") && name.ends_with(".txt")) {
+            continue;
+        }
+        if let Ok(list) = tokio::fs::read_to_string(entry.path()).await {
+            if list
+                .lines()
+                .any(|line| line.split_whitespace().next() == Some("archinstall"))
+            {
+                return true;
+            }
         }
     }
+    false
+}
+
+async fn read_first_line(path: &Path) -> Option<String> {
+    let content = tokio::fs::read_to_string(path).await.ok()?;
+    content
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+}
+
+/// Send each line of `output` until it closes
+async fn forward_lines(output: impl AsyncRead + Unpin, tx: mpsc::Sender<String>) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if tx.send(line).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Last `NN%` in an installer output line
+fn parse_percentage(line: &str) -> Option<u8> {
+    line.rmatch_indices('%').find_map(|(i, _)| {
+        let digits = line[..i].rsplit(|c: char| !c.is_ascii_digit()).next()?;
+        digits.parse::<u8>().ok().filter(|&p| p <= 100)
+    })
 }
 
 impl Default for IsoInstaller {
@@ -373,8 +832,125 @@ mod tests {
             os_type: "debian".to_string(),
             version: None,
             auto_installable: true,
+            live: None,
         };
 
         assert!(!installer.validate_installer(&info).await.unwrap());
     }
+
+    #[test]
+    fn test_parse_release_files() {
+        let treeinfo = "\
+[general]
+family = Red Hat Enterprise Linux
+version = 9.3
+
+[release]
+name = Red Hat Enterprise Linux
+short = RHEL
+version = 9.4
+";
+        let release = parse_treeinfo(treeinfo).unwrap();
+        assert_eq!(release.os_type, "rhel");
+        assert_eq!(release.version.as_deref(), Some("9.4"));
+        let release = parse_treeinfo("[general]\nfamily = Fedora\nversion = 40\n").unwrap();
+        assert_eq!(release.name, "Fedora");
+        assert_eq!(release.os_type, "fedora");
+        assert!(parse_treeinfo("[checksums]\nimages/install.img = sha256:00\n").is_none());
+
+        assert_eq!(
+            parse_suse_version("/ openSUSE-Leap 15.5-1\n").as_deref(),
+            Some("15.5")
+        );
+        assert_eq!(parse_suse_version("/ openSUSE\n"), None);
+
+        let cd_info = "PRODUCT='pve'\nPRODUCTLONG='Proxmox VE'\nRELEASE='8.2'\nISORELEASE='1'\n";
+        assert_eq!(shell_value(cd_info, "PRODUCTLONG"), Some("Proxmox VE"));
+        assert!(proxmox_auto_installable(cd_info));
+        assert!(!proxmox_auto_installable("PRODUCT='pve'\nRELEASE='8.1'\n"));
+        assert!(!proxmox_auto_installable("PRODUCT='pmg'\nRELEASE='8.2'\n"));
+    }
+
+    #[tokio::test]
+    async fn test_discover_arch_and_proxmox() {
+        let installer = IsoInstaller::new();
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("arch/x86_64")).unwrap();
+        std::fs::write(root.join("arch/x86_64/airootfs.sfs"), b"").unwrap();
+        std::fs::write(root.join("arch/version"), "2024.06.01\n").unwrap();
+        std::fs::write(
+            root.join("arch/pkglist.x86_64.txt"),
+            "arch-install-scripts 28-1\narchinstall 2.8.0-1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join(".cd-info"),
+            "PRODUCT='pve'\nPRODUCTLONG='Proxmox VE'\nRELEASE='8.1'\n",
+        )
+        .unwrap();
+
+        let found = installer.discover_installer(root).await.unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].os_type, "arch");
+        assert_eq!(found[0].version.as_deref(), Some("2024.06.01"));
+        assert!(found[0].auto_installable);
+        assert_eq!(found[1].name, "Proxmox VE Installer");
+        assert!(!found[1].auto_installable);
+        for info in &found {
+            assert!(installer.validate_installer(info).await.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_parse_percentage() {
+        assert_eq!(
+            parse_percentage("Installing the base system... 42%"),
+            Some(42)
+        );
+        assert_eq!(parse_percentage("10% done, 35% of packages"), Some(35));
+        assert_eq!(parse_percentage("Retrieving file 3 of 12"), None);
+        assert_eq!(parse_percentage("250% faster"), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_process_streams_and_times_out() {
+        let installer = IsoInstaller::new();
+        let (tx, mut rx) = mpsc::channel(10);
+        *installer.progress_tx.write().await = Some(tx);
+        let limits = InstallerConfig::default();
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo 'Partitioning 20%'; echo warning >&2"]);
+        installer.run_process(cmd, "test", &limits).await.unwrap();
+        let mut messages = Vec::new();
+        while let Ok(progress) = rx.try_recv() {
+            assert_eq!(progress.stage, "installing");
+            if progress.message.starts_with("Partitioning") {
+                assert_eq!(progress.percentage, 20);
+            }
+            messages.push(progress.message);
+        }
+        messages.sort();
+        assert_eq!(messages, ["Partitioning 20%", "warning"]);
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "exit 3"]);
+        assert!(installer.run_process(cmd, "test", &limits).await.is_err());
+
+        // Silent installer: killed once the start stage runs out
+        let limits = InstallerConfig {
+            start_timeout_secs: 1,
+            ..InstallerConfig::default()
+        };
+        let mut cmd = Command::new("sleep");
+        cmd.arg("30");
+        let started = std::time::Instant::now();
+        let err = installer
+            .run_process(cmd, "test", &limits)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("while starting"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
 }
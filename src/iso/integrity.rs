@@ -0,0 +1,225 @@
+use crate::error::{IsoError, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+/// Checksum lists shipped inside the image, strongest first
+const MANIFESTS: &[(&str, &str, IntegrityMethod)] = &[
+    ("sha256sum.txt", "sha256sum", IntegrityMethod::Sha256sumTxt),
+    ("md5sum.txt", "md5sum", IntegrityMethod::Md5sumTxt),
+];
+
+/// Verification the distribution provides for its media
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityMethod {
+    /// `sha256sum.txt` at the root (Debian)
+    Sha256sumTxt,
+    /// `md5sum.txt` at the root (Debian, Ubuntu)
+    Md5sumTxt,
+    /// Whole-image MD5 implanted by `implantisomd5` (Fedora, RHEL)
+    ImplantedMd5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// Content differs from the embedded checksum
+    ChecksumMismatch,
+    /// Listed but missing or unreadable
+    Unreadable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileMismatch {
+    /// Path inside the image as listed in the manifest
+    pub path: String,
+    pub problem: Problem,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IntegrityReport {
+    pub iso: PathBuf,
+    pub method: IntegrityMethod,
+    /// Files (or whole images) checked
+    pub checked: usize,
+    pub mismatches: Vec<FileMismatch>,
+}
+
+impl IntegrityReport {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Verify `iso`, mounted at `root`, with its embedded checksums. Fails when
+/// the image carries none.
+pub fn check(iso: &Path, root: &Path) -> Result<IntegrityReport> {
+    for (manifest, tool, method) in MANIFESTS {
+        if root.join(manifest).is_file() {
+            info!("Checking {} against {}", iso.display(), manifest);
+            return check_manifest(iso, root, manifest, tool, *method);
+        }
+    }
+    match check_implanted(iso)? {
+        Some(report) => Ok(report),
+        None => Err(IsoError::InvalidFormat(format!(
+            "{} carries no embedded checksums",
+            iso.display()
+        ))
+        .into()),
+    }
+}
+
+fn check_manifest(
+    iso: &Path,
+    root: &Path,
+    manifest: &str,
+    tool: &str,
+    method: IntegrityMethod,
+) -> Result<IntegrityReport> {
+    // Exits non-zero on any mismatch; the per-file lines tell which
+    let output = Command::new(tool)
+        .args(["--check", manifest])
+        .current_dir(root)
+        .output()
+        .map_err(|e| IsoError::InvalidFormat(format!("Failed to run {}: {}", tool, e)))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        debug!("{} reported: {}", tool, stderr.trim());
+    }
+
+    let (checked, mismatches) = parse_check(&String::from_utf8_lossy(&output.stdout));
+    if checked == 0 {
+        return Err(IsoError::InvalidFormat(format!(
+            "{}: {} lists no files: {}",
+            iso.display(),
+            manifest,
+            stderr.trim()
+        ))
+        .into());
+    }
+    Ok(IntegrityReport {
+        iso: iso.to_path_buf(),
+        method,
+        checked,
+        mismatches,
+    })
+}
+
+/// `checkisomd5` for images without a checksum list. `None` when the
+/// tool is missing or the image has no implanted checksum.
+fn check_implanted(iso: &Path) -> Result<Option<IntegrityReport>> {
+    let output = match Command::new("checkisomd5").arg(iso).output() {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("checkisomd5 unavailable: {}", e);
+            return Ok(None);
+        }
+    };
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    if text.contains("No checksum information") {
+        return Ok(None);
+    }
+
+    let mut mismatches = Vec::new();
+    if !output.status.success() {
+        mismatches.push(FileMismatch {
+            path: iso
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            problem: Problem::ChecksumMismatch,
+        });
+    }
+    Ok(Some(IntegrityReport {
+        iso: iso.to_path_buf(),
+        method: IntegrityMethod::ImplantedMd5,
+        checked: 1,
+        mismatches,
+    }))
+}
+
+/// Parse `md5sum --check` / `sha256sum --check` output into the number of
+/// files checked and the ones that failed
+fn parse_check(output: &str) -> (usize, Vec<FileMismatch>) {
+    let mut checked = 0;
+    let mut mismatches = Vec::new();
+    for line in output.lines() {
+        let Some((path, status)) = line.rsplit_once(": ") else {
+            continue;
+        };
+        let path = path.strip_prefix("./").unwrap_or(path).to_string();
+        let problem = match status.trim() {
+            "OK" => None,
+            "FAILED" => Some(Problem::ChecksumMismatch),
+            "FAILED open or read" => Some(Problem::Unreadable),
+            _ => continue,
+        };
+        checked += 1;
+        if let Some(problem) = problem {
+            mismatches.push(FileMismatch { path, problem });
+        }
+    }
+    (checked, mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_check() {
+        let output = "\
+./.disk/info: OK
+./pool/main/l/linux/linux-image-6.1.0-18-amd64_6.1.76-1_amd64.deb: FAILED
+./install.amd/vmlinuz: OK
+./firmware/firmware-nonfree.deb: FAILED open or read
+";
+        let (checked, mismatches) = parse_check(output);
+        assert_eq!(checked, 4);
+        assert_eq!(
+            mismatches,
+            vec![
+                FileMismatch {
+                    path: "pool/main/l/linux/linux-image-6.1.0-18-amd64_6.1.76-1_amd64.deb"
+                        .to_string(),
+                    problem: Problem::ChecksumMismatch,
+                },
+                FileMismatch {
+                    path: "firmware/firmware-nonfree.deb".to_string(),
+                    problem: Problem::Unreadable,
+                },
+            ]
+        );
+        assert_eq!(parse_check(""), (0, Vec::new()));
+    }
+
+    #[test]
+    fn test_check_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("casper")).unwrap();
+        std::fs::write(root.join("casper/vmlinuz"), b"kernel").unwrap();
+        std::fs::write(root.join("casper/initrd"), b"initrd").unwrap();
+        // The initrd entry is deliberately wrong
+        std::fs::write(
+            root.join("md5sum.txt"),
+            "50484c19f1afdaf3841a0d821ed393d2  ./casper/vmlinuz\n\
+             00000000000000000000000000000000  ./casper/initrd\n",
+        )
+        .unwrap();
+
+        let report = check(Path::new("ubuntu.iso"), root).unwrap();
+        assert_eq!(report.method, IntegrityMethod::Md5sumTxt);
+        assert_eq!(report.checked, 2);
+        assert!(!report.passed());
+        assert_eq!(report.mismatches[0].path, "casper/initrd");
+    }
+}
@@ -0,0 +1,332 @@
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// Directories live media keep their root filesystem in: casper (Ubuntu),
+/// live (Debian live-build), LiveOS (Fedora)
+const LIVE_DIRS: &[&str] = &["casper", "live", "LiveOS"];
+const SQUASHFS_MAGIC: u32 = 0x7371_7368;
+const SUPERBLOCK_SIZE: usize = 96;
+/// `HdrS` in the x86 boot protocol header
+const BZIMAGE_MAGIC: &[u8] = b"HdrS";
+
+/// Contents of a live image's root filesystem, read without installing it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LiveImage {
+    /// Root filesystem image, relative to the media root
+    pub squashfs: PathBuf,
+    pub compression: Option<String>,
+    /// Size of the compressed filesystem
+    pub size: u64,
+    pub created: Option<SystemTime>,
+    pub kernel_version: Option<String>,
+    /// From the manifest shipped next to the image, or the dpkg database
+    /// inside it; empty when neither is available
+    pub packages: Vec<Package>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+}
+
+/// Squashfs superblock fields worth showing
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Superblock {
+    compression: Option<&'static str>,
+    bytes_used: u64,
+    modified: SystemTime,
+}
+
+/// Inspect the live root filesystem of mounted media. `None` when the media
+/// is not a live image. Reads the image, so call it from a blocking context.
+pub fn inspect(media_root: &Path) -> Option<LiveImage> {
+    let (dir, image) = find_squashfs(media_root)?;
+    let superblock = match read_superblock(&image) {
+        Ok(superblock) => superblock,
+        Err(e) => {
+            debug!("{} is not a squashfs image: {}", image.display(), e);
+            return None;
+        }
+    };
+
+    let packages = read_manifest(&image).unwrap_or_else(|| read_dpkg_status(&image));
+    Some(LiveImage {
+        squashfs: image
+            .strip_prefix(media_root)
+            .unwrap_or(&image)
+            .to_path_buf(),
+        compression: superblock.compression.map(String::from),
+        size: superblock.bytes_used,
+        created: Some(superblock.modified),
+        kernel_version: find_kernel_version(&dir),
+        packages,
+    })
+}
+
+/// Root filesystem image: `filesystem.squashfs` or Fedora's `squashfs.img`
+/// when present, otherwise the largest layer (Ubuntu's layered images)
+fn find_squashfs(media_root: &Path) -> Option<(PathBuf, PathBuf)> {
+    for name in LIVE_DIRS {
+        let dir = media_root.join(name);
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        let mut candidates: Vec<(u64, PathBuf)> = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| {
+                p.extension().is_some_and(|e| e == "squashfs")
+                    || p.file_name().is_some_and(|n| n == "squashfs.img")
+            })
+            .map(|p| (fs::metadata(&p).map(|m| m.len()).unwrap_or(0), p))
+            .collect();
+
+        let preferred = candidates.iter().position(|(_, p)| {
+            p.file_name()
+                .is_some_and(|n| n == "filesystem.squashfs" || n == "squashfs.img")
+        });
+        let image = match preferred {
+            Some(index) => candidates.swap_remove(index).1,
+            None => candidates.into_iter().max()?.1,
+        };
+        return Some((dir, image));
+    }
+    None
+}
+
+fn read_superblock(image: &Path) -> std::io::Result<Superblock> {
+    let mut buf = [0u8; SUPERBLOCK_SIZE];
+    File::open(image)?.read_exact(&mut buf)?;
+    parse_superblock(&buf)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "bad magic"))
+}
+
+fn parse_superblock(buf: &[u8; SUPERBLOCK_SIZE]) -> Option<Superblock> {
+    let u16_at = |at: usize| u16::from_le_bytes([buf[at], buf[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+    let u64_at = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+
+    if u32_at(0) != SQUASHFS_MAGIC {
+        return None;
+    }
+    let compression = match u16_at(20) {
+        1 => Some("gzip"),
+        2 => Some("lzma"),
+        3 => Some("lzo"),
+        4 => Some("xz"),
+        5 => Some("lz4"),
+        6 => Some("zstd"),
+        _ => None,
+    };
+    Some(Superblock {
+        compression,
+        bytes_used: u64_at(40),
+        modified: SystemTime::UNIX_EPOCH + Duration::from_secs(u32_at(8).into()),
+    })
+}
+
+/// Kernel next to the image: the version string in the bzImage header, or
+/// the `vmlinuz-<version>` file name for other architectures
+fn find_kernel_version(dir: &Path) -> Option<String> {
+    let mut kernels: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with("vmlinuz"))
+        })
+        .collect();
+    kernels.sort();
+
+    kernels.iter().find_map(|kernel| {
+        read_bzimage_version(kernel).or_else(|| {
+            let name = kernel.file_name()?.to_string_lossy().into_owned();
+            name.strip_prefix("vmlinuz-").map(String::from)
+        })
+    })
+}
+
+fn read_bzimage_version(kernel: &Path) -> Option<String> {
+    let mut file = File::open(kernel).ok()?;
+    let mut header = [0u8; 0x210];
+    file.read_exact(&mut header).ok()?;
+    if &header[0x202..0x206] != BZIMAGE_MAGIC {
+        return None;
+    }
+
+    let offset = u16::from_le_bytes([header[0x20E], header[0x20F]]);
+    if offset == 0 {
+        return None;
+    }
+    let mut version = [0u8; 128];
+    file.seek(SeekFrom::Start(u64::from(offset) + 0x200)).ok()?;
+    let len = file.read(&mut version).ok()?;
+    let version = &version[..len];
+    let end = version.iter().position(|&b| b == 0).unwrap_or(len);
+    // "6.8.0-31-generic (buildd@...) #31-Ubuntu SMP ..."
+    String::from_utf8_lossy(&version[..end])
+        .split_whitespace()
+        .next()
+        .map(String::from)
+}
+
+/// `<stem>.manifest` (casper) or `<stem>.packages` (live-build) next to the
+/// image: one `name<whitespace>version` per line
+fn read_manifest(image: &Path) -> Option<Vec<Package>> {
+    ["manifest", "packages"].iter().find_map(|extension| {
+        let content = fs::read_to_string(image.with_extension(extension)).ok()?;
+        Some(parse_manifest(&content))
+    })
+}
+
+fn parse_manifest(content: &str) -> Vec<Package> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Package {
+                name: fields.next()?.to_string(),
+                version: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Installed packages from the image's dpkg database, via `unsquashfs`
+fn read_dpkg_status(image: &Path) -> Vec<Package> {
+    let output = Command::new("unsquashfs")
+        .arg("-cat")
+        .arg(image)
+        .arg("var/lib/dpkg/status")
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            parse_dpkg_status(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            debug!("unsquashfs unavailable: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn parse_dpkg_status(content: &str) -> Vec<Package> {
+    content
+        .split("\n\n")
+        .filter(|stanza| {
+            stanza
+                .lines()
+                .any(|l| l.starts_with("Status:") && l.ends_with(" installed"))
+        })
+        .filter_map(|stanza| {
+            let field = |name: &str| {
+                stanza
+                    .lines()
+                    .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+                    .map(|v| v.trim().to_string())
+            };
+            Some(Package {
+                name: field("Package")?,
+                version: field("Version")?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn superblock(compression: u16, bytes_used: u64) -> Vec<u8> {
+        let mut buf = vec![0u8; SUPERBLOCK_SIZE];
+        buf[0..4].copy_from_slice(&SQUASHFS_MAGIC.to_le_bytes());
+        buf[8..12].copy_from_slice(&1_700_000_000u32.to_le_bytes());
+        buf[20..22].copy_from_slice(&compression.to_le_bytes());
+        buf[40..48].copy_from_slice(&bytes_used.to_le_bytes());
+        buf
+    }
+
+    fn bzimage(version: &str) -> Vec<u8> {
+        let mut buf = vec![0u8; 0x400];
+        buf[0x202..0x206].copy_from_slice(BZIMAGE_MAGIC);
+        buf[0x20E..0x210].copy_from_slice(&0x100u16.to_le_bytes());
+        buf[0x300..0x300 + version.len()].copy_from_slice(version.as_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_inspect_casper() {
+        let media = TempDir::new().unwrap();
+        let casper = media.path().join("casper");
+        fs::create_dir(&casper).unwrap();
+        fs::write(casper.join("minimal.squashfs"), superblock(4, 100)).unwrap();
+        let mut layered = superblock(4, 2_000);
+        layered.resize(200, 0);
+        fs::write(casper.join("minimal.standard.squashfs"), layered).unwrap();
+        fs::write(
+            casper.join("minimal.standard.manifest"),
+            "adduser\t3.137ubuntu1\nbash\t5.2.21-2ubuntu4\n",
+        )
+        .unwrap();
+        fs::write(
+            casper.join("vmlinuz"),
+            bzimage("6.8.0-31-generic (buildd@lcy02-amd64-080) #31-Ubuntu SMP"),
+        )
+        .unwrap();
+
+        let live = inspect(media.path()).unwrap();
+        assert_eq!(live.squashfs, Path::new("casper/minimal.standard.squashfs"));
+        assert_eq!(live.compression.as_deref(), Some("xz"));
+        assert_eq!(live.size, 2_000);
+        assert_eq!(live.kernel_version.as_deref(), Some("6.8.0-31-generic"));
+        assert_eq!(
+            live.packages[1],
+            Package {
+                name: "bash".to_string(),
+                version: "5.2.21-2ubuntu4".to_string()
+            }
+        );
+
+        assert!(inspect(&casper).is_none());
+    }
+
+    #[test]
+    fn test_inspect_debian_live() {
+        let media = TempDir::new().unwrap();
+        let live_dir = media.path().join("live");
+        fs::create_dir(&live_dir).unwrap();
+        fs::write(live_dir.join("filesystem.squashfs"), superblock(6, 1)).unwrap();
+        fs::write(live_dir.join("filesystem.packages"), "zstd 1.5.4+dfsg2-5\n").unwrap();
+        // arm64 kernels are not bzImages
+        fs::write(live_dir.join("vmlinuz-6.1.0-18-arm64"), b"MZ").unwrap();
+
+        let live = inspect(media.path()).unwrap();
+        assert_eq!(live.compression.as_deref(), Some("zstd"));
+        assert_eq!(live.kernel_version.as_deref(), Some("6.1.0-18-arm64"));
+        assert_eq!(live.packages.len(), 1);
+
+        fs::write(live_dir.join("filesystem.squashfs"), b"not a squashfs").unwrap();
+        assert!(inspect(media.path()).is_none());
+    }
+
+    #[test]
+    fn test_parse_dpkg_status() {
+        let status = "Package: bash\nStatus: install ok installed\nVersion: 5.2.15-2\n\n\
+                      Package: gone\nStatus: deinstall ok config-files\nVersion: 1.0\n\n\
+                      Package: zstd\nStatus: install ok installed\nArchitecture: amd64\nVersion: 1.5.4\n";
+        let packages = parse_dpkg_status(status);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[1].name, "zstd");
+        assert_eq!(packages[1].version, "1.5.4");
+    }
+}
@@ -0,0 +1,307 @@
+use crate::error::{IsoError, Result};
+use nix::errno::Errno;
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use std::fs::{File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+const LOOP_CONTROL: &str = "/dev/loop-control";
+const LO_FLAGS_READ_ONLY: u32 = 1;
+/// Detach the device once the last user (the mount) is gone
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+/// Another process can claim the free device between the two ioctls
+const ATTACH_ATTEMPTS: usize = 3;
+/// Tried in order: Windows images are UDF with a stub ISO 9660 tree
+const FILESYSTEMS: &[&str] = &["udf", "iso9660"];
+
+mod ioctl {
+    use super::LoopInfo64;
+
+    nix::ioctl_none_bad!(loop_ctl_get_free, 0x4C82);
+    nix::ioctl_write_int_bad!(loop_set_fd, 0x4C00);
+    nix::ioctl_none_bad!(loop_clr_fd, 0x4C01);
+    nix::ioctl_write_ptr_bad!(loop_set_status64, 0x4C04, LoopInfo64);
+}
+
+/// `struct loop_info64` from `<linux/loop.h>`
+#[repr(C)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; 64],
+    lo_crypt_name: [u8; 64],
+    lo_encrypt_key: [u8; 32],
+    lo_init: [u64; 2],
+}
+
+impl LoopInfo64 {
+    fn read_only(image: &Path) -> Self {
+        let mut info = Self {
+            lo_device: 0,
+            lo_inode: 0,
+            lo_rdevice: 0,
+            lo_offset: 0,
+            lo_sizelimit: 0,
+            lo_number: 0,
+            lo_encrypt_type: 0,
+            lo_encrypt_key_size: 0,
+            lo_flags: LO_FLAGS_READ_ONLY | LO_FLAGS_AUTOCLEAR,
+            lo_file_name: [0; 64],
+            lo_crypt_name: [0; 64],
+            lo_encrypt_key: [0; 32],
+            lo_init: [0; 2],
+        };
+        // Shown by losetup; truncated, always NUL-terminated
+        let name = image.as_os_str().as_encoded_bytes();
+        let len = name.len().min(info.lo_file_name.len() - 1);
+        info.lo_file_name[..len].copy_from_slice(&name[..len]);
+        info
+    }
+}
+
+/// Read-only loop device backed by an image. Detaches itself when dropped
+/// unless a mount holds it.
+pub struct LoopDevice {
+    path: PathBuf,
+    device: File,
+}
+
+impl LoopDevice {
+    pub fn attach(image: &Path) -> Result<Self> {
+        let backing = File::open(image).map_err(|e| {
+            IsoError::MountFailed(format!("Cannot open {}: {}", image.display(), e))
+        })?;
+        let control = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(LOOP_CONTROL)
+            .map_err(|e| IsoError::MountFailed(format!("Cannot open {}: {}", LOOP_CONTROL, e)))?;
+
+        let mut last = Errno::EBUSY;
+        for _ in 0..ATTACH_ATTEMPTS {
+            // SAFETY: LOOP_CTL_GET_FREE takes no argument
+            let number = unsafe { ioctl::loop_ctl_get_free(control.as_raw_fd()) }
+                .map_err(|e| errno_error("LOOP_CTL_GET_FREE", Path::new(LOOP_CONTROL), e))?;
+            let path = PathBuf::from(format!("/dev/loop{}", number));
+            let device = OpenOptions::new().read(true).open(&path).map_err(|e| {
+                IsoError::MountFailed(format!("Cannot open {}: {}", path.display(), e))
+            })?;
+
+            // SAFETY: both descriptors are open for the duration of the call
+            match unsafe { ioctl::loop_set_fd(device.as_raw_fd(), backing.as_raw_fd()) } {
+                Ok(_) => {}
+                Err(Errno::EBUSY) => {
+                    debug!("{} was claimed concurrently, retrying", path.display());
+                    last = Errno::EBUSY;
+                    continue;
+                }
+                Err(e) => return Err(errno_error("LOOP_SET_FD", &path, e)),
+            }
+
+            let info = LoopInfo64::read_only(image);
+            // SAFETY: `info` matches the kernel's loop_info64 layout and
+            // outlives the call
+            if let Err(e) = unsafe { ioctl::loop_set_status64(device.as_raw_fd(), &info) } {
+                // SAFETY: the device was bound by LOOP_SET_FD above
+                let _ = unsafe { ioctl::loop_clr_fd(device.as_raw_fd()) };
+                return Err(errno_error("LOOP_SET_STATUS64", &path, e));
+            }
+
+            debug!("Attached {} to {}", image.display(), path.display());
+            return Ok(Self { path, device });
+        }
+        Err(errno_error("LOOP_SET_FD", Path::new(LOOP_CONTROL), last))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn detach(&self) {
+        // SAFETY: LOOP_CLR_FD takes no argument; fails harmlessly when
+        // the device is already free or still mounted
+        let _ = unsafe { ioctl::loop_clr_fd(self.device.as_raw_fd()) };
+    }
+}
+
+/// Attach `image` to a free loop device and mount it read-only at
+/// `target`. Returns the loop device, which is released on unmount.
+pub fn mount_image(image: &Path, target: &Path, options: &[String]) -> Result<PathBuf> {
+    let (flags, data) = mount_options(options);
+    let device = LoopDevice::attach(image)?;
+
+    let mut errors = Vec::new();
+    for fs_type in FILESYSTEMS {
+        match mount(
+            Some(device.path()),
+            target,
+            Some(*fs_type),
+            flags,
+            data.as_deref(),
+        ) {
+            Ok(()) => {
+                info!(
+                    "Mounted {} ({}) on {} via {}",
+                    image.display(),
+                    fs_type,
+                    target.display(),
+                    device.path().display()
+                );
+                // The mount now holds the device; autoclear frees it on unmount
+                return Ok(device.path().to_path_buf());
+            }
+            Err(e) => errors.push(format!("{}: {}", fs_type, e.desc())),
+        }
+    }
+
+    device.detach();
+    Err(IsoError::MountFailed(format!(
+        "{} on {}: {}",
+        device.path().display(),
+        target.display(),
+        errors.join(", ")
+    ))
+    .into())
+}
+
+pub fn unmount(target: &Path) -> Result<()> {
+    umount2(target, MntFlags::empty())
+        .map_err(|e| IsoError::UnmountFailed(format!("{}: {}", target.display(), e.desc())).into())
+}
+
+/// Attached loop devices and the image backing each
+pub fn attached() -> Vec<(PathBuf, PathBuf)> {
+    attached_in(Path::new("/sys/block"))
+}
+
+fn attached_in(sys_block: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(sys_block) else {
+        return Vec::new();
+    };
+    let mut devices: Vec<(PathBuf, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with("loop") {
+                return None;
+            }
+            // Only present while the device is bound
+            let backing = std::fs::read_to_string(entry.path().join("loop/backing_file")).ok()?;
+            Some((
+                Path::new("/dev").join(name),
+                PathBuf::from(backing.trim_end()),
+            ))
+        })
+        .collect();
+    devices.sort();
+    devices
+}
+
+/// Next free loop device, which the kernel creates if none is left
+pub fn free_device() -> Result<PathBuf> {
+    let control = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(LOOP_CONTROL)
+        .map_err(|e| IsoError::MountFailed(format!("Cannot open {}: {}", LOOP_CONTROL, e)))?;
+    // SAFETY: LOOP_CTL_GET_FREE takes no argument
+    let number = unsafe { ioctl::loop_ctl_get_free(control.as_raw_fd()) }
+        .map_err(|e| errno_error("LOOP_CTL_GET_FREE", Path::new(LOOP_CONTROL), e))?;
+    Ok(PathBuf::from(format!("/dev/loop{}", number)))
+}
+
+/// Release a loop device nothing is mounted from
+pub fn detach(device: &Path) -> Result<()> {
+    let file = File::open(device)
+        .map_err(|e| IsoError::UnmountFailed(format!("Cannot open {}: {}", device.display(), e)))?;
+    // SAFETY: LOOP_CLR_FD takes no argument
+    unsafe { ioctl::loop_clr_fd(file.as_raw_fd()) }
+        .map_err(|e| errno_error("LOOP_CLR_FD", device, e))?;
+    debug!("Detached {}", device.display());
+    Ok(())
+}
+
+/// Split `mount -o` style options into syscall flags and filesystem data.
+/// Images are always mounted read-only.
+fn mount_options(options: &[String]) -> (MsFlags, Option<String>) {
+    let mut flags = MsFlags::MS_RDONLY;
+    let mut data = Vec::new();
+    for option in options.iter().map(|o| o.trim()).filter(|o| !o.is_empty()) {
+        match option {
+            "ro" | "loop" => {}
+            "nosuid" => flags |= MsFlags::MS_NOSUID,
+            "nodev" => flags |= MsFlags::MS_NODEV,
+            "noexec" => flags |= MsFlags::MS_NOEXEC,
+            "noatime" => flags |= MsFlags::MS_NOATIME,
+            other => data.push(other),
+        }
+    }
+    let data = (!data.is_empty()).then(|| data.join(","));
+    (flags, data)
+}
+
+fn errno_error(call: &str, path: &Path, errno: Errno) -> crate::error::Error {
+    IsoError::MountFailed(format!("{} on {}: {}", call, path.display(), errno.desc())).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loop_info_layout() {
+        // Must match the kernel ABI or LOOP_SET_STATUS64 reads garbage
+        assert_eq!(std::mem::size_of::<LoopInfo64>(), 232);
+
+        let info = LoopInfo64::read_only(Path::new("/installers/debian.iso"));
+        assert_eq!(info.lo_flags, LO_FLAGS_READ_ONLY | LO_FLAGS_AUTOCLEAR);
+        assert_eq!(&info.lo_file_name[..22], b"/installers/debian.iso");
+        assert_eq!(info.lo_file_name[22], 0);
+
+        let long = "/".repeat(100);
+        let info = LoopInfo64::read_only(Path::new(&long));
+        assert_eq!(info.lo_file_name[63], 0);
+    }
+
+    #[test]
+    fn test_attached() {
+        let sys_block = tempfile::tempdir().unwrap();
+        let bound = sys_block.path().join("loop3/loop");
+        std::fs::create_dir_all(&bound).unwrap();
+        std::fs::write(bound.join("backing_file"), "/installers/debian.iso\n").unwrap();
+        // Free devices have no backing file, disks no loop directory
+        std::fs::create_dir_all(sys_block.path().join("loop4/loop")).unwrap();
+        std::fs::create_dir_all(sys_block.path().join("sda")).unwrap();
+
+        assert_eq!(
+            attached_in(sys_block.path()),
+            vec![(
+                PathBuf::from("/dev/loop3"),
+                PathBuf::from("/installers/debian.iso")
+            )]
+        );
+    }
+
+    #[test]
+    fn test_mount_options() {
+        let (flags, data) = mount_options(&[]);
+        assert_eq!(flags, MsFlags::MS_RDONLY);
+        assert_eq!(data, None);
+
+        let options = ["ro", "nosuid", "nodev", "uid=1000", "norock"].map(String::from);
+        let (flags, data) = mount_options(&options);
+        assert_eq!(
+            flags,
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV
+        );
+        assert_eq!(data.as_deref(), Some("uid=1000,norock"));
+    }
+}
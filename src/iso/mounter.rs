@@ -1,8 +1,12 @@
+use crate::chaos::{self, FaultPoint};
 use crate::error::{IsoError, Result};
+#[cfg(target_os = "linux")]
+use crate::iso::loopdev;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 
 #[derive(Debug, Clone)]
@@ -11,6 +15,8 @@ pub struct MountPoint {
     pub target: PathBuf,
     pub fs_type: String,
     pub options: Vec<String>,
+    /// Loop device attached natively; `None` when mounted through mount(8)
+    pub loop_device: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -22,9 +28,55 @@ pub enum MountState {
     Error(String),
 }
 
+/// Who is using a mount and when it was last touched
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    refs: usize,
+    last_access: Instant,
+}
+
+impl Usage {
+    fn new() -> Self {
+        Self {
+            refs: 0,
+            last_access: Instant::now(),
+        }
+    }
+}
+
+/// Shared use of a mount, released on drop. The mount stays until it has
+/// been unused for the idle timeout or is unmounted explicitly.
+#[derive(Debug)]
+pub struct MountGuard {
+    source: PathBuf,
+    target: PathBuf,
+    usage: Arc<Mutex<HashMap<PathBuf, Usage>>>,
+}
+
+impl MountGuard {
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// Where the image contents are
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+}
+
+impl Drop for MountGuard {
+    fn drop(&mut self) {
+        if let Some(usage) = lock(&self.usage).get_mut(&self.source) {
+            usage.refs = usage.refs.saturating_sub(1);
+            usage.last_access = Instant::now();
+        }
+    }
+}
+
 pub struct IsoMounter {
     mount_points: Arc<Mutex<HashMap<PathBuf, MountPoint>>>,
     state: Arc<Mutex<HashMap<PathBuf, MountState>>>,
+    usage: Arc<Mutex<HashMap<PathBuf, Usage>>>,
 }
 
 impl IsoMounter {
@@ -32,7 +84,79 @@ impl IsoMounter {
         Self {
             mount_points: Arc::new(Mutex::new(HashMap::new())),
             state: Arc::new(Mutex::new(HashMap::new())),
+            usage: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Share the mount of `source`, mounting it at `target` first if no one
+    /// has. The guard's target is the existing mount point when shared.
+    pub fn acquire(
+        &self,
+        source: &Path,
+        target: &Path,
+        options: Vec<String>,
+    ) -> Result<MountGuard> {
+        let target = match self.get_mount_point(source)? {
+            Some(mount_point) => mount_point.target,
+            None => {
+                self.mount(source, target, options)?;
+                target.to_path_buf()
+            }
+        };
+
+        let mut usage = lock(&self.usage);
+        let usage = usage.entry(source.to_path_buf()).or_insert_with(Usage::new);
+        usage.refs += 1;
+        usage.last_access = Instant::now();
+        debug!("{} now has {} users", source.display(), usage.refs);
+
+        Ok(MountGuard {
+            source: source.to_path_buf(),
+            target,
+            usage: self.usage.clone(),
+        })
+    }
+
+    /// Record an access, e.g. a file served from the mount, to keep it
+    /// from being unmounted as idle
+    pub fn touch(&self, source: &Path) -> Result<()> {
+        if let Some(usage) = lock(&self.usage).get_mut(source) {
+            usage.last_access = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Number of live guards on the mount of `source`
+    pub fn ref_count(&self, source: &Path) -> Result<usize> {
+        Ok(lock(&self.usage).get(source).map_or(0, |usage| usage.refs))
+    }
+
+    /// Unmount everything without users that has not been accessed for
+    /// `idle`, freeing its loop device. Returns the unmounted sources.
+    pub fn unmount_idle(&self, idle: Duration) -> Result<Vec<PathBuf>> {
+        let idle_sources: Vec<PathBuf> = {
+            let mount_points = lock(&self.mount_points);
+            let usage = lock(&self.usage);
+            mount_points
+                .keys()
+                .filter(|source| {
+                    usage
+                        .get(*source)
+                        .is_none_or(|u| u.refs == 0 && u.last_access.elapsed() >= idle)
+                })
+                .cloned()
+                .collect()
+        };
+
+        let mut unmounted = Vec::new();
+        for source in idle_sources {
+            info!("Unmounting idle {}", source.display());
+            match self.unmount(&source) {
+                Ok(()) => unmounted.push(source),
+                Err(e) => warn!("Failed to unmount idle {}: {}", source.display(), e),
+            }
         }
+        Ok(unmounted)
     }
 
     pub fn mount(&self, source: &Path, target: &Path, options: Vec<String>) -> Result<()> {
@@ -52,40 +176,29 @@ impl IsoMounter {
 
         self.set_state(source, MountState::Mounting)?;
 
-        let mut cmd = Command::new("mount");
-        cmd.arg("-o").arg(format!("loop,ro,{}", options.join(",")));
-        cmd.arg(source);
-        cmd.arg(target);
-
-        debug!("Executing mount command: {:?}", cmd);
-
-        let output = cmd.output().map_err(|e| {
-            self.set_state(source, MountState::Error(e.to_string()))
-                .ok();
-            IsoError::MountFailed(source.to_string_lossy().to_string(), e.to_string())
-        })?;
+        chaos::inject(FaultPoint::CommandExec, "mount")?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            self.set_state(source, MountState::Error(stderr.to_string()))
-                .ok();
-            return Err(IsoError::MountFailed(
-                source.to_string_lossy().to_string(),
-                stderr.to_string(),
-            ));
-        }
+        let loop_device = match self.mount_native(source, target, &options) {
+            Some(device) => Some(device),
+            None => {
+                self.mount_cli(source, target, &options)?;
+                None
+            }
+        };
 
         let mount_point = MountPoint {
             source: source.to_path_buf(),
             target: target.to_path_buf(),
             fs_type: "iso9660".to_string(),
             options,
+            loop_device,
         };
 
         self.mount_points
             .lock()
             .map_err(|_| IsoError::LockError)?
             .insert(source.to_path_buf(), mount_point);
+        lock(&self.usage).insert(source.to_path_buf(), Usage::new());
 
         self.set_state(source, MountState::Mounted)?;
 
@@ -108,35 +221,114 @@ impl IsoMounter {
             .cloned()
             .ok_or_else(|| IsoError::NotMounted(source.to_string_lossy().to_string()))?;
 
+        let refs = self.ref_count(source)?;
+        if refs > 0 {
+            return Err(IsoError::UnmountFailed(format!(
+                "{} is in use by {} consumers",
+                source.display(),
+                refs
+            ))
+            .into());
+        }
+
         self.set_state(source, MountState::Unmounting)?;
 
-        let mut cmd = Command::new("umount");
-        cmd.arg(&mount_point.target);
+        if !self.unmount_native(&mount_point.target) {
+            self.unmount_cli(source, &mount_point.target)?;
+        }
+
+        self.mount_points
+            .lock()
+            .map_err(|_| IsoError::LockError)?
+            .remove(source);
+        lock(&self.usage).remove(source);
+
+        self.set_state(source, MountState::Unmounted)?;
+
+        info!("Successfully unmounted {}", source.display());
+        Ok(())
+    }
+
+    /// Loop-mount through ioctls and mount(2). `None` when that is not
+    /// possible here and mount(8) should be used instead.
+    #[cfg(target_os = "linux")]
+    fn mount_native(&self, source: &Path, target: &Path, options: &[String]) -> Option<PathBuf> {
+        match loopdev::mount_image(source, target, options) {
+            Ok(device) => Some(device),
+            Err(e) => {
+                warn!("Native mount failed, falling back to mount(8): {}", e);
+                None
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn mount_native(&self, _source: &Path, _target: &Path, _options: &[String]) -> Option<PathBuf> {
+        None
+    }
+
+    fn mount_cli(&self, source: &Path, target: &Path, options: &[String]) -> Result<()> {
+        let mut cmd = Command::new("mount");
+        cmd.arg("-o").arg(format!("loop,ro,{}", options.join(",")));
+        cmd.arg(source);
+        cmd.arg(target);
+
+        debug!("Executing mount command: {:?}", cmd);
 
         let output = cmd.output().map_err(|e| {
             self.set_state(source, MountState::Error(e.to_string()))
                 .ok();
-            IsoError::UnmountFailed(source.to_string_lossy().to_string(), e.to_string())
+            IsoError::MountFailed(source.to_string_lossy().to_string(), e.to_string())
         })?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
             self.set_state(source, MountState::Error(stderr.to_string()))
                 .ok();
-            return Err(IsoError::UnmountFailed(
+            return Err(IsoError::MountFailed(
                 source.to_string_lossy().to_string(),
                 stderr.to_string(),
             ));
         }
+        Ok(())
+    }
 
-        self.mount_points
-            .lock()
-            .map_err(|_| IsoError::LockError)?
-            .remove(source);
+    /// umount(2); `false` when umount(8) should be tried instead
+    #[cfg(target_os = "linux")]
+    fn unmount_native(&self, target: &Path) -> bool {
+        match loopdev::unmount(target) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Native unmount failed, falling back to umount(8): {}", e);
+                false
+            }
+        }
+    }
 
-        self.set_state(source, MountState::Unmounted)?;
+    #[cfg(not(target_os = "linux"))]
+    fn unmount_native(&self, _target: &Path) -> bool {
+        false
+    }
 
-        info!("Successfully unmounted {}", source.display());
+    fn unmount_cli(&self, source: &Path, target: &Path) -> Result<()> {
+        let mut cmd = Command::new("umount");
+        cmd.arg(target);
+
+        let output = cmd.output().map_err(|e| {
+            self.set_state(source, MountState::Error(e.to_string()))
+                .ok();
+            IsoError::UnmountFailed(source.to_string_lossy().to_string(), e.to_string())
+        })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            self.set_state(source, MountState::Error(stderr.to_string()))
+                .ok();
+            return Err(IsoError::UnmountFailed(
+                source.to_string_lossy().to_string(),
+                stderr.to_string(),
+            ));
+        }
         Ok(())
     }
 
@@ -162,6 +354,10 @@ impl IsoMounter {
             .keys()
             .cloned()
             .collect();
+        // Shutdown: outstanding guards no longer keep mounts alive
+        lock(&self.usage)
+            .values_mut()
+            .for_each(|usage| usage.refs = 0);
 
         let mut results = Vec::new();
         for source in sources {
@@ -253,11 +449,98 @@ impl IsoMounter {
 
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
-            Ok(output_str.contains(&source.to_string_lossy().to_string()))
+            // Native mounts show the loop device rather than the image
+            let device = mount_point
+                .loop_device
+                .as_deref()
+                .unwrap_or(source)
+                .to_string_lossy()
+                .to_string();
+            Ok(output_str.contains(&device))
         } else {
             Ok(false)
         }
     }
+
+    /// Images in the mount table that are no longer mounted, e.g. unmounted
+    /// by hand
+    pub fn lost_mounts(&self) -> Result<Vec<PathBuf>> {
+        let mut lost = Vec::new();
+        for mount_point in self.list_mounted()? {
+            if !self.verify_mount(&mount_point.source)? {
+                lost.push(mount_point.source);
+            }
+        }
+        Ok(lost)
+    }
+
+    /// Drop a lost mount from the table. Guards on it stay valid but keep
+    /// nothing mounted.
+    pub fn forget(&self, source: &Path) -> Result<()> {
+        self.mount_points
+            .lock()
+            .map_err(|_| IsoError::LockError)?
+            .remove(source);
+        lock(&self.usage).remove(source);
+        self.set_state(source, MountState::Unmounted)
+    }
+
+    /// Loop devices still backed by an image mounted here although nothing
+    /// is mounted from them, left by a failed or interrupted mount
+    #[cfg(target_os = "linux")]
+    pub fn leaked_loop_devices(&self) -> Result<Vec<PathBuf>> {
+        let images: std::collections::HashSet<PathBuf> = self
+            .state
+            .lock()
+            .map_err(|_| IsoError::LockError)?
+            .keys()
+            .cloned()
+            .collect();
+        let mounts = std::fs::read_to_string("/proc/self/mounts")
+            .map_err(|e| IsoError::IoError(format!("Cannot read mount table: {}", e)))?;
+        let mounted = mounted_devices(&mounts);
+        Ok(loopdev::attached()
+            .into_iter()
+            .filter(|(device, image)| images.contains(image) && !mounted.contains(device))
+            .map(|(device, _)| device)
+            .collect())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn leaked_loop_devices(&self) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+
+    /// Detach the loop devices `leaked_loop_devices` finds
+    #[cfg(target_os = "linux")]
+    pub fn detach_leaked(&self) -> Result<Vec<PathBuf>> {
+        let leaked = self.leaked_loop_devices()?;
+        for device in &leaked {
+            warn!("Detaching leaked loop device {}", device.display());
+            loopdev::detach(device)?;
+        }
+        Ok(leaked)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn detach_leaked(&self) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Devices in a `/proc/mounts` style table
+#[cfg(target_os = "linux")]
+fn mounted_devices(mounts: &str) -> std::collections::HashSet<PathBuf> {
+    mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Usage bookkeeping stays consistent even if a holder panicked
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 impl Default for IsoMounter {
@@ -305,6 +588,7 @@ mod tests {
             target,
             fs_type: "iso9660".to_string(),
             options: vec!["ro".to_string()],
+            loop_device: None,
         };
 
         mounter
@@ -315,6 +599,80 @@ mod tests {
         assert!(mounter.is_mounted(&source).unwrap());
     }
 
+    #[test]
+    fn test_forget_lost_mount() {
+        let mounter = IsoMounter::new();
+        let source = PathBuf::from("/tmp/test.iso");
+        mounter.mount_points.lock().unwrap().insert(
+            source.clone(),
+            MountPoint {
+                source: source.clone(),
+                target: PathBuf::from("/mnt/iso/test"),
+                fs_type: "iso9660".to_string(),
+                options: vec!["ro".to_string()],
+                loop_device: Some(PathBuf::from("/dev/loop3")),
+            },
+        );
+        mounter.set_state(&source, MountState::Mounted).unwrap();
+
+        mounter.forget(&source).unwrap();
+        assert!(!mounter.is_mounted(&source).unwrap());
+        assert_eq!(mounter.get_state(&source).unwrap(), MountState::Unmounted);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mounted_devices() {
+        let mounts = "/dev/loop3 /mnt/iso/debian iso9660 ro,relatime 0 0\n\
+                      proc /proc proc rw,nosuid 0 0\n";
+        let devices = mounted_devices(mounts);
+        assert!(devices.contains(Path::new("/dev/loop3")));
+        assert!(!devices.contains(Path::new("/dev/loop4")));
+    }
+
+    #[test]
+    fn test_shared_mount_refcount() {
+        let mounter = IsoMounter::new();
+        let source = PathBuf::from("/tmp/test.iso");
+        let target = PathBuf::from("/mnt/iso/test");
+
+        mounter.mount_points.lock().unwrap().insert(
+            source.clone(),
+            MountPoint {
+                source: source.clone(),
+                target: target.clone(),
+                fs_type: "iso9660".to_string(),
+                options: vec!["ro".to_string()],
+                loop_device: None,
+            },
+        );
+
+        // Already mounted: both consumers share the existing mount point
+        let discovery = mounter
+            .acquire(&source, Path::new("/elsewhere"), vec![])
+            .unwrap();
+        let serving = mounter
+            .acquire(&source, Path::new("/elsewhere"), vec![])
+            .unwrap();
+        assert_eq!(discovery.target(), target);
+        assert_eq!(mounter.ref_count(&source).unwrap(), 2);
+
+        assert!(mounter.unmount(&source).is_err());
+        assert!(mounter.unmount_idle(Duration::ZERO).unwrap().is_empty());
+        assert!(mounter.is_mounted(&source).unwrap());
+
+        drop(discovery);
+        assert_eq!(mounter.ref_count(&source).unwrap(), 1);
+        drop(serving);
+        assert_eq!(mounter.ref_count(&source).unwrap(), 0);
+
+        // Released but recently used
+        assert!(mounter
+            .unmount_idle(Duration::from_secs(600))
+            .unwrap()
+            .is_empty());
+    }
+
     #[test]
     fn test_mount_nonexistent_file() {
         let mounter = IsoMounter::new();
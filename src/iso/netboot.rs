@@ -0,0 +1,247 @@
+use crate::error::{IsoError, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// How an image boots over the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetbootKind {
+    /// Netboot media (Debian/Ubuntu `mini.iso`): the installer fetches
+    /// everything from a mirror
+    Installer,
+    /// Ubuntu casper; downloads the whole ISO into RAM
+    Casper,
+    /// Debian live-build; fetches the root squashfs
+    DebianLive,
+    /// Fedora/RHEL Anaconda; installs from the image tree over HTTP
+    Anaconda,
+    Archiso,
+}
+
+/// Where each kind keeps its kernel and initrd: directory inside the
+/// image and file name prefixes
+struct Layout {
+    kind: NetbootKind,
+    dir: &'static str,
+    kernel: &'static str,
+    initrd: &'static str,
+}
+
+/// Checked in order; `mini.iso` has nothing but its kernel at the root
+const LAYOUTS: &[Layout] = &[
+    Layout {
+        kind: NetbootKind::Casper,
+        dir: "casper",
+        kernel: "vmlinuz",
+        initrd: "initrd",
+    },
+    Layout {
+        kind: NetbootKind::DebianLive,
+        dir: "live",
+        kernel: "vmlinuz",
+        initrd: "initrd.img",
+    },
+    Layout {
+        kind: NetbootKind::Anaconda,
+        dir: "images/pxeboot",
+        kernel: "vmlinuz",
+        initrd: "initrd.img",
+    },
+    Layout {
+        kind: NetbootKind::Archiso,
+        dir: "arch/boot/x86_64",
+        kernel: "vmlinuz-linux",
+        initrd: "initramfs-linux.img",
+    },
+    Layout {
+        kind: NetbootKind::Installer,
+        dir: "",
+        kernel: "linux",
+        initrd: "initrd.gz",
+    },
+];
+
+/// Kernel and initrd extracted from an image, ready to be served
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NetbootImage {
+    /// ISO file stem, unique among the served images
+    pub name: String,
+    pub iso: PathBuf,
+    pub kind: NetbootKind,
+    pub kernel: PathBuf,
+    pub initrd: PathBuf,
+}
+
+impl NetbootImage {
+    /// Kernel command line for a client booting from `base_url`, where the
+    /// boot server publishes this image: the ISO file as `image.iso` and
+    /// its mounted contents under `tree/`
+    pub fn kernel_args(&self, base_url: &str) -> String {
+        let base = base_url.trim_end_matches('/');
+        match self.kind {
+            NetbootKind::Installer => String::new(),
+            NetbootKind::Casper => format!("boot=casper ip=dhcp url={}/image.iso", base),
+            NetbootKind::DebianLive => format!(
+                "boot=live components ip=dhcp fetch={}/tree/live/filesystem.squashfs",
+                base
+            ),
+            NetbootKind::Anaconda => format!("ip=dhcp inst.repo={}/tree", base),
+            NetbootKind::Archiso => format!(
+                "ip=dhcp archisobasedir=arch archiso_http_srv={}/tree/",
+                base
+            ),
+        }
+    }
+}
+
+/// Netboot layout of a mounted image: its kind, kernel and initrd
+pub fn detect(root: &Path) -> Option<(NetbootKind, PathBuf, PathBuf)> {
+    LAYOUTS.iter().find_map(|layout| {
+        let dir = root.join(layout.dir);
+        let kernel = find_prefixed(&dir, layout.kernel)?;
+        let initrd = find_prefixed(&dir, layout.initrd)?;
+        Some((layout.kind, kernel, initrd))
+    })
+}
+
+/// First file in `dir` named `prefix` or `prefix<suffix>`; live-build
+/// appends the kernel version, casper the compression
+fn find_prefixed(dir: &Path, prefix: &str) -> Option<PathBuf> {
+    let exact = dir.join(prefix);
+    if exact.is_file() {
+        return Some(exact);
+    }
+    let mut matches: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| {
+            p.file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with(prefix))
+        })
+        .collect();
+    matches.sort();
+    matches.into_iter().next()
+}
+
+/// Copy the kernel and initrd of the image mounted at `root` into
+/// `cache_dir/<name>/`. Copies newer than the ISO are reused.
+pub fn extract(iso: &Path, root: &Path, cache_dir: &Path) -> Result<NetbootImage> {
+    let name = iso
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .ok_or_else(|| IsoError::NotFound(iso.display().to_string()))?;
+    let (kind, kernel, initrd) = detect(root).ok_or_else(|| {
+        IsoError::InvalidFormat(format!("{} has no network-bootable kernel", iso.display()))
+    })?;
+
+    let dir = cache_dir.join(&name);
+    fs::create_dir_all(&dir)?;
+    let image = NetbootImage {
+        name,
+        iso: iso.to_path_buf(),
+        kind,
+        kernel: dir.join("vmlinuz"),
+        initrd: dir.join("initrd"),
+    };
+    copy_if_stale(iso, &kernel, &image.kernel)?;
+    copy_if_stale(iso, &initrd, &image.initrd)?;
+
+    info!(
+        "Extracted {:?} netboot files of {} to {}",
+        kind,
+        iso.display(),
+        dir.display()
+    );
+    Ok(image)
+}
+
+fn copy_if_stale(iso: &Path, source: &Path, target: &Path) -> Result<()> {
+    let fresh = match (
+        fs::metadata(iso),
+        fs::metadata(source),
+        fs::metadata(target),
+    ) {
+        (Ok(iso), Ok(source), Ok(target)) => {
+            source.len() == target.len()
+                && matches!((iso.modified(), target.modified()), (Ok(i), Ok(t)) if t >= i)
+        }
+        _ => false,
+    };
+    if fresh {
+        debug!("{} is up to date", target.display());
+        return Ok(());
+    }
+    fs::copy(source, target)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn tree(files: &[&str]) -> TempDir {
+        let root = TempDir::new().unwrap();
+        for file in files {
+            let path = root.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file.as_bytes()).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_detect() {
+        let root = tree(&[
+            "casper/vmlinuz",
+            "casper/initrd",
+            "casper/filesystem.squashfs",
+        ]);
+        let (kind, kernel, initrd) = detect(root.path()).unwrap();
+        assert_eq!(kind, NetbootKind::Casper);
+        assert!(kernel.ends_with("casper/vmlinuz"));
+        assert!(initrd.ends_with("casper/initrd"));
+
+        let root = tree(&[
+            "live/vmlinuz-6.1.0-18-amd64",
+            "live/initrd.img-6.1.0-18-amd64",
+        ]);
+        let (kind, _, initrd) = detect(root.path()).unwrap();
+        assert_eq!(kind, NetbootKind::DebianLive);
+        assert!(initrd.ends_with("live/initrd.img-6.1.0-18-amd64"));
+
+        let root = tree(&["linux", "initrd.gz", "boot/grub/grub.cfg"]);
+        assert_eq!(detect(root.path()).unwrap().0, NetbootKind::Installer);
+
+        // Debian netinst: the CD initrd cannot find its media over the network
+        let root = tree(&["install.amd/vmlinuz", "install.amd/initrd.gz"]);
+        assert!(detect(root.path()).is_none());
+    }
+
+    #[test]
+    fn test_extract() {
+        let root = tree(&["images/pxeboot/vmlinuz", "images/pxeboot/initrd.img"]);
+        let isos = TempDir::new().unwrap();
+        let iso = isos.path().join("Fedora-Server-dvd-x86_64-40.iso");
+        fs::write(&iso, "iso").unwrap();
+        let cache = TempDir::new().unwrap();
+
+        let image = extract(&iso, root.path(), cache.path()).unwrap();
+        assert_eq!(image.name, "Fedora-Server-dvd-x86_64-40");
+        assert_eq!(image.kind, NetbootKind::Anaconda);
+        assert_eq!(
+            fs::read_to_string(&image.initrd).unwrap(),
+            "images/pxeboot/initrd.img"
+        );
+        assert_eq!(
+            image.kernel_args("http://10.42.0.1/boot/Fedora-Server-dvd-x86_64-40/"),
+            "ip=dhcp inst.repo=http://10.42.0.1/boot/Fedora-Server-dvd-x86_64-40/tree"
+        );
+
+        assert!(extract(&iso, isos.path(), cache.path()).is_err());
+    }
+}
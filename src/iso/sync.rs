@@ -0,0 +1,511 @@
+use crate::config::RepoSyncConfig;
+use crate::disk::checksum::{hash_bytes, hash_reader, HashAlgorithm};
+use crate::error::{Error, IsoError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// Repository listing served at `<url>/manifest.json`
+const MANIFEST: &str = "manifest.json";
+const PARTIAL_SUFFIX: &str = ".sync.part";
+/// Largest run of changed blocks fetched with one range request
+const MAX_FETCH_BYTES: u64 = 64 * 1024 * 1024;
+/// Longest the manifest download may take
+const MANIFEST_TIMEOUT_SECS: u64 = 60;
+
+/// Images of the central repository with per-block hashes, so a changed
+/// image can be patched from the local copy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoManifest {
+    /// Algorithm of the block hashes; whole images are always SHA-256
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+    pub block_size: u64,
+    pub images: Vec<RepoImage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoImage {
+    /// File name, served at `<url>/<name>`
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Hash of each `block_size` block, the last one possibly shorter
+    pub blocks: Vec<String>,
+}
+
+impl RepoManifest {
+    fn validate(&self) -> Result<()> {
+        if self.block_size == 0 {
+            return Err(sync_error("manifest has a block size of 0".to_string()));
+        }
+        for image in &self.images {
+            if image.name.is_empty() || image.name.contains('/') || image.name.starts_with('.') {
+                return Err(sync_error(format!("invalid image name {:?}", image.name)));
+            }
+            if image.blocks.len() as u64 != image.size.div_ceil(self.block_size) {
+                return Err(sync_error(format!(
+                    "{} lists {} blocks for {} bytes",
+                    image.name,
+                    image.blocks.len(),
+                    image.size
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of one repository sync
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepoSyncReport {
+    pub unchanged: Vec<String>,
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    /// Local ISOs the repository no longer lists
+    pub removed: Vec<String>,
+    /// Images left alone because they are mounted or selected
+    pub skipped: Vec<String>,
+    /// Bytes downloaded
+    pub fetched_bytes: u64,
+    /// Bytes taken over from the local copies
+    pub reused_bytes: u64,
+    pub errors: Vec<String>,
+}
+
+/// A synced image as it was left on disk, so unchanged files are not
+/// hashed again on every sync
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SyncedFile {
+    size: u64,
+    modified: u64,
+    sha256: String,
+}
+
+/// Mirror the repository at `config.url` into `dir`, fetching only the
+/// blocks of each image that differ from the local copy. `busy` images are
+/// not touched.
+pub async fn sync(config: &RepoSyncConfig, dir: &Path, busy: &[PathBuf]) -> Result<RepoSyncReport> {
+    let url = config
+        .url
+        .as_deref()
+        .ok_or_else(|| sync_error("No repository URL configured".to_string()))?
+        .trim_end_matches('/');
+
+    let manifest = fetch_manifest(url, config.connect_timeout).await?;
+    fs::create_dir_all(dir)
+        .await
+        .map_err(|e| sync_error(format!("{}: {}", dir.display(), e)))?;
+
+    let mut state = load_state(&config.state_path).await;
+    let mut report = RepoSyncReport::default();
+    for image in &manifest.images {
+        let path = dir.join(&image.name);
+        if busy.contains(&path) {
+            report.skipped.push(image.name.clone());
+            continue;
+        }
+        let current = synced_file(&path, Some(&image.sha256)).await;
+        if current.is_some() && current.as_ref() == state.get(&image.name) {
+            report.unchanged.push(image.name.clone());
+            continue;
+        }
+
+        let existed = current.is_some();
+        match sync_image(url, config, &manifest, image, &path).await {
+            Ok((fetched, reused)) => {
+                report.fetched_bytes += fetched;
+                report.reused_bytes += reused;
+                if fetched == 0 {
+                    report.unchanged.push(image.name.clone());
+                } else if existed {
+                    report.updated.push(image.name.clone());
+                } else {
+                    report.added.push(image.name.clone());
+                }
+                if let Some(synced) = synced_file(&path, Some(&image.sha256)).await {
+                    state.insert(image.name.clone(), synced);
+                }
+            }
+            Err(e) => {
+                warn!("Failed to sync {}: {}", image.name, e);
+                report.errors.push(e.to_string());
+            }
+        }
+    }
+
+    if config.delete_removed {
+        for path in stale_isos(dir, &manifest).await? {
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            if busy.contains(&path) {
+                report.skipped.push(name);
+                continue;
+            }
+            match fs::remove_file(&path).await {
+                Ok(()) => {
+                    let _ = fs::remove_file(sidecar(&path)).await;
+                    info!("Removed {}, no longer in the repository", path.display());
+                    state.remove(&name);
+                    report.removed.push(name);
+                }
+                Err(e) => report.errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    save_state(&config.state_path, &state).await;
+    info!(
+        "Repository sync: {} added, {} updated, {} removed, {} MiB fetched, {} MiB reused",
+        report.added.len(),
+        report.updated.len(),
+        report.removed.len(),
+        report.fetched_bytes / (1024 * 1024),
+        report.reused_bytes / (1024 * 1024)
+    );
+    Ok(report)
+}
+
+/// Bring `path` up to date with `image`. Returns the bytes fetched and
+/// the bytes reused from the previous copy.
+async fn sync_image(
+    url: &str,
+    config: &RepoSyncConfig,
+    manifest: &RepoManifest,
+    image: &RepoImage,
+    path: &Path,
+) -> Result<(u64, u64)> {
+    let local = match fs::metadata(path).await {
+        Ok(_) => {
+            let (path, block_size, algorithm) =
+                (path.to_path_buf(), manifest.block_size, manifest.algorithm);
+            tokio::task::spawn_blocking(move || block_hashes(&path, block_size, algorithm))
+                .await
+                .map_err(|e| sync_error(e.to_string()))??
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let fetch = plan_transfer(&local, image, manifest.block_size);
+    let fetched: u64 = fetch.iter().map(|range| range.end - range.start).sum();
+    if fetched == 0 && local.len() == image.blocks.len() {
+        let size = fs::metadata(path).await?.len();
+        if size == image.size {
+            return Ok((0, size));
+        }
+    }
+    info!(
+        "Syncing {}: {} of {} MiB changed",
+        image.name,
+        fetched / (1024 * 1024),
+        image.size / (1024 * 1024)
+    );
+
+    // Patch a copy so the old image stays usable until the new one is
+    // complete and verified
+    let partial = path.with_file_name(format!("{}{}", image.name, PARTIAL_SUFFIX));
+    if local.is_empty() {
+        fs::File::create(&partial).await?;
+    } else {
+        fs::copy(path, &partial).await?;
+    }
+    let result = patch(url, config, manifest, image, &partial, &fetch).await;
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial).await;
+        return Err(e);
+    }
+
+    fs::rename(&partial, path).await?;
+    fs::write(sidecar(path), format!("{}  {}\n", image.sha256, image.name)).await?;
+    Ok((fetched, image.size - fetched))
+}
+
+/// Download the `fetch` ranges into `partial` and verify the result
+async fn patch(
+    url: &str,
+    config: &RepoSyncConfig,
+    manifest: &RepoManifest,
+    image: &RepoImage,
+    partial: &Path,
+    fetch: &[Range<u64>],
+) -> Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).open(partial).await?;
+    file.set_len(image.size).await?;
+
+    let image_url = format!("{}/{}", url, image.name);
+    for range in fetch {
+        let data = fetch_range(&image_url, range, config.connect_timeout).await?;
+        let first = (range.start / manifest.block_size) as usize;
+        let (algorithm, block_size) = (manifest.algorithm, manifest.block_size as usize);
+        let count = (range.end - range.start).div_ceil(manifest.block_size) as usize;
+        let expected = image.blocks[first..first + count].to_vec();
+        let data = tokio::task::spawn_blocking(move || {
+            for (i, block) in data.chunks(block_size).enumerate() {
+                if !hash_bytes(algorithm, block)
+                    .value
+                    .eq_ignore_ascii_case(&expected[i])
+                {
+                    return Err(sync_error(format!("block {} does not match", first + i)));
+                }
+            }
+            Ok(data)
+        })
+        .await
+        .map_err(|e| sync_error(e.to_string()))??;
+
+        file.seek(SeekFrom::Start(range.start)).await?;
+        file.write_all(&data).await?;
+    }
+    file.sync_all().await?;
+    drop(file);
+
+    let partial = partial.to_path_buf();
+    let checksum = tokio::task::spawn_blocking(move || {
+        hash_reader(HashAlgorithm::Sha256, std::fs::File::open(&partial)?)
+    })
+    .await
+    .map_err(|e| sync_error(e.to_string()))??;
+    if !checksum.value.eq_ignore_ascii_case(&image.sha256) {
+        return Err(IsoError::ChecksumMismatch(format!(
+            "{}: expected {}, got {}",
+            image.name, image.sha256, checksum.value
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Byte ranges of `image` whose blocks differ from `local`, adjacent ones
+/// merged up to `MAX_FETCH_BYTES`
+fn plan_transfer(local: &[String], image: &RepoImage, block_size: u64) -> Vec<Range<u64>> {
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for (i, hash) in image.blocks.iter().enumerate() {
+        if local
+            .get(i)
+            .is_some_and(|ours| ours.eq_ignore_ascii_case(hash))
+        {
+            continue;
+        }
+        let start = i as u64 * block_size;
+        let end = (start + block_size).min(image.size);
+        match ranges.last_mut() {
+            Some(last) if last.end == start && end - last.start <= MAX_FETCH_BYTES => {
+                last.end = end
+            }
+            _ => ranges.push(start..end),
+        }
+    }
+    ranges
+}
+
+/// Hash of each `block_size` block of the file at `path`
+fn block_hashes(path: &Path, block_size: u64, algorithm: HashAlgorithm) -> Result<Vec<String>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0u8; block_size as usize];
+    let mut hashes = Vec::new();
+    loop {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match file.read(&mut buf[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        hashes.push(hash_bytes(algorithm, &buf[..filled]).value);
+        if filled < buf.len() {
+            break;
+        }
+    }
+    Ok(hashes)
+}
+
+async fn fetch_manifest(url: &str, connect_timeout: u64) -> Result<RepoManifest> {
+    let manifest_url = format!("{}/{}", url, MANIFEST);
+    debug!("Fetching {}", manifest_url);
+    let output = Command::new("curl")
+        .args(["-sfL", "--connect-timeout"])
+        .arg(connect_timeout.to_string())
+        .arg("--max-time")
+        .arg(MANIFEST_TIMEOUT_SECS.to_string())
+        .arg(&manifest_url)
+        .output()
+        .await
+        .map_err(|e| sync_error(format!("Failed to run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(sync_error(format!(
+            "{}: {}",
+            manifest_url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let manifest: RepoManifest = serde_json::from_slice(&output.stdout)
+        .map_err(|e| sync_error(format!("invalid {}: {}", MANIFEST, e)))?;
+    manifest.validate()?;
+    Ok(manifest)
+}
+
+async fn fetch_range(url: &str, range: &Range<u64>, connect_timeout: u64) -> Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args(["-sfL", "--connect-timeout"])
+        .arg(connect_timeout.to_string())
+        .arg("-r")
+        .arg(format!("{}-{}", range.start, range.end - 1))
+        .arg(url)
+        .output()
+        .await
+        .map_err(|e| sync_error(format!("Failed to run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(sync_error(format!(
+            "{}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // A server ignoring the range answers with the whole file
+    if output.stdout.len() as u64 != range.end - range.start {
+        return Err(sync_error(format!(
+            "{} returned {} bytes for range {}-{}; range requests must be supported",
+            url,
+            output.stdout.len(),
+            range.start,
+            range.end - 1
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// Local ISOs in `dir` that the manifest does not list
+async fn stale_isos(dir: &Path, manifest: &RepoManifest) -> Result<Vec<PathBuf>> {
+    let mut stale = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.to_ascii_lowercase().ends_with(".iso")
+            && !manifest.images.iter().any(|image| image.name == name)
+        {
+            stale.push(entry.path());
+        }
+    }
+    Ok(stale)
+}
+
+/// Size and modification time of `path`, with the checksum it was synced to
+async fn synced_file(path: &Path, sha256: Option<&str>) -> Option<SyncedFile> {
+    let metadata = fs::metadata(path).await.ok()?;
+    Some(SyncedFile {
+        size: metadata.len(),
+        modified: metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs(),
+        sha256: sha256.unwrap_or_default().to_string(),
+    })
+}
+
+async fn load_state(path: &Path) -> HashMap<String, SyncedFile> {
+    match fs::read(path).await {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable sync state {}: {}", path.display(), e);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+async fn save_state(path: &Path, state: &HashMap<String, SyncedFile>) {
+    let result = async {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_vec_pretty(state).map_err(|e| Error::General(e.to_string()))?;
+        fs::write(path, json).await?;
+        Ok::<_, Error>(())
+    }
+    .await;
+    if let Err(e) = result {
+        warn!("Failed to save sync state {}: {}", path.display(), e);
+    }
+}
+
+fn sidecar(iso: &Path) -> PathBuf {
+    let mut name = iso.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+fn sync_error(message: String) -> Error {
+    IsoError::SyncFailed(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(size: u64, blocks: &[&str]) -> RepoImage {
+        RepoImage {
+            name: "debian-12.6.0-amd64-netinst.iso".to_string(),
+            size,
+            sha256: "0".repeat(64),
+            blocks: blocks.iter().map(|b| b.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_plan_transfer() {
+        let remote = image(9, &["a", "B", "c", "d", "e"]);
+        let local = ["a", "b", "x", "y"].map(String::from);
+
+        // Blocks 2-4 changed or are new; the last one is one byte long
+        assert_eq!(
+            plan_transfer(&local, &remote, 2),
+            [Range { start: 4, end: 9 }]
+        );
+        assert!(plan_transfer(&["a", "b", "c", "d", "e"].map(String::from), &remote, 2).is_empty());
+        assert_eq!(plan_transfer(&[], &remote, 2), [Range { start: 0, end: 9 }]);
+
+        // Runs are split once they would exceed the fetch limit
+        let blocks = vec!["h"; 3];
+        let large = image(3 * MAX_FETCH_BYTES / 2, &blocks);
+        assert_eq!(
+            plan_transfer(&[], &large, MAX_FETCH_BYTES / 2),
+            [0..MAX_FETCH_BYTES, MAX_FETCH_BYTES..3 * MAX_FETCH_BYTES / 2]
+        );
+    }
+
+    #[test]
+    fn test_manifest_validation() {
+        let manifest = |block_size: u64, image: RepoImage| RepoManifest {
+            algorithm: HashAlgorithm::Sha256,
+            block_size,
+            images: vec![image],
+        };
+        assert!(manifest(4, image(9, &["a", "b", "c"])).validate().is_ok());
+        assert!(manifest(4, image(9, &["a", "b"])).validate().is_err());
+        assert!(manifest(0, image(0, &[])).validate().is_err());
+
+        let mut escape = image(0, &[]);
+        escape.name = "../etc/passwd".to_string();
+        assert!(manifest(4, escape).validate().is_err());
+
+        let json = r#"{"block_size": 4194304, "images": []}"#;
+        let parsed: RepoManifest = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed.algorithm, HashAlgorithm::Sha256);
+    }
+}
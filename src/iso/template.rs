@@ -0,0 +1,89 @@
+use crate::error::{IsoError, Result};
+use minijinja::{AutoEscape, Environment, UndefinedBehavior};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// Built-in answer file templates, by the name of the file they produce
+const TEMPLATES: &[(&str, &str)] = &[
+    ("preseed.cfg", include_str!("templates/preseed.cfg.j2")),
+    ("ks.cfg", include_str!("templates/ks.cfg.j2")),
+    ("user-data", include_str!("templates/user-data.j2")),
+    ("meta-data", include_str!("templates/meta-data.j2")),
+    (
+        "autounattend.xml",
+        include_str!("templates/autounattend.xml.j2"),
+    ),
+];
+
+/// Renders answer files from Jinja templates.
+///
+/// Undefined variables are errors, so a typo in an override fails the
+/// render instead of leaving an empty answer. Nothing is escaped
+/// implicitly; templates quote values with the `yaml` and `xml` filters.
+pub struct TemplateEngine {
+    env: Environment<'static>,
+}
+
+impl TemplateEngine {
+    /// Load the built-in templates, replacing any that have a `<name>.j2`
+    /// file in `override_dir`
+    pub fn new(override_dir: Option<&Path>) -> Result<Self> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_auto_escape_callback(|_| AutoEscape::None);
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_keep_trailing_newline(true);
+        env.add_filter("yaml", yaml);
+        env.add_filter("xml", xml);
+
+        for (name, builtin) in TEMPLATES {
+            let path = override_dir.map(|dir| dir.join(format!("{}.j2", name)));
+            let source = match path {
+                Some(path) if path.exists() => {
+                    info!("Using answer file template {}", path.display());
+                    fs::read_to_string(&path).map_err(|e| {
+                        IsoError::UnattendedFailed(format!("{}: {}", path.display(), e))
+                    })?
+                }
+                _ => builtin.to_string(),
+            };
+            env.add_template_owned(name.to_string(), source)
+                .map_err(template_error)?;
+        }
+        Ok(Self { env })
+    }
+
+    /// Names of every template, in the order answer files are listed
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        TEMPLATES.iter().map(|(name, _)| *name)
+    }
+
+    pub fn render<S: Serialize>(&self, name: &str, context: S) -> Result<String> {
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .map_err(template_error)
+    }
+}
+
+/// Quote a string as a YAML scalar. JSON strings are valid YAML.
+fn yaml(value: String) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+fn xml(value: String) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// minijinja errors name the template and line, e.g.
+/// "undefined value (in user-data:3)"
+fn template_error(e: minijinja::Error) -> crate::error::Error {
+    IsoError::UnattendedFailed(e.to_string()).into()
+}
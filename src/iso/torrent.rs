@@ -0,0 +1,254 @@
+use crate::config::DownloadConfig;
+use crate::error::{IsoError, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::OnceLock;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command;
+use tracing::{debug, error, info};
+
+/// Peer-to-peer settings for torrent downloads (`torrent` cargo feature)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TorrentConfig {
+    pub enabled: bool,
+    pub listen_port: Option<u16>,
+    pub max_peers: u32,
+    pub enable_dht: bool,
+    /// Minutes to keep seeding after completion; blocks the download
+    pub seed_time: u64,
+}
+
+impl Default for TorrentConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen_port: None,
+            max_peers: 55,
+            enable_dht: true,
+            seed_time: 0,
+        }
+    }
+}
+
+/// Transfer state parsed from an aria2c readout line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentStatus {
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    pub percentage: u8,
+    pub peers: u32,
+    pub bytes_per_sec: u64,
+}
+
+/// Download a .torrent URL, local .torrent file or magnet link into
+/// `staging` with aria2c, which checks every piece hash and uses the
+/// torrent's web seeds. Returns the path of `file_name` inside `staging`.
+pub async fn fetch<F>(
+    source: &str,
+    file_name: &str,
+    staging: &Path,
+    config: &DownloadConfig,
+    mut on_status: F,
+) -> Result<PathBuf>
+where
+    F: FnMut(TorrentStatus),
+{
+    tokio::fs::create_dir_all(staging)
+        .await
+        .map_err(|e| IsoError::DownloadFailed(format!("{}: {}", staging.display(), e)))?;
+
+    info!("Fetching {} via BitTorrent from {}", file_name, source);
+    let mut child = Command::new("aria2c")
+        .args(aria2_args(source, staging, config))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| IsoError::DownloadFailed(format!("Failed to run aria2c: {}", e)))?;
+
+    // aria2c logs warnings to stderr for the whole transfer; reading it
+    // only at exit would stall aria2c once the pipe buffer fills
+    let stderr = child.stderr.take();
+    let stderr_task = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_end(&mut buf).await;
+        }
+        String::from_utf8_lossy(&buf).to_string()
+    });
+
+    if let Some(stdout) = child.stdout.take() {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match parse_readout(&line) {
+                Some(status) => on_status(status),
+                None => debug!("aria2c: {}", line),
+            }
+        }
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| IsoError::DownloadFailed(format!("aria2c failed: {}", e)))?;
+    let stderr = stderr_task.await.unwrap_or_default();
+    if !status.success() {
+        error!("aria2c failed for {}: {}", file_name, stderr.trim());
+        return Err(IsoError::DownloadFailed(format!(
+            "{}: aria2c exited with {}: {}",
+            file_name,
+            status,
+            stderr.trim()
+        ))
+        .into());
+    }
+
+    find_payload(staging, file_name).ok_or_else(|| {
+        IsoError::DownloadFailed(format!("Torrent {} does not contain {}", source, file_name))
+            .into()
+    })
+}
+
+fn aria2_args(source: &str, staging: &Path, config: &DownloadConfig) -> Vec<String> {
+    let torrent = &config.torrent;
+    let mut args = vec![
+        format!("--dir={}", staging.display()),
+        // Resume from files already in the staging directory
+        "--continue=true".to_string(),
+        "--check-integrity=true".to_string(),
+        "--follow-torrent=mem".to_string(),
+        "--file-allocation=none".to_string(),
+        "--summary-interval=1".to_string(),
+        "--console-log-level=warn".to_string(),
+        format!("--seed-time={}", torrent.seed_time),
+        format!("--bt-max-peers={}", torrent.max_peers),
+        format!("--enable-dht={}", torrent.enable_dht),
+        format!("--connect-timeout={}", config.connect_timeout),
+        format!("--max-tries={}", config.retries.max(1)),
+    ];
+    if let Some(port) = torrent.listen_port {
+        args.push(format!("--listen-port={}", port));
+    }
+    if let Some(limit) = config.rate_limit_kbps {
+        args.push(format!("--max-overall-download-limit={}K", limit));
+    }
+    args.push(source.to_string());
+    args
+}
+
+/// Parse `[#2089b0 400.0KiB/33.2MiB(1%) CN:12 SD:3 DL:115.7KiB ETA:4m51s]`
+fn parse_readout(line: &str) -> Option<TorrentStatus> {
+    static READOUT: OnceLock<Regex> = OnceLock::new();
+    let re = READOUT.get_or_init(|| {
+        Regex::new(
+            r"\[#\w+ ([\d.]+[KMGT]?i?B)/([\d.]+[KMGT]?i?B)\((\d+)%\)(?: CN:(\d+))?(?: SD:(\d+))?(?: DL:([\d.]+[KMGT]?i?B))?",
+        )
+        .unwrap()
+    });
+
+    let caps = re.captures(line)?;
+    Some(TorrentStatus {
+        downloaded_bytes: parse_size(&caps[1])?,
+        total_bytes: parse_size(&caps[2])?,
+        percentage: caps[3].parse::<u8>().ok()?.min(100),
+        peers: caps
+            .get(4)
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or(0),
+        bytes_per_sec: caps
+            .get(6)
+            .and_then(|m| parse_size(m.as_str()))
+            .unwrap_or(0),
+    })
+}
+
+/// aria2c sizes: `512B`, `400.0KiB`, `1.2GiB`
+fn parse_size(value: &str) -> Option<u64> {
+    let number_end = value.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (number, unit) = value.split_at(number_end);
+    let multiplier: u64 = match unit {
+        "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "TiB" => 1 << 40,
+        _ => return None,
+    };
+    Some((number.parse::<f64>().ok()? * multiplier as f64) as u64)
+}
+
+/// Locate the downloaded file; multi-file torrents keep their directory layout
+fn find_payload(dir: &Path, file_name: &str) -> Option<PathBuf> {
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_payload(&path, file_name) {
+                return Some(found);
+            }
+        } else if entry.file_name() == file_name {
+            return Some(path);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_readout() {
+        let status =
+            parse_readout("[#2089b0 400.0KiB/33.2MiB(1%) CN:12 SD:3 DL:115.7KiB ETA:4m51s]")
+                .unwrap();
+        assert_eq!(status.downloaded_bytes, 409_600);
+        assert_eq!(status.total_bytes, 34_812_723);
+        assert_eq!(status.percentage, 1);
+        assert_eq!(status.peers, 12);
+        assert_eq!(status.bytes_per_sec, 118_476);
+
+        let seeding = parse_readout("[#2089b0 SEED(0.0) CN:2 SD:0]");
+        assert_eq!(seeding, None);
+        assert_eq!(
+            parse_readout("*** Download Progress Summary as of Mon ***"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_aria2_args() {
+        let config = DownloadConfig {
+            rate_limit_kbps: Some(2048),
+            ..DownloadConfig::default()
+        };
+        let args = aria2_args(
+            "https://cdimage.example/debian.iso.torrent",
+            Path::new("/installers/.torrent"),
+            &config,
+        );
+
+        assert!(args.contains(&"--dir=/installers/.torrent".to_string()));
+        assert!(args.contains(&"--seed-time=0".to_string()));
+        assert!(args.contains(&"--max-overall-download-limit=2048K".to_string()));
+        assert_eq!(
+            args.last().map(String::as_str),
+            Some("https://cdimage.example/debian.iso.torrent")
+        );
+    }
+
+    #[test]
+    fn test_find_payload_in_subdirectory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("debian-12/iso")).unwrap();
+        std::fs::write(dir.path().join("debian-12/iso/netinst.iso"), b"iso").unwrap();
+
+        assert_eq!(
+            find_payload(dir.path(), "netinst.iso"),
+            Some(dir.path().join("debian-12/iso/netinst.iso"))
+        );
+        assert_eq!(find_payload(dir.path(), "other.iso"), None);
+    }
+}
@@ -0,0 +1,762 @@
+use super::template::TemplateEngine;
+use super::windows::WindowsSetup;
+use crate::config::TargetConfig;
+use crate::error::{IsoError, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+/// Boot menus whose kernel lines get the answer file arguments
+const BOOT_CONFIGS: &[&str] = &[
+    "boot/grub/grub.cfg",
+    "EFI/BOOT/grub.cfg",
+    "isolinux/txt.cfg",
+    "isolinux/isolinux.cfg",
+];
+
+/// IANA zones to Windows time zone IDs for autounattend.xml
+const WINDOWS_TIME_ZONES: &[(&str, &str)] = &[
+    ("UTC", "UTC"),
+    ("Etc/UTC", "UTC"),
+    ("Europe/London", "GMT Standard Time"),
+    ("Europe/Berlin", "W. Europe Standard Time"),
+    ("Europe/Vienna", "W. Europe Standard Time"),
+    ("Europe/Zurich", "W. Europe Standard Time"),
+    ("Europe/Amsterdam", "W. Europe Standard Time"),
+    ("Europe/Paris", "Romance Standard Time"),
+    ("Europe/Madrid", "Romance Standard Time"),
+    ("America/New_York", "Eastern Standard Time"),
+    ("America/Chicago", "Central Standard Time"),
+    ("America/Denver", "Mountain Standard Time"),
+    ("America/Los_Angeles", "Pacific Standard Time"),
+    ("Asia/Tokyo", "Tokyo Standard Time"),
+    ("Asia/Shanghai", "China Standard Time"),
+];
+
+/// Answer file format understood by an installer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnswerFormat {
+    /// Debian installer preseed
+    Preseed,
+    /// Anaconda kickstart (RHEL, Fedora and rebuilds)
+    Kickstart,
+    /// Ubuntu Subiquity autoinstall via cloud-init NoCloud
+    Autoinstall,
+    /// Windows Setup autounattend.xml
+    Autounattend,
+}
+
+impl AnswerFormat {
+    pub fn for_os_type(os_type: &str) -> Option<Self> {
+        match os_type {
+            "debian" => Some(Self::Preseed),
+            "ubuntu" => Some(Self::Autoinstall),
+            "rhel" | "fedora" | "centos" | "rocky" | "alma" => Some(Self::Kickstart),
+            "windows" => Some(Self::Autounattend),
+            _ => None,
+        }
+    }
+
+    /// Volume label the installer scans for answer files without boot
+    /// arguments. Windows Setup checks the root of every removable drive.
+    pub fn volume_label(self) -> Option<&'static str> {
+        match self {
+            Self::Preseed | Self::Autounattend => None,
+            Self::Kickstart => Some("OEMDRV"),
+            Self::Autoinstall => Some("CIDATA"),
+        }
+    }
+}
+
+/// A generated file, relative to the media or side partition root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnswerFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Answer files for one installation and the kernel arguments that load them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnattendedFiles {
+    pub format: AnswerFormat,
+    pub files: Vec<AnswerFile>,
+    pub boot_args: String,
+}
+
+/// Template context: the `[target]` model, plus derived Windows values
+#[derive(Serialize)]
+struct Context<'a> {
+    target: &'a TargetConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    windows: Option<WindowsContext>,
+}
+
+#[derive(Serialize)]
+struct WindowsContext {
+    /// `en-US` style locale
+    locale: String,
+    time_zone: &'static str,
+    /// Windows computer names are limited to 15 characters
+    computer_name: String,
+    /// Encoded for `PlainText=false`
+    password: String,
+    image_index: u32,
+    product_key: Option<String>,
+    first_logon_commands: Vec<String>,
+}
+
+impl WindowsContext {
+    fn new(target: &TargetConfig, setup: &WindowsSetup) -> Self {
+        Self {
+            locale: windows_locale(&target.locale),
+            time_zone: windows_time_zone(&target.timezone),
+            computer_name: target.hostname.chars().take(15).collect(),
+            password: encode_password(target.user.windows_password.as_deref().unwrap_or_default()),
+            image_index: setup.edition.index,
+            product_key: setup.setup_key().map(|key| key.as_str().to_string()),
+            first_logon_commands: setup.first_logon_commands(),
+        }
+    }
+}
+
+impl UnattendedFiles {
+    /// Render the answer files. `side_partition` selects the layout used on a
+    /// separate labelled partition instead of the installer media.
+    pub fn render(
+        format: AnswerFormat,
+        target: &TargetConfig,
+        templates: &TemplateEngine,
+        side_partition: bool,
+    ) -> Result<Self> {
+        validate(target)?;
+        if target.user.password_hash.is_none() {
+            return Err(
+                IsoError::UnattendedFailed("user.password_hash is required".to_string()).into(),
+            );
+        }
+        let context = Context {
+            target,
+            windows: None,
+        };
+        let render = |name: &str| templates.render(name, &context);
+
+        let (files, boot_args) = match (format, side_partition) {
+            (AnswerFormat::Preseed, false) => (
+                vec![file("preseed.cfg", render("preseed.cfg")?)],
+                "auto=true priority=critical preseed/file=/cdrom/preseed.cfg",
+            ),
+            (AnswerFormat::Preseed, true) => {
+                return Err(IsoError::UnattendedFailed(
+                    "the Debian installer only loads preseed files from its own media".to_string(),
+                )
+                .into());
+            }
+            (AnswerFormat::Kickstart, false) => (
+                vec![file("ks.cfg", render("ks.cfg")?)],
+                "inst.ks=cdrom:/ks.cfg",
+            ),
+            (AnswerFormat::Kickstart, true) => (
+                vec![file("ks.cfg", render("ks.cfg")?)],
+                "inst.ks=hd:LABEL=OEMDRV:/ks.cfg",
+            ),
+            (AnswerFormat::Autoinstall, false) => (
+                vec![
+                    file("nocloud/user-data", render("user-data")?),
+                    file("nocloud/meta-data", render("meta-data")?),
+                ],
+                "autoinstall ds=nocloud;s=/cdrom/nocloud/",
+            ),
+            (AnswerFormat::Autoinstall, true) => (
+                vec![
+                    file("user-data", render("user-data")?),
+                    file("meta-data", render("meta-data")?),
+                ],
+                "autoinstall",
+            ),
+            (AnswerFormat::Autounattend, _) => {
+                return Err(IsoError::UnattendedFailed(
+                    "Windows answer files need the selected edition".to_string(),
+                )
+                .into());
+            }
+        };
+
+        Ok(Self {
+            format,
+            files,
+            boot_args: boot_args.to_string(),
+        })
+    }
+
+    /// Render autounattend.xml for the edition and activation in `setup`
+    pub fn render_windows(
+        target: &TargetConfig,
+        templates: &TemplateEngine,
+        setup: &WindowsSetup,
+    ) -> Result<Self> {
+        validate(target)?;
+        let context = Context {
+            target,
+            windows: Some(WindowsContext::new(target, setup)),
+        };
+        Ok(Self {
+            format: AnswerFormat::Autounattend,
+            files: vec![file(
+                "autounattend.xml",
+                templates.render("autounattend.xml", &context)?,
+            )],
+            boot_args: String::new(),
+        })
+    }
+
+    /// Write the files into writable installer media and add the boot
+    /// arguments to its GRUB and isolinux menus
+    pub fn inject_media(&self, root: &Path) -> Result<()> {
+        info!(
+            "Injecting {:?} answer files into {}",
+            self.format,
+            root.display()
+        );
+        self.write_files(root)?;
+
+        for config in BOOT_CONFIGS {
+            let path = root.join(config);
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            // `;` separates commands in GRUB scripts
+            let args = if config.ends_with("grub.cfg") {
+                self.boot_args.replace(';', "\\;")
+            } else {
+                self.boot_args.clone()
+            };
+            let updated = add_boot_args(&content, &args);
+            if updated != content {
+                debug!("Adding boot arguments to {}", path.display());
+                fs::write(&path, updated).map_err(|e| io_error(&path, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mount `partition` at `mount_dir`, write the files to its root and
+    /// unmount it again. The partition must carry the label the installer
+    /// scans for, since the read-only media cannot take boot arguments.
+    pub fn inject_side_partition(&self, partition: &str, mount_dir: &Path) -> Result<()> {
+        if let Some(expected) = self.format.volume_label() {
+            let label = run("blkid", &["-s", "LABEL", "-o", "value", partition])?;
+            if label.trim() != expected {
+                return Err(IsoError::UnattendedFailed(format!(
+                    "{} is labelled '{}', the installer looks for '{}'",
+                    partition,
+                    label.trim(),
+                    expected
+                ))
+                .into());
+            }
+        }
+
+        info!(
+            "Injecting {:?} answer files into {}",
+            self.format, partition
+        );
+        fs::create_dir_all(mount_dir).map_err(|e| io_error(mount_dir, e))?;
+        let target = mount_dir.to_string_lossy();
+        run("mount", &[partition, &target])?;
+
+        let result = self.write_files(mount_dir);
+        if let Err(e) = run("umount", &[&target]) {
+            warn!("Failed to unmount {}: {}", partition, e);
+        }
+        result
+    }
+
+    fn write_files(&self, root: &Path) -> Result<()> {
+        for answer in &self.files {
+            let path = root.join(&answer.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| io_error(parent, e))?;
+            }
+            fs::write(&path, &answer.contents).map_err(|e| io_error(&path, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Values end up in line-based formats, so a newline would inject directives
+fn validate(target: &TargetConfig) -> Result<()> {
+    if target.user.username.is_empty() || target.hostname.is_empty() {
+        return Err(IsoError::UnattendedFailed(
+            "hostname and user.username are required".to_string(),
+        )
+        .into());
+    }
+
+    let user = &target.user;
+    let values = [
+        &target.hostname,
+        &target.domain,
+        &target.locale,
+        &target.keyboard,
+        &target.timezone,
+        &user.username,
+        &user.full_name,
+    ]
+    .into_iter()
+    .chain(&user.password_hash)
+    .chain(&user.windows_password)
+    .chain(&target.disk.device)
+    .chain(&target.packages)
+    .chain(&user.ssh_authorized_keys);
+    for value in values {
+        if value.contains(['\n', '\r']) {
+            return Err(IsoError::UnattendedFailed(format!(
+                "value contains a line break: {:?}",
+                value
+            ))
+            .into());
+        }
+    }
+    if user.ssh_authorized_keys.iter().any(|k| k.contains('\'')) {
+        return Err(IsoError::UnattendedFailed(
+            "SSH keys must not contain single quotes".to_string(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Render the template `name` against `target`. autounattend.xml is
+/// rendered for image index 1 without a product key.
+pub fn preview(target: &TargetConfig, templates: &TemplateEngine, name: &str) -> Result<String> {
+    let sample;
+    let windows = if name == "autounattend.xml" {
+        sample = WindowsSetup::new(
+            PathBuf::from("sources/install.wim"),
+            super::windows::WindowsEdition {
+                index: 1,
+                name: "Windows".to_string(),
+                edition_id: None,
+            },
+        );
+        Some(WindowsContext::new(target, &sample))
+    } else {
+        None
+    };
+    templates.render(name, Context { target, windows })
+}
+
+/// Render every template against `target` and check that the results are
+/// well-formed. Returns one line per problem; empty when all pass.
+pub fn check(target: &TargetConfig, templates: &TemplateEngine) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Err(e) = validate(target) {
+        problems.push(e.to_string());
+    }
+    if target.user.password_hash.is_none() {
+        problems.push("user.password_hash is not set; only Windows can be installed".to_string());
+    }
+
+    for name in TemplateEngine::names() {
+        match preview(target, templates, name) {
+            Ok(contents) => {
+                if let Err(problem) = lint(name, &contents) {
+                    problems.push(format!("{}: {}", name, problem));
+                }
+            }
+            Err(e) => problems.push(format!("{}: {}", name, e)),
+        }
+    }
+    problems
+}
+
+/// Structural checks per format, catching broken templates before an
+/// installer stops at an interactive prompt
+fn lint(name: &str, contents: &str) -> std::result::Result<(), String> {
+    let mut lines = contents.lines().enumerate().map(|(i, line)| (i + 1, line));
+    match name {
+        "preseed.cfg" => {
+            for (n, line) in lines {
+                let line = line.trim();
+                if !line.is_empty() && !line.starts_with('#') && line.split_whitespace().count() < 3
+                {
+                    return Err(format!(
+                        "line {}: expected '<owner> <question> <type> <value>'",
+                        n
+                    ));
+                }
+            }
+        }
+        "ks.cfg" => {
+            let mut section: Option<(usize, &str)> = None;
+            for (n, line) in lines {
+                let line = line.trim();
+                match (line.starts_with('%'), section) {
+                    (true, Some(_)) if line == "%end" => section = None,
+                    (true, Some((start, open))) => {
+                        return Err(format!(
+                            "line {}: {} opened before {} from line {} was closed",
+                            n, line, open, start
+                        ));
+                    }
+                    (true, None) if line == "%end" => {
+                        return Err(format!("line {}: unmatched %end", n))
+                    }
+                    (true, None) => section = Some((n, line)),
+                    (false, _) => {}
+                }
+            }
+            if let Some((start, open)) = section {
+                return Err(format!("line {}: {} is not closed with %end", start, open));
+            }
+        }
+        "user-data" => {
+            if lines.next().map(|(_, line)| line) != Some("#cloud-config") {
+                return Err("first line must be #cloud-config".to_string());
+            }
+            if let Some((n, _)) =
+                lines.find(|(_, line)| line.trim_start_matches(' ').starts_with('\t'))
+            {
+                return Err(format!("line {}: YAML does not allow tab indentation", n));
+            }
+        }
+        "meta-data" if !lines.any(|(_, line)| line.starts_with("instance-id:")) => {
+            return Err("instance-id is missing".to_string());
+        }
+        "autounattend.xml" => return check_xml(contents),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Tag nesting and entity check; Windows Setup rejects malformed XML
+/// without saying where
+fn check_xml(xml: &str) -> std::result::Result<(), String> {
+    const ENTITIES: &[&str] = &["&amp;", "&lt;", "&gt;", "&quot;", "&apos;", "&#"];
+    if let Some(pos) = xml
+        .match_indices('&')
+        .map(|(pos, _)| pos)
+        .find(|pos| !ENTITIES.iter().any(|e| xml[*pos..].starts_with(e)))
+    {
+        return Err(format!("unescaped '&' at offset {}", pos));
+    }
+
+    let mut open = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..]
+            .find('>')
+            .map(|end| start + end)
+            .ok_or_else(|| "unterminated tag".to_string())?;
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with(['?', '!']) || tag.ends_with('/') {
+            continue;
+        }
+        let name = tag
+            .trim_start_matches('/')
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        if tag.starts_with('/') {
+            if open.pop() != Some(name) {
+                return Err(format!("unexpected </{}>", name));
+            }
+        } else {
+            open.push(name);
+        }
+    }
+    match open.pop() {
+        Some(name) => Err(format!("<{}> is not closed", name)),
+        None => Ok(()),
+    }
+}
+
+/// `en_US.UTF-8` -> `en-US`
+fn windows_locale(locale: &str) -> String {
+    locale
+        .split(['.', '@'])
+        .next()
+        .unwrap_or(locale)
+        .replace('_', "-")
+}
+
+fn windows_time_zone(zone: &str) -> &'static str {
+    WINDOWS_TIME_ZONES
+        .iter()
+        .find(|(iana, _)| *iana == zone)
+        .map(|(_, windows)| *windows)
+        .unwrap_or_else(|| {
+            warn!("No Windows time zone known for {}, using UTC", zone);
+            "UTC"
+        })
+}
+
+/// Setup's `PlainText=false` encoding: Base64 of the UTF-16LE password
+/// followed by the word "Password"
+fn encode_password(password: &str) -> String {
+    let bytes: Vec<u8> = format!("{}Password", password)
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    base64(&bytes)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Append `args` to every kernel line of a GRUB or isolinux menu, before the
+/// `---` separator that starts the installed system's arguments
+fn add_boot_args(config: &str, args: &str) -> String {
+    let mut out = String::with_capacity(config.len() + args.len());
+    for line in config.lines() {
+        let keyword = line.split_whitespace().next().unwrap_or_default();
+        let is_kernel_line = matches!(keyword, "linux" | "linuxefi" | "append");
+        if is_kernel_line && !line.contains(args) {
+            match line.find(" ---") {
+                Some(pos) => {
+                    out.push_str(&line[..pos]);
+                    out.push(' ');
+                    out.push_str(args);
+                    out.push_str(&line[pos..]);
+                }
+                None => {
+                    out.push_str(line);
+                    out.push(' ');
+                    out.push_str(args);
+                }
+            }
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn file(path: &str, contents: String) -> AnswerFile {
+    AnswerFile {
+        path: PathBuf::from(path),
+        contents,
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| IsoError::UnattendedFailed(format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(IsoError::UnattendedFailed(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> crate::error::Error {
+    IsoError::UnattendedFailed(format!("{}: {}", path.display(), e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iso::windows::{Activation, ProductKey, WindowsEdition};
+
+    fn target() -> TargetConfig {
+        let mut target = TargetConfig::default();
+        target.user.full_name = "Lab & \"Ops\" <Installer>".to_string();
+        target.user.password_hash = Some("$6$salt$hash".to_string());
+        target.user.ssh_authorized_keys = vec![
+            "ssh-ed25519 AAAAC3Nz admin@node".to_string(),
+            "ssh-rsa AAAAB3Nz ops@lab".to_string(),
+        ];
+        target.packages = vec!["curl".to_string(), "vim".to_string()];
+        target.disk.device = Some("/dev/sda".to_string());
+        target
+    }
+
+    fn templates() -> TemplateEngine {
+        TemplateEngine::new(None).unwrap()
+    }
+
+    #[test]
+    fn test_golden_files() {
+        let templates = templates();
+        let render = |format| UnattendedFiles::render(format, &target(), &templates, false);
+
+        let preseed = render(AnswerFormat::Preseed).unwrap();
+        assert_eq!(
+            preseed.files[0].contents,
+            include_str!("testdata/golden/preseed.cfg")
+        );
+        let kickstart = render(AnswerFormat::Kickstart).unwrap();
+        assert_eq!(
+            kickstart.files[0].contents,
+            include_str!("testdata/golden/ks.cfg")
+        );
+        let autoinstall = render(AnswerFormat::Autoinstall).unwrap();
+        assert_eq!(
+            autoinstall.files[0].contents,
+            include_str!("testdata/golden/user-data")
+        );
+        assert_eq!(
+            autoinstall.files[1].contents,
+            include_str!("testdata/golden/meta-data")
+        );
+
+        let mut target = target();
+        target.hostname = "workstation-lab-042".to_string();
+        target.timezone = "Europe/Berlin".to_string();
+        target.locale = "de_DE.UTF-8".to_string();
+        target.user.windows_password = Some("secret".to_string());
+        let setup = WindowsSetup::new(
+            PathBuf::from("/mnt/iso/sources/install.wim"),
+            WindowsEdition {
+                index: 6,
+                name: "Windows 11 Pro".to_string(),
+                edition_id: Some("Professional".to_string()),
+            },
+        )
+        .with_activation(Activation::Kms {
+            key: ProductKey::parse("W269N-WFGWX-YVC9B-4J6C9-T83GX").unwrap(),
+            host: Some("kms.example.lan".to_string()),
+        });
+        let windows = UnattendedFiles::render_windows(&target, &templates, &setup).unwrap();
+        assert_eq!(windows.files[0].path, PathBuf::from("autounattend.xml"));
+        assert_eq!(
+            windows.files[0].contents,
+            include_str!("testdata/golden/autounattend.xml")
+        );
+        assert_eq!(
+            encode_password("secret"),
+            "cwBlAGMAcgBlAHQAUABhAHMAcwB3AG8AcgBkAA=="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let templates = templates();
+        assert!(
+            UnattendedFiles::render(AnswerFormat::Preseed, &target(), &templates, true).is_err()
+        );
+        assert!(
+            UnattendedFiles::render(AnswerFormat::Autounattend, &target(), &templates, false)
+                .is_err()
+        );
+
+        let kickstart =
+            UnattendedFiles::render(AnswerFormat::Kickstart, &target(), &templates, true).unwrap();
+        assert_eq!(kickstart.boot_args, "inst.ks=hd:LABEL=OEMDRV:/ks.cfg");
+
+        let autoinstall =
+            UnattendedFiles::render(AnswerFormat::Autoinstall, &target(), &templates, false)
+                .unwrap();
+        assert_eq!(
+            autoinstall.files[0].path,
+            PathBuf::from("nocloud/user-data")
+        );
+
+        let mut injected = target();
+        injected.hostname = "node\nd-i preseed/early_command string reboot".to_string();
+        assert!(
+            UnattendedFiles::render(AnswerFormat::Preseed, &injected, &templates, false).is_err()
+        );
+        let mut unset = target();
+        unset.user.password_hash = None;
+        assert!(
+            UnattendedFiles::render(AnswerFormat::Kickstart, &unset, &templates, false).is_err()
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check(&target(), &templates()).is_empty());
+
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("user-data.j2"),
+            "#cloud-config\n{{ target.hostnme }}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("ks.cfg.j2"),
+            "text\n%packages\n{{ target.packages | join(\"\\n\") }}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("autounattend.xml.j2"),
+            "<unattend><Name>{{ target.user.full_name }}</Name></unattend>\n",
+        )
+        .unwrap();
+        let templates = TemplateEngine::new(Some(dir.path())).unwrap();
+
+        let problems = check(&target(), &templates);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("ks.cfg: line 2: %packages is not closed"));
+        assert!(problems[1].contains("undefined value (in user-data:2)"));
+        assert!(problems[2].starts_with("autounattend.xml: unescaped '&'"));
+    }
+
+    #[test]
+    fn test_add_boot_args() {
+        let grub = "menuentry 'Install' {\n    linux /install.amd/vmlinuz vga=788 --- quiet\n    initrd /install.amd/initrd.gz\n}\n";
+        let args = "auto=true priority=critical preseed/file=/cdrom/preseed.cfg";
+        let updated = add_boot_args(grub, args);
+        assert!(updated.contains(&format!(
+            "    linux /install.amd/vmlinuz vga=788 {} --- quiet\n",
+            args
+        )));
+        assert!(updated.contains("    initrd /install.amd/initrd.gz\n"));
+        assert_eq!(add_boot_args(&updated, args), updated);
+
+        let isolinux = "label install\n\tkernel /install.amd/vmlinuz\n\tappend initrd=/install.amd/initrd.gz\n";
+        assert!(add_boot_args(isolinux, "auto=true")
+            .contains("\tappend initrd=/install.amd/initrd.gz auto=true\n"));
+    }
+
+    #[test]
+    fn test_inject_media() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("boot/grub")).unwrap();
+        fs::write(
+            dir.path().join("boot/grub/grub.cfg"),
+            "linux /casper/vmlinuz ---\n",
+        )
+        .unwrap();
+
+        let files =
+            UnattendedFiles::render(AnswerFormat::Autoinstall, &target(), &templates(), false)
+                .unwrap();
+        files.inject_media(dir.path()).unwrap();
+
+        assert!(dir.path().join("nocloud/user-data").exists());
+        assert!(dir.path().join("nocloud/meta-data").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("boot/grub/grub.cfg")).unwrap(),
+            "linux /casper/vmlinuz autoinstall ds=nocloud\\;s=/cdrom/nocloud/ ---\n"
+        );
+    }
+}
@@ -0,0 +1,549 @@
+use crate::config::VentoyConfig;
+use crate::disk::inventory::DiskInventory;
+use crate::dryrun::DryRun;
+use crate::error::{DiskError, IsoError, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+/// Label of the small FAT partition holding Ventoy's boot files
+const EFI_LABEL: &str = "VTOYEFI";
+
+const COPY_BUFFER: usize = 4 * 1024 * 1024;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A device with Ventoy installed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VentoyStick {
+    pub device: String,
+    /// Partition Ventoy scans for ISOs
+    pub data_partition: String,
+    pub filesystem: Option<String>,
+}
+
+/// Copy progress across all ISOs of one deployment
+#[derive(Debug, Clone)]
+pub struct DeployProgress {
+    pub device: String,
+    pub file: String,
+    pub copied_bytes: u64,
+    pub total_bytes: u64,
+    pub percentage: u8,
+}
+
+/// An ISO placed on the stick
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeployedIso {
+    pub file_name: String,
+    pub size_bytes: u64,
+    /// Hash of the copy as read back from the stick; `None` without `verify`
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VentoyDeployment {
+    pub device: String,
+    pub isos: Vec<DeployedIso>,
+    pub free_bytes: u64,
+}
+
+/// An ISO queued for copying, with the hash it must have
+struct Source {
+    path: PathBuf,
+    file_name: String,
+    size: u64,
+    sha256: Option<String>,
+}
+
+/// Recognise a Ventoy layout: a data partition followed by the `VTOYEFI`
+/// partition. The data partition label can be changed by the user, so only
+/// the boot partition is matched by label.
+pub fn detect(disk: &DiskInventory) -> Option<VentoyStick> {
+    let efi = disk
+        .partitions
+        .iter()
+        .position(|p| p.label.as_deref() == Some(EFI_LABEL))?;
+    if efi == 0 {
+        return None;
+    }
+    let data = &disk.partitions[0];
+    Some(VentoyStick {
+        device: disk.path.clone(),
+        data_partition: data.path.clone(),
+        filesystem: data.filesystem.clone(),
+    })
+}
+
+/// Copies ISOs onto the data partition of a Ventoy stick
+pub struct VentoyDeployer {
+    progress_tx: Option<broadcast::Sender<DeployProgress>>,
+}
+
+impl VentoyDeployer {
+    pub fn new() -> Self {
+        Self { progress_tx: None }
+    }
+
+    /// Publish progress events on the given channel
+    pub fn with_progress(mut self, tx: broadcast::Sender<DeployProgress>) -> Self {
+        self.progress_tx = Some(tx);
+        self
+    }
+
+    /// Copy `isos` to the stick mounted at `mount_dir`. Sources are checked
+    /// against their published checksums first, and with `config.verify` the
+    /// copies are hashed again after a remount so the data comes from the
+    /// stick rather than the page cache.
+    pub async fn deploy(
+        &self,
+        stick: &VentoyStick,
+        isos: &[PathBuf],
+        config: &VentoyConfig,
+        mount_dir: &Path,
+    ) -> Result<VentoyDeployment> {
+        let mut sources = Vec::new();
+        for iso in isos {
+            sources.push(source(iso, config.verify).await?);
+        }
+
+        fs::create_dir_all(mount_dir)
+            .await
+            .map_err(|e| deploy_error(mount_dir, e))?;
+        mount(&stick.data_partition, mount_dir, false).await?;
+        let result = self.copy_all(stick, &sources, config, mount_dir).await;
+        let unmounted = unmount(mount_dir).await;
+        let free_bytes = result?;
+        unmounted?;
+
+        let target_dir = target_dir(config, mount_dir);
+        let mut deployed: Vec<DeployedIso> = sources
+            .iter()
+            .map(|s| DeployedIso {
+                file_name: s.file_name.clone(),
+                size_bytes: s.size,
+                sha256: None,
+            })
+            .collect();
+
+        if config.verify {
+            mount(&stick.data_partition, mount_dir, true).await?;
+            let result = verify_copies(&sources, &target_dir, &mut deployed).await;
+            let unmounted = unmount(mount_dir).await;
+            result?;
+            unmounted?;
+        }
+
+        info!(
+            "Deployed {} ISOs to Ventoy stick {}",
+            deployed.len(),
+            stick.device
+        );
+        Ok(VentoyDeployment {
+            device: stick.device.clone(),
+            isos: deployed,
+            free_bytes,
+        })
+    }
+
+    /// Record what `deploy` would do in `dry_run`, without mounting the
+    /// stick or writing to it
+    pub fn rehearse(
+        &self,
+        stick: &VentoyStick,
+        isos: &[PathBuf],
+        config: &VentoyConfig,
+        mount_dir: &Path,
+        dry_run: &DryRun,
+    ) -> VentoyDeployment {
+        let mut mount = std::process::Command::new("mount");
+        mount.arg(&stick.data_partition).arg(mount_dir);
+        dry_run.command(
+            format!("Mount the Ventoy data partition of {}", stick.device),
+            &mount,
+        );
+
+        let target_dir = target_dir(config, mount_dir);
+        let deployed: Vec<DeployedIso> = isos
+            .iter()
+            .filter_map(|iso| {
+                let file_name = iso.file_name()?.to_string_lossy().into_owned();
+                dry_run.action(format!(
+                    "Copy {} to {}, replacing an ISO of the same name",
+                    iso.display(),
+                    target_dir.join(&file_name).display()
+                ));
+                Some(DeployedIso {
+                    file_name,
+                    size_bytes: std::fs::metadata(iso).map(|m| m.len()).unwrap_or(0),
+                    sha256: None,
+                })
+            })
+            .collect();
+
+        let mut unmount = std::process::Command::new("umount");
+        unmount.arg(mount_dir);
+        dry_run.command(
+            format!("Unmount the Ventoy data partition of {}", stick.device),
+            &unmount,
+        );
+        if config.verify {
+            dry_run.action(format!(
+                "Remount {} read-only and hash the copies",
+                stick.data_partition
+            ));
+        }
+
+        VentoyDeployment {
+            device: stick.device.clone(),
+            isos: deployed,
+            free_bytes: 0,
+        }
+    }
+
+    /// Check free space and copy every source. Returns the space left.
+    async fn copy_all(
+        &self,
+        stick: &VentoyStick,
+        sources: &[Source],
+        config: &VentoyConfig,
+        mount_dir: &Path,
+    ) -> Result<u64> {
+        let target_dir = target_dir(config, mount_dir);
+        fs::create_dir_all(&target_dir)
+            .await
+            .map_err(|e| deploy_error(&target_dir, e))?;
+
+        // Same-named ISOs are replaced, so their space is reclaimed
+        let mut needed = 0;
+        for source in sources {
+            let existing = fs::metadata(target_dir.join(&source.file_name))
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0);
+            needed += source.size.saturating_sub(existing);
+        }
+        let available = free_space(mount_dir).await?;
+        if needed > available {
+            return Err(DiskError::InsufficientSpace(needed, available).into());
+        }
+
+        let total = sources.iter().map(|s| s.size).sum();
+        let mut copied = 0;
+        for source in sources {
+            info!("Copying {} to {}", source.file_name, stick.device);
+            self.copy(stick, source, &target_dir, copied, total).await?;
+            copied += source.size;
+            self.report(stick, &source.file_name, copied, total);
+        }
+
+        free_space(mount_dir).await
+    }
+
+    /// Copy through a hidden partial file, so an interrupted copy never
+    /// shows up in the Ventoy menu
+    async fn copy(
+        &self,
+        stick: &VentoyStick,
+        source: &Source,
+        target_dir: &Path,
+        done: u64,
+        total: u64,
+    ) -> Result<()> {
+        let target = target_dir.join(&source.file_name);
+        let partial = target_dir.join(format!(".{}.part", source.file_name));
+
+        let mut reader = File::open(&source.path)
+            .await
+            .map_err(|e| deploy_error(&source.path, e))?;
+        let mut writer = File::create(&partial)
+            .await
+            .map_err(|e| deploy_error(&partial, e))?;
+
+        let mut buffer = vec![0u8; COPY_BUFFER];
+        let mut copied = 0;
+        let mut last_report = Instant::now();
+        loop {
+            let n = reader
+                .read(&mut buffer)
+                .await
+                .map_err(|e| deploy_error(&source.path, e))?;
+            if n == 0 {
+                break;
+            }
+            writer
+                .write_all(&buffer[..n])
+                .await
+                .map_err(|e| deploy_error(&partial, e))?;
+            copied += n as u64;
+
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                self.report(stick, &source.file_name, done + copied, total);
+                last_report = Instant::now();
+            }
+        }
+        writer
+            .sync_all()
+            .await
+            .map_err(|e| deploy_error(&partial, e))?;
+        fs::rename(&partial, &target)
+            .await
+            .map_err(|e| deploy_error(&target, e))
+    }
+
+    fn report(&self, stick: &VentoyStick, file: &str, copied: u64, total: u64) {
+        if let Some(tx) = &self.progress_tx {
+            let percentage = (copied * 100)
+                .checked_div(total)
+                .map_or(100, |p| p.min(100) as u8);
+            let _ = tx.send(DeployProgress {
+                device: stick.device.clone(),
+                file: file.to_string(),
+                copied_bytes: copied,
+                total_bytes: total,
+                percentage,
+            });
+        }
+    }
+}
+
+impl Default for VentoyDeployer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn source(iso: &Path, verify: bool) -> Result<Source> {
+    let file_name = iso
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .ok_or_else(|| IsoError::NotFound(iso.display().to_string()))?;
+    let size = fs::metadata(iso)
+        .await
+        .map_err(|_| IsoError::NotFound(iso.display().to_string()))?
+        .len();
+    if !verify {
+        return Ok(Source {
+            path: iso.to_path_buf(),
+            file_name,
+            size,
+            sha256: None,
+        });
+    }
+
+    let actual = sha256(iso).await?;
+    match published_checksum(iso).await {
+        Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+            return Err(IsoError::ChecksumMismatch(file_name).into());
+        }
+        Some(_) => debug!("{} matches its published checksum", file_name),
+        None => warn!("No published checksum for {}", file_name),
+    }
+    Ok(Source {
+        path: iso.to_path_buf(),
+        file_name,
+        size,
+        sha256: Some(actual),
+    })
+}
+
+async fn verify_copies(
+    sources: &[Source],
+    target_dir: &Path,
+    deployed: &mut [DeployedIso],
+) -> Result<()> {
+    for (source, deployed) in sources.iter().zip(deployed) {
+        let copy = sha256(&target_dir.join(&source.file_name)).await?;
+        if source.sha256.as_deref() != Some(copy.as_str()) {
+            return Err(IsoError::ChecksumMismatch(format!(
+                "{} on the Ventoy stick",
+                source.file_name
+            ))
+            .into());
+        }
+        deployed.sha256 = Some(copy);
+    }
+    Ok(())
+}
+
+/// Checksum from `<iso>.sha256` or a `SHA256SUMS` file next to the ISO
+pub(super) async fn published_checksum(iso: &Path) -> Option<String> {
+    let name = iso.file_name()?.to_string_lossy().into_owned();
+    let sidecar = PathBuf::from(format!("{}.sha256", iso.display()));
+    if let Ok(contents) = fs::read_to_string(&sidecar).await {
+        if let Some(hash) = contents.split_whitespace().next() {
+            return Some(hash.to_string());
+        }
+    }
+    let sums = fs::read_to_string(iso.parent()?.join("SHA256SUMS"))
+        .await
+        .ok()?;
+    parse_sums(&sums, &name)
+}
+
+/// Find `name` in `sha256sum` output; binary-mode entries are prefixed with `*`
+fn parse_sums(sums: &str, name: &str) -> Option<String> {
+    sums.lines().find_map(|line| {
+        let (hash, file) = line.split_once(char::is_whitespace)?;
+        let file = file.trim_start().trim_start_matches('*');
+        (file == name).then(|| hash.to_string())
+    })
+}
+
+fn target_dir(config: &VentoyConfig, mount_dir: &Path) -> PathBuf {
+    match &config.directory {
+        Some(dir) => mount_dir.join(dir.strip_prefix("/").unwrap_or(dir)),
+        None => mount_dir.to_path_buf(),
+    }
+}
+
+async fn sha256(path: &Path) -> Result<String> {
+    let stdout = run(Command::new("sha256sum").arg(path)).await?;
+    stdout
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+        .ok_or_else(|| IsoError::DeployFailed(format!("No hash for {}", path.display())).into())
+}
+
+async fn free_space(dir: &Path) -> Result<u64> {
+    let stdout = run(Command::new("df").args(["-B1", "--output=avail"]).arg(dir)).await?;
+    parse_df(&stdout)
+        .ok_or_else(|| IsoError::DeployFailed(format!("Unexpected df output: {}", stdout)).into())
+}
+
+fn parse_df(output: &str) -> Option<u64> {
+    output.lines().last()?.trim().parse().ok()
+}
+
+async fn mount(partition: &str, dir: &Path, read_only: bool) -> Result<()> {
+    let mut cmd = Command::new("mount");
+    if read_only {
+        cmd.args(["-o", "ro"]);
+    }
+    run(cmd.arg(partition).arg(dir)).await.map(|_| ())
+}
+
+async fn unmount(dir: &Path) -> Result<()> {
+    run(Command::new("umount").arg(dir)).await.map(|_| ())
+}
+
+async fn run(cmd: &mut Command) -> Result<String> {
+    let program = cmd.as_std().get_program().to_string_lossy().into_owned();
+    let output = cmd
+        .output()
+        .await
+        .map_err(|e| IsoError::DeployFailed(format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(IsoError::DeployFailed(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn deploy_error(path: &Path, e: std::io::Error) -> crate::error::Error {
+    IsoError::DeployFailed(format!("{}: {}", path.display(), e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::inventory::parse_lsblk;
+
+    #[test]
+    fn test_detect() {
+        let disks = parse_lsblk(
+            r#"{"blockdevices": [
+              {"name":"sdb", "path":"/dev/sdb", "type":"disk", "size":31914983424, "tran":"usb", "rm":true, "rota":false,
+                 "children": [
+                    {"name":"sdb1", "path":"/dev/sdb1", "type":"part", "size":31880380416, "fstype":"exfat", "label":"Ventoy"},
+                    {"name":"sdb2", "path":"/dev/sdb2", "type":"part", "size":33554432, "fstype":"vfat", "label":"VTOYEFI"}
+                 ]
+              },
+              {"name":"sdc", "path":"/dev/sdc", "type":"disk", "size":15376318464, "tran":"usb", "rm":true, "rota":false,
+                 "children": [
+                    {"name":"sdc1", "path":"/dev/sdc1", "type":"part", "size":15375269888, "fstype":"vfat", "label":"USB"}
+                 ]
+              }
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            detect(&disks[0]),
+            Some(VentoyStick {
+                device: "/dev/sdb".to_string(),
+                data_partition: "/dev/sdb1".to_string(),
+                filesystem: Some("exfat".to_string()),
+            })
+        );
+        assert!(detect(&disks[1]).is_none());
+    }
+
+    #[test]
+    fn test_parse_checksums_and_df() {
+        let sums =
+            "1f0e2a  debian-12.5.0-amd64-netinst.iso\n9b3c7d *ubuntu-24.04-live-server-amd64.iso\n";
+        assert_eq!(
+            parse_sums(sums, "ubuntu-24.04-live-server-amd64.iso").as_deref(),
+            Some("9b3c7d")
+        );
+        assert_eq!(
+            parse_sums(sums, "debian-12.5.0-amd64-netinst.iso").as_deref(),
+            Some("1f0e2a")
+        );
+        assert!(parse_sums(sums, "netinst.iso").is_none());
+
+        assert_eq!(
+            parse_df("       Avail\n 28991029248\n"),
+            Some(28_991_029_248)
+        );
+        assert_eq!(parse_df(""), None);
+    }
+
+    #[test]
+    fn test_rehearse() {
+        let stick = VentoyStick {
+            device: "/dev/sdb".to_string(),
+            data_partition: "/dev/sdb1".to_string(),
+            filesystem: Some("exfat".to_string()),
+        };
+        let config = VentoyConfig {
+            directory: Some(PathBuf::from("/iso")),
+            verify: false,
+            ..Default::default()
+        };
+        let dry_run = DryRun::new();
+        let deployment = VentoyDeployer::new().rehearse(
+            &stick,
+            &[PathBuf::from("/srv/iso/debian-12.5.0-amd64-netinst.iso")],
+            &config,
+            Path::new("/mnt/.ventoy"),
+            &dry_run,
+        );
+
+        assert_eq!(
+            deployment.isos[0].file_name,
+            "debian-12.5.0-amd64-netinst.iso"
+        );
+        let actions = dry_run.actions();
+        assert_eq!(actions.len(), 3);
+        assert_eq!(
+            actions[0].command,
+            vec!["mount", "/dev/sdb1", "/mnt/.ventoy"]
+        );
+        assert!(actions[1]
+            .description
+            .contains("/mnt/.ventoy/iso/debian-12.5.0-amd64-netinst.iso"));
+        assert_eq!(actions[2].command, vec!["umount", "/mnt/.ventoy"]);
+    }
+}
@@ -0,0 +1,384 @@
+use crate::config::WindowsConfig;
+use crate::error::{IsoError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info};
+
+/// Characters used by Windows 8+ product keys
+const KEY_ALPHABET: &str = "BCDFGHJKMNPQRTVWXY2346789";
+
+/// Public KMS client setup keys (GVLKs) by install.wim edition ID
+const KMS_CLIENT_KEYS: &[(&str, &str)] = &[
+    ("Professional", "W269N-WFGWX-YVC9B-4J6C9-T83GX"),
+    ("ProfessionalN", "MH37W-N47XK-V7XM9-C7227-GCQG9"),
+    ("ProfessionalWorkstation", "NRG8B-VKK3Q-CXVCJ-9G2XF-6Q84J"),
+    ("ProfessionalEducation", "6TP4R-GNPTD-KYYHQ-7B7DP-J447Y"),
+    ("Education", "NW6C2-QMPVW-D7KKK-3GKT6-VCFB2"),
+    ("EducationN", "2WH4N-8QGBV-H22JP-CT43Q-MDWWJ"),
+    ("Enterprise", "NPPR9-FWDCX-D2C8J-H872K-2YT43"),
+    ("EnterpriseN", "DPH2V-TTNVB-4X9Q3-TJR4H-KHJW4"),
+];
+
+/// An image inside install.wim / install.esd
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowsEdition {
+    pub index: u32,
+    pub name: String,
+    pub edition_id: Option<String>,
+}
+
+/// A validated 5x5 product key; `Debug` and `Display` only show the last group
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ProductKey(String);
+
+impl ProductKey {
+    /// Normalize and validate a key in `XXXXX-XXXXX-XXXXX-XXXXX-XXXXX` form
+    pub fn parse(key: &str) -> Result<Self> {
+        let key = key.trim().to_uppercase();
+        let groups: Vec<&str> = key.split('-').collect();
+
+        if groups.len() != 5 || groups.iter().any(|g| g.len() != 5) {
+            return Err(IsoError::InvalidProductKey(
+                "expected format XXXXX-XXXXX-XXXXX-XXXXX-XXXXX".to_string(),
+            )
+            .into());
+        }
+
+        if let Some(c) = groups
+            .iter()
+            .flat_map(|g| g.chars())
+            .find(|c| !KEY_ALPHABET.contains(*c))
+        {
+            return Err(IsoError::InvalidProductKey(format!("invalid character '{}'", c)).into());
+        }
+
+        if key.matches('N').count() > 1 {
+            return Err(
+                IsoError::InvalidProductKey("key may contain at most one 'N'".to_string()).into(),
+            );
+        }
+
+        Ok(Self(key))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn masked(&self) -> String {
+        format!("XXXXX-XXXXX-XXXXX-XXXXX-{}", &self.0[24..])
+    }
+}
+
+impl TryFrom<String> for ProductKey {
+    type Error = crate::error::Error;
+
+    fn try_from(key: String) -> Result<Self> {
+        Self::parse(&key)
+    }
+}
+
+impl From<ProductKey> for String {
+    fn from(key: ProductKey) -> Self {
+        key.0
+    }
+}
+
+impl fmt::Debug for ProductKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ProductKey({})", self.masked())
+    }
+}
+
+impl fmt::Display for ProductKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.masked())
+    }
+}
+
+/// How the installed system is activated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Activation {
+    /// No key; setup prompts or installs unactivated
+    None,
+    /// Retail or MAK key injected into setup
+    ProductKey { key: ProductKey },
+    /// KMS client key, optionally pointed at a fixed KMS host
+    Kms {
+        key: ProductKey,
+        host: Option<String>,
+    },
+}
+
+/// Edition and activation choices for one Windows installation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowsSetup {
+    pub image: PathBuf,
+    pub edition: WindowsEdition,
+    pub activation: Activation,
+}
+
+impl WindowsSetup {
+    pub fn new(image: PathBuf, edition: WindowsEdition) -> Self {
+        Self {
+            image,
+            edition,
+            activation: Activation::None,
+        }
+    }
+
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.activation = activation;
+        self
+    }
+
+    /// Resolve the configured edition and activation against the editions in `image`
+    pub fn from_config(
+        config: &WindowsConfig,
+        image: PathBuf,
+        editions: &[WindowsEdition],
+    ) -> Result<Self> {
+        let edition = match &config.edition {
+            Some(selector) => select_edition(editions, selector)?,
+            None if editions.len() == 1 => editions[0].clone(),
+            None => {
+                return Err(IsoError::InstallerFailed(format!(
+                    "{} contains {} editions; an edition must be selected",
+                    image.display(),
+                    editions.len()
+                ))
+                .into())
+            }
+        };
+
+        let activation = if config.kms {
+            let key = match &config.product_key {
+                Some(key) => ProductKey::parse(key)?,
+                None => {
+                    let edition_id = edition.edition_id.as_deref().unwrap_or(&edition.name);
+                    let gvlk = kms_client_key(edition_id).ok_or_else(|| {
+                        IsoError::InvalidProductKey(format!(
+                            "no KMS client key known for edition {}",
+                            edition_id
+                        ))
+                    })?;
+                    ProductKey::parse(gvlk)?
+                }
+            };
+            Activation::Kms {
+                key,
+                host: config.kms_host.clone(),
+            }
+        } else if let Some(key) = &config.product_key {
+            Activation::ProductKey {
+                key: ProductKey::parse(key)?,
+            }
+        } else {
+            Activation::None
+        };
+
+        Ok(Self::new(image, edition).with_activation(activation))
+    }
+
+    /// Key to place in the unattend `ProductKey` element
+    pub fn setup_key(&self) -> Option<&ProductKey> {
+        match &self.activation {
+            Activation::None => None,
+            Activation::ProductKey { key } | Activation::Kms { key, .. } => Some(key),
+        }
+    }
+
+    /// Commands to run at first logon to finish activation
+    pub fn first_logon_commands(&self) -> Vec<String> {
+        match &self.activation {
+            Activation::Kms { host, .. } => {
+                let mut commands = Vec::new();
+                if let Some(host) = host {
+                    commands.push(format!(
+                        "cscript //B %windir%\\system32\\slmgr.vbs /skms {}",
+                        host
+                    ));
+                }
+                commands.push("cscript //B %windir%\\system32\\slmgr.vbs /ato".to_string());
+                commands
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Locate install.wim / install.esd on mounted Windows media
+pub fn find_install_image(media_root: &Path) -> Option<PathBuf> {
+    ["sources/install.wim", "sources/install.esd"]
+        .iter()
+        .map(|p| media_root.join(p))
+        .find(|p| p.exists())
+}
+
+/// List the editions in a WIM/ESD image using wimlib
+pub fn list_editions(image: &Path) -> Result<Vec<WindowsEdition>> {
+    debug!("Reading editions from {}", image.display());
+
+    let output = Command::new("wiminfo")
+        .arg(image)
+        .output()
+        .map_err(|e| IsoError::InvalidFormat(format!("Failed to run wiminfo: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(IsoError::InvalidFormat(format!(
+            "wiminfo failed for {}: {}",
+            image.display(),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+        .into());
+    }
+
+    let editions = parse_wiminfo(&String::from_utf8_lossy(&output.stdout));
+    info!("Found {} editions in {}", editions.len(), image.display());
+    Ok(editions)
+}
+
+/// Parse the per-image blocks printed by `wiminfo`
+pub fn parse_wiminfo(output: &str) -> Vec<WindowsEdition> {
+    let mut editions = Vec::new();
+    let mut current: Option<WindowsEdition> = None;
+
+    for line in output.lines() {
+        let Some((field, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match field.trim() {
+            "Index" => {
+                editions.extend(current.take());
+                current = value.parse().ok().map(|index| WindowsEdition {
+                    index,
+                    name: String::new(),
+                    edition_id: None,
+                });
+            }
+            "Name" => {
+                if let Some(edition) = current.as_mut() {
+                    edition.name = value.to_string();
+                }
+            }
+            "Edition ID" => {
+                if let Some(edition) = current.as_mut() {
+                    edition.edition_id = Some(value.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    editions.extend(current);
+    editions
+}
+
+/// Pick an edition by index, edition ID or name (case-insensitive)
+pub fn select_edition(editions: &[WindowsEdition], selector: &str) -> Result<WindowsEdition> {
+    let found = match selector.parse::<u32>() {
+        Ok(index) => editions.iter().find(|e| e.index == index),
+        Err(_) => editions.iter().find(|e| {
+            e.edition_id
+                .as_deref()
+                .is_some_and(|id| id.eq_ignore_ascii_case(selector))
+                || e.name.eq_ignore_ascii_case(selector)
+        }),
+    };
+
+    found
+        .cloned()
+        .ok_or_else(|| IsoError::InstallerNotFound(format!("Windows edition {}", selector)).into())
+}
+
+/// Public KMS client setup key for an edition ID
+pub fn kms_client_key(edition_id: &str) -> Option<&'static str> {
+    KMS_CLIENT_KEYS
+        .iter()
+        .find(|(id, _)| id.eq_ignore_ascii_case(edition_id))
+        .map(|(_, key)| *key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIMINFO: &str = "\
+WIM Information:
+----------------
+Path:           /mnt/iso/win/sources/install.wim
+Image Count:    2
+
+Available Images:
+-----------------
+Index:                  1
+Name:                   Windows 10 Home
+Edition ID:             Core
+
+Index:                  2
+Name:                   Windows 10 Pro
+Edition ID:             Professional
+";
+
+    #[test]
+    fn test_product_key_validation() {
+        let key = ProductKey::parse(" w269n-wfgwx-yvc9b-4j6c9-t83gx ").unwrap();
+        assert_eq!(key.as_str(), "W269N-WFGWX-YVC9B-4J6C9-T83GX");
+        assert_eq!(key.to_string(), "XXXXX-XXXXX-XXXXX-XXXXX-T83GX");
+
+        assert!(ProductKey::parse("W269N-WFGWX-YVC9B-4J6C9").is_err());
+        assert!(ProductKey::parse("W269N-WFGWX-YVC9B-4J6C9-T83GA").is_err());
+        assert!(ProductKey::parse("NNNNN-WFGWX-YVC9B-4J6C9-T83GX").is_err());
+        assert!(KMS_CLIENT_KEYS
+            .iter()
+            .all(|(_, key)| ProductKey::parse(key).is_ok()));
+    }
+
+    #[test]
+    fn test_parse_wiminfo() {
+        let editions = parse_wiminfo(WIMINFO);
+        assert_eq!(editions.len(), 2);
+        assert_eq!(editions[1].index, 2);
+        assert_eq!(editions[1].name, "Windows 10 Pro");
+        assert_eq!(editions[1].edition_id.as_deref(), Some("Professional"));
+    }
+
+    #[test]
+    fn test_select_edition() {
+        let editions = parse_wiminfo(WIMINFO);
+        assert_eq!(
+            select_edition(&editions, "2").unwrap().name,
+            "Windows 10 Pro"
+        );
+        assert_eq!(select_edition(&editions, "core").unwrap().index, 1);
+        assert!(select_edition(&editions, "Enterprise").is_err());
+    }
+
+    #[test]
+    fn test_kms_setup_from_config() {
+        let config = WindowsConfig {
+            edition: Some("Professional".to_string()),
+            product_key: None,
+            kms: true,
+            kms_host: Some("kms.example.lan".to_string()),
+        };
+        let setup = WindowsSetup::from_config(
+            &config,
+            PathBuf::from("sources/install.wim"),
+            &parse_wiminfo(WIMINFO),
+        )
+        .unwrap();
+
+        assert_eq!(
+            setup.setup_key().map(|k| k.as_str()),
+            Some("W269N-WFGWX-YVC9B-4J6C9-T83GX")
+        );
+        assert_eq!(setup.first_logon_commands().len(), 2);
+    }
+}
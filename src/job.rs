@@ -0,0 +1,187 @@
+pub mod install;
+
+use crate::environment::EnvironmentSnapshot;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// Result of a job run on a target device, including the environment it ran in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub kind: String,
+    pub device: String,
+    pub started_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+    pub success: Option<bool>,
+    pub error: Option<String>,
+    /// ISOs written to the device
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// Bytes written to the device, when the job knows
+    #[serde(default)]
+    pub bytes_written: Option<u64>,
+    /// Distribution of the installed image, when the catalog knows it
+    #[serde(default)]
+    pub distro: Option<String>,
+    pub environment: EnvironmentSnapshot,
+}
+
+impl JobRecord {
+    /// Open a record and snapshot the current tool versions
+    pub async fn start(kind: &str, device: &str) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind: kind.to_string(),
+            device: device.to_string(),
+            started_at: SystemTime::now(),
+            finished_at: None,
+            success: None,
+            error: None,
+            images: Vec::new(),
+            bytes_written: None,
+            distro: None,
+            environment: EnvironmentSnapshot::capture_async().await,
+        }
+    }
+
+    /// Close the record with the job outcome and log it as JSON
+    pub fn finish<T>(&mut self, result: &Result<T>) {
+        self.close(result.as_ref().err().map(|e| e.to_string()));
+    }
+
+    /// Close the record as failed with `error`, or as successful without
+    /// one, and log it as JSON
+    pub fn close(&mut self, error: Option<String>) {
+        self.finished_at = Some(SystemTime::now());
+        self.success = Some(error.is_none());
+        self.error = error;
+
+        if let Ok(json) = serde_json::to_string(self) {
+            info!(target: "job", "{}", json);
+        }
+    }
+}
+
+/// Finished jobs as JSON lines, kept across restarts for shift reports
+#[derive(Debug, Clone)]
+pub struct JobHistory {
+    path: PathBuf,
+}
+
+impl JobHistory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub async fn append(&self, record: &JobRecord) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut line = serde_json::to_string(record)
+            .map_err(|e| Error::General(format!("Failed to serialize job record: {}", e)))?;
+        line.push('\n');
+
+        // One write per record so concurrent appends don't interleave
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Jobs started in `[since, until)`, oldest first
+    pub async fn load(&self, since: SystemTime, until: SystemTime) -> Result<Vec<JobRecord>> {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut records = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<JobRecord>(line) {
+                Ok(record) if record.started_at >= since && record.started_at < until => {
+                    records.push(record)
+                }
+                Ok(_) => {}
+                // A crash mid-write leaves a truncated last line
+                Err(e) => warn!(
+                    "Skipping line {} of {}: {}",
+                    number + 1,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        records.sort_by_key(|r| r.started_at);
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[tokio::test]
+    async fn test_job_record_embeds_environment() {
+        let mut record = JobRecord::start("prepare_disk", "/dev/sdb").await;
+        assert!(record.success.is_none());
+
+        record.finish::<()>(&Err(Error::General("no space".to_string())));
+        assert_eq!(record.success, Some(false));
+        assert!(record.error.as_deref().unwrap().contains("no space"));
+
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(
+            json["environment"]["node_version"],
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(json["environment"]["tools"]
+            .as_object()
+            .unwrap()
+            .contains_key("parted"));
+    }
+
+    #[tokio::test]
+    async fn test_history_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = JobHistory::new(dir.path().join("state/jobs.jsonl"));
+        let now = SystemTime::now();
+        assert!(history
+            .load(SystemTime::UNIX_EPOCH, now)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut record = JobRecord::start("windows_usb", "/dev/sdb").await;
+        record.images = vec!["/isos/win11.iso".to_string()];
+        record.finish::<()>(&Ok(()));
+        history.append(&record).await.unwrap();
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("state/jobs.jsonl"))
+            .await
+            .unwrap()
+            .write_all(b"{\"id\": \"trunc")
+            .await
+            .unwrap();
+
+        let until = SystemTime::now() + std::time::Duration::from_secs(1);
+        let records = history.load(SystemTime::UNIX_EPOCH, until).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, record.id);
+        assert_eq!(records[0].images, record.images);
+        assert!(history.load(until, until).await.unwrap().is_empty());
+    }
+}
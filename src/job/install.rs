@@ -0,0 +1,756 @@
+use crate::disk::DiskManager;
+use crate::error::{Error, IsoError, Result};
+use crate::iso::hooks::HookResult;
+use crate::iso::installer::InstallerInfo;
+use crate::iso::IsoManager;
+use crate::job::{JobHistory, JobRecord};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Kind of the job history records install jobs leave
+pub const RECORD_KIND: &str = "install";
+
+/// Steps of an install job, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallStep {
+    /// Mount the ISO and pick its installer
+    SelectIso,
+    PrepareDisk,
+    Install,
+    /// Check the target now holds an installed system
+    Verify,
+    PostHooks,
+}
+
+impl InstallStep {
+    pub fn next(self) -> Option<Self> {
+        match self {
+            Self::SelectIso => Some(Self::PrepareDisk),
+            Self::PrepareDisk => Some(Self::Install),
+            Self::Install => Some(Self::Verify),
+            Self::Verify => Some(Self::PostHooks),
+            Self::PostHooks => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallJobStatus {
+    /// Waiting for a free slot and for its device
+    Queued,
+    Running,
+    Failed,
+    /// The node stopped while the job was running
+    Interrupted,
+    Cancelled,
+    Completed,
+}
+
+/// An ISO install onto a target disk, persisted after every transition so
+/// a crash leaves a record of the step it stopped in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallJob {
+    pub id: String,
+    pub iso: PathBuf,
+    pub device: String,
+    pub auto_mode: bool,
+    /// Installer picked from the ISO; the first one found when unset
+    pub installer: Option<String>,
+    /// Queued jobs with a higher priority start first
+    #[serde(default)]
+    pub priority: i32,
+    /// Step that is running, failed or was interrupted
+    pub step: InstallStep,
+    pub status: InstallJobStatus,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub hooks: Vec<HookResult>,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+impl InstallJob {
+    pub fn new(iso: impl Into<PathBuf>, device: &str, auto_mode: bool) -> Self {
+        let now = SystemTime::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            iso: iso.into(),
+            device: device.to_string(),
+            auto_mode,
+            installer: None,
+            priority: 0,
+            step: InstallStep::SelectIso,
+            status: InstallJobStatus::Queued,
+            error: None,
+            hooks: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Move on after the current step succeeded
+    fn advance(&mut self) {
+        match self.step.next() {
+            Some(next) => self.step = next,
+            None => self.status = InstallJobStatus::Completed,
+        }
+        self.updated_at = SystemTime::now();
+    }
+
+    fn fail(&mut self, error: &Error) {
+        self.status = InstallJobStatus::Failed;
+        self.error = Some(error.to_string());
+        self.updated_at = SystemTime::now();
+    }
+
+    /// Queue again from the step that failed or was interrupted
+    pub fn resume(&mut self) -> Result<()> {
+        self.check_stopped()?;
+        self.status = InstallJobStatus::Queued;
+        self.error = None;
+        self.updated_at = SystemTime::now();
+        Ok(())
+    }
+
+    /// Queue again from the first step
+    pub fn restart(&mut self) -> Result<()> {
+        self.resume()?;
+        self.step = InstallStep::SelectIso;
+        self.hooks.clear();
+        Ok(())
+    }
+
+    fn check_stopped(&self) -> Result<()> {
+        match self.status {
+            InstallJobStatus::Failed
+            | InstallJobStatus::Interrupted
+            | InstallJobStatus::Cancelled => Ok(()),
+            status => Err(IsoError::JobConflict(format!(
+                "install job {} is {:?}",
+                self.id, status
+            ))
+            .into()),
+        }
+    }
+}
+
+/// One JSON file per install job
+#[derive(Debug, Clone)]
+pub struct InstallJobStore {
+    dir: PathBuf,
+}
+
+impl InstallJobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Write the job through a temporary file, so a power loss leaves
+    /// either the old or the new state
+    pub async fn save(&self, job: &InstallJob) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let json = serde_json::to_vec_pretty(job)
+            .map_err(|e| Error::General(format!("Failed to serialize install job: {}", e)))?;
+
+        let path = self.path(&job.id);
+        let tmp = path.with_extension("json.tmp");
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(&json).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    pub async fn load(&self, id: &str) -> Result<InstallJob> {
+        // IDs come from API paths; anything but a UUID is not a job
+        if uuid::Uuid::parse_str(id).is_err() {
+            return Err(IsoError::NotFound(format!("install job {}", id)).into());
+        }
+        let content = match tokio::fs::read(self.path(id)).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(IsoError::NotFound(format!("install job {}", id)).into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&content)
+            .map_err(|e| Error::General(format!("Install job {} is unreadable: {}", id, e)))
+    }
+
+    /// Every stored job, newest first
+    pub async fn list(&self) -> Result<Vec<InstallJob>> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut jobs = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_job(&path).await {
+                Ok(job) => jobs.push(job),
+                Err(e) => warn!("Skipping {}: {}", path.display(), e),
+            }
+        }
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        Ok(jobs)
+    }
+
+    /// Mark jobs that were running when the node went down as interrupted
+    pub async fn recover(&self) -> Result<Vec<InstallJob>> {
+        let mut interrupted = Vec::new();
+        for mut job in self.list().await? {
+            if job.status != InstallJobStatus::Running {
+                continue;
+            }
+            warn!(
+                "Install job {} on {} was interrupted during {:?}",
+                job.id, job.device, job.step
+            );
+            job.status = InstallJobStatus::Interrupted;
+            job.updated_at = SystemTime::now();
+            self.save(&job).await?;
+            interrupted.push(job);
+        }
+        Ok(interrupted)
+    }
+}
+
+async fn read_job(path: &Path) -> Result<InstallJob> {
+    let content = tokio::fs::read(path).await?;
+    serde_json::from_slice(&content).map_err(|e| Error::General(e.to_string()))
+}
+
+/// Queued and running jobs. Their state is in the store; this decides
+/// which job starts next.
+#[derive(Default)]
+struct Queue {
+    waiting: Vec<InstallJob>,
+    running: HashMap<String, RunningJob>,
+    /// No job starts while set, e.g. in safe mode
+    held: bool,
+}
+
+struct RunningJob {
+    /// Last saved state
+    job: InstallJob,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Queue {
+    /// Index of the waiting job to start: the highest priority, then the
+    /// oldest, whose device no running job is writing to
+    fn next(&self, max_concurrent: usize) -> Option<usize> {
+        if self.held || self.running.len() >= max_concurrent {
+            return None;
+        }
+        self.waiting
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| !self.running.values().any(|r| r.job.device == job.device))
+            .max_by_key(|(_, job)| (job.priority, Reverse(job.created_at)))
+            .map(|(i, _)| i)
+    }
+
+    fn has_device(&self, device: &str) -> bool {
+        self.waiting.iter().any(|job| job.device == device)
+            || self.running.values().any(|r| r.job.device == device)
+    }
+
+    /// Running jobs, then waiting ones in the order they will start
+    fn snapshot(&self) -> Vec<InstallJob> {
+        let mut running: Vec<InstallJob> = self.running.values().map(|r| r.job.clone()).collect();
+        running.sort_by_key(|job| job.created_at);
+        let mut waiting = self.waiting.clone();
+        waiting.sort_by_key(|job| (Reverse(job.priority), job.created_at));
+        running.extend(waiting);
+        running
+    }
+}
+
+/// Runs queued install jobs, several at once on different devices
+#[derive(Clone)]
+pub struct InstallJobRunner {
+    store: InstallJobStore,
+    disk_manager: Arc<DiskManager>,
+    iso_manager: Arc<IsoManager>,
+    max_concurrent: usize,
+    queue: Arc<Mutex<Queue>>,
+    queue_tx: Arc<watch::Sender<Vec<InstallJob>>>,
+    /// The ISO manager has one active ISO, so installers are picked one
+    /// job at a time
+    select_lock: Arc<Mutex<()>>,
+    history: Option<JobHistory>,
+}
+
+impl InstallJobRunner {
+    pub fn new(
+        store: InstallJobStore,
+        disk_manager: Arc<DiskManager>,
+        iso_manager: Arc<IsoManager>,
+    ) -> Self {
+        let (queue_tx, _) = watch::channel(Vec::new());
+        Self {
+            store,
+            disk_manager,
+            iso_manager,
+            max_concurrent: 1,
+            queue: Arc::new(Mutex::new(Queue::default())),
+            queue_tx: Arc::new(queue_tx),
+            select_lock: Arc::new(Mutex::new(())),
+            history: None,
+        }
+    }
+
+    /// Record every run that completes or fails, for install statistics
+    pub fn with_history(mut self, history: JobHistory) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    pub fn with_max_concurrent(mut self, max_concurrent: u32) -> Self {
+        self.max_concurrent = max_concurrent.max(1) as usize;
+        self
+    }
+
+    pub fn store(&self) -> &InstallJobStore {
+        &self.store
+    }
+
+    pub fn history(&self) -> Option<&JobHistory> {
+        self.history.as_ref()
+    }
+
+    /// Running and waiting jobs, updated on every change
+    pub fn subscribe_queue(&self) -> watch::Receiver<Vec<InstallJob>> {
+        self.queue_tx.subscribe()
+    }
+
+    pub async fn queue(&self) -> Vec<InstallJob> {
+        self.queue.lock().await.snapshot()
+    }
+
+    /// Queue a job. Only one job per device may be queued or running.
+    pub async fn enqueue(&self, mut job: InstallJob) -> Result<InstallJob> {
+        let mut queue = self.queue.lock().await;
+        if queue.has_device(&job.device) {
+            return Err(IsoError::JobConflict(format!(
+                "{} already has an install job",
+                job.device
+            ))
+            .into());
+        }
+        job.status = InstallJobStatus::Queued;
+        self.store.save(&job).await?;
+        info!(
+            "Queued install job {}: {} onto {}",
+            job.id,
+            job.iso.display(),
+            job.device
+        );
+        queue.waiting.push(job.clone());
+        self.dispatch(&mut queue);
+        Ok(job)
+    }
+
+    /// Queue a failed, interrupted or cancelled job from the step it
+    /// stopped in
+    pub async fn resume(&self, id: &str) -> Result<InstallJob> {
+        let mut job = self.store.load(id).await?;
+        job.resume()?;
+        self.enqueue(job).await
+    }
+
+    /// Queue a failed, interrupted or cancelled job from the first step
+    pub async fn restart(&self, id: &str) -> Result<InstallJob> {
+        let mut job = self.store.load(id).await?;
+        job.restart()?;
+        self.enqueue(job).await
+    }
+
+    /// Drop a waiting job, or stop a running one. A running installer is
+    /// killed; the job keeps the step it was cancelled in.
+    pub async fn cancel(&self, id: &str) -> Result<InstallJob> {
+        let mut queue = self.queue.lock().await;
+        let mut job = if let Some(i) = queue.waiting.iter().position(|job| job.id == id) {
+            queue.waiting.remove(i)
+        } else if let Some(running) = queue.running.remove(id) {
+            if let Some(handle) = running.handle {
+                handle.abort();
+            }
+            running.job
+        } else {
+            return Err(IsoError::JobConflict(format!(
+                "install job {} is not queued or running",
+                id
+            ))
+            .into());
+        };
+        job.status = InstallJobStatus::Cancelled;
+        job.updated_at = SystemTime::now();
+        self.store.save(&job).await?;
+        info!("Cancelled install job {} during {:?}", job.id, job.step);
+        self.dispatch(&mut queue);
+        Ok(job)
+    }
+
+    /// Stop starting jobs and cancel the running ones, or start the waiting
+    /// jobs again. Returns the cancelled jobs, which can be resumed later.
+    pub async fn hold(&self, held: bool) -> Vec<InstallJob> {
+        let running: Vec<String> = {
+            let mut queue = self.queue.lock().await;
+            queue.held = held;
+            if !held {
+                info!("Install queue released");
+                self.dispatch(&mut queue);
+                return Vec::new();
+            }
+            queue.running.keys().cloned().collect()
+        };
+        warn!(
+            "Install queue held, cancelling {} running jobs",
+            running.len()
+        );
+        let mut cancelled = Vec::new();
+        for id in running {
+            match self.cancel(&id).await {
+                Ok(job) => cancelled.push(job),
+                // Finished meanwhile
+                Err(e) => debug!("Install job {} not cancelled: {}", id, e),
+            }
+        }
+        cancelled
+    }
+
+    /// Change the priority of a waiting job
+    pub async fn set_priority(&self, id: &str, priority: i32) -> Result<InstallJob> {
+        let mut queue = self.queue.lock().await;
+        let job = queue
+            .waiting
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| IsoError::JobConflict(format!("install job {} is not queued", id)))?;
+        job.priority = priority;
+        job.updated_at = SystemTime::now();
+        let job = job.clone();
+        self.store.save(&job).await?;
+        self.publish(&queue);
+        Ok(job)
+    }
+
+    /// Mark jobs a crash or power loss stopped as interrupted, queue them
+    /// again when `resume_interrupted` is set, and pick up jobs that were
+    /// still waiting. Returns the interrupted jobs.
+    pub async fn recover(&self, resume_interrupted: bool) -> Result<Vec<InstallJob>> {
+        let interrupted = self.store.recover().await?;
+        for job in self.store.list().await? {
+            if job.status == InstallJobStatus::Queued {
+                if let Err(e) = self.enqueue(job.clone()).await {
+                    warn!("Failed to queue install job {}: {}", job.id, e);
+                }
+            }
+        }
+        if resume_interrupted {
+            for job in &interrupted {
+                match self.resume(&job.id).await {
+                    Ok(job) => info!("Resuming install job {} at {:?}", job.id, job.step),
+                    Err(e) => warn!("Failed to resume install job {}: {}", job.id, e),
+                }
+            }
+        }
+        Ok(interrupted)
+    }
+
+    /// Start waiting jobs while there are free slots. Each job saves its
+    /// running state once it has started.
+    fn dispatch(&self, queue: &mut Queue) {
+        while let Some(i) = queue.next(self.max_concurrent) {
+            let mut job = queue.waiting.remove(i);
+            job.status = InstallJobStatus::Running;
+            job.updated_at = SystemTime::now();
+            info!("Starting install job {} on {}", job.id, job.device);
+
+            let runner = self.clone();
+            let id = job.id.clone();
+            let handle = tokio::spawn({
+                let job = job.clone();
+                async move { runner.run(job).await }
+            });
+            queue.running.insert(
+                id,
+                RunningJob {
+                    job,
+                    handle: Some(handle),
+                },
+            );
+        }
+        self.publish(queue);
+    }
+
+    fn publish(&self, queue: &Queue) {
+        self.queue_tx.send_replace(queue.snapshot());
+    }
+
+    async fn run(&self, mut job: InstallJob) {
+        if !self.save_progress(&job).await {
+            return;
+        }
+        let started_at = SystemTime::now();
+        let mut installer = None;
+        while job.status == InstallJobStatus::Running {
+            info!("Install job {}: {:?}", job.id, job.step);
+            match self.run_step(&mut job, &mut installer).await {
+                Ok(()) => job.advance(),
+                Err(e) => {
+                    error!("Install job {} failed during {:?}: {}", job.id, job.step, e);
+                    job.fail(&e);
+                }
+            }
+            if !self.save_progress(&job).await {
+                // Cancelled meanwhile
+                return;
+            }
+        }
+        if job.status == InstallJobStatus::Completed {
+            info!("Install job {} completed", job.id);
+        }
+        self.record_outcome(&job, started_at).await;
+
+        let mut queue = self.queue.lock().await;
+        queue.running.remove(&job.id);
+        self.dispatch(&mut queue);
+    }
+
+    /// Append the run to the job history. A resumed job leaves one record
+    /// per run, each timed from where that run started.
+    async fn record_outcome(&self, job: &InstallJob, started_at: SystemTime) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        let mut record = JobRecord::start(RECORD_KIND, &job.device).await;
+        record.id = job.id.clone();
+        record.started_at = started_at;
+        record.images = vec![job.iso.display().to_string()];
+        if let Some(name) = job.iso.file_name() {
+            record.distro = self
+                .iso_manager
+                .get_catalog_entry(&name.to_string_lossy())
+                .await
+                .ok()
+                .and_then(|entry| entry.distro);
+        }
+        let error = match job.status {
+            InstallJobStatus::Completed => None,
+            _ => Some(job.error.clone().unwrap_or_default()),
+        };
+        record.close(error);
+        if let Err(e) = history.append(&record).await {
+            warn!("Failed to record install job {} in history: {}", job.id, e);
+        }
+    }
+
+    /// Save the job and show its new step in the queue, unless it is no
+    /// longer running
+    async fn save_progress(&self, job: &InstallJob) -> bool {
+        let mut queue = self.queue.lock().await;
+        let Some(running) = queue.running.get_mut(&job.id) else {
+            return false;
+        };
+        running.job = job.clone();
+        if let Err(e) = self.store.save(job).await {
+            error!("Failed to save install job {}: {}", job.id, e);
+        }
+        self.publish(&queue);
+        true
+    }
+
+    async fn run_step(
+        &self,
+        job: &mut InstallJob,
+        installer: &mut Option<InstallerInfo>,
+    ) -> Result<()> {
+        // A resumed job picks its installer again before a step needs it
+        let needs_installer = matches!(
+            job.step,
+            InstallStep::SelectIso | InstallStep::Install | InstallStep::Verify
+        );
+        if installer.is_none() && needs_installer {
+            let selected = self.select_installer(job).await?;
+            job.installer = Some(selected.name.clone());
+            *installer = Some(selected);
+        }
+        let (progress_tx, progress_rx) = mpsc::channel(100);
+        let forward = tokio::spawn(log_progress(job.id.clone(), progress_rx));
+
+        let result = match (job.step, installer.as_ref()) {
+            (InstallStep::SelectIso, _) => Ok(()),
+            (InstallStep::PrepareDisk, _) => self.disk_manager.prepare_disk(&job.device).await,
+            (InstallStep::Install, Some(installer)) => {
+                self.iso_manager
+                    .run_installation(installer, job.auto_mode, progress_tx)
+                    .await
+            }
+            (InstallStep::Verify, Some(installer)) => {
+                self.iso_manager
+                    .verify_installation(installer, &job.device)
+                    .await
+            }
+            (InstallStep::PostHooks, _) => {
+                job.hooks = self
+                    .iso_manager
+                    .run_post_install(&job.device, &progress_tx)
+                    .await;
+                Ok(())
+            }
+            (_, None) => unreachable!("installer is selected before it is used"),
+        };
+        let _ = forward.await;
+        result
+    }
+
+    async fn select_installer(&self, job: &InstallJob) -> Result<InstallerInfo> {
+        let _guard = self.select_lock.lock().await;
+        self.iso_manager.mount_iso(&job.iso).await?;
+        let installers = self.iso_manager.discover_installers().await?;
+        installers
+            .into_iter()
+            .find(|i| match &job.installer {
+                Some(name) => &i.name == name,
+                None => true,
+            })
+            .ok_or_else(|| IsoError::InstallerNotFound(job.iso.display().to_string()).into())
+    }
+}
+
+async fn log_progress(
+    id: String,
+    mut progress_rx: mpsc::Receiver<crate::iso::installer::InstallerProgress>,
+) {
+    while let Some(progress) = progress_rx.recv().await {
+        info!(
+            job = %id,
+            stage = %progress.stage,
+            "{}% {}",
+            progress.percentage,
+            progress.message
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_transitions() {
+        let mut job = InstallJob::new("/srv/isos/debian-12.iso", "/dev/sdb", true);
+        let mut steps = vec![job.step];
+        while job.status != InstallJobStatus::Completed {
+            job.advance();
+            steps.push(job.step);
+        }
+        assert_eq!(
+            steps,
+            [
+                InstallStep::SelectIso,
+                InstallStep::PrepareDisk,
+                InstallStep::Install,
+                InstallStep::Verify,
+                InstallStep::PostHooks,
+                InstallStep::PostHooks,
+            ]
+        );
+        assert_eq!(job.status, InstallJobStatus::Completed);
+        assert!(job.resume().is_err());
+
+        let mut job = InstallJob::new("/srv/isos/debian-12.iso", "/dev/sdb", true);
+        job.advance();
+        job.advance();
+        job.fail(&Error::General("installer crashed".to_string()));
+        job.resume().unwrap();
+        assert_eq!(job.step, InstallStep::Install);
+        assert!(job.error.is_none());
+        assert_eq!(job.status, InstallJobStatus::Queued);
+        // Already queued again
+        assert!(job.restart().is_err());
+    }
+
+    fn running(job: &InstallJob) -> RunningJob {
+        RunningJob {
+            job: job.clone(),
+            handle: None,
+        }
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let mut queue = Queue::default();
+        let first = InstallJob::new("/srv/isos/debian-12.iso", "/dev/sdb", true);
+        let mut second = InstallJob::new("/srv/isos/debian-12.iso", "/dev/sdc", true);
+        second.created_at = first.created_at + std::time::Duration::from_secs(1);
+        let mut urgent = InstallJob::new("/srv/isos/ubuntu-24.04.iso", "/dev/sdb", true);
+        urgent.priority = 10;
+        queue.waiting = vec![first.clone(), second.clone(), urgent.clone()];
+
+        assert_eq!(queue.next(2), Some(2));
+        queue.running.insert(urgent.id.clone(), running(&urgent));
+        queue.waiting.remove(2);
+        // sdb is busy, so the older sdb job waits
+        assert_eq!(queue.next(2), Some(1));
+        queue.running.insert(second.id.clone(), running(&second));
+        queue.waiting.remove(1);
+        assert_eq!(queue.next(2), None);
+        queue.running.remove(&urgent.id);
+        assert_eq!(queue.next(2), Some(0));
+        queue.held = true;
+        assert_eq!(queue.next(2), None);
+        queue.held = false;
+
+        let ids: Vec<String> = queue.snapshot().into_iter().map(|job| job.id).collect();
+        assert_eq!(ids, [second.id, first.id]);
+    }
+
+    #[tokio::test]
+    async fn test_store_recovers_running_jobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = InstallJobStore::new(dir.path().join("install-jobs"));
+        assert!(store.list().await.unwrap().is_empty());
+
+        let mut running = InstallJob::new("/srv/isos/debian-12.iso", "/dev/sdb", false);
+        running.status = InstallJobStatus::Running;
+        running.advance();
+        store.save(&running).await.unwrap();
+        let mut failed = InstallJob::new("/srv/isos/ubuntu-24.04.iso", "/dev/sdc", false);
+        failed.fail(&Error::General("no space".to_string()));
+        store.save(&failed).await.unwrap();
+
+        let interrupted = store.recover().await.unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!(interrupted[0].id, running.id);
+
+        let reloaded = store.load(&running.id).await.unwrap();
+        assert_eq!(reloaded.status, InstallJobStatus::Interrupted);
+        assert_eq!(reloaded.step, InstallStep::PrepareDisk);
+        assert_eq!(store.list().await.unwrap().len(), 2);
+        assert!(store.load("missing").await.is_err());
+    }
+}
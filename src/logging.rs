@@ -1,3 +1,5 @@
+pub mod progress;
+
 use crate::config::LoggingConfig;
 use crate::error::{UsbInstallerError, UsbInstallerResult};
 use log::{Level, LevelFilter};
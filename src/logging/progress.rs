@@ -0,0 +1,94 @@
+use crate::config::ProgressLogConfig;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Picks which progress updates are worth a log line. Each job phase is
+/// logged when it starts and completes, and in between at most once per
+/// interval unless it advanced by the percent step. Subscribers of the
+/// progress channels still see every update.
+pub struct ProgressThrottle {
+    interval: Duration,
+    percent_step: u8,
+    /// Last logged time and percentage per phase
+    phases: HashMap<String, (Instant, u8)>,
+}
+
+impl ProgressThrottle {
+    pub fn new(config: &ProgressLogConfig) -> Self {
+        Self {
+            interval: Duration::from_secs(config.interval_secs),
+            percent_step: config.percent_step,
+            phases: HashMap::new(),
+        }
+    }
+
+    /// Whether the update of `phase` to `percentage` should be logged
+    pub fn should_log(&mut self, phase: &str, percentage: u8) -> bool {
+        self.should_log_at(phase, percentage, Instant::now())
+    }
+
+    fn should_log_at(&mut self, phase: &str, percentage: u8, now: Instant) -> bool {
+        let log = match self.phases.get(phase) {
+            None => true,
+            // Restarted, e.g. the verify pass after the write
+            Some(&(_, last)) if percentage < last => true,
+            Some(&(_, last)) if percentage == last => false,
+            Some(&(at, last)) => {
+                percentage >= 100
+                    || now.duration_since(at) >= self.interval
+                    || (self.percent_step > 0 && percentage - last >= self.percent_step)
+            }
+        };
+        if percentage >= 100 {
+            // A later run of the same phase starts afresh
+            self.phases.remove(phase);
+        } else if log {
+            self.phases.insert(phase.to_string(), (now, percentage));
+        }
+        log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> ProgressThrottle {
+        ProgressThrottle::new(&ProgressLogConfig {
+            interval_secs: 30,
+            percent_step: 10,
+        })
+    }
+
+    #[test]
+    fn test_percent_step_and_interval() {
+        let mut throttle = throttle();
+        let start = Instant::now();
+
+        let logged: Vec<u8> = (0..=100)
+            .filter(|&p| throttle.should_log_at("sdb:imaging:1", p, start))
+            .collect();
+        assert_eq!(logged, [0, 10, 20, 30, 40, 50, 60, 70, 80, 90, 100]);
+
+        // Slow phase: one line per interval even without a full step
+        assert!(throttle.should_log_at("sdb:imaging:2", 1, start));
+        assert!(!throttle.should_log_at("sdb:imaging:2", 2, start + Duration::from_secs(10)));
+        assert!(throttle.should_log_at("sdb:imaging:2", 3, start + Duration::from_secs(31)));
+        assert!(!throttle.should_log_at("sdb:imaging:2", 3, start + Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn test_phases_are_independent() {
+        let mut throttle = throttle();
+        let now = Instant::now();
+
+        assert!(throttle.should_log_at("sdb:format:1", 50, now));
+        assert!(throttle.should_log_at("sdc:format:1", 50, now));
+        assert!(!throttle.should_log_at("sdb:format:1", 55, now));
+
+        // A phase that runs again is logged from its start
+        assert!(throttle.should_log_at("sdb:format:1", 100, now));
+        assert!(throttle.should_log_at("sdb:format:1", 5, now));
+        assert!(throttle.should_log_at("sdb:format:1", 0, now));
+    }
+}
@@ -1,22 +1,48 @@
+mod api;
+mod audit;
+mod auth;
+mod button;
+mod capabilities;
+mod chaos;
 mod config;
+mod diagnose;
 mod disk;
+mod dryrun;
+mod environment;
 mod error;
+mod events;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod heartbeat;
+mod identify;
 mod iso;
+mod job;
 mod logging;
 mod monitoring;
+mod mqtt;
 mod network;
+mod pxe;
 mod remote;
+mod report;
 mod service;
+mod transfer;
 mod ui;
 
 use crate::config::Config;
 use crate::error::Result;
+use crate::events::AppEvent;
+use crate::iso::template::TemplateEngine;
+use crate::iso::unattended;
+use crate::logging::progress::ProgressThrottle;
 use crate::logging::Logger;
-use crate::monitoring::{Monitor, Monitorable};
+use crate::monitoring::{AlertSeverity, Metric, Monitor, Monitorable};
+use crate::service::shutdown::ShutdownPlan;
+use crate::service::startup::{StartupPlan, StartupStatus};
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::signal;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, mpsc, RwLock};
 use tokio::time::sleep;
 use tracing::{debug, error, info, warn};
 
@@ -28,6 +54,19 @@ struct AppState {
     remote_manager: Arc<RwLock<remote::RemoteManager>>,
     ui_manager: Arc<RwLock<ui::UiManager>>,
     monitor: Arc<RwLock<Monitor>>,
+    metrics_exporter: Arc<RwLock<monitoring::exporter::MetricsExporter>>,
+    api_server: Arc<RwLock<api::ApiServer>>,
+    pxe_server: Arc<RwLock<pxe::PxeServer>>,
+    button_manager: Arc<RwLock<button::ButtonManager>>,
+    install_jobs: job::install::InstallJobRunner,
+    ssh_keys: Arc<remote::keys::AuthorizedKeys>,
+    bans: Arc<auth::bans::BanList>,
+    events: events::EventBus,
+    mqtt_client: Arc<mqtt::MqttClient>,
+    heartbeat: Arc<heartbeat::HeartbeatPublisher>,
+    #[cfg(feature = "grpc")]
+    grpc_server: Arc<RwLock<grpc::GrpcServer>>,
+    startup: StartupStatus,
     shutdown_tx: broadcast::Sender<()>,
 }
 
@@ -94,34 +133,275 @@ impl Monitorable for UiMonitorAdapter {
     }
 }
 
-impl AppState {
-    async fn new(config: Config) -> Result<Self> {
-        let config = Arc::new(RwLock::new(config));
-        let (shutdown_tx, _) = broadcast::channel(16);
+struct PxeMonitorAdapter {
+    server: Arc<RwLock<pxe::PxeServer>>,
+}
 
-        let network_manager = Arc::new(RwLock::new(network::NetworkManager::new(Arc::new(
-            RwLock::new(config.read().await.network.clone()),
-        ))));
+impl Monitorable for PxeMonitorAdapter {
+    fn name(&self) -> &str {
+        "pxe"
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.server.read().await.health_check().await
+    }
 
-        let disk_manager = Arc::new(disk::DiskManager::new(Arc::new(RwLock::new(
-            config.read().await.disk.clone(),
-        ))));
+    async fn restart(&mut self) -> Result<()> {
+        let mut server = self.server.write().await;
+        server.stop().await?;
+        sleep(Duration::from_millis(500)).await;
+        server.start().await
+    }
+}
 
-        let iso_manager = Arc::new(iso::IsoManager::new(Arc::new(RwLock::new(
-            config.read().await.iso.clone(),
-        ))));
+struct DiskMonitorAdapter {
+    manager: Arc<disk::DiskManager>,
+}
 
-        let remote_manager = Arc::new(RwLock::new(remote::RemoteManager::new(Arc::new(
-            RwLock::new(config.read().await.remote.clone()),
-        ))));
+impl Monitorable for DiskMonitorAdapter {
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.manager.health_check().await
+    }
+
+    async fn restart(&mut self) -> Result<()> {
+        self.manager.clear_error().await;
+        Ok(())
+    }
+}
+
+struct IsoMonitorAdapter {
+    manager: Arc<iso::IsoManager>,
+}
+
+impl Monitorable for IsoMonitorAdapter {
+    fn name(&self) -> &str {
+        "iso"
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.manager.health_check().await
+    }
+
+    // Not stop and start, which would cancel a running installer
+    async fn restart(&mut self) -> Result<()> {
+        self.manager.recover().await
+    }
+}
+
+struct TunnelMonitorAdapter {
+    tunnel: network::tunnel::TunnelManager,
+}
+
+impl Monitorable for TunnelMonitorAdapter {
+    fn name(&self) -> &str {
+        "tunnel"
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        if self.tunnel.health_check().await? {
+            Ok(())
+        } else {
+            Err(error::NetworkError::TunnelFailed("Tunnel is down".to_string()).into())
+        }
+    }
+
+    async fn restart(&mut self) -> Result<()> {
+        self.tunnel.stop().await?;
+        sleep(Duration::from_millis(500)).await;
+        self.tunnel.start().await
+    }
+}
+
+impl AppState {
+    async fn new(config: Config, dry_run: Option<dryrun::DryRun>) -> Result<Self> {
+        let config = Arc::new(RwLock::new(config));
+        let (shutdown_tx, _) = broadcast::channel(16);
+
+        let capabilities = capabilities::NodeCapabilities::detect(&*config.read().await);
+        info!(
+            "Capabilities: filesystems {:?}, tools {:?}, vnc {}, pxe {}",
+            capabilities.filesystems, capabilities.tools, capabilities.vnc, capabilities.pxe
+        );
+
+        let events = events::EventBus::new();
+        let audit = audit::AuditLog::new(config.read().await.audit.clone());
+        // Edits made between runs show up as a new digest
+        audit.record_config("config.toml".as_ref()).await;
+
+        let network_manager = Arc::new(RwLock::new(
+            network::NetworkManager::new(Arc::new(RwLock::new(
+                config.read().await.network.clone(),
+            )))
+            .with_txt_records(capabilities.txt_records())
+            .with_service_ports(service_ports(&*config.read().await))
+            .with_firewall_ports(firewall_ports(&*config.read().await))
+            .with_events(events.clone()),
+        ));
+
+        let mut disk_manager =
+            disk::DiskManager::new(Arc::new(RwLock::new(config.read().await.disk.clone())));
+        let mut iso_manager =
+            iso::IsoManager::new(Arc::new(RwLock::new(config.read().await.iso.clone())))
+                .with_target(Arc::new(RwLock::new(config.read().await.target.clone())))
+                .with_offline(config.read().await.network.offline)
+                .with_audit(audit.clone());
+        disk_manager = disk_manager.with_audit(audit.clone());
+        if let Some(dry_run) = &dry_run {
+            warn!("Dry-run mode: destructive operations are logged, not performed");
+            disk_manager = disk_manager.with_dry_run(dry_run.clone());
+            iso_manager = iso_manager.with_dry_run(dry_run.clone());
+        }
+        let disk_manager = Arc::new(disk_manager);
+        let iso_manager = Arc::new(iso_manager);
 
         let ui_manager = Arc::new(RwLock::new(ui::UiManager::new(Arc::new(RwLock::new(
             config.read().await.ui.clone(),
         )))));
 
-        let monitor = Arc::new(RwLock::new(Monitor::new(Arc::new(RwLock::new(
-            config.read().await.monitoring.clone(),
-        )))));
+        let monitor = Arc::new(RwLock::new(
+            Monitor::new(Arc::new(RwLock::new(
+                config.read().await.monitoring.clone(),
+            )))
+            .with_events(events.clone()),
+        ));
+
+        let metrics_exporter = Arc::new(RwLock::new(monitoring::exporter::MetricsExporter::new(
+            config.read().await.monitoring.metrics_port,
+            monitor.clone(),
+        )));
+
+        let startup = StartupStatus::default().with_events(events.clone());
+        let reports = config.read().await.reports.clone();
+        let job_history = job::JobHistory::new(reports.history_path);
+        let jobs = config.read().await.jobs.clone();
+        let install_jobs = job::install::InstallJobRunner::new(
+            job::install::InstallJobStore::new(jobs.state_dir),
+            disk_manager.clone(),
+            iso_manager.clone(),
+        )
+        .with_max_concurrent(jobs.max_concurrent)
+        .with_history(job_history.clone());
+
+        let ssh = config.read().await.remote.ssh.clone();
+        let ssh_keys = Arc::new(
+            remote::keys::AuthorizedKeys::new(ssh.keys, ssh.authorized_keys_path)
+                .with_offline(config.read().await.network.offline),
+        );
+
+        let bans = Arc::new(
+            auth::bans::BanList::new(config.read().await.auth.bans.clone())
+                .with_firewall(network::firewall::Firewall::new(
+                    config.read().await.network.firewall.clone(),
+                ))
+                .with_events(events.clone()),
+        );
+
+        let node_commands = remote::ssh::NodeCommands {
+            startup: startup.clone(),
+            install_jobs: install_jobs.clone(),
+            iso_manager: iso_manager.clone(),
+            disk_manager: disk_manager.clone(),
+            log_file: config.read().await.logging.file_path.clone(),
+        };
+        let mut mqtt_client = mqtt::MqttClient::new(
+            config.read().await.mqtt.clone(),
+            monitor.clone(),
+            events.clone(),
+        )
+        .with_commands(node_commands.clone())
+        .with_audit(audit.clone())
+        .with_capabilities(capabilities.clone());
+        if let Some(dry_run) = &dry_run {
+            mqtt_client = mqtt_client.with_dry_run(dry_run.clone());
+        }
+        let mqtt_client = Arc::new(mqtt_client);
+        let heartbeat = Arc::new(
+            heartbeat::HeartbeatPublisher::new(
+                config.read().await.heartbeat.clone(),
+                monitor.clone(),
+                startup.clone(),
+                install_jobs.clone(),
+            )
+            .with_capabilities(capabilities.clone())
+            .with_offline(config.read().await.network.offline),
+        );
+
+        let web_vnc_login = web_vnc_login(&*config.read().await);
+        let serial = Arc::new(remote::serial::SerialConsoles::new());
+        let transfer = Arc::new(transfer::FileTransfer::new(
+            config.read().await.transfer.clone(),
+        ));
+        let remote_manager = Arc::new(RwLock::new(
+            remote::RemoteManager::new(Arc::new(RwLock::new(config.read().await.remote.clone())))
+                .with_commands(
+                    node_commands.clone(),
+                    iso::download_dir(&config.read().await.iso),
+                )
+                .with_web_vnc_login(web_vnc_login.clone())
+                .with_bans(bans.clone())
+                .with_serial(serial.clone())
+                .with_file_shares(transfer.shares())
+                .with_events(events.clone())
+                .with_audit(audit.clone()),
+        ));
+
+        let api_context = api::ApiContext {
+            disk_manager: disk_manager.clone(),
+            iso_manager: iso_manager.clone(),
+            startup: startup.clone(),
+            job_history: job_history.clone(),
+            install_jobs: install_jobs.clone(),
+            shift: Duration::from_secs(reports.shift_hours * 3600),
+            interface: ui_manager.read().await.interface_status(),
+            installer_gui: ui_manager.read().await.installer_gui(),
+            capabilities,
+            diagnostics: diagnose::Diagnostics::from_config(&*config.read().await),
+            identifier: Arc::new(identify::Identifier::new(Arc::new(RwLock::new(
+                config.read().await.identify.clone(),
+            )))),
+            dry_run,
+            peers: network_manager.read().await.peer_discovery(),
+            ssh_keys: ssh_keys.clone(),
+            auth: Arc::new(auth::Authenticator::new(
+                &config.read().await.auth,
+                config.read().await.api.admin_token.clone(),
+            )),
+            web_vnc_login,
+            bans: bans.clone(),
+            serial,
+            remote: remote_manager.clone(),
+            transfer,
+            events: events.clone(),
+            audit,
+            monitor: monitor.clone(),
+        };
+        #[cfg(feature = "grpc")]
+        let grpc_server = Arc::new(RwLock::new(grpc::GrpcServer::new(
+            config.read().await.grpc.clone(),
+            api_context.clone(),
+        )));
+        let api_server = Arc::new(RwLock::new(api::ApiServer::new(
+            Arc::new(RwLock::new(config.read().await.api.clone())),
+            api_context,
+        )));
+
+        let pxe_server = Arc::new(RwLock::new(pxe::PxeServer::new(
+            Arc::new(RwLock::new(config.read().await.pxe.clone())),
+            iso_manager.clone(),
+        )));
+
+        let button_manager = Arc::new(RwLock::new(
+            button::ButtonManager::new(
+                Arc::new(RwLock::new(config.read().await.button.clone())),
+                disk_manager.clone(),
+                iso_manager.clone(),
+            )
+            .with_history(job_history),
+        ));
 
         Ok(Self {
             config,
@@ -131,6 +411,19 @@ impl AppState {
             remote_manager,
             ui_manager,
             monitor,
+            metrics_exporter,
+            api_server,
+            pxe_server,
+            button_manager,
+            install_jobs,
+            ssh_keys,
+            bans,
+            events,
+            mqtt_client,
+            heartbeat,
+            #[cfg(feature = "grpc")]
+            grpc_server,
+            startup,
             shutdown_tx,
         })
     }
@@ -140,7 +433,30 @@ impl AppState {
 
         self.check_preconditions().await?;
         self.setup_monitoring().await?;
+        self.start_event_forwarding();
+        self.start_progress_forwarding();
+        self.start_download_forwarding();
+        self.start_queue_forwarding();
+        self.start_remote_forwarding();
+        self.start_access_forwarding();
+        self.start_alert_forwarding();
+        self.start_ui_actions().await;
+        self.start_ban_alerts();
+        self.start_safe_mode();
+        self.start_mdns_status();
+        self.start_cache_metrics();
+        self.start_install_metrics();
+        self.start_process_metrics();
+        self.start_clock_check().await;
+        self.start_thermal_monitor().await;
+        self.start_tunnel_metrics();
+        self.start_traffic_metrics().await;
         self.start_subsystems().await?;
+        self.start_feed_sync().await;
+        self.start_repo_sync().await;
+        self.start_report_mail().await;
+        self.start_ssh_key_sync().await;
+        self.recover_install_jobs().await;
 
         info!("Initialization complete");
         Ok(())
@@ -156,8 +472,7 @@ impl AppState {
             .into());
         }
 
-        let required_commands = ["mount", "umount", "fdisk", "mkfs.ext4", "x11vnc", "sshd"];
-        for cmd in &required_commands {
+        for cmd in diagnose::REQUIRED_TOOLS {
             if std::process::Command::new("which")
                 .arg(cmd)
                 .output()
@@ -192,35 +507,1128 @@ impl AppState {
             }))
             .await;
 
+        monitor
+            .register_service(Box::new(PxeMonitorAdapter {
+                server: self.pxe_server.clone(),
+            }))
+            .await;
+
+        monitor
+            .register_service(Box::new(DiskMonitorAdapter {
+                manager: self.disk_manager.clone(),
+            }))
+            .await;
+
+        monitor
+            .register_service(Box::new(IsoMonitorAdapter {
+                manager: self.iso_manager.clone(),
+            }))
+            .await;
+
+        if let Some(tunnel) = self.network_manager.read().await.tunnel() {
+            monitor
+                .register_service(Box::new(TunnelMonitorAdapter { tunnel }))
+                .await;
+        }
+
         monitor.start().await?;
         Ok(())
     }
 
-    async fn start_subsystems(&mut self) -> Result<()> {
-        info!("Starting subsystems");
+    fn start_progress_forwarding(&self) {
+        let mut events = self.events.subscribe();
+        let ui_manager = self.ui_manager.clone();
+        let monitor = self.monitor.clone();
+        let config = self.config.clone();
+
+        tokio::spawn(async move {
+            let mut throttle = ProgressThrottle::new(&config.read().await.logging.progress);
+            loop {
+                let progress = match events.recv().await {
+                    Ok(AppEvent::DiskProgress(progress)) => progress,
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("Dropped {} disk progress updates", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                let operation = format!("{:?}", progress.operation).to_lowercase();
+                let phase = format!("{}:{}:{}", progress.device, operation, progress.step);
+                if throttle.should_log(&phase, progress.percentage) {
+                    info!(
+                        device = %progress.device,
+                        operation,
+                        step = progress.step,
+                        total_steps = progress.total_steps,
+                        percent = progress.percentage,
+                        "{}",
+                        progress.message
+                    );
+                }
+
+                if let Err(e) = ui_manager
+                    .read()
+                    .await
+                    .update_progress(
+                        &progress.message,
+                        progress.percentage,
+                        &format!("{}: {}", progress.device, progress.message),
+                        progress.total_steps,
+                        progress.step,
+                    )
+                    .await
+                {
+                    debug!("Failed to forward disk progress to UI: {}", e);
+                }
+
+                monitor
+                    .read()
+                    .await
+                    .record_metric(Metric {
+                        name: "disk_operation_progress".to_string(),
+                        value: progress.percentage as f64,
+                        unit: "percent".to_string(),
+                        timestamp: SystemTime::now(),
+                        labels: [
+                            ("device".to_string(), progress.device.clone()),
+                            ("operation".to_string(), operation),
+                        ]
+                        .into(),
+                    })
+                    .await;
+            }
+        });
+    }
+
+    /// Report ISO cache occupancy and hit counts to the monitor every minute
+    fn start_cache_metrics(&self) {
+        let iso_manager = self.iso_manager.clone();
+        let monitor = self.monitor.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let stats = match iso_manager.cache_stats().await {
+                    Ok(stats) => stats,
+                    Err(e) => {
+                        debug!("Failed to read ISO cache: {}", e);
+                        continue;
+                    }
+                };
+
+                let mut values = vec![
+                    ("iso_cache_used", stats.used_bytes as f64, "bytes"),
+                    ("iso_cache_files", stats.isos.len() as f64, "count"),
+                    ("iso_cache_pinned", stats.pinned as f64, "count"),
+                    ("iso_cache_hits", stats.hits as f64, "count"),
+                    ("iso_cache_misses", stats.misses as f64, "count"),
+                ];
+                if let Some(quota) = stats.quota_bytes {
+                    values.push(("iso_cache_quota", quota as f64, "bytes"));
+                }
+                let monitor = monitor.read().await;
+                for (name, value, unit) in values {
+                    monitor
+                        .record_metric(Metric {
+                            name: name.to_string(),
+                            value,
+                            unit: unit.to_string(),
+                            timestamp: SystemTime::now(),
+                            labels: HashMap::new(),
+                        })
+                        .await;
+                }
+            }
+        });
+    }
 
-        if let Err(e) = self.network_manager.write().await.start().await {
-            error!("Failed to start network manager: {}", e);
-            return Err(e);
+    /// Memory and CPU use of every supervised child process, and how the
+    /// processes of each program ended, reported to the monitor every
+    /// minute
+    fn start_process_metrics(&self) {
+        let monitor = self.monitor.clone();
+
+        tokio::spawn(async move {
+            let supervisor = service::supervisor::ProcessSupervisor::global();
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let now = SystemTime::now();
+                let metric = |name: &str, value: f64, unit: &str, labels: &HashMap<_, _>| Metric {
+                    name: name.to_string(),
+                    value,
+                    unit: unit.to_string(),
+                    timestamp: now,
+                    labels: labels.clone(),
+                };
+                let mut metrics = Vec::new();
+                for process in supervisor.processes() {
+                    let labels = HashMap::from([
+                        ("process".to_string(), process.name.clone()),
+                        ("pid".to_string(), process.pid.to_string()),
+                    ]);
+                    let usage = [
+                        (
+                            "process_resident_bytes",
+                            process.resident_bytes.map(|bytes| bytes as f64),
+                            "bytes",
+                        ),
+                        ("process_cpu_seconds", process.cpu_seconds, "seconds"),
+                    ];
+                    for (name, value, unit) in usage {
+                        if let Some(value) = value {
+                            metrics.push(metric(name, value, unit, &labels));
+                        }
+                    }
+                }
+                for (process, counters) in supervisor.counters() {
+                    let labels = HashMap::from([("process".to_string(), process)]);
+                    let ended = [
+                        ("process_started", counters.started),
+                        ("process_failed", counters.failed),
+                        ("process_killed", counters.killed),
+                    ];
+                    for (name, count) in ended {
+                        metrics.push(metric(name, count as f64, "count", &labels));
+                    }
+                }
+
+                let monitor = monitor.read().await;
+                for metric in metrics {
+                    monitor.record_metric(metric).await;
+                }
+            }
+        });
+    }
+
+    /// Compare the system clock with NTP or the controller every
+    /// `interval_secs`, alerting while it is off by more than
+    /// `max_skew_secs`
+    async fn start_clock_check(&self) {
+        let (clock, heartbeat, offline) = {
+            let config = self.config.read().await;
+            (
+                config.monitoring.clock.clone(),
+                config.heartbeat.clone(),
+                config.network.offline,
+            )
+        };
+        if !clock.enabled || offline {
+            return;
         }
+        let interval = Duration::from_secs(clock.interval_secs);
+        let max_skew = clock.max_skew_secs;
+        let mut check = monitoring::clock::ClockCheck::new(clock);
+        if heartbeat.enabled {
+            check = check.with_controller(heartbeat.url);
+        }
+        let monitor = self.monitor.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            let mut alerted = false;
+            loop {
+                interval.tick().await;
+                let skew = match check.measure().await {
+                    Ok(skew) => skew,
+                    Err(e) => {
+                        warn!("{}", e);
+                        continue;
+                    }
+                };
+                let monitor = monitor.read().await;
+                monitor
+                    .record_metric(Metric {
+                        name: "clock_skew_seconds".to_string(),
+                        value: skew.skew_secs,
+                        unit: "seconds".to_string(),
+                        timestamp: SystemTime::now(),
+                        labels: [("source".to_string(), skew.source.clone())].into(),
+                    })
+                    .await;
+
+                if check.is_skewed(&skew) {
+                    warn!(
+                        "System clock is {:.1}s off according to {}",
+                        skew.skew_secs, skew.source
+                    );
+                    let message = format!(
+                        "System clock is off by more than {}s; TLS downloads and tunnels may fail",
+                        max_skew
+                    );
+                    monitor
+                        .raise_alert(AlertSeverity::Warning, "clock", message, false)
+                        .await;
+                    alerted = true;
+                } else if alerted {
+                    monitor
+                        .recover("clock", "System clock is in sync again".to_string())
+                        .await;
+                    alerted = false;
+                }
+            }
+        });
+    }
 
-        sleep(Duration::from_millis(500)).await;
+    /// Report SoC temperatures and Raspberry Pi throttling flags every
+    /// `interval_secs`, alerting on heat, throttling and undervoltage;
+    /// as errors while an image is being written, which they slow down or
+    /// corrupt
+    async fn start_thermal_monitor(&self) {
+        let config = self.config.read().await.monitoring.thermal.clone();
+        if !config.enabled {
+            return;
+        }
+        let monitor = self.monitor.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+            let mut alerted = false;
+            loop {
+                interval.tick().await;
+                let reading = monitoring::thermal::read().await;
+                let now = SystemTime::now();
+                let mut metrics: Vec<Metric> = reading
+                    .temperatures
+                    .iter()
+                    .map(|zone| Metric {
+                        name: "soc_temperature_celsius".to_string(),
+                        value: zone.celsius,
+                        unit: "celsius".to_string(),
+                        timestamp: now,
+                        labels: [("zone".to_string(), zone.zone.clone())].into(),
+                    })
+                    .collect();
+                let mut problems = Vec::new();
+                if let Some(throttle) = reading.throttle {
+                    let flags = [
+                        ("soc_under_voltage", throttle.under_voltage),
+                        ("soc_frequency_capped", throttle.frequency_capped),
+                        ("soc_throttled", throttle.throttled),
+                        ("soc_soft_temp_limit", throttle.soft_temp_limit),
+                    ];
+                    for (name, set) in flags {
+                        metrics.push(Metric {
+                            name: name.to_string(),
+                            value: if set { 1.0 } else { 0.0 },
+                            unit: "boolean".to_string(),
+                            timestamp: now,
+                            labels: HashMap::new(),
+                        });
+                    }
+                    if throttle.under_voltage {
+                        problems.push("Under-voltage: the power supply is too weak".to_string());
+                    }
+                    if throttle.throttled || throttle.frequency_capped || throttle.soft_temp_limit {
+                        problems.push("SoC is throttled".to_string());
+                    }
+                }
+                if let Some(hottest) = reading.hottest() {
+                    if hottest.celsius > config.max_temp_c {
+                        problems.push(format!("SoC temperature above {}°C", config.max_temp_c));
+                    }
+                }
+
+                let monitor = monitor.read().await;
+                for metric in metrics {
+                    monitor.record_metric(metric).await;
+                }
+                if problems.is_empty() {
+                    if alerted {
+                        monitor
+                            .recover(
+                                "thermal",
+                                "SoC temperature and power are normal".to_string(),
+                            )
+                            .await;
+                        alerted = false;
+                    }
+                    continue;
+                }
+                let writing = !matches!(
+                    disk_manager.get_state().await,
+                    disk::DiskManagerState::Idle | disk::DiskManagerState::Error(_)
+                );
+                for problem in problems {
+                    let (severity, message) = if writing {
+                        (
+                            AlertSeverity::Error,
+                            format!("{} during an image write", problem),
+                        )
+                    } else {
+                        (AlertSeverity::Warning, problem)
+                    };
+                    monitor
+                        .raise_alert(severity, "thermal", message, false)
+                        .await;
+                }
+                alerted = true;
+            }
+        });
+    }
+
+    /// Install success rate and mean install time per distribution from
+    /// the job history, reported to the monitor every minute
+    fn start_install_metrics(&self) {
+        let Some(history) = self.install_jobs.history().cloned() else {
+            return;
+        };
+        let monitor = self.monitor.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let records = match history
+                    .load(SystemTime::UNIX_EPOCH, SystemTime::now())
+                    .await
+                {
+                    Ok(records) => records,
+                    Err(e) => {
+                        debug!("Failed to read job history: {}", e);
+                        continue;
+                    }
+                };
+                let stats = report::InstallStatistics::build(&records);
+
+                let mut values = vec![
+                    ("installs_succeeded", stats.succeeded as f64, "count", None),
+                    ("installs_failed", stats.failed as f64, "count", None),
+                ];
+                if let Some(rate) = stats.success_rate {
+                    values.push(("install_success_ratio", rate, "ratio", None));
+                }
+                for (distro, distro_stats) in &stats.distros {
+                    if let Some(mean) = distro_stats.mean_duration_secs {
+                        values.push((
+                            "install_duration_mean_seconds",
+                            mean,
+                            "seconds",
+                            Some(distro),
+                        ));
+                    }
+                }
+                let monitor = monitor.read().await;
+                for (name, value, unit, distro) in values {
+                    let labels = distro
+                        .map(|distro| [("distro".to_string(), distro.clone())].into())
+                        .unwrap_or_default();
+                    monitor
+                        .record_metric(Metric {
+                            name: name.to_string(),
+                            value,
+                            unit: unit.to_string(),
+                            timestamp: SystemTime::now(),
+                            labels,
+                        })
+                        .await;
+                }
+            }
+        });
+    }
+
+    /// Per-interface counters and throughput, exported as Prometheus
+    /// counters and gauges
+    async fn start_traffic_metrics(&self) {
+        let traffic = self.config.read().await.monitoring.traffic.clone();
+        if !traffic.enabled {
+            return;
+        }
+        let monitor = self.monitor.clone();
+
+        tokio::spawn(async move {
+            let mut sampler = network::traffic::TrafficSampler::new(traffic.interfaces);
+            let mut interval = tokio::time::interval(Duration::from_secs(traffic.interval));
+            loop {
+                interval.tick().await;
+                let monitor = monitor.read().await;
+                for sample in sampler.sample().await {
+                    let labels: HashMap<String, String> =
+                        [("interface".to_string(), sample.interface.clone())].into();
+                    let mut values: Vec<(&str, f64, &str)> = sample
+                        .counters()
+                        .into_iter()
+                        .map(|(name, value)| (name, value as f64, "count"))
+                        .collect();
+                    values.push(("network_receive_bytes_per_second", sample.rx_rate, "bytes/s"));
+                    values.push(("network_transmit_bytes_per_second", sample.tx_rate, "bytes/s"));
+                    for (name, value, unit) in values {
+                        monitor
+                            .update_metric(Metric {
+                                name: name.to_string(),
+                                value,
+                                unit: unit.to_string(),
+                                timestamp: SystemTime::now(),
+                                labels: labels.clone(),
+                            })
+                            .await;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Round trip through the tunnel as measured by the network health
+    /// checks
+    fn start_tunnel_metrics(&self) {
+        let network_manager = self.network_manager.clone();
+        let monitor = self.monitor.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let Some(status) = network_manager.read().await.tunnel_status().await else {
+                    break;
+                };
+                let Some(latency) = status.latency else {
+                    continue;
+                };
+
+                let mut labels = HashMap::new();
+                if let Some(endpoint) = status.endpoint {
+                    labels.insert("endpoint".to_string(), endpoint);
+                }
+                monitor
+                    .read()
+                    .await
+                    .record_metric(Metric {
+                        name: "tunnel_rtt".to_string(),
+                        value: latency.as_secs_f64() * 1000.0,
+                        unit: "ms".to_string(),
+                        timestamp: SystemTime::now(),
+                        labels,
+                    })
+                    .await;
+            }
+        });
+    }
+
+    /// Show the install job queue in the UI whenever a job changes
+    fn start_queue_forwarding(&self) {
+        let mut events = self.events.subscribe();
+        let install_jobs = self.install_jobs.clone();
+        let ui_manager = self.ui_manager.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let changed = match events.recv().await {
+                    Ok(AppEvent::InstallJob(job)) => Some(job),
+                    Err(broadcast::error::RecvError::Lagged(_)) => None,
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let jobs = install_jobs.queue().await;
+                let ui = ui_manager.read().await;
+                ui.refresh_jobs(&jobs).await;
+
+                // Moves the install wizard to its result step
+                let Some(job) = changed else {
+                    continue;
+                };
+                let success = match job.status {
+                    job::install::InstallJobStatus::Completed => true,
+                    job::install::InstallJobStatus::Failed
+                    | job::install::InstallJobStatus::Interrupted
+                    | job::install::InstallJobStatus::Cancelled => false,
+                    _ => continue,
+                };
+                let message = job
+                    .error
+                    .clone()
+                    .unwrap_or_else(|| format!("{:?}", job.status));
+                ui.wizard_finished(Some(&job.id), success, message).await;
+            }
+        });
+    }
+
+    /// Show the remote services in the UI as they start and stop
+    fn start_remote_forwarding(&self) {
+        let mut events = self.events.subscribe();
+        let remote_manager = self.remote_manager.clone();
+        let ui_manager = self.ui_manager.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(AppEvent::Remote { .. } | AppEvent::RemoteService(_))
+                    | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                let services = remote_manager.read().await.services().await;
+                ui_manager
+                    .read()
+                    .await
+                    .refresh_remote_services(&services)
+                    .await;
+            }
+        });
+    }
+
+    /// Keep the access QR code in the UI current as the address and the
+    /// remote services change
+    fn start_access_forwarding(&self) {
+        let mut events = self.events.subscribe();
+        let config = self.config.clone();
+        let network_manager = self.network_manager.clone();
+        let remote_manager = self.remote_manager.clone();
+        let ui_manager = self.ui_manager.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(
+                        AppEvent::Network { .. }
+                        | AppEvent::Remote { .. }
+                        | AppEvent::RemoteService(_),
+                    )
+                    | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                if let Some(access) = node_access(&config, &network_manager, &remote_manager).await
+                {
+                    ui_manager.read().await.set_access(access).await;
+                }
+            }
+        });
+    }
 
-        if let Err(e) = self.remote_manager.write().await.start_all().await {
-            warn!("Failed to start some remote services: {}", e);
+    /// Show the open alerts in the UI as they are raised and resolved
+    fn start_alert_forwarding(&self) {
+        let mut events = self.events.subscribe();
+        let monitor = self.monitor.clone();
+        let ui_manager = self.ui_manager.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(AppEvent::Alert(_)) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                let alerts = monitor.read().await.get_alerts(Some(false)).await;
+                ui_manager.read().await.refresh_alerts(&alerts).await;
+            }
+        });
+    }
+
+    /// Queue the installs confirmed on the local interface. Whoever stands
+    /// at the node may install, as with the button trigger.
+    async fn start_ui_actions(&self) {
+        let (backend_tx, mut backend_rx) = mpsc::channel(32);
+        self.ui_manager
+            .write()
+            .await
+            .set_backend_channel(backend_tx)
+            .await;
+
+        let ui_manager = self.ui_manager.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(200));
+            loop {
+                interval.tick().await;
+                if let Err(e) = ui_manager.read().await.process_gui_events().await {
+                    debug!("Failed to process UI events: {}", e);
+                }
+            }
+        });
+
+        let ui_manager = self.ui_manager.clone();
+        let disk_manager = self.disk_manager.clone();
+        let install_jobs = self.install_jobs.clone();
+        tokio::spawn(async move {
+            while let Some(data) = backend_rx.recv().await {
+                if data.get("action").map(String::as_str) != Some("install") {
+                    continue;
+                }
+                let (Some(image), Some(device)) = (data.get("image"), data.get("device")) else {
+                    continue;
+                };
+                let queued = match disk_manager.get_disk_inventory(device).await {
+                    Ok(disk) => {
+                        let job = job::install::InstallJob::new(image, &disk.path, false);
+                        install_jobs.enqueue(job).await
+                    }
+                    Err(e) => Err(e),
+                };
+                let ui = ui_manager.read().await;
+                match queued {
+                    Ok(job) => {
+                        info!("Queued install job {} from the local interface", job.id);
+                        ui.wizard_started(job.id).await;
+                    }
+                    Err(e) => {
+                        ui.wizard_finished(None, false, e.to_string()).await;
+                        let _ = ui.show_localized_error(&e).await;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Publish the managers' progress channels, install jobs and inventory
+    /// changes on the event bus
+    fn start_event_forwarding(&self) {
+        self.events.forward_from(
+            self.disk_manager.clone(),
+            self.iso_manager.clone(),
+            self.install_jobs.clone(),
+        );
+    }
+
+    fn start_ban_alerts(&self) {
+        let mut events = self.events.subscribe();
+        let monitor = self.monitor.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let (severity, message, resolved) = match event {
+                    AppEvent::Banned(ban) => (
+                        AlertSeverity::Warning,
+                        format!(
+                            "Banned {} for {}s after {} failed sign-ins",
+                            ban.address, ban.expires_in_secs, ban.failures
+                        ),
+                        false,
+                    ),
+                    AppEvent::BanLifted { address } => (
+                        AlertSeverity::Info,
+                        format!("Ban of {} lifted", address),
+                        true,
+                    ),
+                    _ => continue,
+                };
+                monitor
+                    .read()
+                    .await
+                    .raise_alert(severity, "auth", message, resolved)
+                    .await;
+            }
+        });
+    }
+
+    /// Hold the install queue, disk writes and the button trigger while the
+    /// node is in safe mode, with a banner in the UI. Remote access and the
+    /// API keep running so an operator can look into it.
+    fn start_safe_mode(&self) {
+        let monitor = self.monitor.clone();
+        let install_jobs = self.install_jobs.clone();
+        let disk_manager = self.disk_manager.clone();
+        let button_manager = self.button_manager.clone();
+        let ui_manager = self.ui_manager.clone();
+        let events = self.events.clone();
+
+        tokio::spawn(async move {
+            let mut safe_mode_rx = monitor.read().await.subscribe_safe_mode();
+            while safe_mode_rx.changed().await.is_ok() {
+                let safe_mode = safe_mode_rx.borrow_and_update().clone();
+                let ui = ui_manager.read().await;
+                match &safe_mode {
+                    Some(entered) => {
+                        warn!("Safe mode: {}", entered.reason);
+                        let cancelled = install_jobs.hold(true).await;
+                        disk_manager.hold(Some(entered.reason.clone())).await;
+                        if let Err(e) = button_manager.write().await.stop().await {
+                            warn!("Failed to stop button trigger: {}", e);
+                        }
+                        ui.set_banner(Some(format!(
+                            "Safe mode: {}. Installs are paused until an operator resumes them.",
+                            entered.reason
+                        )))
+                        .await;
+                        let _ = ui
+                            .show_error(&format!(
+                                "Safe mode entered, {} install jobs cancelled",
+                                cancelled.len()
+                            ))
+                            .await;
+                    }
+                    None => {
+                        info!("Left safe mode");
+                        disk_manager.hold(None).await;
+                        install_jobs.hold(false).await;
+                        if let Err(e) = button_manager.write().await.start().await {
+                            warn!("Failed to start button trigger: {}", e);
+                        }
+                        ui.set_banner(None).await;
+                        let _ = ui.show_info("Safe mode left, installs resumed").await;
+                    }
+                }
+                events.publish(AppEvent::SafeMode { safe_mode });
+            }
+        });
+    }
+
+    /// Keep the state, active ISO and progress in the mDNS TXT record
+    /// current. The responder only announces entries that changed.
+    fn start_mdns_status(&self) {
+        let mut queue_rx = self.install_jobs.subscribe_queue();
+        let mut progress_rx = self.disk_manager.subscribe_progress();
+        let iso_manager = self.iso_manager.clone();
+        let network_manager = self.network_manager.clone();
+
+        tokio::spawn(async move {
+            // The ISO manager's state has no channel, so it is polled
+            let mut interval = tokio::time::interval(Duration::from_secs(5));
+            let mut progress = None;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    changed = queue_rx.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
+                    received = progress_rx.recv() => match received {
+                        Ok(update) => progress = Some(update.percentage),
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+
+                let jobs = queue_rx.borrow_and_update().clone();
+                if !jobs
+                    .iter()
+                    .any(|queued| queued.status == job::install::InstallJobStatus::Running)
+                {
+                    progress = None;
+                }
+                let records = mdns_status(
+                    &jobs,
+                    &iso_manager.get_state().await,
+                    iso_manager.get_active_iso().await.as_deref(),
+                    progress,
+                );
+                network_manager.read().await.set_mdns_status(records);
+            }
+        });
+    }
+
+    fn start_download_forwarding(&self) {
+        let mut events = self.events.subscribe();
+        let ui_manager = self.ui_manager.clone();
+        let monitor = self.monitor.clone();
+        let config = self.config.clone();
+
+        tokio::spawn(async move {
+            let mut throttle = ProgressThrottle::new(&config.read().await.logging.progress);
+            loop {
+                let progress = match events.recv().await {
+                    Ok(AppEvent::DownloadProgress(progress)) => progress,
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                let details = format!(
+                    "{}: {} MiB at {} KiB/s",
+                    progress.file,
+                    progress.downloaded_bytes / (1024 * 1024),
+                    progress.bytes_per_sec / 1024
+                );
+                if throttle.should_log(&progress.file, progress.percentage) {
+                    info!(
+                        file = %progress.file,
+                        mirror = %progress.mirror,
+                        percent = progress.percentage,
+                        bytes_per_sec = progress.bytes_per_sec,
+                        "Downloading ISO"
+                    );
+                }
+                if let Err(e) = ui_manager
+                    .read()
+                    .await
+                    .update_progress("Downloading ISO", progress.percentage, &details, 0, 0)
+                    .await
+                {
+                    debug!("Failed to forward download progress to UI: {}", e);
+                }
+
+                let labels: HashMap<String, String> = [
+                    ("file".to_string(), progress.file.clone()),
+                    ("mirror".to_string(), progress.mirror.clone()),
+                ]
+                .into();
+                let monitor = monitor.read().await;
+                monitor
+                    .record_metric(Metric {
+                        name: "iso_download_progress".to_string(),
+                        value: progress.percentage as f64,
+                        unit: "percent".to_string(),
+                        timestamp: SystemTime::now(),
+                        labels: labels.clone(),
+                    })
+                    .await;
+                monitor
+                    .record_metric(Metric {
+                        name: "iso_download_rate".to_string(),
+                        value: progress.bytes_per_sec as f64,
+                        unit: "bytes_per_second".to_string(),
+                        timestamp: SystemTime::now(),
+                        labels,
+                    })
+                    .await;
+            }
+        });
+    }
+
+    /// Check the release feeds now and then every `interval_hours`
+    async fn start_feed_sync(&self) {
+        let config = self.config.read().await;
+        let feeds = config.iso.feeds.clone();
+        if config.network.offline || feeds.sources.is_empty() || feeds.interval_hours == 0 {
+            return;
         }
+        drop(config);
+
+        let iso_manager = self.iso_manager.clone();
+        let period = Duration::from_secs(feeds.interval_hours * 3600);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match iso_manager.sync_feeds().await {
+                    Ok(report) => info!(
+                        "Release feeds: {} images, {} newer, {} downloaded",
+                        report.releases,
+                        report.updates.len(),
+                        report.downloaded.len()
+                    ),
+                    Err(e) => warn!("Release feed sync failed: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Mail the report of the past shift to the email notifiers at the end
+    /// of every `shift_hours`
+    async fn start_report_mail(&self) {
+        let config = self.config.read().await;
+        let reports = config.reports.clone();
+        if config.network.offline || !reports.mail || reports.shift_hours == 0 {
+            return;
+        }
+        let notifiers = monitoring::notify::Notifiers::new(config.monitoring.notifiers.clone());
+        drop(config);
+        let Some(history) = self.install_jobs.history().cloned() else {
+            return;
+        };
+
+        let shift = Duration::from_secs(reports.shift_hours * 3600);
+        tokio::spawn(async move {
+            // The first report covers a whole shift
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + shift, shift);
+            loop {
+                interval.tick().await;
+                let until = SystemTime::now();
+                let since = until.checked_sub(shift).unwrap_or(SystemTime::UNIX_EPOCH);
+                let records = match history.load(since, until).await {
+                    Ok(records) => records,
+                    Err(e) => {
+                        warn!("Failed to read job history for the shift report: {}", e);
+                        continue;
+                    }
+                };
+                let report = report::ShiftReport::build(&records, since, until);
+                let subject = format!(
+                    "Shift report: {} jobs in the last {}h",
+                    records.len(),
+                    reports.shift_hours
+                );
+                match notifiers
+                    .mail_report(&reports.mail_notifiers, &subject, &report.to_html())
+                    .await
+                {
+                    Ok(()) => info!("Mailed the shift report"),
+                    Err(e) => warn!("Failed to mail the shift report: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Mirror the central ISO repository now and then every `interval_hours`
+    async fn start_repo_sync(&self) {
+        let config = self.config.read().await;
+        let sync = config.iso.sync.clone();
+        if config.network.offline || sync.url.is_none() || sync.interval_hours == 0 {
+            return;
+        }
+        drop(config);
+
+        let iso_manager = self.iso_manager.clone();
+        let period = Duration::from_secs(sync.interval_hours * 3600);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match iso_manager.sync_repository().await {
+                    Ok(report) if !report.errors.is_empty() => warn!(
+                        "Repository sync finished with {} errors",
+                        report.errors.len()
+                    ),
+                    Ok(_) => {}
+                    Err(e) => warn!("Repository sync failed: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Write `authorized_keys` now, then fetch the key URLs and GitHub
+    /// users again every `refresh_hours`
+    async fn start_ssh_key_sync(&self) {
+        let ssh = self.config.read().await.remote.ssh.clone();
+        if !ssh.enabled {
+            return;
+        }
+
+        let ssh_keys = self.ssh_keys.clone();
+        tokio::spawn(async move {
+            if let Err(e) = ssh_keys.provision().await {
+                warn!("Failed to provision SSH keys: {}", e);
+            }
+            if ssh.keys.refresh_hours == 0 || !ssh_keys.has_remote_sources() {
+                return;
+            }
+            let period = Duration::from_secs(ssh.keys.refresh_hours * 3600);
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = ssh_keys.provision().await {
+                    warn!("SSH key refresh failed: {}", e);
+                }
+            }
+        });
+    }
 
-        if let Err(e) = self.iso_manager.start().await {
-            warn!("Failed to start ISO manager: {}", e);
+    /// Report install jobs a crash or power loss stopped, resume them when
+    /// configured to, and run the jobs that were still queued
+    async fn recover_install_jobs(&self) {
+        let resume = self.config.read().await.jobs.resume_interrupted;
+        match self.install_jobs.recover(resume).await {
+            Ok(interrupted) if !interrupted.is_empty() && !resume => info!(
+                "{} interrupted install jobs are waiting to be resumed or restarted",
+                interrupted.len()
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to read install jobs: {}", e),
         }
+    }
 
-        if let Err(e) = self.ui_manager.write().await.start().await {
-            warn!("Failed to start UI manager: {}", e);
+    async fn start_subsystems(&mut self) -> Result<()> {
+        info!("Starting subsystems");
+
+        let network = self.network_manager.clone();
+        let remote = self.remote_manager.clone();
+        let display = self.remote_manager.clone();
+        let iso = self.iso_manager.clone();
+        let ui = self.ui_manager.clone();
+        let api = self.api_server.clone();
+        let metrics = self.metrics_exporter.clone();
+        let pxe = self.pxe_server.clone();
+        let button = self.button_manager.clone();
+        let mqtt_client = self.mqtt_client.clone();
+        let heartbeat = self.heartbeat.clone();
+        #[cfg(feature = "grpc")]
+        let grpc = self.grpc_server.clone();
+
+        // Remote access, ISO downloads and the API need the network; the
+        // button copies ISOs and network boot serves them, so both wait
+        // for the first scan. Offline, the network is not a precondition:
+        // a node with no link at all still serves the local UI and ISOs.
+        let config = self.config.read().await.startup.clone();
+        let offline = self.config.read().await.network.offline;
+        let on_network = network_precondition(offline);
+        let plan = StartupPlan::from_config(&config).with_status(self.startup.clone());
+        let start_network = async move { network.read().await.start().await };
+        let plan = if offline {
+            plan.add("network", &[], start_network)
+        } else {
+            plan.add_required("network", &[], start_network)
+        };
+        let plan = plan
+            .add("remote", on_network, async move {
+                remote.write().await.start_all().await
+            })
+            .add("iso", on_network, async move { iso.start().await })
+            // A headless node's virtual display must exist before the UI
+            // looks for one; the UI starts even when it cannot
+            .add("display", &[], async move {
+                if let Err(e) = display.write().await.start_display().await {
+                    warn!("No display for VNC: {}", e);
+                }
+                Ok(())
+            })
+            .add(
+                "ui",
+                &["display"],
+                async move { ui.write().await.start().await },
+            )
+            .add(
+                "api",
+                on_network,
+                async move { api.write().await.start().await },
+            )
+            .add("metrics", on_network, async move {
+                metrics.write().await.start().await
+            })
+            .add("pxe", &["network", "iso"], async move {
+                pxe.write().await.start().await
+            })
+            .add("button", &["iso"], async move {
+                button.write().await.start().await
+            })
+            .add("mqtt", on_network, async move { mqtt_client.start().await })
+            .add(
+                "heartbeat",
+                on_network,
+                async move { heartbeat.start().await },
+            );
+        #[cfg(feature = "grpc")]
+        let plan = plan.add("grpc", on_network, async move {
+            grpc.write().await.start().await
+        });
+        let report = plan.run().await?;
+
+        if report.is_ready("ui") {
+            self.refresh_device_picker().await;
+            self.refresh_image_picker().await;
+        }
+        if let Some(access) =
+            node_access(&self.config, &self.network_manager, &self.remote_manager).await
+        {
+            self.ui_manager.read().await.set_access(access).await;
         }
 
         Ok(())
     }
 
+    async fn refresh_device_picker(&self) {
+        match self.disk_manager.list_disks().await {
+            Ok(disks) => self.ui_manager.read().await.refresh_devices(&disks).await,
+            Err(e) => warn!("Failed to list disks: {}", e),
+        }
+    }
+
+    async fn refresh_image_picker(&self) {
+        let images = self
+            .iso_manager
+            .get_catalog(&iso::catalog::CatalogQuery::default())
+            .await;
+        self.ui_manager.read().await.refresh_images(&images).await;
+    }
+
     async fn run(&mut self) -> Result<()> {
         info!("USB Installer Node is running");
 
@@ -259,6 +1667,7 @@ impl AppState {
         }
 
         self.monitor.read().await.clear_resolved_alerts().await;
+        self.refresh_device_picker().await;
     }
 
     async fn shutdown(&mut self) -> Result<()> {
@@ -268,26 +1677,67 @@ impl AppState {
 
         let _ = self.shutdown_tx.send(());
 
-        if let Err(e) = self.ui_manager.write().await.stop().await {
-            warn!("Error stopping UI manager: {}", e);
-        }
-
-        if let Err(e) = self.iso_manager.stop().await {
-            warn!("Error stopping ISO manager: {}", e);
-        }
-
-        if let Err(e) = self.remote_manager.write().await.stop_all().await {
-            warn!("Error stopping remote manager: {}", e);
-        }
-
-        if let Err(e) = self.network_manager.write().await.stop().await {
-            warn!("Error stopping network manager: {}", e);
-        }
-
+        // First, so nothing stopped below is restarted as unhealthy
         if let Err(e) = self.monitor.write().await.stop().await {
             warn!("Error stopping monitor: {}", e);
         }
 
+        let network = self.network_manager.clone();
+        let remote = self.remote_manager.clone();
+        let iso = self.iso_manager.clone();
+        let ui = self.ui_manager.clone();
+        let api = self.api_server.clone();
+        let metrics = self.metrics_exporter.clone();
+        let pxe = self.pxe_server.clone();
+        let button = self.button_manager.clone();
+        let mqtt_client = self.mqtt_client.clone();
+        let heartbeat = self.heartbeat.clone();
+        #[cfg(feature = "grpc")]
+        let grpc = self.grpc_server.clone();
+
+        // The dependencies of `start_subsystems`, stopped the other way round
+        let config = self.config.read().await.startup.clone();
+        let plan = ShutdownPlan::from_config(&config)
+            .add(
+                "network",
+                &[],
+                async move { network.write().await.stop().await },
+            )
+            .add("remote", &["network"], async move {
+                remote.write().await.stop_all().await
+            })
+            .add("iso", &["network"], async move { iso.stop().await })
+            .add("ui", &[], async move { ui.write().await.stop().await })
+            .add("api", &["network"], async move {
+                api.write().await.stop().await
+            })
+            .add("metrics", &["network"], async move {
+                metrics.write().await.stop().await
+            })
+            .add("pxe", &["network", "iso"], async move {
+                pxe.write().await.stop().await
+            })
+            .add("button", &["iso"], async move {
+                button.write().await.stop().await
+            })
+            .add(
+                "mqtt",
+                &["network"],
+                async move { mqtt_client.stop().await },
+            )
+            .add(
+                "heartbeat",
+                &["network"],
+                async move { heartbeat.stop().await },
+            );
+        #[cfg(feature = "grpc")]
+        let plan = plan.add("grpc", &["network"], async move {
+            grpc.write().await.stop().await
+        });
+        if let Err(e) = plan.run().await {
+            error!("Shutdown plan is invalid: {}", e);
+        }
+
         let shutdown_duration = shutdown_start.elapsed();
         info!("Shutdown complete in {:?}", shutdown_duration);
 
@@ -297,7 +1747,21 @@ impl AppState {
 
 #[tokio::main]
 async fn main() {
-    let result = run_app().await;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("render") {
+        std::process::exit(render_answers(&args[1..]));
+    }
+    if args.first().map(String::as_str) == Some("diagnose") {
+        std::process::exit(run_diagnostics(&args[1..]).await);
+    }
+
+    // Rehearse a configuration: log the commands that would change disks
+    // and installer media instead of running them
+    let dry_run = args
+        .iter()
+        .any(|arg| arg == "--dry-run")
+        .then(dryrun::DryRun::new);
+    let result = run_app(dry_run).await;
 
     match result {
         Ok(_) => {
@@ -311,11 +1775,205 @@ async fn main() {
     }
 }
 
-async fn run_app() -> Result<()> {
+/// `render --check` renders every answer file template against `[target]`
+/// and reports problems; `render <name>` prints one file, e.g. `render ks.cfg`
+fn render_answers(args: &[String]) -> i32 {
+    let result = Config::load("config.toml").and_then(|config| {
+        let templates = TemplateEngine::new(config.iso.unattended.template_dir.as_deref())?;
+        match args.first().map(String::as_str) {
+            Some("--check") => {
+                let problems = unattended::check(&config.target, &templates);
+                for problem in &problems {
+                    eprintln!("{}", problem);
+                }
+                if problems.is_empty() {
+                    println!("All answer file templates render cleanly");
+                }
+                Ok(problems.is_empty())
+            }
+            Some(name) => {
+                print!("{}", unattended::preview(&config.target, &templates, name)?);
+                Ok(true)
+            }
+            None => {
+                let names: Vec<&str> = TemplateEngine::names().collect();
+                eprintln!("Usage: render --check | render <{}>", names.join("|"));
+                Ok(false)
+            }
+        }
+    });
+
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// `diagnose` runs the self-test and prints one line per check, or the
+/// report as JSON with `--json`. Exits non-zero when a check failed.
+async fn run_diagnostics(args: &[String]) -> i32 {
+    let config = match Config::load("config.toml") {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let report = diagnose::Diagnostics::from_config(&config).run().await;
+    if args.iter().any(|arg| arg == "--json") {
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+    } else {
+        print!("{}", report.to_text());
+    }
+    if report.passed {
+        0
+    } else {
+        1
+    }
+}
+
+/// What subsystems that reach beyond the node wait for. Offline there is
+/// no outside connection to wait for.
+fn network_precondition(offline: bool) -> &'static [&'static str] {
+    if offline {
+        &[]
+    } else {
+        &["network"]
+    }
+}
+
+/// Ports of the enabled remote services, advertised over mDNS
+fn service_ports(config: &Config) -> network::mdns::ServicePorts {
+    let remote = &config.remote;
+    network::mdns::ServicePorts {
+        api: config.api.port,
+        vnc: remote.vnc.enabled.then_some(remote.vnc.port),
+        ssh: remote.ssh.enabled.then_some(remote.ssh.port),
+    }
+}
+
+/// Ports the firewall opens: the management services, plus network boot,
+/// mDNS and the WireGuard listener for everyone
+fn firewall_ports(config: &Config) -> network::firewall::FirewallPorts {
+    let remote = &config.remote;
+    let mut ports = network::firewall::FirewallPorts {
+        management_tcp: vec![config.api.port],
+        ..Default::default()
+    };
+    ports.management_tcp.extend(config.monitoring.metrics_port);
+    for (enabled, port) in [
+        (remote.ssh.enabled, remote.ssh.port),
+        (remote.vnc.enabled, remote.vnc.port),
+        (remote.web_vnc.enabled, remote.web_vnc.port),
+    ] {
+        if enabled {
+            ports.management_tcp.push(port);
+        }
+    }
+
+    if config.network.mdns_enabled || config.network.discovery.enabled {
+        ports.public_udp.push(5353);
+    }
+    if config.pxe.enabled {
+        ports.public_udp.extend([67, 69, 4011]);
+        ports.public_tcp.push(config.pxe.http_port);
+    }
+    let provisioning = &config.network.provisioning;
+    if provisioning.enabled {
+        ports.public_udp.extend(&provisioning.allowed_udp);
+        ports.public_tcp.extend(&provisioning.allowed_tcp);
+    }
+    let tunnel = &config.network.tunnel;
+    if tunnel.enabled && tunnel.wireguard.embedded {
+        ports.public_udp.extend(tunnel.wireguard.listen_port);
+    }
+    ports
+}
+
+/// WebVNC sign-in through the API, when WebVNC requires authentication
+fn web_vnc_login(config: &Config) -> Option<remote::web_vnc::WebVncLogin> {
+    let web_vnc = &config.remote.web_vnc;
+    if !web_vnc.enabled || !web_vnc.auth_required {
+        return None;
+    }
+    Some(remote::web_vnc::WebVncLogin::new(
+        web_vnc.username.clone()?,
+        web_vnc.password.clone()?,
+        Duration::from_secs(web_vnc.session_timeout),
+    ))
+}
+
+/// Where the node is reached: its address, else its host name, with the
+/// API port and the remote services that run. `None` before either is known.
+async fn node_access(
+    config: &RwLock<Config>,
+    network: &RwLock<network::NetworkManager>,
+    remote: &RwLock<remote::RemoteManager>,
+) -> Option<ui::access::AccessInfo> {
+    let status = network.read().await.get_status().await;
+    let host = status.ip_address.or(status.hostname)?;
+    let api_port = config.read().await.api.port;
+    let services = remote.read().await.services().await;
+    Some(ui::access::AccessInfo::new(&host, api_port, &services))
+}
+
+/// TXT entries describing what the node is doing: `state`, and while
+/// installing `step` and `progress` (percent); `iso` while one is active
+fn mdns_status(
+    jobs: &[job::install::InstallJob],
+    iso_state: &iso::IsoManagerState,
+    active_iso: Option<&std::path::Path>,
+    progress: Option<u8>,
+) -> Vec<String> {
+    let running = jobs
+        .iter()
+        .find(|queued| queued.status == job::install::InstallJobStatus::Running);
+    let state = match (running, iso_state) {
+        (Some(_), _) | (None, iso::IsoManagerState::Installing) => "installing",
+        (None, iso::IsoManagerState::Idle) => "idle",
+        (None, iso::IsoManagerState::Scanning) => "scanning",
+        (None, iso::IsoManagerState::Mounting) => "mounting",
+        (None, iso::IsoManagerState::Downloading) => "downloading",
+        (None, iso::IsoManagerState::Ready) => "ready",
+        (None, iso::IsoManagerState::Error(_)) => "error",
+    };
+
+    let mut records = vec![format!("state={}", state)];
+    if let Some(job) = running {
+        // Same spelling as the API
+        let step = serde_json::to_value(job.step)
+            .ok()
+            .and_then(|step| step.as_str().map(str::to_string))
+            .unwrap_or_default();
+        records.push(format!("step={}", step));
+    }
+    if let Some(name) = active_iso.and_then(|iso| iso.file_name()) {
+        records.push(format!("iso={}", name.to_string_lossy()));
+    }
+    if let Some(progress) = progress {
+        records.push(format!("progress={}", progress));
+    }
+    records
+}
+
+async fn run_app(dry_run: Option<dryrun::DryRun>) -> Result<()> {
     let config = Config::load("config.toml")?;
 
     Logger::init(&config.logging)?;
 
+    #[cfg(feature = "chaos")]
+    chaos::install(&config.chaos);
+
     info!("Starting USB Installer Node v{}", env!("CARGO_PKG_VERSION"));
     debug!("Configuration loaded from config.toml");
 
@@ -325,7 +1983,7 @@ async fn run_app() -> Result<()> {
         panic_hook(panic_info);
     }));
 
-    let mut app = AppState::new(config).await?;
+    let mut app = AppState::new(config, dry_run).await?;
 
     if let Err(e) = app.initialize().await {
         error!("Initialization failed: {}", e);
@@ -347,4 +2005,57 @@ mod tests {
     fn test_main_compiles() {
         assert!(true);
     }
+
+    #[tokio::test]
+    async fn test_offline_startup() {
+        assert_eq!(network_precondition(false), ["network"]);
+        assert!(network_precondition(true).is_empty());
+
+        // A node with no link still starts the API and the UI
+        let started = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = |name: &'static str| {
+            let started = started.clone();
+            async move {
+                started.lock().unwrap().push(name);
+                Ok(())
+            }
+        };
+        let report = StartupPlan::new()
+            .add("network", &[], async {
+                Err(error::Error::General("no link".to_string()))
+            })
+            .add("api", network_precondition(true), record("api"))
+            .add("remote", network_precondition(false), record("remote"))
+            .run()
+            .await
+            .unwrap();
+        assert!(report.is_ready("api"));
+        assert!(!report.is_ready("remote"));
+        assert_eq!(*started.lock().unwrap(), ["api"]);
+    }
+
+    #[test]
+    fn test_mdns_status() {
+        let idle = mdns_status(&[], &iso::IsoManagerState::Idle, None, None);
+        assert_eq!(idle, vec!["state=idle"]);
+
+        let mut job = job::install::InstallJob::new("/isos/debian-12.iso", "/dev/sdb", true);
+        job.status = job::install::InstallJobStatus::Running;
+        job.step = job::install::InstallStep::PrepareDisk;
+        let installing = mdns_status(
+            &[job],
+            &iso::IsoManagerState::Ready,
+            Some(std::path::Path::new("/isos/debian-12.iso")),
+            Some(40),
+        );
+        assert_eq!(
+            installing,
+            vec![
+                "state=installing",
+                "step=prepare_disk",
+                "iso=debian-12.iso",
+                "progress=40"
+            ]
+        );
+    }
 }
@@ -1,23 +1,93 @@
+pub mod clock;
+pub mod exporter;
+pub mod notify;
+pub mod store;
+pub mod thermal;
+
+use crate::chaos::{self, FaultPoint};
 use crate::config::MonitoringConfig;
 use crate::error::{MonitoringError, Result};
-use std::collections::HashMap;
+use crate::events::{AppEvent, EventBus};
+use notify::Notifiers;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
-use tokio::sync::{mpsc, RwLock};
+use store::{MetricQuery, MetricStore};
+use tokio::sync::{mpsc, watch, RwLock};
 use tokio::time::interval;
 use tracing::{debug, error, info, warn};
 
-#[derive(Debug, Clone)]
+/// Repeats of an open alert with the same module and message are counted
+/// on it instead of added
+#[derive(Debug, Clone, Serialize)]
 pub struct Alert {
     pub id: String,
     pub severity: AlertSeverity,
     pub module: String,
     pub message: String,
+    /// Latest occurrence
     pub timestamp: SystemTime,
+    pub first_seen: SystemTime,
+    pub occurrences: u32,
+    /// Who acknowledged the alert; it stays open until resolved
+    pub acknowledged_by: Option<String>,
     pub resolved: bool,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl Alert {
+    pub fn new(severity: AlertSeverity, module: impl Into<String>, message: String) -> Self {
+        let now = SystemTime::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            severity,
+            module: module.into(),
+            message,
+            timestamp: now,
+            first_seen: now,
+            occurrences: 1,
+            acknowledged_by: None,
+            resolved: false,
+        }
+    }
+}
+
+/// What the alert processor is sent
+#[derive(Debug)]
+enum AlertUpdate {
+    Raised(Alert),
+    /// A service passed its health check again: its open alerts are
+    /// resolved and the recovery recorded
+    Recovered(Alert),
+}
+
+/// Longest wait between two restarts of a service
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(600);
+/// Longest a health check may take before it counts as failed
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Restarts of a service since it last passed a health check
+#[derive(Debug, Default)]
+struct RestartBackoff {
+    attempts: u32,
+    next_attempt: Option<Instant>,
+    /// `max_restart_attempts` reached and reported
+    gave_up: bool,
+}
+
+/// Entered when the monitor gives up restarting a service: destructive
+/// subsystems stop while remote access and the API stay up, until an
+/// operator leaves it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SafeMode {
+    /// Service that stayed down
+    pub service: String,
+    pub reason: String,
+    pub entered_at: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum AlertSeverity {
     Info,
     Warning,
@@ -25,7 +95,7 @@ pub enum AlertSeverity {
     Critical,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Metric {
     pub name: String,
     pub value: f64,
@@ -55,9 +125,13 @@ pub struct Monitor {
     services: Arc<RwLock<HashMap<String, Box<dyn Monitorable>>>>,
     health_status: Arc<RwLock<HashMap<String, ServiceHealth>>>,
     alerts: Arc<RwLock<Vec<Alert>>>,
-    metrics: Arc<RwLock<Vec<Metric>>>,
-    alert_tx: mpsc::Sender<Alert>,
-    alert_rx: Arc<RwLock<mpsc::Receiver<Alert>>>,
+    metrics: Arc<RwLock<MetricStore>>,
+    restarts: Arc<RwLock<HashMap<String, RestartBackoff>>>,
+    safe_mode: Arc<watch::Sender<Option<SafeMode>>>,
+    alert_tx: mpsc::Sender<AlertUpdate>,
+    alert_rx: Arc<RwLock<mpsc::Receiver<AlertUpdate>>>,
+    /// Where processed alerts are published
+    events: Option<EventBus>,
     shutdown_tx: Option<mpsc::Sender<()>>,
 }
 
@@ -70,13 +144,23 @@ impl Monitor {
             services: Arc::new(RwLock::new(HashMap::new())),
             health_status: Arc::new(RwLock::new(HashMap::new())),
             alerts: Arc::new(RwLock::new(Vec::new())),
-            metrics: Arc::new(RwLock::new(Vec::new())),
+            metrics: Arc::new(RwLock::new(MetricStore::new(Default::default()))),
+            restarts: Arc::new(RwLock::new(HashMap::new())),
+            safe_mode: Arc::new(watch::channel(None).0),
             alert_tx,
             alert_rx: Arc::new(RwLock::new(alert_rx)),
+            events: None,
             shutdown_tx: None,
         }
     }
 
+    /// Publish alerts from the health checks and `raise_alert` once
+    /// processed
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
     pub async fn register_service(&self, service: Box<dyn Monitorable>) {
         let name = service.name().to_string();
         let health = ServiceHealth {
@@ -98,6 +182,11 @@ impl Monitor {
         info!("Starting monitoring service");
 
         let config = self.config.read().await;
+        self.metrics
+            .write()
+            .await
+            .open(config.history.clone())
+            .await;
         if !config.enabled {
             info!("Monitoring disabled");
             return Ok(());
@@ -106,11 +195,13 @@ impl Monitor {
         let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
         self.shutdown_tx = Some(shutdown_tx);
 
-        let check_interval = Duration::from_secs(config.check_interval);
+        let check_interval = Duration::from_secs(config.watchdog_interval);
         let mut interval_timer = interval(check_interval);
 
         let services = self.services.clone();
         let health_status = self.health_status.clone();
+        let restarts = self.restarts.clone();
+        let safe_mode = self.safe_mode.clone();
         let alert_tx = self.alert_tx.clone();
         let config = self.config.clone();
 
@@ -118,7 +209,7 @@ impl Monitor {
             loop {
                 tokio::select! {
                     _ = interval_timer.tick() => {
-                        Self::check_all_services(&services, &health_status, &alert_tx, &config).await;
+                        Self::check_all_services(&services, &health_status, &restarts, &safe_mode, &alert_tx, &config).await;
                     }
                     _ = shutdown_rx.recv() => {
                         info!("Monitoring shutdown received");
@@ -144,21 +235,69 @@ impl Monitor {
         Ok(())
     }
 
+    /// Current safe mode, `None` in normal operation
+    pub fn safe_mode(&self) -> Option<SafeMode> {
+        self.safe_mode.borrow().clone()
+    }
+
+    /// Changes of the safe mode, for the subsystems it stops
+    pub fn subscribe_safe_mode(&self) -> watch::Receiver<Option<SafeMode>> {
+        self.safe_mode.subscribe()
+    }
+
+    /// Leave safe mode. Services given up on are restarted again from the
+    /// next check. Returns the safe mode that was left.
+    pub async fn leave_safe_mode(&self) -> Option<SafeMode> {
+        let left = self.safe_mode.send_replace(None);
+        if let Some(left) = &left {
+            info!("Leaving safe mode entered for {}", left.service);
+            self.restarts.write().await.clear();
+        }
+        left
+    }
+
+    /// Check every service, then restart those that failed
+    /// `max_failures` checks in a row. Checks run with the health status
+    /// unlocked and give up after [`HEALTH_CHECK_TIMEOUT`]; restarts run
+    /// after them, one service at a time.
     async fn check_all_services(
         services: &Arc<RwLock<HashMap<String, Box<dyn Monitorable>>>>,
         health_status: &Arc<RwLock<HashMap<String, ServiceHealth>>>,
-        alert_tx: &mpsc::Sender<Alert>,
+        restarts: &Arc<RwLock<HashMap<String, RestartBackoff>>>,
+        safe_mode: &watch::Sender<Option<SafeMode>>,
+        alert_tx: &mpsc::Sender<AlertUpdate>,
         config: &Arc<RwLock<MonitoringConfig>>,
     ) {
-        let services = services.read().await;
-        let mut status = health_status.write().await;
-        let config = config.read().await;
+        let config = config.read().await.clone();
+        let check_interval = Duration::from_secs(config.watchdog_interval);
+        let mut recovered = Vec::new();
+        let mut down = Vec::new();
 
-        for (name, service) in services.iter() {
+        let mut results = Vec::new();
+        for (name, service) in services.read().await.iter() {
             let start = Instant::now();
-            let result = service.health_check().await;
+            let result = match chaos::inject(FaultPoint::HealthCheck, name) {
+                Ok(()) => tokio::time::timeout(HEALTH_CHECK_TIMEOUT, service.health_check())
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(MonitoringError::WatchdogError(format!(
+                            "no answer within {}s",
+                            HEALTH_CHECK_TIMEOUT.as_secs()
+                        ))
+                        .into())
+                    }),
+                Err(e) => Err(e),
+            };
+            results.push((name.clone(), start, result));
+        }
 
-            if let Some(health) = status.get_mut(name) {
+        let mut alerts = Vec::new();
+        {
+            let mut status = health_status.write().await;
+            for (name, start, result) in results {
+                let Some(health) = status.get_mut(&name) else {
+                    continue;
+                };
                 health.last_check = start;
 
                 match result {
@@ -168,17 +307,17 @@ impl Monitor {
                             health.error_count = 0;
 
                             let alert = Alert {
-                                id: uuid::Uuid::new_v4().to_string(),
-                                severity: AlertSeverity::Info,
-                                module: name.clone(),
-                                message: format!("Service {} recovered", name),
-                                timestamp: SystemTime::now(),
                                 resolved: true,
+                                ..Alert::new(
+                                    AlertSeverity::Info,
+                                    &name,
+                                    format!("Service {} recovered", name),
+                                )
                             };
-
-                            let _ = alert_tx.send(alert).await;
+                            alerts.push(AlertUpdate::Recovered(alert));
+                            recovered.push(name);
                         }
-                        health.uptime = health.uptime.saturating_add(config.check_interval);
+                        health.uptime = health.uptime.saturating_add(check_interval);
                     }
                     Err(e) => {
                         health.healthy = false;
@@ -190,48 +329,101 @@ impl Monitor {
                             AlertSeverity::Warning
                         };
 
-                        let alert = Alert {
-                            id: uuid::Uuid::new_v4().to_string(),
-                            severity,
-                            module: name.clone(),
-                            message: format!("Health check failed: {}", e),
-                            timestamp: SystemTime::now(),
-                            resolved: false,
-                        };
-
-                        let _ = alert_tx.send(alert).await;
+                        let alert =
+                            Alert::new(severity, &name, format!("Health check failed: {}", e));
+                        alerts.push(AlertUpdate::Raised(alert));
 
                         if health.error_count >= config.max_failures && config.auto_restart {
-                            warn!(
-                                "Service {} exceeded failure threshold, attempting restart",
-                                name
-                            );
-                            health.error_count = 0;
-                            health.restart_count += 1;
-
-                            drop(status);
-                            drop(services);
+                            down.push(name);
+                        }
+                    }
+                }
+            }
+        }
+        for alert in alerts {
+            let _ = alert_tx.send(alert).await;
+        }
 
-                            if let Some(mut service) = services.write().await.get_mut(name) {
-                                if let Err(e) = service.restart().await {
-                                    error!("Failed to restart service {}: {}", name, e);
-                                }
-                            }
+        let mut restarts = restarts.write().await;
+        for name in &recovered {
+            restarts.remove(name);
+        }
 
-                            return;
-                        }
+        for name in down {
+            let backoff = restarts.entry(name.clone()).or_default();
+            let now = Instant::now();
+            if backoff.next_attempt.is_some_and(|at| now < at) {
+                debug!("Service {} is down, waiting to restart it again", name);
+                continue;
+            }
+            if backoff.attempts >= config.max_restart_attempts {
+                if !backoff.gave_up {
+                    backoff.gave_up = true;
+                    let message = format!(
+                        "Service {} still down after {} restarts, no longer restarting it",
+                        name, backoff.attempts
+                    );
+                    error!("{}", message);
+                    let alert = Alert::new(AlertSeverity::Critical, &name, message.clone());
+                    let _ = alert_tx.send(AlertUpdate::Raised(alert)).await;
+                    if config.safe_mode && safe_mode.borrow().is_none() {
+                        error!("Entering safe mode");
+                        safe_mode.send_replace(Some(SafeMode {
+                            service: name.clone(),
+                            reason: message,
+                            entered_at: SystemTime::now(),
+                        }));
                     }
                 }
+                continue;
+            }
+
+            backoff.attempts += 1;
+            let delay =
+                restart_backoff(Duration::from_secs(config.restart_delay), backoff.attempts);
+            backoff.next_attempt = Some(now + delay);
+            warn!(
+                "Service {} exceeded failure threshold, restarting (attempt {} of {})",
+                name, backoff.attempts, config.max_restart_attempts
+            );
+
+            let result = match services.write().await.get_mut(&name) {
+                Some(service) => service.restart().await,
+                // Unregistered since the check
+                None => continue,
+            };
+
+            if result.is_ok() {
+                if let Some(health) = health_status.write().await.get_mut(&name) {
+                    health.error_count = 0;
+                    health.restart_count += 1;
+                }
+            }
+
+            if let Err(e) = result {
+                error!("Failed to restart service {}: {}", name, e);
+                let alert = Alert::new(
+                    AlertSeverity::Error,
+                    &name,
+                    format!("Failed to restart service {}: {}", name, e),
+                );
+                let _ = alert_tx.send(AlertUpdate::Raised(alert)).await;
             }
         }
     }
 
     async fn start_alert_processor(&self) {
         let alerts = self.alerts.clone();
+        let events = self.events.clone();
+        let notifiers = Arc::new(Notifiers::new(self.config.read().await.notifiers.clone()));
         let mut alert_rx = self.alert_rx.write().await;
 
         tokio::spawn(async move {
-            while let Some(alert) = alert_rx.recv().await {
+            while let Some(update) = alert_rx.recv().await {
+                let (alert, recovered) = match update {
+                    AlertUpdate::Raised(alert) => (alert, false),
+                    AlertUpdate::Recovered(alert) => (alert, true),
+                };
                 match alert.severity {
                     AlertSeverity::Info => info!("[ALERT] {}: {}", alert.module, alert.message),
                     AlertSeverity::Warning => warn!("[ALERT] {}: {}", alert.module, alert.message),
@@ -241,7 +433,27 @@ impl Monitor {
                     }
                 }
 
-                alerts.write().await.push(alert);
+                let mut stored = alerts.write().await;
+                let mut changed = if recovered {
+                    resolve_module(&mut stored, &alert.module)
+                } else {
+                    Vec::new()
+                };
+                let alert = record_alert(&mut stored, alert);
+                drop(stored);
+                changed.push(alert.clone());
+
+                if let Some(events) = &events {
+                    for alert in changed {
+                        events.publish(AppEvent::Alert(alert));
+                    }
+                }
+                // Slow or unreachable channels must not hold up alerts;
+                // acknowledged alerts are not sent again
+                if !notifiers.is_empty() && alert.acknowledged_by.is_none() {
+                    let notifiers = notifiers.clone();
+                    tokio::spawn(async move { notifiers.notify(&alert).await });
+                }
             }
         });
     }
@@ -292,11 +504,67 @@ impl Monitor {
                     });
                 }
 
-                metrics.write().await.extend(current_metrics);
+                let mut metrics = metrics.write().await;
+                for metric in current_metrics {
+                    metrics.push(metric).await;
+                }
             }
         });
     }
 
+    /// Report an event from outside the health checks, such as a ban
+    pub async fn raise_alert(
+        &self,
+        severity: AlertSeverity,
+        module: &str,
+        message: String,
+        resolved: bool,
+    ) {
+        let alert = Alert {
+            resolved,
+            ..Alert::new(severity, module, message)
+        };
+        let _ = self.alert_tx.send(AlertUpdate::Raised(alert)).await;
+    }
+
+    /// Resolve the open alerts of `module` raised with [`Self::raise_alert`]
+    /// once the condition is gone
+    pub async fn recover(&self, module: &str, message: String) {
+        let alert = Alert {
+            resolved: true,
+            ..Alert::new(AlertSeverity::Info, module, message)
+        };
+        let _ = self.alert_tx.send(AlertUpdate::Recovered(alert)).await;
+    }
+
+    /// Mark an alert as seen. It stays open, and repeats are still
+    /// counted, but no longer sent to the notifiers.
+    pub async fn acknowledge_alert(&self, id: &str, by: &str) -> Result<Alert> {
+        self.update_alert(id, |alert| alert.acknowledged_by = Some(by.to_string()))
+            .await
+    }
+
+    /// Close an alert; its next occurrence opens a new one
+    pub async fn resolve_alert(&self, id: &str) -> Result<Alert> {
+        self.update_alert(id, |alert| alert.resolved = true).await
+    }
+
+    async fn update_alert(&self, id: &str, update: impl FnOnce(&mut Alert)) -> Result<Alert> {
+        let alert = {
+            let mut alerts = self.alerts.write().await;
+            let alert = alerts
+                .iter_mut()
+                .find(|alert| alert.id == id)
+                .ok_or_else(|| MonitoringError::AlertNotFound(id.to_string()))?;
+            update(alert);
+            alert.clone()
+        };
+        if let Some(events) = &self.events {
+            events.publish(AppEvent::Alert(alert.clone()));
+        }
+        Ok(alert)
+    }
+
     pub async fn get_alerts(&self, resolved: Option<bool>) -> Vec<Alert> {
         let alerts = self.alerts.read().await;
 
@@ -310,28 +578,68 @@ impl Monitor {
         self.health_status.read().await.clone()
     }
 
-    pub async fn get_metrics(&self) -> Vec<Metric> {
-        self.metrics.read().await.clone()
+    pub async fn record_metric(&self, metric: Metric) {
+        self.metrics.write().await.push(metric).await;
     }
 
-    pub async fn get_prometheus_metrics(&self) -> String {
+    /// A new sample of a counter read from elsewhere. History keeps every
+    /// sample, so this is `record_metric`; it stays for the callers that
+    /// only care about the current value.
+    pub async fn update_metric(&self, metric: Metric) {
+        self.record_metric(metric).await;
+    }
+
+    /// Latest sample of every series
+    pub async fn get_metrics(&self) -> Vec<Metric> {
         let metrics = self.metrics.read().await;
-        let mut output = String::new();
+        metrics.latest().into_iter().cloned().collect()
+    }
 
-        for metric in metrics.iter() {
-            output.push_str(&format!("# TYPE {} gauge\n", metric.name));
+    /// Samples of one metric over time, for history graphs
+    pub async fn get_metric_history(&self, query: &MetricQuery) -> Vec<Metric> {
+        self.metrics.read().await.range(query)
+    }
 
-            let labels = metric
+    /// Latest sample of every series in the Prometheus text exposition
+    /// format, one family per metric name
+    pub async fn get_prometheus_metrics(&self) -> String {
+        let metrics = self.metrics.read().await;
+        let mut families: BTreeMap<String, BTreeMap<Vec<(String, String)>, f64>> = BTreeMap::new();
+        for metric in metrics.latest() {
+            let mut labels: Vec<(String, String)> = metric
                 .labels
                 .iter()
-                .map(|(k, v)| format!("{}=\"{}\"", k, v))
-                .collect::<Vec<_>>()
-                .join(",");
+                .map(|(k, v)| (prometheus_name(k), escape_label(v)))
+                .collect();
+            labels.sort();
+            families
+                .entry(prometheus_name(&metric.name))
+                .or_default()
+                .insert(labels, metric.value);
+        }
 
-            if labels.is_empty() {
-                output.push_str(&format!("{} {}\n", metric.name, metric.value));
+        let mut output = String::new();
+        for (name, series) in families {
+            // Prometheus convention: counters end in `_total`
+            let kind = if name.ends_with("_total") {
+                "counter"
             } else {
-                output.push_str(&format!("{}{{{}}} {}\n", metric.name, labels, metric.value));
+                "gauge"
+            };
+            output.push_str(&format!("# TYPE {} {}\n", name, kind));
+
+            for (labels, value) in series {
+                let labels = labels
+                    .iter()
+                    .map(|(k, v)| format!("{}=\"{}\"", k, v))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let value = prometheus_value(value);
+                if labels.is_empty() {
+                    output.push_str(&format!("{} {}\n", name, value));
+                } else {
+                    output.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+                }
             }
         }
 
@@ -347,6 +655,78 @@ impl Monitor {
     }
 }
 
+/// Wait after restart `attempt` before the next one: `delay` after the
+/// first, doubled for each one after that
+fn restart_backoff(delay: Duration, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    delay.saturating_mul(factor).min(MAX_RESTART_BACKOFF)
+}
+
+/// Count `alert` on the open alert with the same module and message, or
+/// add it. Returns the alert as stored.
+fn record_alert(alerts: &mut Vec<Alert>, alert: Alert) -> Alert {
+    let open = alerts.iter_mut().find(|open| {
+        !alert.resolved
+            && !open.resolved
+            && open.module == alert.module
+            && open.message == alert.message
+    });
+    match open {
+        Some(open) => {
+            open.occurrences += 1;
+            open.timestamp = alert.timestamp;
+            // A failing check escalates from warning to critical
+            open.severity = alert.severity;
+            open.clone()
+        }
+        None => {
+            alerts.push(alert.clone());
+            alert
+        }
+    }
+}
+
+/// Resolve the open alerts of `module`, returning them
+fn resolve_module(alerts: &mut [Alert], module: &str) -> Vec<Alert> {
+    alerts
+        .iter_mut()
+        .filter(|alert| !alert.resolved && alert.module == module)
+        .map(|alert| {
+            alert.resolved = true;
+            alert.clone()
+        })
+        .collect()
+}
+
+/// Metric or label name with characters Prometheus does not allow
+/// replaced by `_`
+fn prometheus_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn prometheus_value(value: f64) -> String {
+    match value {
+        v if v.is_nan() => "NaN".to_string(),
+        v if v == f64::INFINITY => "+Inf".to_string(),
+        v if v == f64::NEG_INFINITY => "-Inf".to_string(),
+        v => v.to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -377,6 +757,42 @@ mod tests {
         }
     }
 
+    /// Fails every check, restarts do not help
+    struct BrokenService;
+
+    impl Monitorable for BrokenService {
+        fn name(&self) -> &str {
+            "broken"
+        }
+
+        async fn health_check(&self) -> Result<()> {
+            Err(MonitoringError::HealthCheckFailed(
+                "still broken".to_string(),
+            ))
+        }
+
+        async fn restart(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Never answers a check, and cannot be restarted
+    struct HungService;
+
+    impl Monitorable for HungService {
+        fn name(&self) -> &str {
+            "hung"
+        }
+
+        async fn health_check(&self) -> Result<()> {
+            std::future::pending().await
+        }
+
+        async fn restart(&mut self) -> Result<()> {
+            Err(MonitoringError::RecoveryFailed("no way back".to_string()).into())
+        }
+    }
+
     #[tokio::test]
     async fn test_monitor_creation() {
         let config = Arc::new(RwLock::new(MonitoringConfig::default()));
@@ -404,17 +820,184 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_alert_creation() {
-        let alert = Alert {
-            id: "test-id".to_string(),
-            severity: AlertSeverity::Warning,
-            module: "test".to_string(),
-            message: "Test alert".to_string(),
+    async fn test_restart_backoff() {
+        let config = Arc::new(RwLock::new(MonitoringConfig {
+            max_failures: 1,
+            max_restart_attempts: 2,
+            restart_delay: 60,
+            ..MonitoringConfig::default()
+        }));
+        let monitor = Monitor::new(config.clone());
+        monitor.register_service(Box::new(BrokenService)).await;
+        let check = || {
+            Monitor::check_all_services(
+                &monitor.services,
+                &monitor.health_status,
+                &monitor.restarts,
+                &monitor.safe_mode,
+                &monitor.alert_tx,
+                &config,
+            )
+        };
+        let restarts = || async { monitor.get_health_status().await["broken"].restart_count };
+        let skip_backoff = || async {
+            let mut backoffs = monitor.restarts.write().await;
+            backoffs.get_mut("broken").unwrap().next_attempt = None;
+        };
+
+        check().await;
+        check().await;
+        assert_eq!(restarts().await, 1, "second restart waits restart_delay");
+
+        skip_backoff().await;
+        check().await;
+        assert_eq!(restarts().await, 2);
+
+        skip_backoff().await;
+        check().await;
+        assert_eq!(restarts().await, 2, "gave up after max_restart_attempts");
+        assert!(monitor.restarts.read().await["broken"].gave_up);
+        assert_eq!(monitor.safe_mode().unwrap().service, "broken");
+
+        assert!(monitor.leave_safe_mode().await.is_some());
+        assert!(monitor.safe_mode().is_none());
+        assert!(monitor.restarts.read().await.is_empty());
+
+        let delay = Duration::from_secs(5);
+        assert_eq!(restart_backoff(delay, 1), delay);
+        assert_eq!(restart_backoff(delay, 3), Duration::from_secs(20));
+        assert_eq!(restart_backoff(delay, 40), MAX_RESTART_BACKOFF);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hung_check_times_out() {
+        let config = Arc::new(RwLock::new(MonitoringConfig {
+            max_failures: 1,
+            ..MonitoringConfig::default()
+        }));
+        let monitor = Monitor::new(config.clone());
+        monitor.register_service(Box::new(HungService)).await;
+
+        let check = Monitor::check_all_services(
+            &monitor.services,
+            &monitor.health_status,
+            &monitor.restarts,
+            &monitor.safe_mode,
+            &monitor.alert_tx,
+            &config,
+        );
+        tokio::pin!(check);
+        tokio::select! {
+            _ = &mut check => panic!("check finished before the timeout"),
+            // Readers are not blocked while the check hangs
+            status = monitor.get_health_status() => assert!(status["hung"].healthy),
+        }
+        check.await;
+
+        let health = &monitor.get_health_status().await["hung"];
+        assert!(!health.healthy);
+        assert_eq!(health.error_count, 1, "failed restarts reset nothing");
+        assert_eq!(health.restart_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_update_metric() {
+        let config = Arc::new(RwLock::new(MonitoringConfig::default()));
+        let monitor = Monitor::new(config);
+        let metric = |value| Metric {
+            name: "network_receive_bytes_total".to_string(),
+            value,
+            unit: "bytes".to_string(),
             timestamp: SystemTime::now(),
-            resolved: false,
+            labels: [("interface".to_string(), "eth0".to_string())].into(),
+        };
+
+        monitor.update_metric(metric(100.0)).await;
+        monitor.update_metric(metric(250.0)).await;
+
+        let metrics = monitor.get_metrics().await;
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].value, 250.0);
+        assert!(monitor
+            .get_prometheus_metrics()
+            .await
+            .starts_with("# TYPE network_receive_bytes_total counter\n"));
+    }
+
+    #[tokio::test]
+    async fn test_exposition_format() {
+        let config = Arc::new(RwLock::new(MonitoringConfig::default()));
+        let monitor = Monitor::new(config);
+        let metric = |service: &str, value| Metric {
+            name: "service_healthy".to_string(),
+            value,
+            unit: "boolean".to_string(),
+            timestamp: SystemTime::now(),
+            labels: [("service".to_string(), service.to_string())].into(),
         };
 
+        // Collected every minute; only the latest sample of a series counts
+        monitor.record_metric(metric("network", 1.0)).await;
+        monitor.record_metric(metric("ui", 1.0)).await;
+        monitor.record_metric(metric("network", 0.0)).await;
+        monitor.record_metric(metric("say \"hi\"", 1.0)).await;
+
+        assert_eq!(
+            monitor.get_prometheus_metrics().await,
+            "# TYPE service_healthy gauge\n\
+             service_healthy{service=\"network\"} 0\n\
+             service_healthy{service=\"say \\\"hi\\\"\"} 1\n\
+             service_healthy{service=\"ui\"} 1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_creation() {
+        let alert = Alert::new(AlertSeverity::Warning, "test", "Test alert".to_string());
+
         assert_eq!(alert.severity, AlertSeverity::Warning);
+        assert_eq!(alert.occurrences, 1);
         assert!(!alert.resolved);
     }
+
+    #[test]
+    fn test_repeats_are_counted() {
+        let mut alerts = Vec::new();
+        let failed = |module: &str, severity| {
+            Alert::new(severity, module, "Health check failed".to_string())
+        };
+
+        let first = record_alert(&mut alerts, failed("network", AlertSeverity::Warning));
+        let again = record_alert(&mut alerts, failed("network", AlertSeverity::Critical));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(again.id, first.id);
+        assert_eq!(again.occurrences, 2);
+        assert_eq!(again.severity, AlertSeverity::Critical);
+
+        record_alert(&mut alerts, failed("ui", AlertSeverity::Warning));
+        let resolved = resolve_module(&mut alerts, "network");
+        assert_eq!(resolved.len(), 1);
+        assert!(!alerts[1].resolved);
+
+        // Once resolved, the next failure is a new alert
+        let next = record_alert(&mut alerts, failed("network", AlertSeverity::Warning));
+        assert_ne!(next.id, first.id);
+        assert_eq!(next.occurrences, 1);
+    }
+
+    #[tokio::test]
+    async fn test_acknowledge_and_resolve() {
+        let config = Arc::new(RwLock::new(MonitoringConfig::default()));
+        let monitor = Monitor::new(config);
+        let alert = Alert::new(AlertSeverity::Error, "iso", "Mount failed".to_string());
+        monitor.alerts.write().await.push(alert.clone());
+
+        let acknowledged = monitor.acknowledge_alert(&alert.id, "admin").await.unwrap();
+        assert_eq!(acknowledged.acknowledged_by.as_deref(), Some("admin"));
+        assert_eq!(monitor.get_alerts(Some(false)).await.len(), 1);
+
+        monitor.resolve_alert(&alert.id).await.unwrap();
+        assert!(monitor.get_alerts(Some(false)).await.is_empty());
+        assert!(monitor.resolve_alert("missing").await.is_err());
+    }
 }
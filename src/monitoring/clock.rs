@@ -0,0 +1,234 @@
+use crate::config::ClockConfig;
+use crate::error::{MonitoringError, Result};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::process::Command;
+use tracing::debug;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch
+const NTP_UNIX_OFFSET: f64 = 2_208_988_800.0;
+const NTP_PORT: u16 = 123;
+/// Leap indicator 0, version 4, client mode
+const NTP_CLIENT_REQUEST: u8 = 0x23;
+const NTP_SERVER_MODE: u8 = 4;
+
+/// How far the system clock is off
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockSkew {
+    /// Positive when the system clock is ahead
+    pub skew_secs: f64,
+    /// NTP server or controller URL the time came from
+    pub source: String,
+}
+
+/// Compares the system clock with NTP servers, falling back to the `Date`
+/// header of the fleet controller for nodes whose network blocks NTP
+#[derive(Debug, Clone)]
+pub struct ClockCheck {
+    config: ClockConfig,
+    controller: Option<String>,
+}
+
+impl ClockCheck {
+    pub fn new(config: ClockConfig) -> Self {
+        Self {
+            config,
+            controller: None,
+        }
+    }
+
+    /// URL whose `Date` header is used when no NTP server answers, with
+    /// `use_controller`
+    pub fn with_controller(mut self, url: String) -> Self {
+        if self.config.use_controller {
+            self.controller = Some(url);
+        }
+        self
+    }
+
+    /// Skew beyond `max_skew_secs` in either direction
+    pub fn is_skewed(&self, skew: &ClockSkew) -> bool {
+        skew.skew_secs.abs() > self.config.max_skew_secs as f64
+    }
+
+    /// Skew against the first NTP server that answers, or the controller
+    pub async fn measure(&self) -> Result<ClockSkew> {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let mut errors = Vec::new();
+        for server in &self.config.servers {
+            match tokio::time::timeout(timeout, ntp_skew(server)).await {
+                Ok(Ok(skew_secs)) => {
+                    return Ok(ClockSkew {
+                        skew_secs,
+                        source: server.clone(),
+                    })
+                }
+                Ok(Err(e)) => errors.push(format!("{}: {}", server, e)),
+                Err(_) => errors.push(format!("{}: timed out", server)),
+            }
+        }
+
+        if let Some(url) = &self.controller {
+            match controller_skew(url, timeout).await {
+                Ok(skew_secs) => {
+                    return Ok(ClockSkew {
+                        skew_secs,
+                        source: url.clone(),
+                    })
+                }
+                Err(e) => errors.push(format!("{}: {}", url, e)),
+            }
+        }
+        debug!("No time source answered: {:?}", errors);
+        Err(MonitoringError::ClockCheckFailed(errors.join("; ")).into())
+    }
+}
+
+/// One SNTP exchange with `server`
+async fn ntp_skew(server: &str) -> std::io::Result<f64> {
+    let target = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:{}", server, NTP_PORT)
+    };
+    let address = lookup_host(target)
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::other("no address"))?;
+    let bind = if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(address).await?;
+
+    let mut request = [0u8; 48];
+    request[0] = NTP_CLIENT_REQUEST;
+    let sent = unix_secs(SystemTime::now());
+    socket.send(&request).await?;
+    let mut reply = [0u8; 48];
+    let len = socket.recv(&mut reply).await?;
+    let received = unix_secs(SystemTime::now());
+
+    skew_from_reply(&reply[..len], sent, received)
+        .ok_or_else(|| std::io::Error::other("invalid NTP reply"))
+}
+
+/// System clock minus server clock from an NTP reply, with the round trip
+/// split evenly between both directions
+fn skew_from_reply(reply: &[u8], sent: f64, received: f64) -> Option<f64> {
+    if reply.len() < 48 || reply[0] & 0x07 != NTP_SERVER_MODE {
+        return None;
+    }
+    // Stratum 0 is a kiss-o'-death telling the client to back off
+    if !(1..=15).contains(&reply[1]) {
+        return None;
+    }
+    let server_received = ntp_timestamp(&reply[32..40]);
+    let server_sent = ntp_timestamp(&reply[40..48]);
+    let offset = ((server_received - sent) + (server_sent - received)) / 2.0;
+    Some(-offset)
+}
+
+fn ntp_timestamp(bytes: &[u8]) -> f64 {
+    let seconds = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    seconds as f64 - NTP_UNIX_OFFSET + fraction as f64 / 2f64.powi(32)
+}
+
+/// Skew against the `Date` header of a HEAD request to `url`
+async fn controller_skew(url: &str, timeout: Duration) -> std::io::Result<f64> {
+    let sent = unix_secs(SystemTime::now());
+    let output = Command::new("curl")
+        .args(["-sSI", "--max-time"])
+        .arg(timeout.as_secs().to_string())
+        .arg(url)
+        .output()
+        .await?;
+    let received = unix_secs(SystemTime::now());
+    let date = http_date(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| std::io::Error::other("no Date header"))?;
+    // The header has whole seconds, set somewhere during the request
+    Ok((sent + received) / 2.0 - date)
+}
+
+/// Unix seconds of the `Date` header in a response head
+fn http_date(head: &str) -> Option<f64> {
+    let value = head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("date").then_some(value.trim())
+    })?;
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(date.timestamp() as f64)
+}
+
+/// Seconds since the Unix epoch, 0 while the clock is before it
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+fn unix_secs(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs_f64())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(server_received: f64, server_sent: f64) -> Vec<u8> {
+        let timestamp = |unix: f64| {
+            let ntp = unix + NTP_UNIX_OFFSET;
+            let seconds = ntp.trunc() as u32;
+            let fraction = (ntp.fract() * 2f64.powi(32)) as u32;
+            [seconds.to_be_bytes(), fraction.to_be_bytes()].concat()
+        };
+        let mut reply = vec![0u8; 32];
+        reply[0] = 0x24;
+        reply[1] = 2;
+        reply.extend(timestamp(server_received));
+        reply.extend(timestamp(server_sent));
+        reply
+    }
+
+    #[test]
+    fn test_skew_from_reply() {
+        // Local clock 40s ahead, 100ms each way, 10ms on the server
+        let sent = 1_760_000_040.0;
+        let reply = reply(1_760_000_000.1, 1_760_000_000.11);
+        let skew = skew_from_reply(&reply, sent, sent + 0.21).unwrap();
+        assert!((skew - 40.0).abs() < 0.001, "{}", skew);
+
+        let mut kiss_of_death = reply.clone();
+        kiss_of_death[1] = 0;
+        assert_eq!(skew_from_reply(&kiss_of_death, sent, sent), None);
+        assert_eq!(skew_from_reply(&reply[..40], sent, sent), None);
+    }
+
+    #[test]
+    fn test_http_date() {
+        let head = "HTTP/1.1 405 Method Not Allowed\r\n\
+                    content-type: text/plain\r\n\
+                    date: Thu, 16 Oct 2025 12:00:00 GMT\r\n\r\n";
+        assert_eq!(http_date(head), Some(1_760_616_000.0));
+        assert_eq!(http_date("HTTP/1.1 200 OK\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn test_is_skewed() {
+        let check = ClockCheck::new(ClockConfig::default());
+        let skew = |skew_secs| ClockSkew {
+            skew_secs,
+            source: "pool.ntp.org".to_string(),
+        };
+        assert!(!check.is_skewed(&skew(-3.0)));
+        assert!(check.is_skewed(&skew(-45.0)));
+        assert!(check.is_skewed(&skew(3600.0)));
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Monitor, ServiceHealth};
+use crate::error::{MonitoringError, Result};
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{oneshot, RwLock};
+use tracing::{error, info};
+
+/// Prometheus text exposition format
+const EXPOSITION_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// One monitored service as `/healthz` reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceProbe {
+    pub healthy: bool,
+    pub error_count: u32,
+    pub restart_count: u32,
+    pub uptime_secs: u64,
+}
+
+/// Healthy when every monitored service passed its last check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub services: BTreeMap<String, ServiceProbe>,
+}
+
+impl HealthReport {
+    pub fn from_status(status: &HashMap<String, ServiceHealth>) -> Self {
+        let services: BTreeMap<String, ServiceProbe> = status
+            .iter()
+            .map(|(name, health)| {
+                let probe = ServiceProbe {
+                    healthy: health.healthy,
+                    error_count: health.error_count,
+                    restart_count: health.restart_count,
+                    uptime_secs: health.uptime.as_secs(),
+                };
+                (name.clone(), probe)
+            })
+            .collect();
+        Self {
+            healthy: services.values().all(|probe| probe.healthy),
+            services,
+        }
+    }
+}
+
+/// HTTP listener on `monitoring.metrics_port` for Prometheus scrapes and
+/// Kubernetes-style probes. Unauthenticated, so it only exposes metrics
+/// and health.
+pub struct MetricsExporter {
+    port: Option<u16>,
+    monitor: Arc<RwLock<Monitor>>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl MetricsExporter {
+    pub fn new(port: Option<u16>, monitor: Arc<RwLock<Monitor>>) -> Self {
+        Self {
+            port,
+            monitor,
+            shutdown_tx: None,
+        }
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        let Some(port) = self.port else {
+            info!("Metrics endpoint disabled");
+            return Ok(());
+        };
+
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+            MonitoringError::MetricsError(format!("Failed to listen on {}: {}", addr, e))
+        })?;
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let app = router(self.monitor.clone());
+        tokio::spawn(async move {
+            let result = axum::serve(listener, app)
+                .with_graceful_shutdown(async {
+                    let _ = shutdown_rx.await;
+                })
+                .await;
+            if let Err(e) = result {
+                error!("Metrics endpoint failed: {}", e);
+            }
+        });
+
+        self.shutdown_tx = Some(shutdown_tx);
+        info!("Metrics endpoint listening on {}", addr);
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        if let Some(tx) = self.shutdown_tx.take() {
+            info!("Stopping metrics endpoint");
+            let _ = tx.send(());
+        }
+        Ok(())
+    }
+}
+
+pub fn router(monitor: Arc<RwLock<Monitor>>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .with_state(monitor)
+}
+
+async fn metrics(State(monitor): State<Arc<RwLock<Monitor>>>) -> impl IntoResponse {
+    let body = monitor.read().await.get_prometheus_metrics().await;
+    ([(header::CONTENT_TYPE, EXPOSITION_CONTENT_TYPE)], body)
+}
+
+/// 200 while every monitored service is healthy, 503 otherwise
+async fn healthz(State(monitor): State<Arc<RwLock<Monitor>>>) -> (StatusCode, Json<HealthReport>) {
+    let status = monitor.read().await.get_health_status().await;
+    let report = HealthReport::from_status(&status);
+    let code = if report.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MonitoringConfig;
+    use std::time::{Duration, Instant};
+    use tower::ServiceExt;
+
+    fn health(healthy: bool) -> ServiceHealth {
+        ServiceHealth {
+            name: "network".to_string(),
+            healthy,
+            uptime: Duration::from_secs(90),
+            last_check: Instant::now(),
+            error_count: if healthy { 0 } else { 2 },
+            restart_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_report_needs_every_service_healthy() {
+        let mut status = HashMap::new();
+        status.insert("network".to_string(), health(true));
+        assert!(HealthReport::from_status(&status).healthy);
+
+        status.insert("remote".to_string(), health(false));
+        let report = HealthReport::from_status(&status);
+        assert!(!report.healthy);
+        assert_eq!(report.services["remote"].error_count, 2);
+        assert_eq!(report.services["network"].uptime_secs, 90);
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() {
+        let monitor = Arc::new(RwLock::new(Monitor::new(Arc::new(RwLock::new(
+            MonitoringConfig::default(),
+        )))));
+        let app = router(monitor);
+        let request = |uri: &str| {
+            axum::http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("/metrics")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            EXPOSITION_CONTENT_TYPE
+        );
+
+        // Nothing registered yet is nothing unhealthy
+        let response = app.oneshot(request("/healthz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,548 @@
+use super::{Alert, AlertSeverity};
+use crate::error::{MonitoringError, Result};
+use crate::network::curl;
+use minijinja::{AutoEscape, Environment, UndefinedBehavior};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Seconds curl may take to deliver one notification
+const SEND_TIMEOUT_SECS: u64 = 30;
+
+/// One alert channel from `[[monitoring.notifiers]]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotifierConfig {
+    /// Named in the log when sending fails
+    pub name: String,
+    /// Severities routed here; every severity when empty
+    #[serde(default)]
+    pub severities: Vec<AlertSeverity>,
+    /// The same alert goes out at most once per this many seconds
+    #[serde(default = "default_repeat_secs")]
+    pub repeat_secs: u64,
+    #[serde(flatten)]
+    pub channel: NotifyChannel,
+}
+
+fn default_repeat_secs() -> u64 {
+    900
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifyChannel {
+    /// POST of `template` rendered with the alert, or of the alert as JSON
+    Webhook {
+        url: String,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    /// Mail through an `smtp://` or `smtps://` server; with credentials
+    /// `smtp://` requires STARTTLS
+    Email {
+        server: String,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    /// Slack incoming webhook
+    Slack { webhook_url: String },
+    /// Message to a Matrix room with a bot account's access token
+    Matrix {
+        homeserver: String,
+        room_id: String,
+        access_token: String,
+    },
+}
+
+/// What a webhook template sees
+#[derive(Debug, Serialize)]
+struct AlertContext<'a> {
+    id: &'a str,
+    node: &'a str,
+    severity: AlertSeverity,
+    module: &'a str,
+    message: &'a str,
+    /// Unix seconds
+    timestamp: u64,
+    resolved: bool,
+    /// Times the alert occurred while open
+    occurrences: u32,
+    /// Times the alert was held back since it was last sent
+    repeats: u32,
+}
+
+/// Last time a notifier sent an alert, and how often it was held back since
+#[derive(Debug)]
+struct Repeat {
+    sent: Instant,
+    suppressed: u32,
+}
+
+/// Sends alerts to the configured notifiers, routed by severity. Repeats
+/// of an alert (same module and message) within a notifier's
+/// `repeat_secs` are counted instead of sent.
+pub struct Notifiers {
+    notifiers: Vec<NotifierConfig>,
+    node: String,
+    repeats: Mutex<HashMap<(usize, String, String), Repeat>>,
+}
+
+impl Notifiers {
+    pub fn new(notifiers: Vec<NotifierConfig>) -> Self {
+        let node = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default();
+        Self {
+            notifiers,
+            node,
+            repeats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notifiers.is_empty()
+    }
+
+    /// Send `alert` everywhere it is routed; failures are logged
+    pub async fn notify(&self, alert: &Alert) {
+        for (index, notifier) in self.notifiers.iter().enumerate() {
+            let routed =
+                notifier.severities.is_empty() || notifier.severities.contains(&alert.severity);
+            if !routed {
+                continue;
+            }
+            let Some(repeats) = self.due(index, notifier, alert, Instant::now()).await else {
+                debug!("Holding back repeated alert for {}", notifier.name);
+                continue;
+            };
+            if let Err(e) = self.send(&notifier.channel, alert, repeats).await {
+                warn!("Failed to notify {}: {}", notifier.name, e);
+            }
+        }
+    }
+
+    /// Times the alert was held back when it is due again, `None` while
+    /// it is still within `repeat_secs` of the last send
+    async fn due(
+        &self,
+        index: usize,
+        notifier: &NotifierConfig,
+        alert: &Alert,
+        now: Instant,
+    ) -> Option<u32> {
+        let key = (index, alert.module.clone(), alert.message.clone());
+        let window = Duration::from_secs(notifier.repeat_secs);
+        let mut repeats = self.repeats.lock().await;
+        // Held-back counts are kept until the alert is sent again
+        repeats.retain(|(notifier, ..), repeat| {
+            *notifier != index || repeat.suppressed > 0 || now.duration_since(repeat.sent) < window
+        });
+        match repeats.get_mut(&key) {
+            Some(repeat) if now.duration_since(repeat.sent) < window => {
+                repeat.suppressed += 1;
+                None
+            }
+            Some(repeat) => {
+                let suppressed = repeat.suppressed;
+                *repeat = Repeat {
+                    sent: now,
+                    suppressed: 0,
+                };
+                Some(suppressed)
+            }
+            None => {
+                repeats.insert(
+                    key,
+                    Repeat {
+                        sent: now,
+                        suppressed: 0,
+                    },
+                );
+                Some(0)
+            }
+        }
+    }
+
+    async fn send(&self, channel: &NotifyChannel, alert: &Alert, repeats: u32) -> Result<()> {
+        let context = self.context(alert, repeats);
+        match channel {
+            NotifyChannel::Webhook { url, template } => {
+                let body = match template {
+                    Some(template) => render(template, &context)?,
+                    None => serde_json::to_string(&context).map_err(alert_error)?,
+                };
+                post(url, &[], &body).await
+            }
+            NotifyChannel::Slack { webhook_url } => {
+                let body = serde_json::json!({ "text": summary(&context) });
+                post(webhook_url, &[], &body.to_string()).await
+            }
+            NotifyChannel::Matrix {
+                homeserver,
+                room_id,
+                access_token,
+            } => {
+                let url = format!(
+                    "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+                    homeserver.trim_end_matches('/'),
+                    percent_encode(room_id),
+                    alert.id
+                );
+                let body = serde_json::json!({ "msgtype": "m.text", "body": summary(&context) });
+                let auth = format!("Authorization: Bearer {}", access_token);
+                put(&url, &[&auth], &body.to_string()).await
+            }
+            NotifyChannel::Email {
+                server,
+                username,
+                password,
+                from,
+                to,
+            } => {
+                let subject = summary(&context);
+                let message = mail(from, to, &subject, "text/plain", &subject);
+                send_mail(
+                    server,
+                    username.as_deref(),
+                    password.as_deref(),
+                    from,
+                    to,
+                    &message,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Mail an HTML report to the email notifiers, or only to those named
+    /// in `only`; failures are logged and an error means none got it
+    pub async fn mail_report(&self, only: &[String], subject: &str, html: &str) -> Result<()> {
+        let mut sent = 0;
+        for notifier in self.mail_notifiers(only) {
+            let NotifyChannel::Email {
+                server,
+                username,
+                password,
+                from,
+                to,
+            } = &notifier.channel
+            else {
+                continue;
+            };
+            let message = mail(from, to, subject, "text/html", html);
+            match send_mail(
+                server,
+                username.as_deref(),
+                password.as_deref(),
+                from,
+                to,
+                &message,
+            )
+            .await
+            {
+                Ok(()) => sent += 1,
+                Err(e) => warn!("Failed to mail report to {}: {}", notifier.name, e),
+            }
+        }
+        if sent == 0 {
+            return Err(alert_error("report not delivered to any email notifier"));
+        }
+        Ok(())
+    }
+
+    fn mail_notifiers<'a>(
+        &'a self,
+        only: &'a [String],
+    ) -> impl Iterator<Item = &'a NotifierConfig> {
+        self.notifiers.iter().filter(move |notifier| {
+            matches!(notifier.channel, NotifyChannel::Email { .. })
+                && (only.is_empty() || only.contains(&notifier.name))
+        })
+    }
+
+    fn context<'a>(&'a self, alert: &'a Alert, repeats: u32) -> AlertContext<'a> {
+        AlertContext {
+            id: &alert.id,
+            node: &self.node,
+            severity: alert.severity,
+            module: &alert.module,
+            message: &alert.message,
+            timestamp: alert
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default(),
+            resolved: alert.resolved,
+            occurrences: alert.occurrences,
+            repeats,
+        }
+    }
+}
+
+/// One line for chat channels and mail subjects
+fn summary(context: &AlertContext) -> String {
+    let mut line = format!(
+        "[{:?}] {} {}: {}",
+        context.severity, context.node, context.module, context.message
+    );
+    if context.repeats > 0 {
+        line.push_str(&format!(" (repeated {} times)", context.repeats));
+    }
+    line
+}
+
+fn mail(from: &str, to: &[String], subject: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\
+         Content-Type: {}; charset=utf-8\r\n\r\n{}\r\n",
+        from,
+        to.join(", "),
+        subject.replace(['\r', '\n'], " "),
+        chrono::Utc::now().to_rfc2822(),
+        content_type,
+        body
+    )
+}
+
+/// Deliver `message` through an SMTP server. stdin carries the
+/// credentials, so the message goes through a file.
+async fn send_mail(
+    server: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+    from: &str,
+    to: &[String],
+    message: &str,
+) -> Result<()> {
+    let path =
+        std::env::temp_dir().join(format!("usb-installer-mail-{}.eml", uuid::Uuid::new_v4()));
+    tokio::fs::write(&path, message).await?;
+
+    let mut cmd = Command::new("curl");
+    cmd.args(["-fsS", "--max-time"])
+        .arg(SEND_TIMEOUT_SECS.to_string())
+        .args(["--url", server, "--mail-from", from]);
+    for recipient in to {
+        cmd.args(["--mail-rcpt", recipient]);
+    }
+    let mut input = String::new();
+    if let Some(username) = username {
+        // Never send the password in the clear
+        cmd.arg("--ssl-reqd");
+        input.push_str(&curl::config_line(
+            "user",
+            &format!("{}:{}", username, password.unwrap_or_default()),
+        ));
+    }
+    cmd.arg("--upload-file").arg(&path).args(["--config", "-"]);
+    let result = run(cmd, input).await;
+    let _ = tokio::fs::remove_file(&path).await;
+    result
+}
+
+/// Render a webhook body. Values are inserted as they are; the `json`
+/// filter quotes them for JSON bodies.
+fn render(template: &str, context: &AlertContext) -> Result<String> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_auto_escape_callback(|_| AutoEscape::None);
+    env.add_filter("json", json);
+    env.render_str(template, context).map_err(alert_error)
+}
+
+fn json(value: minijinja::Value) -> String {
+    serde_json::to_string(&value).unwrap_or_default()
+}
+
+/// Matrix room IDs and aliases start with `!` or `#` and contain `:`
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+async fn post(url: &str, headers: &[&str], body: &str) -> Result<()> {
+    request("POST", url, headers, body).await
+}
+
+async fn put(url: &str, headers: &[&str], body: &str) -> Result<()> {
+    request("PUT", url, headers, body).await
+}
+
+/// Headers, which may carry tokens, and the body go to curl on stdin
+async fn request(method: &str, url: &str, headers: &[&str], body: &str) -> Result<()> {
+    let mut input = String::new();
+    for header in headers {
+        input.push_str(&curl::config_line("header", header));
+    }
+    input.push_str(&curl::config_line("data-raw", body));
+
+    let mut cmd = Command::new("curl");
+    cmd.args(["-fsS", "--max-time"])
+        .arg(SEND_TIMEOUT_SECS.to_string())
+        .args(["-X", method, "-H", "Content-Type: application/json"])
+        .args(["--config", "-", url]);
+    run(cmd, input).await
+}
+
+/// Run curl, feeding it the config lines in `input`; anything but success
+/// is an error with curl's message
+async fn run(mut cmd: Command, input: String) -> Result<()> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| MonitoringError::AlertError(format!("Failed to run curl: {}", e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(MonitoringError::AlertError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn alert_error(e: impl std::fmt::Display) -> crate::error::Error {
+    MonitoringError::AlertError(e.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(severity: AlertSeverity, message: &str) -> Alert {
+        Alert::new(severity, "network", message.to_string())
+    }
+
+    fn notifier(repeat_secs: u64) -> NotifierConfig {
+        NotifierConfig {
+            name: "ops".to_string(),
+            severities: vec![AlertSeverity::Critical],
+            repeat_secs,
+            channel: NotifyChannel::Slack {
+                webhook_url: "https://hooks.example/1".to_string(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repeats_are_held_back() {
+        let notifiers = Notifiers::new(vec![notifier(600)]);
+        let config = &notifiers.notifiers[0];
+        let down = alert(AlertSeverity::Critical, "Health check failed: link down");
+        let start = Instant::now();
+
+        assert_eq!(notifiers.due(0, config, &down, start).await, Some(0));
+        let later = start + Duration::from_secs(60);
+        assert_eq!(notifiers.due(0, config, &down, later).await, None);
+        assert_eq!(notifiers.due(0, config, &down, later).await, None);
+        // A different message is a different alert
+        let other = alert(
+            AlertSeverity::Critical,
+            "Health check failed: no DHCP lease",
+        );
+        assert_eq!(notifiers.due(0, config, &other, later).await, Some(0));
+
+        let after = start + Duration::from_secs(601);
+        assert_eq!(notifiers.due(0, config, &down, after).await, Some(2));
+    }
+
+    #[test]
+    fn test_config() {
+        let config: NotifierConfig = toml::from_str(
+            r#"
+            name = "matrix"
+            type = "matrix"
+            severities = ["error", "critical"]
+            homeserver = "https://matrix.example"
+            room_id = "!ops:matrix.example"
+            access_token = "syt_token"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.repeat_secs, 900);
+        assert_eq!(
+            config.severities,
+            vec![AlertSeverity::Error, AlertSeverity::Critical]
+        );
+        assert_eq!(
+            percent_encode("!ops:matrix.example"),
+            "%21ops%3Amatrix.example"
+        );
+    }
+
+    #[test]
+    fn test_webhook_template() {
+        let notifiers = Notifiers::new(Vec::new());
+        let down = alert(AlertSeverity::Warning, "say \"hi\"");
+        let context = notifiers.context(&down, 3);
+
+        let body = render(
+            r#"{"title": {{ module|json }}, "text": {{ message|json }}, "count": {{ repeats }}}"#,
+            &context,
+        )
+        .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["text"], "say \"hi\"");
+        assert_eq!(body["count"], 3);
+
+        assert!(render("{{ nonexistent }}", &context).is_err());
+    }
+
+    #[test]
+    fn test_report_recipients() {
+        let email = |name: &str| NotifierConfig {
+            name: name.to_string(),
+            severities: Vec::new(),
+            repeat_secs: 900,
+            channel: NotifyChannel::Email {
+                server: "smtps://mail.example".to_string(),
+                username: None,
+                password: None,
+                from: "node@example.com".to_string(),
+                to: vec!["ops@example.com".to_string()],
+            },
+        };
+        let notifiers = Notifiers::new(vec![notifier(600), email("ops"), email("leads")]);
+
+        let names = |only: &[String]| -> Vec<String> {
+            notifiers
+                .mail_notifiers(only)
+                .map(|notifier| notifier.name.clone())
+                .collect()
+        };
+        assert_eq!(names(&[]), vec!["ops", "leads"]);
+        assert_eq!(names(&["leads".to_string()]), vec!["leads"]);
+
+        let message = mail(
+            "node@example.com",
+            &["a@example.com".to_string(), "b@example.com".to_string()],
+            "Shift\r\nBcc: x@example.com",
+            "text/html",
+            "<p>report</p>",
+        );
+        assert!(message.contains("Subject: Shift  Bcc: x@example.com\r\n"));
+        assert!(message.contains("To: a@example.com, b@example.com\r\n"));
+        assert!(message.contains("Content-Type: text/html; charset=utf-8\r\n\r\n<p>report</p>"));
+    }
+}
@@ -0,0 +1,288 @@
+use super::Metric;
+use crate::config::MetricsHistoryConfig;
+use crate::error::{MonitoringError, Result};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// Samples of one metric in a time range
+#[derive(Debug, Default, Deserialize)]
+pub struct MetricQuery {
+    pub name: String,
+    /// Unix seconds
+    pub since: Option<u64>,
+    /// Unix seconds, exclusive
+    pub until: Option<u64>,
+    /// Only samples with these labels, as `key=value,key=value`
+    pub labels: Option<String>,
+}
+
+impl MetricQuery {
+    fn label_filter(&self) -> Vec<(&str, &str)> {
+        self.labels
+            .iter()
+            .flat_map(|labels| labels.split(','))
+            .filter_map(|pair| pair.split_once('='))
+            .collect()
+    }
+}
+
+/// Metric samples in arrival order, bounded by count and age. With a
+/// path the samples are also appended to a JSON lines file and read back
+/// on start; the file is rewritten once it holds more expired samples
+/// than live ones.
+#[derive(Debug)]
+pub struct MetricStore {
+    config: MetricsHistoryConfig,
+    samples: VecDeque<Metric>,
+    /// Lines in the file, live and expired
+    persisted: usize,
+}
+
+impl MetricStore {
+    pub fn new(config: MetricsHistoryConfig) -> Self {
+        Self {
+            config,
+            samples: VecDeque::new(),
+            persisted: 0,
+        }
+    }
+
+    /// Switch to `config` and add the unexpired samples from its file
+    /// before the ones recorded so far
+    pub async fn open(&mut self, config: MetricsHistoryConfig) {
+        self.config = config;
+        let Some(path) = self.config.path.clone() else {
+            self.prune(SystemTime::now());
+            return;
+        };
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                warn!("Cannot read metrics history {}: {}", path.display(), e);
+                String::new()
+            }
+        };
+        let mut loaded: VecDeque<Metric> = content
+            .lines()
+            // A crash mid-write leaves a truncated last line
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        loaded.extend(self.samples.drain(..));
+        self.samples = loaded;
+        self.prune(SystemTime::now());
+        info!(
+            "Loaded {} metric samples from {}",
+            self.samples.len(),
+            path.display()
+        );
+        if let Err(e) = self.compact().await {
+            warn!("Cannot rewrite metrics history: {}", e);
+        }
+    }
+
+    pub async fn push(&mut self, metric: Metric) {
+        if let Err(e) = self.append(&metric).await {
+            warn!("Cannot persist metric {}: {}", metric.name, e);
+        }
+        self.samples.push_back(metric);
+        self.prune(SystemTime::now());
+        if self.persisted > 2 * self.samples.len().max(1000) {
+            if let Err(e) = self.compact().await {
+                warn!("Cannot rewrite metrics history: {}", e);
+            }
+        }
+    }
+
+    pub fn samples(&self) -> Vec<Metric> {
+        self.samples.iter().cloned().collect()
+    }
+
+    /// Latest sample of every series, by name and labels
+    pub fn latest(&self) -> Vec<&Metric> {
+        let mut latest: Vec<&Metric> = Vec::new();
+        for metric in self.samples.iter().rev() {
+            let seen = latest
+                .iter()
+                .any(|m| m.name == metric.name && m.labels == metric.labels);
+            if !seen {
+                latest.push(metric);
+            }
+        }
+        latest.reverse();
+        latest
+    }
+
+    /// Samples matching `query`, oldest first. The time range is found by
+    /// binary search, so graphs over a short window stay cheap.
+    pub fn range(&self, query: &MetricQuery) -> Vec<Metric> {
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+        let start = query.since.map_or(0, |since| {
+            self.samples
+                .partition_point(|metric| metric.timestamp < at(since))
+        });
+        let end = query.until.map_or(self.samples.len(), |until| {
+            self.samples
+                .partition_point(|metric| metric.timestamp < at(until))
+        });
+        let labels = query.label_filter();
+        self.samples
+            .range(start..end.max(start))
+            .filter(|metric| metric.name == query.name)
+            .filter(|metric| {
+                labels
+                    .iter()
+                    .all(|(key, value)| metric.labels.get(*key).is_some_and(|label| label == value))
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn prune(&mut self, now: SystemTime) {
+        let retention = Duration::from_secs(self.config.retention_secs);
+        while let Some(oldest) = self.samples.front() {
+            let expired = now
+                .duration_since(oldest.timestamp)
+                .is_ok_and(|age| age > retention);
+            if !expired && self.samples.len() <= self.config.max_samples {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    async fn append(&mut self, metric: &Metric) -> Result<()> {
+        let Some(path) = &self.config.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_string(metric)
+            .map_err(|e| MonitoringError::MetricsError(e.to_string()))?;
+        line.push('\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        self.persisted += 1;
+        Ok(())
+    }
+
+    /// Rewrite the file with the live samples only
+    async fn compact(&mut self) -> Result<()> {
+        let Some(path) = &self.config.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut content = String::new();
+        for metric in &self.samples {
+            let line = serde_json::to_string(metric)
+                .map_err(|e| MonitoringError::MetricsError(e.to_string()))?;
+            content.push_str(&line);
+            content.push('\n');
+        }
+        let temp = path.with_extension("tmp");
+        tokio::fs::write(&temp, content).await?;
+        tokio::fs::rename(&temp, path).await?;
+        self.persisted = self.samples.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(interface: &str, value: f64, secs: u64) -> Metric {
+        Metric {
+            name: "network_receive_bytes_per_second".to_string(),
+            value,
+            unit: "bytes/s".to_string(),
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            labels: [("interface".to_string(), interface.to_string())].into(),
+        }
+    }
+
+    fn config(max_samples: usize) -> MetricsHistoryConfig {
+        MetricsHistoryConfig {
+            max_samples,
+            // Samples from 1970 are never expired in these tests
+            retention_secs: u64::MAX / 2,
+            path: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bounded_and_range() {
+        let mut store = MetricStore::new(config(4));
+        for secs in 0..6 {
+            let interface = if secs % 2 == 0 { "eth0" } else { "eth1" };
+            store.push(metric(interface, secs as f64, 100 + secs)).await;
+        }
+        assert_eq!(store.samples().len(), 4);
+
+        let query = MetricQuery {
+            name: "network_receive_bytes_per_second".to_string(),
+            since: Some(103),
+            until: Some(105),
+            labels: None,
+        };
+        let values: Vec<f64> = store.range(&query).iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![3.0, 4.0]);
+
+        let query = MetricQuery {
+            labels: Some("interface=eth0".to_string()),
+            ..query
+        };
+        let values: Vec<f64> = store.range(&query).iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![4.0]);
+
+        let latest = store.latest();
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest[1].value, 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_retention() {
+        let mut store = MetricStore::new(MetricsHistoryConfig {
+            retention_secs: 3600,
+            ..config(100)
+        });
+        store.push(metric("eth0", 1.0, 0)).await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        store.push(metric("eth0", 2.0, now)).await;
+
+        let samples = store.samples();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].value, 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let persistent = MetricsHistoryConfig {
+            path: Some(dir.path().join("metrics.jsonl")),
+            ..config(3)
+        };
+
+        let mut store = MetricStore::new(persistent.clone());
+        store.open(persistent.clone()).await;
+        for secs in 0..5 {
+            store.push(metric("eth0", secs as f64, secs)).await;
+        }
+
+        let mut reopened = MetricStore::new(config(3));
+        reopened.open(persistent).await;
+        let values: Vec<f64> = reopened.samples().iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![2.0, 3.0, 4.0]);
+    }
+}
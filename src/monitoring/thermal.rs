@@ -0,0 +1,198 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+const THERMAL_DIR: &str = "/sys/class/thermal";
+const HWMON_DIR: &str = "/sys/class/hwmon";
+/// Raspberry Pi firmware flags, on kernels that expose them
+const GET_THROTTLED: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+
+/// Raspberry Pi firmware throttling flags, as reported by
+/// `vcgencmd get_throttled`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ThrottleFlags {
+    pub under_voltage: bool,
+    pub frequency_capped: bool,
+    pub throttled: bool,
+    pub soft_temp_limit: bool,
+    /// Any of the above happened since boot
+    pub occurred_since_boot: bool,
+}
+
+impl ThrottleFlags {
+    pub fn from_bits(bits: u32) -> Self {
+        Self {
+            under_voltage: bits & 0x1 != 0,
+            frequency_capped: bits & 0x2 != 0,
+            throttled: bits & 0x4 != 0,
+            soft_temp_limit: bits & 0x8 != 0,
+            occurred_since_boot: bits & 0xF_0000 != 0,
+        }
+    }
+
+    /// Slowed down or short of power right now
+    pub fn active(&self) -> bool {
+        self.under_voltage || self.frequency_capped || self.throttled || self.soft_temp_limit
+    }
+}
+
+/// Temperature of one thermal zone
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ZoneTemperature {
+    /// Zone type, such as `cpu-thermal`
+    pub zone: String,
+    pub celsius: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ThermalReading {
+    pub temperatures: Vec<ZoneTemperature>,
+    /// `None` on boards without the Raspberry Pi firmware
+    pub throttle: Option<ThrottleFlags>,
+}
+
+impl ThermalReading {
+    pub fn hottest(&self) -> Option<&ZoneTemperature> {
+        self.temperatures
+            .iter()
+            .max_by(|a, b| a.celsius.total_cmp(&b.celsius))
+    }
+}
+
+/// SoC temperatures from sysfs and, on a Raspberry Pi, the firmware's
+/// throttling and undervoltage flags
+pub async fn read() -> ThermalReading {
+    ThermalReading {
+        temperatures: read_zones(Path::new(THERMAL_DIR)),
+        throttle: read_throttle().await,
+    }
+}
+
+fn read_zones(dir: &Path) -> Vec<ZoneTemperature> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut zones: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("thermal_zone"))
+        })
+        .collect();
+    zones.sort();
+    zones
+        .iter()
+        .filter_map(|zone| {
+            let millidegrees: i64 = std::fs::read_to_string(zone.join("temp"))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()?;
+            let name = std::fs::read_to_string(zone.join("type"))
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|_| zone.file_name().unwrap().to_string_lossy().into_owned());
+            Some(ZoneTemperature {
+                zone: name,
+                celsius: millidegrees as f64 / 1000.0,
+            })
+        })
+        .collect()
+}
+
+async fn read_throttle() -> Option<ThrottleFlags> {
+    if let Ok(value) = tokio::fs::read_to_string(GET_THROTTLED).await {
+        return parse_throttled(&value).map(ThrottleFlags::from_bits);
+    }
+    if let Ok(output) = Command::new("vcgencmd").arg("get_throttled").output().await {
+        if output.status.success() {
+            return parse_throttled(&String::from_utf8_lossy(&output.stdout))
+                .map(ThrottleFlags::from_bits);
+        }
+    }
+    hwmon_under_voltage(Path::new(HWMON_DIR)).map(|under_voltage| ThrottleFlags {
+        under_voltage,
+        ..ThrottleFlags::default()
+    })
+}
+
+/// Undervoltage alarm of the `rpi_volt` hwmon device, for kernels without
+/// the firmware flags and images without vcgencmd
+fn hwmon_under_voltage(dir: &Path) -> Option<bool> {
+    std::fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+        let name = std::fs::read_to_string(entry.path().join("name")).ok()?;
+        if name.trim() != "rpi_volt" {
+            return None;
+        }
+        let alarm = std::fs::read_to_string(entry.path().join("in0_lcrit_alarm")).ok()?;
+        Some(alarm.trim() == "1")
+    })
+}
+
+/// `throttled=0x50005` from vcgencmd, or the bare hex from sysfs
+fn parse_throttled(value: &str) -> Option<u32> {
+    let value = value.trim();
+    let value = value.strip_prefix("throttled=").unwrap_or(value);
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    u32::from_str_radix(value, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_flags() {
+        let flags = ThrottleFlags::from_bits(parse_throttled("throttled=0x50005\n").unwrap());
+        assert!(flags.under_voltage);
+        assert!(flags.throttled);
+        assert!(!flags.frequency_capped);
+        assert!(flags.occurred_since_boot);
+        assert!(flags.active());
+
+        let past = ThrottleFlags::from_bits(parse_throttled("50000").unwrap());
+        assert!(!past.active());
+        assert!(past.occurred_since_boot);
+        assert_eq!(parse_throttled("throttled=0x0"), Some(0));
+        assert_eq!(parse_throttled("error=2"), None);
+    }
+
+    #[test]
+    fn test_read_zones() {
+        let dir = tempfile::tempdir().unwrap();
+        for (zone, kind, temp) in [
+            ("thermal_zone0", "cpu-thermal", "61850"),
+            ("thermal_zone1", "gpu", "x"),
+        ] {
+            let path = dir.path().join(zone);
+            std::fs::create_dir(&path).unwrap();
+            std::fs::write(path.join("type"), format!("{}\n", kind)).unwrap();
+            std::fs::write(path.join("temp"), temp).unwrap();
+        }
+        std::fs::create_dir(dir.path().join("cooling_device0")).unwrap();
+
+        let reading = ThermalReading {
+            temperatures: read_zones(dir.path()),
+            throttle: None,
+        };
+        assert_eq!(
+            reading.temperatures,
+            vec![ZoneTemperature {
+                zone: "cpu-thermal".to_string(),
+                celsius: 61.85,
+            }]
+        );
+        assert_eq!(reading.hottest().unwrap().zone, "cpu-thermal");
+    }
+
+    #[test]
+    fn test_hwmon_under_voltage() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(hwmon_under_voltage(dir.path()), None);
+        let volt = dir.path().join("hwmon1");
+        std::fs::create_dir(&volt).unwrap();
+        std::fs::write(volt.join("name"), "rpi_volt\n").unwrap();
+        std::fs::write(volt.join("in0_lcrit_alarm"), "1\n").unwrap();
+        assert_eq!(hwmon_under_voltage(dir.path()), Some(true));
+    }
+}
@@ -0,0 +1,842 @@
+use crate::audit::{AuditAction, AuditLog, AuditSource};
+use crate::capabilities::NodeCapabilities;
+use crate::config::MqttConfig;
+use crate::dryrun::DryRun;
+use crate::error::{MqttError, Result};
+use crate::events::{AppEvent, EventBus};
+use crate::job::install::InstallJob;
+use crate::monitoring::{Alert, Metric, Monitor};
+use crate::remote::ssh::NodeCommands;
+use rumqttc::tokio_rustls::rustls;
+use rumqttc::{
+    AsyncClient, ConnectionError, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet,
+    Publish, QoS, SubscribeFilter, SubscribeReasonCode, Transport,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time for the reply to a `reboot` command to reach the broker
+const REBOOT_DELAY: Duration = Duration::from_secs(2);
+/// Requests queued for the event loop; status and metrics beyond it are
+/// dropped rather than waited for
+const REQUEST_CAPACITY: usize = 64;
+
+/// Command taken from a `command/<name>` topic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FleetCommand {
+    Install { iso: String, device: String },
+    Cancel(String),
+    Rescan,
+    Reboot,
+    Status,
+}
+
+/// JSON body of a command message; `id` is echoed in the response
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CommandPayload {
+    id: Option<String>,
+    iso: Option<String>,
+    device: Option<String>,
+    job: Option<String>,
+}
+
+impl FleetCommand {
+    fn parse(name: &str, payload: &CommandPayload) -> Result<Self> {
+        let field = |value: &Option<String>, field: &str| {
+            value
+                .clone()
+                .ok_or_else(|| MqttError::InvalidCommand(format!("{} needs \"{}\"", name, field)))
+        };
+        Ok(match name {
+            "install" => FleetCommand::Install {
+                iso: field(&payload.iso, "iso")?,
+                device: field(&payload.device, "device")?,
+            },
+            "cancel" => FleetCommand::Cancel(field(&payload.job, "job")?),
+            "rescan" => FleetCommand::Rescan,
+            "reboot" => FleetCommand::Reboot,
+            "status" => FleetCommand::Status,
+            other => return Err(MqttError::InvalidCommand(other.to_string()).into()),
+        })
+    }
+
+    /// What the command acts on, for the audit log
+    fn target(&self) -> Option<String> {
+        match self {
+            FleetCommand::Install { iso, device } => Some(format!("{} to {}", iso, device)),
+            FleetCommand::Cancel(job) => Some(job.clone()),
+            FleetCommand::Rescan | FleetCommand::Reboot | FleetCommand::Status => None,
+        }
+    }
+}
+
+/// Topics of one node under the configured prefix
+#[derive(Debug, Clone)]
+struct Topics {
+    /// `<prefix>/<node>`
+    node: String,
+    /// `<prefix>/all`, commands for every node
+    fleet: String,
+}
+
+impl Topics {
+    fn new(prefix: &str, node_id: &str) -> Self {
+        let prefix = prefix.trim_end_matches('/');
+        Self {
+            node: format!("{}/{}", prefix, node_id),
+            fleet: format!("{}/all", prefix),
+        }
+    }
+
+    fn topic(&self, leaf: &str) -> String {
+        format!("{}/{}", self.node, leaf)
+    }
+
+    fn command_filters(&self) -> [String; 2] {
+        [
+            format!("{}/command/+", self.node),
+            format!("{}/command/+", self.fleet),
+        ]
+    }
+
+    /// Command name of a message on one of the command topics
+    fn command<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        let matches = self
+            .command_filters()
+            .iter()
+            .any(|filter| rumqttc::matches(topic, filter));
+        if matches {
+            topic.rsplit('/').next()
+        } else {
+            None
+        }
+    }
+}
+
+/// Optional MQTT client for fleet infrastructure: publishes status,
+/// metrics and alerts and takes install, cancel, rescan, reboot and status
+/// commands, reconnecting whenever the broker goes away
+pub struct MqttClient {
+    config: MqttConfig,
+    monitor: Arc<RwLock<Monitor>>,
+    events: EventBus,
+    commands: Option<NodeCommands>,
+    dry_run: Option<DryRun>,
+    audit: Option<AuditLog>,
+    capabilities: Option<NodeCapabilities>,
+    stop_tx: Mutex<Option<watch::Sender<bool>>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl MqttClient {
+    pub fn new(config: MqttConfig, monitor: Arc<RwLock<Monitor>>, events: EventBus) -> Self {
+        Self {
+            config,
+            monitor,
+            events,
+            commands: None,
+            dry_run: None,
+            audit: None,
+            capabilities: None,
+            stop_tx: Mutex::new(None),
+            task: Mutex::new(None),
+        }
+    }
+
+    /// Report on and act on this node state; without it only metrics and
+    /// alerts are published
+    pub fn with_commands(mut self, commands: NodeCommands) -> Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Record the reboot instead of rebooting
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Record the fleet commands carried out
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Include what the node can run in the status
+    pub fn with_capabilities(mut self, capabilities: NodeCapabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        if !self.config.enabled {
+            debug!("MQTT disabled");
+            return Ok(());
+        }
+
+        let node_id = self.config.node_id.clone().unwrap_or_else(|| {
+            std::fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|_| "usb-installer-node".to_string())
+        });
+        let topics = Topics::new(&self.config.topic_prefix, &node_id);
+        let options = mqtt_options(&self.config, &node_id, &topics)?;
+        info!(
+            "Reporting to MQTT broker {} as {}",
+            self.config.broker, node_id
+        );
+
+        let (client, mut eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        eventloop
+            .network_options
+            .set_connection_timeout(CONNECT_TIMEOUT.as_secs());
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let connection = Connection {
+            client,
+            topics,
+            node_id,
+            config: self.config.clone(),
+            monitor: self.monitor.clone(),
+            events: self.events.clone(),
+            commands: self.commands.clone(),
+            dry_run: self.dry_run.clone(),
+            audit: self.audit.clone(),
+            capabilities: self.capabilities.clone(),
+        };
+        let task = tokio::spawn(connection.run(eventloop, stop_rx));
+        *self.stop_tx.lock().unwrap() = Some(stop_tx);
+        *self.task.lock().unwrap() = Some(task);
+        Ok(())
+    }
+
+    /// Mark the node offline and disconnect
+    pub async fn stop(&self) -> Result<()> {
+        let stop_tx = self.stop_tx.lock().unwrap().take();
+        let task = self.task.lock().unwrap().take();
+        if let (Some(stop_tx), Some(task)) = (stop_tx, task) {
+            let _ = stop_tx.send(true);
+            if tokio::time::timeout(CONNECT_TIMEOUT, task).await.is_err() {
+                warn!("MQTT client did not disconnect in time");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Connection settings, with the last will marking the node offline. With
+/// `tls` the broker certificate is checked against `ca_file`, or the system
+/// roots when unset.
+fn mqtt_options(config: &MqttConfig, node_id: &str, topics: &Topics) -> Result<MqttOptions> {
+    let (host, port) = broker_address(&config.broker)?;
+    let mut options = MqttOptions::new(format!("usb-installer-{}", node_id), host, port);
+    options
+        .set_keep_alive(Duration::from_secs(config.keep_alive_secs.max(1) as u64))
+        .set_last_will(LastWill::new(
+            topics.topic("online"),
+            "false",
+            QoS::AtLeastOnce,
+            true,
+        ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    if config.tls {
+        options.set_transport(Transport::tls_with_config(tls_config(config)?.into()));
+    } else if config.password.is_some() {
+        warn!(
+            "MQTT password is sent to {} without TLS; set [mqtt] tls",
+            config.broker
+        );
+    }
+    Ok(options)
+}
+
+/// `host:port`, with IPv6 hosts in brackets
+fn broker_address(broker: &str) -> Result<(String, u16)> {
+    let invalid = || MqttError::ConnectionFailed(format!("Invalid broker address {}", broker));
+    let (host, port) = broker.rsplit_once(':').ok_or_else(invalid)?;
+    let port = port.parse().map_err(|_| invalid())?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid().into());
+    }
+    Ok((host.to_string(), port))
+}
+
+fn tls_config(config: &MqttConfig) -> Result<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    match &config.ca_file {
+        Some(path) => {
+            let ca_error = |e: std::io::Error| {
+                MqttError::ConnectionFailed(format!("CA file {}: {}", path.display(), e))
+            };
+            let mut file = std::io::BufReader::new(std::fs::File::open(path).map_err(ca_error)?);
+            let certs = rustls_pemfile::certs(&mut file)
+                .collect::<std::io::Result<Vec<_>>>()
+                .map_err(ca_error)?;
+            roots.add_parsable_certificates(certs);
+        }
+        None => {
+            let native = rustls_native_certs::load_native_certs();
+            for e in &native.errors {
+                warn!("Loading system CA certificates: {}", e);
+            }
+            roots.add_parsable_certificates(native.certs);
+        }
+    }
+    if roots.is_empty() {
+        return Err(MqttError::ConnectionFailed(
+            "No CA certificate to check the broker against".to_string(),
+        )
+        .into());
+    }
+    Ok(rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// Everything the connection loop needs, moved into its task
+struct Connection {
+    client: AsyncClient,
+    config: MqttConfig,
+    node_id: String,
+    topics: Topics,
+    monitor: Arc<RwLock<Monitor>>,
+    events: EventBus,
+    commands: Option<NodeCommands>,
+    dry_run: Option<DryRun>,
+    audit: Option<AuditLog>,
+    capabilities: Option<NodeCapabilities>,
+}
+
+impl Connection {
+    /// Drive the event loop until the client is stopped. `rumqttc`
+    /// reconnects on the next poll after an error, so failures only pause
+    /// the loop for `reconnect_secs`.
+    async fn run(self, mut eventloop: EventLoop, mut stop: watch::Receiver<bool>) {
+        let broker = &self.config.broker;
+        let retry = Duration::from_secs(self.config.reconnect_secs.max(1));
+        let mut events = self.events.subscribe();
+        let mut report = tokio::time::interval(Duration::from_secs(
+            self.config.publish_interval_secs.max(1),
+        ));
+        let mut connected = false;
+
+        loop {
+            tokio::select! {
+                event = eventloop.poll() => match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker {}", broker);
+                        connected = true;
+                        self.announce();
+                    }
+                    Ok(Event::Incoming(Packet::Publish(message))) => {
+                        self.handle_command(&message).await;
+                    }
+                    Ok(Event::Incoming(Packet::SubAck(ack)))
+                        if ack.return_codes.contains(&SubscribeReasonCode::Failure) =>
+                    {
+                        warn!("MQTT broker refused the command subscription");
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("MQTT connection to {}: {}", broker, connection_error(e));
+                        connected = false;
+                        tokio::select! {
+                            _ = tokio::time::sleep(retry) => {}
+                            _ = stop.changed() => return,
+                        }
+                    }
+                },
+                _ = report.tick(), if connected => self.report().await,
+                event = events.recv() => match event {
+                    Ok(AppEvent::Alert(alert)) if connected => {
+                        self.publish("alerts", alert_json(&alert).to_string(), false);
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("Dropped {} events for MQTT", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {}
+                },
+                _ = stop.changed() => {
+                    if connected {
+                        self.publish("online", "false", true);
+                        let _ = self.client.try_disconnect();
+                        flush(&mut eventloop).await;
+                        info!("Disconnected from MQTT broker {}", broker);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Subscribe to commands and mark the node online; the session is clean,
+    /// so this is repeated on every connect
+    fn announce(&self) {
+        if self.config.commands {
+            let filters = self
+                .topics
+                .command_filters()
+                .into_iter()
+                .map(|filter| SubscribeFilter::new(filter, QoS::AtLeastOnce));
+            if let Err(e) = self.client.try_subscribe_many(filters) {
+                warn!("Subscribing to MQTT commands failed: {}", e);
+            }
+        }
+        self.publish("online", "true", true);
+    }
+
+    /// QoS 0; a lost status or metric is replaced by the next one
+    fn publish(&self, leaf: &str, payload: impl Into<Vec<u8>>, retain: bool) {
+        let topic = self.topics.topic(leaf);
+        if let Err(e) = self
+            .client
+            .try_publish(&topic, QoS::AtMostOnce, retain, payload)
+        {
+            debug!("Dropped MQTT message on {}: {}", topic, e);
+        }
+    }
+
+    /// Status, retained so new subscribers see it at once, and the latest
+    /// metrics
+    async fn report(&self) {
+        let status = self.status().await;
+        self.publish("status", status.to_string(), true);
+        let metrics = latest_metrics(&self.monitor.read().await.get_metrics().await);
+        self.publish("metrics", Value::from(metrics).to_string(), false);
+    }
+
+    async fn status(&self) -> Value {
+        let mut status = json!({ "node": self.node_id });
+        if let Some(capabilities) = &self.capabilities {
+            status["capabilities"] = json!(capabilities);
+        }
+        let Some(commands) = &self.commands else {
+            return status;
+        };
+        status["subsystems"] = json!(commands.startup.snapshot().await);
+        status["jobs"] = json!(commands.install_jobs.queue().await);
+        status["isos"] = json!(commands.iso_manager.get_available_isos().await.len());
+        status
+    }
+
+    /// Carry out a command message and publish the outcome to `response`
+    async fn handle_command(&self, message: &Publish) {
+        let Some(name) = self.topics.command(&message.topic) else {
+            return;
+        };
+        let payload: CommandPayload = if message.payload.is_empty() {
+            CommandPayload::default()
+        } else {
+            match serde_json::from_slice(&message.payload) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!("Ignoring MQTT command {} with invalid JSON: {}", name, e);
+                    return;
+                }
+            }
+        };
+
+        info!("MQTT command {} from {}", name, message.topic);
+        let outcome = match FleetCommand::parse(name, &payload) {
+            Ok(command) => {
+                let target = command.target();
+                let outcome = self.execute(command).await;
+                self.audit(name, &message.topic, target, &outcome).await;
+                outcome
+            }
+            Err(e) => Err(e),
+        };
+        let response = match outcome {
+            Ok(result) => json!({
+                "command": name,
+                "id": payload.id,
+                "ok": true,
+                "result": result,
+            }),
+            Err(e) => {
+                warn!("MQTT command {} failed: {}", name, e);
+                json!({
+                    "command": name,
+                    "id": payload.id,
+                    "ok": false,
+                    "error": e.to_string(),
+                })
+            }
+        };
+        self.publish("response", response.to_string(), false);
+        if name == "status" {
+            self.report().await;
+        }
+    }
+
+    /// Record a command carried out on behalf of the fleet. The broker does
+    /// not say who published it, so the topic stands in for the actor.
+    /// `status` is a read and only recorded with `record_reads`.
+    async fn audit(
+        &self,
+        name: &str,
+        topic: &str,
+        target: Option<String>,
+        outcome: &Result<Value>,
+    ) {
+        let Some(audit) = &self.audit else {
+            return;
+        };
+        if name == "status" && !audit.records_reads() {
+            return;
+        }
+        let mut action = AuditAction::new(AuditSource::Mqtt, topic, name).with_result(outcome);
+        if let Some(target) = target {
+            action = action.with_target(target);
+        }
+        audit.record(action).await;
+    }
+
+    async fn execute(&self, command: FleetCommand) -> Result<Value> {
+        let Some(commands) = &self.commands else {
+            return Err(
+                MqttError::InvalidCommand("Node commands are not available".to_string()).into(),
+            );
+        };
+        match command {
+            FleetCommand::Install { iso, device } => {
+                let entry = commands.iso_manager.get_catalog_entry(&iso).await?;
+                let disk = commands.disk_manager.get_disk_inventory(&device).await?;
+                let job = InstallJob::new(entry.path, &disk.path, false);
+                Ok(json!(commands.install_jobs.enqueue(job).await?))
+            }
+            FleetCommand::Cancel(id) => Ok(json!(commands.install_jobs.cancel(&id).await?)),
+            FleetCommand::Rescan => {
+                let isos = commands.iso_manager.rescan().await?;
+                Ok(json!({ "isos": isos.len() }))
+            }
+            FleetCommand::Reboot => {
+                if !self.config.allow_reboot {
+                    return Err(MqttError::InvalidCommand(
+                        "reboot is not allowed on this node".to_string(),
+                    )
+                    .into());
+                }
+                self.reboot();
+                Ok(json!({ "rebooting": true }))
+            }
+            FleetCommand::Status => Ok(self.status().await),
+        }
+    }
+
+    /// Reboot shortly, once the response is out
+    fn reboot(&self) {
+        let mut cmd = std::process::Command::new("systemctl");
+        cmd.arg("reboot");
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.command("Reboot the node on an MQTT command", &cmd);
+            return;
+        }
+        warn!("Rebooting on an MQTT command");
+        tokio::spawn(async move {
+            tokio::time::sleep(REBOOT_DELAY).await;
+            if let Err(e) = tokio::process::Command::from(cmd).status().await {
+                warn!("Reboot failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Poll until the queued goodbye has been written, so the broker sees the
+/// node go offline rather than time out
+async fn flush(eventloop: &mut EventLoop) {
+    let sent = tokio::time::timeout(CONNECT_TIMEOUT, async {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => return,
+                Ok(_) => {}
+            }
+        }
+    })
+    .await;
+    if sent.is_err() {
+        warn!("MQTT disconnect was not sent in time");
+    }
+}
+
+fn connection_error(e: ConnectionError) -> MqttError {
+    match e {
+        ConnectionError::ConnectionRefused(code) => MqttError::Refused(code as u8),
+        other => MqttError::ConnectionFailed(other.to_string()),
+    }
+}
+
+/// The most recent sample of every metric and label set
+fn latest_metrics(metrics: &[Metric]) -> Vec<Value> {
+    let mut latest = BTreeMap::new();
+    for metric in metrics {
+        let labels: BTreeMap<&String, &String> = metric.labels.iter().collect();
+        latest.insert(
+            (metric.name.clone(), format!("{:?}", labels)),
+            json!({
+                "name": metric.name,
+                "value": metric.value,
+                "unit": metric.unit,
+                "labels": labels,
+            }),
+        );
+    }
+    latest.into_values().collect()
+}
+
+fn alert_json(alert: &Alert) -> Value {
+    json!({
+        "id": alert.id,
+        "severity": format!("{:?}", alert.severity).to_lowercase(),
+        "module": alert.module,
+        "message": alert.message,
+        "resolved": alert.resolved,
+        "occurrences": alert.occurrences,
+        "acknowledged": alert.acknowledged_by.is_some(),
+        "timestamp": alert
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|age| age.as_secs())
+            .unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditQuery;
+    use crate::config::{AuditConfig, MonitoringConfig};
+    use crate::error::Error;
+    use crate::monitoring::AlertSeverity;
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, PubAck};
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const MAX_PACKET: usize = 64 * 1024;
+
+    /// The broker side of a connection, built on the client's own codec
+    struct Broker {
+        stream: TcpStream,
+        buf: BytesMut,
+    }
+
+    impl Broker {
+        async fn recv(&mut self) -> Packet {
+            loop {
+                match Packet::read(&mut self.buf, MAX_PACKET) {
+                    Ok(packet) => return packet,
+                    Err(rumqttc::Error::InsufficientBytes(_)) => {}
+                    Err(e) => panic!("invalid packet: {}", e),
+                }
+                let read = self.stream.read_buf(&mut self.buf).await.unwrap();
+                assert_ne!(read, 0, "client closed the connection");
+            }
+        }
+
+        async fn send(&mut self, packet: Packet) {
+            let mut buf = BytesMut::new();
+            packet.write(&mut buf, MAX_PACKET).unwrap();
+            self.stream.write_all(&buf).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let payload: CommandPayload =
+            serde_json::from_str(r#"{"id": "42", "iso": "debian.iso", "device": "sdb"}"#).unwrap();
+        assert_eq!(
+            FleetCommand::parse("install", &payload).unwrap(),
+            FleetCommand::Install {
+                iso: "debian.iso".to_string(),
+                device: "sdb".to_string()
+            }
+        );
+        assert!(matches!(
+            FleetCommand::parse("cancel", &payload),
+            Err(Error::Mqtt(MqttError::InvalidCommand(_)))
+        ));
+        assert_eq!(
+            FleetCommand::parse("rescan", &CommandPayload::default()).unwrap(),
+            FleetCommand::Rescan
+        );
+        assert!(FleetCommand::parse("format", &CommandPayload::default()).is_err());
+
+        let topics = Topics::new("fleet/site1/", "usb-node-a1b2c3");
+        assert_eq!(topics.topic("status"), "fleet/site1/usb-node-a1b2c3/status");
+        assert_eq!(
+            topics.command("fleet/site1/usb-node-a1b2c3/command/install"),
+            Some("install")
+        );
+        assert_eq!(
+            topics.command("fleet/site1/all/command/rescan"),
+            Some("rescan")
+        );
+        assert_eq!(topics.command("fleet/site1/other/command/rescan"), None);
+        assert_eq!(topics.command("fleet/site1/usb-node-a1b2c3/status"), None);
+    }
+
+    #[test]
+    fn test_latest_metrics() {
+        let metric = |value: f64, device: &str| Metric {
+            name: "disk_operation_progress".to_string(),
+            value,
+            unit: "percent".to_string(),
+            timestamp: SystemTime::now(),
+            labels: [("device".to_string(), device.to_string())].into(),
+        };
+        let latest = latest_metrics(&[
+            metric(10.0, "sdb"),
+            metric(20.0, "sdc"),
+            metric(55.0, "sdb"),
+        ]);
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest[0]["value"], 55.0);
+        assert_eq!(latest[0]["labels"]["device"], "sdb");
+        assert_eq!(latest[1]["value"], 20.0);
+
+        let alert = alert_json(&Alert {
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_760_000_000),
+            ..Alert::new(
+                AlertSeverity::Critical,
+                "network",
+                "Health check failed".to_string(),
+            )
+        });
+        assert_eq!(alert["severity"], "critical");
+        assert_eq!(alert["timestamp"], 1_760_000_000);
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(
+            broker_address("broker.fleet.local:8883").unwrap(),
+            ("broker.fleet.local".to_string(), 8883)
+        );
+        assert_eq!(
+            broker_address("[fd00::1]:1883").unwrap(),
+            ("fd00::1".to_string(), 1883)
+        );
+        assert!(broker_address("broker.fleet.local").is_err());
+        assert!(broker_address(":1883").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let ca_file = dir.path().join("ca.pem");
+        std::fs::write(&ca_file, "not a certificate\n").unwrap();
+        let config = MqttConfig {
+            ca_file: Some(ca_file),
+            ..MqttConfig::default()
+        };
+        assert!(matches!(
+            tls_config(&config),
+            Err(Error::Mqtt(MqttError::ConnectionFailed(_)))
+        ));
+    }
+
+    /// The client against a scripted broker: connect, subscribe, report,
+    /// answer and audit a command and say goodbye
+    #[tokio::test]
+    async fn test_session_with_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = MqttConfig {
+            enabled: true,
+            broker: listener.local_addr().unwrap().to_string(),
+            tls: false,
+            node_id: Some("node1".to_string()),
+            topic_prefix: "fleet".to_string(),
+            ..MqttConfig::default()
+        };
+        let monitor = Arc::new(RwLock::new(Monitor::new(Arc::new(RwLock::new(
+            MonitoringConfig::default(),
+        )))));
+        let dir = tempfile::tempdir().unwrap();
+        let audit = AuditLog::new(AuditConfig {
+            path: dir.path().join("audit.jsonl"),
+            ..Default::default()
+        });
+        let capabilities = NodeCapabilities::detect(&crate::config::Config::default());
+        let client = MqttClient::new(config, monitor, EventBus::new())
+            .with_audit(audit.clone())
+            .with_capabilities(capabilities.clone());
+        client.start().await.unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut broker = Broker {
+            stream,
+            buf: BytesMut::new(),
+        };
+        let Packet::Connect(connect) = broker.recv().await else {
+            panic!("expected CONNECT");
+        };
+        assert_eq!(connect.client_id, "usb-installer-node1");
+        assert_eq!(connect.last_will.unwrap().topic, "fleet/node1/online");
+        broker
+            .send(Packet::ConnAck(ConnAck::new(
+                ConnectReturnCode::Success,
+                false,
+            )))
+            .await;
+
+        let Packet::Subscribe(subscribe) = broker.recv().await else {
+            panic!("expected SUBSCRIBE");
+        };
+        assert_eq!(subscribe.filters[1].path, "fleet/all/command/+");
+        let mut published = HashMap::new();
+        for _ in 0..3 {
+            let Packet::Publish(message) = broker.recv().await else {
+                panic!("expected PUBLISH");
+            };
+            published.insert(message.topic.clone(), message);
+        }
+        assert_eq!(&published["fleet/node1/online"].payload[..], b"true");
+        assert!(published["fleet/node1/status"].retain);
+        let status: Value =
+            serde_json::from_slice(&published["fleet/node1/status"].payload).unwrap();
+        assert_eq!(status["capabilities"], json!(capabilities));
+        assert!(published.contains_key("fleet/node1/metrics"));
+
+        let mut command = Publish::new(
+            "fleet/all/command/reboot",
+            QoS::AtLeastOnce,
+            br#"{"id": "r1"}"#.to_vec(),
+        );
+        command.pkid = 9;
+        broker.send(Packet::Publish(command)).await;
+        assert_eq!(broker.recv().await, Packet::PubAck(PubAck::new(9)));
+        let Packet::Publish(response) = broker.recv().await else {
+            panic!("expected the response");
+        };
+        assert_eq!(response.topic, "fleet/node1/response");
+        let response: Value = serde_json::from_slice(&response.payload).unwrap();
+        assert_eq!(response["id"], "r1");
+        assert_eq!(response["ok"], false);
+
+        let entries = audit.entries(&AuditQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, AuditSource::Mqtt);
+        assert_eq!(entries[0].actor, "fleet/all/command/reboot");
+        assert_eq!(entries[0].action, "reboot");
+        assert!(entries[0].result.starts_with("failed"));
+
+        let stopped = tokio::spawn(async move { client.stop().await });
+        let Packet::Publish(offline) = broker.recv().await else {
+            panic!("expected offline");
+        };
+        assert_eq!(&offline.payload[..], b"false");
+        assert_eq!(broker.recv().await, Packet::Disconnect);
+        stopped.await.unwrap().unwrap();
+    }
+}
@@ -1,21 +1,54 @@
 use crate::config::NetworkConfig;
-use crate::error::{Result, UsbNodeError};
-use crate::network::dhcp::DhcpManager;
+use crate::error::{Error, NetworkError, Result, UsbNodeError};
+use crate::events::{AppEvent, EventBus};
+use crate::network::dhcp::{DhcpClient, DhcpManager};
+use crate::network::discovery::PeerDiscovery;
+use crate::network::failover::{InterfaceMetrics, UplinkFailover};
+use crate::network::firewall::{Firewall, FirewallPorts};
 use crate::network::hostname::HostnameManager;
-use crate::network::tunnel::TunnelManager;
+use crate::network::isolation::ProvisioningIsolation;
+use crate::network::link_local::LinkLocalAddressing;
+use crate::network::links::VirtualLinks;
+use crate::network::mdns::ServicePorts;
+use crate::network::static_ip::StaticAddressing;
+use crate::network::tunnel::{TunnelManager, TunnelStatus};
 use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 
 pub mod dhcp;
+pub mod discovery;
+pub mod curl;
+pub mod failover;
+pub mod firewall;
 pub mod hostname;
+pub mod isolation;
+pub mod link_local;
+pub mod links;
+pub mod mdns;
+pub mod static_ip;
+pub mod traffic;
 pub mod tunnel;
 
-#[derive(Debug, Clone, PartialEq)]
+/// How long the tunnel may take to connect (e.g. waiting on auth) before
+/// the network is reported degraded instead of failed
+const TUNNEL_START_TIMEOUT: Duration = Duration::from_secs(30);
+/// Pause before asking for a lease again on an uplink without one
+const UPLINK_RETRY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum NetworkState {
     Down,
     Configuring,
     Up,
+    /// Local network is up but an optional part (tunnel, provisioning
+    /// isolation) is not
+    Degraded,
     Error,
     Recovering,
 }
@@ -25,8 +58,14 @@ pub struct NetworkStatus {
     pub state: NetworkState,
     pub interface: Option<String>,
     pub ip_address: Option<String>,
+    /// Global IPv6 addresses of the primary interface
+    pub ipv6_addresses: Vec<String>,
     pub hostname: Option<String>,
     pub tunnel_connected: bool,
+    /// Provisioning interface is fenced off and safe to serve targets on
+    pub provisioning_isolated: bool,
+    /// Link state and counters of the `[network.failover]` uplinks
+    pub uplinks: Vec<InterfaceMetrics>,
     pub error_message: Option<String>,
 }
 
@@ -35,43 +74,131 @@ pub struct NetworkManager {
     dhcp_manager: DhcpManager,
     hostname_manager: HostnameManager,
     tunnel_manager: TunnelManager,
+    isolation: ProvisioningIsolation,
+    firewall: Firewall,
+    failover: UplinkFailover,
+    discovery: PeerDiscovery,
+    links: VirtualLinks,
+    /// DHCP on uplinks other than the primary interface, and on the
+    /// primary one while it is link-local
+    uplink_tasks: Arc<RwLock<Vec<JoinHandle<()>>>>,
+    static_addresses: Vec<StaticAddressing>,
+    /// The primary interface kept its static address; DHCP is not running
+    static_primary: Arc<RwLock<bool>>,
+    /// Set while the primary interface has no lease and a link-local
+    /// address instead
+    link_local: Arc<RwLock<Option<Arc<LinkLocalAddressing>>>>,
     state: Arc<RwLock<NetworkState>>,
     status: Arc<RwLock<NetworkStatus>>,
+    events: Option<EventBus>,
 }
 
 impl NetworkManager {
     pub fn new(config: NetworkConfig) -> Self {
         let dhcp_manager = DhcpManager::new(config.dhcp.clone());
-        let hostname_manager = HostnameManager::new(config.hostname.clone());
+        let hostname_manager = HostnameManager::new(config.hostname.clone())
+            .with_naming(
+                &config.hostname_prefix,
+                config.hostname_strategy,
+                config.interface.as_deref(),
+            )
+            .with_ipv6(config.ipv6.enabled);
         let tunnel_manager = TunnelManager::new(config.tunnel.clone());
+        let isolation = ProvisioningIsolation::new(config.provisioning.clone());
+        let firewall = Firewall::new(config.firewall.clone());
+        let failover = UplinkFailover::new(config.failover.clone());
+        let discovery = PeerDiscovery::new(config.discovery.clone());
+        let links = VirtualLinks::new(config.vlans.clone(), config.bridges.clone());
+        let static_addresses = config
+            .static_addresses
+            .iter()
+            .cloned()
+            .map(StaticAddressing::new)
+            .collect();
 
         Self {
             config,
             dhcp_manager,
             hostname_manager,
             tunnel_manager,
+            isolation,
+            firewall,
+            failover,
+            discovery,
+            links,
+            uplink_tasks: Arc::new(RwLock::new(Vec::new())),
+            static_addresses,
+            static_primary: Arc::new(RwLock::new(false)),
+            link_local: Arc::new(RwLock::new(None)),
             state: Arc::new(RwLock::new(NetworkState::Down)),
             status: Arc::new(RwLock::new(NetworkStatus {
                 state: NetworkState::Down,
                 interface: None,
                 ip_address: None,
+                ipv6_addresses: Vec::new(),
                 hostname: None,
                 tunnel_connected: false,
+                provisioning_isolated: false,
+                uplinks: Vec::new(),
                 error_message: None,
             })),
+            events: None,
         }
     }
 
+    /// Publish `records` in the node's mDNS service record
+    pub fn with_txt_records(mut self, records: Vec<String>) -> Self {
+        self.hostname_manager = self.hostname_manager.with_txt_records(records);
+        self
+    }
+
+    /// Ports advertised over mDNS alongside the TXT records and published
+    /// by a reverse SSH tunnel
+    pub fn with_service_ports(mut self, ports: ServicePorts) -> Self {
+        self.tunnel_manager = self.tunnel_manager.with_service_ports(ports.clone());
+        self.hostname_manager = self.hostname_manager.with_service_ports(ports);
+        self
+    }
+
+    /// Ports of the node's services the firewall lets through
+    pub fn with_firewall_ports(mut self, ports: FirewallPorts) -> Self {
+        self.firewall = self.firewall.with_ports(ports);
+        self
+    }
+
+    /// Publish state changes, for the UI and event stream clients
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Install state published in the node's mDNS TXT record, for
+    /// dashboards that browse instead of polling the API
+    pub fn set_mdns_status(&self, records: Vec<String>) {
+        self.hostname_manager.set_status(records);
+    }
+
+    /// Handle on the list of other nodes found on the LAN
+    pub fn peer_discovery(&self) -> PeerDiscovery {
+        self.discovery.clone()
+    }
+
     pub async fn start(&self) -> Result<()> {
         info!("Starting network manager");
         self.set_state(NetworkState::Configuring).await;
 
         match self.configure_network().await {
-            Ok(_) => {
+            Ok(None) => {
                 self.set_state(NetworkState::Up).await;
                 info!("Network manager started successfully");
                 Ok(())
             }
+            Ok(Some(reason)) => {
+                self.set_state(NetworkState::Degraded).await;
+                warn!("Network manager started degraded: {}", reason);
+                self.set_error_message(Some(reason)).await;
+                Ok(())
+            }
             Err(e) => {
                 self.set_state(NetworkState::Error).await;
                 self.set_error_message(Some(e.to_string())).await;
@@ -88,10 +215,39 @@ impl NetworkManager {
             warn!("Error stopping tunnel manager: {}", e);
         }
 
+        if self.isolation.is_enabled() {
+            if let Err(e) = self.isolation.remove().await {
+                warn!("Error removing provisioning isolation: {}", e);
+            }
+        }
+
+        if self.firewall.is_enabled() {
+            if let Err(e) = self.firewall.remove().await {
+                warn!("Error removing firewall: {}", e);
+            }
+        }
+
+        if let Err(e) = self.hostname_manager.cleanup_mdns() {
+            warn!("Error withdrawing mDNS records: {}", e);
+        }
+
+        self.discovery.stop().await;
+        self.failover.stop().await;
+        for task in self.uplink_tasks.write().await.drain(..) {
+            task.abort();
+        }
+        if let Some(link_local) = self.link_local.write().await.take() {
+            link_local.remove().await;
+        }
+
         if let Err(e) = self.dhcp_manager.stop().await {
             warn!("Error stopping DHCP manager: {}", e);
         }
 
+        if let Err(e) = self.links.remove().await {
+            warn!("Error removing VLANs and bridges: {}", e);
+        }
+
         self.set_state(NetworkState::Down).await;
         self.clear_status().await;
         info!("Network manager stopped");
@@ -102,23 +258,55 @@ impl NetworkManager {
         let mut status = self.status.read().await.clone();
         status.state = *self.state.read().await;
         status.tunnel_connected = self.tunnel_manager.health_check().await.unwrap_or(false);
+        if self.failover.is_enabled() {
+            // Report the uplink actually carrying the default route
+            let failover = self.failover.get_status().await;
+            if let Some(active) = failover.interfaces.iter().find(|m| m.active) {
+                status.interface = Some(active.interface.clone());
+                status.ip_address = active.address.map(|address| address.to_string());
+            }
+            status.uplinks = failover.interfaces;
+        }
         status
     }
 
+    /// The tunnel when one is configured, for monitoring and restarting
+    /// it apart from the rest of the network. Clones share its state.
+    pub fn tunnel(&self) -> Option<TunnelManager> {
+        self.tunnel_enabled().then(|| self.tunnel_manager.clone())
+    }
+
+    /// Probe results of the tunnel, refreshed by each health check
+    pub async fn tunnel_status(&self) -> Option<TunnelStatus> {
+        if !self.tunnel_enabled() {
+            return None;
+        }
+        Some(self.tunnel_manager.get_status().await)
+    }
+
     pub async fn health_check(&self) -> Result<bool> {
         let state = *self.state.read().await;
+        let link_local = self.link_local.read().await.is_some();
 
         match state {
-            NetworkState::Up => {
-                let dhcp_healthy = self.dhcp_manager.health_check().await?;
-                let tunnel_healthy = if self.config.tunnel.enabled {
-                    self.tunnel_manager.health_check().await.unwrap_or(false)
-                } else {
-                    true
-                };
-
-                Ok(dhcp_healthy && tunnel_healthy)
+            // Only optional parts are missing, which is expected without an
+            // outside connection
+            NetworkState::Degraded if self.config.offline => Ok(true),
+            // Reachable on the link; DHCP keeps being retried meanwhile
+            NetworkState::Degraded if link_local => Ok(true),
+            // The primary interface may be down while a backup carries the
+            // traffic
+            NetworkState::Degraded | NetworkState::Up if self.failover.is_enabled() => {
+                Ok(self.failover.get_status().await.active.is_some())
+            }
+            NetworkState::Degraded | NetworkState::Up if *self.static_primary.read().await => {
+                match self.primary_static() {
+                    Some(addressing) => Ok(addressing.is_up().await),
+                    None => Ok(false),
+                }
             }
+            // The tunnel is monitored on its own, see `tunnel()`
+            NetworkState::Degraded | NetworkState::Up => self.dhcp_manager.health_check().await,
             _ => Ok(false),
         }
     }
@@ -132,12 +320,58 @@ impl NetworkManager {
         self.start().await
     }
 
-    async fn configure_network(&self) -> Result<()> {
-        debug!("Configuring DHCP");
-        self.dhcp_manager.start().await?;
+    /// Bring up VLANs and bridges, the firewall, DHCP, hostname, peer
+    /// discovery, uplink failover, provisioning isolation and the tunnel.
+    /// Firewall, discovery, isolation or tunnel failures leave the network
+    /// usable; the reason is returned.
+    async fn configure_network(&self) -> Result<Option<String>> {
+        if self.links.is_enabled() {
+            // DHCP and static addresses may be on these links
+            debug!("Creating VLANs and bridges");
+            self.links.apply().await?;
+        }
+
+        let mut degraded = Vec::new();
+        if self.firewall.is_enabled() {
+            debug!("Loading firewall");
+            // Before any address is up, so services are never exposed
+            if let Err(e) = self.firewall.apply().await {
+                degraded.push(e.to_string());
+            }
+        }
 
-        let dhcp_status = self.dhcp_manager.get_status().await;
-        self.update_dhcp_status(&dhcp_status).await;
+        let static_primary = self.configure_static_addresses().await?;
+        *self.static_primary.write().await = static_primary;
+        if !static_primary {
+            debug!("Configuring DHCP");
+            match self.dhcp_manager.start().await {
+                Ok(()) => {
+                    let dhcp_status = self.dhcp_manager.get_status().await;
+                    self.update_dhcp_status(&dhcp_status).await;
+                }
+                Err(e) if self.config.link_local.enabled => {
+                    warn!("DHCP failed, falling back to a link-local address: {}", e);
+                    let address = self.configure_link_local().await?;
+                    degraded.push(format!("No DHCP lease, using link-local {}", address));
+                }
+                // Air-gapped segments often have no DHCP server
+                Err(e) if self.config.offline => degraded.push(format!("No DHCP lease: {}", e)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if self.config.ipv6.enabled {
+            debug!("Configuring IPv6");
+            // IPv4-only networks are common; a missing address is no fault
+            match self.configure_ipv6().await {
+                Ok(addresses) if addresses.is_empty() => info!("No IPv6 address assigned"),
+                Ok(addresses) => {
+                    self.status.write().await.ipv6_addresses =
+                        addresses.iter().map(Ipv6Addr::to_string).collect();
+                }
+                Err(e) => warn!("IPv6 configuration failed: {}", e),
+            }
+        }
 
         debug!("Configuring hostname");
         self.hostname_manager.start().await?;
@@ -145,14 +379,206 @@ impl NetworkManager {
         let hostname_status = self.hostname_manager.get_status().await;
         self.update_hostname_status(&hostname_status).await;
 
-        if self.config.tunnel.enabled {
+        if self.discovery.is_enabled() {
+            debug!("Starting peer discovery");
+            let hostname = self.hostname_manager.get_hostname().to_string();
+            if let Err(e) = self.discovery.start(&hostname).await {
+                degraded.push(format!("Peer discovery failed to start: {}", e));
+            }
+        }
+
+        if self.failover.is_enabled() {
+            debug!("Configuring uplinks");
+            self.configure_uplinks().await?;
+            if let Err(e) = self.failover.start().await {
+                degraded.push(format!("Uplink failover failed to start: {}", e));
+            }
+        }
+
+        if self.isolation.is_enabled() {
+            debug!("Isolating provisioning interface");
+            // Targets must not be served on an open interface
+            match self.isolation.apply().await {
+                Ok(()) => self.status.write().await.provisioning_isolated = true,
+                Err(e) => degraded.push(e.to_string()),
+            }
+        }
+
+        if self.config.offline {
+            info!("Offline mode: no tunnel");
+        } else if self.config.tunnel.enabled {
             debug!("Configuring tunnel");
-            self.tunnel_manager.start().await?;
+            match tokio::time::timeout(TUNNEL_START_TIMEOUT, self.tunnel_manager.start()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => degraded.push(format!("Tunnel failed to start: {}", e)),
+                Err(_) => degraded.push(format!(
+                    "Tunnel not connected after {}s",
+                    TUNNEL_START_TIMEOUT.as_secs()
+                )),
+            }
+        }
+
+        Ok((!degraded.is_empty()).then(|| degraded.join("; ")))
+    }
+
+    /// Apply `[[network.static_addresses]]`. On an address conflict an
+    /// interface that allows it is addressed through DHCP instead. Returns
+    /// whether the primary interface kept its static address.
+    async fn configure_static_addresses(&self) -> Result<bool> {
+        let mut static_primary = false;
+        for addressing in &self.static_addresses {
+            let interface = addressing.interface();
+            let primary = self.config.interface.as_deref() == Some(interface);
+            match addressing.apply().await {
+                Ok(()) => {
+                    if primary {
+                        let mut status = self.status.write().await;
+                        status.interface = Some(interface.to_string());
+                        status.ip_address = Some(addressing.address());
+                        static_primary = true;
+                    }
+                }
+                Err(Error::Network(NetworkError::AddressConflict(address)))
+                    if addressing.falls_back_to_dhcp() =>
+                {
+                    warn!(
+                        "Address {} is already in use, falling back to DHCP on {}",
+                        address, interface
+                    );
+                    // The primary interface is left to the DHCP manager
+                    if !primary {
+                        self.request_fallback_lease(interface).await?;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(static_primary)
+    }
+
+    async fn request_fallback_lease(&self, interface: &str) -> Result<()> {
+        let mut client = DhcpClient::new(Some(interface.to_string()))
+            .map_err(|e| NetworkError::DhcpFailed(e.to_string()))?;
+        let uplinks = self.failover.interfaces();
+        if let Some(priority) = uplinks.iter().position(|uplink| uplink == interface) {
+            client = client.with_route_metric(self.failover.route_metric(priority));
+        }
+        client
+            .request_lease()
+            .await
+            .map_err(|e| NetworkError::DhcpFailed(e.to_string()))?;
+        // Renew at T1 and rebind at T2 for as long as the node runs
+        let interface = interface.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = client.maintain_lease().await {
+                warn!("Lost DHCP lease on {}: {}", interface, e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Link-local address on the primary interface, dropped again once a
+    /// background DHCP attempt gets a lease
+    async fn configure_link_local(&self) -> Result<Ipv4Addr> {
+        let mut client = DhcpClient::new(self.config.interface.clone())
+            .map_err(|e| NetworkError::DhcpFailed(e.to_string()))?;
+        let link_local = Arc::new(LinkLocalAddressing::new(client.get_interface()));
+        let address = link_local.apply().await?;
+        {
+            let mut status = self.status.write().await;
+            status.interface = Some(link_local.interface().to_string());
+            status.ip_address = Some(address.to_string());
         }
+        *self.link_local.write().await = Some(link_local.clone());
+
+        let retry = Duration::from_secs(self.config.link_local.retry_interval);
+        let status = self.status.clone();
+        let active = self.link_local.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(retry).await;
+                match client.request_lease().await {
+                    Ok(lease) => {
+                        info!("DHCP lease {} replaces link-local {}", lease.ip, address);
+                        link_local.remove().await;
+                        *active.write().await = None;
+                        status.write().await.ip_address = Some(lease.ip.to_string());
+                        break;
+                    }
+                    Err(e) => debug!("Still no DHCP lease on {}: {}", link_local.interface(), e),
+                }
+            }
+            if let Err(e) = client.maintain_lease().await {
+                warn!("Lost DHCP lease on {}: {}", link_local.interface(), e);
+            }
+        });
+        self.uplink_tasks.write().await.push(task);
+        Ok(address)
+    }
+
+    /// DHCP on the failover uplinks that are neither the primary interface
+    /// nor statically addressed. Each keeps asking while its link is down.
+    async fn configure_uplinks(&self) -> Result<()> {
+        let mut tasks = self.uplink_tasks.write().await;
+        for (priority, interface) in self.failover.interfaces().iter().enumerate() {
+            let primary = self.config.interface.as_deref() == Some(interface.as_str());
+            let addressed = self
+                .static_addresses
+                .iter()
+                .any(|addressing| addressing.interface() == interface);
+            if primary || addressed {
+                continue;
+            }
 
+            let mut client = DhcpClient::new(Some(interface.clone()))
+                .map_err(|e| NetworkError::DhcpFailed(e.to_string()))?
+                .with_route_metric(self.failover.route_metric(priority));
+            let interface = interface.clone();
+            tasks.push(tokio::spawn(async move {
+                loop {
+                    if let Err(e) = client.maintain_lease().await {
+                        debug!("No DHCP lease on uplink {}: {}", interface, e);
+                    }
+                    tokio::time::sleep(UPLINK_RETRY).await;
+                }
+            }));
+        }
         Ok(())
     }
 
+    /// SLAAC, and DHCPv6 when configured, on the primary interface
+    async fn configure_ipv6(&self) -> Result<Vec<Ipv6Addr>> {
+        let interface = self
+            .status
+            .read()
+            .await
+            .interface
+            .clone()
+            .or_else(|| self.config.interface.clone());
+        let mut client =
+            DhcpClient::new(interface).map_err(|e| NetworkError::DhcpFailed(e.to_string()))?;
+        let addresses = client
+            .acquire_ipv6(
+                self.config.ipv6.dhcpv6,
+                Duration::from_secs(self.config.ipv6.timeout),
+            )
+            .await
+            .map_err(|e| NetworkError::DhcpFailed(e.to_string()))?;
+        Ok(addresses)
+    }
+
+    /// Offline mode keeps a configured tunnel down
+    fn tunnel_enabled(&self) -> bool {
+        self.config.tunnel.enabled && !self.config.offline
+    }
+
+    fn primary_static(&self) -> Option<&StaticAddressing> {
+        let interface = self.config.interface.as_deref()?;
+        self.static_addresses
+            .iter()
+            .find(|s| s.interface() == interface)
+    }
+
     async fn update_dhcp_status(&self, dhcp_status: &crate::network::dhcp::DhcpStatus) {
         let mut status = self.status.write().await;
         status.interface = dhcp_status.interface.clone();
@@ -168,7 +594,16 @@ impl NetworkManager {
     }
 
     async fn set_state(&self, state: NetworkState) {
-        *self.state.write().await = state;
+        let mut current = self.state.write().await;
+        if *current == state {
+            return;
+        }
+        *current = state.clone();
+        drop(current);
+
+        if let Some(events) = &self.events {
+            events.publish(AppEvent::Network { state });
+        }
     }
 
     async fn set_error_message(&self, message: Option<String>) {
@@ -179,8 +614,10 @@ impl NetworkManager {
         let mut status = self.status.write().await;
         status.interface = None;
         status.ip_address = None;
+        status.ipv6_addresses.clear();
         status.hostname = None;
         status.tunnel_connected = false;
+        status.provisioning_isolated = false;
         status.error_message = None;
     }
 }
@@ -266,4 +703,21 @@ mod tests {
         let health = manager.health_check().await.unwrap();
         assert!(!health);
     }
+
+    #[tokio::test]
+    async fn test_offline_mode() {
+        let mut config = create_test_config();
+        config.tunnel.enabled = true;
+
+        let manager = NetworkManager::new(config.clone());
+        assert!(manager.tunnel_enabled());
+
+        // Offline keeps the tunnel down and a degraded network healthy
+        config.offline = true;
+        let manager = NetworkManager::new(config);
+        assert!(!manager.tunnel_enabled());
+        assert!(manager.tunnel_status().await.is_none());
+        manager.set_state(NetworkState::Degraded).await;
+        assert!(manager.health_check().await.unwrap());
+    }
 }
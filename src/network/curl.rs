@@ -0,0 +1,36 @@
+/// One line of a curl config file, read with `--config -`. Tokens and
+/// passwords go this way rather than on the command line, where every local
+/// user can read them from the process list.
+pub fn config_line(option: &str, value: &str) -> String {
+    let mut line = format!("{} = \"", option);
+    for c in value.chars() {
+        match c {
+            '\\' => line.push_str("\\\\"),
+            '"' => line.push_str("\\\""),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c => line.push(c),
+        }
+    }
+    line.push_str("\"\n");
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_line() {
+        assert_eq!(
+            config_line("header", "Authorization: Bearer s3cr3t"),
+            "header = \"Authorization: Bearer s3cr3t\"\n"
+        );
+        assert_eq!(
+            config_line("data-raw", "{\"message\":\"disk\\\\sdb\\nfailed\"}"),
+            "data-raw = \"{\\\"message\\\":\\\"disk\\\\\\\\sdb\\\\nfailed\\\"}\"\n"
+        );
+        assert_eq!(config_line("user", "tech:p\"w"), "user = \"tech:p\\\"w\"\n");
+    }
+}
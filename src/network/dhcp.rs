@@ -1,19 +1,78 @@
+pub mod packet;
+
 use crate::error::{UsbInstallerError, UsbInstallerResult};
-use std::net::Ipv4Addr;
+use crate::network::static_ip;
+use crate::service::supervisor::{ProcessSupervisor, STOP_TIMEOUT};
+use packet::{DhcpReply, DhcpRequest, MessageType, CLIENT_PORT, SERVER_PORT};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4};
 use std::process::{Command, Stdio};
 use std::str;
 use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
 use tokio::time;
+use uuid::Uuid;
+
+/// First wait for a reply, doubled on every retransmission
+const REPLY_TIMEOUT: Duration = Duration::from_secs(4);
+const TRANSMISSIONS: u32 = 4;
+/// Renewal and rebinding retries are never closer together than this
+const MIN_RETRANSMIT: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone)]
 pub struct DhcpLease {
     pub ip: Ipv4Addr,
+    /// Global IPv6 addresses from SLAAC or DHCPv6
+    pub ipv6: Vec<Ipv6Addr>,
+    pub prefix_len: u8,
     pub gateway: Option<Ipv4Addr>,
-    pub dns: Vec<Ipv4Addr>,
+    pub dns: Vec<IpAddr>,
+    pub domain: Option<String>,
+    /// Server that granted the lease, asked first when renewing
+    pub server: Ipv4Addr,
+    /// Seconds, as granted by the server
     pub lease_time: u32,
+    /// T1: when renewing starts
+    pub renewal_time: u32,
+    /// T2: when rebinding with any server starts
+    pub rebinding_time: u32,
     pub acquired_at: Instant,
 }
 
+impl DhcpLease {
+    fn from_ack(ack: &DhcpReply) -> UsbInstallerResult<Self> {
+        let server = ack.server_id.ok_or_else(|| {
+            UsbInstallerError::Network("DHCP ACK without server identifier".to_string())
+        })?;
+        let lease_time = ack
+            .lease_time
+            .ok_or_else(|| UsbInstallerError::Network("DHCP ACK without lease time".to_string()))?;
+        // RFC 2131 defaults: T1 at half the lease, T2 at seven eighths
+        let rebinding_time = ack
+            .rebinding_time
+            .unwrap_or((u64::from(lease_time) * 7 / 8) as u32);
+        let renewal_time = ack.renewal_time.unwrap_or(lease_time / 2);
+        let prefix_len = ack
+            .subnet_mask
+            .map(|mask| u32::from(mask).count_ones() as u8)
+            .unwrap_or(24);
+
+        Ok(Self {
+            ip: ack.your_ip,
+            ipv6: Vec::new(),
+            prefix_len,
+            gateway: ack.router,
+            dns: ack.dns.iter().copied().map(IpAddr::V4).collect(),
+            domain: ack.domain_name.clone(),
+            server,
+            lease_time,
+            renewal_time,
+            rebinding_time,
+            acquired_at: Instant::now(),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DhcpState {
     Down,
@@ -30,6 +89,8 @@ pub struct DhcpClient {
     state: DhcpState,
     retry_count: u32,
     max_retries: u32,
+    /// Metric of the default route via the leased gateway
+    route_metric: Option<u32>,
 }
 
 impl DhcpClient {
@@ -45,9 +106,17 @@ impl DhcpClient {
             state: DhcpState::Down,
             retry_count: 0,
             max_retries: 5,
+            route_metric: None,
         })
     }
 
+    /// Install the default route with `metric`, so uplinks of different
+    /// priority can each keep one
+    pub fn with_route_metric(mut self, metric: u32) -> Self {
+        self.route_metric = Some(metric);
+        self
+    }
+
     fn detect_interface() -> UsbInstallerResult<String> {
         let output = Command::new("ip")
             .args(&["link", "show"])
@@ -115,50 +184,50 @@ impl DhcpClient {
         Err(error)
     }
 
+    /// One DISCOVER/OFFER/REQUEST/ACK exchange, then the lease is applied
+    /// to the interface
     async fn attempt_dhcp_request(&self) -> UsbInstallerResult<DhcpLease> {
-        let output = Command::new("dhclient")
-            .args(&["-v", &self.interface])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| UsbInstallerError::Network(format!("Failed to run dhclient: {}", e)))?;
-
-        if !output.status.success() {
-            let stderr = str::from_utf8(&output.stderr).unwrap_or("Unknown error");
-            return Err(UsbInstallerError::Network(format!(
-                "dhclient failed: {}",
-                stderr
-            )));
-        }
-
-        self.parse_lease_info().await
+        self.run_ip(&["link", "set", &self.interface, "up"])?;
+        let mac = self.hardware_address()?;
+        let socket = self.open_socket()?;
+        let xid = Uuid::new_v4().as_u128() as u32;
+
+        let discover = DhcpRequest::discover(xid, mac, self.previous_address());
+        let offer = self
+            .exchange(
+                &socket,
+                &discover,
+                Ipv4Addr::BROADCAST,
+                &[MessageType::Offer],
+            )
+            .await?;
+        tracing::debug!(
+            "DHCP offer of {} from {:?} on {}",
+            offer.your_ip,
+            offer.server_id,
+            self.interface
+        );
+
+        let request = DhcpRequest::select(xid, mac, &offer);
+        let ack = self
+            .exchange(&socket, &request, Ipv4Addr::BROADCAST, &[MessageType::Ack])
+            .await?;
+        let lease = DhcpLease::from_ack(&ack)?;
+        self.configure_interface(&lease).await?;
+        Ok(lease)
     }
 
-    async fn parse_lease_info(&self) -> UsbInstallerResult<DhcpLease> {
+    /// Address left on the interface by an earlier lease, asked for again
+    /// so a restart keeps the same address
+    fn previous_address(&self) -> Option<Ipv4Addr> {
         let output = Command::new("ip")
-            .args(&["addr", "show", &self.interface])
+            .args(["-4", "addr", "show", "dev", &self.interface])
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
+            .stderr(Stdio::null())
             .output()
-            .map_err(|e| {
-                UsbInstallerError::Network(format!("Failed to get interface info: {}", e))
-            })?;
-
-        let output_str = str::from_utf8(&output.stdout).map_err(|e| {
-            UsbInstallerError::Network(format!("Invalid UTF-8 in interface info: {}", e))
-        })?;
-
-        let ip = self.extract_ip_address(output_str)?;
-        let gateway = self.get_gateway().await?;
-        let dns = self.get_dns_servers().await?;
-
-        Ok(DhcpLease {
-            ip,
-            gateway,
-            dns,
-            lease_time: 3600,
-            acquired_at: Instant::now(),
-        })
+            .ok()?;
+        let output_str = str::from_utf8(&output.stdout).ok()?;
+        self.extract_ip_address(output_str).ok()
     }
 
     fn extract_ip_address(&self, output: &str) -> UsbInstallerResult<Ipv4Addr> {
@@ -178,91 +247,414 @@ impl DhcpClient {
         ))
     }
 
-    async fn get_gateway(&self) -> UsbInstallerResult<Option<Ipv4Addr>> {
-        let output = Command::new("ip")
-            .args(&["route", "show", "default"])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| UsbInstallerError::Network(format!("Failed to get gateway: {}", e)))?;
+    fn hardware_address(&self) -> UsbInstallerResult<[u8; 6]> {
+        let path = format!("/sys/class/net/{}/address", self.interface);
+        let address = std::fs::read_to_string(&path)
+            .map_err(|e| UsbInstallerError::Network(format!("Failed to read {}: {}", path, e)))?;
+        parse_mac(&address).ok_or_else(|| {
+            UsbInstallerError::Network(format!(
+                "{} has no Ethernet address: {}",
+                self.interface,
+                address.trim()
+            ))
+        })
+    }
 
-        let output_str = str::from_utf8(&output.stdout).unwrap_or("");
+    fn open_socket(&self) -> UsbInstallerResult<UdpSocket> {
+        UdpSocket::from_std(self.bind_socket()?)
+            .map_err(|e| UsbInstallerError::Network(format!("Failed to open DHCP socket: {}", e)))
+    }
 
-        for line in output_str.lines() {
-            if line.contains("default via") {
-                if let Some(gateway_str) = line.split_whitespace().nth(2) {
-                    if let Ok(gateway) = gateway_str.parse::<Ipv4Addr>() {
-                        return Ok(Some(gateway));
-                    }
+    /// UDP socket on the client port, tied to the interface so requests
+    /// leave through it even before it has an address
+    fn bind_socket(&self) -> UsbInstallerResult<std::net::UdpSocket> {
+        let socket_error = |e: std::io::Error| {
+            UsbInstallerError::Network(format!(
+                "Failed to open DHCP socket on {}: {}",
+                self.interface, e
+            ))
+        };
+        let socket =
+            Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).map_err(socket_error)?;
+        socket.set_reuse_address(true).map_err(socket_error)?;
+        socket.set_broadcast(true).map_err(socket_error)?;
+        socket
+            .bind_device(Some(self.interface.as_bytes()))
+            .map_err(socket_error)?;
+        socket
+            .bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, CLIENT_PORT).into())
+            .map_err(socket_error)?;
+        socket.set_nonblocking(true).map_err(socket_error)?;
+        Ok(socket.into())
+    }
+
+    /// Send `request` and wait for a reply of one of the `accepted` types,
+    /// retransmitting with a doubling timeout. A NAK is turned into an error
+    /// unless it is accepted.
+    async fn exchange(
+        &self,
+        socket: &UdpSocket,
+        request: &DhcpRequest,
+        destination: Ipv4Addr,
+        accepted: &[MessageType],
+    ) -> UsbInstallerResult<DhcpReply> {
+        let message = request.encode();
+        let mut buf = [0u8; 1500];
+        let mut wait = REPLY_TIMEOUT;
+
+        for _ in 0..TRANSMISSIONS {
+            socket
+                .send_to(&message, SocketAddrV4::new(destination, SERVER_PORT))
+                .await
+                .map_err(|e| {
+                    UsbInstallerError::Network(format!("Failed to send DHCP message: {}", e))
+                })?;
+
+            let deadline = time::Instant::now() + wait;
+            while let Ok(received) = time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+                let (len, _) = received.map_err(|e| {
+                    UsbInstallerError::Network(format!("Failed to receive DHCP reply: {}", e))
+                })?;
+                let reply = match DhcpReply::parse(&buf[..len], request.mac) {
+                    Some(reply) if reply.xid == request.xid => reply,
+                    _ => continue,
+                };
+                if accepted.contains(&reply.message_type) {
+                    return Ok(reply);
+                }
+                if reply.message_type == MessageType::Nak {
+                    return Err(UsbInstallerError::Network(format!(
+                        "DHCP server {:?} declined the request on {}",
+                        reply.server_id, self.interface
+                    )));
                 }
             }
+            wait *= 2;
+        }
+
+        Err(UsbInstallerError::Network(format!(
+            "No DHCP reply to {:?} on {}",
+            request.message_type, self.interface
+        )))
+    }
+
+    /// Assign the leased address with the lease lifetime, so the kernel
+    /// drops it if the lease is not extended, plus the route and DNS
+    async fn configure_interface(&self, lease: &DhcpLease) -> UsbInstallerResult<()> {
+        let address = format!("{}/{}", lease.ip, lease.prefix_len);
+        let lifetime = lease.lease_time.to_string();
+        self.run_ip(&[
+            "addr",
+            "replace",
+            &address,
+            "dev",
+            &self.interface,
+            "valid_lft",
+            &lifetime,
+            "preferred_lft",
+            &lifetime,
+        ])?;
+        if let Some(gateway) = lease.gateway {
+            let gateway = gateway.to_string();
+            let metric = self.route_metric.unwrap_or(0).to_string();
+            self.run_ip(&[
+                "route",
+                "replace",
+                "default",
+                "via",
+                &gateway,
+                "dev",
+                &self.interface,
+                "metric",
+                &metric,
+            ])?;
+        }
+
+        let dns: Vec<Ipv4Addr> = lease
+            .dns
+            .iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(*ip),
+                IpAddr::V6(_) => None,
+            })
+            .collect();
+        let search: Vec<String> = lease.domain.iter().cloned().collect();
+        if let Err(e) = static_ip::apply_dns(&self.interface, &dns, &search).await {
+            tracing::warn!("Failed to apply DNS from DHCP lease: {}", e);
         }
-        Ok(None)
+        Ok(())
     }
 
-    async fn get_dns_servers(&self) -> UsbInstallerResult<Vec<Ipv4Addr>> {
-        let mut dns_servers = Vec::new();
+    fn remove_address(&self, lease: &DhcpLease) {
+        let address = format!("{}/{}", lease.ip, lease.prefix_len);
+        if let Err(e) = self.run_ip(&["addr", "del", &address, "dev", &self.interface]) {
+            tracing::warn!("Failed to remove {}: {}", address, e);
+        }
+    }
 
-        if let Ok(content) = std::fs::read_to_string("/etc/resolv.conf") {
-            for line in content.lines() {
-                if line.starts_with("nameserver ") {
-                    if let Some(dns_str) = line.split_whitespace().nth(1) {
-                        if let Ok(dns) = dns_str.parse::<Ipv4Addr>() {
-                            dns_servers.push(dns);
-                        }
-                    }
+    fn run_ip(&self, args: &[&str]) -> UsbInstallerResult<()> {
+        let output = Command::new("ip")
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|e| UsbInstallerError::Network(format!("Failed to run ip: {}", e)))?;
+        if !output.status.success() {
+            let stderr = str::from_utf8(&output.stderr).unwrap_or("Unknown error");
+            return Err(UsbInstallerError::Network(format!(
+                "ip {} failed: {}",
+                args.join(" "),
+                stderr.trim()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Bring up IPv6 on the interface: accept router advertisements for
+    /// SLAAC and, with `dhcpv6`, also ask a DHCPv6 server. Returns the
+    /// global addresses present once one appears or `timeout` passes.
+    pub async fn acquire_ipv6(
+        &mut self,
+        dhcpv6: bool,
+        timeout: Duration,
+    ) -> UsbInstallerResult<Vec<Ipv6Addr>> {
+        // accept_ra=2 keeps SLAAC working when forwarding is on for NAT
+        for (key, value) in [("disable_ipv6", "0"), ("accept_ra", "2"), ("autoconf", "1")] {
+            let setting = format!("net.ipv6.conf.{}.{}={}", self.interface, key, value);
+            let output = Command::new("sysctl")
+                .args(["-w", &setting])
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .output()
+                .map_err(|e| UsbInstallerError::Network(format!("Failed to run sysctl: {}", e)))?;
+            if !output.status.success() {
+                let stderr = str::from_utf8(&output.stderr).unwrap_or("Unknown error");
+                return Err(UsbInstallerError::Network(format!(
+                    "sysctl {} failed: {}",
+                    setting, stderr
+                )));
+            }
+        }
+
+        if dhcpv6 {
+            let mut cmd = tokio::process::Command::new("dhclient");
+            cmd.args(["-6", "-v", &self.interface]);
+            let mut process = ProcessSupervisor::global()
+                .spawn("dhclient", cmd)
+                .map_err(|e| {
+                    UsbInstallerError::Network(format!("Failed to run dhclient: {}", e))
+                })?;
+            // dhclient forks into the background once it has a lease
+            let status = match time::timeout(timeout, process.wait()).await {
+                Ok(status) => status.ok(),
+                Err(_) => process.stop(STOP_TIMEOUT).await.ok(),
+            };
+            if !status.is_some_and(|status| status.success()) {
+                // SLAAC may still provide an address
+                tracing::warn!("DHCPv6 request on {} failed", self.interface);
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let addresses = self.ipv6_addresses()?;
+            if !addresses.is_empty() || Instant::now() >= deadline {
+                tracing::info!("IPv6 addresses on {}: {:?}", self.interface, addresses);
+                if let Some(lease) = &mut self.current_lease {
+                    lease.ipv6 = addresses.clone();
                 }
+                return Ok(addresses);
             }
+            time::sleep(Duration::from_secs(1)).await;
         }
+    }
+
+    fn ipv6_addresses(&self) -> UsbInstallerResult<Vec<Ipv6Addr>> {
+        let output = Command::new("ip")
+            .args([
+                "-6",
+                "addr",
+                "show",
+                "dev",
+                &self.interface,
+                "scope",
+                "global",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|e| {
+                UsbInstallerError::Network(format!("Failed to get interface info: {}", e))
+            })?;
+        let output_str = str::from_utf8(&output.stdout).unwrap_or("");
+        Ok(self.extract_ipv6_addresses(output_str))
+    }
 
-        Ok(dns_servers)
+    /// Usable global addresses; ones still in or failing duplicate address
+    /// detection are skipped
+    fn extract_ipv6_addresses(&self, output: &str) -> Vec<Ipv6Addr> {
+        output
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("inet6 "))
+            .filter(|line| !line.contains("tentative") && !line.contains("dadfailed"))
+            .filter_map(|line| {
+                line.split_whitespace()
+                    .nth(1)?
+                    .split('/')
+                    .next()?
+                    .parse()
+                    .ok()
+            })
+            .collect()
     }
 
+    /// Extend the lease with the server that granted it (RENEWING state,
+    /// from T1)
     pub async fn renew_lease(&mut self) -> UsbInstallerResult<()> {
+        let server = match &self.current_lease {
+            Some(lease) => lease.server,
+            None => {
+                return Err(UsbInstallerError::Network(
+                    "No active lease to renew".to_string(),
+                ))
+            }
+        };
+
+        self.state = DhcpState::Renewing;
+        tracing::info!("Renewing DHCP lease for interface {}", self.interface);
+        self.extend_lease(server).await
+    }
+
+    /// Extend the lease with any server once the one that granted it has not
+    /// answered by T2 (REBINDING state)
+    pub async fn rebind_lease(&mut self) -> UsbInstallerResult<()> {
         if self.current_lease.is_none() {
             return Err(UsbInstallerError::Network(
-                "No active lease to renew".to_string(),
+                "No active lease to rebind".to_string(),
             ));
         }
 
-        self.state = DhcpState::Renewing;
-        log::info!("Renewing DHCP lease for interface {}", self.interface);
+        self.state = DhcpState::Rebinding;
+        tracing::info!("Rebinding DHCP lease for interface {}", self.interface);
+        self.extend_lease(Ipv4Addr::BROADCAST).await
+    }
 
-        match self.request_lease().await {
-            Ok(_) => {
-                log::info!("DHCP lease renewed successfully");
-                Ok(())
+    async fn extend_lease(&mut self, destination: Ipv4Addr) -> UsbInstallerResult<()> {
+        let ip = match &self.current_lease {
+            Some(lease) => lease.ip,
+            None => return Ok(()),
+        };
+        let mac = self.hardware_address()?;
+        let socket = self.open_socket()?;
+        let request = DhcpRequest::extend(Uuid::new_v4().as_u128() as u32, mac, ip);
+
+        let accepted = [MessageType::Ack, MessageType::Nak];
+        let result = self
+            .exchange(&socket, &request, destination, &accepted)
+            .await;
+        let lease = match result {
+            Ok(reply) if reply.message_type == MessageType::Nak => {
+                // The address is no longer ours; start over with DISCOVER
+                tracing::warn!("DHCP server refused to extend the lease on {}", ip);
+                if let Some(old) = self.current_lease.take() {
+                    self.remove_address(&old);
+                }
+                self.state = DhcpState::Down;
+                return Err(UsbInstallerError::Network(format!(
+                    "DHCP lease for {} was refused",
+                    ip
+                )));
             }
+            Ok(ack) => DhcpLease::from_ack(&ack)?,
             Err(e) => {
-                log::error!("Failed to renew DHCP lease: {}", e);
-                self.state = DhcpState::Error(e.to_string());
-                Err(e)
+                tracing::warn!("Failed to extend DHCP lease: {}", e);
+                return Err(e);
+            }
+        };
+
+        if lease.ip != ip {
+            // Servers should not move a bound client, but never keep both
+            if let Some(old) = self.current_lease.take() {
+                self.remove_address(&old);
             }
         }
+        self.configure_interface(&lease).await?;
+        tracing::info!(
+            "DHCP lease for {} extended by {} seconds",
+            lease.ip,
+            lease.lease_time
+        );
+        self.current_lease = Some(lease);
+        self.state = DhcpState::Bound;
+        Ok(())
     }
 
-    pub fn release_lease(&mut self) -> UsbInstallerResult<()> {
-        if self.current_lease.is_none() {
-            return Ok(());
+    /// Keep the lease alive: renew from T1, rebind from T2 and start over
+    /// with DISCOVER once it has expired. Only returns when a new lease
+    /// cannot be acquired.
+    pub async fn maintain_lease(&mut self) -> UsbInstallerResult<()> {
+        loop {
+            let lease = match &self.current_lease {
+                Some(lease) => lease.clone(),
+                None => {
+                    self.request_lease().await?;
+                    continue;
+                }
+            };
+
+            let elapsed = lease.acquired_at.elapsed();
+            let renew_at = Duration::from_secs(lease.renewal_time.into());
+            let rebind_at = Duration::from_secs(lease.rebinding_time.into());
+            let expires_at = Duration::from_secs(lease.lease_time.into());
+
+            if elapsed < renew_at {
+                time::sleep(renew_at - elapsed).await;
+            } else if elapsed < rebind_at {
+                if self.renew_lease().await.is_err() {
+                    time::sleep(retransmit_delay(rebind_at - elapsed)).await;
+                }
+            } else if elapsed < expires_at {
+                if self.rebind_lease().await.is_err() {
+                    time::sleep(retransmit_delay(expires_at - elapsed)).await;
+                }
+            } else {
+                tracing::warn!("DHCP lease for {} expired", lease.ip);
+                self.remove_address(&lease);
+                self.current_lease = None;
+                self.state = DhcpState::Down;
+            }
         }
+    }
 
-        log::info!("Releasing DHCP lease for interface {}", self.interface);
-
-        let output = Command::new("dhclient")
-            .args(&["-r", &self.interface])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| UsbInstallerError::Network(format!("Failed to release lease: {}", e)))?;
+    pub fn release_lease(&mut self) -> UsbInstallerResult<()> {
+        let lease = match self.current_lease.take() {
+            Some(lease) => lease,
+            None => return Ok(()),
+        };
 
-        if !output.status.success() {
-            let stderr = str::from_utf8(&output.stderr).unwrap_or("Unknown error");
-            log::warn!("dhclient release warning: {}", stderr);
+        tracing::info!("Releasing DHCP lease for interface {}", self.interface);
+
+        let release = self.hardware_address().and_then(|mac| {
+            let request =
+                DhcpRequest::release(Uuid::new_v4().as_u128() as u32, mac, lease.ip, lease.server);
+            self.bind_socket()?
+                .send_to(
+                    &request.encode(),
+                    SocketAddrV4::new(lease.server, SERVER_PORT),
+                )
+                .map_err(|e| {
+                    UsbInstallerError::Network(format!("Failed to send DHCPRELEASE: {}", e))
+                })
+        });
+        if let Err(e) = release {
+            // The server reclaims the address once the lease runs out
+            tracing::warn!("DHCP release warning: {}", e);
         }
+        self.remove_address(&lease);
 
-        self.current_lease = None;
         self.state = DhcpState::Down;
-        log::info!("DHCP lease released");
+        tracing::info!("DHCP lease released");
         Ok(())
     }
 
@@ -302,6 +694,20 @@ impl DhcpClient {
     }
 }
 
+/// Wait half the time left before the next state, as RFC 2131 suggests
+fn retransmit_delay(remaining: Duration) -> Duration {
+    (remaining / 2).max(MIN_RETRANSMIT).min(remaining)
+}
+
+fn parse_mac(address: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut octets = address.trim().split(':');
+    for byte in &mut mac {
+        *byte = u8::from_str_radix(octets.next()?, 16).ok()?;
+    }
+    octets.next().is_none().then_some(mac)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -323,6 +729,19 @@ mod tests {
         assert_eq!(ip, Ipv4Addr::new(192, 168, 1, 100));
     }
 
+    #[test]
+    fn test_ipv6_address_extraction() {
+        let client = DhcpClient::new(Some("eth0".to_string())).unwrap();
+        let output = "2: eth0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 state UP qlen 1000\n    inet6 2001:db8::5054:ff:fe12:3456/64 scope global dynamic mngtmpaddr noprefixroute\n       valid_lft 86380sec preferred_lft 14380sec\n    inet6 2001:db8::10/128 scope global tentative dynamic\n    inet6 fd00::1/64 scope global\n";
+        assert_eq!(
+            client.extract_ipv6_addresses(output),
+            vec![
+                "2001:db8::5054:ff:fe12:3456".parse::<Ipv6Addr>().unwrap(),
+                "fd00::1".parse::<Ipv6Addr>().unwrap(),
+            ]
+        );
+    }
+
     #[test]
     fn test_lease_expiration() {
         let mut client = DhcpClient::new(Some("eth0".to_string())).unwrap();
@@ -330,12 +749,61 @@ mod tests {
 
         client.current_lease = Some(DhcpLease {
             ip: Ipv4Addr::new(192, 168, 1, 100),
+            ipv6: vec![],
+            prefix_len: 24,
             gateway: None,
             dns: vec![],
+            domain: None,
+            server: Ipv4Addr::new(192, 168, 1, 1),
             lease_time: 3600,
+            renewal_time: 1800,
+            rebinding_time: 3150,
             acquired_at: Instant::now(),
         });
 
         assert!(!client.is_lease_expired());
     }
+
+    #[test]
+    fn test_lease_from_ack() {
+        let mut ack = DhcpReply {
+            message_type: MessageType::Ack,
+            xid: 1,
+            your_ip: Ipv4Addr::new(10, 0, 8, 20),
+            server_id: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            subnet_mask: Some(Ipv4Addr::new(255, 255, 248, 0)),
+            router: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            dns: vec![Ipv4Addr::new(10, 0, 0, 2)],
+            domain_name: None,
+            lease_time: Some(7200),
+            renewal_time: None,
+            rebinding_time: None,
+        };
+        let lease = DhcpLease::from_ack(&ack).unwrap();
+        assert_eq!(lease.prefix_len, 21);
+        assert_eq!(lease.lease_time, 7200);
+        assert_eq!((lease.renewal_time, lease.rebinding_time), (3600, 6300));
+
+        ack.renewal_time = Some(600);
+        ack.rebinding_time = Some(900);
+        let lease = DhcpLease::from_ack(&ack).unwrap();
+        assert_eq!((lease.renewal_time, lease.rebinding_time), (600, 900));
+
+        ack.lease_time = None;
+        assert!(DhcpLease::from_ack(&ack).is_err());
+
+        assert_eq!(
+            retransmit_delay(Duration::from_secs(600)),
+            Duration::from_secs(300)
+        );
+        assert_eq!(
+            retransmit_delay(Duration::from_secs(30)),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            parse_mac("52:54:00:12:34:56\n"),
+            Some([0x52, 0x54, 0x00, 0x12, 0x34, 0x56])
+        );
+        assert_eq!(parse_mac("00:00:00:00:00:00:00:00"), None);
+    }
 }
@@ -0,0 +1,323 @@
+use std::net::Ipv4Addr;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const ETHERNET: u8 = 1;
+/// Ask the server to broadcast its reply; the client has no address yet
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Fixed BOOTP header up to and including the magic cookie
+const HEADER_LEN: usize = 240;
+/// Some relays drop BOOTP messages shorter than this
+const MIN_LEN: usize = 300;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_DOMAIN_NAME: u8 = 15;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETERS: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_CLIENT_ID: u8 = 61;
+const OPT_END: u8 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => Self::Discover,
+            2 => Self::Offer,
+            3 => Self::Request,
+            4 => Self::Decline,
+            5 => Self::Ack,
+            6 => Self::Nak,
+            7 => Self::Release,
+            _ => return None,
+        })
+    }
+}
+
+/// A message from the client to the server
+#[derive(Debug, Clone)]
+pub struct DhcpRequest {
+    pub message_type: MessageType,
+    pub xid: u32,
+    pub mac: [u8; 6],
+    /// Address currently held, when renewing, rebinding or releasing
+    pub client_ip: Option<Ipv4Addr>,
+    /// Address taken from an offer, or held before a restart
+    pub requested_ip: Option<Ipv4Addr>,
+    pub server_id: Option<Ipv4Addr>,
+}
+
+impl DhcpRequest {
+    /// Look for servers, hinting at `previous` to get the same address back
+    pub fn discover(xid: u32, mac: [u8; 6], previous: Option<Ipv4Addr>) -> Self {
+        Self {
+            message_type: MessageType::Discover,
+            xid,
+            mac,
+            client_ip: None,
+            requested_ip: previous,
+            server_id: None,
+        }
+    }
+
+    /// Accept `offer` (SELECTING state)
+    pub fn select(xid: u32, mac: [u8; 6], offer: &DhcpReply) -> Self {
+        Self {
+            message_type: MessageType::Request,
+            requested_ip: Some(offer.your_ip),
+            server_id: offer.server_id,
+            ..Self::discover(xid, mac, None)
+        }
+    }
+
+    /// Extend the lease on `ip` (RENEWING and REBINDING states)
+    pub fn extend(xid: u32, mac: [u8; 6], ip: Ipv4Addr) -> Self {
+        Self {
+            message_type: MessageType::Request,
+            client_ip: Some(ip),
+            ..Self::discover(xid, mac, None)
+        }
+    }
+
+    pub fn release(xid: u32, mac: [u8; 6], ip: Ipv4Addr, server: Ipv4Addr) -> Self {
+        Self {
+            message_type: MessageType::Release,
+            client_ip: Some(ip),
+            server_id: Some(server),
+            ..Self::discover(xid, mac, None)
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(MIN_LEN);
+        buf.extend([BOOTREQUEST, ETHERNET, 6, 0]);
+        buf.extend(self.xid.to_be_bytes());
+        buf.extend([0, 0]); // secs
+                            // Without an address the reply cannot be unicast to us
+        let flags = if self.client_ip.is_none() {
+            FLAG_BROADCAST
+        } else {
+            0
+        };
+        buf.extend(flags.to_be_bytes());
+        buf.extend(self.client_ip.unwrap_or(Ipv4Addr::UNSPECIFIED).octets());
+        buf.extend([0; 12]); // yiaddr, siaddr, giaddr
+        buf.extend(self.mac);
+        buf.extend([0; 10 + 64 + 128]); // chaddr padding, sname, file
+        buf.extend(MAGIC_COOKIE);
+
+        push_option(&mut buf, OPT_MESSAGE_TYPE, &[self.message_type as u8]);
+        let mut client_id = vec![ETHERNET];
+        client_id.extend(self.mac);
+        push_option(&mut buf, OPT_CLIENT_ID, &client_id);
+        if let Some(ip) = self.requested_ip {
+            push_option(&mut buf, OPT_REQUESTED_IP, &ip.octets());
+        }
+        if let Some(server) = self.server_id {
+            push_option(&mut buf, OPT_SERVER_ID, &server.octets());
+        }
+        if self.message_type != MessageType::Release {
+            push_option(
+                &mut buf,
+                OPT_PARAMETERS,
+                &[
+                    OPT_SUBNET_MASK,
+                    OPT_ROUTER,
+                    OPT_DNS,
+                    OPT_DOMAIN_NAME,
+                    OPT_LEASE_TIME,
+                    OPT_RENEWAL_TIME,
+                    OPT_REBINDING_TIME,
+                ],
+            );
+        }
+        buf.push(OPT_END);
+        if buf.len() < MIN_LEN {
+            buf.resize(MIN_LEN, OPT_PAD);
+        }
+        buf
+    }
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u8, data: &[u8]) {
+    buf.push(code);
+    buf.push(data.len() as u8);
+    buf.extend(data);
+}
+
+/// An OFFER, ACK or NAK from a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpReply {
+    pub message_type: MessageType,
+    pub xid: u32,
+    pub your_ip: Ipv4Addr,
+    pub server_id: Option<Ipv4Addr>,
+    pub subnet_mask: Option<Ipv4Addr>,
+    pub router: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+    pub domain_name: Option<String>,
+    /// Seconds
+    pub lease_time: Option<u32>,
+    pub renewal_time: Option<u32>,
+    pub rebinding_time: Option<u32>,
+}
+
+impl DhcpReply {
+    /// Parse a server message meant for the client with hardware address
+    /// `mac`. Anything else on the port yields `None`.
+    pub fn parse(buf: &[u8], mac: [u8; 6]) -> Option<Self> {
+        if buf.len() < HEADER_LEN || buf[0] != BOOTREPLY || buf[28..34] != mac {
+            return None;
+        }
+        if buf[236..240] != MAGIC_COOKIE {
+            return None;
+        }
+
+        let mut reply = DhcpReply {
+            message_type: MessageType::Nak,
+            xid: u32::from_be_bytes(buf[4..8].try_into().ok()?),
+            your_ip: ipv4(&buf[16..20])?,
+            server_id: None,
+            subnet_mask: None,
+            router: None,
+            dns: Vec::new(),
+            domain_name: None,
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+        };
+        let mut message_type = None;
+
+        let mut options = &buf[HEADER_LEN..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPT_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPT_END => break,
+                _ => {}
+            }
+            let (&len, rest) = rest.split_first()?;
+            let data = rest.get(..len as usize)?;
+            options = &rest[len as usize..];
+            match code {
+                OPT_MESSAGE_TYPE => message_type = data.first().copied(),
+                OPT_SERVER_ID => reply.server_id = ipv4(data),
+                OPT_SUBNET_MASK => reply.subnet_mask = ipv4(data),
+                OPT_ROUTER => reply.router = ipv4(data.get(..4)?),
+                OPT_DNS => reply.dns = data.chunks_exact(4).filter_map(ipv4).collect(),
+                OPT_DOMAIN_NAME => {
+                    reply.domain_name = Some(String::from_utf8_lossy(data).into_owned())
+                }
+                OPT_LEASE_TIME => reply.lease_time = seconds(data),
+                OPT_RENEWAL_TIME => reply.renewal_time = seconds(data),
+                OPT_REBINDING_TIME => reply.rebinding_time = seconds(data),
+                _ => {}
+            }
+        }
+
+        reply.message_type = MessageType::from_u8(message_type?)?;
+        Some(reply)
+    }
+}
+
+fn ipv4(data: &[u8]) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = data.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+fn seconds(data: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(data.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+    /// What a server would answer to `request`
+    fn reply_to(request: &[u8], message_type: MessageType) -> Vec<u8> {
+        let mut buf = request[..HEADER_LEN].to_vec();
+        buf[0] = BOOTREPLY;
+        buf[16..20].copy_from_slice(&[192, 168, 1, 50]);
+        push_option(&mut buf, OPT_MESSAGE_TYPE, &[message_type as u8]);
+        push_option(&mut buf, OPT_SERVER_ID, &[192, 168, 1, 1]);
+        push_option(&mut buf, OPT_SUBNET_MASK, &[255, 255, 255, 0]);
+        push_option(&mut buf, OPT_ROUTER, &[192, 168, 1, 1]);
+        push_option(&mut buf, OPT_DNS, &[192, 168, 1, 1, 9, 9, 9, 9]);
+        push_option(&mut buf, OPT_DOMAIN_NAME, b"lab.example.com");
+        buf.push(OPT_PAD);
+        push_option(&mut buf, OPT_LEASE_TIME, &86400u32.to_be_bytes());
+        buf.push(OPT_END);
+        buf
+    }
+
+    #[test]
+    fn test_encode_discover() {
+        let buf = DhcpRequest::discover(0xdeadbeef, MAC, None).encode();
+        assert_eq!(buf.len(), MIN_LEN);
+        assert_eq!(&buf[..4], &[BOOTREQUEST, ETHERNET, 6, 0]);
+        assert_eq!(&buf[4..8], &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(&buf[10..12], &FLAG_BROADCAST.to_be_bytes());
+        assert_eq!(&buf[28..34], &MAC);
+        assert_eq!(&buf[236..240], &MAGIC_COOKIE);
+        assert_eq!(&buf[240..243], &[OPT_MESSAGE_TYPE, 1, 1]);
+
+        let renew = DhcpRequest::extend(1, MAC, Ipv4Addr::new(192, 168, 1, 50)).encode();
+        assert_eq!(&renew[10..12], &[0, 0]);
+        assert_eq!(&renew[12..16], &[192, 168, 1, 50]);
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let request = DhcpRequest::discover(7, MAC, None).encode();
+        let offer = DhcpReply::parse(&reply_to(&request, MessageType::Offer), MAC).unwrap();
+        assert_eq!(offer.message_type, MessageType::Offer);
+        assert_eq!(offer.xid, 7);
+        assert_eq!(offer.your_ip, Ipv4Addr::new(192, 168, 1, 50));
+        assert_eq!(offer.server_id, Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(offer.subnet_mask, Some(Ipv4Addr::new(255, 255, 255, 0)));
+        assert_eq!(
+            offer.dns,
+            vec![Ipv4Addr::new(192, 168, 1, 1), Ipv4Addr::new(9, 9, 9, 9)]
+        );
+        assert_eq!(offer.domain_name.as_deref(), Some("lab.example.com"));
+        assert_eq!(offer.lease_time, Some(86400));
+        assert_eq!(offer.renewal_time, None);
+
+        let select = DhcpRequest::select(7, MAC, &offer).encode();
+        assert!(select
+            .windows(6)
+            .any(|w| w == [OPT_REQUESTED_IP, 4, 192, 168, 1, 50]));
+
+        // Replies for another client and truncated options are ignored
+        let other = [0x52, 0x54, 0x00, 0xff, 0xff, 0xff];
+        assert!(DhcpReply::parse(&reply_to(&request, MessageType::Ack), other).is_none());
+        let mut truncated = reply_to(&request, MessageType::Ack);
+        truncated.truncate(HEADER_LEN + 5);
+        assert!(DhcpReply::parse(&truncated, MAC).is_none());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::config::DiscoveryConfig;
+use crate::error::Result;
+use crate::monitoring::clock::unix_now;
+use crate::network::mdns::{self, Record, RecordData, MDNS_PORT, MDNS_V4, SERVICE_TYPE, TYPE_PTR};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Bounds the cache against a flood of bogus instances
+const MAX_PEERS: usize = 256;
+
+/// Another node seen on the LAN
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Peer {
+    /// Service instance, e.g. `usb-node-1a2b._usb-installer._tcp.local`
+    pub instance: String,
+    /// Target of the SRV record, e.g. `usb-node-1a2b.local`
+    pub hostname: Option<String>,
+    pub addresses: Vec<IpAddr>,
+    /// REST API port
+    pub port: Option<u16>,
+    pub version: Option<String>,
+    /// Remaining TXT entries, e.g. `arch`, `fs`, `vnc_port`
+    pub capabilities: BTreeMap<String, String>,
+    /// Unix seconds of the last record received for the instance
+    pub last_seen: u64,
+}
+
+struct Instance {
+    name: String,
+    expires: Instant,
+    srv: Option<(String, u16)>,
+    txt: Vec<String>,
+    last_seen: u64,
+}
+
+/// Records learned from responses, each dropped when its TTL runs out.
+/// Names are keyed in lowercase.
+#[derive(Default)]
+struct PeerCache {
+    instances: HashMap<String, Instance>,
+    /// Addresses by host name
+    hosts: HashMap<String, HashMap<IpAddr, Instant>>,
+}
+
+impl PeerCache {
+    fn update(&mut self, records: Vec<Record>, now: Instant, seen: u64) {
+        let suffix = format!(".{}", SERVICE_TYPE);
+        for record in records {
+            let expires = now + Duration::from_secs(record.ttl.into());
+            let (name, data) = match record.data {
+                RecordData::Ptr(instance) if record.name.eq_ignore_ascii_case(SERVICE_TYPE) => {
+                    (instance, None)
+                }
+                RecordData::A(ip) => {
+                    self.update_host(&record.name, ip.into(), record.ttl, expires);
+                    continue;
+                }
+                RecordData::Aaaa(ip) => {
+                    self.update_host(&record.name, ip.into(), record.ttl, expires);
+                    continue;
+                }
+                data @ (RecordData::Srv { .. } | RecordData::Txt(_)) => (record.name, Some(data)),
+                RecordData::Ptr(_) => continue,
+            };
+            let key = name.to_ascii_lowercase();
+            if !key.ends_with(&suffix) {
+                continue;
+            }
+
+            // TTL 0 is a goodbye
+            if record.ttl == 0 {
+                if data.is_none() && self.instances.remove(&key).is_some() {
+                    info!("Peer node {} left", name);
+                }
+                continue;
+            }
+            if !self.instances.contains_key(&key) {
+                if self.instances.len() >= MAX_PEERS {
+                    continue;
+                }
+                debug!("Discovered peer node {}", name);
+            }
+            let instance = self.instances.entry(key).or_insert_with(|| Instance {
+                name,
+                expires,
+                srv: None,
+                txt: Vec::new(),
+                last_seen: seen,
+            });
+            instance.expires = instance.expires.max(expires);
+            instance.last_seen = seen;
+            match data {
+                Some(RecordData::Srv { port, target }) => instance.srv = Some((target, port)),
+                Some(RecordData::Txt(txt)) => instance.txt = txt,
+                _ => {}
+            }
+        }
+    }
+
+    fn update_host(&mut self, host: &str, address: IpAddr, ttl: u32, expires: Instant) {
+        let key = host.to_ascii_lowercase();
+        if ttl == 0 {
+            if let Some(addresses) = self.hosts.get_mut(&key) {
+                addresses.remove(&address);
+            }
+        } else if self.hosts.contains_key(&key) || self.hosts.len() < MAX_PEERS {
+            self.hosts.entry(key).or_default().insert(address, expires);
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        self.instances.retain(|_, instance| {
+            let live = instance.expires > now;
+            if !live {
+                info!("Peer node {} expired", instance.name);
+            }
+            live
+        });
+        for addresses in self.hosts.values_mut() {
+            addresses.retain(|_, expires| *expires > now);
+        }
+        self.hosts.retain(|_, addresses| !addresses.is_empty());
+    }
+
+    /// Live instances other than `own`, sorted by name
+    fn peers(&self, own: Option<&str>) -> Vec<Peer> {
+        let mut peers: Vec<Peer> = self
+            .instances
+            .iter()
+            .filter(|(key, _)| Some(key.as_str()) != own)
+            .map(|(_, instance)| {
+                let mut addresses: Vec<IpAddr> = instance
+                    .srv
+                    .as_ref()
+                    .and_then(|(target, _)| self.hosts.get(&target.to_ascii_lowercase()))
+                    .map(|addresses| addresses.keys().copied().collect())
+                    .unwrap_or_default();
+                addresses.sort();
+
+                let mut version = None;
+                let mut capabilities = BTreeMap::new();
+                for entry in &instance.txt {
+                    let (key, value) = entry.split_once('=').unwrap_or((entry, ""));
+                    if key == "version" {
+                        version = Some(value.to_string());
+                    } else {
+                        capabilities.insert(key.to_string(), value.to_string());
+                    }
+                }
+
+                Peer {
+                    instance: instance.name.clone(),
+                    hostname: instance.srv.as_ref().map(|(target, _)| target.clone()),
+                    addresses,
+                    port: instance.srv.as_ref().map(|(_, port)| *port),
+                    version,
+                    capabilities,
+                    last_seen: instance.last_seen,
+                }
+            })
+            .collect();
+        peers.sort_by(|a, b| a.instance.cmp(&b.instance));
+        peers
+    }
+}
+
+/// Browses for other nodes advertising `_usb-installer._tcp` on the IPv4
+/// mDNS group and keeps the list of the ones currently present
+#[derive(Clone)]
+pub struct PeerDiscovery {
+    config: DiscoveryConfig,
+    cache: Arc<RwLock<PeerCache>>,
+    /// This node's instance, left out of the list
+    own_instance: Arc<RwLock<Option<String>>>,
+    browser: Arc<RwLock<Option<JoinHandle<()>>>>,
+}
+
+impl PeerDiscovery {
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            config,
+            cache: Arc::new(RwLock::new(PeerCache::default())),
+            own_instance: Arc::new(RwLock::new(None)),
+            browser: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Start browsing; `hostname` is this node's, whose own service is not
+    /// a peer
+    pub async fn start(&self, hostname: &str) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        *self.own_instance.write().await =
+            Some(format!("{}.{}", hostname, SERVICE_TYPE).to_ascii_lowercase());
+        let socket = mdns::bind_v4().map_err(mdns::socket_error)?;
+        let socket = UdpSocket::from_std(socket).map_err(mdns::socket_error)?;
+        let browser = tokio::spawn(self.clone().browse(socket));
+        if let Some(previous) = self.browser.write().await.replace(browser) {
+            previous.abort();
+        }
+        info!("Browsing for peer nodes on {}", SERVICE_TYPE);
+        Ok(())
+    }
+
+    pub async fn stop(&self) {
+        if let Some(browser) = self.browser.write().await.take() {
+            browser.abort();
+            debug!("Peer discovery stopped");
+        }
+    }
+
+    /// Nodes currently present on the LAN
+    pub async fn peers(&self) -> Vec<Peer> {
+        let own = self.own_instance.read().await.clone();
+        let mut cache = self.cache.write().await;
+        cache.prune(Instant::now());
+        cache.peers(own.as_deref())
+    }
+
+    async fn browse(self, socket: UdpSocket) {
+        let group = SocketAddr::from((MDNS_V4, MDNS_PORT));
+        let query = mdns::encode_query(SERVICE_TYPE, TYPE_PTR);
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.query_interval));
+        let mut buf = vec![0u8; 9000];
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = socket.send_to(&query, group).await {
+                        warn!("Failed to query for peer nodes: {}", e);
+                    }
+                    self.cache.write().await.prune(Instant::now());
+                }
+                received = socket.recv_from(&mut buf) => {
+                    let len = match received {
+                        Ok((len, _)) => len,
+                        Err(e) => {
+                            warn!("mDNS receive failed: {}", e);
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            continue;
+                        }
+                    };
+                    if let Some(records) = mdns::parse_response(&buf[..len]) {
+                        self.cache
+                            .write()
+                            .await
+                            .update(records, Instant::now(), unix_now());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::mdns::{encode_response, ServiceInfo};
+    use std::net::Ipv4Addr;
+
+    fn announcement(hostname: &str, address: Ipv4Addr, ttl: Option<u32>) -> Vec<u8> {
+        let service = ServiceInfo {
+            hostname: hostname.to_string(),
+            port: 8080,
+            txt: vec![
+                "version=0.4.0".to_string(),
+                "api_port=8080".to_string(),
+                "arch=aarch64".to_string(),
+            ],
+            ipv6: false,
+        };
+        let records = service.records(&[address.into()]);
+        encode_response(0, &[], &records, &[], ttl)
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let records = mdns::parse_response(&announcement(
+            "usb-node-aa",
+            Ipv4Addr::new(10, 0, 0, 7),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(records.len(), 5);
+        assert!(records.contains(&Record::new(
+            "usb-node-aa._usb-installer._tcp.local",
+            120,
+            RecordData::Srv {
+                port: 8080,
+                target: "usb-node-aa.local".to_string(),
+            },
+        )));
+        assert!(records.contains(&Record::new(
+            "usb-node-aa.local",
+            120,
+            RecordData::A(Ipv4Addr::new(10, 0, 0, 7)),
+        )));
+
+        // Queries are not responses
+        assert!(mdns::parse_response(&mdns::encode_query(SERVICE_TYPE, TYPE_PTR)).is_none());
+    }
+
+    #[test]
+    fn test_peer_cache() {
+        let now = Instant::now();
+        let mut cache = PeerCache::default();
+        for (hostname, last) in [("usb-node-aa", 7), ("usb-node-bb", 8)] {
+            let packet = announcement(hostname, Ipv4Addr::new(10, 0, 0, last), None);
+            cache.update(mdns::parse_response(&packet).unwrap(), now, 1000);
+        }
+
+        let peers = cache.peers(Some("usb-node-bb._usb-installer._tcp.local"));
+        assert_eq!(peers.len(), 1);
+        let peer = &peers[0];
+        assert_eq!(peer.instance, "usb-node-aa._usb-installer._tcp.local");
+        assert_eq!(peer.hostname.as_deref(), Some("usb-node-aa.local"));
+        assert_eq!(peer.addresses, vec![IpAddr::from([10, 0, 0, 7])]);
+        assert_eq!(peer.port, Some(8080));
+        assert_eq!(peer.version.as_deref(), Some("0.4.0"));
+        assert_eq!(peer.capabilities["arch"], "aarch64");
+        assert_eq!(peer.last_seen, 1000);
+
+        // Goodbye removes the peer at once, expiry after the TTL
+        let goodbye = announcement("usb-node-aa", Ipv4Addr::new(10, 0, 0, 7), Some(0));
+        cache.update(mdns::parse_response(&goodbye).unwrap(), now, 1001);
+        assert_eq!(cache.peers(None).len(), 1);
+        cache.prune(now + Duration::from_secs(4501));
+        assert!(cache.peers(None).is_empty());
+        assert!(cache.hosts.is_empty());
+    }
+}
@@ -0,0 +1,518 @@
+use crate::config::FailoverConfig;
+use crate::error::{NetworkError, Result};
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Routing table of the first uplink; the others follow in priority order
+const TABLE_BASE: u32 = 100;
+/// Priority of the first source rule, ahead of the main table at 32766
+const RULE_PRIORITY_BASE: u32 = 1000;
+/// Gap between the route metrics of neighbouring uplinks
+const METRIC_STEP: u32 = 10;
+
+/// Link state and counters of one uplink
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InterfaceMetrics {
+    pub interface: String,
+    /// Position in the priority list, 0 is preferred
+    pub priority: usize,
+    pub carrier: bool,
+    pub address: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    /// Times the carrier was lost since the node started
+    pub link_drops: u32,
+    /// Carries the default route
+    pub active: bool,
+}
+
+impl InterfaceMetrics {
+    fn usable(&self) -> bool {
+        self.carrier && self.address.is_some() && self.gateway.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FailoverStatus {
+    pub active: Option<String>,
+    /// Moves of the default route from one uplink to another
+    pub failovers: u32,
+    pub interfaces: Vec<InterfaceMetrics>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct DefaultRoute {
+    interface: String,
+    gateway: Ipv4Addr,
+    metric: u32,
+}
+
+/// Keeps the default route on the most preferred working uplink from
+/// `[network.failover]`. Every usable uplink keeps a default route with a
+/// metric by priority, and replies from an uplink's address leave through
+/// that uplink via a per-uplink routing table.
+#[derive(Clone)]
+pub struct UplinkFailover {
+    config: FailoverConfig,
+    status: Arc<RwLock<FailoverStatus>>,
+    monitor: Arc<RwLock<Option<JoinHandle<()>>>>,
+}
+
+impl UplinkFailover {
+    pub fn new(config: FailoverConfig) -> Self {
+        let interfaces = config
+            .interfaces
+            .iter()
+            .enumerate()
+            .map(|(priority, interface)| InterfaceMetrics {
+                interface: interface.clone(),
+                priority,
+                ..InterfaceMetrics::default()
+            })
+            .collect();
+
+        Self {
+            config,
+            status: Arc::new(RwLock::new(FailoverStatus {
+                interfaces,
+                ..FailoverStatus::default()
+            })),
+            monitor: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.config.interfaces.is_empty()
+    }
+
+    pub fn interfaces(&self) -> &[String] {
+        &self.config.interfaces
+    }
+
+    /// Metric for the default route of the uplink at `priority`
+    pub fn route_metric(&self, priority: usize) -> u32 {
+        self.config.base_metric + priority as u32 * METRIC_STEP
+    }
+
+    /// Pick the first uplink and keep checking the links in the background
+    pub async fn start(&self) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        self.check().await?;
+        let monitor = tokio::spawn(self.clone().watch());
+        if let Some(previous) = self.monitor.write().await.replace(monitor) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    pub async fn stop(&self) {
+        if let Some(monitor) = self.monitor.write().await.take() {
+            monitor.abort();
+            debug!("Uplink monitor stopped");
+        }
+    }
+
+    pub async fn get_status(&self) -> FailoverStatus {
+        self.status.read().await.clone()
+    }
+
+    async fn watch(self) {
+        let interval = Duration::from_secs(self.config.check_interval);
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = self.check().await {
+                warn!("Uplink check failed: {}", e);
+            }
+        }
+    }
+
+    /// Refresh the metrics of every uplink and fix the routes when the
+    /// usable uplinks changed or something else replaced a default route.
+    /// Returns the uplink now carrying the default route.
+    pub async fn check(&self) -> Result<Option<String>> {
+        let mut current = Vec::with_capacity(self.config.interfaces.len());
+        for (priority, interface) in self.config.interfaces.iter().enumerate() {
+            current.push(read_metrics(interface, priority).await);
+        }
+        let active = current
+            .iter()
+            .find(|m| m.usable())
+            .map(|m| m.interface.clone());
+
+        let mut readdressed = Vec::new();
+        {
+            let mut status = self.status.write().await;
+            for (metrics, previous) in current.iter_mut().zip(&status.interfaces) {
+                if previous.carrier && !metrics.carrier {
+                    warn!("Uplink {} lost carrier", metrics.interface);
+                    metrics.link_drops = previous.link_drops + 1;
+                } else {
+                    metrics.link_drops = previous.link_drops;
+                }
+                metrics.active = active.as_ref() == Some(&metrics.interface);
+                if (metrics.address, metrics.gateway) != (previous.address, previous.gateway) {
+                    readdressed.push(metrics.clone());
+                }
+            }
+
+            if status.active != active {
+                match (&status.active, &active) {
+                    (Some(old), Some(new)) => {
+                        warn!("Default route moves from {} to {}", old, new);
+                        status.failovers += 1;
+                    }
+                    (None, Some(new)) => info!("Default route via uplink {}", new),
+                    (Some(old), None) => warn!("No usable uplink left after {}", old),
+                    (None, None) => {}
+                }
+            }
+            status.active = active.clone();
+            status.interfaces = current.clone();
+        }
+
+        let expected = self.expected_routes(&current);
+        let installed: Vec<DefaultRoute> =
+            parse_default_routes(&ip(&["route", "show", "default"]).await?)
+                .into_iter()
+                .filter(|route| self.config.interfaces.contains(&route.interface))
+                .collect();
+        if !same_routes(&installed, &expected) {
+            self.apply_routes(&installed, &expected).await?;
+        }
+
+        for metrics in readdressed {
+            if let (Some(address), Some(gateway)) = (metrics.address, metrics.gateway) {
+                let applied = self
+                    .apply_policy(&metrics.interface, metrics.priority, address, gateway)
+                    .await;
+                if let Err(e) = applied {
+                    // Forget the address so the next check tries again
+                    self.status.write().await.interfaces[metrics.priority].address = None;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(active)
+    }
+
+    fn expected_routes(&self, current: &[InterfaceMetrics]) -> Vec<DefaultRoute> {
+        current
+            .iter()
+            .filter(|m| m.usable())
+            .filter_map(|m| {
+                Some(DefaultRoute {
+                    interface: m.interface.clone(),
+                    gateway: m.gateway?,
+                    metric: self.route_metric(m.priority),
+                })
+            })
+            .collect()
+    }
+
+    /// Install the expected routes before removing stale ones, so there is
+    /// no moment without a default route
+    async fn apply_routes(
+        &self,
+        installed: &[DefaultRoute],
+        expected: &[DefaultRoute],
+    ) -> Result<()> {
+        for route in expected {
+            debug!(
+                "Default route via {} dev {} metric {}",
+                route.gateway, route.interface, route.metric
+            );
+            ip(&[
+                "route",
+                "replace",
+                "default",
+                "via",
+                &route.gateway.to_string(),
+                "dev",
+                &route.interface,
+                "metric",
+                &route.metric.to_string(),
+            ])
+            .await?;
+        }
+        for route in installed.iter().filter(|r| !expected.contains(r)) {
+            debug!(
+                "Removing default route via {} dev {}",
+                route.gateway, route.interface
+            );
+            ip(&[
+                "route",
+                "del",
+                "default",
+                "via",
+                &route.gateway.to_string(),
+                "dev",
+                &route.interface,
+                "metric",
+                &route.metric.to_string(),
+            ])
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Traffic from `address` uses the uplink's own table, so connections to
+    /// a backup address are answered through the backup. Connected routes
+    /// in the main table still take precedence.
+    async fn apply_policy(
+        &self,
+        interface: &str,
+        priority: usize,
+        address: Ipv4Addr,
+        gateway: Ipv4Addr,
+    ) -> Result<()> {
+        let table = (TABLE_BASE + priority as u32).to_string();
+        let rule_priority = (RULE_PRIORITY_BASE + priority as u32).to_string();
+        let connected_priority = (RULE_PRIORITY_BASE - 1).to_string();
+
+        ip(&[
+            "route",
+            "replace",
+            "default",
+            "via",
+            &gateway.to_string(),
+            "dev",
+            interface,
+            "table",
+            &table,
+        ])
+        .await?;
+
+        // Rules are not replaceable; drop any earlier ones first
+        for priority in [&connected_priority, &rule_priority] {
+            let _ = ip(&["rule", "del", "priority", priority]).await;
+        }
+        ip(&[
+            "rule",
+            "add",
+            "lookup",
+            "main",
+            "suppress_prefixlength",
+            "0",
+            "priority",
+            &connected_priority,
+        ])
+        .await?;
+        ip(&[
+            "rule",
+            "add",
+            "from",
+            &address.to_string(),
+            "lookup",
+            &table,
+            "priority",
+            &rule_priority,
+        ])
+        .await?;
+        Ok(())
+    }
+}
+
+async fn read_metrics(interface: &str, priority: usize) -> InterfaceMetrics {
+    let sys = format!("/sys/class/net/{}", interface);
+    let counter = |name: &str| {
+        let path = format!("{}/statistics/{}", sys, name);
+        async move {
+            tokio::fs::read_to_string(path)
+                .await
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(0)
+        }
+    };
+
+    // Reading carrier fails while the interface is administratively down
+    let carrier = tokio::fs::read_to_string(format!("{}/carrier", sys))
+        .await
+        .is_ok_and(|value| value.trim() == "1");
+    let address = match ip(&["-o", "addr", "show", "dev", interface, "scope", "global"]).await {
+        Ok(output) => parse_address(&output),
+        Err(_) => None,
+    };
+
+    InterfaceMetrics {
+        interface: interface.to_string(),
+        priority,
+        carrier,
+        address,
+        gateway: gateway(interface).await,
+        rx_bytes: counter("rx_bytes").await,
+        tx_bytes: counter("tx_bytes").await,
+        rx_errors: counter("rx_errors").await,
+        tx_errors: counter("tx_errors").await,
+        link_drops: 0,
+        active: false,
+    }
+}
+
+/// Gateway from the main table, or from the uplink's own table once its
+/// main route was removed while the link was down
+async fn gateway(interface: &str) -> Option<Ipv4Addr> {
+    for table in ["main", "all"] {
+        let filter = ["route", "show", "table", table, "default", "dev", interface];
+        if let Some(gateway) = ip(&filter).await.ok().as_deref().and_then(parse_gateway) {
+            return Some(gateway);
+        }
+    }
+    None
+}
+
+/// Gateway of the first route in `ip route show default dev X` output,
+/// which leaves out the device
+fn parse_gateway(output: &str) -> Option<Ipv4Addr> {
+    output.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("default") || words.next() != Some("via") {
+            return None;
+        }
+        words.next()?.parse().ok()
+    })
+}
+
+/// First global address in `ip -4 -o addr show` output
+fn parse_address(output: &str) -> Option<Ipv4Addr> {
+    output.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        words.find(|word| *word == "inet")?;
+        words.next()?.split('/').next()?.parse().ok()
+    })
+}
+
+/// Default routes in `ip -4 route show default` output. Routes without a
+/// metric have metric 0.
+fn parse_default_routes(output: &str) -> Vec<DefaultRoute> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.first() != Some(&"default") {
+                return None;
+            }
+            let value = |key: &str| {
+                words
+                    .iter()
+                    .position(|word| *word == key)
+                    .and_then(|i| words.get(i + 1))
+                    .copied()
+            };
+            Some(DefaultRoute {
+                interface: value("dev")?.to_string(),
+                gateway: value("via")?.parse().ok()?,
+                metric: value("metric").and_then(|m| m.parse().ok()).unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+fn same_routes(installed: &[DefaultRoute], expected: &[DefaultRoute]) -> bool {
+    let mut installed = installed.to_vec();
+    let mut expected = expected.to_vec();
+    installed.sort();
+    expected.sort();
+    installed == expected
+}
+
+async fn ip(args: &[&str]) -> Result<String> {
+    let output = Command::new("ip")
+        .arg("-4")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| NetworkError::RouteFailed(format!("Failed to run ip: {}", e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::RouteFailed(format!(
+            "ip {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_default_routes() {
+        let output = "default via 192.168.1.1 dev eth0 proto dhcp src 192.168.1.20 metric 100\n\
+                      default via 10.0.0.1 dev wlan0 metric 110 linkdown\n\
+                      default via 172.16.0.1 dev usb0\n\
+                      default dev wg0 scope link\n";
+        let routes = parse_default_routes(output);
+        assert_eq!(routes.len(), 3);
+        assert_eq!(
+            routes[0],
+            DefaultRoute {
+                interface: "eth0".to_string(),
+                gateway: Ipv4Addr::new(192, 168, 1, 1),
+                metric: 100,
+            }
+        );
+        assert_eq!(routes[2].metric, 0);
+
+        assert!(same_routes(
+            &routes[..2],
+            &[routes[1].clone(), routes[0].clone()]
+        ));
+        assert!(!same_routes(&routes, &routes[..2]));
+
+        assert_eq!(
+            parse_address("2: eth0    inet 192.168.1.20/24 brd 192.168.1.255 scope global dynamic eth0\\       valid_lft 3542sec preferred_lft 3542sec"),
+            Some(Ipv4Addr::new(192, 168, 1, 20))
+        );
+        assert_eq!(parse_address(""), None);
+        assert_eq!(
+            parse_gateway("default via 10.0.0.1 proto dhcp metric 110 \n"),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+    }
+
+    #[test]
+    fn test_route_metrics() {
+        let failover = UplinkFailover::new(FailoverConfig {
+            interfaces: vec!["eth0".to_string(), "wlan0".to_string(), "usb0".to_string()],
+            ..FailoverConfig::default()
+        });
+        assert!(failover.is_enabled());
+        assert_eq!(failover.route_metric(0), 100);
+        assert_eq!(failover.route_metric(2), 120);
+
+        let metrics = |interface: &str, priority, carrier| InterfaceMetrics {
+            interface: interface.to_string(),
+            priority,
+            carrier,
+            address: Some(Ipv4Addr::new(10, 0, priority as u8, 2)),
+            gateway: Some(Ipv4Addr::new(10, 0, priority as u8, 1)),
+            ..InterfaceMetrics::default()
+        };
+        let expected = failover.expected_routes(&[
+            metrics("eth0", 0, false),
+            metrics("wlan0", 1, true),
+            metrics("usb0", 2, true),
+        ]);
+        assert_eq!(expected.len(), 2);
+        assert_eq!(expected[0].interface, "wlan0");
+        assert_eq!(expected[0].metric, 110);
+
+        assert!(!UplinkFailover::new(FailoverConfig::default()).is_enabled());
+    }
+}
@@ -0,0 +1,326 @@
+use crate::config::FirewallConfig;
+use crate::error::{NetworkError, Result};
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+/// nftables table owning every firewall rule, so teardown is one delete
+const TABLE: &str = "usb_node_firewall";
+/// DHCP and DHCPv6 replies reach the client port from the server port
+/// and are not tracked as replies to anything
+const DHCP_REPLIES: &[(u16, u16)] = &[(67, 68), (547, 546)];
+
+/// Ports the node's services listen on
+#[derive(Debug, Clone, Default)]
+pub struct FirewallPorts {
+    /// SSH, VNC, WebVNC and the API; only reachable from the allowed
+    /// sources
+    pub management_tcp: Vec<u16>,
+    /// Network boot and mDNS, open to every host since PXE clients have
+    /// no address yet
+    pub public_udp: Vec<u16>,
+    pub public_tcp: Vec<u16>,
+}
+
+/// Drops incoming traffic except to the node's own services, with the
+/// management services optionally limited to some source networks
+#[derive(Clone)]
+pub struct Firewall {
+    config: FirewallConfig,
+    ports: FirewallPorts,
+}
+
+impl Firewall {
+    pub fn new(config: FirewallConfig) -> Self {
+        Self {
+            config,
+            ports: FirewallPorts::default(),
+        }
+    }
+
+    pub fn with_ports(mut self, ports: FirewallPorts) -> Self {
+        self.ports = ports;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub async fn apply(&self) -> Result<()> {
+        let ruleset = self.ruleset()?;
+        info!(
+            "Loading firewall: management ports {:?} from {}",
+            self.management_tcp(),
+            if self.config.allowed_sources.is_empty() {
+                "anywhere".to_string()
+            } else {
+                self.config.allowed_sources.join(", ")
+            }
+        );
+        debug!("Firewall ruleset:\n{}", ruleset);
+        // Replace rules left behind by an earlier run
+        let _ = run("nft", &["delete", "table", "inet", TABLE]).await;
+        load_ruleset(&ruleset).await
+    }
+
+    pub async fn remove(&self) -> Result<()> {
+        run("nft", &["delete", "table", "inet", TABLE]).await
+    }
+
+    /// Drop everything from `address` until `duration` has passed; nftables
+    /// removes the entry by itself
+    pub async fn ban(&self, address: IpAddr, duration: Duration) -> Result<()> {
+        let element = format!("{{ {} timeout {}s }}", address, duration.as_secs().max(1));
+        run(
+            "nft",
+            &["add", "element", "inet", TABLE, ban_set(address), &element],
+        )
+        .await
+    }
+
+    pub async fn unban(&self, address: IpAddr) -> Result<()> {
+        let element = format!("{{ {} }}", address);
+        run(
+            "nft",
+            &[
+                "delete",
+                "element",
+                "inet",
+                TABLE,
+                ban_set(address),
+                &element,
+            ],
+        )
+        .await
+    }
+
+    /// Management ports plus the extra ones from the config, deduplicated
+    fn management_tcp(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self
+            .ports
+            .management_tcp
+            .iter()
+            .chain(&self.config.allowed_tcp)
+            .copied()
+            .collect();
+        ports.sort_unstable();
+        ports.dedup();
+        ports
+    }
+
+    fn ruleset(&self) -> Result<String> {
+        let (v4, v6) = split_sources(&self.config.allowed_sources)?;
+        let mut rules = format!("table inet {TABLE} {{\n");
+
+        for (name, family, sources) in [
+            ("sources_v4", "ipv4_addr", &v4),
+            ("sources_v6", "ipv6_addr", &v6),
+        ] {
+            if !sources.is_empty() {
+                rules.push_str(&format!(
+                    "    set {name} {{\n        type {family}; flags interval;\n        elements = {{ {} }}\n    }}\n",
+                    sources.join(", ")
+                ));
+            }
+        }
+
+        // Filled by `ban`; entries expire on their own
+        for (name, family) in [("banned_v4", "ipv4_addr"), ("banned_v6", "ipv6_addr")] {
+            rules.push_str(&format!(
+                "    set {name} {{\n        type {family}; flags timeout;\n    }}\n"
+            ));
+        }
+
+        rules.push_str("    chain input {\n");
+        rules.push_str("        type filter hook input priority 0; policy drop;\n");
+        rules.push_str("        iif \"lo\" accept\n");
+        // Before the established rule, so open sessions are cut off too
+        rules.push_str("        ip saddr @banned_v4 drop\n");
+        rules.push_str("        ip6 saddr @banned_v6 drop\n");
+        rules.push_str("        ct state established,related accept\n");
+        rules.push_str("        ct state invalid drop\n");
+        // IPv6 neighbour discovery and path MTU discovery need ICMP
+        rules.push_str("        meta l4proto { icmp, ipv6-icmp } accept\n");
+        for (sport, dport) in DHCP_REPLIES {
+            rules.push_str(&format!(
+                "        udp sport {sport} udp dport {dport} accept\n"
+            ));
+        }
+        let mut udp = self.ports.public_udp.clone();
+        udp.extend(&self.config.allowed_udp);
+        for (protocol, ports) in [("udp", udp), ("tcp", self.ports.public_tcp.clone())] {
+            if !ports.is_empty() {
+                rules.push_str(&format!(
+                    "        {protocol} dport {{ {} }} accept\n",
+                    port_list(ports)
+                ));
+            }
+        }
+
+        let management = self.management_tcp();
+        if !management.is_empty() {
+            let ports = port_list(management);
+            if v4.is_empty() && v6.is_empty() {
+                rules.push_str(&format!("        tcp dport {{ {ports} }} accept\n"));
+            }
+            if !v4.is_empty() {
+                rules.push_str(&format!(
+                    "        ip saddr @sources_v4 tcp dport {{ {ports} }} accept\n"
+                ));
+            }
+            if !v6.is_empty() {
+                rules.push_str(&format!(
+                    "        ip6 saddr @sources_v6 tcp dport {{ {ports} }} accept\n"
+                ));
+            }
+        }
+        rules.push_str("    }\n");
+
+        rules.push_str("}\n");
+        Ok(rules)
+    }
+}
+
+fn ban_set(address: IpAddr) -> &'static str {
+    match address {
+        IpAddr::V4(_) => "banned_v4",
+        IpAddr::V6(_) => "banned_v6",
+    }
+}
+
+fn port_list(mut ports: Vec<u16>) -> String {
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Source addresses or networks like `10.0.0.0/8`, by family
+fn split_sources(sources: &[String]) -> Result<(Vec<String>, Vec<String>)> {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+    for source in sources {
+        let invalid = || NetworkError::FirewallFailed(format!("Invalid source: {}", source));
+        let (address, prefix) = match source.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (source.as_str(), None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        if let Some(prefix) = prefix {
+            match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max => {}
+                _ => return Err(invalid().into()),
+            }
+        }
+        match address {
+            IpAddr::V4(_) => v4.push(source.clone()),
+            IpAddr::V6(_) => v6.push(source.clone()),
+        }
+    }
+    Ok((v4, v6))
+}
+
+async fn load_ruleset(ruleset: &str) -> Result<()> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| NetworkError::FirewallFailed(format!("Failed to run nft: {}", e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(ruleset.as_bytes())
+            .await
+            .map_err(|e| NetworkError::FirewallFailed(format!("Failed to write rules: {}", e)))?;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| NetworkError::FirewallFailed(format!("nft failed: {}", e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::FirewallFailed(format!(
+            "nft rejected the ruleset: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| NetworkError::FirewallFailed(format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::FirewallFailed(format!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn firewall(allowed_sources: &[&str]) -> Firewall {
+        Firewall::new(FirewallConfig {
+            enabled: true,
+            allowed_sources: allowed_sources.iter().map(|s| s.to_string()).collect(),
+            ..FirewallConfig::default()
+        })
+        .with_ports(FirewallPorts {
+            management_tcp: vec![8080, 22, 5900],
+            public_udp: vec![67, 69, 4011, 5353],
+            public_tcp: vec![8081],
+        })
+    }
+
+    #[test]
+    fn test_ruleset() {
+        let rules = firewall(&[]).ruleset().unwrap();
+        assert!(rules.contains("policy drop;"));
+        assert!(rules.contains("udp sport 67 udp dport 68 accept"));
+        assert!(rules.contains("udp dport { 67, 69, 4011, 5353 } accept"));
+        assert!(rules.contains("tcp dport { 8081 } accept"));
+        assert!(rules.contains("        tcp dport { 22, 5900, 8080 } accept"));
+        assert!(!rules.contains("@sources_v4"));
+        assert!(rules.contains("type ipv4_addr; flags timeout;"));
+        assert!(rules.contains("ip saddr @banned_v4 drop"));
+        assert!(rules.contains("ip6 saddr @banned_v6 drop"));
+        assert!(
+            rules.find("@banned_v4 drop").unwrap() < rules.find("established,related").unwrap()
+        );
+
+        let rules = firewall(&["10.0.0.0/8", "192.168.1.5", "fd00::/8"])
+            .ruleset()
+            .unwrap();
+        assert!(rules.contains("elements = { 10.0.0.0/8, 192.168.1.5 }"));
+        assert!(rules.contains("ip saddr @sources_v4 tcp dport { 22, 5900, 8080 } accept"));
+        assert!(rules.contains("ip6 saddr @sources_v6 tcp dport { 22, 5900, 8080 } accept"));
+        assert!(!rules.contains("        tcp dport { 22, 5900, 8080 } accept"));
+        // Boot services stay open to targets without an address
+        assert!(rules.contains("udp dport { 67, 69, 4011, 5353 } accept"));
+    }
+
+    #[test]
+    fn test_invalid_sources() {
+        assert!(firewall(&["10.0.0.0/33"]).ruleset().is_err());
+        assert!(firewall(&["lab-network"]).ruleset().is_err());
+        assert!(firewall(&["fd00::/129"]).ruleset().is_err());
+    }
+}
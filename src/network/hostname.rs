@@ -1,25 +1,128 @@
+use crate::config::HostnameStrategy;
+use crate::disk::checksum::{hash_bytes, HashAlgorithm};
 use crate::error::{UsbInstallerError, UsbInstallerResult};
+use crate::network::mdns::{MdnsResponder, ServiceInfo, ServicePorts};
 use rand::Rng;
+use std::fs;
+use std::path::Path;
 use std::process::{Command, Stdio};
 use std::str;
+use std::sync::{Mutex, PoisonError};
+
+const DEFAULT_PREFIX: &str = "usb-node";
+const SYS_CLASS_NET: &str = "/sys/class/net";
+/// x86 firmware serial, then the SoC serial of ARM boards such as the
+/// Raspberry Pi
+const SERIAL_SOURCES: &[&str] = &[
+    "/sys/class/dmi/id/product_serial",
+    "/proc/device-tree/serial-number",
+];
+const MACHINE_ID: &str = "/etc/machine-id";
+/// Firmware fills these in when the vendor did not, so they are shared by
+/// many machines
+const PLACEHOLDER_SERIALS: &[&str] = &[
+    "tobefilledbyoem",
+    "defaultstring",
+    "systemserialnumber",
+    "notspecified",
+    "notapplicable",
+    "none",
+    "0123456789",
+];
+/// Serial characters kept; enough to tell units of one model apart
+const SERIAL_SUFFIX_LEN: usize = 12;
 
 pub struct HostnameManager {
     hostname: String,
+    prefix: String,
+    strategy: HostnameStrategy,
+    /// Interface whose MAC address names the node with `HostnameStrategy::Mac`
+    interface: Option<String>,
     mdns_enabled: bool,
+    /// Announce on IPv6 as well as IPv4
+    ipv6: bool,
+    /// `key=value` strings published with the mDNS service
+    txt_records: Vec<String>,
+    service_ports: ServicePorts,
+    /// Latest status entries, carried over when the responder restarts
+    status: Mutex<Vec<String>>,
+    responder: Mutex<Option<MdnsResponder>>,
 }
 
 impl HostnameManager {
     pub fn new(mdns_enabled: bool) -> Self {
         Self {
-            hostname: Self::generate_hostname(),
+            hostname: Self::generate_hostname(DEFAULT_PREFIX),
+            prefix: DEFAULT_PREFIX.to_string(),
+            strategy: HostnameStrategy::Random,
+            interface: None,
             mdns_enabled,
+            ipv6: false,
+            txt_records: Vec::new(),
+            service_ports: ServicePorts::default(),
+            status: Mutex::new(Vec::new()),
+            responder: Mutex::new(None),
         }
     }
 
-    fn generate_hostname() -> String {
+    pub fn with_txt_records(mut self, records: Vec<String>) -> Self {
+        self.txt_records = records;
+        self
+    }
+
+    /// Ports published with the mDNS service; the API port is the one in
+    /// its SRV record
+    pub fn with_service_ports(mut self, ports: ServicePorts) -> Self {
+        self.service_ports = ports;
+        self
+    }
+
+    pub fn with_ipv6(mut self, enabled: bool) -> Self {
+        self.ipv6 = enabled;
+        self
+    }
+
+    /// Name the node `<prefix>-<suffix>` with the suffix from `strategy`.
+    /// `interface` is the primary interface, for `HostnameStrategy::Mac`;
+    /// without one the first physical interface is used.
+    pub fn with_naming(
+        mut self,
+        prefix: &str,
+        strategy: HostnameStrategy,
+        interface: Option<&str>,
+    ) -> Self {
+        self.prefix = prefix.to_string();
+        self.strategy = strategy;
+        self.interface = interface.map(str::to_string);
+        self.hostname = self.derive_hostname();
+        self
+    }
+
+    fn generate_hostname(prefix: &str) -> String {
         let mut rng = rand::thread_rng();
         let suffix: u16 = rng.gen_range(1000..9999);
-        format!("usb-node-{}", suffix)
+        format!("{}-{}", prefix, suffix)
+    }
+
+    fn derive_hostname(&self) -> String {
+        let suffix = match self.strategy {
+            HostnameStrategy::Random => None,
+            HostnameStrategy::Mac => mac_suffix(self.interface.as_deref()),
+            HostnameStrategy::Serial => serial_suffix(),
+            HostnameStrategy::MachineId => machine_id_suffix(),
+        };
+        match suffix {
+            Some(suffix) => format!("{}-{}", self.prefix, suffix),
+            None => {
+                if self.strategy != HostnameStrategy::Random {
+                    tracing::warn!(
+                        "No stable identity for hostname strategy {:?}, using a random suffix",
+                        self.strategy
+                    );
+                }
+                Self::generate_hostname(&self.prefix)
+            }
+        }
     }
 
     pub fn set_hostname(&self) -> UsbInstallerResult<()> {
@@ -82,103 +185,56 @@ impl HostnameManager {
         Ok(())
     }
 
-    #[cfg(target_os = "linux")]
+    /// Advertise `_usb-installer._tcp` from the in-process responder. A
+    /// responder for a previous hostname says goodbye first.
     fn register_mdns(&self) -> UsbInstallerResult<()> {
-        let output = Command::new("systemctl")
-            .args(&["is-active", "avahi-daemon"])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| {
-                UsbInstallerError::Network(format!("Failed to check avahi status: {}", e))
-            })?;
-
-        if !output.status.success() {
-            log::warn!("Avahi daemon not running, attempting to start");
-            self.start_avahi()?;
-        }
-
-        let service_file = format!("[Unit]\nDescription=USB Installer Node\n\n[Service]\nType=notify\nUser=root\nExecStart=/usr/bin/avahi-publish -s {} _usb-installer._tcp 22\nRestart=always\n\n[Install]\nWantedBy=multi-user.target", self.hostname);
-
-        std::fs::write(
-            "/etc/systemd/system/usb-installer-mdns.service",
-            service_file,
-        )
-        .map_err(|e| {
-            UsbInstallerError::Network(format!("Failed to write mDNS service file: {}", e))
-        })?;
-
-        let output = Command::new("systemctl")
-            .args(&["enable", "--now", "usb-installer-mdns.service"])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| {
-                UsbInstallerError::Network(format!("Failed to enable mDNS service: {}", e))
-            })?;
-
-        if !output.status.success() {
-            let stderr = str::from_utf8(&output.stderr).unwrap_or("Unknown error");
-            log::warn!("Failed to enable mDNS service: {}", stderr);
-        } else {
-            log::info!(
-                "mDNS service registered for hostname: {}.local",
-                self.hostname
-            );
-        }
-
-        Ok(())
-    }
-
-    #[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "netbsd"))]
-    fn register_mdns(&self) -> UsbInstallerResult<()> {
-        let output = Command::new("service")
-            .args(&["mdnsd", "status"])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| {
-                UsbInstallerError::Network(format!("Failed to check mdnsd status: {}", e))
-            })?;
-
-        if !output.status.success() {
-            log::warn!("mdnsd not running, attempting to start");
-            let start_output = Command::new("service")
-                .args(&["mdnsd", "start"])
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .output()
-                .map_err(|e| UsbInstallerError::Network(format!("Failed to start mdnsd: {}", e)))?;
-
-            if !start_output.status.success() {
-                let stderr = str::from_utf8(&start_output.stderr).unwrap_or("Unknown error");
-                log::warn!("Failed to start mdnsd: {}", stderr);
-                return Ok(());
-            }
+        // Peers browsing for nodes read the version from here
+        let mut txt = vec![format!("version={}", env!("CARGO_PKG_VERSION"))];
+        txt.extend(self.service_ports.txt_records());
+        txt.extend(self.txt_records.iter().cloned());
+        let service = ServiceInfo {
+            hostname: self.hostname.clone(),
+            port: self.service_ports.api,
+            txt,
+            ipv6: self.ipv6,
+        };
+
+        let mut responder = self
+            .responder
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(previous) = responder.take() {
+            previous.stop();
         }
-
-        log::info!("mDNS hostname registered: {}.local", self.hostname);
+        let started =
+            MdnsResponder::start(service).map_err(|e| UsbInstallerError::Network(e.to_string()))?;
+        started.set_status(
+            self.status
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        );
+        *responder = Some(started);
+
+        tracing::info!(
+            "mDNS service registered for hostname: {}.local",
+            self.hostname
+        );
         Ok(())
     }
 
-    #[cfg(target_os = "linux")]
-    fn start_avahi(&self) -> UsbInstallerResult<()> {
-        let output = Command::new("systemctl")
-            .args(&["start", "avahi-daemon"])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| UsbInstallerError::Network(format!("Failed to start avahi: {}", e)))?;
-
-        if !output.status.success() {
-            let stderr = str::from_utf8(&output.stderr).unwrap_or("Unknown error");
-            return Err(UsbInstallerError::Network(format!(
-                "Failed to start avahi daemon: {}",
-                stderr
-            )));
+    /// Publish `records` (e.g. `state=installing`) in the TXT record after
+    /// the fixed entries, replacing the previous status
+    pub fn set_status(&self, records: Vec<String>) {
+        if let Some(responder) = self
+            .responder
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+        {
+            responder.set_status(records.clone());
         }
-
-        Ok(())
+        *self.status.lock().unwrap_or_else(PoisonError::into_inner) = records;
     }
 
     pub fn get_hostname(&self) -> &str {
@@ -213,8 +269,9 @@ impl HostnameManager {
         Ok(current_hostname == self.hostname)
     }
 
+    /// Derive the hostname again; only a random one changes
     pub fn reset_hostname(&mut self) -> UsbInstallerResult<()> {
-        self.hostname = Self::generate_hostname();
+        self.hostname = self.derive_hostname();
         self.set_hostname()
     }
 
@@ -223,17 +280,14 @@ impl HostnameManager {
             return Ok(());
         }
 
-        #[cfg(target_os = "linux")]
+        // Goodbye packets so browsers drop the node right away
+        if let Some(responder) = self
+            .responder
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
         {
-            let _ = Command::new("systemctl")
-                .args(&["stop", "usb-installer-mdns.service"])
-                .output();
-
-            let _ = Command::new("systemctl")
-                .args(&["disable", "usb-installer-mdns.service"])
-                .output();
-
-            let _ = std::fs::remove_file("/etc/systemd/system/usb-installer-mdns.service");
+            responder.stop();
         }
 
         log::info!("mDNS cleanup completed");
@@ -241,13 +295,81 @@ impl HostnameManager {
     }
 }
 
+/// Last three octets of the interface's MAC address, e.g. `5e8f21`
+fn mac_suffix(interface: Option<&str>) -> Option<String> {
+    let interface = match interface {
+        Some(interface) => interface.to_string(),
+        None => first_physical_interface()?,
+    };
+    let address = fs::read_to_string(format!("{}/{}/address", SYS_CLASS_NET, interface)).ok()?;
+    mac_octets(&address)
+}
+
+fn mac_octets(address: &str) -> Option<String> {
+    let octets: Vec<&str> = address.trim().split(':').collect();
+    if octets.len() != 6 || octets.iter().all(|octet| *octet == "00") {
+        return None;
+    }
+    Some(octets[3..].concat().to_lowercase())
+}
+
+/// Interfaces backed by a device, leaving out bridges, VLANs, tunnels and
+/// container veths, whose addresses are not tied to the hardware
+fn first_physical_interface() -> Option<String> {
+    let mut interfaces: Vec<String> = fs::read_dir(SYS_CLASS_NET)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("device").exists())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    interfaces.sort();
+    interfaces.into_iter().next()
+}
+
+fn serial_suffix() -> Option<String> {
+    SERIAL_SOURCES
+        .iter()
+        .filter_map(|source| fs::read_to_string(source).ok())
+        .find_map(|serial| serial_chars(&serial))
+}
+
+/// Lowercase alphanumerics of the serial, at most `SERIAL_SUFFIX_LEN` from
+/// the end; `None` for placeholders
+fn serial_chars(serial: &str) -> Option<String> {
+    let cleaned: String = serial
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let first = cleaned.chars().next()?;
+    if cleaned.chars().all(|c| c == first) || PLACEHOLDER_SERIALS.contains(&cleaned.as_str()) {
+        return None;
+    }
+    Some(cleaned[cleaned.len().saturating_sub(SERIAL_SUFFIX_LEN)..].to_string())
+}
+
+/// The machine ID is meant to stay private, so only an application-specific
+/// hash of it is published
+fn machine_id_suffix() -> Option<String> {
+    let machine_id = fs::read_to_string(Path::new(MACHINE_ID)).ok()?;
+    let machine_id = machine_id.trim();
+    if machine_id.is_empty() {
+        return None;
+    }
+    let digest = hash_bytes(
+        HashAlgorithm::Sha256,
+        format!("usb-installer-node:{}", machine_id).as_bytes(),
+    );
+    Some(digest.value[..6].to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_hostname_generation() {
-        let hostname = HostnameManager::generate_hostname();
+        let hostname = HostnameManager::generate_hostname(DEFAULT_PREFIX);
         assert!(hostname.starts_with("usb-node-"));
         assert_eq!(hostname.len(), 13); // "usb-node-" + 4 digits
     }
@@ -283,4 +405,27 @@ mod tests {
         assert!(new_hostname.starts_with("usb-node-"));
         // Note: There's a tiny chance they could be the same, but very unlikely
     }
+
+    #[test]
+    fn test_stable_suffixes() {
+        assert_eq!(mac_octets("52:54:00:5E:8F:21\n").as_deref(), Some("5e8f21"));
+        assert_eq!(mac_octets("00:00:00:00:00:00"), None);
+        assert_eq!(mac_octets("garbage"), None);
+
+        assert_eq!(serial_chars("PF2XK7LM\n").as_deref(), Some("pf2xk7lm"));
+        assert_eq!(
+            serial_chars("10000000a1b2c3d4e5\0").as_deref(),
+            Some("00a1b2c3d4e5")
+        );
+        assert_eq!(serial_chars("To Be Filled By O.E.M."), None);
+        assert_eq!(serial_chars("0000000000"), None);
+        assert_eq!(serial_chars(" "), None);
+    }
+
+    #[test]
+    fn test_random_strategy_uses_prefix() {
+        let manager =
+            HostnameManager::new(false).with_naming("rack3", HostnameStrategy::Random, None);
+        assert!(manager.get_hostname().starts_with("rack3-"));
+    }
 }
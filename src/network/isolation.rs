@@ -0,0 +1,254 @@
+use crate::config::{Forwarding, ProvisioningConfig};
+use crate::error::{NetworkError, Result};
+use std::net::Ipv4Addr;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// nftables table owning every isolation rule, so teardown is one delete
+const TABLE: &str = "usb_node_provisioning";
+
+/// Fences off the interface PXE targets are served on: its own subnet,
+/// only the node's boot and package services reachable, and either no
+/// forwarding or NAT out of a single uplink.
+pub struct ProvisioningIsolation {
+    config: ProvisioningConfig,
+}
+
+impl ProvisioningIsolation {
+    pub fn new(config: ProvisioningConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Address the provisioning interface and load the firewall rules
+    pub async fn apply(&self) -> Result<()> {
+        let interface = self.interface()?;
+        let (network, prefix) = parse_cidr(&self.config.address)?;
+        info!(
+            "Isolating provisioning interface {} on {}/{}",
+            interface, network, prefix
+        );
+
+        run(
+            "ip",
+            &["addr", "replace", &self.config.address, "dev", interface],
+        )
+        .await?;
+        run("ip", &["link", "set", interface, "up"]).await?;
+
+        let ruleset = self.ruleset(interface, network, prefix)?;
+        debug!("Provisioning ruleset:\n{}", ruleset);
+        // Replace rules left behind by an earlier run
+        let _ = run("nft", &["delete", "table", "inet", TABLE]).await;
+        load_ruleset(&ruleset).await?;
+
+        if self.config.forwarding == Forwarding::Nat {
+            run("sysctl", &["-w", "net.ipv4.ip_forward=1"]).await?;
+        }
+        Ok(())
+    }
+
+    /// Drop the firewall rules and the provisioning address
+    pub async fn remove(&self) -> Result<()> {
+        let interface = self.interface()?;
+        if let Err(e) = run("nft", &["delete", "table", "inet", TABLE]).await {
+            warn!("Failed to remove provisioning rules: {}", e);
+        }
+        run(
+            "ip",
+            &["addr", "del", &self.config.address, "dev", interface],
+        )
+        .await
+    }
+
+    fn interface(&self) -> Result<&str> {
+        self.config.interface.as_deref().ok_or_else(|| {
+            NetworkError::IsolationFailed("No provisioning interface configured".to_string()).into()
+        })
+    }
+
+    /// nftables script for `interface` serving `network/prefix`
+    fn ruleset(&self, interface: &str, network: Ipv4Addr, prefix: u8) -> Result<String> {
+        let mut rules = format!("table inet {TABLE} {{\n");
+
+        rules.push_str("    chain input {\n");
+        rules.push_str("        type filter hook input priority 0; policy accept;\n");
+        rules.push_str(&format!(
+            "        iifname \"{interface}\" ct state established,related accept\n"
+        ));
+        rules.push_str(&format!(
+            "        iifname \"{interface}\" icmp type echo-request accept\n"
+        ));
+        if !self.config.allowed_udp.is_empty() {
+            rules.push_str(&format!(
+                "        iifname \"{interface}\" udp dport {{ {} }} accept\n",
+                ports(&self.config.allowed_udp)
+            ));
+        }
+        if !self.config.allowed_tcp.is_empty() {
+            rules.push_str(&format!(
+                "        iifname \"{interface}\" tcp dport {{ {} }} accept\n",
+                ports(&self.config.allowed_tcp)
+            ));
+        }
+        rules.push_str(&format!("        iifname \"{interface}\" drop\n"));
+        rules.push_str("    }\n");
+
+        rules.push_str("    chain forward {\n");
+        rules.push_str("        type filter hook forward priority 0; policy accept;\n");
+        if self.config.forwarding == Forwarding::Nat {
+            let uplink = self.config.uplink.as_deref().ok_or_else(|| {
+                NetworkError::IsolationFailed("NAT forwarding needs an uplink".to_string())
+            })?;
+            rules.push_str(&format!(
+                "        iifname \"{interface}\" oifname \"{uplink}\" accept\n"
+            ));
+            rules.push_str(&format!(
+                "        iifname \"{uplink}\" oifname \"{interface}\" ct state established,related accept\n"
+            ));
+        }
+        rules.push_str(&format!("        iifname \"{interface}\" drop\n"));
+        rules.push_str(&format!("        oifname \"{interface}\" drop\n"));
+        rules.push_str("    }\n");
+
+        if let (Forwarding::Nat, Some(uplink)) = (self.config.forwarding, &self.config.uplink) {
+            rules.push_str("    chain postrouting {\n");
+            rules.push_str("        type nat hook postrouting priority 100; policy accept;\n");
+            rules.push_str(&format!(
+                "        ip saddr {network}/{prefix} oifname \"{uplink}\" masquerade\n"
+            ));
+            rules.push_str("    }\n");
+        }
+
+        rules.push_str("}\n");
+        Ok(rules)
+    }
+}
+
+fn ports(ports: &[u16]) -> String {
+    ports
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Network address and prefix length of an interface address like `10.42.0.1/24`
+fn parse_cidr(address: &str) -> Result<(Ipv4Addr, u8)> {
+    let invalid = || NetworkError::IsolationFailed(format!("Invalid address: {}", address));
+    let (ip, prefix) = address.split_once('/').ok_or_else(invalid)?;
+    let ip: Ipv4Addr = ip.parse().map_err(|_| invalid())?;
+    let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+    if prefix == 0 || prefix > 30 {
+        return Err(invalid().into());
+    }
+    let mask = u32::MAX << (32 - prefix);
+    Ok((Ipv4Addr::from(u32::from(ip) & mask), prefix))
+}
+
+async fn load_ruleset(ruleset: &str) -> Result<()> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| NetworkError::IsolationFailed(format!("Failed to run nft: {}", e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(ruleset.as_bytes())
+            .await
+            .map_err(|e| NetworkError::IsolationFailed(format!("Failed to write rules: {}", e)))?;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| NetworkError::IsolationFailed(format!("nft failed: {}", e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::IsolationFailed(format!(
+            "nft rejected the ruleset: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| NetworkError::IsolationFailed(format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::IsolationFailed(format!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(forwarding: Forwarding) -> ProvisioningConfig {
+        ProvisioningConfig {
+            enabled: true,
+            interface: Some("eth1".to_string()),
+            forwarding,
+            uplink: Some("eth0".to_string()),
+            ..ProvisioningConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        assert_eq!(
+            parse_cidr("10.42.0.1/24").unwrap(),
+            (Ipv4Addr::new(10, 42, 0, 0), 24)
+        );
+        assert_eq!(
+            parse_cidr("192.168.77.130/25").unwrap(),
+            (Ipv4Addr::new(192, 168, 77, 128), 25)
+        );
+        assert!(parse_cidr("10.42.0.1").is_err());
+        assert!(parse_cidr("10.42.0.1/31").is_err());
+        assert!(parse_cidr("fe80::1/64").is_err());
+    }
+
+    #[test]
+    fn test_ruleset() {
+        let isolation = ProvisioningIsolation::new(config(Forwarding::None));
+        let rules = isolation
+            .ruleset("eth1", Ipv4Addr::new(10, 42, 0, 0), 24)
+            .unwrap();
+        assert!(rules.contains("iifname \"eth1\" udp dport { 53, 67, 69, 4011 } accept"));
+        assert!(rules.contains("iifname \"eth1\" tcp dport { 80, 8081 } accept"));
+        assert!(rules.contains("oifname \"eth1\" drop"));
+        assert!(!rules.contains("masquerade"));
+
+        let isolation = ProvisioningIsolation::new(config(Forwarding::Nat));
+        let rules = isolation
+            .ruleset("eth1", Ipv4Addr::new(10, 42, 0, 0), 24)
+            .unwrap();
+        assert!(rules.contains("iifname \"eth1\" oifname \"eth0\" accept"));
+        assert!(rules.contains("ip saddr 10.42.0.0/24 oifname \"eth0\" masquerade"));
+
+        let isolation = ProvisioningIsolation::new(ProvisioningConfig {
+            uplink: None,
+            ..config(Forwarding::Nat)
+        });
+        assert!(isolation
+            .ruleset("eth1", Ipv4Addr::new(10, 42, 0, 0), 24)
+            .is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::{NetworkError, Result};
+use crate::network::static_ip;
+use std::net::Ipv4Addr;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// RFC 3927: hosts pick from 169.254.1.0 - 169.254.254.255
+const FIRST_HOST: u32 = u32::from_be_bytes([169, 254, 1, 0]);
+const HOST_COUNT: u32 = 254 * 256;
+/// Candidates tried before giving up; the RFC rate-limits after ten
+/// conflicts
+const MAX_CONFLICTS: u32 = 10;
+
+/// IPv4 link-local address on an interface no DHCP server answers on, so
+/// a directly attached laptop can still reach the node
+pub struct LinkLocalAddressing {
+    interface: String,
+    address: Mutex<Option<Ipv4Addr>>,
+}
+
+impl LinkLocalAddressing {
+    pub fn new(interface: &str) -> Self {
+        Self {
+            interface: interface.to_string(),
+            address: Mutex::new(None),
+        }
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// Claim an address nobody answers ARP for. The first candidate comes
+    /// from the MAC address, so a node usually gets the same one again.
+    pub async fn apply(&self) -> Result<Ipv4Addr> {
+        let seed = hardware_seed(&self.interface).await;
+        for attempt in 0..MAX_CONFLICTS {
+            let address = candidate(seed, attempt);
+            if static_ip::address_in_use(&self.interface, address).await? {
+                warn!(
+                    "Link-local address {} is in use on {}",
+                    address, self.interface
+                );
+                continue;
+            }
+
+            run_ip(&[
+                "addr",
+                "replace",
+                &format!("{}/16", address),
+                "dev",
+                &self.interface,
+                "scope",
+                "link",
+                "broadcast",
+                "169.254.255.255",
+            ])
+            .await?;
+            info!("Using link-local address {} on {}", address, self.interface);
+            *self.address.lock().await = Some(address);
+            return Ok(address);
+        }
+        Err(NetworkError::AddressConflict(format!(
+            "{} link-local addresses on {}",
+            MAX_CONFLICTS, self.interface
+        ))
+        .into())
+    }
+
+    /// Drop the address again, e.g. once DHCP succeeded
+    pub async fn remove(&self) {
+        let Some(address) = self.address.lock().await.take() else {
+            return;
+        };
+        let address = format!("{}/16", address);
+        if let Err(e) = run_ip(&["addr", "del", &address, "dev", &self.interface]).await {
+            warn!("Failed to remove {}: {}", address, e);
+        }
+    }
+}
+
+/// Candidate `attempt`, spread over the range by a multiplicative hash so
+/// neighbouring MACs do not probe the same addresses
+fn candidate(seed: u64, attempt: u32) -> Ipv4Addr {
+    let mixed = seed
+        .wrapping_add(attempt as u64)
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let offset = ((mixed >> 32) as u32) % HOST_COUNT;
+    Ipv4Addr::from(FIRST_HOST + offset)
+}
+
+async fn hardware_seed(interface: &str) -> u64 {
+    let address = tokio::fs::read_to_string(format!("/sys/class/net/{}/address", interface))
+        .await
+        .unwrap_or_default();
+    address
+        .trim()
+        .split(':')
+        .filter_map(|octet| u8::from_str_radix(octet, 16).ok())
+        .fold(0, |seed, octet| seed << 8 | octet as u64)
+}
+
+async fn run_ip(args: &[&str]) -> Result<()> {
+    let output = Command::new("ip")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| NetworkError::StaticAddressFailed(format!("Failed to run ip: {}", e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::StaticAddressFailed(format!(
+            "ip {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let seed = 0x5254_005e_8f21;
+        let first = candidate(seed, 0);
+        assert_eq!(first, candidate(seed, 0));
+        assert_ne!(first, candidate(seed, 1));
+        assert_ne!(first, candidate(seed + 1, 0));
+
+        for attempt in 0..1000 {
+            let octets = candidate(seed, attempt).octets();
+            assert_eq!(&octets[..2], &[169, 254]);
+            assert!((1..=254).contains(&octets[2]));
+        }
+    }
+}
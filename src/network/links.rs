@@ -0,0 +1,163 @@
+use crate::config::{BridgeConfig, VlanConfig};
+use crate::error::{NetworkError, Result};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// VLAN sub-interfaces and bridges from `[[network.vlans]]` and
+/// `[[network.bridges]]`. Links that already exist are reused and left in
+/// place on removal; only the ones created here are deleted.
+pub struct VirtualLinks {
+    vlans: Vec<VlanConfig>,
+    bridges: Vec<BridgeConfig>,
+    /// Created links, in creation order
+    created: Mutex<Vec<String>>,
+}
+
+impl VirtualLinks {
+    pub fn new(vlans: Vec<VlanConfig>, bridges: Vec<BridgeConfig>) -> Self {
+        Self {
+            vlans,
+            bridges,
+            created: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.vlans.is_empty() || !self.bridges.is_empty()
+    }
+
+    /// Create and bring up the VLANs, then the bridges with their members
+    pub async fn apply(&self) -> Result<()> {
+        let mut created = self.created.lock().await;
+
+        for vlan in &self.vlans {
+            let name = vlan.interface();
+            run("ip", &["link", "set", &vlan.parent, "up"]).await?;
+            if link_exists(&name).await {
+                debug!("VLAN interface {} already exists", name);
+            } else {
+                info!("Creating VLAN {} on {} as {}", vlan.id, vlan.parent, name);
+                let id = vlan.id.to_string();
+                run(
+                    "ip",
+                    &[
+                        "link",
+                        "add",
+                        "link",
+                        &vlan.parent,
+                        "name",
+                        &name,
+                        "type",
+                        "vlan",
+                        "id",
+                        &id,
+                    ],
+                )
+                .await?;
+                created.push(name.clone());
+            }
+            run("ip", &["link", "set", &name, "up"]).await?;
+        }
+
+        for bridge in &self.bridges {
+            if link_exists(&bridge.name).await {
+                debug!("Bridge {} already exists", bridge.name);
+            } else {
+                info!("Creating bridge {}", bridge.name);
+                let stp = if bridge.stp { "1" } else { "0" };
+                run(
+                    "ip",
+                    &[
+                        "link",
+                        "add",
+                        "name",
+                        &bridge.name,
+                        "type",
+                        "bridge",
+                        "stp_state",
+                        stp,
+                    ],
+                )
+                .await?;
+                created.push(bridge.name.clone());
+            }
+            for member in &bridge.members {
+                run("ip", &["link", "set", member, "master", &bridge.name]).await?;
+                run("ip", &["link", "set", member, "up"]).await?;
+            }
+            run("ip", &["link", "set", &bridge.name, "up"]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete the links created by `apply`, bridges before the VLANs in them
+    pub async fn remove(&self) -> Result<()> {
+        let mut created = self.created.lock().await;
+        let mut result = Ok(());
+        while let Some(name) = created.pop() {
+            if let Err(e) = run("ip", &["link", "delete", &name]).await {
+                warn!("Failed to delete {}: {}", name, e);
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+async fn link_exists(name: &str) -> bool {
+    Command::new("ip")
+        .args(["link", "show", "dev", name])
+        .output()
+        .await
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| NetworkError::LinkSetupFailed(format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::LinkSetupFailed(format!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_names() {
+        let vlan = VlanConfig {
+            parent: "eth0".to_string(),
+            id: 100,
+            name: None,
+        };
+        assert_eq!(vlan.interface(), "eth0.100");
+
+        let named = VlanConfig {
+            name: Some("prov".to_string()),
+            ..vlan
+        };
+        assert_eq!(named.interface(), "prov");
+
+        let bridge = BridgeConfig {
+            name: "br0".to_string(),
+            members: vec!["eth0.100".to_string()],
+            stp: false,
+        };
+        assert!(VirtualLinks::new(Vec::new(), vec![bridge]).is_enabled());
+        assert!(!VirtualLinks::new(Vec::new(), Vec::new()).is_enabled());
+    }
+}
@@ -0,0 +1,726 @@
+use crate::error::{NetworkError, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+pub(super) const MDNS_PORT: u16 = 5353;
+pub(super) const MDNS_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+pub const SERVICE_TYPE: &str = "_usb-installer._tcp.local";
+const SERVICE_ENUMERATION: &str = "_services._dns-sd._udp.local";
+
+/// RFC 6762 TTLs: records naming the host change with its addresses,
+/// the others rarely
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+/// RFC 6762 section 6.2: a record is multicast at most once per second
+const MIN_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+const TYPE_A: u16 = 1;
+pub(super) const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on records only this node answers for
+const CACHE_FLUSH: u16 = 0x8000;
+/// Set on questions asking for a unicast reply
+const UNICAST_RESPONSE: u16 = 0x8000;
+/// Response with the authoritative answer bit
+const RESPONSE_FLAGS: u16 = 0x8400;
+
+/// Ports of the node's services, published in the TXT record
+#[derive(Debug, Clone, Default)]
+pub struct ServicePorts {
+    /// REST API; also the port of the SRV record
+    pub api: u16,
+    pub vnc: Option<u16>,
+    pub ssh: Option<u16>,
+}
+
+impl ServicePorts {
+    pub fn txt_records(&self) -> Vec<String> {
+        let mut records = vec![format!("api_port={}", self.api)];
+        if let Some(port) = self.vnc {
+            records.push(format!("vnc_port={}", port));
+        }
+        if let Some(port) = self.ssh {
+            records.push(format!("ssh_port={}", port));
+        }
+        records
+    }
+}
+
+/// What the responder advertises
+#[derive(Debug, Clone)]
+pub struct ServiceInfo {
+    pub hostname: String,
+    pub port: u16,
+    pub txt: Vec<String>,
+    /// Also answer on ff02::fb and publish AAAA records
+    pub ipv6: bool,
+}
+
+impl ServiceInfo {
+    fn host_name(&self) -> String {
+        format!("{}.local", self.hostname)
+    }
+
+    fn instance_name(&self) -> String {
+        format!("{}.{}", self.hostname, SERVICE_TYPE)
+    }
+
+    pub(super) fn records(&self, addresses: &[IpAddr]) -> Vec<Record> {
+        let host = self.host_name();
+        let instance = self.instance_name();
+        let mut records = vec![
+            Record::new(SERVICE_TYPE, SERVICE_TTL, RecordData::Ptr(instance.clone())),
+            Record::new(
+                SERVICE_ENUMERATION,
+                SERVICE_TTL,
+                RecordData::Ptr(SERVICE_TYPE.to_string()),
+            ),
+            Record::new(
+                &instance,
+                HOST_TTL,
+                RecordData::Srv {
+                    port: self.port,
+                    target: host.clone(),
+                },
+            ),
+            Record::new(&instance, SERVICE_TTL, RecordData::Txt(self.txt.clone())),
+        ];
+        for address in addresses {
+            match address {
+                IpAddr::V4(ip) => records.push(Record::new(&host, HOST_TTL, RecordData::A(*ip))),
+                IpAddr::V6(ip) if self.ipv6 => {
+                    records.push(Record::new(&host, HOST_TTL, RecordData::Aaaa(*ip)))
+                }
+                IpAddr::V6(_) => {}
+            }
+        }
+        records
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Srv { port: u16, target: String },
+    Txt(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Record {
+    pub(super) name: String,
+    pub(super) ttl: u32,
+    pub(super) data: RecordData,
+}
+
+impl Record {
+    pub(super) fn new(name: &str, ttl: u32, data: RecordData) -> Self {
+        Self {
+            name: name.to_string(),
+            ttl,
+            data,
+        }
+    }
+
+    fn record_type(&self) -> u16 {
+        match self.data {
+            RecordData::A(_) => TYPE_A,
+            RecordData::Aaaa(_) => TYPE_AAAA,
+            RecordData::Ptr(_) => TYPE_PTR,
+            RecordData::Srv { .. } => TYPE_SRV,
+            RecordData::Txt(_) => TYPE_TXT,
+        }
+    }
+
+    fn answers(&self, question: &Question) -> bool {
+        self.name.eq_ignore_ascii_case(&question.name)
+            && (question.qtype == TYPE_ANY || question.qtype == self.record_type())
+    }
+
+    /// Append the record, with `ttl` instead of its own when given
+    fn encode(&self, buf: &mut Vec<u8>, ttl: Option<u32>) {
+        encode_name(buf, &self.name);
+        buf.extend(self.record_type().to_be_bytes());
+        // Shared PTR records must not flush other responders' entries
+        let class = match self.data {
+            RecordData::Ptr(_) => CLASS_IN,
+            _ => CLASS_IN | CACHE_FLUSH,
+        };
+        buf.extend(class.to_be_bytes());
+        buf.extend(ttl.unwrap_or(self.ttl).to_be_bytes());
+
+        let mut rdata = Vec::new();
+        match &self.data {
+            RecordData::A(ip) => rdata.extend(ip.octets()),
+            RecordData::Aaaa(ip) => rdata.extend(ip.octets()),
+            RecordData::Ptr(name) => encode_name(&mut rdata, name),
+            RecordData::Srv { port, target } => {
+                rdata.extend([0, 0, 0, 0]); // priority, weight
+                rdata.extend(port.to_be_bytes());
+                encode_name(&mut rdata, target);
+            }
+            RecordData::Txt(strings) if strings.is_empty() => rdata.push(0),
+            RecordData::Txt(strings) => {
+                for string in strings {
+                    let bytes = &string.as_bytes()[..string.len().min(255)];
+                    rdata.push(bytes.len() as u8);
+                    rdata.extend(bytes);
+                }
+            }
+        }
+        buf.extend((rdata.len() as u16).to_be_bytes());
+        buf.extend(rdata);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Question {
+    name: String,
+    qtype: u16,
+    unicast: bool,
+}
+
+fn encode_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        buf.push(label.len() as u8);
+        buf.extend(label);
+    }
+    buf.push(0);
+}
+
+/// Name at `offset`, following compression pointers. Returns the name and
+/// the offset after it in the original position.
+fn read_name(buf: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bounds pointer loops in malicious packets
+    for _ in 0..128 {
+        let len = *buf.get(offset)? as usize;
+        match len {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(offset + 1)));
+            }
+            l if l & 0xc0 == 0xc0 => {
+                let pointer = (l & 0x3f) << 8 | *buf.get(offset + 1)? as usize;
+                end.get_or_insert(offset + 2);
+                offset = pointer;
+            }
+            l if l <= 63 => {
+                let label = buf.get(offset + 1..offset + 1 + l)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + l;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// ID and questions of an mDNS query; responses and malformed packets
+/// yield `None`
+fn parse_query(buf: &[u8]) -> Option<(u16, Vec<Question>)> {
+    let header = buf.get(..12)?;
+    let id = u16::from_be_bytes([header[0], header[1]]);
+    if header[2] & 0x80 != 0 {
+        return None;
+    }
+    let count = u16::from_be_bytes([header[4], header[5]]);
+
+    let mut offset = 12;
+    let mut questions = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (name, next) = read_name(buf, offset)?;
+        let fields = buf.get(next..next + 4)?;
+        questions.push(Question {
+            name,
+            qtype: u16::from_be_bytes([fields[0], fields[1]]),
+            unicast: u16::from_be_bytes([fields[2], fields[3]]) & UNICAST_RESPONSE != 0,
+        });
+        offset = next + 4;
+    }
+    Some((id, questions))
+}
+
+/// Records in the answer, authority and additional sections of an mDNS
+/// response, skipping types this node has no use for. Queries and
+/// malformed packets yield `None`.
+pub(super) fn parse_response(buf: &[u8]) -> Option<Vec<Record>> {
+    let header = buf.get(..12)?;
+    if header[2] & 0x80 == 0 {
+        return None;
+    }
+    let count = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]) as usize;
+
+    let mut offset = 12;
+    for _ in 0..count(4) {
+        let (_, next) = read_name(buf, offset)?;
+        offset = next + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..count(6) + count(8) + count(10) {
+        let (name, next) = read_name(buf, offset)?;
+        let fields = buf.get(next..next + 10)?;
+        let rtype = u16::from_be_bytes([fields[0], fields[1]]);
+        let ttl = u32::from_be_bytes([fields[4], fields[5], fields[6], fields[7]]);
+        let start = next + 10;
+        let rdata = buf.get(start..start + u16::from_be_bytes([fields[8], fields[9]]) as usize)?;
+        let data = match rtype {
+            TYPE_A => <[u8; 4]>::try_from(rdata)
+                .ok()
+                .map(|ip| RecordData::A(ip.into())),
+            TYPE_AAAA => <[u8; 16]>::try_from(rdata)
+                .ok()
+                .map(|ip| RecordData::Aaaa(ip.into())),
+            TYPE_PTR => read_name(buf, start).map(|(name, _)| RecordData::Ptr(name)),
+            TYPE_SRV if rdata.len() > 6 => {
+                read_name(buf, start + 6).map(|(target, _)| RecordData::Srv {
+                    port: u16::from_be_bytes([rdata[4], rdata[5]]),
+                    target,
+                })
+            }
+            TYPE_TXT => Some(RecordData::Txt(parse_txt(rdata))),
+            _ => None,
+        };
+        if let Some(data) = data {
+            records.push(Record { name, ttl, data });
+        }
+        offset = start + rdata.len();
+    }
+    Some(records)
+}
+
+fn parse_txt(rdata: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut rest = rdata;
+    while let Some((&len, tail)) = rest.split_first() {
+        let Some(string) = tail.get(..len as usize) else {
+            break;
+        };
+        if !string.is_empty() {
+            strings.push(String::from_utf8_lossy(string).into_owned());
+        }
+        rest = &tail[len as usize..];
+    }
+    strings
+}
+
+/// Multicast query for `name`
+pub(super) fn encode_query(name: &str, qtype: u16) -> Vec<u8> {
+    let mut buf = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    encode_name(&mut buf, name);
+    buf.extend(qtype.to_be_bytes());
+    buf.extend(CLASS_IN.to_be_bytes());
+    buf
+}
+
+pub(super) fn encode_response(
+    id: u16,
+    questions: &[Question],
+    answers: &[Record],
+    additional: &[Record],
+    ttl: Option<u32>,
+) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(512);
+    buf.extend(id.to_be_bytes());
+    buf.extend(RESPONSE_FLAGS.to_be_bytes());
+    for count in [questions.len(), answers.len(), 0, additional.len()] {
+        buf.extend((count as u16).to_be_bytes());
+    }
+    for question in questions {
+        encode_name(&mut buf, &question.name);
+        buf.extend(question.qtype.to_be_bytes());
+        buf.extend(CLASS_IN.to_be_bytes());
+    }
+    for record in answers.iter().chain(additional) {
+        record.encode(&mut buf, ttl);
+    }
+    buf
+}
+
+/// Records answering `questions`, plus the SRV, TXT and address records a
+/// browser needs next when it asked for the service
+fn select_records(records: &[Record], questions: &[Question]) -> (Vec<Record>, Vec<Record>) {
+    let answers: Vec<Record> = records
+        .iter()
+        .filter(|record| questions.iter().any(|q| record.answers(q)))
+        .cloned()
+        .collect();
+    let browsed = answers
+        .iter()
+        .any(|record| matches!(record.data, RecordData::Ptr(_)) && record.name == SERVICE_TYPE);
+    let additional = records
+        .iter()
+        .filter(|record| browsed && record.record_type() != TYPE_PTR)
+        .filter(|record| !answers.contains(record))
+        .cloned()
+        .collect();
+    (answers, additional)
+}
+
+/// Global addresses in `ip -o addr show` output
+fn parse_addresses(output: &str) -> Vec<IpAddr> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            words.find(|word| *word == "inet" || *word == "inet6")?;
+            words.next()?.split('/').next()?.parse().ok()
+        })
+        .collect()
+}
+
+async fn local_addresses() -> Vec<IpAddr> {
+    match Command::new("ip")
+        .args(["-o", "addr", "show", "scope", "global"])
+        .output()
+        .await
+    {
+        Ok(output) => parse_addresses(&String::from_utf8_lossy(&output.stdout)),
+        Err(e) => {
+            tracing::warn!("Failed to list addresses for mDNS: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// In-process mDNS responder advertising the node as `_usb-installer._tcp`
+pub struct MdnsResponder {
+    service: ServiceInfo,
+    /// Send handles kept for the goodbye packets
+    sockets: Vec<(std::net::UdpSocket, SocketAddr)>,
+    /// Addresses last published, withdrawn again on stop
+    announced: Arc<Mutex<Vec<IpAddr>>>,
+    /// TXT entries describing what the node is doing, after the fixed ones
+    status: watch::Sender<Vec<String>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl MdnsResponder {
+    /// Join the mDNS groups, announce the service and answer queries until
+    /// stopped
+    pub fn start(service: ServiceInfo) -> Result<Self> {
+        let mut sockets = vec![(
+            bind_v4().map_err(socket_error)?,
+            SocketAddr::from((MDNS_V4, MDNS_PORT)),
+        )];
+        if service.ipv6 {
+            match bind_v6() {
+                Ok(socket) => sockets.push((socket, SocketAddr::from((MDNS_V6, MDNS_PORT)))),
+                Err(e) => tracing::warn!("mDNS over IPv6 unavailable: {}", e),
+            }
+        }
+
+        let announced = Arc::new(Mutex::new(Vec::new()));
+        let (status, _) = watch::channel(Vec::new());
+        let mut tasks = Vec::with_capacity(sockets.len());
+        for (socket, group) in &sockets {
+            let socket = UdpSocket::from_std(socket.try_clone().map_err(socket_error)?)
+                .map_err(socket_error)?;
+            tasks.push(tokio::spawn(serve(
+                socket,
+                *group,
+                service.clone(),
+                status.subscribe(),
+                announced.clone(),
+            )));
+        }
+
+        tracing::info!(
+            "mDNS responder advertising {} on port {}",
+            service.instance_name(),
+            service.port
+        );
+        Ok(Self {
+            service,
+            sockets,
+            announced,
+            status,
+            tasks,
+        })
+    }
+
+    /// Replace the status entries of the TXT record and announce it when
+    /// they changed, so browsers see progress without querying
+    pub fn set_status(&self, records: Vec<String>) {
+        self.status.send_if_modified(|current| {
+            let changed = *current != records;
+            *current = records;
+            changed
+        });
+    }
+
+    /// Stop answering and tell caches to drop the records (TTL 0)
+    pub fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        let addresses = self
+            .announced
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let records = with_status(&self.service, &self.status.borrow()).records(&addresses);
+        let goodbye = encode_response(0, &[], &records, &[], Some(0));
+        for (socket, group) in &self.sockets {
+            if let Err(e) = socket.send_to(&goodbye, group) {
+                tracing::warn!("Failed to send mDNS goodbye to {}: {}", group, e);
+            }
+        }
+        tracing::info!("mDNS records for {} withdrawn", self.service.host_name());
+    }
+}
+
+fn with_status(service: &ServiceInfo, status: &[String]) -> ServiceInfo {
+    let mut service = service.clone();
+    service.txt.extend(status.iter().cloned());
+    service
+}
+
+/// Records for the node's current addresses, which are remembered for the
+/// goodbye
+async fn current_records(
+    service: &ServiceInfo,
+    status: &watch::Receiver<Vec<String>>,
+    announced: &Mutex<Vec<IpAddr>>,
+) -> Vec<Record> {
+    let addresses = local_addresses().await;
+    let records = with_status(service, &status.borrow()).records(&addresses);
+    *announced.lock().unwrap_or_else(PoisonError::into_inner) = addresses;
+    records
+}
+
+async fn serve(
+    socket: UdpSocket,
+    group: SocketAddr,
+    service: ServiceInfo,
+    mut status: watch::Receiver<Vec<String>>,
+    announced: Arc<Mutex<Vec<IpAddr>>>,
+) {
+    // RFC 6762 section 8.3: at least two announcements, one second apart
+    for round in 0..2 {
+        if round > 0 {
+            tokio::time::sleep(MIN_ANNOUNCE_INTERVAL).await;
+        }
+        let records = current_records(&service, &status, &announced).await;
+        let announcement = encode_response(0, &[], &records, &[], None);
+        if let Err(e) = socket.send_to(&announcement, group).await {
+            tracing::warn!("Failed to announce on {}: {}", group, e);
+        }
+    }
+    status.mark_unchanged();
+    let mut last_announcement = Instant::now();
+    let mut status_open = true;
+
+    let mut buf = vec![0u8; 9000];
+    loop {
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buf) => received,
+            changed = status.changed(), if status_open => {
+                if changed.is_err() {
+                    status_open = false;
+                    continue;
+                }
+                // Updates arriving meanwhile are coalesced by the channel
+                tokio::time::sleep_until(last_announcement + MIN_ANNOUNCE_INTERVAL).await;
+                let records = current_records(&service, &status, &announced).await;
+                status.mark_unchanged();
+                let txt: Vec<Record> = records
+                    .into_iter()
+                    .filter(|record| record.record_type() == TYPE_TXT)
+                    .collect();
+                let announcement = encode_response(0, &[], &txt, &[], None);
+                if let Err(e) = socket.send_to(&announcement, group).await {
+                    tracing::debug!("Failed to announce status on {}: {}", group, e);
+                }
+                last_announcement = Instant::now();
+                continue;
+            }
+        };
+        let (len, source) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::warn!("mDNS receive failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let Some((id, questions)) = parse_query(&buf[..len]) else {
+            continue;
+        };
+        let records = current_records(&service, &status, &announced).await;
+        let (answers, additional) = select_records(&records, &questions);
+        if answers.is_empty() {
+            continue;
+        }
+
+        // One-shot resolvers query from another port and expect a plain
+        // DNS reply carrying their ID and questions
+        let legacy = source.port() != MDNS_PORT;
+        let response = if legacy {
+            encode_response(id, &questions, &answers, &additional, None)
+        } else {
+            encode_response(0, &[], &answers, &additional, None)
+        };
+        let destination = if legacy || questions.iter().all(|q| q.unicast) {
+            source
+        } else {
+            group
+        };
+        if let Err(e) = socket.send_to(&response, destination).await {
+            tracing::debug!("Failed to answer mDNS query from {}: {}", source, e);
+        }
+    }
+}
+
+pub(super) fn bind_v4() -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // Other responders on the host may hold the port too
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT).into())?;
+    socket.join_multicast_v4(&MDNS_V4, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+fn bind_v6() -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, MDNS_PORT, 0, 0).into())?;
+    socket.join_multicast_v6(&MDNS_V6, 0)?;
+    socket.set_multicast_hops_v6(255)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+pub(super) fn socket_error(e: io::Error) -> NetworkError {
+    NetworkError::HostnameFailed(format!("Failed to open mDNS socket: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> ServiceInfo {
+        ServiceInfo {
+            hostname: "usb-node-1234".to_string(),
+            port: 8080,
+            txt: ServicePorts {
+                api: 8080,
+                vnc: Some(5900),
+                ssh: None,
+            }
+            .txt_records(),
+            ipv6: false,
+        }
+    }
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut buf = vec![0x12, 0x34, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        encode_name(&mut buf, name);
+        buf.extend(qtype.to_be_bytes());
+        buf.extend((CLASS_IN | UNICAST_RESPONSE).to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let (id, questions) = parse_query(&query("usb-node-1234.local", TYPE_A)).unwrap();
+        assert_eq!(id, 0x1234);
+        assert_eq!(
+            questions,
+            vec![Question {
+                name: "usb-node-1234.local".to_string(),
+                qtype: TYPE_A,
+                unicast: true,
+            }]
+        );
+
+        // Second question compressed to point at the first name's "local"
+        let mut buf = query("usb-node-1234.local", TYPE_A);
+        buf[5] = 2;
+        buf.extend([3, b'f', b'o', b'o', 0xc0, 26]);
+        buf.extend(TYPE_AAAA.to_be_bytes());
+        buf.extend(CLASS_IN.to_be_bytes());
+        let (_, questions) = parse_query(&buf).unwrap();
+        assert_eq!(questions[1].name, "foo.local");
+        assert!(!questions[1].unicast);
+
+        // Responses and pointer loops are ignored
+        let mut response = query("usb-node-1234.local", TYPE_A);
+        response[2] = 0x84;
+        assert!(parse_query(&response).is_none());
+        let mut looped = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        looped.extend([0xc0, 12, 0, 1, 0, 1]);
+        assert!(parse_query(&looped).is_none());
+    }
+
+    #[test]
+    fn test_select_records() {
+        let addresses = parse_addresses(
+            "2: eth0    inet 192.168.1.20/24 brd 192.168.1.255 scope global eth0\n\
+             2: eth0    inet6 2001:db8::20/64 scope global dynamic\n",
+        );
+        assert_eq!(addresses.len(), 2);
+        let records = service().records(&addresses);
+        // AAAA left out without IPv6
+        assert_eq!(records.len(), 5);
+
+        let (_, questions) = parse_query(&query("usb-node-1234.LOCAL", TYPE_A)).unwrap();
+        let (answers, additional) = select_records(&records, &questions);
+        assert_eq!(
+            answers[0].data,
+            RecordData::A(Ipv4Addr::new(192, 168, 1, 20))
+        );
+        assert!(additional.is_empty());
+
+        let (_, questions) = parse_query(&query(SERVICE_TYPE, TYPE_PTR)).unwrap();
+        let (answers, additional) = select_records(&records, &questions);
+        assert_eq!(answers.len(), 1);
+        assert_eq!(additional.len(), 3);
+
+        let (_, questions) = parse_query(&query("other.local", TYPE_ANY)).unwrap();
+        assert!(select_records(&records, &questions).0.is_empty());
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let records = service().records(&[IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5))]);
+        let txt = records
+            .iter()
+            .find(|record| record.record_type() == TYPE_TXT)
+            .unwrap();
+        let buf = encode_response(0, &[], std::slice::from_ref(txt), &[], Some(0));
+        assert_eq!(&buf[..12], &[0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+
+        let (name, offset) = read_name(&buf, 12).unwrap();
+        assert_eq!(name, "usb-node-1234._usb-installer._tcp.local");
+        let fields = &buf[offset..offset + 10];
+        assert_eq!(&fields[..2], &TYPE_TXT.to_be_bytes());
+        assert_eq!(&fields[2..4], &(CLASS_IN | CACHE_FLUSH).to_be_bytes());
+        // Goodbye: TTL 0
+        assert_eq!(&fields[4..8], &[0, 0, 0, 0]);
+        let rdata = &buf[offset + 10..];
+        assert_eq!(rdata[0] as usize, "api_port=8080".len());
+        assert!(rdata.ends_with(b"\x0dvnc_port=5900"));
+    }
+}
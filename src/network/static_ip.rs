@@ -0,0 +1,239 @@
+use crate::config::StaticAddressConfig;
+use crate::error::{NetworkError, Result};
+use std::io::ErrorKind;
+use std::net::Ipv4Addr;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Addresses one interface from `[[network.static_addresses]]` instead of
+/// DHCP. The address is probed first so a node does not take over an
+/// address another host already uses.
+pub struct StaticAddressing {
+    config: StaticAddressConfig,
+}
+
+impl StaticAddressing {
+    pub fn new(config: StaticAddressConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.config.interface
+    }
+
+    /// Address without the prefix, as reported in the network status
+    pub fn address(&self) -> String {
+        self.config
+            .address
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    pub fn falls_back_to_dhcp(&self) -> bool {
+        self.config.fallback_to_dhcp
+    }
+
+    /// Assign the address, default route and DNS settings. Fails with
+    /// `NetworkError::AddressConflict` without changing the interface when
+    /// another host answers for the address.
+    pub async fn apply(&self) -> Result<()> {
+        let interface = self.interface();
+        let (address, prefix) = parse_address(&self.config.address)?;
+        info!(
+            "Configuring {} with static address {}/{}",
+            interface, address, prefix
+        );
+
+        run("ip", &["link", "set", interface, "up"]).await?;
+        if address_in_use(interface, address).await? {
+            return Err(NetworkError::AddressConflict(address.to_string()).into());
+        }
+
+        run(
+            "ip",
+            &["addr", "replace", &self.config.address, "dev", interface],
+        )
+        .await?;
+        if let Some(gateway) = self.config.gateway {
+            run(
+                "ip",
+                &[
+                    "route",
+                    "replace",
+                    "default",
+                    "via",
+                    &gateway.to_string(),
+                    "dev",
+                    interface,
+                ],
+            )
+            .await?;
+        }
+        apply_dns(
+            interface,
+            &self.config.dns_servers,
+            &self.config.search_domains,
+        )
+        .await
+    }
+
+    /// Whether the interface still has carrier
+    pub async fn is_up(&self) -> bool {
+        match Command::new("ip")
+            .args(["link", "show", "dev", self.interface()])
+            .output()
+            .await
+        {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).contains("LOWER_UP"),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Host address and prefix length of an address like `192.168.10.20/24`
+fn parse_address(address: &str) -> Result<(Ipv4Addr, u8)> {
+    let invalid = || NetworkError::StaticAddressFailed(format!("Invalid address: {}", address));
+    let (ip, prefix) = address.split_once('/').ok_or_else(invalid)?;
+    let ip: Ipv4Addr = ip.parse().map_err(|_| invalid())?;
+    let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+    if prefix == 0 || prefix > 32 {
+        return Err(invalid().into());
+    }
+    if prefix < 31 {
+        // Network and broadcast addresses cannot be assigned to a host
+        let host = u32::from(ip) & (u32::MAX >> prefix);
+        if host == 0 || host == u32::MAX >> prefix {
+            return Err(invalid().into());
+        }
+    }
+    Ok((ip, prefix))
+}
+
+/// Per-link DNS through systemd-resolved, or `/etc/resolv.conf` where it is
+/// not running. Also used for the settings from a DHCP lease.
+pub(crate) async fn apply_dns(
+    interface: &str,
+    dns_servers: &[Ipv4Addr],
+    search_domains: &[String],
+) -> Result<()> {
+    if dns_servers.is_empty() && search_domains.is_empty() {
+        return Ok(());
+    }
+
+    let servers: Vec<String> = dns_servers.iter().map(Ipv4Addr::to_string).collect();
+    let mut args = vec!["dns", interface];
+    args.extend(servers.iter().map(String::as_str));
+    let resolved = run("resolvectl", &args).await.is_ok() && {
+        let mut args = vec!["domain", interface];
+        args.extend(search_domains.iter().map(String::as_str));
+        run("resolvectl", &args).await.is_ok()
+    };
+    if resolved {
+        return Ok(());
+    }
+
+    debug!("systemd-resolved unavailable, writing {}", RESOLV_CONF);
+    tokio::fs::write(RESOLV_CONF, resolv_conf(dns_servers, search_domains))
+        .await
+        .map_err(|e| {
+            NetworkError::StaticAddressFailed(format!("Failed to write {}: {}", RESOLV_CONF, e))
+                .into()
+        })
+}
+
+fn resolv_conf(dns_servers: &[Ipv4Addr], search_domains: &[String]) -> String {
+    let mut contents = String::from("# Generated by usb-installer-node\n");
+    if !search_domains.is_empty() {
+        contents.push_str(&format!("search {}\n", search_domains.join(" ")));
+    }
+    for server in dns_servers {
+        contents.push_str(&format!("nameserver {}\n", server));
+    }
+    contents
+}
+
+/// Duplicate address detection with `arping -D`, which exits non-zero when
+/// another host replies. Without arping the check is skipped.
+pub(crate) async fn address_in_use(interface: &str, address: Ipv4Addr) -> Result<bool> {
+    let output = Command::new("arping")
+        .args(["-D", "-q", "-c", "2", "-w", "3", "-I", interface])
+        .arg(address.to_string())
+        .output()
+        .await;
+    match output {
+        Ok(output) => Ok(!output.status.success()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            warn!(
+                "arping not installed, not checking {} for conflicts",
+                address
+            );
+            Ok(false)
+        }
+        Err(e) => {
+            Err(NetworkError::StaticAddressFailed(format!("Failed to run arping: {}", e)).into())
+        }
+    }
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| {
+            NetworkError::StaticAddressFailed(format!("Failed to run {}: {}", program, e))
+        })?;
+    if !output.status.success() {
+        return Err(NetworkError::StaticAddressFailed(format!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("192.168.10.20/24").unwrap(),
+            (Ipv4Addr::new(192, 168, 10, 20), 24)
+        );
+        assert_eq!(
+            parse_address("10.0.0.1/32").unwrap(),
+            (Ipv4Addr::new(10, 0, 0, 1), 32)
+        );
+        assert!(parse_address("192.168.10.20").is_err());
+        assert!(parse_address("192.168.10.0/24").is_err());
+        assert!(parse_address("192.168.10.255/24").is_err());
+        assert!(parse_address("192.168.10.20/33").is_err());
+    }
+
+    #[test]
+    fn test_resolv_conf() {
+        let contents = resolv_conf(
+            &[Ipv4Addr::new(192, 168, 10, 1), Ipv4Addr::new(9, 9, 9, 9)],
+            &["lab.example.com".to_string(), "example.com".to_string()],
+        );
+        assert!(contents.contains("search lab.example.com example.com\n"));
+        assert!(contents.ends_with("nameserver 192.168.10.1\nnameserver 9.9.9.9\n"));
+
+        let node = StaticAddressing::new(StaticAddressConfig {
+            interface: "eth0".to_string(),
+            address: "192.168.10.20/24".to_string(),
+            ..StaticAddressConfig::default()
+        });
+        assert_eq!(node.address(), "192.168.10.20");
+        assert!(node.falls_back_to_dhcp());
+    }
+}
@@ -0,0 +1,167 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Instant;
+use tracing::debug;
+
+const SYS_CLASS_NET: &str = "/sys/class/net";
+
+/// Counters of one interface from `/sys/class/net/<if>/statistics`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct InterfaceTraffic {
+    pub interface: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+    /// Bytes per second since the previous sample
+    pub rx_rate: f64,
+    pub tx_rate: f64,
+}
+
+impl InterfaceTraffic {
+    /// Counters under their Prometheus names, as (name, value)
+    pub fn counters(&self) -> [(&'static str, u64); 8] {
+        [
+            ("network_receive_bytes_total", self.rx_bytes),
+            ("network_transmit_bytes_total", self.tx_bytes),
+            ("network_receive_packets_total", self.rx_packets),
+            ("network_transmit_packets_total", self.tx_packets),
+            ("network_receive_errors_total", self.rx_errors),
+            ("network_transmit_errors_total", self.tx_errors),
+            ("network_receive_drop_total", self.rx_dropped),
+            ("network_transmit_drop_total", self.tx_dropped),
+        ]
+    }
+}
+
+/// Samples interface counters and derives throughput from consecutive
+/// samples
+pub struct TrafficSampler {
+    /// Interfaces to sample; every one but loopback when empty
+    interfaces: Vec<String>,
+    previous: HashMap<String, (Instant, InterfaceTraffic)>,
+}
+
+impl TrafficSampler {
+    pub fn new(interfaces: Vec<String>) -> Self {
+        Self {
+            interfaces,
+            previous: HashMap::new(),
+        }
+    }
+
+    pub async fn sample(&mut self) -> Vec<InterfaceTraffic> {
+        let interfaces = if self.interfaces.is_empty() {
+            list_interfaces().await
+        } else {
+            self.interfaces.clone()
+        };
+
+        let now = Instant::now();
+        let mut samples = Vec::with_capacity(interfaces.len());
+        for interface in interfaces {
+            let Some(mut traffic) = read_counters(&interface).await else {
+                debug!("No counters for interface {}", interface);
+                continue;
+            };
+            if let Some((then, previous)) = self.previous.get(&interface) {
+                let elapsed = now.duration_since(*then).as_secs_f64();
+                traffic.rx_rate = rate(previous.rx_bytes, traffic.rx_bytes, elapsed);
+                traffic.tx_rate = rate(previous.tx_bytes, traffic.tx_bytes, elapsed);
+            }
+            self.previous
+                .insert(interface.clone(), (now, traffic.clone()));
+            samples.push(traffic);
+        }
+        samples
+    }
+}
+
+/// Bytes per second between two counter readings; a counter that went
+/// backwards (interface recreated) restarts from zero
+fn rate(previous: u64, current: u64, elapsed: f64) -> f64 {
+    if elapsed <= 0.0 {
+        return 0.0;
+    }
+    let delta = if current >= previous {
+        current - previous
+    } else {
+        current
+    };
+    delta as f64 / elapsed
+}
+
+async fn list_interfaces() -> Vec<String> {
+    let mut interfaces = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(SYS_CLASS_NET).await else {
+        return interfaces;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name != "lo" {
+            interfaces.push(name);
+        }
+    }
+    interfaces.sort();
+    interfaces
+}
+
+async fn read_counters(interface: &str) -> Option<InterfaceTraffic> {
+    let statistics = format!("{}/{}/statistics", SYS_CLASS_NET, interface);
+    let read = |name: &str| {
+        let path = format!("{}/{}", statistics, name);
+        async move {
+            tokio::fs::read_to_string(path)
+                .await
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        }
+    };
+
+    Some(InterfaceTraffic {
+        interface: interface.to_string(),
+        // Present for every interface; missing means it is gone
+        rx_bytes: read("rx_bytes").await?,
+        tx_bytes: read("tx_bytes").await.unwrap_or(0),
+        rx_packets: read("rx_packets").await.unwrap_or(0),
+        tx_packets: read("tx_packets").await.unwrap_or(0),
+        rx_errors: read("rx_errors").await.unwrap_or(0),
+        tx_errors: read("tx_errors").await.unwrap_or(0),
+        rx_dropped: read("rx_dropped").await.unwrap_or(0),
+        tx_dropped: read("tx_dropped").await.unwrap_or(0),
+        ..InterfaceTraffic::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(rate(1_000, 11_000, 2.0), 5_000.0);
+        // Counter reset
+        assert_eq!(rate(50_000, 4_000, 2.0), 2_000.0);
+        assert_eq!(rate(0, 100, 0.0), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_sample_loopback() {
+        let mut sampler = TrafficSampler::new(vec!["lo".to_string()]);
+        let first = sampler.sample().await;
+        if first.is_empty() {
+            // No sysfs in this environment
+            return;
+        }
+        assert_eq!(first[0].interface, "lo");
+        assert_eq!(first[0].rx_rate, 0.0);
+
+        let second = sampler.sample().await;
+        assert!(second[0].rx_bytes >= first[0].rx_bytes);
+        assert_eq!(second[0].counters()[0].1, second[0].rx_bytes);
+    }
+}
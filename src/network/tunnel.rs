@@ -1,11 +1,21 @@
-use crate::config::TunnelConfig;
-use crate::error::{Result, UsbNodeError};
+pub mod wireguard;
+
+use crate::config::{TunnelConfig, TunnelProvider};
+use crate::error::{NetworkError, Result, UsbNodeError};
+use crate::monitoring::clock::unix_now;
+use crate::network::mdns::ServicePorts;
+use crate::service::supervisor::{ProcessSupervisor, SupervisedProcess, STOP_TIMEOUT};
 use log::{debug, error, info, warn};
-use std::process::{Child, Command, Stdio};
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{ChildStderr, Command};
+use tokio::sync::{mpsc, RwLock};
 use tokio::time::sleep;
+use wireguard::{PeerStats, WireguardTunnel};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TunnelState {
@@ -16,18 +26,30 @@ pub enum TunnelState {
     Reconnecting,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TunnelStatus {
     pub state: TunnelState,
+    /// Tailscale address of the node, or the WireGuard peer or SSH host
+    /// the tunnel leads to
     pub endpoint: Option<String>,
+    /// Round trip of the probe, or of the last WireGuard handshake without
+    /// one
     pub latency: Option<Duration>,
     pub last_connected: Option<Instant>,
     pub error_count: u32,
+    /// Handshake and traffic counters of the embedded WireGuard peers
+    pub wireguard_peers: Vec<PeerStats>,
+    /// Jump host port of each service published over the reverse SSH
+    /// tunnel
+    pub published_ports: BTreeMap<String, u16>,
 }
 
 pub struct TunnelManager {
     config: TunnelConfig,
-    process: Arc<RwLock<Option<Child>>>,
+    process: Arc<RwLock<Option<SupervisedProcess>>>,
+    embedded: Arc<RwLock<Option<WireguardTunnel>>>,
+    /// Local ports of the services a reverse SSH tunnel publishes
+    service_ports: ServicePorts,
     status: Arc<RwLock<TunnelStatus>>,
     shutdown: Arc<RwLock<bool>>,
 }
@@ -37,17 +59,27 @@ impl TunnelManager {
         Self {
             config,
             process: Arc::new(RwLock::new(None)),
+            embedded: Arc::new(RwLock::new(None)),
+            service_ports: ServicePorts::default(),
             status: Arc::new(RwLock::new(TunnelStatus {
                 state: TunnelState::Disconnected,
                 endpoint: None,
                 latency: None,
                 last_connected: None,
                 error_count: 0,
+                wireguard_peers: Vec::new(),
+                published_ports: BTreeMap::new(),
             })),
             shutdown: Arc::new(RwLock::new(false)),
         }
     }
 
+    /// Ports of the services `[network.tunnel.ssh] publish` refers to
+    pub fn with_service_ports(mut self, ports: ServicePorts) -> Self {
+        self.service_ports = ports;
+        self
+    }
+
     pub async fn start(&self) -> Result<()> {
         if !self.config.enabled {
             info!("Tunnel disabled in configuration");
@@ -55,11 +87,17 @@ impl TunnelManager {
         }
 
         self.validate_config()?;
+        // Started again after `stop`
+        *self.shutdown.write().await = false;
         self.set_state(TunnelState::Connecting).await;
 
+        if self.is_embedded_wireguard() {
+            return self.start_embedded_wireguard().await;
+        }
+
         match self.spawn_tunnel_process().await {
-            Ok(child) => {
-                *self.process.write().await = Some(child);
+            Ok(process) => {
+                *self.process.write().await = Some(process);
                 self.set_state(TunnelState::Connected).await;
                 self.update_connected_time().await;
                 info!("Tunnel started successfully");
@@ -79,43 +117,155 @@ impl TunnelManager {
     pub async fn stop(&self) -> Result<()> {
         *self.shutdown.write().await = true;
 
-        if let Some(mut child) = self.process.write().await.take() {
-            match child.kill() {
-                Ok(_) => {
-                    info!("Tunnel process terminated");
-                    let _ = child.wait();
-                }
-                Err(e) => {
-                    warn!("Failed to kill tunnel process: {}", e);
-                }
+        if let Some(tunnel) = self.embedded.write().await.take() {
+            tunnel.stop();
+        }
+
+        if let Some(mut process) = self.process.write().await.take() {
+            match process.stop(STOP_TIMEOUT).await {
+                Ok(_) => info!("Tunnel process terminated"),
+                Err(e) => warn!("Failed to stop tunnel process: {}", e),
             }
         }
 
+        self.status.write().await.published_ports.clear();
         self.set_state(TunnelState::Disconnected).await;
         Ok(())
     }
 
     pub async fn get_status(&self) -> TunnelStatus {
-        self.status.read().await.clone()
+        let mut status = self.status.read().await.clone();
+        if let Some(tunnel) = self.embedded.read().await.as_ref() {
+            status.wireguard_peers = tunnel.peer_stats();
+            if self.config.probe.target.is_none() {
+                status.latency = status
+                    .wireguard_peers
+                    .iter()
+                    .filter_map(|peer| peer.latency_ms)
+                    .min()
+                    .map(|ms| Duration::from_millis(ms.into()));
+            }
+        }
+        status
     }
 
+    /// Ask the provider whether the tunnel is up, then reach the probe
+    /// target through it. Updates the endpoint and latency in the status.
     pub async fn health_check(&self) -> Result<bool> {
-        let status = self.get_status().await;
-
-        match status.state {
-            TunnelState::Connected => {
-                if let Some(endpoint) = &status.endpoint {
-                    self.ping_endpoint(endpoint).await
-                } else {
-                    Ok(false)
-                }
+        if self.get_status().await.state != TunnelState::Connected {
+            return Ok(false);
+        }
+
+        let (provider_healthy, endpoint) = match self.config.provider {
+            TunnelProvider::Tailscale => self.check_tailscale().await?,
+            TunnelProvider::Wireguard => self.check_wireguard().await?,
+            // The monitor restarts ssh once keepalives go unanswered
+            TunnelProvider::Ssh => (true, self.config.ssh.jump_host.clone()),
+        };
+        self.status.write().await.endpoint = endpoint;
+        if !provider_healthy {
+            return Ok(false);
+        }
+
+        let Some(target) = &self.config.probe.target else {
+            return Ok(true);
+        };
+        let timeout = Duration::from_secs(self.config.probe.timeout);
+        match probe(target, timeout).await {
+            Ok(rtt) => {
+                debug!("Tunnel probe {} answered in {:?}", target, rtt);
+                self.status.write().await.latency = Some(rtt);
+                Ok(true)
+            }
+            Err(e) => {
+                warn!("Tunnel probe failed: {}", e);
+                self.status.write().await.latency = None;
+                Ok(false)
+            }
+        }
+    }
+
+    /// `tailscale status --json`: logged in, running and online
+    async fn check_tailscale(&self) -> Result<(bool, Option<String>)> {
+        let output = tokio::process::Command::new("tailscale")
+            .args(["status", "--json"])
+            .output()
+            .await
+            .map_err(|e| NetworkError::TunnelFailed(format!("Failed to run tailscale: {}", e)))?;
+        match parse_tailscale_status(&String::from_utf8_lossy(&output.stdout)) {
+            Some(status) => Ok(status),
+            None => {
+                warn!("Unreadable tailscale status");
+                Ok((false, None))
             }
-            _ => Ok(false),
         }
     }
 
-    async fn spawn_tunnel_process(&self) -> Result<Child> {
-        let mut cmd = match self.config.tunnel_type.as_str() {
+    /// Age of the newest handshake with any peer
+    async fn check_wireguard(&self) -> Result<(bool, Option<String>)> {
+        let max_age = self.config.probe.max_handshake_age;
+        if let Some(tunnel) = self.embedded.read().await.as_ref() {
+            let peers = tunnel.peer_stats();
+            let healthy = peers
+                .iter()
+                .any(|peer| peer.last_handshake.is_some_and(|age| age < max_age));
+            let endpoint = peers
+                .iter()
+                .find_map(|peer| peer.endpoint)
+                .map(|endpoint| endpoint.to_string());
+            return Ok((healthy, endpoint));
+        }
+
+        // wg-quick names the interface after the configuration file
+        let interface = self
+            .config
+            .config_path
+            .as_deref()
+            .and_then(Path::file_stem)
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .ok_or_else(|| {
+                NetworkError::TunnelFailed("WireGuard config path required".to_string())
+            })?;
+        let handshakes = wg_show(&interface, "latest-handshakes").await?;
+        let now = unix_now();
+        let healthy = handshake_ages(&handshakes, now)
+            .iter()
+            .any(|age| *age < max_age);
+        let endpoint = wg_show(&interface, "endpoints")
+            .await?
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .find(|endpoint| *endpoint != "(none)")
+            .map(str::to_string);
+        Ok((healthy, endpoint))
+    }
+
+    fn is_embedded_wireguard(&self) -> bool {
+        matches!(self.config.provider, TunnelProvider::Wireguard) && self.config.wireguard.embedded
+    }
+
+    /// In-process WireGuard; restarts are not needed as the handshakes
+    /// retry on their own
+    async fn start_embedded_wireguard(&self) -> Result<()> {
+        match WireguardTunnel::start(&self.config.wireguard).await {
+            Ok(tunnel) => {
+                *self.embedded.write().await = Some(tunnel);
+                self.set_state(TunnelState::Connected).await;
+                self.update_connected_time().await;
+                info!("Embedded WireGuard tunnel started");
+                Ok(())
+            }
+            Err(e) => {
+                self.increment_error_count().await;
+                self.set_state(TunnelState::Error).await;
+                error!("Failed to start embedded WireGuard tunnel: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn spawn_tunnel_process(&self) -> Result<SupervisedProcess> {
+        let cmd = match self.config.tunnel_type.as_str() {
             "tailscale" => self.build_tailscale_command()?,
             "wireguard" => self.build_wireguard_command()?,
             "ssh" => self.build_ssh_command()?,
@@ -127,12 +277,17 @@ impl TunnelManager {
             }
         };
 
-        cmd.stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .stdin(Stdio::null());
-
-        cmd.spawn()
-            .map_err(|e| UsbNodeError::Network(format!("Failed to spawn tunnel process: {}", e)))
+        let name = cmd.as_std().get_program().to_string_lossy().into_owned();
+        let mut process = ProcessSupervisor::global()
+            .spawn_piped(&name, cmd)
+            .map_err(|e| UsbNodeError::Network(format!("Failed to spawn tunnel process: {}", e)))?;
+        if let Some(jump_host) = &self.config.ssh.jump_host {
+            if let Some(stderr) = process.take_stderr() {
+                tokio::spawn(self.clone().register_forwards(jump_host.clone(), stderr));
+            }
+        }
+        process.log_output();
+        Ok(process)
     }
 
     fn build_tailscale_command(&self) -> Result<Command> {
@@ -163,6 +318,10 @@ impl TunnelManager {
     }
 
     fn build_ssh_command(&self) -> Result<Command> {
+        if let Some(jump_host) = &self.config.ssh.jump_host {
+            return Ok(self.build_reverse_ssh_command(jump_host));
+        }
+
         let remote_host = self
             .config
             .remote_host
@@ -194,6 +353,103 @@ impl TunnelManager {
         Ok(cmd)
     }
 
+    /// `ssh -R` for every published service. Runs in the foreground so the
+    /// monitor notices a dropped connection and reconnects.
+    fn build_reverse_ssh_command(&self, jump_host: &str) -> Command {
+        let ssh = &self.config.ssh;
+        let mut cmd = Command::new("ssh");
+        cmd.arg("-N")
+            .arg("-p")
+            .arg(ssh.port.to_string())
+            .arg("-o")
+            .arg("BatchMode=yes")
+            .arg("-o")
+            .arg("StrictHostKeyChecking=accept-new")
+            // A port that cannot be published is a failed tunnel
+            .arg("-o")
+            .arg("ExitOnForwardFailure=yes")
+            .arg("-o")
+            .arg(format!("ServerAliveInterval={}", ssh.keepalive_interval))
+            .arg("-o")
+            .arg("ServerAliveCountMax=3");
+
+        if let Some(identity_file) = &ssh.identity_file {
+            cmd.arg("-i").arg(identity_file);
+        }
+
+        // Port 0: the jump host picks a free port and ssh reports it
+        for (_, port) in self.published_services() {
+            cmd.arg("-R").arg(format!("0:localhost:{}", port));
+        }
+
+        cmd.arg(jump_host);
+        cmd
+    }
+
+    /// Configured services with their local ports; disabled ones are skipped
+    fn published_services(&self) -> Vec<(String, u16)> {
+        self.config
+            .ssh
+            .publish
+            .iter()
+            .filter_map(|service| {
+                let port = match service.as_str() {
+                    "api" => Some(self.service_ports.api),
+                    "vnc" => self.service_ports.vnc,
+                    "ssh" => self.service_ports.ssh,
+                    _ => None,
+                };
+                if port.is_none() {
+                    warn!("Not publishing {}: service is not enabled", service);
+                }
+                port.map(|port| (service.clone(), port))
+            })
+            .collect()
+    }
+
+    /// Collect the ports the jump host allocated from ssh's log and report
+    /// them to the registration endpoint
+    async fn register_forwards(self, jump_host: String, stderr: ChildStderr) {
+        let services = self.published_services();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                match parse_allocated_port(&line) {
+                    Some(allocation) => {
+                        if tx.send(allocation).is_err() {
+                            break;
+                        }
+                    }
+                    None => debug!("ssh: {}", line),
+                }
+            }
+        });
+
+        let mut published = BTreeMap::new();
+        while published.len() < services.len() {
+            // ssh exited before all forwards were up; the monitor restarts it
+            let Some((remote, local)) = rx.recv().await else {
+                return;
+            };
+            if let Some((service, _)) = services.iter().find(|(_, port)| *port == local) {
+                info!("Published {} on {} port {}", service, jump_host, remote);
+                published.insert(service.clone(), remote);
+            }
+        }
+        self.status.write().await.published_ports = published.clone();
+
+        if let Some(url) = &self.config.ssh.registration_url {
+            match register(url, &jump_host, &published).await {
+                Ok(()) => info!("Registered published ports with {}", url),
+                Err(e) => {
+                    warn!("Registration failed: {}", e);
+                    self.increment_error_count().await;
+                }
+            }
+        }
+    }
+
     fn validate_config(&self) -> Result<()> {
         match self.config.tunnel_type.as_str() {
             "tailscale" => {
@@ -204,14 +460,14 @@ impl TunnelManager {
                 }
             }
             "wireguard" => {
-                if self.config.config_path.is_none() {
+                if self.config.config_path.is_none() && !self.config.wireguard.embedded {
                     return Err(UsbNodeError::Config(
                         "WireGuard config path required".to_string(),
                     ));
                 }
             }
             "ssh" => {
-                if self.config.remote_host.is_none() {
+                if self.config.remote_host.is_none() && self.config.ssh.jump_host.is_none() {
                     return Err(UsbNodeError::Config("SSH remote host required".to_string()));
                 }
             }
@@ -225,20 +481,6 @@ impl TunnelManager {
         Ok(())
     }
 
-    async fn ping_endpoint(&self, endpoint: &str) -> Result<bool> {
-        let output = Command::new("ping")
-            .arg("-c")
-            .arg("1")
-            .arg("-W")
-            .arg("3")
-            .arg(endpoint)
-            .output()
-            .await
-            .map_err(|e| UsbNodeError::Network(format!("Ping failed: {}", e)))?;
-
-        Ok(output.status.success())
-    }
-
     async fn set_state(&self, state: TunnelState) {
         self.status.write().await.state = state;
     }
@@ -265,27 +507,23 @@ impl TunnelManager {
 
             let process_alive = {
                 let mut process_guard = self.process.write().await;
-                if let Some(ref mut child) = process_guard.as_mut() {
-                    match child.try_wait() {
-                        Ok(Some(_)) => false, // Process exited
-                        Ok(None) => true,     // Process still running
-                        Err(_) => false,      // Error checking process
-                    }
-                } else {
-                    false
+                match process_guard.as_mut() {
+                    Some(process) => process.is_running(),
+                    None => false,
                 }
             };
 
             if !process_alive {
                 warn!("Tunnel process died, attempting restart");
                 self.set_state(TunnelState::Reconnecting).await;
+                self.status.write().await.published_ports.clear();
                 self.increment_error_count().await;
 
                 sleep(backoff).await;
 
                 match self.spawn_tunnel_process().await {
-                    Ok(child) => {
-                        *self.process.write().await = Some(child);
+                    Ok(process) => {
+                        *self.process.write().await = Some(process);
                         self.set_state(TunnelState::Connected).await;
                         self.update_connected_time().await;
                         info!("Tunnel process restarted successfully");
@@ -309,8 +547,176 @@ impl Clone for TunnelManager {
         Self {
             config: self.config.clone(),
             process: Arc::clone(&self.process),
+            embedded: Arc::clone(&self.embedded),
+            service_ports: self.service_ports.clone(),
             status: Arc::clone(&self.status),
             shutdown: Arc::clone(&self.shutdown),
         }
     }
 }
+
+/// Whether the node is up in `tailscale status --json`, and its first
+/// Tailscale address
+fn parse_tailscale_status(json: &str) -> Option<(bool, Option<String>)> {
+    let status: serde_json::Value = serde_json::from_str(json).ok()?;
+    let running = status.get("BackendState")?.as_str()? == "Running";
+    let node = status.get("Self")?;
+    // Older clients leave `Online` out
+    let online = node
+        .get("Online")
+        .and_then(serde_json::Value::as_bool)
+        .unwrap_or(true);
+    let address = node
+        .get("TailscaleIPs")
+        .and_then(|ips| ips.get(0))
+        .and_then(serde_json::Value::as_str)
+        .map(str::to_string);
+    Some((running && online, address))
+}
+
+async fn wg_show(interface: &str, field: &str) -> Result<String> {
+    let output = tokio::process::Command::new("wg")
+        .args(["show", interface, field])
+        .output()
+        .await
+        .map_err(|e| NetworkError::TunnelFailed(format!("Failed to run wg: {}", e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::TunnelFailed(format!(
+            "wg show {} failed: {}",
+            interface,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Seconds since each peer's handshake in `wg show <if> latest-handshakes`
+/// output; peers that never completed one are left out
+fn handshake_ages(output: &str, now: u64) -> Vec<u64> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1)?.parse::<u64>().ok())
+        .filter(|timestamp| *timestamp > 0)
+        .map(|timestamp| now.saturating_sub(timestamp))
+        .collect()
+}
+
+/// Round trip to `target`: TCP connect for `tcp://host:port`, a full
+/// request for HTTP(S)
+async fn probe(target: &str, timeout: Duration) -> Result<Duration> {
+    if let Some(address) = target.strip_prefix("tcp://") {
+        let start = Instant::now();
+        return match tokio::time::timeout(timeout, TcpStream::connect(address)).await {
+            Ok(Ok(_)) => Ok(start.elapsed()),
+            Ok(Err(e)) => {
+                Err(NetworkError::TunnelFailed(format!("{} unreachable: {}", target, e)).into())
+            }
+            Err(_) => Err(NetworkError::TunnelFailed(format!("{} timed out", target)).into()),
+        };
+    }
+
+    let output = tokio::process::Command::new("curl")
+        .args(["-fsS", "-o", "/dev/null", "-w", "%{time_total}"])
+        .arg("--max-time")
+        .arg(timeout.as_secs().to_string())
+        .arg(target)
+        .output()
+        .await
+        .map_err(|e| NetworkError::TunnelFailed(format!("Failed to run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::TunnelFailed(format!(
+            "{} unreachable: {}",
+            target,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<f64>()
+        .map(Duration::from_secs_f64)
+        .map_err(|_| NetworkError::TunnelFailed(format!("No timing from {}", target)).into())
+}
+
+/// Forward from ssh's log line "Allocated port 43021 for remote forward to
+/// localhost:8080", as (jump host port, local port)
+fn parse_allocated_port(line: &str) -> Option<(u16, u16)> {
+    let rest = line.trim().strip_prefix("Allocated port ")?;
+    let (remote, target) = rest.split_once(" for remote forward to ")?;
+    let (_, local) = target.rsplit_once(':')?;
+    Some((remote.parse().ok()?, local.parse().ok()?))
+}
+
+/// Handshake with the registry: POST the node's hostname and published
+/// ports; anything but a 2xx answer is a failure
+async fn register(url: &str, jump_host: &str, ports: &BTreeMap<String, u16>) -> Result<()> {
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default();
+    let body = serde_json::json!({
+        "hostname": hostname,
+        "jump_host": jump_host,
+        "ports": ports,
+    });
+
+    let output = tokio::process::Command::new("curl")
+        .args(["-fsS", "--retry", "3", "-X", "POST"])
+        .args(["-H", "Content-Type: application/json"])
+        .arg("-d")
+        .arg(body.to_string())
+        .arg(url)
+        .output()
+        .await
+        .map_err(|e| NetworkError::TunnelFailed(format!("Failed to run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(NetworkError::TunnelFailed(format!(
+            "{} rejected the registration: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tailscale_status() {
+        let json = r#"{
+            "BackendState": "Running",
+            "Self": {"HostName": "usb-node-1a2b", "Online": true,
+                     "TailscaleIPs": ["100.101.102.103", "fd7a:115c:a1e0::1"]}
+        }"#;
+        assert_eq!(
+            parse_tailscale_status(json),
+            Some((true, Some("100.101.102.103".to_string())))
+        );
+        let json = r#"{"BackendState": "NeedsLogin", "Self": {"Online": false}}"#;
+        assert_eq!(parse_tailscale_status(json), Some((false, None)));
+        assert_eq!(parse_tailscale_status("tailscaled not running"), None);
+
+        let handshakes = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\t1760600000\n\
+                          TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=\t0\n";
+        assert_eq!(handshake_ages(handshakes, 1760600042), vec![42]);
+    }
+
+    #[test]
+    fn test_parse_allocated_port() {
+        assert_eq!(
+            parse_allocated_port("Allocated port 43021 for remote forward to localhost:8080\r"),
+            Some((43021, 8080))
+        );
+        assert_eq!(
+            parse_allocated_port("Warning: Permanently added 'jump'"),
+            None
+        );
+        assert_eq!(
+            parse_allocated_port("Allocated port x for remote forward to localhost:8080"),
+            None
+        );
+    }
+}
@@ -0,0 +1,578 @@
+use crate::config::WireguardConfig;
+use crate::error::{NetworkError, Result};
+use boringtun::noise::errors::WireGuardError;
+use boringtun::noise::{Tunn, TunnResult};
+use boringtun::x25519::{PublicKey, StaticSecret};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::net::UdpSocket;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+const TUN_DEVICE: &str = "/dev/net/tun";
+const IFF_TUN: i16 = 0x0001;
+/// Packets without the 4-byte protocol header
+const IFF_NO_PI: i16 = 0x1000;
+/// boringtun expects its timers to be driven at least this often
+const TIMER_TICK: Duration = Duration::from_millis(250);
+/// Largest IP packet plus WireGuard overhead
+const BUFFER_SIZE: usize = 65536 + 148;
+
+mod ioctl {
+    use super::IfReq;
+
+    // TUNSETIFF, _IOW('T', 202, int)
+    nix::ioctl_write_ptr_bad!(tun_set_iff, 0x400454ca, IfReq);
+}
+
+/// `struct ifreq` from `<net/if.h>`, as far as TUNSETIFF reads it
+#[repr(C)]
+struct IfReq {
+    ifr_name: [u8; 16],
+    ifr_flags: i16,
+    _pad: [u8; 22],
+}
+
+impl IfReq {
+    fn new(name: &str, flags: i16) -> Self {
+        let mut request = Self {
+            ifr_name: [0; 16],
+            ifr_flags: flags,
+            _pad: [0; 22],
+        };
+        // Always NUL-terminated
+        let len = name.len().min(request.ifr_name.len() - 1);
+        request.ifr_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        request
+    }
+}
+
+/// Handshake and traffic counters of one peer
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerStats {
+    pub public_key: String,
+    pub endpoint: Option<SocketAddr>,
+    /// Seconds since the last completed handshake
+    pub last_handshake: Option<u64>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Round trip of the last handshake
+    pub latency_ms: Option<u32>,
+    /// Estimated share of packets lost, 0.0 to 1.0
+    pub packet_loss: f32,
+}
+
+/// TUN interface; removed by the kernel when the descriptor closes
+struct TunDevice {
+    fd: AsyncFd<File>,
+}
+
+impl TunDevice {
+    fn open(name: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(nix::libc::O_NONBLOCK)
+            .open(TUN_DEVICE)
+            .map_err(|e| tunnel_error(&format!("Cannot open {}", TUN_DEVICE), e))?;
+        let request = IfReq::new(name, IFF_TUN | IFF_NO_PI);
+        // SAFETY: `request` matches the kernel's ifreq layout and outlives
+        // the call
+        unsafe { ioctl::tun_set_iff(file.as_raw_fd(), &request) }
+            .map_err(|e| tunnel_error(&format!("Cannot create {}", name), e.into()))?;
+        let fd = AsyncFd::new(file).map_err(|e| tunnel_error("Cannot poll TUN device", e))?;
+        Ok(Self { fd })
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            if let Ok(result) = guard.try_io(|file| (&mut file.get_ref()).read(buf)) {
+                return result;
+            }
+        }
+    }
+
+    async fn write(&self, packet: &[u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.fd.writable().await?;
+            if let Ok(result) = guard.try_io(|file| (&mut file.get_ref()).write(packet)) {
+                return result;
+            }
+        }
+    }
+}
+
+struct Peer {
+    /// As configured, to tell peers apart in the stats
+    public_key: String,
+    tunnel: Mutex<Tunn>,
+    /// Configured endpoint, then wherever the peer last sent from
+    endpoint: Mutex<Option<SocketAddr>>,
+    allowed_ips: Vec<(IpAddr, u8)>,
+}
+
+impl Peer {
+    fn endpoint(&self) -> Option<SocketAddr> {
+        *self.endpoint.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn allows(&self, address: IpAddr) -> bool {
+        self.allowed_ips
+            .iter()
+            .any(|network| prefix_len_matching(*network, address).is_some())
+    }
+}
+
+/// Packets produced by one call into a peer's tunnel, copied out so the
+/// lock is released before sending
+#[derive(Default)]
+struct Output {
+    network: Vec<Vec<u8>>,
+    tunnel: Vec<Vec<u8>>,
+}
+
+struct Shared {
+    tun: TunDevice,
+    socket: UdpSocket,
+    peers: Vec<Peer>,
+}
+
+impl Shared {
+    async fn send(&self, peer: &Peer, output: Output) {
+        for packet in output.tunnel {
+            if let Err(e) = self.tun.write(&packet).await {
+                debug!("Failed to deliver WireGuard packet: {}", e);
+            }
+        }
+        let Some(endpoint) = peer.endpoint() else {
+            return;
+        };
+        for packet in output.network {
+            if let Err(e) = self.socket.send_to(&packet, endpoint).await {
+                debug!("Failed to send to WireGuard peer {}: {}", endpoint, e);
+            }
+        }
+    }
+}
+
+/// In-process WireGuard interface built from `[network.tunnel.wireguard]`;
+/// needs neither the kernel module's tools nor wg-quick
+pub struct WireguardTunnel {
+    interface: String,
+    shared: Arc<Shared>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl WireguardTunnel {
+    /// Create the interface, address it, route the peers' allowed IPs
+    /// through it and start the handshakes
+    pub async fn start(config: &WireguardConfig) -> Result<Self> {
+        let private_key = config
+            .private_key
+            .as_deref()
+            .and_then(decode_key)
+            .ok_or_else(|| NetworkError::TunnelFailed("Invalid WireGuard private key".into()))?;
+
+        let mut peers = Vec::with_capacity(config.peers.len());
+        for (index, peer) in config.peers.iter().enumerate() {
+            let public_key = decode_key(&peer.public_key).ok_or_else(|| {
+                NetworkError::TunnelFailed(format!("Invalid public key {}", peer.public_key))
+            })?;
+            let preshared_key = match &peer.preshared_key {
+                Some(key) => Some(decode_key(key).ok_or_else(|| {
+                    NetworkError::TunnelFailed(format!(
+                        "Invalid preshared key for {}",
+                        peer.public_key
+                    ))
+                })?),
+                None => None,
+            };
+            let endpoint = match &peer.endpoint {
+                Some(endpoint) => Some(resolve_endpoint(endpoint).await?),
+                None => None,
+            };
+            let allowed_ips = peer
+                .allowed_ips
+                .iter()
+                .map(|network| {
+                    parse_network(network).ok_or_else(|| {
+                        NetworkError::TunnelFailed(format!("Invalid allowed IP {}", network))
+                    })
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let tunnel = Tunn::new(
+                StaticSecret::from(private_key),
+                PublicKey::from(public_key),
+                preshared_key,
+                peer.persistent_keepalive,
+                index as u32,
+                None,
+            );
+
+            peers.push(Peer {
+                public_key: peer.public_key.clone(),
+                tunnel: Mutex::new(tunnel),
+                endpoint: Mutex::new(endpoint),
+                allowed_ips,
+            });
+        }
+
+        let tun = TunDevice::open(&config.interface)?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.listen_port.unwrap_or(0)))
+            .await
+            .map_err(|e| tunnel_error("Cannot bind WireGuard socket", e))?;
+        configure_interface(config).await?;
+
+        let shared = Arc::new(Shared { tun, socket, peers });
+        let tasks = vec![
+            tokio::spawn(outbound(shared.clone())),
+            tokio::spawn(inbound(shared.clone())),
+            tokio::spawn(timers(shared.clone())),
+        ];
+
+        info!(
+            "WireGuard interface {} up with {} peer(s)",
+            config.interface,
+            shared.peers.len()
+        );
+        Ok(Self {
+            interface: config.interface.clone(),
+            shared,
+            tasks,
+        })
+    }
+
+    pub fn peer_stats(&self) -> Vec<PeerStats> {
+        self.shared
+            .peers
+            .iter()
+            .map(|peer| {
+                let (handshake, tx_bytes, rx_bytes, loss, rtt) = peer
+                    .tunnel
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .stats();
+                PeerStats {
+                    public_key: peer.public_key.clone(),
+                    endpoint: peer.endpoint(),
+                    last_handshake: handshake.map(|elapsed| elapsed.as_secs()),
+                    rx_bytes: rx_bytes as u64,
+                    tx_bytes: tx_bytes as u64,
+                    latency_ms: rtt,
+                    packet_loss: loss,
+                }
+            })
+            .collect()
+    }
+
+    /// Stop forwarding; the interface and its routes go away with the
+    /// last reference to the device
+    pub fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        info!("WireGuard interface {} stopped", self.interface);
+    }
+}
+
+/// Packets the node sends into the tunnel
+async fn outbound(shared: Arc<Shared>) {
+    let mut packet = vec![0u8; BUFFER_SIZE];
+    let mut out = vec![0u8; BUFFER_SIZE];
+    loop {
+        let len = match shared.tun.read(&mut packet).await {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("WireGuard interface read failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let Some(destination) = Tunn::dst_address(&packet[..len]) else {
+            continue;
+        };
+        let networks: Vec<&[(IpAddr, u8)]> = shared
+            .peers
+            .iter()
+            .map(|peer| peer.allowed_ips.as_slice())
+            .collect();
+        let Some(index) = select_peer(&networks, destination) else {
+            debug!("No WireGuard peer for {}", destination);
+            continue;
+        };
+        let peer = &shared.peers[index];
+
+        let mut output = Output::default();
+        {
+            let mut tunnel = peer.tunnel.lock().unwrap_or_else(PoisonError::into_inner);
+            match tunnel.encapsulate(&packet[..len], &mut out) {
+                TunnResult::WriteToNetwork(datagram) => output.network.push(datagram.to_vec()),
+                TunnResult::Err(e) => debug!("WireGuard encapsulation failed: {:?}", e),
+                _ => {}
+            }
+        }
+        shared.send(peer, output).await;
+    }
+}
+
+/// Datagrams from the peers
+async fn inbound(shared: Arc<Shared>) {
+    let mut datagram = vec![0u8; BUFFER_SIZE];
+    let mut out = vec![0u8; BUFFER_SIZE];
+    loop {
+        let (len, source) = match shared.socket.recv_from(&mut datagram).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("WireGuard receive failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        // The peer at that endpoint first; a roaming peer is found by
+        // trying the others
+        let mut order: Vec<&Peer> = shared.peers.iter().collect();
+        order.sort_by_key(|peer| peer.endpoint() != Some(source));
+        for peer in order {
+            let Some(output) = receive(peer, source, &datagram[..len], &mut out) else {
+                continue;
+            };
+            *peer.endpoint.lock().unwrap_or_else(PoisonError::into_inner) = Some(source);
+            shared.send(peer, output).await;
+            break;
+        }
+    }
+}
+
+/// Decrypt one datagram for `peer`; `None` when it is not the sender
+fn receive(peer: &Peer, source: SocketAddr, datagram: &[u8], out: &mut [u8]) -> Option<Output> {
+    let mut output = Output::default();
+    let mut tunnel = peer.tunnel.lock().unwrap_or_else(PoisonError::into_inner);
+    match tunnel.decapsulate(Some(source.ip()), datagram, out) {
+        TunnResult::Done => {}
+        TunnResult::Err(e) => {
+            debug!(
+                "Datagram from {} not for {}: {:?}",
+                source, peer.public_key, e
+            );
+            return None;
+        }
+        TunnResult::WriteToNetwork(packet) => {
+            output.network.push(packet.to_vec());
+            // Packets queued while the handshake was pending
+            while let TunnResult::WriteToNetwork(packet) = tunnel.decapsulate(None, &[], out) {
+                output.network.push(packet.to_vec());
+            }
+        }
+        TunnResult::WriteToTunnelV4(packet, address) if peer.allows(address.into()) => {
+            output.tunnel.push(packet.to_vec())
+        }
+        TunnResult::WriteToTunnelV6(packet, address) if peer.allows(address.into()) => {
+            output.tunnel.push(packet.to_vec())
+        }
+        TunnResult::WriteToTunnelV4(..) | TunnResult::WriteToTunnelV6(..) => {
+            debug!("Dropped packet from {} outside its allowed IPs", source);
+        }
+    }
+    Some(output)
+}
+
+/// Handshakes, rekeying and keepalives
+async fn timers(shared: Arc<Shared>) {
+    let mut out = vec![0u8; BUFFER_SIZE];
+    for peer in shared.peers.iter().filter(|peer| peer.endpoint().is_some()) {
+        let mut output = Output::default();
+        if let TunnResult::WriteToNetwork(packet) = peer
+            .tunnel
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .format_handshake_initiation(&mut out, false)
+        {
+            output.network.push(packet.to_vec());
+        }
+        shared.send(peer, output).await;
+    }
+
+    loop {
+        tokio::time::sleep(TIMER_TICK).await;
+        for peer in &shared.peers {
+            let mut output = Output::default();
+            match peer
+                .tunnel
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .update_timers(&mut out)
+            {
+                TunnResult::WriteToNetwork(packet) => output.network.push(packet.to_vec()),
+                TunnResult::Err(WireGuardError::ConnectionExpired) => {
+                    debug!("WireGuard session with {} expired", peer.public_key)
+                }
+                TunnResult::Err(e) => debug!("WireGuard timer error: {:?}", e),
+                _ => {}
+            }
+            shared.send(peer, output).await;
+        }
+    }
+}
+
+async fn configure_interface(config: &WireguardConfig) -> Result<()> {
+    let interface = config.interface.as_str();
+    let mtu = config.mtu.to_string();
+    run_ip(&["addr", "replace", &config.address, "dev", interface]).await?;
+    run_ip(&["link", "set", "dev", interface, "mtu", &mtu, "up"]).await?;
+    for peer in &config.peers {
+        for network in &peer.allowed_ips {
+            run_ip(&["route", "replace", network, "dev", interface]).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn run_ip(args: &[&str]) -> Result<()> {
+    let output = Command::new("ip")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| tunnel_error("Failed to run ip", e))?;
+    if !output.status.success() {
+        return Err(NetworkError::TunnelFailed(format!(
+            "ip {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+async fn resolve_endpoint(endpoint: &str) -> Result<SocketAddr> {
+    tokio::net::lookup_host(endpoint)
+        .await
+        .map_err(|e| tunnel_error(&format!("Cannot resolve {}", endpoint), e))?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| {
+            NetworkError::TunnelFailed(format!("No IPv4 address for {}", endpoint)).into()
+        })
+}
+
+/// Base64 key as printed by `wg genkey` / `wg pubkey`
+fn decode_key(key: &str) -> Option<[u8; 32]> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut bytes = Vec::with_capacity(32);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in key.trim().trim_end_matches('=').bytes() {
+        let value = ALPHABET.iter().position(|a| *a == c)? as u32;
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    bytes.try_into().ok()
+}
+
+/// `10.100.0.0/24` or `fd00::/64`; a bare address is a host route
+fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+    let (address, len) = match network.split_once('/') {
+        Some((address, len)) => (address.parse::<IpAddr>().ok()?, len.parse::<u8>().ok()?),
+        None => {
+            let address = network.parse::<IpAddr>().ok()?;
+            (address, if address.is_ipv4() { 32 } else { 128 })
+        }
+    };
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    (len <= max).then_some((address, len))
+}
+
+/// Prefix length of `network` when it contains `address`
+fn prefix_len_matching(network: (IpAddr, u8), address: IpAddr) -> Option<u8> {
+    let (base, len) = network;
+    let matches = match (base, address) {
+        (IpAddr::V4(base), IpAddr::V4(address)) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            u32::from(base) & mask == u32::from(address) & mask
+        }
+        (IpAddr::V6(base), IpAddr::V6(address)) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            u128::from(base) & mask == u128::from(address) & mask
+        }
+        _ => false,
+    };
+    matches.then_some(len)
+}
+
+/// Peer whose allowed IPs hold `destination` most specifically
+fn select_peer(networks: &[&[(IpAddr, u8)]], destination: IpAddr) -> Option<usize> {
+    networks
+        .iter()
+        .enumerate()
+        .filter_map(|(index, networks)| {
+            networks
+                .iter()
+                .filter_map(|network| prefix_len_matching(*network, destination))
+                .max()
+                .map(|len| (len, index))
+        })
+        .max_by_key(|(len, index)| (*len, std::cmp::Reverse(*index)))
+        .map(|(_, index)| index)
+}
+
+fn tunnel_error(context: &str, e: io::Error) -> crate::error::Error {
+    NetworkError::TunnelFailed(format!("{}: {}", context, e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_key() {
+        assert_eq!(
+            decode_key("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="),
+            Some([0; 32])
+        );
+        let key = decode_key("yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=").unwrap();
+        assert_eq!(&key[..3], &[0xc8, 0x09, 0xf3]);
+        assert_eq!(key[31], 0x69);
+        // Wrong length or alphabet
+        assert!(decode_key("AAAA").is_none());
+        assert!(decode_key("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA*=").is_none());
+    }
+
+    #[test]
+    fn test_select_peer() {
+        let hub = [parse_network("10.100.0.0/16").unwrap()];
+        let site = [
+            parse_network("10.100.7.0/24").unwrap(),
+            parse_network("fd00::/64").unwrap(),
+        ];
+        let host = [parse_network("10.100.7.9").unwrap()];
+        let networks: Vec<&[(IpAddr, u8)]> = vec![&hub, &site, &host];
+
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(select_peer(&networks, ip("10.100.1.1")), Some(0));
+        assert_eq!(select_peer(&networks, ip("10.100.7.1")), Some(1));
+        assert_eq!(select_peer(&networks, ip("10.100.7.9")), Some(2));
+        assert_eq!(select_peer(&networks, ip("fd00::5")), Some(1));
+        assert_eq!(select_peer(&networks, ip("192.168.1.1")), None);
+
+        assert!(parse_network("10.0.0.0/33").is_none());
+        assert_eq!(parse_network("0.0.0.0/0"), Some((ip("0.0.0.0"), 0)));
+        assert_eq!(
+            select_peer(&[&[(ip("0.0.0.0"), 0)]], ip("8.8.8.8")),
+            Some(0)
+        );
+    }
+}
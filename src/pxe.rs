@@ -0,0 +1,394 @@
+pub mod dhcp;
+pub mod tftp;
+
+use crate::config::PxeConfig;
+use crate::error::{PxeError, Result};
+use crate::iso::mounter::MountGuard;
+use crate::iso::netboot::{NetbootImage, NetbootKind};
+use crate::iso::IsoManager;
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use std::fmt::Write;
+use std::future::Future;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+const DHCP_PORT: u16 = 67;
+/// PXE clients ask the boot server here after a ProxyDHCP offer
+const PXE_PORT: u16 = 4011;
+const TFTP_PORT: u16 = 69;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PxeServerState {
+    Stopped,
+    Running,
+    Error(String),
+}
+
+/// An image on the boot menu
+struct ServedImage {
+    image: NetbootImage,
+    title: String,
+    /// Keeps the image mounted for kinds that install from its contents
+    tree: Option<MountGuard>,
+}
+
+/// Handles shared with the HTTP handlers
+#[derive(Clone)]
+struct BootContext {
+    images: Arc<RwLock<Vec<ServedImage>>>,
+    base_url: String,
+    menu_timeout_secs: u64,
+}
+
+/// Network boot service: ProxyDHCP points PXE firmware at iPXE on TFTP,
+/// iPXE loads a menu of the network-bootable ISOs over HTTP
+pub struct PxeServer {
+    config: Arc<RwLock<PxeConfig>>,
+    iso_manager: Arc<IsoManager>,
+    state: Arc<RwLock<PxeServerState>>,
+    images: Arc<RwLock<Vec<ServedImage>>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl PxeServer {
+    pub fn new(config: Arc<RwLock<PxeConfig>>, iso_manager: Arc<IsoManager>) -> Self {
+        Self {
+            config,
+            iso_manager,
+            state: Arc::new(RwLock::new(PxeServerState::Stopped)),
+            images: Arc::new(RwLock::new(Vec::new())),
+            tasks: Vec::new(),
+        }
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        let config = self.config.read().await.clone();
+        if !config.enabled {
+            info!("Network boot server disabled");
+            return Ok(());
+        }
+
+        let base_url = format!("http://{}:{}", config.server_address, config.http_port);
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, config.http_port))
+            .await
+            .map_err(|e| PxeError::BindFailed(format!("HTTP port {}: {}", config.http_port, e)))?;
+        let tftp_socket = bind_udp(TFTP_PORT).await?;
+        let dhcp_sockets = if config.proxy_dhcp {
+            vec![bind_udp(DHCP_PORT).await?, bind_udp(PXE_PORT).await?]
+        } else {
+            Vec::new()
+        };
+
+        let served = self.refresh_menu().await;
+
+        let app = router(BootContext {
+            images: self.images.clone(),
+            base_url: base_url.clone(),
+            menu_timeout_secs: config.menu_timeout_secs,
+        });
+        self.spawn("HTTP", async move {
+            axum::serve(listener, app)
+                .await
+                .map_err(|e| PxeError::ServerFailed(format!("HTTP: {}", e)).into())
+        });
+        self.spawn("TFTP", tftp::serve(tftp_socket, config.tftp_root.clone()));
+        let menu_url = format!("{}/boot.ipxe", base_url);
+        for socket in dhcp_sockets {
+            self.spawn(
+                "ProxyDHCP",
+                dhcp::serve(socket, config.clone(), menu_url.clone()),
+            );
+        }
+
+        self.set_state(PxeServerState::Running).await;
+        info!("Network boot server on {} with {} images", base_url, served);
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        if !self.tasks.is_empty() {
+            info!("Stopping network boot server");
+        }
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        // Releases the image mounts
+        self.images.write().await.clear();
+        self.set_state(PxeServerState::Stopped).await;
+        Ok(())
+    }
+
+    /// Rebuild the boot menu from the ISOs available now; returns the
+    /// number of images on it
+    pub async fn refresh_menu(&self) -> usize {
+        let mut served = Vec::new();
+        for image in self.iso_manager.prepare_all_netboot().await {
+            let file_name = image
+                .iso
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let title = match self.iso_manager.get_catalog_entry(&file_name).await {
+                Ok(entry) => entry.display_name(),
+                Err(_) => image.name.clone(),
+            };
+            let tree = if serves_tree(image.kind) {
+                match self.iso_manager.hold_netboot_mount(&image.iso).await {
+                    Ok(guard) => Some(guard),
+                    Err(e) => {
+                        warn!("Not serving {} for network boot: {}", file_name, e);
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+            served.push(ServedImage { image, title, tree });
+        }
+        served.sort_by(|a, b| a.title.cmp(&b.title));
+
+        let count = served.len();
+        *self.images.write().await = served;
+        count
+    }
+
+    pub async fn get_state(&self) -> PxeServerState {
+        self.state.read().await.clone()
+    }
+
+    async fn set_state(&self, state: PxeServerState) {
+        *self.state.write().await = state;
+    }
+
+    pub async fn health_check(&self) -> Result<()> {
+        match self.get_state().await {
+            PxeServerState::Error(e) => Err(PxeError::ServerFailed(e).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run one of the boot services; its failure puts the server in error
+    fn spawn<F>(&mut self, service: &'static str, task: F)
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let state = self.state.clone();
+        self.tasks.push(tokio::spawn(async move {
+            if let Err(e) = task.await {
+                error!("Network boot {} service failed: {}", service, e);
+                *state.write().await = PxeServerState::Error(format!("{}: {}", service, e));
+            }
+        }));
+    }
+}
+
+async fn bind_udp(port: u16) -> Result<UdpSocket> {
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))
+        .await
+        .map_err(|e| PxeError::BindFailed(format!("UDP port {}: {}", port, e)).into())
+}
+
+/// Kinds whose installer reads the image contents rather than the whole ISO
+fn serves_tree(kind: NetbootKind) -> bool {
+    matches!(
+        kind,
+        NetbootKind::DebianLive | NetbootKind::Anaconda | NetbootKind::Archiso
+    )
+}
+
+fn router(context: BootContext) -> Router {
+    Router::new()
+        .route("/boot.ipxe", get(boot_menu))
+        .route("/images/:name/vmlinuz", get(kernel))
+        .route("/images/:name/initrd", get(initrd))
+        .route("/images/:name/image.iso", get(image_iso))
+        .route("/images/:name/tree/*path", get(tree_file))
+        .with_state(context)
+}
+
+async fn boot_menu(State(ctx): State<BootContext>) -> Response {
+    let images = ctx.images.read().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        menu(&images, &ctx.base_url, ctx.menu_timeout_secs),
+    )
+        .into_response()
+}
+
+/// iPXE script listing every served image; booting the local disk is
+/// the default so an unattended machine does not reinstall itself
+fn menu(images: &[ServedImage], base_url: &str, timeout_secs: u64) -> String {
+    let mut script = String::from("#!ipxe\n\n:start\nmenu USB Installer Node\n");
+    for (index, served) in images.iter().enumerate() {
+        let _ = writeln!(script, "item img{} {}", index, served.title);
+    }
+    let _ = writeln!(
+        script,
+        "item --gap\nitem shell iPXE shell\nitem exit Boot from local disk\n\
+         choose --timeout {} --default exit target && goto ${{target}} || goto exit",
+        timeout_secs * 1000
+    );
+
+    for (index, served) in images.iter().enumerate() {
+        let base = format!("{}/images/{}", base_url, url_segment(&served.image.name));
+        let _ = write!(
+            script,
+            "\n:img{}\nkernel {}/vmlinuz initrd=initrd {}\ninitrd --name initrd {}/initrd\nboot || goto start\n",
+            index,
+            base,
+            served.image.kernel_args(&base),
+            base
+        );
+    }
+    script.push_str("\n:shell\nshell\ngoto start\n\n:exit\nexit\n");
+    script
+}
+
+/// Percent-encode an image name for use as one URL path segment
+fn url_segment(name: &str) -> String {
+    let mut segment = String::new();
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            segment.push(byte as char);
+        } else {
+            let _ = write!(segment, "%{:02X}", byte);
+        }
+    }
+    segment
+}
+
+async fn kernel(State(ctx): State<BootContext>, UrlPath(name): UrlPath<String>) -> Response {
+    image_file(&ctx, &name, |served| Some(served.image.kernel.clone())).await
+}
+
+async fn initrd(State(ctx): State<BootContext>, UrlPath(name): UrlPath<String>) -> Response {
+    image_file(&ctx, &name, |served| Some(served.image.initrd.clone())).await
+}
+
+async fn image_iso(State(ctx): State<BootContext>, UrlPath(name): UrlPath<String>) -> Response {
+    image_file(&ctx, &name, |served| Some(served.image.iso.clone())).await
+}
+
+async fn tree_file(
+    State(ctx): State<BootContext>,
+    UrlPath((name, path)): UrlPath<(String, String)>,
+) -> Response {
+    image_file(&ctx, &name, |served| {
+        tftp::resolve(served.tree.as_ref()?.target(), &path).ok()
+    })
+    .await
+}
+
+async fn image_file(
+    ctx: &BootContext,
+    name: &str,
+    pick: impl FnOnce(&ServedImage) -> Option<PathBuf>,
+) -> Response {
+    let path = {
+        let images = ctx.images.read().await;
+        images
+            .iter()
+            .find(|served| served.image.name == name)
+            .and_then(pick)
+    };
+    match path {
+        Some(path) => send_file(&path).await,
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Stream a file; images are gigabytes, so never read them whole
+async fn send_file(path: &Path) -> Response {
+    let file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let chunks = futures_util::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(buf), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_LENGTH, len)
+        .body(Body::from_stream(chunks))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn served(name: &str, kind: NetbootKind, title: &str) -> ServedImage {
+        ServedImage {
+            image: NetbootImage {
+                name: name.to_string(),
+                iso: PathBuf::from(format!("/isos/{}.iso", name)),
+                kind,
+                kernel: PathBuf::from("vmlinuz"),
+                initrd: PathBuf::from("initrd"),
+            },
+            title: title.to_string(),
+            tree: None,
+        }
+    }
+
+    #[test]
+    fn test_menu() {
+        let images = [
+            served(
+                "Fedora Server 40",
+                NetbootKind::Anaconda,
+                "Fedora 40 server (x86_64)",
+            ),
+            served(
+                "ubuntu-24.04-live-server-amd64",
+                NetbootKind::Casper,
+                "Ubuntu 24.04 server (x86_64)",
+            ),
+        ];
+        let script = menu(&images, "http://10.42.0.1:8081", 10);
+
+        assert!(script.starts_with("#!ipxe\n"));
+        assert!(script.contains("item img0 Fedora 40 server (x86_64)\n"));
+        assert!(script.contains("choose --timeout 10000 --default exit target"));
+        assert!(script.contains(
+            "kernel http://10.42.0.1:8081/images/Fedora%20Server%2040/vmlinuz initrd=initrd \
+             ip=dhcp inst.repo=http://10.42.0.1:8081/images/Fedora%20Server%2040/tree\n"
+        ));
+        assert!(script.contains(
+            "initrd --name initrd http://10.42.0.1:8081/images/ubuntu-24.04-live-server-amd64/initrd\n"
+        ));
+        assert!(script.contains(
+            "url=http://10.42.0.1:8081/images/ubuntu-24.04-live-server-amd64/image.iso\n"
+        ));
+
+        let empty = menu(&[], "http://10.42.0.1:8081", 0);
+        assert!(!empty.contains(":img"));
+        assert!(empty.contains("item exit Boot from local disk\n"));
+    }
+}
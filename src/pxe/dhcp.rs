@@ -0,0 +1,268 @@
+use crate::config::PxeConfig;
+use crate::error::{PxeError, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+/// Fixed BOOTP part before the options
+const BOOTP_HEADER: usize = 236;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const CLIENT_PORT: u16 = 68;
+
+const OPT_PAD: u8 = 0;
+const OPT_VENDOR_OPTIONS: u8 = 43;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_VENDOR_CLASS: u8 = 60;
+const OPT_BOOTFILE: u8 = 67;
+const OPT_USER_CLASS: u8 = 77;
+const OPT_CLIENT_ARCH: u8 = 93;
+const OPT_CLIENT_UUID: u8 = 97;
+const OPT_END: u8 = 255;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+
+/// PXE_DISCOVERY_CONTROL: skip boot server discovery and use the boot
+/// file from this reply
+const DISCOVERY_CONTROL: [u8; 4] = [6, 1, 8, OPT_END];
+
+/// DHCP request from PXE firmware or iPXE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PxeRequest {
+    pub message_type: u8,
+    xid: [u8; 4],
+    flags: [u8; 2],
+    ciaddr: Ipv4Addr,
+    chaddr: [u8; 16],
+    /// Client system architecture (option 93); 0 is BIOS
+    pub arch: Option<u16>,
+    /// Sent by iPXE, which fetches the menu over HTTP
+    pub ipxe: bool,
+    uuid: Option<Vec<u8>>,
+}
+
+impl PxeRequest {
+    pub fn is_uefi(&self) -> bool {
+        self.arch.is_some_and(|arch| arch != 0)
+    }
+}
+
+/// Parse a DHCPDISCOVER or DHCPREQUEST from a PXE client; anything else,
+/// including requests from ordinary DHCP clients, gives `None`
+pub fn parse(packet: &[u8]) -> Option<PxeRequest> {
+    if packet.len() < BOOTP_HEADER + MAGIC_COOKIE.len()
+        || packet[0] != 1
+        || packet[BOOTP_HEADER..BOOTP_HEADER + 4] != MAGIC_COOKIE
+    {
+        return None;
+    }
+
+    let mut request = PxeRequest {
+        message_type: 0,
+        xid: packet[4..8].try_into().ok()?,
+        flags: packet[10..12].try_into().ok()?,
+        ciaddr: Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]),
+        chaddr: packet[28..44].try_into().ok()?,
+        arch: None,
+        ipxe: false,
+        uuid: None,
+    };
+    let mut pxe_client = false;
+
+    let mut options = &packet[BOOTP_HEADER + 4..];
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPT_PAD => {
+                options = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let value = rest.get(..len as usize)?;
+        match code {
+            OPT_MESSAGE_TYPE => request.message_type = *value.first()?,
+            OPT_VENDOR_CLASS => pxe_client = value.starts_with(b"PXEClient"),
+            OPT_USER_CLASS => request.ipxe = value.windows(4).any(|w| w == b"iPXE"),
+            OPT_CLIENT_ARCH if len >= 2 => {
+                request.arch = Some(u16::from_be_bytes([value[0], value[1]]))
+            }
+            OPT_CLIENT_UUID => request.uuid = Some(value.to_vec()),
+            _ => {}
+        }
+        options = &rest[len as usize..];
+    }
+
+    (pxe_client && matches!(request.message_type, DISCOVER | REQUEST)).then_some(request)
+}
+
+/// ProxyDHCP answer: no address, only where to boot from
+pub fn reply(request: &PxeRequest, server: Ipv4Addr, boot_file: &str) -> Vec<u8> {
+    let mut packet = vec![0u8; BOOTP_HEADER];
+    packet[0] = 2;
+    packet[1] = 1;
+    packet[2] = 6;
+    packet[4..8].copy_from_slice(&request.xid);
+    packet[10..12].copy_from_slice(&request.flags);
+    packet[12..16].copy_from_slice(&request.ciaddr.octets());
+    packet[20..24].copy_from_slice(&server.octets());
+    packet[28..44].copy_from_slice(&request.chaddr);
+    let sname = server.to_string();
+    packet[44..44 + sname.len()].copy_from_slice(sname.as_bytes());
+    // Both NUL-terminated fields; longer names go in option 67 only
+    let file = boot_file.as_bytes();
+    if file.len() < 128 {
+        packet[108..108 + file.len()].copy_from_slice(file);
+    }
+
+    packet.extend_from_slice(&MAGIC_COOKIE);
+    let message_type = if request.message_type == DISCOVER {
+        OFFER
+    } else {
+        ACK
+    };
+    push_option(&mut packet, OPT_MESSAGE_TYPE, &[message_type]);
+    push_option(&mut packet, OPT_SERVER_ID, &server.octets());
+    push_option(&mut packet, OPT_VENDOR_CLASS, b"PXEClient");
+    push_option(&mut packet, OPT_VENDOR_OPTIONS, &DISCOVERY_CONTROL);
+    if let Some(uuid) = &request.uuid {
+        push_option(&mut packet, OPT_CLIENT_UUID, uuid);
+    }
+    for chunk in file.chunks(255) {
+        push_option(&mut packet, OPT_BOOTFILE, chunk);
+    }
+    packet.push(OPT_END);
+    packet
+}
+
+fn push_option(packet: &mut Vec<u8>, code: u8, value: &[u8]) {
+    packet.push(code);
+    packet.push(value.len() as u8);
+    packet.extend_from_slice(value);
+}
+
+/// What the client should load next: PXE firmware gets iPXE over TFTP,
+/// iPXE gets the boot menu over HTTP
+pub fn boot_file(request: &PxeRequest, config: &PxeConfig, menu_url: &str) -> String {
+    if request.ipxe {
+        menu_url.to_string()
+    } else if request.is_uefi() {
+        config.uefi_boot_file.clone()
+    } else {
+        config.bios_boot_file.clone()
+    }
+}
+
+/// Answer PXE clients on `socket` (port 67 or 4011) until it fails.
+/// Discovers are answered by broadcast, requests to the sender.
+pub async fn serve(socket: UdpSocket, config: PxeConfig, menu_url: String) -> Result<()> {
+    socket
+        .set_broadcast(true)
+        .map_err(|e| PxeError::BindFailed(format!("ProxyDHCP broadcast: {}", e)))?;
+    let mut buf = [0u8; 1500];
+
+    loop {
+        let (len, peer) = socket
+            .recv_from(&mut buf)
+            .await
+            .map_err(|e| PxeError::ServerFailed(format!("ProxyDHCP receive: {}", e)))?;
+        let Some(request) = parse(&buf[..len]) else {
+            continue;
+        };
+
+        let boot_file = boot_file(&request, &config, &menu_url);
+        debug!(
+            "PXE {} from {} (arch {:?}), booting {}",
+            if request.message_type == DISCOVER {
+                "discover"
+            } else {
+                "request"
+            },
+            peer,
+            request.arch,
+            boot_file
+        );
+
+        let packet = reply(&request, config.server_address, &boot_file);
+        let destination = match (request.message_type, peer) {
+            (DISCOVER, _) if request.ciaddr.is_unspecified() => {
+                SocketAddr::from((Ipv4Addr::BROADCAST, CLIENT_PORT))
+            }
+            (DISCOVER, _) => SocketAddr::from((request.ciaddr, CLIENT_PORT)),
+            (_, peer) => peer,
+        };
+        if let Err(e) = socket.send_to(&packet, destination).await {
+            warn!("Failed to answer PXE client {}: {}", peer, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discover(options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut packet = vec![0u8; BOOTP_HEADER];
+        packet[0] = 1;
+        packet[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        packet[28..34].copy_from_slice(&[0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        packet.extend_from_slice(&MAGIC_COOKIE);
+        push_option(&mut packet, OPT_MESSAGE_TYPE, &[DISCOVER]);
+        for (code, value) in options {
+            push_option(&mut packet, *code, value);
+        }
+        packet.push(OPT_END);
+        packet
+    }
+
+    #[test]
+    fn test_parse() {
+        let packet = discover(&[
+            (OPT_VENDOR_CLASS, b"PXEClient:Arch:00007:UNDI:003016"),
+            (OPT_CLIENT_ARCH, &[0, 7]),
+        ]);
+        let request = parse(&packet).unwrap();
+        assert_eq!(request.message_type, DISCOVER);
+        assert_eq!(request.arch, Some(7));
+        assert!(request.is_uefi());
+        assert!(!request.ipxe);
+
+        let packet = discover(&[
+            (OPT_VENDOR_CLASS, b"PXEClient:Arch:00000:UNDI:002001"),
+            (OPT_USER_CLASS, b"\x04iPXE"),
+        ]);
+        assert!(parse(&packet).unwrap().ipxe);
+
+        // Ordinary DHCP clients are left to the LAN's DHCP server
+        assert!(parse(&discover(&[(OPT_VENDOR_CLASS, b"MSFT 5.0")])).is_none());
+        assert!(parse(&packet[..100]).is_none());
+    }
+
+    #[test]
+    fn test_reply() {
+        let config = PxeConfig::default();
+        let packet = discover(&[
+            (OPT_VENDOR_CLASS, b"PXEClient:Arch:00000:UNDI:002001"),
+            (OPT_CLIENT_UUID, &[0; 17]),
+        ]);
+        let request = parse(&packet).unwrap();
+        let file = boot_file(&request, &config, "http://10.42.0.1:8081/boot.ipxe");
+        assert_eq!(file, "undionly.kpxe");
+
+        let offer = reply(&request, config.server_address, &file);
+        assert_eq!(offer[0], 2);
+        assert_eq!(offer[4..8], [1, 2, 3, 4]);
+        assert_eq!(offer[20..24], [10, 42, 0, 1]);
+        assert_eq!(&offer[108..121], b"undionly.kpxe");
+        assert_eq!(
+            offer[BOOTP_HEADER + 4..BOOTP_HEADER + 7],
+            [OPT_MESSAGE_TYPE, 1, OFFER]
+        );
+        assert!(offer.windows(9).any(|w| w == b"PXEClient"));
+        assert_eq!(offer.last(), Some(&OPT_END));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::error::{PxeError, Result};
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+const OP_RRQ: u16 = 1;
+const OP_WRQ: u16 = 2;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+const ERR_UNDEFINED: u16 = 0;
+const ERR_NOT_FOUND: u16 = 1;
+const ERR_ACCESS: u16 = 2;
+const ERR_ILLEGAL: u16 = 4;
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+/// Largest block that fits an Ethernet frame without fragmenting
+const MAX_BLOCK_SIZE: usize = 1468;
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_RETRIES: u32 = 5;
+
+/// Read request with the options this server honours
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadRequest {
+    pub filename: String,
+    pub block_size: Option<usize>,
+    /// Client asked for the transfer size (`tsize`)
+    pub transfer_size: bool,
+}
+
+/// Parse a read request in octet mode. `Err` carries the TFTP error to
+/// send back.
+pub fn parse_request(packet: &[u8]) -> std::result::Result<ReadRequest, (u16, &'static str)> {
+    if packet.len() < 4 {
+        return Err((ERR_ILLEGAL, "Malformed request"));
+    }
+    match u16::from_be_bytes([packet[0], packet[1]]) {
+        OP_RRQ => {}
+        OP_WRQ => return Err((ERR_ACCESS, "Read-only server")),
+        _ => return Err((ERR_ILLEGAL, "Expected a read request")),
+    }
+
+    let mut fields = packet[2..]
+        .split(|&b| b == 0)
+        .map(|f| String::from_utf8_lossy(f).into_owned());
+    let filename = fields.next().filter(|f| !f.is_empty());
+    let mode = fields.next();
+    let (Some(filename), Some(mode)) = (filename, mode) else {
+        return Err((ERR_ILLEGAL, "Malformed request"));
+    };
+    if !mode.eq_ignore_ascii_case("octet") {
+        return Err((ERR_ILLEGAL, "Only octet mode is supported"));
+    }
+
+    let mut request = ReadRequest {
+        filename,
+        block_size: None,
+        transfer_size: false,
+    };
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        match name.to_ascii_lowercase().as_str() {
+            "blksize" => {
+                request.block_size = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&size| size >= 8)
+                    .map(|size| size.min(MAX_BLOCK_SIZE))
+            }
+            "tsize" => request.transfer_size = true,
+            _ => {}
+        }
+    }
+    Ok(request)
+}
+
+/// File under `root` for a requested name. Names may not leave it, also
+/// not through symlinks. `Err` carries the TFTP error to send back.
+pub fn resolve(root: &Path, filename: &str) -> std::result::Result<PathBuf, (u16, &'static str)> {
+    let denied = (ERR_ACCESS, "Access violation");
+    let not_found = (ERR_NOT_FOUND, "File not found");
+    let relative = Path::new(filename.trim_start_matches('/'));
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(denied);
+    }
+    let root = root.canonicalize().map_err(|_| not_found)?;
+    let file = root.join(relative).canonicalize().map_err(|_| not_found)?;
+    if !file.starts_with(&root) {
+        return Err(denied);
+    }
+    Ok(file)
+}
+
+/// Serve read requests for files under `root` until the socket fails
+pub async fn serve(socket: UdpSocket, root: PathBuf) -> Result<()> {
+    let mut buf = [0u8; 1500];
+    loop {
+        let (len, peer) = socket
+            .recv_from(&mut buf)
+            .await
+            .map_err(|e| PxeError::ServerFailed(format!("TFTP receive: {}", e)))?;
+        let packet = buf[..len].to_vec();
+        let root = root.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(&packet, peer, &root).await {
+                warn!("TFTP transfer to {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// One transfer, from its own port as the protocol requires
+async fn handle(packet: &[u8], peer: SocketAddr, root: &Path) -> Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))
+        .await
+        .map_err(|e| PxeError::BindFailed(format!("TFTP transfer: {}", e)))?;
+    socket.connect(peer).await.map_err(transfer_error)?;
+
+    let request = match parse_request(packet) {
+        Ok(request) => request,
+        Err((code, message)) => return send_error(&socket, code, message).await,
+    };
+    let path = match resolve(root, &request.filename) {
+        Ok(path) => path,
+        Err((code, message)) => return send_error(&socket, code, message).await,
+    };
+    // Boot loaders are a few hundred kilobytes; images go over HTTP
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(_) => return send_error(&socket, ERR_NOT_FOUND, "File not found").await,
+    };
+    debug!("TFTP {} to {}", request.filename, peer);
+
+    // A final block shorter than the block size ends the transfer, so an
+    // exact multiple needs a trailing empty one
+    let block_size = request.block_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+    let blocks = data.len() / block_size + 1;
+    // Block numbers are 16 bits; clients disagree on what follows 65535
+    if blocks > u16::MAX as usize {
+        return send_error(&socket, ERR_UNDEFINED, "File too large for this block size").await;
+    }
+    if request.block_size.is_some() || request.transfer_size {
+        let oack = oack(&request, data.len());
+        if !exchange(&socket, &oack, 0).await? {
+            // Client declined the options
+            return Ok(());
+        }
+    }
+
+    for index in 0..blocks {
+        let number = (index + 1) as u16;
+        let start = index * block_size;
+        let chunk = &data[start..(start + block_size).min(data.len())];
+        let mut packet = Vec::with_capacity(4 + chunk.len());
+        packet.extend_from_slice(&OP_DATA.to_be_bytes());
+        packet.extend_from_slice(&number.to_be_bytes());
+        packet.extend_from_slice(chunk);
+        if !exchange(&socket, &packet, number).await? {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Option acknowledgement for the options the client sent
+fn oack(request: &ReadRequest, size: usize) -> Vec<u8> {
+    let mut packet = OP_OACK.to_be_bytes().to_vec();
+    let mut option = |name: &str, value: String| {
+        packet.extend_from_slice(name.as_bytes());
+        packet.push(0);
+        packet.extend_from_slice(value.as_bytes());
+        packet.push(0);
+    };
+    if let Some(block_size) = request.block_size {
+        option("blksize", block_size.to_string());
+    }
+    if request.transfer_size {
+        option("tsize", size.to_string());
+    }
+    packet
+}
+
+/// Send `packet` until the client acknowledges `block`. `false` when the
+/// client aborted with an error packet.
+async fn exchange(socket: &UdpSocket, packet: &[u8], block: u16) -> Result<bool> {
+    let mut buf = [0u8; 516];
+    for _ in 0..MAX_RETRIES {
+        socket.send(packet).await.map_err(transfer_error)?;
+        loop {
+            let len = match timeout(RETRANSMIT_TIMEOUT, socket.recv(&mut buf)).await {
+                Ok(len) => len.map_err(transfer_error)?,
+                Err(_) => break,
+            };
+            if len < 4 {
+                continue;
+            }
+            match u16::from_be_bytes([buf[0], buf[1]]) {
+                OP_ACK if u16::from_be_bytes([buf[2], buf[3]]) == block => return Ok(true),
+                OP_ERROR => return Ok(false),
+                // Duplicate of an earlier acknowledgement
+                _ => {}
+            }
+        }
+    }
+    Err(PxeError::ServerFailed(format!("TFTP block {} was not acknowledged", block)).into())
+}
+
+async fn send_error(socket: &UdpSocket, code: u16, message: &str) -> Result<()> {
+    let mut packet = OP_ERROR.to_be_bytes().to_vec();
+    packet.extend_from_slice(&code.to_be_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    socket.send(&packet).await.map_err(transfer_error)?;
+    Ok(())
+}
+
+fn transfer_error(e: std::io::Error) -> PxeError {
+    PxeError::ServerFailed(format!("TFTP transfer: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn rrq(fields: &[&str]) -> Vec<u8> {
+        let mut packet = OP_RRQ.to_be_bytes().to_vec();
+        for field in fields {
+            packet.extend_from_slice(field.as_bytes());
+            packet.push(0);
+        }
+        packet
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(&rrq(&[
+            "ipxe.efi", "octet", "tsize", "0", "blksize", "9000",
+        ]))
+        .unwrap();
+        assert_eq!(request.filename, "ipxe.efi");
+        assert_eq!(request.block_size, Some(MAX_BLOCK_SIZE));
+        assert!(request.transfer_size);
+
+        let request = parse_request(&rrq(&["undionly.kpxe", "OCTET"])).unwrap();
+        assert_eq!(request.block_size, None);
+        assert!(!request.transfer_size);
+
+        assert!(parse_request(&rrq(&["undionly.kpxe", "netascii"])).is_err());
+        let mut wrq = rrq(&["x", "octet"]);
+        wrq[1] = OP_WRQ as u8;
+        assert_eq!(parse_request(&wrq).unwrap_err().0, ERR_ACCESS);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("tftp");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("ipxe.efi"), b"boot").unwrap();
+        std::fs::write(dir.path().join("shadow"), b"secret").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("shadow"), root.join("escape")).unwrap();
+
+        assert_eq!(
+            resolve(&root, "/ipxe.efi"),
+            Ok(root.canonicalize().unwrap().join("ipxe.efi"))
+        );
+        assert_eq!(resolve(&root, "../shadow").unwrap_err().0, ERR_ACCESS);
+        assert_eq!(
+            resolve(&root, "efi/../../shadow").unwrap_err().0,
+            ERR_ACCESS
+        );
+        assert_eq!(resolve(&root, "").unwrap_err().0, ERR_ACCESS);
+        assert_eq!(resolve(&root, "escape").unwrap_err().0, ERR_ACCESS);
+        assert_eq!(resolve(&root, "missing").unwrap_err().0, ERR_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_transfer() {
+        let root = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..1024u32).map(|i| i as u8).collect();
+        std::fs::write(root.path().join("undionly.kpxe"), &content).unwrap();
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(serve(server, root.path().to_path_buf()));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client
+            .send_to(&rrq(&["undionly.kpxe", "octet", "tsize", "0"]), address)
+            .await
+            .unwrap();
+        let mut buf = [0u8; 1500];
+
+        let (len, transfer) = client.recv_from(&mut buf).await.unwrap();
+        assert_ne!(transfer, address);
+        assert_eq!(&buf[..len], b"\x00\x06tsize\x001024\x00");
+        client.send_to(&[0, 4, 0, 0], transfer).await.unwrap();
+
+        // 1024 bytes in two full blocks, then the empty terminator
+        let mut received = Vec::new();
+        for block in 1..=3u16 {
+            let (len, _) = client.recv_from(&mut buf).await.unwrap();
+            assert_eq!(buf[..4], [0, 3, 0, block as u8]);
+            received.extend_from_slice(&buf[4..len]);
+            client
+                .send_to(&[0, 4, 0, block as u8], transfer)
+                .await
+                .unwrap();
+        }
+        assert_eq!(received, content);
+
+        client
+            .send_to(&rrq(&["missing", "octet"]), address)
+            .await
+            .unwrap();
+        let (len, _) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(buf[..4], [0, 5, 0, 1]);
+        assert_eq!(&buf[4..len], b"File not found\x00");
+
+        // 65535 blocks of 8 bytes, plus the empty terminator
+        std::fs::write(root.path().join("large.efi"), vec![0u8; 8 * 65535]).unwrap();
+        client
+            .send_to(&rrq(&["large.efi", "octet", "blksize", "8"]), address)
+            .await
+            .unwrap();
+        let (len, _) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(buf[..4], [0, 5, 0, 0]);
+        assert_eq!(&buf[4..len], b"File too large for this block size\x00");
+    }
+}
@@ -1,32 +1,78 @@
+pub mod display;
+pub mod keys;
+pub mod serial;
 pub mod ssh;
 pub mod vnc;
 pub mod web_vnc;
 
+use crate::audit::AuditLog;
+use crate::auth::bans::BanList;
 use crate::config::RemoteConfig;
 use crate::error::{RemoteError, Result};
-use ssh::{SshConfig, SshServer};
-use std::collections::HashMap;
+use crate::events::{AppEvent, EventBus};
+use display::{Backend, VirtualDisplay};
+use serde::Serialize;
+use serial::SerialConsoles;
+use ssh::{NodeCommands, SshConfig, SshServer};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 use vnc::{VncConfig, VncServer};
-use web_vnc::{WebVncConfig, WebVncServer};
+use web_vnc::{WebVncConfig, WebVncLogin, WebVncServer};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RemoteManagerState {
     Stopped,
     Starting,
     Running,
+    /// Running with some enabled services down
+    Degraded(String),
     Stopping,
     Error(String),
 }
 
+/// Services that can be started, stopped and restarted one at a time
+pub const SERVICES: [&str; 3] = ["vnc", "ssh", "web_vnc"];
+
+/// One of [`SERVICES`], as reported to the API and UI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RemoteServiceStatus {
+    pub name: String,
+    /// Started with the other remote services
+    pub enabled: bool,
+    pub running: bool,
+    /// Port it listens on, or will on its next start
+    pub port: u16,
+    /// What the running server reports
+    pub details: HashMap<String, String>,
+}
+
 pub struct RemoteManager {
     config: Arc<RwLock<RemoteConfig>>,
     state: Arc<RwLock<RemoteManagerState>>,
     vnc_server: Option<Arc<VncServer>>,
     ssh_server: Option<Arc<SshServer>>,
     web_vnc_server: Option<Arc<WebVncServer>>,
+    /// Node state behind the SSH server's commands
+    commands: Option<NodeCommands>,
+    /// Fallback SFTP root when `remote.ssh.sftp_root` is unset
+    iso_dir: Option<std::path::PathBuf>,
+    /// Sessions the API opens for WebVNC users
+    web_vnc_login: Option<WebVncLogin>,
+    /// Failed sign-ins shared with the API
+    bans: Option<Arc<BanList>>,
+    /// Serial ports, attached to through the API
+    serial: Option<Arc<SerialConsoles>>,
+    /// Read-only download shares offered over SFTP
+    file_shares: BTreeMap<String, std::path::PathBuf>,
+    /// What VNC shares, resolved once per start
+    display_backend: Option<Backend>,
+    /// Xvfb on headless nodes
+    virtual_display: Option<VirtualDisplay>,
+    events: Option<EventBus>,
+    audit: Option<AuditLog>,
 }
 
 impl RemoteManager {
@@ -37,9 +83,66 @@ impl RemoteManager {
             vnc_server: None,
             ssh_server: None,
             web_vnc_server: None,
+            commands: None,
+            iso_dir: None,
+            web_vnc_login: None,
+            bans: None,
+            serial: None,
+            file_shares: BTreeMap::new(),
+            display_backend: None,
+            virtual_display: None,
+            events: None,
+            audit: None,
         }
     }
 
+    /// Let SSH clients query the node and control install jobs, and copy
+    /// ISOs to and from `iso_dir` over SFTP
+    pub fn with_commands(
+        mut self,
+        commands: NodeCommands,
+        iso_dir: Option<std::path::PathBuf>,
+    ) -> Self {
+        self.commands = Some(commands);
+        self.iso_dir = iso_dir;
+        self
+    }
+
+    pub fn with_web_vnc_login(mut self, login: Option<WebVncLogin>) -> Self {
+        self.web_vnc_login = login;
+        self
+    }
+
+    /// Let SSH and WebVNC refuse banned addresses and report their failures
+    pub fn with_bans(mut self, bans: Arc<BanList>) -> Self {
+        self.bans = Some(bans);
+        self
+    }
+
+    /// Open `[remote.serial]` ports on these consoles when starting
+    pub fn with_serial(mut self, serial: Arc<SerialConsoles>) -> Self {
+        self.serial = Some(serial);
+        self
+    }
+
+    /// Offer these shares read-only under `/files` in SFTP
+    pub fn with_file_shares(mut self, shares: BTreeMap<String, std::path::PathBuf>) -> Self {
+        self.file_shares = shares;
+        self
+    }
+
+    /// Publish state changes, for the UI and event stream clients
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Record SSH logins, commands and SFTP sessions
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
     pub async fn start_all(&mut self) -> Result<()> {
         info!("Starting remote access services");
         self.set_state(RemoteManagerState::Starting).await;
@@ -47,10 +150,15 @@ impl RemoteManager {
         let config = self.config.read().await;
         let mut any_started = false;
         let mut errors = Vec::new();
+        // Without the VNC server WebVNC would proxy to nothing
+        let mut vnc_up = !config.vnc.enabled;
 
         if config.vnc.enabled {
             match self.start_vnc(&config.vnc).await {
-                Ok(_) => any_started = true,
+                Ok(_) => {
+                    any_started = true;
+                    vnc_up = true;
+                }
                 Err(e) => {
                     error!("Failed to start VNC: {}", e);
                     errors.push(format!("VNC: {}", e));
@@ -68,8 +176,11 @@ impl RemoteManager {
             }
         }
 
-        if config.web_vnc.enabled {
-            match self.start_web_vnc(&config.web_vnc).await {
+        if config.web_vnc.enabled && !vnc_up {
+            warn!("Not starting Web VNC: VNC is not running");
+            errors.push("Web VNC: VNC is not running".to_string());
+        } else if config.web_vnc.enabled {
+            match self.start_web_vnc(&config.web_vnc, config.vnc.port).await {
                 Ok(_) => any_started = true,
                 Err(e) => {
                     error!("Failed to start Web VNC: {}", e);
@@ -78,6 +189,16 @@ impl RemoteManager {
             }
         }
 
+        if let (true, Some(serial)) = (config.serial.enabled, &self.serial) {
+            match serial.start(&config.serial).await {
+                Ok(_) => any_started = true,
+                Err(e) => {
+                    error!("Failed to start serial consoles: {}", e);
+                    errors.push(format!("Serial: {}", e));
+                }
+            }
+        }
+
         if !any_started && !errors.is_empty() {
             self.set_state(RemoteManagerState::Error(errors.join(", ")))
                 .await;
@@ -86,8 +207,14 @@ impl RemoteManager {
             ));
         }
 
-        self.set_state(RemoteManagerState::Running).await;
-        info!("Remote access services started");
+        if errors.is_empty() {
+            self.set_state(RemoteManagerState::Running).await;
+            info!("Remote access services started");
+        } else {
+            warn!("Remote access started degraded: {}", errors.join(", "));
+            self.set_state(RemoteManagerState::Degraded(errors.join(", ")))
+                .await;
+        }
         Ok(())
     }
 
@@ -97,6 +224,14 @@ impl RemoteManager {
 
         let mut errors = Vec::new();
 
+        // WebVNC first, its clients are connected through it to VNC
+        if let Some(web_vnc) = &self.web_vnc_server {
+            if let Err(e) = web_vnc.stop().await {
+                error!("Failed to stop Web VNC: {}", e);
+                errors.push(format!("Web VNC: {}", e));
+            }
+        }
+
         if let Some(vnc) = &self.vnc_server {
             if let Err(e) = vnc.stop().await {
                 error!("Failed to stop VNC: {}", e);
@@ -111,16 +246,17 @@ impl RemoteManager {
             }
         }
 
-        if let Some(web_vnc) = &self.web_vnc_server {
-            if let Err(e) = web_vnc.stop().await {
-                error!("Failed to stop Web VNC: {}", e);
-                errors.push(format!("Web VNC: {}", e));
-            }
+        if let Some(serial) = &self.serial {
+            serial.stop().await;
         }
 
         self.vnc_server = None;
         self.ssh_server = None;
         self.web_vnc_server = None;
+        self.display_backend = None;
+        if let Some(mut virtual_display) = self.virtual_display.take() {
+            virtual_display.stop().await;
+        }
 
         if !errors.is_empty() {
             self.set_state(RemoteManagerState::Error(errors.join(", ")))
@@ -135,9 +271,42 @@ impl RemoteManager {
         Ok(())
     }
 
+    /// Pick what VNC shares and, on a headless node, start the virtual
+    /// display. Runs before the UI so the GUI can draw on it.
+    pub async fn start_display(&mut self) -> Result<()> {
+        let config = self.config.read().await.vnc.clone();
+        if config.enabled {
+            self.resolve_display(&config).await?;
+        }
+        Ok(())
+    }
+
+    async fn resolve_display(&mut self, config: &crate::config::VncConfig) -> Result<Backend> {
+        if let Some(backend) = &self.display_backend {
+            return Ok(backend.clone());
+        }
+        let backend = display::select(
+            config.backend,
+            &config.display,
+            crate::ui::interface::detect_display(),
+        )?;
+        if let Backend::Headless(display) = &backend {
+            self.virtual_display =
+                Some(VirtualDisplay::start(display, &config.headless_screen).await?);
+        }
+        info!("VNC shares the {} display", backend.name());
+        self.display_backend = Some(backend.clone());
+        Ok(backend)
+    }
+
     async fn start_vnc(&mut self, config: &crate::config::VncConfig) -> Result<()> {
+        let (display, wayland_socket) = match self.resolve_display(config).await? {
+            Backend::X11(display) | Backend::Headless(display) => (display, None),
+            Backend::Wayland(socket) => (config.display.clone(), Some(socket)),
+        };
         let vnc_config = VncConfig {
-            display: config.display.clone(),
+            display,
+            wayland_socket,
             port: config.port,
             password: config.password.clone(),
             auth_file: config.auth_file.clone(),
@@ -154,37 +323,62 @@ impl RemoteManager {
     }
 
     async fn start_ssh(&mut self, config: &crate::config::SshConfig) -> Result<()> {
+        let sftp_root = config
+            .sftp_root
+            .clone()
+            .or_else(|| self.iso_dir.clone())
+            .ok_or_else(|| RemoteError::SshError("No SFTP root configured".to_string()))?;
         let ssh_config = SshConfig {
             port: config.port,
-            bind_address: config.bind_address.clone(),
-            host_key_path: config.host_key_path.clone(),
+            host_key_path: config.key_path.clone(),
             authorized_keys_path: config.authorized_keys_path.clone(),
-            allow_password_auth: config.allow_password_auth,
-            allow_root_login: config.allow_root_login,
-            max_sessions: config.max_sessions,
-            permit_empty_passwords: config.permit_empty_passwords,
+            sftp_root,
+            shares: self.file_shares.clone(),
         };
 
-        let server = Arc::new(SshServer::new(ssh_config));
+        let mut server = SshServer::new(ssh_config);
+        if let Some(commands) = &self.commands {
+            server = server.with_commands(commands.clone());
+        }
+        if let Some(bans) = &self.bans {
+            server = server.with_bans(bans.clone());
+        }
+        if let Some(audit) = &self.audit {
+            server = server.with_audit(audit.clone());
+        }
+        let server = Arc::new(server);
         server.start().await?;
         self.ssh_server = Some(server);
         Ok(())
     }
 
-    async fn start_web_vnc(&mut self, config: &crate::config::WebVncConfig) -> Result<()> {
+    async fn start_web_vnc(
+        &mut self,
+        config: &crate::config::WebVncConfig,
+        vnc_port: u16,
+    ) -> Result<()> {
         let web_vnc_config = WebVncConfig {
-            listen_port: config.listen_port,
-            vnc_host: config.vnc_host.clone(),
-            vnc_port: config.vnc_port,
+            listen_port: config.port,
+            vnc_host: "localhost".to_string(),
+            vnc_port,
+            https: config.https,
             cert_path: config.cert_path.clone(),
             key_path: config.key_path.clone(),
-            enable_auth: config.enable_auth,
+            assets_dir: config.assets_dir.clone(),
+            enable_auth: config.auth_required,
             username: config.username.clone(),
             password: config.password.clone(),
             session_timeout: config.session_timeout,
         };
 
-        let server = Arc::new(WebVncServer::new(web_vnc_config));
+        let mut server = WebVncServer::new(web_vnc_config);
+        if let Some(login) = &self.web_vnc_login {
+            server = server.with_login(login.clone());
+        }
+        if let Some(bans) = &self.bans {
+            server = server.with_bans(bans.clone());
+        }
+        let server = Arc::new(server);
         server.start().await?;
         self.web_vnc_server = Some(server);
         Ok(())
@@ -194,7 +388,11 @@ impl RemoteManager {
         let mut status = HashMap::new();
 
         if let Some(vnc) = &self.vnc_server {
-            status.insert("vnc".to_string(), vnc.get_status().await);
+            let mut vnc_status = vnc.get_status().await;
+            if let Some(backend) = &self.display_backend {
+                vnc_status.insert("display_mode".to_string(), backend.name().to_string());
+            }
+            status.insert("vnc".to_string(), vnc_status);
         }
 
         if let Some(ssh) = &self.ssh_server {
@@ -205,6 +403,23 @@ impl RemoteManager {
             status.insert("web_vnc".to_string(), web_vnc.get_status().await);
         }
 
+        if let Some(serial) = &self.serial {
+            let ports = serial.ports().await;
+            if !ports.is_empty() {
+                let open = ports.iter().filter(|port| port.open).count();
+                let terminals: usize = ports.iter().map(|port| port.terminals).sum();
+                status.insert(
+                    "serial".to_string(),
+                    [
+                        ("ports".to_string(), ports.len().to_string()),
+                        ("open".to_string(), open.to_string()),
+                        ("terminals".to_string(), terminals.to_string()),
+                    ]
+                    .into(),
+                );
+            }
+        }
+
         status
     }
 
@@ -213,13 +428,25 @@ impl RemoteManager {
     }
 
     async fn set_state(&self, state: RemoteManagerState) {
-        *self.state.write().await = state;
+        let mut current = self.state.write().await;
+        if *current == state {
+            return;
+        }
+        *current = state.clone();
+        drop(current);
+
+        if let Some(events) = &self.events {
+            events.publish(AppEvent::Remote { state });
+        }
     }
 
     pub async fn reload_config(&mut self, config: Arc<RwLock<RemoteConfig>>) -> Result<()> {
         info!("Reloading remote configuration");
 
-        let was_running = self.get_state().await == RemoteManagerState::Running;
+        let was_running = matches!(
+            self.get_state().await,
+            RemoteManagerState::Running | RemoteManagerState::Degraded(_)
+        );
 
         if was_running {
             self.stop_all().await?;
@@ -238,7 +465,8 @@ impl RemoteManager {
         let state = self.get_state().await;
         match state {
             RemoteManagerState::Error(e) => Err(RemoteError::HealthCheckFailed(e)),
-            RemoteManagerState::Running => {
+            // Only services that started are checked while degraded
+            RemoteManagerState::Running | RemoteManagerState::Degraded(_) => {
                 let mut unhealthy = Vec::new();
 
                 if let Some(vnc) = &self.vnc_server {
@@ -272,29 +500,181 @@ impl RemoteManager {
         }
     }
 
-    pub async fn restart_service(&mut self, service: &str) -> Result<()> {
+    /// VNC, SSH and WebVNC, running or not
+    pub async fn services(&self) -> Vec<RemoteServiceStatus> {
+        let mut services = Vec::new();
+        for name in SERVICES {
+            services.push(self.service_status(name).await);
+        }
+        services
+    }
+
+    pub async fn service(&self, service: &str) -> Result<RemoteServiceStatus> {
+        check_service(service)?;
+        Ok(self.service_status(service).await)
+    }
+
+    /// Start one service, moving it to `port` first if given. WebVNC
+    /// follows VNC to its new port.
+    pub async fn start_service(&mut self, service: &str, port: Option<u16>) -> Result<()> {
+        check_service(service)?;
+        if self.is_running(service) {
+            return Err(RemoteError::ServiceRunning(service.to_string()).into());
+        }
+        if let Some(port) = port {
+            self.set_port(service, port).await?;
+        }
+
+        info!("Starting remote service {}", service);
+        let config = self.config.read().await.clone();
+        match service {
+            "vnc" => self.start_vnc(&config.vnc).await?,
+            "ssh" => self.start_ssh(&config.ssh).await?,
+            _ => self.start_web_vnc(&config.web_vnc, config.vnc.port).await?,
+        }
+
+        if service == "vnc" && port.is_some() && self.web_vnc_server.is_some() {
+            self.stop_server("web_vnc").await?;
+            self.start_web_vnc(&config.web_vnc, config.vnc.port).await?;
+            self.service_changed("web_vnc").await;
+        }
+        self.service_changed(service).await;
+        Ok(())
+    }
+
+    /// Stop one service and leave the others running
+    pub async fn stop_service(&mut self, service: &str) -> Result<()> {
+        check_service(service)?;
+        info!("Stopping remote service {}", service);
+        self.stop_server(service).await?;
+        self.service_changed(service).await;
+        Ok(())
+    }
+
+    /// Stop and start one service, on `port` if given
+    pub async fn restart_service(&mut self, service: &str, port: Option<u16>) -> Result<()> {
+        check_service(service)?;
+        self.stop_server(service).await?;
+        let result = self.start_service(service, port).await;
+        if result.is_err() {
+            self.service_changed(service).await;
+        }
+        result
+    }
+
+    async fn stop_server(&mut self, service: &str) -> Result<()> {
         match service {
             "vnc" => {
-                if let Some(vnc) = &self.vnc_server {
-                    vnc.restart().await?;
+                if let Some(vnc) = self.vnc_server.take() {
+                    vnc.stop().await?;
                 }
             }
             "ssh" => {
-                if let Some(ssh) = &self.ssh_server {
+                if let Some(ssh) = self.ssh_server.take() {
                     ssh.stop().await?;
-                    ssh.start().await?;
                 }
             }
-            "web_vnc" => {
-                if let Some(web_vnc) = &self.web_vnc_server {
+            _ => {
+                if let Some(web_vnc) = self.web_vnc_server.take() {
                     web_vnc.stop().await?;
-                    web_vnc.start().await?;
                 }
             }
-            _ => return Err(RemoteError::InvalidService(service.to_string())),
         }
         Ok(())
     }
+
+    /// Change the port a service starts on until the next config reload.
+    /// Ports of the other remote services are refused.
+    async fn set_port(&self, service: &str, port: u16) -> Result<()> {
+        let mut config = self.config.write().await;
+        let ports = [
+            ("vnc", config.vnc.port),
+            ("ssh", config.ssh.port),
+            ("web_vnc", config.web_vnc.port),
+        ];
+        let taken = ports
+            .iter()
+            .any(|(name, used)| *name != service && *used == port);
+        if port == 0 || taken {
+            return Err(RemoteError::InvalidPort(port).into());
+        }
+        match service {
+            "vnc" => config.vnc.port = port,
+            "ssh" => config.ssh.port = port,
+            _ => config.web_vnc.port = port,
+        }
+        Ok(())
+    }
+
+    fn is_running(&self, service: &str) -> bool {
+        match service {
+            "vnc" => self.vnc_server.is_some(),
+            "ssh" => self.ssh_server.is_some(),
+            _ => self.web_vnc_server.is_some(),
+        }
+    }
+
+    async fn service_status(&self, service: &str) -> RemoteServiceStatus {
+        let config = self.config.read().await;
+        let (enabled, port, details) = match service {
+            "vnc" => (
+                config.vnc.enabled,
+                config.vnc.port,
+                match &self.vnc_server {
+                    Some(vnc) => vnc.get_status().await,
+                    None => HashMap::new(),
+                },
+            ),
+            "ssh" => (
+                config.ssh.enabled,
+                config.ssh.port,
+                match &self.ssh_server {
+                    Some(ssh) => ssh.get_status().await,
+                    None => HashMap::new(),
+                },
+            ),
+            _ => (
+                config.web_vnc.enabled,
+                config.web_vnc.port,
+                match &self.web_vnc_server {
+                    Some(web_vnc) => web_vnc.get_status().await,
+                    None => HashMap::new(),
+                },
+            ),
+        };
+        RemoteServiceStatus {
+            name: service.to_string(),
+            enabled,
+            running: self.is_running(service),
+            port,
+            details,
+        }
+    }
+
+    /// Publish the service and settle the manager's state: running while
+    /// any service is, stopped once none are
+    async fn service_changed(&self, service: &str) {
+        if let Some(events) = &self.events {
+            events.publish(AppEvent::RemoteService(self.service_status(service).await));
+        }
+        let serial_open = match &self.serial {
+            Some(serial) => serial.ports().await.iter().any(|port| port.open),
+            None => false,
+        };
+        if SERVICES.iter().any(|name| self.is_running(name)) || serial_open {
+            self.set_state(RemoteManagerState::Running).await;
+        } else {
+            self.set_state(RemoteManagerState::Stopped).await;
+        }
+    }
+}
+
+fn check_service(service: &str) -> Result<()> {
+    if SERVICES.contains(&service) {
+        Ok(())
+    } else {
+        Err(RemoteError::InvalidService(service.to_string()).into())
+    }
 }
 
 #[cfg(test)]
@@ -330,4 +710,40 @@ mod tests {
         let status = manager.get_status().await;
         assert!(status.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_stopped_services() {
+        let config = Arc::new(RwLock::new(RemoteConfig::default()));
+        let mut manager = RemoteManager::new(config.clone());
+
+        let services = manager.services().await;
+        assert_eq!(
+            services.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            SERVICES
+        );
+        assert!(services.iter().all(|s| !s.running));
+        assert!(matches!(
+            manager.service("telnet").await,
+            Err(crate::error::Error::Remote(RemoteError::InvalidService(_)))
+        ));
+
+        // Stopping a stopped service is a no-op
+        manager.stop_service("ssh").await.unwrap();
+        assert_eq!(manager.get_state().await, RemoteManagerState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_port_change() {
+        let config = Arc::new(RwLock::new(RemoteConfig::default()));
+        let manager = RemoteManager::new(config.clone());
+        let vnc_port = config.read().await.vnc.port;
+
+        manager.set_port("ssh", 2222).await.unwrap();
+        assert_eq!(manager.service("ssh").await.unwrap().port, 2222);
+        assert!(matches!(
+            manager.set_port("ssh", vnc_port).await,
+            Err(crate::error::Error::Remote(RemoteError::InvalidPort(_)))
+        ));
+        assert!(manager.set_port("ssh", 0).await.is_err());
+    }
 }
@@ -0,0 +1,177 @@
+use crate::config::DisplayBackend;
+use crate::error::{RemoteError, Result};
+use crate::service::supervisor::{ProcessSupervisor, SupervisedProcess, STOP_TIMEOUT};
+use crate::ui::interface::DisplayServer;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Where X servers put their sockets: `X0` serves display `:0`
+const X11_SOCKET_DIR: &str = "/tmp/.X11-unix";
+/// How long Xvfb gets to create its socket
+const XVFB_START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Picture the VNC server shares, resolved from `remote.vnc.backend`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// x11vnc on a running X server
+    X11(String),
+    /// wayvnc on the compositor listening on this socket
+    Wayland(PathBuf),
+    /// Xvfb started by the node on this display, shared with x11vnc
+    Headless(String),
+}
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::X11(_) => "x11",
+            Backend::Wayland(_) => "wayland",
+            Backend::Headless(_) => "headless",
+        }
+    }
+}
+
+/// Backend for the requested mode given the display found at startup.
+/// `Auto` prefers the session that is running and only starts a virtual
+/// display when there is none.
+pub fn select(
+    requested: DisplayBackend,
+    display: &str,
+    detected: Option<DisplayServer>,
+) -> Result<Backend> {
+    match (requested, detected) {
+        (DisplayBackend::Auto, Some(DisplayServer::Wayland(socket))) => {
+            Ok(Backend::Wayland(socket))
+        }
+        (DisplayBackend::Auto, Some(DisplayServer::X11(found))) => Ok(Backend::X11(found)),
+        (DisplayBackend::Auto, None) => Ok(Backend::Headless(display.to_string())),
+        (DisplayBackend::X11, _) => Ok(Backend::X11(display.to_string())),
+        (DisplayBackend::Wayland, Some(DisplayServer::Wayland(socket))) => {
+            Ok(Backend::Wayland(socket))
+        }
+        (DisplayBackend::Wayland, _) => Err(RemoteError::VncError(
+            "Wayland backend requested but no compositor socket found".to_string(),
+        )
+        .into()),
+        (DisplayBackend::Headless, _) => Ok(Backend::Headless(display.to_string())),
+    }
+}
+
+/// Xvfb for nodes without a display, so the installer GUI has somewhere
+/// to draw and x11vnc something to share
+pub struct VirtualDisplay {
+    display: String,
+    process: SupervisedProcess,
+}
+
+impl VirtualDisplay {
+    /// Start Xvfb on `display_name` and wait for its socket. `screen` is
+    /// `WIDTHxHEIGHTxDEPTH`.
+    pub async fn start(display_name: &str, screen: &str) -> Result<Self> {
+        let socket = x11_socket(Path::new(X11_SOCKET_DIR), display_name).ok_or_else(|| {
+            RemoteError::VncError(format!("Not a local X display: {}", display_name))
+        })?;
+        if socket.exists() {
+            return Err(RemoteError::VncError(format!(
+                "Display {} is already in use",
+                display_name
+            ))
+            .into());
+        }
+
+        info!("Starting virtual display {} ({})", display_name, screen);
+        let mut cmd = Command::new("Xvfb");
+        cmd.arg(display_name)
+            .args(["-screen", "0", screen])
+            .args(["-nolisten", "tcp"]);
+        debug!("Executing Xvfb command: {:?}", cmd);
+        let process = ProcessSupervisor::global()
+            .spawn("Xvfb", cmd)
+            .map_err(|e| RemoteError::VncError(format!("Failed to start Xvfb: {}", e)))?;
+        let mut virtual_display = Self {
+            display: display_name.to_string(),
+            process,
+        };
+
+        let deadline = Instant::now() + XVFB_START_TIMEOUT;
+        while !socket.exists() {
+            if !virtual_display.is_running() || Instant::now() >= deadline {
+                virtual_display.stop().await;
+                return Err(RemoteError::VncError(format!(
+                    "Xvfb did not come up on {}",
+                    display_name
+                ))
+                .into());
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        Ok(virtual_display)
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        self.process.is_running()
+    }
+
+    /// Stop Xvfb. Dropping the display without stopping it kills Xvfb.
+    pub async fn stop(&mut self) {
+        if self.is_running() {
+            info!("Stopping virtual display {}", self.display);
+        }
+        if let Err(e) = self.process.stop(STOP_TIMEOUT).await {
+            warn!("Failed to stop Xvfb: {}", e);
+        }
+    }
+}
+
+/// Socket of a local display such as `:1` or `:1.0`
+fn x11_socket(dir: &Path, display: &str) -> Option<PathBuf> {
+    let number = display.strip_prefix(':')?.split('.').next()?;
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(dir.join(format!("X{}", number)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_backend() {
+        let wayland = DisplayServer::Wayland(PathBuf::from("/run/user/0/wayland-1"));
+        let x11 = DisplayServer::X11(":1".to_string());
+
+        assert_eq!(
+            select(DisplayBackend::Auto, ":0", Some(wayland.clone())).unwrap(),
+            Backend::Wayland(PathBuf::from("/run/user/0/wayland-1"))
+        );
+        assert_eq!(
+            select(DisplayBackend::Auto, ":0", Some(x11.clone())).unwrap(),
+            Backend::X11(":1".to_string())
+        );
+        assert_eq!(
+            select(DisplayBackend::Auto, ":0", None).unwrap(),
+            Backend::Headless(":0".to_string())
+        );
+        assert_eq!(
+            select(DisplayBackend::X11, ":0", Some(wayland)).unwrap(),
+            Backend::X11(":0".to_string())
+        );
+        assert!(select(DisplayBackend::Wayland, ":0", Some(x11)).is_err());
+        assert_eq!(
+            select(DisplayBackend::Headless, ":2", None).unwrap().name(),
+            "headless"
+        );
+    }
+
+    #[test]
+    fn test_x11_socket() {
+        let dir = Path::new("/tmp/.X11-unix");
+        assert_eq!(x11_socket(dir, ":0"), Some(dir.join("X0")));
+        assert_eq!(x11_socket(dir, ":12.0"), Some(dir.join("X12")));
+        assert_eq!(x11_socket(dir, "host:0"), None);
+        assert_eq!(x11_socket(dir, ":"), None);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::config::SshKeysConfig;
+use crate::error::{RemoteError, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// Seconds a key URL may take to answer
+const FETCH_TIMEOUT_SECS: u64 = 30;
+const HEADER: &str = "# Managed by usb-installer-node; edits are overwritten\n";
+
+/// One entry of the managed `authorized_keys` file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorizedKey {
+    /// Line as written to `authorized_keys`
+    pub line: String,
+    /// `SHA256:...`, as printed by `ssh-keygen -l`
+    pub fingerprint: String,
+    /// `config`, `url:<url>`, `github:<user>`, `api`, or `existing` for
+    /// keys that were in the file before it was managed
+    pub source: String,
+}
+
+/// Changes made through the API, kept across restarts
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KeyState {
+    added: Vec<AuthorizedKey>,
+    /// Fingerprints removed through the API; provisioned keys with one of
+    /// these stay out until added again
+    revoked: Vec<String>,
+}
+
+/// `authorized_keys` built from `[remote.ssh.keys]` and the API. The SSH
+/// server reads the file on every login, so changes apply at once.
+pub struct AuthorizedKeys {
+    config: SshKeysConfig,
+    path: PathBuf,
+    offline: bool,
+    /// Keys from the config, URLs and GitHub as last fetched; held while
+    /// the file is rewritten
+    provisioned: Mutex<Vec<AuthorizedKey>>,
+}
+
+impl AuthorizedKeys {
+    pub fn new(config: SshKeysConfig, path: PathBuf) -> Self {
+        Self {
+            config,
+            path,
+            offline: false,
+            provisioned: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Air-gapped nodes use the configured and API keys only
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Whether `provision` fetches anything, so is worth repeating
+    pub fn has_remote_sources(&self) -> bool {
+        !self.offline && (!self.config.urls.is_empty() || !self.config.github_users.is_empty())
+    }
+
+    /// Fetch keys from every source and rewrite the file. A source that
+    /// cannot be reached keeps the keys it gave last time, so an outage
+    /// does not lock technicians out.
+    pub async fn provision(&self) -> Result<Vec<AuthorizedKey>> {
+        let mut provisioned = self.provisioned.lock().await;
+        let mut keys = parse_keys(&self.config.keys.join("\n"), "config");
+
+        let mut sources: Vec<(String, String)> = self
+            .config
+            .urls
+            .iter()
+            .map(|url| (format!("url:{}", url), url.clone()))
+            .collect();
+        sources.extend(self.config.github_users.iter().map(|user| {
+            (
+                format!("github:{}", user),
+                format!("https://github.com/{}.keys", user),
+            )
+        }));
+        for (source, url) in sources {
+            if self.offline {
+                debug!("Offline: not fetching SSH keys from {}", url);
+                continue;
+            }
+            match fetch(&url).await {
+                Ok(text) => keys.extend(parse_keys(&text, &source)),
+                Err(e) => {
+                    warn!("Keeping previous SSH keys of {}: {}", source, e);
+                    keys.extend(provisioned.iter().filter(|k| k.source == source).cloned());
+                }
+            }
+        }
+        *provisioned = keys;
+
+        if !tokio::fs::try_exists(&self.config.state_path)
+            .await
+            .unwrap_or(false)
+        {
+            self.adopt_existing().await?;
+        }
+        let effective = self.write(&provisioned).await?;
+        info!(
+            "{} SSH keys authorized in {}",
+            effective.len(),
+            self.path.display()
+        );
+        Ok(effective)
+    }
+
+    pub async fn list(&self) -> Result<Vec<AuthorizedKey>> {
+        let provisioned = self.provisioned.lock().await;
+        Ok(effective(&provisioned, &self.load_state().await?))
+    }
+
+    /// Authorize an `authorized_keys` line; adding a key again is a no-op
+    pub async fn add(&self, line: &str) -> Result<AuthorizedKey> {
+        let key = parse_key(line, "api")?;
+        let provisioned = self.provisioned.lock().await;
+        let mut state = self.load_state().await?;
+        if let Some(existing) = effective(&provisioned, &state)
+            .into_iter()
+            .find(|k| k.fingerprint == key.fingerprint)
+        {
+            return Ok(existing);
+        }
+
+        state.revoked.retain(|f| *f != key.fingerprint);
+        if !provisioned.iter().any(|k| k.fingerprint == key.fingerprint) {
+            state.added.push(key.clone());
+        }
+        self.save_state(&state).await?;
+        let keys = self.write(&provisioned).await?;
+        info!("Authorized SSH key {}", key.fingerprint);
+        Ok(keys
+            .into_iter()
+            .find(|k| k.fingerprint == key.fingerprint)
+            .unwrap_or(key))
+    }
+
+    /// Revoke a key by fingerprint, whichever source it came from
+    pub async fn remove(&self, fingerprint: &str) -> Result<()> {
+        let provisioned = self.provisioned.lock().await;
+        let mut state = self.load_state().await?;
+        if !effective(&provisioned, &state)
+            .iter()
+            .any(|k| k.fingerprint == fingerprint)
+        {
+            return Err(RemoteError::KeyNotFound(fingerprint.to_string()).into());
+        }
+
+        state.added.retain(|k| k.fingerprint != fingerprint);
+        if provisioned.iter().any(|k| k.fingerprint == fingerprint) {
+            state.revoked.push(fingerprint.to_string());
+        }
+        self.save_state(&state).await?;
+        self.write(&provisioned).await?;
+        info!("Revoked SSH key {}", fingerprint);
+        Ok(())
+    }
+
+    /// Keep the keys already in the file the first time it is managed
+    async fn adopt_existing(&self) -> Result<()> {
+        let text = tokio::fs::read_to_string(&self.path)
+            .await
+            .unwrap_or_default();
+        let state = KeyState {
+            added: parse_keys(&text, "existing"),
+            revoked: Vec::new(),
+        };
+        if !state.added.is_empty() {
+            info!(
+                "Keeping {} SSH keys already in {}",
+                state.added.len(),
+                self.path.display()
+            );
+        }
+        self.save_state(&state).await
+    }
+
+    async fn load_state(&self) -> Result<KeyState> {
+        match tokio::fs::read(&self.config.state_path).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| {
+                RemoteError::SshError(format!("{}: {}", self.config.state_path.display(), e)).into()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(KeyState::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_state(&self, state: &KeyState) -> Result<()> {
+        let data = serde_json::to_vec_pretty(state)
+            .map_err(|e| RemoteError::SshError(format!("Failed to encode key state: {}", e)))?;
+        write_private(&self.config.state_path, &data)
+    }
+
+    /// Replace the file with the keys in effect and return them
+    async fn write(&self, provisioned: &[AuthorizedKey]) -> Result<Vec<AuthorizedKey>> {
+        let keys = effective(provisioned, &self.load_state().await?);
+        let mut contents = HEADER.to_string();
+        for key in &keys {
+            contents.push_str(&key.line);
+            contents.push('\n');
+        }
+        write_private(&self.path, contents.as_bytes())?;
+        Ok(keys)
+    }
+}
+
+/// Provisioned keys that were not revoked, then the added ones, each
+/// fingerprint once
+fn effective(provisioned: &[AuthorizedKey], state: &KeyState) -> Vec<AuthorizedKey> {
+    let mut keys: Vec<AuthorizedKey> = Vec::new();
+    for key in provisioned.iter().chain(&state.added) {
+        if !state.revoked.contains(&key.fingerprint)
+            && !keys.iter().any(|k| k.fingerprint == key.fingerprint)
+        {
+            keys.push(key.clone());
+        }
+    }
+    keys
+}
+
+/// Base64 key of an `authorized_keys` line; options before the key type
+/// are skipped
+pub(crate) fn key_blob(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split_whitespace();
+    fields.by_ref().find(|field| {
+        field.starts_with("ssh-") || field.starts_with("ecdsa-sha2-") || field.starts_with("sk-")
+    })?;
+    fields.next()
+}
+
+fn parse_key(line: &str, source: &str) -> Result<AuthorizedKey> {
+    let invalid = || RemoteError::InvalidKey(line.trim().chars().take(40).collect());
+    let blob = key_blob(line).ok_or_else(invalid)?;
+    let key = russh_keys::parse_public_key_base64(blob).map_err(|_| invalid())?;
+    Ok(AuthorizedKey {
+        line: line.trim().to_string(),
+        fingerprint: format!("SHA256:{}", key.fingerprint()),
+        source: source.to_string(),
+    })
+}
+
+/// Every valid key in an `authorized_keys` text; others are logged
+fn parse_keys(text: &str, source: &str) -> Vec<AuthorizedKey> {
+    text.lines()
+        .filter(|line| key_blob(line).is_some())
+        .filter_map(|line| {
+            parse_key(line, source)
+                .inspect_err(|e| warn!("Skipping SSH key from {}: {}", source, e))
+                .ok()
+        })
+        .collect()
+}
+
+async fn fetch(url: &str) -> Result<String> {
+    debug!("Fetching SSH keys from {}", url);
+    let output = Command::new("curl")
+        .args(["-sfL", "--max-time"])
+        .arg(FETCH_TIMEOUT_SECS.to_string())
+        .arg(url)
+        .output()
+        .await
+        .map_err(|e| RemoteError::SshError(format!("Failed to run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(RemoteError::SshError(format!(
+            "{}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Write through a temporary file so sshd never reads half a file; only
+/// root may read the result
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension("tmp");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp)
+        .and_then(|mut file| file.write_all(data))?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl tech@lab";
+    const FINGERPRINT: &str = "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU";
+
+    #[test]
+    fn test_parse_key() {
+        let key = parse_key(KEY, "api").unwrap();
+        assert_eq!(key.fingerprint, FINGERPRINT);
+        assert_eq!(key.line, KEY);
+
+        let with_options = format!("from=\"10.0.0.0/8\",no-pty {}", KEY);
+        assert_eq!(
+            parse_key(&with_options, "api").unwrap().fingerprint,
+            FINGERPRINT
+        );
+        assert!(parse_key("ssh-ed25519 not-base64", "api").is_err());
+        assert!(parse_key("# comment", "api").is_err());
+        assert_eq!(key_blob("  "), None);
+    }
+
+    #[tokio::test]
+    async fn test_add_and_revoke() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("authorized_keys");
+        let config = SshKeysConfig {
+            keys: vec![KEY.to_string()],
+            state_path: dir.path().join("ssh-keys.json"),
+            ..SshKeysConfig::default()
+        };
+        let keys = AuthorizedKeys::new(config.clone(), path.clone()).with_offline(true);
+
+        let provisioned = keys.provision().await.unwrap();
+        assert_eq!(provisioned.len(), 1);
+        assert_eq!(provisioned[0].source, "config");
+        assert!(std::fs::read_to_string(&path).unwrap().contains(KEY));
+
+        // A configured key stays revoked after provisioning again
+        keys.remove(FINGERPRINT).await.unwrap();
+        assert!(keys.list().await.unwrap().is_empty());
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(KEY));
+        let restarted = AuthorizedKeys::new(config, path.clone()).with_offline(true);
+        assert!(restarted.provision().await.unwrap().is_empty());
+        assert!(restarted.remove(FINGERPRINT).await.is_err());
+
+        // Adding it through the API lifts the revocation
+        let added = restarted.add(KEY).await.unwrap();
+        assert_eq!(added.fingerprint, FINGERPRINT);
+        assert_eq!(added.source, "config");
+        assert_eq!(restarted.list().await.unwrap().len(), 1);
+        assert!(std::fs::read_to_string(&path).unwrap().contains(KEY));
+        assert!(restarted.add("ssh-rsa garbage").await.is_err());
+    }
+}
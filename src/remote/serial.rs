@@ -0,0 +1,485 @@
+use crate::auth::Principal;
+use crate::config::{Role, SerialConfig, SerialPortConfig};
+use crate::error::{AuthError, RemoteError, Result};
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::unix::AsyncFd;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Output replayed to terminals that attach later, so they see what is on
+/// the console instead of a blank screen
+const BACKLOG_BYTES: usize = 16 * 1024;
+const READ_SIZE: usize = 4096;
+
+/// A configured port and whether it could be opened
+#[derive(Debug, Clone, Serialize)]
+pub struct SerialPortStatus {
+    pub name: String,
+    pub device: PathBuf,
+    pub baud: u32,
+    /// Role needed to attach
+    pub role: Role,
+    pub read_only: bool,
+    pub open: bool,
+    /// Why the device is not open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Terminals attached right now
+    pub terminals: usize,
+}
+
+/// Bridges serial consoles of install targets to WebSocket terminals.
+/// Each port is read continuously, its output logged to a file and fanned
+/// out to every attached terminal; typed input goes back to the device.
+#[derive(Default)]
+pub struct SerialConsoles {
+    ports: RwLock<Vec<SerialPort>>,
+}
+
+struct SerialPort {
+    config: SerialPortConfig,
+    state: std::result::Result<OpenPort, String>,
+}
+
+struct OpenPort {
+    /// Subscribes terminals; each one is a receiver
+    output: broadcast::Sender<Vec<u8>>,
+    /// Only the reader holds the sender, so terminals see the stream end
+    /// when the device goes away
+    closed: watch::Receiver<()>,
+    input: mpsc::Sender<Vec<u8>>,
+    backlog: Arc<Mutex<VecDeque<u8>>>,
+    reader: JoinHandle<()>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl SerialConsoles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open every configured port. Ports that fail stay listed with the
+    /// error; it is an error only when none opens.
+    pub async fn start(&self, config: &SerialConfig) -> Result<()> {
+        self.stop().await;
+        if let Err(e) = tokio::fs::create_dir_all(&config.log_dir).await {
+            warn!(
+                "Cannot create serial log directory {}: {}",
+                config.log_dir.display(),
+                e
+            );
+        }
+
+        let mut ports = Vec::new();
+        for port in &config.ports {
+            let state = match open_port(port, &config.log_dir).await {
+                Ok(open) => {
+                    info!(
+                        "Serial console {} on {} at {} baud",
+                        port.name,
+                        port.device.display(),
+                        port.baud
+                    );
+                    Ok(open)
+                }
+                Err(e) => {
+                    warn!("Serial console {} unavailable: {}", port.name, e);
+                    Err(e.to_string())
+                }
+            };
+            ports.push(SerialPort {
+                config: port.clone(),
+                state,
+            });
+        }
+
+        let opened = ports.iter().filter(|p| p.state.is_ok()).count();
+        let total = ports.len();
+        *self.ports.write().await = ports;
+        if total > 0 && opened == 0 {
+            return Err(
+                RemoteError::SerialError("No serial console could be opened".to_string()).into(),
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn stop(&self) {
+        for mut port in self.ports.write().await.drain(..) {
+            if let Ok(open) = &mut port.state {
+                if let Some(tx) = open.shutdown_tx.take() {
+                    let _ = tx.send(());
+                }
+                debug!("Closed serial console {}", port.config.name);
+            }
+        }
+    }
+
+    pub async fn ports(&self) -> Vec<SerialPortStatus> {
+        self.ports
+            .read()
+            .await
+            .iter()
+            .map(|port| {
+                let (open, error, terminals) = match &port.state {
+                    Ok(open) if !open.reader.is_finished() => {
+                        (true, None, open.output.receiver_count())
+                    }
+                    Ok(_) => (false, Some("Device closed".to_string()), 0),
+                    Err(e) => (false, Some(e.clone()), 0),
+                };
+                SerialPortStatus {
+                    name: port.config.name.clone(),
+                    device: port.config.device.clone(),
+                    baud: port.config.baud,
+                    role: port.config.role,
+                    read_only: port.config.read_only,
+                    open,
+                    error,
+                    terminals,
+                }
+            })
+            .collect()
+    }
+
+    /// Attach a terminal to a port, if `principal` may use it
+    pub async fn attach(&self, name: &str, principal: &Principal) -> Result<SerialAttachment> {
+        let ports = self.ports.read().await;
+        let port = ports
+            .iter()
+            .find(|port| port.config.name == name)
+            .ok_or_else(|| RemoteError::PortNotFound(name.to_string()))?;
+        check_access(&port.config, principal)?;
+        let open = match &port.state {
+            Ok(open) if !open.reader.is_finished() => open,
+            Ok(_) => {
+                return Err(RemoteError::SerialError(format!("{}: device closed", name)).into())
+            }
+            Err(e) => return Err(RemoteError::SerialError(format!("{}: {}", name, e)).into()),
+        };
+
+        // The reader appends and sends under this lock, so nothing is
+        // missed or repeated between the backlog and the stream
+        let backlog = open.backlog.lock().unwrap();
+        Ok(SerialAttachment {
+            port: name.to_string(),
+            user: principal.name.clone(),
+            backlog: backlog.iter().copied().collect(),
+            output: open.output.subscribe(),
+            closed: open.closed.clone(),
+            input: (!port.config.read_only).then(|| open.input.clone()),
+        })
+    }
+}
+
+/// A terminal on a serial port, until its WebSocket closes
+pub struct SerialAttachment {
+    port: String,
+    user: String,
+    backlog: Vec<u8>,
+    output: broadcast::Receiver<Vec<u8>>,
+    closed: watch::Receiver<()>,
+    /// `None` on read-only ports
+    input: Option<mpsc::Sender<Vec<u8>>>,
+}
+
+impl SerialAttachment {
+    pub fn is_read_only(&self) -> bool {
+        self.input.is_none()
+    }
+
+    /// Relay between the port and an xterm.js-style terminal: console
+    /// output as binary frames, typed input as text or binary frames
+    pub async fn run(mut self, socket: WebSocket) {
+        info!(
+            "{} attached to serial console {}{}",
+            self.user,
+            self.port,
+            if self.is_read_only() {
+                " read-only"
+            } else {
+                ""
+            }
+        );
+        let (mut sink, mut stream) = socket.split();
+        let backlog = std::mem::take(&mut self.backlog);
+        let mut open = backlog.is_empty() || sink.send(Message::Binary(backlog)).await.is_ok();
+
+        while open {
+            tokio::select! {
+                output = self.output.recv() => match output {
+                    Ok(chunk) => open = sink.send(Message::Binary(chunk)).await.is_ok(),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("Terminal on {} skipped {} chunks", self.port, skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => open = false,
+                },
+                _ = self.closed.changed() => open = false,
+                message = stream.next() => match message {
+                    Some(Ok(Message::Text(text))) => self.type_input(text.into_bytes()).await,
+                    Some(Ok(Message::Binary(data))) => self.type_input(data).await,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => open = false,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = sink.close().await;
+        info!("{} detached from serial console {}", self.user, self.port);
+    }
+
+    async fn type_input(&self, data: Vec<u8>) {
+        if let Some(input) = &self.input {
+            let _ = input.send(data).await;
+        }
+    }
+}
+
+/// Admins may use every port; others need its role and, when the port
+/// names users, to be one of them
+fn check_access(port: &SerialPortConfig, principal: &Principal) -> Result<()> {
+    let listed = port.users.is_empty() || port.users.contains(&principal.name);
+    if principal.role == Role::Admin || (principal.role >= port.role && listed) {
+        Ok(())
+    } else {
+        Err(AuthError::Forbidden(principal.name.clone(), port.role).into())
+    }
+}
+
+async fn open_port(config: &SerialPortConfig, log_dir: &Path) -> Result<OpenPort> {
+    configure(&config.device, config.baud).await?;
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(nix::libc::O_NOCTTY | nix::libc::O_NONBLOCK)
+        .open(&config.device)
+        .map_err(|e| {
+            RemoteError::SerialError(format!("Cannot open {}: {}", config.device.display(), e))
+        })?;
+    let tty = AsyncFd::new(file)
+        .map_err(|e| RemoteError::SerialError(format!("{}: {}", config.device.display(), e)))?;
+
+    let log_path = log_dir.join(format!("{}.log", config.name));
+    let log = match tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .await
+    {
+        Ok(log) => Some(log),
+        Err(e) => {
+            warn!("Cannot log serial console to {}: {}", log_path.display(), e);
+            None
+        }
+    };
+
+    let (output, _) = broadcast::channel(256);
+    let (closed_tx, closed) = watch::channel(());
+    let (input, input_rx) = mpsc::channel(64);
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let backlog = Arc::new(Mutex::new(VecDeque::with_capacity(BACKLOG_BYTES)));
+    let reader = tokio::spawn(relay(
+        config.name.clone(),
+        tty,
+        log,
+        backlog.clone(),
+        output.clone(),
+        closed_tx,
+        input_rx,
+        shutdown_rx,
+    ));
+
+    Ok(OpenPort {
+        output,
+        closed,
+        input,
+        backlog,
+        reader,
+        shutdown_tx: Some(shutdown_tx),
+    })
+}
+
+/// 8N1 raw mode at `baud`, without local echo or modem hang-up on close
+async fn configure(device: &Path, baud: u32) -> Result<()> {
+    let output = Command::new("stty")
+        .arg("-F")
+        .arg(device)
+        .arg(baud.to_string())
+        .args([
+            "raw", "-echo", "cs8", "-cstopb", "-parenb", "clocal", "-hupcl",
+        ])
+        .output()
+        .await
+        .map_err(|e| RemoteError::SerialError(format!("Failed to run stty: {}", e)))?;
+    if !output.status.success() {
+        return Err(RemoteError::SerialError(format!(
+            "Cannot set up {}: {}",
+            device.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+async fn relay(
+    name: String,
+    tty: AsyncFd<File>,
+    mut log: Option<tokio::fs::File>,
+    backlog: Arc<Mutex<VecDeque<u8>>>,
+    output: broadcast::Sender<Vec<u8>>,
+    // Dropped on return, which ends every terminal
+    _closed: watch::Sender<()>,
+    mut input: mpsc::Receiver<Vec<u8>>,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) {
+    let mut buf = [0u8; READ_SIZE];
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_rx => break,
+            read = read_tty(&tty, &mut buf) => {
+                let chunk = match read {
+                    Ok(0) => {
+                        warn!("Serial console {} closed", name);
+                        break;
+                    }
+                    Ok(n) => buf[..n].to_vec(),
+                    Err(e) => {
+                        warn!("Serial console {} failed: {}", name, e);
+                        break;
+                    }
+                };
+                if let Some(file) = &mut log {
+                    if let Err(e) = file.write_all(&chunk).await {
+                        warn!("Stopped logging serial console {}: {}", name, e);
+                        log = None;
+                    }
+                }
+                let mut backlog = backlog.lock().unwrap();
+                push_backlog(&mut backlog, &chunk);
+                let _ = output.send(chunk);
+            }
+            Some(data) = input.recv() => {
+                if let Err(e) = write_tty(&tty, &data).await {
+                    warn!("Cannot write to serial console {}: {}", name, e);
+                }
+            }
+        }
+    }
+}
+
+async fn read_tty(tty: &AsyncFd<File>, buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        let mut guard = tty.readable().await?;
+        if let Ok(result) = guard.try_io(|fd| fd.get_ref().read(buf)) {
+            return result;
+        }
+    }
+}
+
+async fn write_tty(tty: &AsyncFd<File>, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        let mut guard = tty.writable().await?;
+        if let Ok(written) = guard.try_io(|fd| fd.get_ref().write(data)) {
+            data = &data[written?..];
+        }
+    }
+    Ok(())
+}
+
+fn push_backlog(backlog: &mut VecDeque<u8>, chunk: &[u8]) {
+    let chunk = &chunk[chunk.len().saturating_sub(BACKLOG_BYTES)..];
+    let overflow = (backlog.len() + chunk.len()).saturating_sub(BACKLOG_BYTES);
+    backlog.drain(..overflow);
+    backlog.extend(chunk);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(role: Role, users: &[&str]) -> SerialPortConfig {
+        SerialPortConfig {
+            name: "rack1".to_string(),
+            device: PathBuf::from("/dev/ttyUSB0"),
+            role,
+            users: users.iter().map(|u| u.to_string()).collect(),
+            ..SerialPortConfig::default()
+        }
+    }
+
+    fn principal(name: &str, role: Role) -> Principal {
+        Principal {
+            name: name.to_string(),
+            role,
+        }
+    }
+
+    #[test]
+    fn test_check_access() {
+        let open = port(Role::Operator, &[]);
+        assert!(check_access(&open, &principal("tech", Role::Operator)).is_ok());
+        assert!(check_access(&open, &principal("dashboard", Role::Viewer)).is_err());
+
+        let listed = port(Role::Viewer, &["tech"]);
+        assert!(check_access(&listed, &principal("tech", Role::Viewer)).is_ok());
+        assert!(check_access(&listed, &principal("other", Role::Operator)).is_err());
+        assert!(check_access(&listed, &principal("admin", Role::Admin)).is_ok());
+    }
+
+    #[test]
+    fn test_backlog_keeps_latest_output() {
+        let mut backlog = VecDeque::new();
+        push_backlog(&mut backlog, b"login: ");
+        assert_eq!(backlog.iter().copied().collect::<Vec<_>>(), b"login: ");
+
+        push_backlog(&mut backlog, &vec![b'x'; BACKLOG_BYTES - 2]);
+        assert_eq!(backlog.len(), BACKLOG_BYTES);
+        assert_eq!(backlog.front(), Some(&b'n'));
+
+        let mut big = vec![b'a'; BACKLOG_BYTES];
+        big.push(b'z');
+        push_backlog(&mut backlog, &big);
+        assert_eq!(backlog.len(), BACKLOG_BYTES);
+        assert_eq!(backlog.back(), Some(&b'z'));
+    }
+
+    #[tokio::test]
+    async fn test_attach_unknown_or_failed_port() {
+        let consoles = SerialConsoles::new();
+        let config = SerialConfig {
+            enabled: true,
+            log_dir: std::env::temp_dir(),
+            ports: vec![SerialPortConfig {
+                device: PathBuf::from("/nonexistent/ttyUSB9"),
+                ..port(Role::Operator, &[])
+            }],
+        };
+        assert!(consoles.start(&config).await.is_err());
+
+        let status = consoles.ports().await;
+        assert_eq!(status.len(), 1);
+        assert!(!status[0].open);
+        assert!(status[0].error.is_some());
+
+        let admin = principal("admin", Role::Admin);
+        assert!(matches!(
+            consoles.attach("rack2", &admin).await,
+            Err(crate::error::Error::Remote(RemoteError::PortNotFound(_)))
+        ));
+        assert!(matches!(
+            consoles.attach("rack1", &admin).await,
+            Err(crate::error::Error::Remote(RemoteError::SerialError(_)))
+        ));
+    }
+}
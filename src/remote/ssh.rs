@@ -1,214 +1,901 @@
+use crate::audit::{AuditAction, AuditLog, AuditSource};
+use crate::auth::bans::{BanList, BanSource};
+use crate::disk::DiskManager;
 use crate::error::{RemoteError, Result};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
+use crate::iso::IsoManager;
+use crate::job::install::{InstallJob, InstallJobRunner};
+use crate::remote::keys;
+use crate::service::startup::StartupStatus;
+use async_trait::async_trait;
+use russh::server::{Auth, Handler, Msg, Server, Session};
+use russh::{Channel, ChannelId, CryptoVec, MethodSet};
+use russh_keys::key::{KeyPair, PublicKey};
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio::time::{sleep, Duration};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{oneshot, RwLock};
 use tracing::{debug, error, info, warn};
 
+/// Log lines `logs` prints when no count is given
+const DEFAULT_LOG_LINES: usize = 100;
+/// Idle sessions are closed after this long
+const INACTIVITY_TIMEOUT: Duration = Duration::from_secs(3600);
+
+const HELP: &str = "\
+Commands:
+  status                     startup state of every subsystem
+  logs [lines]               end of the node log (default 100 lines)
+  jobs                       running and queued install jobs
+  install <iso> <device>     queue an install of a catalogued ISO
+  cancel <job>               drop a queued job or stop a running one
+  resume <job>               queue a stopped job from its step
+  restart <job>              queue a stopped job from the start
+  help                       this list
+Use sftp to copy ISOs to and from the node; logs are under /files.
+";
+
+/// SFTP directory the download shares appear in, read-only
+const SHARES_DIR: &str = "files";
+
 #[derive(Debug, Clone)]
-pub struct VncConfig {
-    pub display: String,
+pub struct SshConfig {
     pub port: u16,
-    pub password: Option<String>,
-    pub auth_file: Option<PathBuf>,
-    pub geometry: Option<String>,
-    pub depth: Option<u8>,
-    pub allow_shared: bool,
-    pub view_only: bool,
-}
-
-impl Default for VncConfig {
-    fn default() -> Self {
-        Self {
-            display: ":0".to_string(),
-            port: 5900,
-            password: None,
-            auth_file: None,
-            geometry: None,
-            depth: None,
-            allow_shared: true,
-            view_only: false,
-        }
-    }
+    pub host_key_path: PathBuf,
+    pub authorized_keys_path: PathBuf,
+    /// Directory SFTP clients see as `/`
+    pub sftp_root: PathBuf,
+    /// Read-only shares shown under `/files`
+    pub shares: BTreeMap<String, PathBuf>,
 }
 
-#[derive(Debug, Clone)]
-pub struct VncClient {
-    pub address: String,
-    pub connected_at: std::time::SystemTime,
+/// Node state the SSH and MQTT commands act on
+#[derive(Clone)]
+pub struct NodeCommands {
+    pub startup: StartupStatus,
+    pub install_jobs: InstallJobRunner,
+    pub iso_manager: Arc<IsoManager>,
+    pub disk_manager: Arc<DiskManager>,
+    /// Log file `logs` reads; unset when logging to the console only
+    pub log_file: Option<PathBuf>,
 }
 
-pub struct VncServer {
-    config: Arc<RwLock<VncConfig>>,
-    process: Arc<RwLock<Option<Child>>>,
-    clients: Arc<RwLock<HashMap<String, VncClient>>>,
-    restart_count: Arc<RwLock<u32>>,
+/// Embedded SSH server: public key login, the commands in `HELP` instead
+/// of a shell, and SFTP confined to the ISO directory
+pub struct SshServer {
+    config: SshConfig,
+    commands: Option<NodeCommands>,
+    bans: Option<Arc<BanList>>,
+    audit: Option<AuditLog>,
+    sessions: Arc<AtomicUsize>,
+    /// `SHA256:` fingerprint of the host key, once loaded
+    host_key_fingerprint: RwLock<Option<String>>,
+    shutdown_tx: RwLock<Option<oneshot::Sender<()>>>,
 }
 
-impl VncServer {
-    pub fn new(config: VncConfig) -> Self {
+impl SshServer {
+    pub fn new(config: SshConfig) -> Self {
         Self {
-            config: Arc::new(RwLock::new(config)),
-            process: Arc::new(RwLock::new(None)),
-            clients: Arc::new(RwLock::new(HashMap::new())),
-            restart_count: Arc::new(RwLock::new(0)),
+            config,
+            commands: None,
+            bans: None,
+            audit: None,
+            sessions: Arc::new(AtomicUsize::new(0)),
+            host_key_fingerprint: RwLock::new(None),
+            shutdown_tx: RwLock::new(None),
         }
     }
 
+    /// Without commands only SFTP is offered
+    pub fn with_commands(mut self, commands: NodeCommands) -> Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Refuse banned addresses and report rejected logins
+    pub fn with_bans(mut self, bans: Arc<BanList>) -> Self {
+        self.bans = Some(bans);
+        self
+    }
+
+    /// Record logins, commands and SFTP sessions
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
     pub async fn start(&self) -> Result<()> {
         if self.is_running().await {
-            return Err(RemoteError::AlreadyRunning("VNC server".to_string()));
+            return Err(RemoteError::SshError("SSH server already running".to_string()).into());
         }
 
-        info!("Starting VNC server");
+        let host_key = load_host_key(&self.config.host_key_path).await?;
+        let public_key = host_key
+            .clone_public_key()
+            .map_err(|e| RemoteError::SshError(format!("Invalid host key: {}", e)))?;
+        *self.host_key_fingerprint.write().await =
+            Some(format!("SHA256:{}", public_key.fingerprint()));
+        let server_config = Arc::new(russh::server::Config {
+            methods: MethodSet::PUBLICKEY,
+            keys: vec![host_key],
+            auth_rejection_time: Duration::from_secs(1),
+            auth_rejection_time_initial: Some(Duration::from_secs(0)),
+            inactivity_timeout: Some(INACTIVITY_TIMEOUT),
+            ..Default::default()
+        });
 
-        let config = self.config.read().await;
-        let mut cmd = Command::new("x11vnc");
+        let addr = SocketAddr::from(([0, 0, 0, 0], self.config.port));
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| RemoteError::SshError(format!("Failed to listen on {}: {}", addr, e)))?;
 
-        cmd.arg("-display").arg(&config.display);
-        cmd.arg("-rfbport").arg(config.port.to_string());
+        let mut server = SshListener {
+            config: self.config.clone(),
+            commands: self.commands.clone(),
+            bans: self.bans.clone(),
+            audit: self.audit.clone(),
+            sessions: self.sessions.clone(),
+        };
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            tokio::select! {
+                result = server.run_on_socket(server_config, &listener) => {
+                    if let Err(e) = result {
+                        error!("SSH server failed: {}", e);
+                    }
+                }
+                _ = shutdown_rx => {}
+            }
+        });
 
-        if let Some(password) = &config.password {
-            cmd.arg("-passwd").arg(password);
-        } else if let Some(auth_file) = &config.auth_file {
-            cmd.arg("-rfbauth").arg(auth_file);
-        } else {
-            cmd.arg("-nopw");
-        }
+        *self.shutdown_tx.write().await = Some(shutdown_tx);
+        info!("SSH server listening on {}", addr);
+        Ok(())
+    }
 
-        if let Some(geometry) = &config.geometry {
-            cmd.arg("-geometry").arg(geometry);
+    pub async fn stop(&self) -> Result<()> {
+        if let Some(tx) = self.shutdown_tx.write().await.take() {
+            info!("Stopping SSH server");
+            let _ = tx.send(());
         }
+        Ok(())
+    }
 
-        if let Some(depth) = config.depth {
-            cmd.arg("-depth").arg(depth.to_string());
-        }
+    pub async fn is_running(&self) -> bool {
+        self.shutdown_tx
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|tx| !tx.is_closed())
+    }
 
-        if config.allow_shared {
-            cmd.arg("-shared");
+    pub async fn get_status(&self) -> HashMap<String, String> {
+        let mut status = HashMap::new();
+        status.insert("running".to_string(), self.is_running().await.to_string());
+        status.insert(
+            "sessions".to_string(),
+            self.sessions.load(Ordering::Relaxed).to_string(),
+        );
+        status.insert("port".to_string(), self.config.port.to_string());
+        status.insert(
+            "sftp_root".to_string(),
+            self.config.sftp_root.display().to_string(),
+        );
+        if let Some(fingerprint) = self.host_key_fingerprint.read().await.clone() {
+            status.insert("host_key_fingerprint".to_string(), fingerprint);
         }
+        status
+    }
+}
 
-        if config.view_only {
-            cmd.arg("-viewonly");
-        }
+/// Host key from `path`; a new Ed25519 key is saved there when missing
+async fn load_host_key(path: &Path) -> Result<KeyPair> {
+    if tokio::fs::try_exists(path).await.unwrap_or(false) {
+        return russh_keys::load_secret_key(path, None).map_err(|e| {
+            RemoteError::KeyGenerationFailed(format!("{}: {}", path.display(), e)).into()
+        });
+    }
 
-        cmd.arg("-forever");
-        cmd.arg("-bg");
-        cmd.arg("-noxdamage");
+    info!("Generating SSH host key {}", path.display());
+    let key = KeyPair::generate_ed25519().ok_or_else(|| {
+        RemoteError::KeyGenerationFailed("Ed25519 key generation failed".to_string())
+    })?;
+    let mut pem = Vec::new();
+    russh_keys::encode_pkcs8_pem(&key, &mut pem)
+        .map_err(|e| RemoteError::KeyGenerationFailed(e.to_string()))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(&pem))
+        .map_err(|e| RemoteError::KeyGenerationFailed(format!("{}: {}", path.display(), e)))?;
+    Ok(key)
+}
 
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
+struct SshListener {
+    config: SshConfig,
+    commands: Option<NodeCommands>,
+    bans: Option<Arc<BanList>>,
+    audit: Option<AuditLog>,
+    sessions: Arc<AtomicUsize>,
+}
 
-        debug!("Executing VNC command: {:?}", cmd);
+impl Server for SshListener {
+    type Handler = SshSession;
 
-        let child = cmd
-            .spawn()
-            .map_err(|e| RemoteError::StartFailed(format!("Failed to start x11vnc: {}", e)))?;
+    fn new_client(&mut self, peer: Option<SocketAddr>) -> SshSession {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        SshSession {
+            config: self.config.clone(),
+            commands: self.commands.clone(),
+            bans: self.bans.clone(),
+            audit: self.audit.clone(),
+            sessions: self.sessions.clone(),
+            peer,
+            user: None,
+            rejected: false,
+            channels: HashMap::new(),
+        }
+    }
+}
 
-        *self.process.write().await = Some(child);
+/// One client connection
+struct SshSession {
+    config: SshConfig,
+    commands: Option<NodeCommands>,
+    bans: Option<Arc<BanList>>,
+    audit: Option<AuditLog>,
+    sessions: Arc<AtomicUsize>,
+    peer: Option<SocketAddr>,
+    /// Set once a key is accepted
+    user: Option<String>,
+    /// A key was already rejected; clients offer several per connection
+    rejected: bool,
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
 
-        sleep(Duration::from_millis(500)).await;
+impl SshSession {
+    async fn audit(&self, user: &str, action: &str, target: Option<String>, outcome: &str) {
+        if let Some(audit) = &self.audit {
+            let mut entry = AuditAction::new(AuditSource::Ssh, user, action)
+                .with_address(self.peer.map(|peer| peer.ip()))
+                .with_outcome(outcome);
+            if let Some(target) = target {
+                entry = entry.with_target(target);
+            }
+            audit.record(entry).await;
+        }
+    }
+}
 
-        if !self.is_running().await {
-            return Err(RemoteError::StartFailed(
-                "VNC server exited immediately".to_string(),
-            ));
+impl Drop for SshSession {
+    fn drop(&mut self) {
+        self.sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl Handler for SshSession {
+    type Error = russh::Error;
+
+    /// `authorized_keys` is read on every attempt, so keys added or removed
+    /// take effect without a restart. The user name is only logged: there
+    /// is no shell for it to select.
+    async fn auth_publickey(
+        &mut self,
+        user: &str,
+        key: &PublicKey,
+    ) -> std::result::Result<Auth, Self::Error> {
+        if let (Some(bans), Some(peer)) = (&self.bans, self.peer) {
+            if let Err(e) = bans.check(peer.ip()) {
+                warn!("Refused SSH login from {}: {}", peer, e);
+                return Ok(Auth::Reject {
+                    proceed_with_methods: None,
+                });
+            }
+        }
+        if is_authorized(&self.config.authorized_keys_path, key).await {
+            info!(
+                "SSH login as {} from {:?} with {}",
+                user,
+                self.peer,
+                key.fingerprint()
+            );
+            self.audit(user, "login", Some(key.fingerprint()), "ok")
+                .await;
+            self.user = Some(user.to_string());
+            Ok(Auth::Accept)
+        } else {
+            warn!(
+                "Rejected SSH key {} from {:?}",
+                key.fingerprint(),
+                self.peer
+            );
+            // One failure per connection, however many keys it offers
+            if let (Some(bans), Some(peer), false) = (&self.bans, self.peer, self.rejected) {
+                bans.record_failure(peer.ip(), BanSource::Ssh).await;
+            }
+            if !self.rejected {
+                self.audit(
+                    user,
+                    "login",
+                    Some(key.fingerprint()),
+                    "failed: key not authorized",
+                )
+                .await;
+            }
+            self.rejected = true;
+            Ok(Auth::Reject {
+                proceed_with_methods: None,
+            })
         }
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        _session: &mut Session,
+    ) -> std::result::Result<bool, Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        Ok(true)
+    }
 
-        info!("VNC server started on port {}", config.port);
+    async fn channel_close(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> std::result::Result<(), Self::Error> {
+        self.channels.remove(&channel);
         Ok(())
     }
 
-    pub async fn stop(&self) -> Result<()> {
-        if let Some(mut child) = self.process.write().await.take() {
-            info!("Stopping VNC server");
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> std::result::Result<(), Self::Error> {
+        let line = String::from_utf8_lossy(data);
+        debug!("SSH command from {:?}: {}", self.peer, line);
+        session.channel_success(channel);
 
-            child
-                .kill()
-                .map_err(|e| RemoteError::StopFailed(format!("Failed to kill process: {}", e)))?;
+        let (output, code) = match (&self.commands, parse_command(&line)) {
+            (None, _) => ("Node commands are not available\n".to_string(), 1),
+            (_, Err(e)) => (format!("{}\n\n{}", e, HELP), 2),
+            (Some(commands), Ok(command)) => match commands.run(command).await {
+                Ok(output) => (output, 0),
+                Err(e) => (format!("{}\n", e), 1),
+            },
+        };
+        let outcome = match code {
+            0 => "ok".to_string(),
+            code => format!("failed: exit status {}", code),
+        };
+        let user = self.user.clone().unwrap_or_default();
+        self.audit(&user, &format!("ssh: {}", line.trim()), None, &outcome)
+            .await;
+        if code == 0 {
+            session.data(channel, CryptoVec::from(output));
+        } else {
+            session.extended_data(channel, 1, CryptoVec::from(output));
+        }
+        session.exit_status_request(channel, code);
+        session.eof(channel);
+        session.close(channel);
+        Ok(())
+    }
 
-            self.clients.write().await.clear();
-            info!("VNC server stopped");
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> std::result::Result<(), Self::Error> {
+        session.channel_success(channel);
+        let message = format!(
+            "No interactive shell; run `ssh <node> <command>`.\n\n{}",
+            HELP
+        );
+        session.extended_data(channel, 1, CryptoVec::from(message));
+        session.exit_status_request(channel, 1);
+        session.eof(channel);
+        session.close(channel);
+        Ok(())
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> std::result::Result<(), Self::Error> {
+        match (name, self.channels.remove(&channel)) {
+            ("sftp", Some(stream)) => {
+                info!(
+                    "SFTP session from {:?} in {}",
+                    self.peer,
+                    self.config.sftp_root.display()
+                );
+                let user = self.user.clone().unwrap_or_default();
+                let root = self.config.sftp_root.display().to_string();
+                self.audit(&user, "sftp", Some(root), "ok").await;
+                session.channel_success(channel);
+                let mut sftp = SftpSession::new(self.config.sftp_root.clone())
+                    .with_shares(self.config.shares.clone());
+                if let Some(commands) = &self.commands {
+                    sftp = sftp.with_iso_manager(commands.iso_manager.clone());
+                }
+                russh_sftp::server::run(stream.into_stream(), sftp).await;
+            }
+            _ => session.channel_failure(channel),
         }
         Ok(())
     }
+}
+
+async fn is_authorized(path: &Path, key: &PublicKey) -> bool {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("Cannot read {}: {}", path.display(), e);
+            return false;
+        }
+    };
+    let fingerprint = key.fingerprint();
+    let authorized = key_blobs(&contents)
+        .filter_map(|blob| russh_keys::parse_public_key_base64(blob).ok())
+        .any(|authorized| authorized.fingerprint() == fingerprint);
+    authorized
+}
 
-    pub async fn restart(&self) -> Result<()> {
-        info!("Restarting VNC server");
-        self.stop().await?;
-        sleep(Duration::from_millis(100)).await;
-        self.start().await?;
+/// Base64 key of every `authorized_keys` entry
+fn key_blobs(contents: &str) -> impl Iterator<Item = &str> {
+    contents.lines().filter_map(keys::key_blob)
+}
 
-        let mut count = self.restart_count.write().await;
-        *count += 1;
+#[derive(Debug, PartialEq, Eq)]
+enum NodeCommand {
+    Help,
+    Status,
+    Logs(usize),
+    Jobs,
+    Install { iso: String, device: String },
+    Cancel(String),
+    Resume(String),
+    Restart(String),
+}
 
-        Ok(())
+fn parse_command(line: &str) -> std::result::Result<NodeCommand, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [] | ["help"] => Ok(NodeCommand::Help),
+        ["status"] => Ok(NodeCommand::Status),
+        ["logs"] => Ok(NodeCommand::Logs(DEFAULT_LOG_LINES)),
+        ["logs", lines] => lines
+            .parse()
+            .map(NodeCommand::Logs)
+            .map_err(|_| format!("Not a line count: {}", lines)),
+        ["jobs"] => Ok(NodeCommand::Jobs),
+        ["install", iso, device] => Ok(NodeCommand::Install {
+            iso: iso.to_string(),
+            device: device.to_string(),
+        }),
+        ["cancel", id] => Ok(NodeCommand::Cancel(id.to_string())),
+        ["resume", id] => Ok(NodeCommand::Resume(id.to_string())),
+        ["restart", id] => Ok(NodeCommand::Restart(id.to_string())),
+        _ => Err(format!("Unknown command: {}", line.trim())),
     }
+}
 
-    pub async fn is_running(&self) -> bool {
-        if let Some(child) = &mut *self.process.write().await {
-            match child.try_wait() {
-                Ok(Some(_)) => false,
-                Ok(None) => true,
-                Err(_) => false,
+impl NodeCommands {
+    /// Output of `command`; structured results are JSON like the REST API's
+    async fn run(&self, command: NodeCommand) -> Result<String> {
+        match command {
+            NodeCommand::Help => Ok(HELP.to_string()),
+            NodeCommand::Status => to_json(&self.startup.snapshot().await),
+            NodeCommand::Logs(lines) => self.logs(lines).await,
+            NodeCommand::Jobs => to_json(&self.install_jobs.queue().await),
+            NodeCommand::Install { iso, device } => {
+                let entry = self.iso_manager.get_catalog_entry(&iso).await?;
+                let disk = self.disk_manager.get_disk_inventory(&device).await?;
+                let job = InstallJob::new(entry.path, &disk.path, false);
+                to_json(&self.install_jobs.enqueue(job).await?)
             }
-        } else {
-            false
+            NodeCommand::Cancel(id) => to_json(&self.install_jobs.cancel(&id).await?),
+            NodeCommand::Resume(id) => to_json(&self.install_jobs.resume(&id).await?),
+            NodeCommand::Restart(id) => to_json(&self.install_jobs.restart(&id).await?),
+        }
+    }
+
+    async fn logs(&self, lines: usize) -> Result<String> {
+        let Some(path) = &self.log_file else {
+            return Ok("The node logs to the console only\n".to_string());
+        };
+        let contents = tokio::fs::read_to_string(path).await?;
+        let all: Vec<&str> = contents.lines().collect();
+        let mut tail = all[all.len().saturating_sub(lines)..].join("\n");
+        tail.push('\n');
+        Ok(tail)
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String> {
+    let mut json = serde_json::to_string_pretty(value)
+        .map_err(|e| RemoteError::SshError(format!("Failed to encode output: {}", e)))?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// SFTP confined to one directory, which clients see as `/`, plus the
+/// download shares read-only under `/files`
+struct SftpSession {
+    root: PathBuf,
+    shares: BTreeMap<String, PathBuf>,
+    /// Catalogs ISOs once their upload is closed
+    iso_manager: Option<Arc<IsoManager>>,
+    files: HashMap<String, tokio::fs::File>,
+    /// ISOs open for writing, by handle
+    uploads: HashMap<String, PathBuf>,
+    /// Open directories and whether their listing was sent
+    dirs: HashMap<String, (SftpPath, bool)>,
+    next_handle: u64,
+}
+
+/// Where a client path leads
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SftpPath {
+    /// Below the SFTP root, writable
+    Root(PathBuf),
+    /// `/files` itself, listing the shares
+    Shares,
+    /// In a share, read-only: the share and the path in it
+    Shared(PathBuf, PathBuf),
+    /// Under `/files`, but no such share
+    Missing,
+}
+
+impl SftpSession {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            shares: BTreeMap::new(),
+            iso_manager: None,
+            files: HashMap::new(),
+            uploads: HashMap::new(),
+            dirs: HashMap::new(),
+            next_handle: 0,
         }
     }
 
-    pub async fn get_clients(&self) -> HashMap<String, VncClient> {
-        self.clients.read().await.clone()
+    fn with_shares(mut self, shares: BTreeMap<String, PathBuf>) -> Self {
+        self.shares = shares;
+        self
+    }
+
+    fn with_iso_manager(mut self, iso_manager: Arc<IsoManager>) -> Self {
+        self.iso_manager = Some(iso_manager);
+        self
     }
 
-    pub async fn add_client(&self, address: String) {
-        let client = VncClient {
-            address: address.clone(),
-            connected_at: std::time::SystemTime::now(),
+    fn resolve(&self, path: &str) -> SftpPath {
+        let path = normalize(path);
+        if self.shares.is_empty() {
+            return SftpPath::Root(self.root.join(path));
+        }
+        let mut components = path.components();
+        if components.next() != Some(Component::Normal(SHARES_DIR.as_ref())) {
+            return SftpPath::Root(self.root.join(path));
+        }
+        let Some(share) = components.next() else {
+            return SftpPath::Shares;
         };
-        self.clients.write().await.insert(address, client);
+        match self.shares.get(&*share.as_os_str().to_string_lossy()) {
+            Some(share) => SftpPath::Shared(share.clone(), share.join(components.as_path())),
+            None => SftpPath::Missing,
+        }
     }
 
-    pub async fn remove_client(&self, address: &str) {
-        self.clients.write().await.remove(address);
+    /// Path that may be changed; shares are read-only. Its directory must
+    /// stay in the root once symlinks are resolved; the path itself is not
+    /// followed, so removing or renaming a symlink acts on the link.
+    async fn writable(&self, path: &str) -> std::result::Result<PathBuf, StatusCode> {
+        let path = match self.resolve(path) {
+            SftpPath::Root(path) => path,
+            SftpPath::Missing => return Err(StatusCode::NoSuchFile),
+            SftpPath::Shares | SftpPath::Shared(..) => return Err(StatusCode::PermissionDenied),
+        };
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(StatusCode::PermissionDenied);
+        };
+        Ok(confined(&self.root, parent).await?.join(name))
     }
 
-    pub async fn get_status(&self) -> HashMap<String, String> {
-        let mut status = HashMap::new();
+    /// Local path behind a client path, the SFTP root standing in for
+    /// `/files`. Symlinks may not lead out of the root or a share.
+    async fn local(&self, path: &str) -> std::result::Result<PathBuf, StatusCode> {
+        match self.resolve(path) {
+            SftpPath::Root(path) => confined(&self.root, &path).await,
+            SftpPath::Shares => Ok(self.root.clone()),
+            SftpPath::Missing => Err(StatusCode::NoSuchFile),
+            SftpPath::Shared(share, path) => confined(&share, &path).await,
+        }
+    }
 
-        status.insert("running".to_string(), self.is_running().await.to_string());
-        status.insert(
-            "clients".to_string(),
-            self.clients.read().await.len().to_string(),
-        );
-        status.insert(
-            "restart_count".to_string(),
-            self.restart_count.read().await.to_string(),
-        );
+    fn handle(&mut self) -> String {
+        self.next_handle += 1;
+        self.next_handle.to_string()
+    }
+}
+
+/// `path` with symlinks resolved, if that stays under `root`
+async fn confined(root: &Path, path: &Path) -> std::result::Result<PathBuf, StatusCode> {
+    let root = tokio::fs::canonicalize(root).await.map_err(io_status)?;
+    let path = tokio::fs::canonicalize(path).await.map_err(io_status)?;
+    if path.starts_with(root) {
+        Ok(path)
+    } else {
+        Err(StatusCode::NoSuchFile)
+    }
+}
 
-        let config = self.config.read().await;
-        status.insert("port".to_string(), config.port.to_string());
-        status.insert("display".to_string(), config.display.clone());
+/// `path` relative to the SFTP root; `..` stops at the root
+fn normalize(path: &str) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+    normalized
+}
 
-        status
+fn status(id: u32, code: StatusCode) -> Status {
+    Status {
+        id,
+        status_code: code,
+        error_message: code.to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn io_status(e: std::io::Error) -> StatusCode {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+        std::io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        _ => StatusCode::Failure,
+    }
+}
+
+impl russh_sftp::server::Handler for SftpSession {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+
+    async fn init(
+        &mut self,
+        _version: u32,
+        _extensions: HashMap<String, String>,
+    ) -> std::result::Result<Version, Self::Error> {
+        Ok(Version::new())
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> std::result::Result<Name, Self::Error> {
+        let path = Path::new("/").join(normalize(&path));
+        Ok(Name {
+            id,
+            files: vec![File::dummy(path.to_string_lossy())],
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> std::result::Result<Attrs, Self::Error> {
+        let metadata = tokio::fs::metadata(self.local(&path).await?)
+            .await
+            .map_err(io_status)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> std::result::Result<Attrs, Self::Error> {
+        self.stat(id, path).await
     }
 
-    pub async fn update_config(&self, new_config: VncConfig) -> Result<()> {
-        let was_running = self.is_running().await;
+    async fn fstat(&mut self, id: u32, handle: String) -> std::result::Result<Attrs, Self::Error> {
+        let file = self.files.get(&handle).ok_or(StatusCode::Failure)?;
+        let metadata = file.metadata().await.map_err(io_status)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
 
-        if was_running {
-            self.stop().await?;
+    async fn opendir(&mut self, id: u32, path: String) -> std::result::Result<Handle, Self::Error> {
+        let dir = self.local(&path).await?;
+        if !tokio::fs::metadata(&dir).await.map_err(io_status)?.is_dir() {
+            return Err(StatusCode::NoSuchFile);
         }
+        // `/files` lists the shares, anything else the directory itself
+        let listing = match self.resolve(&path) {
+            SftpPath::Shares => SftpPath::Shares,
+            _ => SftpPath::Root(dir),
+        };
+        let handle = self.handle();
+        self.dirs.insert(handle.clone(), (listing, false));
+        Ok(Handle { id, handle })
+    }
 
-        *self.config.write().await = new_config;
+    /// The whole listing in one reply, then end of file
+    async fn readdir(&mut self, id: u32, handle: String) -> std::result::Result<Name, Self::Error> {
+        let (path, listed) = self.dirs.get_mut(&handle).ok_or(StatusCode::Failure)?;
+        if *listed {
+            return Err(StatusCode::Eof);
+        }
+        *listed = true;
 
-        if was_running {
-            self.start().await?;
+        let mut files = Vec::new();
+        let dir = match path.clone() {
+            SftpPath::Root(dir) => {
+                let root = tokio::fs::canonicalize(&self.root)
+                    .await
+                    .map_err(io_status)?;
+                if dir == root && !self.shares.is_empty() {
+                    let metadata = tokio::fs::metadata(&dir).await.map_err(io_status)?;
+                    files.push(File::new(SHARES_DIR, FileAttributes::from(&metadata)));
+                }
+                dir
+            }
+            SftpPath::Shares => {
+                for (name, share) in &self.shares {
+                    if let Ok(metadata) = tokio::fs::metadata(share).await {
+                        files.push(File::new(name, FileAttributes::from(&metadata)));
+                    }
+                }
+                return Ok(Name { id, files });
+            }
+            SftpPath::Shared(_, dir) => dir,
+            SftpPath::Missing => return Err(StatusCode::NoSuchFile),
+        };
+        let mut entries = tokio::fs::read_dir(&dir).await.map_err(io_status)?;
+        while let Some(entry) = entries.next_entry().await.map_err(io_status)? {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            files.push(File::new(
+                entry.file_name().to_string_lossy(),
+                FileAttributes::from(&metadata),
+            ));
         }
+        Ok(Name { id, files })
+    }
 
-        Ok(())
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        _attrs: FileAttributes,
+    ) -> std::result::Result<Handle, Self::Error> {
+        let writes = pflags.intersects(
+            OpenFlags::WRITE | OpenFlags::APPEND | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+        );
+        let path = if writes {
+            let path = self.writable(&filename).await?;
+            // An existing file is opened through a symlink, which must not
+            // lead out of the root either
+            if tokio::fs::symlink_metadata(&path).await.is_ok() {
+                confined(&self.root, &path).await?
+            } else {
+                path
+            }
+        } else {
+            self.local(&filename).await?
+        };
+        let file = tokio::fs::OpenOptions::new()
+            .read(pflags.contains(OpenFlags::READ))
+            .write(pflags.contains(OpenFlags::WRITE))
+            .append(pflags.contains(OpenFlags::APPEND))
+            .create(pflags.contains(OpenFlags::CREATE))
+            .truncate(pflags.contains(OpenFlags::TRUNCATE))
+            .open(&path)
+            .await
+            .map_err(io_status)?;
+        let handle = self.handle();
+        self.files.insert(handle.clone(), file);
+        if writes && path.extension().is_some_and(|ext| ext == "iso") {
+            self.uploads.insert(handle.clone(), path);
+        }
+        Ok(Handle { id, handle })
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> std::result::Result<Data, Self::Error> {
+        let file = self.files.get_mut(&handle).ok_or(StatusCode::Failure)?;
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(io_status)?;
+        let mut data = vec![0; len as usize];
+        let read = file.read(&mut data).await.map_err(io_status)?;
+        if read == 0 {
+            return Err(StatusCode::Eof);
+        }
+        data.truncate(read);
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> std::result::Result<Status, Self::Error> {
+        let file = self.files.get_mut(&handle).ok_or(StatusCode::Failure)?;
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(io_status)?;
+        file.write_all(&data).await.map_err(io_status)?;
+        Ok(status(id, StatusCode::Ok))
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> std::result::Result<Status, Self::Error> {
+        if let Some(mut file) = self.files.remove(&handle) {
+            file.flush().await.map_err(io_status)?;
+        }
+        if let (Some(path), Some(iso_manager)) = (self.uploads.remove(&handle), &self.iso_manager) {
+            info!("ISO {} uploaded over SFTP", path.display());
+            iso_manager.add_iso(path).await;
+        }
+        self.dirs.remove(&handle);
+        Ok(status(id, StatusCode::Ok))
+    }
+
+    async fn remove(
+        &mut self,
+        id: u32,
+        filename: String,
+    ) -> std::result::Result<Status, Self::Error> {
+        tokio::fs::remove_file(self.writable(&filename).await?)
+            .await
+            .map_err(io_status)?;
+        Ok(status(id, StatusCode::Ok))
+    }
+
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> std::result::Result<Status, Self::Error> {
+        tokio::fs::rename(
+            self.writable(&oldpath).await?,
+            self.writable(&newpath).await?,
+        )
+        .await
+        .map_err(io_status)?;
+        Ok(status(id, StatusCode::Ok))
     }
 }
 
@@ -216,37 +903,129 @@ impl VncServer {
 mod tests {
     use super::*;
 
-    #[tokio::test]
-    async fn test_vnc_server_creation() {
-        let config = VncConfig::default();
-        let server = VncServer::new(config);
-        assert!(!server.is_running().await);
-        assert!(server.get_clients().await.is_empty());
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(""), Ok(NodeCommand::Help));
+        assert_eq!(parse_command("status"), Ok(NodeCommand::Status));
+        assert_eq!(
+            parse_command("logs"),
+            Ok(NodeCommand::Logs(DEFAULT_LOG_LINES))
+        );
+        assert_eq!(parse_command(" logs  20 "), Ok(NodeCommand::Logs(20)));
+        assert_eq!(
+            parse_command("install ubuntu.iso sdb"),
+            Ok(NodeCommand::Install {
+                iso: "ubuntu.iso".to_string(),
+                device: "sdb".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_command("cancel 42"),
+            Ok(NodeCommand::Cancel("42".to_string()))
+        );
+        assert!(parse_command("logs many").is_err());
+        assert!(parse_command("bash").is_err());
+        assert!(parse_command("cancel").is_err());
     }
 
-    #[tokio::test]
-    async fn test_client_management() {
-        let server = VncServer::new(VncConfig::default());
+    #[test]
+    fn test_key_blobs() {
+        let contents = "\
+# technicians
+ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIA alice@laptop
 
-        server.add_client("192.168.1.100".to_string()).await;
-        assert_eq!(server.get_clients().await.len(), 1);
+from=\"10.0.0.0/8\",no-pty ecdsa-sha2-nistp256 AAAAE2VjZHNh bob
+not a key
+";
+        let blobs: Vec<&str> = key_blobs(contents).collect();
+        assert_eq!(blobs, ["AAAAC3NzaC1lZDI1NTE5AAAAIA", "AAAAE2VjZHNh"]);
+    }
 
-        server.add_client("192.168.1.101".to_string()).await;
-        assert_eq!(server.get_clients().await.len(), 2);
+    #[test]
+    fn test_sftp_paths_stay_in_root() {
+        let session = SftpSession::new(PathBuf::from("/installers"));
+        let root = |path: &str| SftpPath::Root(PathBuf::from(path));
+        assert_eq!(
+            session.resolve("/ubuntu.iso"),
+            root("/installers/ubuntu.iso")
+        );
+        assert_eq!(session.resolve("."), root("/installers"));
+        assert_eq!(
+            session.resolve("../../etc/shadow"),
+            root("/installers/etc/shadow")
+        );
+        assert_eq!(
+            session.resolve("win/../../x.iso"),
+            root("/installers/x.iso")
+        );
+        // Without shares `/files` is an ordinary directory
+        assert_eq!(session.resolve("/files/a"), root("/installers/files/a"));
+        assert_eq!(normalize("/.."), PathBuf::new());
+    }
 
-        server.remove_client("192.168.1.100").await;
-        assert_eq!(server.get_clients().await.len(), 1);
+    #[tokio::test]
+    async fn test_sftp_shares_are_read_only() {
+        let session = SftpSession::new(PathBuf::from("/installers")).with_shares(
+            [(
+                "logs".to_string(),
+                PathBuf::from("/var/log/usb-installer-node"),
+            )]
+            .into(),
+        );
+        assert_eq!(session.resolve("/files"), SftpPath::Shares);
+        assert_eq!(
+            session.resolve("/files/logs/serial/rack1.log"),
+            SftpPath::Shared(
+                PathBuf::from("/var/log/usb-installer-node"),
+                PathBuf::from("/var/log/usb-installer-node/serial/rack1.log")
+            )
+        );
+        assert_eq!(
+            session.resolve("/files/logs/../../etc/shadow"),
+            SftpPath::Root(PathBuf::from("/installers/etc/shadow"))
+        );
+        assert_eq!(session.resolve("/files/other/x"), SftpPath::Missing);
+        assert_eq!(
+            session.writable("/files/logs/x.log").await,
+            Err(StatusCode::PermissionDenied)
+        );
     }
 
     #[tokio::test]
-    async fn test_status() {
-        let server = VncServer::new(VncConfig::default());
-        let status = server.get_status().await;
-
-        assert_eq!(status.get("running").unwrap(), "false");
-        assert_eq!(status.get("clients").unwrap(), "0");
-        assert_eq!(status.get("restart_count").unwrap(), "0");
-        assert_eq!(status.get("port").unwrap(), "5900");
-        assert_eq!(status.get("display").unwrap(), ":0");
+    async fn test_sftp_symlinks_stay_in_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("installers");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("ubuntu.iso"), b"iso").unwrap();
+        std::fs::write(dir.path().join("shadow"), b"secret").unwrap();
+        std::os::unix::fs::symlink(dir.path(), root.join("up")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("shadow"), root.join("shadow")).unwrap();
+        let session = SftpSession::new(root.clone());
+        let canonical = root.canonicalize().unwrap();
+
+        assert_eq!(
+            session.local("/ubuntu.iso").await,
+            Ok(canonical.join("ubuntu.iso"))
+        );
+        assert_eq!(session.local("/shadow").await, Err(StatusCode::NoSuchFile));
+        assert_eq!(
+            session.local("/up/shadow").await,
+            Err(StatusCode::NoSuchFile)
+        );
+
+        assert_eq!(
+            session.writable("/new.iso").await,
+            Ok(canonical.join("new.iso"))
+        );
+        assert_eq!(
+            session.writable("/up/new.iso").await,
+            Err(StatusCode::NoSuchFile)
+        );
+        // The link itself, not its target
+        assert_eq!(
+            session.writable("/shadow").await,
+            Ok(canonical.join("shadow"))
+        );
+        assert_eq!(session.writable("/").await, Err(StatusCode::NoSuchFile));
     }
 }
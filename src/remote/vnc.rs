@@ -1,13 +1,16 @@
 use crate::error::{RemoteError, Result};
+use crate::service::supervisor::{ProcessSupervisor, SupervisedProcess, STOP_TIMEOUT};
 use std::{
     collections::HashMap,
-    process::{Child, Command, Stdio},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
 };
 use tokio::{
+    process::Command,
     sync::RwLock,
     task,
     time::{sleep, Duration},
@@ -17,10 +20,16 @@ use tracing::{debug, error, info, warn};
 /// Maximum number of times to attempt restarting the VNC server on crash.
 const MAX_RESTARTS: u32 = 5;
 
-/// Configuration for the x11vnc server.
+/// wayvnc configuration and RSA key, readable by root only.
+const WAYVNC_DIR: &str = "/run/usb-installer-node/wayvnc";
+
+/// Configuration for the x11vnc or wayvnc server.
 #[derive(Debug, Clone)]
 pub struct VncConfig {
     pub display: String,
+    /// Compositor socket; when set, wayvnc shares it instead of x11vnc
+    /// sharing `display`.
+    pub wayland_socket: Option<PathBuf>,
     pub port: u16,
     pub password: Option<String>,
     pub auth_file: Option<std::path::PathBuf>,
@@ -34,6 +43,7 @@ impl Default for VncConfig {
     fn default() -> Self {
         Self {
             display: ":0".to_string(),
+            wayland_socket: None,
             port: 5900,
             password: None,
             auth_file: None,
@@ -45,11 +55,11 @@ impl Default for VncConfig {
     }
 }
 
-/// Manages an x11vnc child process, restarts on crash, tracks clients.
+/// Manages an x11vnc or wayvnc child process, restarts on crash, tracks clients.
 #[derive(Clone)]
 pub struct VncServer {
     config: Arc<RwLock<VncConfig>>,
-    process: Arc<RwLock<Option<Child>>>,
+    process: Arc<RwLock<Option<SupervisedProcess>>>,
     client_count: Arc<AtomicUsize>,
     restart_count: Arc<AtomicUsize>,
     monitor_handle: Arc<RwLock<Option<task::JoinHandle<()>>>>,
@@ -67,7 +77,7 @@ impl VncServer {
         }
     }
 
-    /// Start the x11vnc process and monitoring task.
+    /// Start the VNC process and monitoring task.
     pub async fn start(&self) -> Result<()> {
         if self.is_running().await {
             return Err(RemoteError::AlreadyRunning("VNC server".into()));
@@ -94,56 +104,29 @@ impl VncServer {
         Ok(())
     }
 
-    /// Internal helper to spawn the x11vnc child process.
+    /// Internal helper to spawn the x11vnc or wayvnc child process.
     async fn spawn_process(&self) -> Result<()> {
         let cfg = self.config.read().await;
-        let mut cmd = Command::new("x11vnc");
-        cmd.arg("-display").arg(&cfg.display);
-        cmd.arg("-rfbport").arg(cfg.port.to_string());
-        if let Some(pass) = &cfg.password {
-            cmd.arg("-passwd").arg(pass);
-        } else if let Some(auth) = &cfg.auth_file {
-            cmd.arg("-rfbauth").arg(auth);
-        } else {
-            cmd.arg("-nopw");
-        }
-        if let Some(geom) = &cfg.geometry {
-            cmd.arg("-geometry").arg(geom);
-        }
-        if let Some(d) = cfg.depth {
-            cmd.arg("-depth").arg(d.to_string());
-        }
-        if cfg.allow_shared {
-            cmd.arg("-shared");
-        }
-        if cfg.view_only {
-            cmd.arg("-viewonly");
-        }
-        cmd.arg("-forever").arg("-bg").arg("-noxdamage");
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-        debug!("Executing x11vnc command: {:?}", cmd);
-        let child = cmd
-            .spawn()
-            .map_err(|e| RemoteError::StartFailed(format!("Failed to start x11vnc: {}", e)))?;
-        *self.process.write().await = Some(child);
+        let (name, cmd) = match &cfg.wayland_socket {
+            Some(socket) => ("wayvnc", wayvnc_command(&cfg, socket).await?),
+            None => ("x11vnc", x11vnc_command(&cfg)),
+        };
+        debug!("Executing VNC command: {:?}", cmd);
+        let process = ProcessSupervisor::global()
+            .spawn(name, cmd)
+            .map_err(|e| RemoteError::StartFailed(format!("Failed to start {}: {}", name, e)))?;
+        *self.process.write().await = Some(process);
         Ok(())
     }
 
     /// Monitor the child process, restart on crash up to MAX_RESTARTS.
     async fn monitor(&self) {
         loop {
-            // take ownership of the running child
-            let child_opt = { self.process.write().await.take() };
-            let mut child = match child_opt {
-                Some(c) => c,
+            sleep(Duration::from_secs(1)).await;
+            match &mut *self.process.write().await {
+                Some(process) if process.is_running() => continue,
+                Some(process) => warn!("VNC server {} exited", process.name()),
                 None => break,
-            };
-            // wait for exit without blocking async executor
-            match task::spawn_blocking(move || child.wait()).await {
-                Ok(Ok(status)) => warn!("VNC server exited: {:?}", status),
-                Ok(Err(e)) => error!("Error waiting for VNC server: {}", e),
-                Err(e) => error!("Monitor task failed: {}", e),
             }
             // check bounded restart
             let prev = self.restart_count.fetch_add(1, Ordering::SeqCst);
@@ -163,28 +146,23 @@ impl VncServer {
 
     /// Stop the VNC server and monitoring task.
     pub async fn stop(&self) -> Result<()> {
-        if let Some(mut child) = self.process.write().await.take() {
-            info!("Stopping VNC server");
-            child
-                .kill()
-                .map_err(|e| RemoteError::StopFailed(format!("Failed to kill x11vnc: {}", e)))?;
-        }
         if let Some(handle) = self.monitor_handle.write().await.take() {
             handle.abort();
         }
+        if let Some(mut process) = self.process.write().await.take() {
+            info!("Stopping VNC server");
+            process.stop(STOP_TIMEOUT).await.map_err(|e| {
+                RemoteError::StopFailed(format!("Failed to stop {}: {}", process.name(), e))
+            })?;
+        }
         Ok(())
     }
 
     /// Returns true if the x11vnc process is alive.
     pub async fn is_running(&self) -> bool {
-        if let Some(child) = &mut *self.process.write().await {
-            match child.try_wait() {
-                Ok(Some(_)) => false,
-                Ok(None) => true,
-                Err(_) => false,
-            }
-        } else {
-            false
+        match &mut *self.process.write().await {
+            Some(process) => process.is_running(),
+            None => false,
         }
     }
 
@@ -214,11 +192,115 @@ impl VncServer {
         );
         let cfg = self.config.read().await;
         status.insert("port".to_string(), cfg.port.to_string());
-        status.insert("display".to_string(), cfg.display.clone());
+        match &cfg.wayland_socket {
+            Some(socket) => {
+                status.insert("backend".to_string(), "wayvnc".to_string());
+                status.insert("display".to_string(), socket.display().to_string());
+            }
+            None => {
+                status.insert("backend".to_string(), "x11vnc".to_string());
+                status.insert("display".to_string(), cfg.display.clone());
+            }
+        }
         status
     }
 }
 
+fn x11vnc_command(cfg: &VncConfig) -> Command {
+    let mut cmd = Command::new("x11vnc");
+    cmd.arg("-display").arg(&cfg.display);
+    cmd.arg("-rfbport").arg(cfg.port.to_string());
+    if let Some(pass) = &cfg.password {
+        cmd.arg("-passwd").arg(pass);
+    } else if let Some(auth) = &cfg.auth_file {
+        cmd.arg("-rfbauth").arg(auth);
+    } else {
+        cmd.arg("-nopw");
+    }
+    if let Some(geom) = &cfg.geometry {
+        cmd.arg("-geometry").arg(geom);
+    }
+    if let Some(d) = cfg.depth {
+        cmd.arg("-depth").arg(d.to_string());
+    }
+    if cfg.allow_shared {
+        cmd.arg("-shared");
+    }
+    if cfg.view_only {
+        cmd.arg("-viewonly");
+    }
+    // Stay in the foreground so the monitor sees crashes
+    cmd.arg("-forever").arg("-noxdamage");
+    // Listen on IPv6 in addition to IPv4
+    cmd.arg("-6");
+    cmd
+}
+
+/// wayvnc on the compositor behind `socket`. A password needs an
+/// encrypted connection, so it comes with an RSA key for RSA-AES.
+async fn wayvnc_command(cfg: &VncConfig, socket: &Path) -> Result<Command> {
+    let mut cmd = Command::new("wayvnc");
+    cmd.env("WAYLAND_DISPLAY", socket);
+    if let Some(runtime_dir) = socket.parent() {
+        cmd.env("XDG_RUNTIME_DIR", runtime_dir);
+    }
+    let dir = Path::new(WAYVNC_DIR);
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(|e| RemoteError::VncError(format!("Failed to create {}: {}", WAYVNC_DIR, e)))?;
+    tokio::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .await
+        .map_err(|e| RemoteError::VncError(format!("Failed to protect {}: {}", WAYVNC_DIR, e)))?;
+
+    let mut config = String::new();
+    if let Some(password) = &cfg.password {
+        let key = dir.join("rsa_key.pem");
+        if !key.exists() {
+            generate_rsa_key(&key).await?;
+        }
+        config.push_str(&wayvnc_auth(password, &key));
+    }
+    let config_path = dir.join("config");
+    tokio::fs::write(&config_path, config)
+        .await
+        .map_err(|e| RemoteError::VncError(format!("Failed to write wayvnc config: {}", e)))?;
+    cmd.arg("--config").arg(&config_path);
+
+    if cfg.view_only {
+        cmd.arg("--disable-input");
+    }
+    // `::` also accepts IPv4 unless the kernel is set to v6-only
+    cmd.arg("::").arg(cfg.port.to_string());
+    Ok(cmd)
+}
+
+/// wayvnc config lines enabling password login
+fn wayvnc_auth(password: &str, rsa_key: &Path) -> String {
+    format!(
+        "enable_auth=true\nusername=vnc\npassword={}\nrsa_private_key_file={}\n",
+        password,
+        rsa_key.display()
+    )
+}
+
+async fn generate_rsa_key(path: &Path) -> Result<()> {
+    info!("Generating wayvnc RSA key");
+    let output = tokio::process::Command::new("openssl")
+        .args(["genrsa", "-traditional", "-out"])
+        .arg(path)
+        .arg("2048")
+        .output()
+        .await
+        .map_err(|e| RemoteError::KeyGenerationFailed(e.to_string()))?;
+    if !output.status.success() {
+        return Err(RemoteError::KeyGenerationFailed(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -251,5 +333,24 @@ mod tests {
         assert_eq!(status.get("restart_count").unwrap(), "0");
         assert_eq!(status.get("port").unwrap(), "5900");
         assert_eq!(status.get("display").unwrap(), ":0");
+        assert_eq!(status.get("backend").unwrap(), "x11vnc");
+    }
+
+    #[test]
+    fn test_x11vnc_command() {
+        let cmd = x11vnc_command(&VncConfig::default());
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(cmd.get_program(), "x11vnc");
+        assert!(args.windows(2).any(|w| w[0] == "-display" && w[1] == ":0"));
+        assert!(args.contains(&"-nopw".into()));
+        assert!(!args.contains(&"-bg".into()));
+    }
+
+    #[test]
+    fn test_wayvnc_auth() {
+        let config = wayvnc_auth("s3cret", Path::new("/run/wayvnc/rsa_key.pem"));
+        assert!(config.contains("enable_auth=true\n"));
+        assert!(config.contains("password=s3cret\n"));
+        assert!(config.contains("rsa_private_key_file=/run/wayvnc/rsa_key.pem\n"));
     }
 }
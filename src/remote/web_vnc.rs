@@ -1,19 +1,42 @@
+use crate::auth::bans::{BanList, BanSource};
 use crate::error::{RemoteError, Result};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{header, Request, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use futures_util::{SinkExt, StreamExt};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio::time::{sleep, Duration};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{oneshot, RwLock};
+use tokio::time::Duration;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
 use tracing::{debug, error, info, warn};
 
+/// Largest chunk read from the VNC server per WebSocket frame
+const FRAME_SIZE: usize = 64 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct WebVncConfig {
     pub listen_port: u16,
     pub vnc_host: String,
     pub vnc_port: u16,
+    pub https: bool,
     pub cert_path: Option<PathBuf>,
     pub key_path: Option<PathBuf>,
+    /// noVNC client files served next to the proxy
+    pub assets_dir: PathBuf,
     pub enable_auth: bool,
     pub username: Option<String>,
     pub password: Option<String>,
@@ -26,8 +49,10 @@ impl Default for WebVncConfig {
             listen_port: 6080,
             vnc_host: "localhost".to_string(),
             vnc_port: 5900,
+            https: false,
             cert_path: None,
             key_path: None,
+            assets_dir: PathBuf::from("/usr/share/novnc"),
             enable_auth: false,
             username: None,
             password: None,
@@ -44,96 +69,233 @@ pub struct WebSession {
     pub last_activity: std::time::SystemTime,
 }
 
+/// Credentials checked before a WebVNC session is handed out, and the
+/// tokens the proxy accepts for those sessions. Shared with the API,
+/// which opens sessions after the password and, when enrolled, a TOTP code.
+#[derive(Debug, Clone)]
+pub struct WebVncLogin {
+    username: String,
+    password: String,
+    /// Issued tokens and when they were issued
+    tokens: Arc<Mutex<HashMap<String, Instant>>>,
+    session_timeout: Duration,
+}
+
+impl WebVncLogin {
+    pub fn new(username: String, password: String, session_timeout: Duration) -> Self {
+        Self {
+            username,
+            password,
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+            session_timeout,
+        }
+    }
+
+    pub fn session_timeout(&self) -> Duration {
+        self.session_timeout
+    }
+
+    /// Compare both fields in full so the time taken reveals nothing
+    pub fn check_password(&self, username: &str, password: &str) -> bool {
+        fn same(a: &str, b: &str) -> bool {
+            a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |d, (x, y)| d | (x ^ y)) == 0
+        }
+        same(username, &self.username) & same(password, &self.password)
+    }
+
+    /// Token the proxy accepts until the session times out
+    pub fn issue(&self) -> String {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(token.clone(), Instant::now());
+        token
+    }
+
+    /// Whether `token` was issued and has not timed out
+    fn accepts(&self, token: &str) -> bool {
+        self.tokens
+            .lock()
+            .unwrap()
+            .get(token)
+            .is_some_and(|issued| issued.elapsed() < self.session_timeout)
+    }
+
+    /// Forget tokens older than the session timeout
+    fn expire(&self) {
+        let timeout = self.session_timeout;
+        self.tokens
+            .lock()
+            .unwrap()
+            .retain(|_, issued| issued.elapsed() < timeout);
+    }
+
+    fn clear(&self) {
+        self.tokens.lock().unwrap().clear();
+    }
+
+    fn active_sessions(&self) -> usize {
+        self.tokens.lock().unwrap().len()
+    }
+}
+
+/// Serves the noVNC client and proxies RFB between its WebSocket and the
+/// VNC server, so no websockify is needed on the image
 pub struct WebVncServer {
     config: Arc<RwLock<WebVncConfig>>,
-    process: Arc<RwLock<Option<Child>>>,
     sessions: Arc<RwLock<HashMap<String, WebSession>>>,
     proxy_health: Arc<RwLock<bool>>,
+    login: Option<WebVncLogin>,
+    bans: Option<Arc<BanList>>,
+    shutdown_tx: RwLock<Option<oneshot::Sender<()>>>,
+}
+
+/// What the HTTP handlers share with the server
+#[derive(Clone)]
+struct ProxyState {
+    server: Arc<WebVncServer>,
+    target: String,
+    assets_dir: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConnectQuery {
+    #[serde(default)]
+    token: Option<String>,
 }
 
 impl WebVncServer {
     pub fn new(config: WebVncConfig) -> Self {
         Self {
             config: Arc::new(RwLock::new(config)),
-            process: Arc::new(RwLock::new(None)),
             sessions: Arc::new(RwLock::new(HashMap::new())),
             proxy_health: Arc::new(RwLock::new(false)),
+            login: None,
+            bans: None,
+            shutdown_tx: RwLock::new(None),
         }
     }
 
-    pub async fn start(&self) -> Result<()> {
+    /// Accept only session tokens issued through `login`
+    pub fn with_login(mut self, login: WebVncLogin) -> Self {
+        self.login = Some(login);
+        self
+    }
+
+    /// Refuse banned addresses and report forged tokens
+    pub fn with_bans(mut self, bans: Arc<BanList>) -> Self {
+        self.bans = Some(bans);
+        self
+    }
+
+    pub async fn start(self: &Arc<Self>) -> Result<()> {
         if self.is_running().await {
-            return Err(RemoteError::AlreadyRunning("Web VNC server".to_string()));
+            return Err(
+                RemoteError::WebVncError("Web VNC server already running".to_string()).into(),
+            );
         }
 
         info!("Starting Web VNC server");
 
-        let config = self.config.read().await;
+        let config = self.config.read().await.clone();
 
         if config.enable_auth && (config.username.is_none() || config.password.is_none()) {
-            return Err(RemoteError::ConfigError(
+            return Err(RemoteError::WebVncError(
                 "Authentication enabled but username/password not set".to_string(),
-            ));
+            )
+            .into());
         }
-
-        let mut cmd = Command::new("websockify");
-
-        cmd.arg("--web").arg("/usr/share/novnc");
-
-        if let (Some(cert), Some(key)) = (&config.cert_path, &config.key_path) {
-            cmd.arg("--cert").arg(cert);
-            cmd.arg("--key").arg(key);
-        } else {
-            self.generate_self_signed_cert().await?;
-            cmd.arg("--cert").arg("/tmp/novnc.crt");
-            cmd.arg("--key").arg("/tmp/novnc.key");
-        }
-
         if config.enable_auth {
-            let auth_file = self.create_auth_file().await?;
-            cmd.arg("--auth-plugin").arg("BasicHTTPAuth");
-            cmd.arg("--auth-source").arg(auth_file);
+            // Connections need a token from `WebVncLogin`
+            let login = self.login.as_ref().ok_or_else(|| {
+                RemoteError::WebVncError("Authentication enabled without a login".to_string())
+            })?;
+            login.clear();
         }
 
-        cmd.arg(format!("0.0.0.0:{}", config.listen_port));
-        cmd.arg(format!("{}:{}", config.vnc_host, config.vnc_port));
-
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-
-        debug!("Executing websockify command: {:?}", cmd);
-
-        let child = cmd
-            .spawn()
-            .map_err(|e| RemoteError::StartFailed(format!("Failed to start websockify: {}", e)))?;
-
-        *self.process.write().await = Some(child);
-
-        sleep(Duration::from_secs(1)).await;
+        let tls = if config.https {
+            match (&config.cert_path, &config.key_path) {
+                (Some(cert), Some(key)) => Some(tls_acceptor(cert, key)?),
+                _ => {
+                    return Err(RemoteError::CertificateError(
+                        "HTTPS enabled but cert_path/key_path not set".to_string(),
+                    )
+                    .into())
+                }
+            }
+        } else {
+            None
+        };
 
-        if !self.is_running().await {
-            return Err(RemoteError::StartFailed(
-                "Web VNC server exited immediately".to_string(),
-            ));
+        if !config.assets_dir.join("vnc.html").is_file() {
+            warn!(
+                "noVNC client not found in {}, only the WebSocket proxy is served",
+                config.assets_dir.display()
+            );
         }
 
+        let listener = bind(config.listen_port).await?;
+        let app = Router::new()
+            .route("/websockify", get(websockify))
+            .fallback(serve_asset)
+            .with_state(ProxyState {
+                server: self.clone(),
+                target: format!("{}:{}", config.vnc_host, config.vnc_port),
+                assets_dir: config.assets_dir.clone(),
+            });
+
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let proxy_health = self.proxy_health.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            error!("Web VNC server failed: {}", e);
+                            *proxy_health.write().await = false;
+                            break;
+                        }
+                    },
+                    _ = &mut shutdown_rx => break,
+                };
+                let app = app.clone();
+                let tls = tls.clone();
+                tokio::spawn(async move {
+                    match tls {
+                        Some(tls) => match tls.accept(stream).await {
+                            Ok(stream) => serve_connection(stream, addr, app).await,
+                            Err(e) => debug!("TLS handshake with {} failed: {}", addr, e),
+                        },
+                        None => serve_connection(stream, addr, app).await,
+                    }
+                });
+            }
+        });
+
+        *self.shutdown_tx.write().await = Some(shutdown_tx);
         *self.proxy_health.write().await = true;
         self.start_health_monitor();
 
-        info!("Web VNC server started on port {}", config.listen_port);
+        info!(
+            "Web VNC server listening on port {} ({})",
+            config.listen_port,
+            if config.https { "https" } else { "http" }
+        );
         Ok(())
     }
 
     pub async fn stop(&self) -> Result<()> {
-        if let Some(mut child) = self.process.write().await.take() {
+        if let Some(tx) = self.shutdown_tx.write().await.take() {
             info!("Stopping Web VNC server");
-
-            child
-                .kill()
-                .map_err(|e| RemoteError::StopFailed(format!("Failed to kill process: {}", e)))?;
+            let _ = tx.send(());
 
             self.sessions.write().await.clear();
             *self.proxy_health.write().await = false;
+            if let Some(login) = &self.login {
+                login.clear();
+            }
 
             info!("Web VNC server stopped");
         }
@@ -141,87 +303,31 @@ impl WebVncServer {
     }
 
     pub async fn is_running(&self) -> bool {
-        if let Some(child) = &mut *self.process.write().await {
-            match child.try_wait() {
-                Ok(Some(_)) => false,
-                Ok(None) => true,
-                Err(_) => false,
-            }
-        } else {
-            false
-        }
-    }
-
-    async fn generate_self_signed_cert(&self) -> Result<()> {
-        info!("Generating self-signed certificate for HTTPS");
-
-        let output = Command::new("openssl")
-            .args(&[
-                "req",
-                "-x509",
-                "-nodes",
-                "-days",
-                "365",
-                "-newkey",
-                "rsa:2048",
-                "-keyout",
-                "/tmp/novnc.key",
-                "-out",
-                "/tmp/novnc.crt",
-                "-subj",
-                "/C=US/ST=State/L=City/O=Organization/CN=localhost",
-            ])
-            .output()
-            .map_err(|e| RemoteError::CertGenerationFailed(e.to_string()))?;
-
-        if !output.status.success() {
-            return Err(RemoteError::CertGenerationFailed(
-                String::from_utf8_lossy(&output.stderr).to_string(),
-            ));
-        }
-
-        Ok(())
-    }
-
-    async fn create_auth_file(&self) -> Result<PathBuf> {
-        let config = self.config.read().await;
-        let auth_file = PathBuf::from("/tmp/novnc_auth");
-
-        if let (Some(user), Some(pass)) = (&config.username, &config.password) {
-            let content = format!("{}:{}\n", user, pass);
-            tokio::fs::write(&auth_file, content)
-                .await
-                .map_err(|e| RemoteError::IoError(e.to_string()))?;
-        }
-
-        Ok(auth_file)
+        self.shutdown_tx
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|tx| !tx.is_closed())
     }
 
-    fn start_health_monitor(&self) {
-        let proxy_health = self.proxy_health.clone();
-        let config = self.config.clone();
+    fn start_health_monitor(self: &Arc<Self>) {
+        let server = Arc::downgrade(self);
 
         tokio::spawn(async move {
             loop {
-                sleep(Duration::from_secs(10)).await;
+                tokio::time::sleep(Duration::from_secs(10)).await;
 
-                if !*proxy_health.read().await {
+                let Some(server) = server.upgrade() else {
+                    break;
+                };
+                if !*server.proxy_health.read().await {
                     break;
                 }
 
-                let config = config.read().await;
-                let url = format!("http://localhost:{}/vnc.html", config.listen_port);
-
-                match reqwest::get(&url).await {
-                    Ok(response) => {
-                        if !response.status().is_success() {
-                            warn!("Web VNC health check failed: {}", response.status());
-                        }
-                    }
-                    Err(e) => {
-                        error!("Web VNC health check error: {}", e);
-                    }
+                if let Some(login) = &server.login {
+                    login.expire();
                 }
+                server.cleanup_expired_sessions().await;
             }
         });
     }
@@ -248,6 +354,10 @@ impl WebVncServer {
         }
     }
 
+    pub async fn end_session(&self, session_id: &str) {
+        self.sessions.write().await.remove(session_id);
+    }
+
     pub async fn cleanup_expired_sessions(&self) {
         let config = self.config.read().await;
         let timeout = Duration::from_secs(config.session_timeout);
@@ -278,6 +388,12 @@ impl WebVncServer {
             "sessions".to_string(),
             self.sessions.read().await.len().to_string(),
         );
+        if let Some(login) = &self.login {
+            status.insert(
+                "issued_tokens".to_string(),
+                login.active_sessions().to_string(),
+            );
+        }
 
         let config = self.config.read().await;
         status.insert("listen_port".to_string(), config.listen_port.to_string());
@@ -286,14 +402,200 @@ impl WebVncServer {
             format!("{}:{}", config.vnc_host, config.vnc_port),
         );
         status.insert("auth_enabled".to_string(), config.enable_auth.to_string());
+        status.insert("https".to_string(), config.https.to_string());
 
         status
     }
 }
 
+/// All addresses, IPv6 and IPv4 where available
+async fn bind(port: u16) -> Result<TcpListener> {
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    match TcpListener::bind(addr).await {
+        Ok(listener) => Ok(listener),
+        Err(e) => {
+            warn!("Cannot listen on {} ({}), using IPv4 only", addr, e);
+            let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+            TcpListener::bind(addr).await.map_err(|e| {
+                RemoteError::WebVncError(format!("Failed to listen on {}: {}", addr, e)).into()
+            })
+        }
+    }
+}
+
+fn tls_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor> {
+    let certificate_error = |e: std::io::Error| RemoteError::CertificateError(format!("{}", e));
+
+    let mut cert_file =
+        std::io::BufReader::new(std::fs::File::open(cert_path).map_err(certificate_error)?);
+    let certs = rustls_pemfile::certs(&mut cert_file)
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(certificate_error)?;
+    let mut key_file =
+        std::io::BufReader::new(std::fs::File::open(key_path).map_err(certificate_error)?);
+    let key = rustls_pemfile::private_key(&mut key_file)
+        .map_err(certificate_error)?
+        .ok_or_else(|| {
+            RemoteError::CertificateError(format!("No private key in {}", key_path.display()))
+        })?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| RemoteError::CertificateError(e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// HTTP/1.1 with upgrades, which the WebSocket handshake needs
+async fn serve_connection<S>(stream: S, addr: SocketAddr, app: Router)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(addr));
+        app.clone().oneshot(request)
+    });
+    if let Err(e) = hyper::server::conn::http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .with_upgrades()
+        .await
+    {
+        debug!("Web VNC connection from {} ended: {}", addr, e);
+    }
+}
+
+async fn websockify(
+    State(state): State<ProxyState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<ConnectQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let bans = state.server.bans.as_ref();
+    if let Some(Err(e)) = bans.map(|bans| bans.check(addr.ip())) {
+        warn!("Refused Web VNC connection from {}: {}", addr, e);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if let Some(login) = &state.server.login {
+        if !query.token.as_deref().is_some_and(|t| login.accepts(t)) {
+            warn!("Web VNC connection from {} without a valid token", addr);
+            // A client that never signed in is not guessing
+            if let (Some(bans), Some(_)) = (bans, &query.token) {
+                bans.record_failure(addr.ip(), BanSource::WebVnc).await;
+            }
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    ws.protocols(["binary"])
+        .on_upgrade(move |socket| async move {
+            let session_id = state.server.create_session(addr.to_string()).await;
+            info!("Web VNC session {} opened from {}", session_id, addr);
+            if let Err(e) = proxy(&state, &session_id, socket).await {
+                warn!("Web VNC session {}: {}", session_id, e);
+            }
+            state.server.end_session(&session_id).await;
+            info!("Web VNC session {} closed", session_id);
+        })
+}
+
+/// Copy RFB bytes both ways until either side closes
+async fn proxy(state: &ProxyState, session_id: &str, socket: WebSocket) -> Result<()> {
+    let vnc = TcpStream::connect(&state.target).await.map_err(|e| {
+        RemoteError::WebVncError(format!("Cannot reach VNC server {}: {}", state.target, e))
+    })?;
+    let (mut vnc_read, mut vnc_write) = vnc.into_split();
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    let to_vnc = async {
+        while let Some(message) = ws_rx.next().await {
+            let message = message.map_err(|e| RemoteError::WebVncError(e.to_string()))?;
+            match message {
+                Message::Binary(data) => {
+                    vnc_write
+                        .write_all(&data)
+                        .await
+                        .map_err(|e| RemoteError::WebVncError(e.to_string()))?;
+                    state.server.update_session_activity(session_id).await;
+                }
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        Ok::<_, RemoteError>(())
+    };
+
+    let to_client = async {
+        let mut buffer = vec![0u8; FRAME_SIZE];
+        loop {
+            let read = vnc_read
+                .read(&mut buffer)
+                .await
+                .map_err(|e| RemoteError::WebVncError(e.to_string()))?;
+            if read == 0 {
+                break;
+            }
+            ws_tx
+                .send(Message::Binary(buffer[..read].to_vec()))
+                .await
+                .map_err(|e| RemoteError::WebVncError(e.to_string()))?;
+        }
+        let _ = ws_tx.send(Message::Close(None)).await;
+        Ok::<_, RemoteError>(())
+    };
+
+    tokio::select! {
+        result = to_vnc => result?,
+        result = to_client => result?,
+    }
+    Ok(())
+}
+
+async fn serve_asset(State(state): State<ProxyState>, uri: Uri) -> Response {
+    let path = uri.path().trim_start_matches('/');
+    let path = if path.is_empty() { "vnc.html" } else { path };
+    let Some(file) = asset_path(&state.assets_dir, path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match tokio::fs::read(&file).await {
+        Ok(data) => ([(header::CONTENT_TYPE, content_type(&file))], data).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// File under `root` for a request path; `..` and absolute paths are
+/// refused so nothing outside the client directory is served
+fn asset_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(root.join(relative))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("ogg") | Some("oga") => "audio/ogg",
+        Some("mp3") => "audio/mpeg",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::Error;
 
     #[tokio::test]
     async fn test_web_vnc_server_creation() {
@@ -315,6 +617,9 @@ mod tests {
         let session = server.sessions.read().await.get(&session_id).cloned();
         assert!(session.is_some());
         assert_eq!(session.unwrap().client_address, "192.168.1.100");
+
+        server.end_session(&session_id).await;
+        assert!(server.sessions.read().await.is_empty());
     }
 
     #[tokio::test]
@@ -324,13 +629,58 @@ mod tests {
         config.username = None;
         config.password = None;
 
-        let server = WebVncServer::new(config);
+        let server = Arc::new(WebVncServer::new(config));
         let result = server.start().await;
 
-        assert!(result.is_err());
-        if let Err(RemoteError::ConfigError(_)) = result {
-        } else {
-            panic!("Expected ConfigError");
-        }
+        assert!(matches!(
+            result,
+            Err(Error::Remote(RemoteError::WebVncError(_)))
+        ));
+        assert!(!server.is_running().await);
+    }
+
+    #[test]
+    fn test_login_tokens() {
+        let login = WebVncLogin::new(
+            "tech".to_string(),
+            "s3cret".to_string(),
+            Duration::from_secs(3600),
+        );
+        assert!(login.check_password("tech", "s3cret"));
+        assert!(!login.check_password("tech", "s3cre"));
+        assert!(!login.check_password("admin", "s3cret"));
+
+        let token = login.issue();
+        assert!(login.accepts(&token));
+        assert!(!login.accepts("forged"));
+        assert_eq!(login.active_sessions(), 1);
+
+        login.expire();
+        assert_eq!(login.active_sessions(), 1);
+        login.clear();
+        assert!(!login.accepts(&token));
+
+        let expired = WebVncLogin::new("tech".to_string(), "s3cret".to_string(), Duration::ZERO);
+        let token = expired.issue();
+        assert!(!expired.accepts(&token));
+        expired.expire();
+        assert_eq!(expired.active_sessions(), 0);
+    }
+
+    #[test]
+    fn test_asset_path() {
+        let root = Path::new("/usr/share/novnc");
+        assert_eq!(
+            asset_path(root, "app/ui.js").unwrap(),
+            PathBuf::from("/usr/share/novnc/app/ui.js")
+        );
+        assert!(asset_path(root, "../../etc/shadow").is_none());
+        assert!(asset_path(root, "app/../../secret").is_none());
+        assert!(asset_path(root, "/etc/shadow").is_none());
+        assert_eq!(
+            content_type(Path::new("vnc.html")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(content_type(Path::new("core/rfb.js")), "text/javascript");
     }
 }
@@ -0,0 +1,420 @@
+use crate::job::install::RECORD_KIND as INSTALL_KIND;
+use crate::job::JobRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Jobs that repartition the target, so every success is one wiped device.
+/// Ventoy deployments only copy files.
+const WIPING_JOBS: &[&str] = &["prepare_disk", "windows_usb", INSTALL_KIND];
+
+/// Installs whose image the catalog could not identify
+const UNKNOWN_DISTRO: &str = "unknown";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ReportFormat::Json => "application/json",
+            ReportFormat::Csv => "text/csv; charset=utf-8",
+            ReportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// A job that failed during the shift, for the handover
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedJob {
+    pub kind: String,
+    pub device: String,
+    pub started_at: SystemTime,
+    pub error: Option<String>,
+}
+
+/// What the node did over one operator shift
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShiftReport {
+    pub since: SystemTime,
+    pub until: SystemTime,
+    pub attempted: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Successful wiping jobs. Sticks are swapped on the same device node,
+    /// so this counts jobs rather than distinct device paths.
+    pub devices_wiped: usize,
+    /// ISO file name to the number of jobs that wrote it
+    pub images: BTreeMap<String, usize>,
+    pub bytes_written: u64,
+    /// Over successful jobs that report their size
+    pub average_throughput_bytes_per_sec: Option<u64>,
+    pub failures: Vec<FailedJob>,
+}
+
+impl ShiftReport {
+    /// Summarise `records`, which should all have started in `[since, until)`
+    pub fn build(records: &[JobRecord], since: SystemTime, until: SystemTime) -> Self {
+        let mut report = Self {
+            since,
+            until,
+            attempted: records.len(),
+            succeeded: 0,
+            failed: 0,
+            devices_wiped: 0,
+            images: BTreeMap::new(),
+            bytes_written: 0,
+            average_throughput_bytes_per_sec: None,
+            failures: Vec::new(),
+        };
+        let mut timed_bytes = 0u64;
+        let mut timed_secs = 0f64;
+
+        for record in records {
+            for image in &record.images {
+                let name = Path::new(image)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| image.clone());
+                *report.images.entry(name).or_default() += 1;
+            }
+
+            match record.success {
+                Some(true) => {
+                    report.succeeded += 1;
+                    if WIPING_JOBS.contains(&record.kind.as_str()) {
+                        report.devices_wiped += 1;
+                    }
+                    if let Some(bytes) = record.bytes_written {
+                        report.bytes_written += bytes;
+                        let took = record
+                            .finished_at
+                            .and_then(|f| f.duration_since(record.started_at).ok());
+                        if let Some(took) = took.filter(|t| !t.is_zero()) {
+                            timed_bytes += bytes;
+                            timed_secs += took.as_secs_f64();
+                        }
+                    }
+                }
+                Some(false) => {
+                    report.failed += 1;
+                    report.failures.push(FailedJob {
+                        kind: record.kind.clone(),
+                        device: record.device.clone(),
+                        started_at: record.started_at,
+                        error: record.error.clone(),
+                    });
+                }
+                // Interrupted by a restart; attempted but neither outcome
+                None => {}
+            }
+        }
+
+        if timed_secs > 0.0 {
+            report.average_throughput_bytes_per_sec =
+                Some((timed_bytes as f64 / timed_secs) as u64);
+        }
+        report
+    }
+
+    /// `metric,value` rows, one per figure and one per image
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("metric,value\n");
+        for (metric, value) in self.figures() {
+            let _ = writeln!(csv, "{},{}", metric, csv_field(&value));
+        }
+        for (image, count) in &self.images {
+            let _ = writeln!(csv, "{},{}", csv_field(&format!("image:{}", image)), count);
+        }
+        csv
+    }
+
+    /// Standalone page, suitable for printing or mailing
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Shift report</title>\n</head>\n<body>\n",
+        );
+        let _ = writeln!(
+            html,
+            "<h1>Shift report {} &ndash; {}</h1>",
+            format_time(self.since),
+            format_time(self.until)
+        );
+
+        html.push_str("<table>\n");
+        for (metric, value) in self.figures().into_iter().skip(2) {
+            let _ = writeln!(
+                html,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                metric.replace('_', " "),
+                html_escape(&value)
+            );
+        }
+        html.push_str("</table>\n");
+
+        if !self.images.is_empty() {
+            html.push_str("<h2>Images</h2>\n<table>\n");
+            for (image, count) in &self.images {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td></tr>",
+                    html_escape(image),
+                    count
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        if !self.failures.is_empty() {
+            html.push_str("<h2>Failures</h2>\n<table>\n");
+            html.push_str("<tr><th>Started</th><th>Job</th><th>Device</th><th>Error</th></tr>\n");
+            for failure in &self.failures {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    format_time(failure.started_at),
+                    html_escape(&failure.kind),
+                    html_escape(&failure.device),
+                    html_escape(failure.error.as_deref().unwrap_or(""))
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    fn figures(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("since", format_time(self.since)),
+            ("until", format_time(self.until)),
+            ("attempted", self.attempted.to_string()),
+            ("succeeded", self.succeeded.to_string()),
+            ("failed", self.failed.to_string()),
+            ("devices_wiped", self.devices_wiped.to_string()),
+            ("bytes_written", self.bytes_written.to_string()),
+            (
+                "average_throughput_mb_per_sec",
+                self.average_throughput_bytes_per_sec
+                    .map(|b| format!("{:.1}", b as f64 / 1_000_000.0))
+                    .unwrap_or_default(),
+            ),
+        ]
+    }
+}
+
+/// Outcomes of the installs of one distribution
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DistroStatistics {
+    pub installs: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Over successful installs
+    pub mean_duration_secs: Option<f64>,
+}
+
+/// Install job outcomes, overall and per distribution
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct InstallStatistics {
+    pub installs: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Succeeded out of finished installs, 0 to 1
+    pub success_rate: Option<f64>,
+    pub distros: BTreeMap<String, DistroStatistics>,
+}
+
+impl InstallStatistics {
+    /// Summarise the install records among `records`
+    pub fn build(records: &[JobRecord]) -> Self {
+        let mut stats = Self::default();
+        let mut durations: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        for record in records.iter().filter(|r| r.kind == INSTALL_KIND) {
+            let distro = record
+                .distro
+                .clone()
+                .unwrap_or_else(|| UNKNOWN_DISTRO.to_string());
+            let entry = stats.distros.entry(distro.clone()).or_default();
+            stats.installs += 1;
+            entry.installs += 1;
+            match record.success {
+                Some(true) => {
+                    stats.succeeded += 1;
+                    entry.succeeded += 1;
+                    let took = record
+                        .finished_at
+                        .and_then(|f| f.duration_since(record.started_at).ok());
+                    if let Some(took) = took {
+                        durations
+                            .entry(distro)
+                            .or_default()
+                            .push(took.as_secs_f64());
+                    }
+                }
+                Some(false) => {
+                    stats.failed += 1;
+                    entry.failed += 1;
+                }
+                None => {}
+            }
+        }
+
+        let finished = stats.succeeded + stats.failed;
+        if finished > 0 {
+            stats.success_rate = Some(stats.succeeded as f64 / finished as f64);
+        }
+        for (distro, took) in durations {
+            if let Some(entry) = stats.distros.get_mut(&distro) {
+                entry.mean_duration_secs = Some(took.iter().sum::<f64>() / took.len() as f64);
+            }
+        }
+        stats
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%Y-%m-%d %H:%M:%S UTC")
+        .to_string()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::EnvironmentSnapshot;
+    use std::time::Duration;
+
+    fn record(kind: &str, success: Option<bool>, images: &[&str], bytes: Option<u64>) -> JobRecord {
+        let started_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        JobRecord {
+            id: kind.to_string(),
+            kind: kind.to_string(),
+            device: "/dev/sdb".to_string(),
+            started_at,
+            finished_at: success.map(|_| started_at + Duration::from_secs(100)),
+            success,
+            error: (success == Some(false)).then(|| "write <failed>".to_string()),
+            images: images.iter().map(|i| i.to_string()).collect(),
+            bytes_written: bytes,
+            distro: None,
+            environment: EnvironmentSnapshot {
+                node_version: String::new(),
+                kernel: None,
+                tools: BTreeMap::new(),
+                captured_at: started_at,
+            },
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let records = [
+            record(
+                "windows_usb",
+                Some(true),
+                &["/isos/win11.iso"],
+                Some(600_000_000),
+            ),
+            record("prepare_disk", Some(true), &[], None),
+            record("windows_usb", Some(false), &["/isos/win11.iso"], None),
+            record(
+                "ventoy",
+                Some(true),
+                &["/isos/win11.iso", "/isos/debian.iso"],
+                Some(400_000_000),
+            ),
+            record("prepare_disk", None, &[], None),
+        ];
+        let report = ShiftReport::build(&records, SystemTime::UNIX_EPOCH, SystemTime::now());
+
+        assert_eq!(report.attempted, 5);
+        assert_eq!(report.succeeded, 3);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.devices_wiped, 2);
+        assert_eq!(report.images["win11.iso"], 3);
+        assert_eq!(report.images["debian.iso"], 1);
+        assert_eq!(report.bytes_written, 1_000_000_000);
+        assert_eq!(report.average_throughput_bytes_per_sec, Some(5_000_000));
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].kind, "windows_usb");
+
+        let empty = ShiftReport::build(&[], SystemTime::UNIX_EPOCH, SystemTime::now());
+        assert_eq!(empty.attempted, 0);
+        assert_eq!(empty.average_throughput_bytes_per_sec, None);
+    }
+
+    #[test]
+    fn test_install_statistics() {
+        let install = |distro: Option<&str>, success: Option<bool>| JobRecord {
+            distro: distro.map(str::to_string),
+            ..record(INSTALL_KIND, success, &["/isos/debian.iso"], None)
+        };
+        let records = [
+            install(Some("Debian"), Some(true)),
+            install(Some("Debian"), Some(false)),
+            install(Some("Debian"), Some(true)),
+            install(None, Some(false)),
+            record("prepare_disk", Some(true), &[], None),
+        ];
+        let stats = InstallStatistics::build(&records);
+
+        assert_eq!(stats.installs, 4);
+        assert_eq!(stats.succeeded, 2);
+        assert_eq!(stats.success_rate, Some(0.5));
+        let debian = &stats.distros["Debian"];
+        assert_eq!((debian.installs, debian.failed), (3, 1));
+        assert_eq!(debian.mean_duration_secs, Some(100.0));
+        assert_eq!(stats.distros[UNKNOWN_DISTRO].mean_duration_secs, None);
+
+        assert_eq!(InstallStatistics::build(&[]).success_rate, None);
+    }
+
+    #[test]
+    fn test_render() {
+        let records = [
+            record(
+                "windows_usb",
+                Some(true),
+                &["/isos/a,b.iso"],
+                Some(100_000_000),
+            ),
+            record("windows_usb", Some(false), &[], None),
+        ];
+        let report = ShiftReport::build(&records, SystemTime::UNIX_EPOCH, SystemTime::UNIX_EPOCH);
+
+        let csv = report.to_csv();
+        assert!(csv.starts_with("metric,value\nsince,1970-01-01 00:00:00 UTC\n"));
+        assert!(csv.contains("\nsucceeded,1\n"));
+        assert!(csv.contains("\naverage_throughput_mb_per_sec,1.0\n"));
+        assert!(csv.contains("\n\"image:a,b.iso\",1\n"));
+
+        let html = report.to_html();
+        assert!(html.contains("<tr><th>devices wiped</th><td>1</td></tr>"));
+        assert!(html.contains("<td>write &lt;failed&gt;</td>"));
+    }
+}
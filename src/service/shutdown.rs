@@ -0,0 +1,188 @@
+use super::startup::dependency_waves;
+use crate::config::StartupConfig;
+use crate::error::Result;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+type StopFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// How stopping one subsystem ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopOutcome {
+    Stopped { took_ms: u64 },
+    Failed { error: String },
+    /// Abandoned after its stop timeout so the rest could stop
+    TimedOut,
+}
+
+struct Subsystem {
+    name: &'static str,
+    depends_on: Vec<&'static str>,
+    stop: StopFuture,
+}
+
+/// Subsystems stopped in the reverse of their startup order: each one only
+/// after everything that depends on it has stopped, independent ones in
+/// parallel, each within its own timeout.
+pub struct ShutdownPlan {
+    subsystems: Vec<Subsystem>,
+    timeout: Duration,
+    timeouts: HashMap<String, Duration>,
+}
+
+impl ShutdownPlan {
+    pub fn new() -> Self {
+        Self::from_config(&StartupConfig::default())
+    }
+
+    /// Plan with the configured default and per-subsystem stop timeouts
+    pub fn from_config(config: &StartupConfig) -> Self {
+        Self {
+            subsystems: Vec::new(),
+            timeout: Duration::from_secs(config.stop_timeout_secs),
+            timeouts: config
+                .stop_timeouts
+                .iter()
+                .map(|(name, secs)| (name.clone(), Duration::from_secs(*secs)))
+                .collect(),
+        }
+    }
+
+    /// Override the stop timeout of one subsystem
+    pub fn with_timeout(mut self, name: &str, timeout: Duration) -> Self {
+        self.timeouts.insert(name.to_string(), timeout);
+        self
+    }
+
+    /// Add a subsystem with the same dependencies it was started with
+    pub fn add<F>(mut self, name: &'static str, depends_on: &[&'static str], stop: F) -> Self
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.subsystems.push(Subsystem {
+            name,
+            depends_on: depends_on.to_vec(),
+            stop: Box::pin(stop),
+        });
+        self
+    }
+
+    /// Stop every subsystem. Failures and timeouts are logged and do not
+    /// hold up the others; only an invalid plan fails.
+    pub async fn run(self) -> Result<Vec<(&'static str, StopOutcome)>> {
+        let nodes: Vec<_> = self
+            .subsystems
+            .iter()
+            .map(|s| (s.name, s.depends_on.as_slice()))
+            .collect();
+        let mut waves = dependency_waves(&nodes)?;
+        waves.reverse();
+        info!("Stopping subsystems: {:?}", waves);
+
+        let mut stops: HashMap<&'static str, StopFuture> = self
+            .subsystems
+            .into_iter()
+            .map(|s| (s.name, s.stop))
+            .collect();
+        let mut outcomes = Vec::new();
+        for wave in waves {
+            let mut tasks = Vec::new();
+            for name in wave {
+                let stop = stops.remove(name).expect("every subsystem is in one wave");
+                let timeout = self.timeouts.get(name).copied().unwrap_or(self.timeout);
+                tasks.push((name, tokio::spawn(stop_within(name, stop, timeout))));
+            }
+            for (name, task) in tasks {
+                let outcome = task.await.unwrap_or_else(|e| StopOutcome::Failed {
+                    error: e.to_string(),
+                });
+                outcomes.push((name, outcome));
+            }
+        }
+        Ok(outcomes)
+    }
+}
+
+impl Default for ShutdownPlan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn stop_within(name: &'static str, stop: StopFuture, timeout: Duration) -> StopOutcome {
+    let began = Instant::now();
+    match tokio::time::timeout(timeout, stop).await {
+        Ok(Ok(())) => {
+            info!("{} stopped after {:?}", name, began.elapsed());
+            StopOutcome::Stopped {
+                took_ms: began.elapsed().as_millis() as u64,
+            }
+        }
+        Ok(Err(e)) => {
+            warn!("Error stopping {}: {}", name, e);
+            StopOutcome::Failed {
+                error: e.to_string(),
+            }
+        }
+        Err(_) => {
+            warn!("{} did not stop within {:?}, moving on", name, timeout);
+            StopOutcome::TimedOut
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn record(
+        log: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+    ) -> impl Future<Output = Result<()>> {
+        let log = log.clone();
+        async move {
+            tokio::task::yield_now().await;
+            log.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dependents_stop_first() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let outcomes = ShutdownPlan::new()
+            .add("network", &[], record(&log, "network"))
+            .add("remote", &["network", "iso"], record(&log, "remote"))
+            .add("iso", &["network"], record(&log, "iso"))
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec!["remote", "iso", "network"]);
+        assert!(outcomes
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, StopOutcome::Stopped { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_does_not_block() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let outcomes = ShutdownPlan::new()
+            .with_timeout("remote", Duration::from_millis(20))
+            .add("network", &[], record(&log, "network"))
+            .add("remote", &["network"], async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(outcomes[0], ("remote", StopOutcome::TimedOut));
+        assert_eq!(*log.lock().unwrap(), vec!["network"]);
+    }
+}
@@ -0,0 +1,612 @@
+use crate::config::StartupConfig;
+use crate::error::{Result, ServiceError};
+use crate::events::{AppEvent, EventBus};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, RwLock};
+use tracing::{error, info, warn};
+
+type StartFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// Where bringing up one subsystem stands
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum StartOutcome {
+    /// Waiting for dependencies or still starting
+    Starting,
+    /// Started, taking `took_ms` once its dependencies were ready
+    Ready { took_ms: u64 },
+    /// Missed its startup timeout and is still being started in the
+    /// background; dependents start once it is ready
+    Degraded { reason: String },
+    Failed { error: String },
+    /// Not started because the dependency did not come up
+    Skipped { dependency: String },
+}
+
+/// One subsystem in the startup status
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubsystemStatus {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub outcome: StartOutcome,
+}
+
+/// Live startup state of every subsystem, shared with the REST API. Entries
+/// of degraded subsystems change once their start finishes.
+#[derive(Debug, Clone, Default)]
+pub struct StartupStatus {
+    subsystems: Arc<RwLock<Vec<SubsystemStatus>>>,
+    events: Option<EventBus>,
+}
+
+impl StartupStatus {
+    /// Publish every change of a subsystem's state
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub async fn snapshot(&self) -> Vec<SubsystemStatus> {
+        self.subsystems.read().await.clone()
+    }
+
+    async fn set(&self, name: &'static str, outcome: StartOutcome) {
+        if let Some(events) = &self.events {
+            events.publish(AppEvent::Subsystem(SubsystemStatus {
+                name,
+                outcome: outcome.clone(),
+            }));
+        }
+        let mut subsystems = self.subsystems.write().await;
+        match subsystems.iter_mut().find(|s| s.name == name) {
+            Some(status) => status.outcome = outcome,
+            None => subsystems.push(SubsystemStatus { name, outcome }),
+        }
+    }
+}
+
+struct Subsystem {
+    name: &'static str,
+    depends_on: Vec<&'static str>,
+    required: bool,
+    start: StartFuture,
+}
+
+/// Subsystems and what they depend on. Each one starts as soon as all of
+/// its dependencies are ready, so independent subsystems come up in
+/// parallel and nothing waits on a fixed delay.
+pub struct StartupPlan {
+    subsystems: Vec<Subsystem>,
+    timeout: Duration,
+    timeouts: HashMap<String, Duration>,
+    status: StartupStatus,
+}
+
+impl StartupPlan {
+    pub fn new() -> Self {
+        Self::from_config(&StartupConfig::default())
+    }
+
+    /// Plan with the configured default and per-subsystem timeouts
+    pub fn from_config(config: &StartupConfig) -> Self {
+        Self {
+            subsystems: Vec::new(),
+            timeout: Duration::from_secs(config.timeout_secs),
+            timeouts: config
+                .timeouts
+                .iter()
+                .map(|(name, secs)| (name.clone(), Duration::from_secs(*secs)))
+                .collect(),
+            status: StartupStatus::default(),
+        }
+    }
+
+    /// Override the timeout of one subsystem
+    pub fn with_timeout(mut self, name: &str, timeout: Duration) -> Self {
+        self.timeouts.insert(name.to_string(), timeout);
+        self
+    }
+
+    /// Publish progress to `status`
+    pub fn with_status(mut self, status: StartupStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Add a subsystem whose failure is logged but does not stop the node
+    pub fn add<F>(self, name: &'static str, depends_on: &[&'static str], start: F) -> Self
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.push(name, depends_on, false, Box::pin(start))
+    }
+
+    /// Add a subsystem the node cannot run without
+    pub fn add_required<F>(self, name: &'static str, depends_on: &[&'static str], start: F) -> Self
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.push(name, depends_on, true, Box::pin(start))
+    }
+
+    fn push(
+        mut self,
+        name: &'static str,
+        depends_on: &[&'static str],
+        required: bool,
+        start: StartFuture,
+    ) -> Self {
+        self.subsystems.push(Subsystem {
+            name,
+            depends_on: depends_on.to_vec(),
+            required,
+            start,
+        });
+        self
+    }
+
+    /// Subsystems grouped into waves: each wave only depends on earlier ones.
+    /// Fails on duplicate names, unknown dependencies and cycles.
+    pub fn waves(&self) -> Result<Vec<Vec<&'static str>>> {
+        let nodes: Vec<_> = self
+            .subsystems
+            .iter()
+            .map(|s| (s.name, s.depends_on.as_slice()))
+            .collect();
+        dependency_waves(&nodes)
+    }
+
+    /// Start every subsystem, each gated on the readiness of its
+    /// dependencies. A subsystem that misses its timeout is reported as
+    /// degraded and does not hold up the rest; its dependents are reported
+    /// as starting and come up in the background once it is ready. Fails if a required
+    /// subsystem failed or was skipped; the others have still been started
+    /// and need the usual shutdown.
+    pub async fn run(self) -> Result<StartupReport> {
+        let waves = self.waves()?;
+        info!("Starting subsystems: {:?}", waves);
+
+        let mut ready_tx = HashMap::new();
+        let mut ready_rx = HashMap::new();
+        for subsystem in &self.subsystems {
+            let (tx, rx) = watch::channel(Readiness::Pending);
+            ready_tx.insert(subsystem.name, tx);
+            ready_rx.insert(subsystem.name, rx);
+            self.status.set(subsystem.name, StartOutcome::Starting).await;
+        }
+
+        let began = Instant::now();
+        let mut tasks = Vec::new();
+        for subsystem in self.subsystems {
+            let dependencies: Vec<_> = subsystem
+                .depends_on
+                .iter()
+                .map(|dep| (*dep, ready_rx[dep].clone()))
+                .collect();
+            let gate = Gate {
+                name: subsystem.name,
+                dependencies,
+                ready: ready_tx
+                    .remove(subsystem.name)
+                    .expect("every subsystem has a readiness channel"),
+                timeout: self
+                    .timeouts
+                    .get(subsystem.name)
+                    .copied()
+                    .unwrap_or(self.timeout),
+                status: self.status.clone(),
+            };
+            let task = tokio::spawn(gate.start(subsystem.start));
+            tasks.push((subsystem.name, subsystem.required, task));
+        }
+
+        let mut report = StartupReport {
+            subsystems: Vec::new(),
+            elapsed: Duration::ZERO,
+        };
+        let mut missing = Vec::new();
+        for (name, required, task) in tasks {
+            let outcome = task.await.unwrap_or_else(|e| StartOutcome::Failed {
+                error: e.to_string(),
+            });
+            if required
+                && matches!(
+                    outcome,
+                    StartOutcome::Failed { .. } | StartOutcome::Skipped { .. }
+                )
+            {
+                missing.push(name);
+            }
+            report.subsystems.push(SubsystemStatus { name, outcome });
+        }
+        report.elapsed = began.elapsed();
+        info!("Subsystems started in {:?}", report.elapsed);
+
+        if !missing.is_empty() {
+            return Err(ServiceError::StartFailed(format!(
+                "required subsystems did not start: {}",
+                missing.join(", ")
+            ))
+            .into());
+        }
+        Ok(report)
+    }
+}
+
+impl Default for StartupPlan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where a subsystem stands, as seen by its dependents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Readiness {
+    Pending,
+    /// Past its timeout and still starting in the background
+    Degraded,
+    Ready,
+    /// Failed, skipped or gone
+    Down,
+}
+
+/// What one subsystem waits for and where it reports
+struct Gate {
+    name: &'static str,
+    dependencies: Vec<(&'static str, watch::Receiver<Readiness>)>,
+    ready: watch::Sender<Readiness>,
+    timeout: Duration,
+    status: StartupStatus,
+}
+
+impl Gate {
+    /// Wait for the dependencies, then start within the timeout. While a
+    /// dependency is degraded, the start waits for it in the background
+    /// and is reported as starting.
+    async fn start(mut self, future: StartFuture) -> StartOutcome {
+        match self.wait_for_dependencies(false).await {
+            Err(outcome) => outcome,
+            Ok(true) => self.start_now(future).await,
+            Ok(false) => {
+                info!("{} waits for degraded dependencies", self.name);
+                let _ = self.ready.send(Readiness::Degraded);
+                tokio::spawn(async move {
+                    if self.wait_for_dependencies(true).await.is_ok() {
+                        self.start_now(future).await;
+                    }
+                });
+                StartOutcome::Starting
+            }
+        }
+    }
+
+    /// `Ok(true)` once every dependency is ready, `Ok(false)` when one is
+    /// degraded and `through_degraded` is not set. A dependency that is
+    /// down, or whose sender was dropped by a panicked task, skips this
+    /// subsystem.
+    async fn wait_for_dependencies(
+        &mut self,
+        through_degraded: bool,
+    ) -> std::result::Result<bool, StartOutcome> {
+        let mut all_ready = true;
+        let mut down = None;
+        for (dep, rx) in &mut self.dependencies {
+            let state = rx
+                .wait_for(|state| match state {
+                    Readiness::Pending => false,
+                    Readiness::Degraded => !through_degraded,
+                    Readiness::Ready | Readiness::Down => true,
+                })
+                .await
+                .map(|state| *state)
+                .unwrap_or(Readiness::Down);
+            match state {
+                Readiness::Ready => {}
+                Readiness::Degraded => all_ready = false,
+                Readiness::Pending | Readiness::Down => {
+                    down = Some(*dep);
+                    break;
+                }
+            }
+        }
+
+        let Some(dep) = down else {
+            return Ok(all_ready);
+        };
+        let outcome = StartOutcome::Skipped {
+            dependency: dep.to_string(),
+        };
+        warn!("Not starting {}: {} is not up", self.name, dep);
+        let _ = self.ready.send(Readiness::Down);
+        self.status.set(self.name, outcome.clone()).await;
+        Err(outcome)
+    }
+
+    /// Start with the dependencies ready. On timeout the start goes on in
+    /// the background, and dependents follow once it is ready.
+    async fn start_now(self, mut future: StartFuture) -> StartOutcome {
+        let began = Instant::now();
+        let outcome = match tokio::time::timeout(self.timeout, &mut future).await {
+            Ok(result) => finished(self.name, result, began),
+            Err(_) => {
+                let reason = format!("not ready after {:?}", self.timeout);
+                warn!("{} is degraded: {}", self.name, reason);
+                let _ = self.ready.send(Readiness::Degraded);
+                self.status
+                    .set(self.name, StartOutcome::Degraded { reason: reason.clone() })
+                    .await;
+
+                tokio::spawn(async move {
+                    let outcome = finished(self.name, future.await, began);
+                    self.report(outcome).await;
+                });
+                return StartOutcome::Degraded { reason };
+            }
+        };
+        self.report(outcome.clone()).await;
+        outcome
+    }
+
+    async fn report(&self, outcome: StartOutcome) {
+        let readiness = match outcome {
+            StartOutcome::Ready { .. } => Readiness::Ready,
+            _ => Readiness::Down,
+        };
+        let _ = self.ready.send(readiness);
+        self.status.set(self.name, outcome).await;
+    }
+}
+
+fn finished(name: &str, result: Result<()>, began: Instant) -> StartOutcome {
+    match result {
+        Ok(()) => {
+            info!("{} ready after {:?}", name, began.elapsed());
+            StartOutcome::Ready {
+                took_ms: began.elapsed().as_millis() as u64,
+            }
+        }
+        Err(e) => {
+            error!("Failed to start {}: {}", name, e);
+            StartOutcome::Failed {
+                error: e.to_string(),
+            }
+        }
+    }
+}
+
+/// Outcome of every subsystem, in declaration order
+#[derive(Debug, Clone)]
+pub struct StartupReport {
+    pub subsystems: Vec<SubsystemStatus>,
+    pub elapsed: Duration,
+}
+
+impl StartupReport {
+    pub fn outcome(&self, name: &str) -> Option<&StartOutcome> {
+        self.subsystems
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| &s.outcome)
+    }
+
+    pub fn is_ready(&self, name: &str) -> bool {
+        matches!(self.outcome(name), Some(StartOutcome::Ready { .. }))
+    }
+}
+
+/// Names grouped into waves, each depending only on earlier ones. Fails on
+/// duplicate names, unknown dependencies and cycles. Shared with the
+/// shutdown plan, which runs the waves backwards.
+pub(super) fn dependency_waves(
+    nodes: &[(&'static str, &[&'static str])],
+) -> Result<Vec<Vec<&'static str>>> {
+    let mut names = HashSet::new();
+    for (name, _) in nodes {
+        if !names.insert(*name) {
+            return Err(invalid(format!("{} is declared twice", name)));
+        }
+    }
+    for (name, depends_on) in nodes {
+        if let Some(dep) = depends_on.iter().find(|d| !names.contains(*d)) {
+            return Err(invalid(format!(
+                "{} depends on unknown subsystem {}",
+                name, dep
+            )));
+        }
+    }
+
+    let mut started: HashSet<&str> = HashSet::new();
+    let mut waves = Vec::new();
+    while started.len() < nodes.len() {
+        let wave: Vec<&'static str> = nodes
+            .iter()
+            .filter(|(name, _)| !started.contains(name))
+            .filter(|(_, depends_on)| depends_on.iter().all(|d| started.contains(d)))
+            .map(|(name, _)| *name)
+            .collect();
+        if wave.is_empty() {
+            let mut cycle: Vec<&str> = nodes
+                .iter()
+                .map(|(name, _)| *name)
+                .filter(|n| !started.contains(n))
+                .collect();
+            cycle.sort_unstable();
+            return Err(invalid(format!(
+                "dependency cycle between {}",
+                cycle.join(", ")
+            )));
+        }
+        started.extend(&wave);
+        waves.push(wave);
+    }
+    Ok(waves)
+}
+
+fn invalid(msg: String) -> crate::error::Error {
+    ServiceError::InvalidConfig(format!("startup plan: {}", msg)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::sync::{Arc, Mutex};
+
+    fn record(
+        log: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+    ) -> impl Future<Output = Result<()>> {
+        let log = log.clone();
+        async move {
+            tokio::task::yield_now().await;
+            log.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_waves() {
+        let plan = StartupPlan::new()
+            .add("ui", &[], async { Ok(()) })
+            .add("remote", &["network"], async { Ok(()) })
+            .add_required("network", &[], async { Ok(()) })
+            .add("iso", &["network"], async { Ok(()) });
+        assert_eq!(
+            plan.waves().unwrap(),
+            vec![vec!["ui", "network"], vec!["remote", "iso"]]
+        );
+
+        let cycle = StartupPlan::new()
+            .add("a", &["b"], async { Ok(()) })
+            .add("b", &["a"], async { Ok(()) });
+        assert!(cycle
+            .waves()
+            .unwrap_err()
+            .to_string()
+            .contains("cycle between a, b"));
+
+        let unknown = StartupPlan::new().add("a", &["missing"], async { Ok(()) });
+        assert!(unknown.waves().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dependencies_start_first() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let report = StartupPlan::new()
+            .add("iso", &["network"], record(&log, "iso"))
+            .add("remote", &["network", "iso"], record(&log, "remote"))
+            .add_required("network", &[], record(&log, "network"))
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec!["network", "iso", "remote"]);
+        assert!(report.is_ready("remote"));
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        let report = StartupPlan::new()
+            .add("network", &[], async {
+                Err(Error::General("no link".to_string()))
+            })
+            .add("remote", &["network"], async { Ok(()) })
+            .add("ui", &[], async { Ok(()) })
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(
+            report.outcome("network"),
+            Some(&StartOutcome::Failed {
+                error: "General error: no link".to_string()
+            })
+        );
+        assert_eq!(
+            report.outcome("remote"),
+            Some(&StartOutcome::Skipped {
+                dependency: "network".to_string()
+            })
+        );
+        assert!(report.is_ready("ui"));
+
+        let required = StartupPlan::new()
+            .add_required("network", &[], async {
+                Err(Error::General("no link".to_string()))
+            })
+            .run()
+            .await;
+        assert!(required.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_degrades() {
+        let status = StartupStatus::default();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let report = StartupPlan::new()
+            .with_timeout("network", Duration::from_secs(20))
+            .with_status(status.clone())
+            .add_required("network", &[], async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .add("remote", &["network"], record(&log, "remote"))
+            .add("pxe", &["remote"], record(&log, "pxe"))
+            .add("ui", &[], async { Ok(()) })
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.outcome("network"),
+            Some(&StartOutcome::Degraded {
+                reason: "not ready after 20s".to_string()
+            })
+        );
+        assert_eq!(report.outcome("remote"), Some(&StartOutcome::Starting));
+        assert_eq!(report.outcome("pxe"), Some(&StartOutcome::Starting));
+        assert!(report.is_ready("ui"));
+        assert!(log.lock().unwrap().is_empty());
+
+        // The slow start completes later, and its dependents follow
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(*log.lock().unwrap(), vec!["remote", "pxe"]);
+        for subsystem in status.snapshot().await {
+            assert!(
+                matches!(subsystem.outcome, StartOutcome::Ready { .. }),
+                "{} is not ready",
+                subsystem.name
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_degraded_then_failed_skips_dependents() {
+        let status = StartupStatus::default();
+        let report = StartupPlan::new()
+            .with_timeout("network", Duration::from_secs(20))
+            .with_status(status.clone())
+            .add("network", &[], async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Err(Error::General("no lease".to_string()))
+            })
+            .add("remote", &["network"], async { Ok(()) })
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(report.outcome("remote"), Some(&StartOutcome::Starting));
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let remote = status.snapshot().await.remove(1);
+        assert_eq!(
+            remote.outcome,
+            StartOutcome::Skipped {
+                dependency: "network".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdout, Command};
+use tracing::{debug, warn};
+
+/// Time a process gets to exit after SIGTERM before it is killed
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Clock ticks per second in `/proc/<pid>/stat`, fixed for user space on
+/// Linux
+const USER_HZ: f64 = 100.0;
+
+/// A child process that is still running
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessInfo {
+    pub name: String,
+    pub pid: u32,
+    pub started_at: SystemTime,
+    /// From `/proc`, `None` when it could not be read
+    pub resident_bytes: Option<u64>,
+    pub cpu_seconds: Option<f64>,
+}
+
+/// How the processes started under one name ended
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProcessCounters {
+    pub started: u64,
+    /// Exited on their own with status 0
+    pub exited: u64,
+    /// Exited on their own with another status or on a signal
+    pub failed: u64,
+    /// Exited after SIGTERM
+    pub stopped: u64,
+    /// Needed SIGKILL, or were dropped while running
+    pub killed: u64,
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    running: HashMap<u32, (String, SystemTime)>,
+    counters: BTreeMap<String, ProcessCounters>,
+}
+
+/// Starts the external programs the node keeps running or waits on, such
+/// as VNC servers, Xvfb, tunnels, dhclient and installers. It keeps their
+/// PIDs, logs their output, stops them with SIGTERM and then SIGKILL, and
+/// reaps them; a process dropped while running is killed and reaped in
+/// the background.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessSupervisor {
+    table: Arc<Mutex<Table>>,
+}
+
+impl ProcessSupervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The supervisor shared by the whole node
+    pub fn global() -> &'static ProcessSupervisor {
+        static SUPERVISOR: OnceLock<ProcessSupervisor> = OnceLock::new();
+        SUPERVISOR.get_or_init(ProcessSupervisor::new)
+    }
+
+    /// Start `cmd` as `name` with its output forwarded to the log
+    pub fn spawn(&self, name: &str, cmd: Command) -> io::Result<SupervisedProcess> {
+        let mut process = self.spawn_piped(name, cmd)?;
+        process.log_output();
+        Ok(process)
+    }
+
+    /// Start `cmd` as `name` with stdout and stderr piped for the caller.
+    /// Whatever it does not take can still go to the log with
+    /// [`SupervisedProcess::log_output`].
+    pub fn spawn_piped(&self, name: &str, mut cmd: Command) -> io::Result<SupervisedProcess> {
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let child = cmd.spawn()?;
+        // Only unknown once the child was waited on
+        let pid = child.id().unwrap_or_default();
+        debug!("Started {} (pid {})", name, pid);
+
+        let mut table = self.table.lock().unwrap();
+        table
+            .running
+            .insert(pid, (name.to_string(), SystemTime::now()));
+        table.counters.entry(name.to_string()).or_default().started += 1;
+        Ok(SupervisedProcess {
+            name: name.to_string(),
+            pid,
+            child,
+            supervisor: self.clone(),
+            reaped: false,
+        })
+    }
+
+    /// Running processes with their memory and CPU use, oldest first
+    pub fn processes(&self) -> Vec<ProcessInfo> {
+        let running: Vec<_> = self
+            .table
+            .lock()
+            .unwrap()
+            .running
+            .iter()
+            .map(|(pid, (name, started_at))| (*pid, name.clone(), *started_at))
+            .collect();
+        let mut processes: Vec<ProcessInfo> = running
+            .into_iter()
+            .map(|(pid, name, started_at)| ProcessInfo {
+                name,
+                pid,
+                started_at,
+                resident_bytes: resident_bytes(pid),
+                cpu_seconds: cpu_seconds(pid),
+            })
+            .collect();
+        processes.sort_by_key(|process| process.started_at);
+        processes
+    }
+
+    /// Counters by process name
+    pub fn counters(&self) -> BTreeMap<String, ProcessCounters> {
+        self.table.lock().unwrap().counters.clone()
+    }
+
+    fn record_exit(&self, pid: u32, name: &str, exit: Exit) {
+        let mut table = self.table.lock().unwrap();
+        table.running.remove(&pid);
+        let counters = table.counters.entry(name.to_string()).or_default();
+        match exit {
+            Exit::Own(status) if status.success() => counters.exited += 1,
+            Exit::Own(_) => counters.failed += 1,
+            Exit::Stopped => counters.stopped += 1,
+            Exit::Killed => counters.killed += 1,
+        }
+    }
+}
+
+enum Exit {
+    Own(ExitStatus),
+    Stopped,
+    Killed,
+}
+
+/// A process started by the [`ProcessSupervisor`]. Dropping it while the
+/// process runs kills it.
+#[derive(Debug)]
+pub struct SupervisedProcess {
+    name: String,
+    pid: u32,
+    child: Child,
+    supervisor: ProcessSupervisor,
+    reaped: bool,
+}
+
+impl SupervisedProcess {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.child.stdout.take()
+    }
+
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.child.stderr.take()
+    }
+
+    /// Forward the output streams not taken by the caller to the log
+    pub fn log_output(&mut self) {
+        let source = format!("{}[{}]", self.name, self.pid);
+        if let Some(stdout) = self.child.stdout.take() {
+            tokio::spawn(log_lines(source.clone(), stdout));
+        }
+        if let Some(stderr) = self.child.stderr.take() {
+            tokio::spawn(log_lines(source, stderr));
+        }
+    }
+
+    /// Whether the process still runs; one that exited is reaped
+    pub fn is_running(&mut self) -> bool {
+        match self.child.try_wait() {
+            Ok(None) => true,
+            Ok(Some(status)) => {
+                self.reap(Exit::Own(status));
+                false
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Wait for the process to exit on its own
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait().await?;
+        self.reap(Exit::Own(status));
+        Ok(status)
+    }
+
+    /// Send SIGTERM and wait up to `timeout` for the process to exit, then
+    /// kill it
+    pub async fn stop(&mut self, timeout: Duration) -> io::Result<ExitStatus> {
+        if let Some(status) = self.child.try_wait()? {
+            self.reap(Exit::Own(status));
+            return Ok(status);
+        }
+
+        debug!("Stopping {} (pid {})", self.name, self.pid);
+        if let Err(e) = kill(Pid::from_raw(self.pid as i32), Signal::SIGTERM) {
+            debug!("Failed to signal {}: {}", self.name, e);
+        }
+        if let Ok(status) = tokio::time::timeout(timeout, self.child.wait()).await {
+            let status = status?;
+            self.reap(Exit::Stopped);
+            return Ok(status);
+        }
+
+        warn!(
+            "{} (pid {}) did not exit within {:?} of SIGTERM, killing it",
+            self.name, self.pid, timeout
+        );
+        self.child.start_kill()?;
+        let status = self.child.wait().await?;
+        self.reap(Exit::Killed);
+        Ok(status)
+    }
+
+    fn reap(&mut self, exit: Exit) {
+        if !self.reaped {
+            self.reaped = true;
+            self.supervisor.record_exit(self.pid, &self.name, exit);
+        }
+    }
+}
+
+impl Drop for SupervisedProcess {
+    fn drop(&mut self) {
+        if !self.reaped {
+            let exit = match self.child.try_wait() {
+                Ok(Some(status)) => Exit::Own(status),
+                // kill_on_drop kills it, and tokio reaps it
+                _ => Exit::Killed,
+            };
+            self.reap(exit);
+        }
+    }
+}
+
+async fn log_lines(source: String, stream: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!("{}: {}", source, line);
+    }
+}
+
+fn resident_bytes(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+fn cpu_seconds(pid: u32) -> Option<f64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_cpu_ticks(&stat).map(|ticks| ticks as f64 / USER_HZ)
+}
+
+/// User plus system time from `/proc/<pid>/stat`. The command name in
+/// parentheses may contain spaces, so fields are counted after it.
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    let mut fields = rest.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(utime + stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    #[tokio::test]
+    async fn test_stop_and_kill() {
+        let supervisor = ProcessSupervisor::new();
+        let mut polite = supervisor.spawn("polite", sh("sleep 30")).unwrap();
+        let mut stubborn = supervisor
+            .spawn("stubborn", sh("trap '' TERM; sleep 30"))
+            .unwrap();
+        // Give the shell time to install its trap
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(supervisor.processes().len(), 2);
+
+        polite.stop(Duration::from_secs(5)).await.unwrap();
+        stubborn.stop(Duration::from_millis(100)).await.unwrap();
+        assert!(supervisor.processes().is_empty());
+
+        let counters = supervisor.counters();
+        assert_eq!(counters["polite"].stopped, 1);
+        assert_eq!(counters["stubborn"].killed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_exit_counted_once() {
+        let supervisor = ProcessSupervisor::new();
+        let mut failing = supervisor.spawn("failing", sh("exit 3")).unwrap();
+        assert_eq!(failing.wait().await.unwrap().code(), Some(3));
+        assert!(!failing.is_running());
+        drop(failing);
+
+        let mut fine = supervisor.spawn("fine", sh("true")).unwrap();
+        fine.wait().await.unwrap();
+
+        let counters = supervisor.counters();
+        assert_eq!(counters["failing"].failed, 1);
+        assert_eq!(counters["failing"].killed, 0);
+        assert_eq!(counters["fine"].exited, 1);
+    }
+
+    #[test]
+    fn test_parse_cpu_ticks() {
+        let stat = "42 (x11 vnc) S 1 42 42 0 -1 4194560 500 0 0 0 120 30 0 0 20 0 1 0";
+        assert_eq!(parse_cpu_ticks(stat), Some(150));
+        assert_eq!(parse_cpu_ticks("42 (sleep"), None);
+    }
+}
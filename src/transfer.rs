@@ -0,0 +1,348 @@
+use crate::config::TransferConfig;
+use crate::error::{Result, TransferError};
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, warn};
+
+/// Entries listed per share, so a huge log directory cannot stall the API
+const MAX_LISTED: usize = 1000;
+/// Directory levels listed below a share
+const MAX_DEPTH: usize = 4;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A file operators may download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SharedFile {
+    pub share: String,
+    /// Relative to the share; empty when the share is the file itself
+    pub path: String,
+    pub size: u64,
+    /// Unix seconds
+    pub modified: Option<u64>,
+}
+
+/// ISO uploads into the download directory and downloads from the
+/// configured shares
+pub struct FileTransfer {
+    config: TransferConfig,
+}
+
+impl FileTransfer {
+    pub fn new(config: TransferConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn max_upload_bytes(&self) -> u64 {
+        self.config.max_upload_mb.saturating_mul(1024 * 1024)
+    }
+
+    /// Share names and paths, none while transfers are disabled
+    pub fn shares(&self) -> BTreeMap<String, PathBuf> {
+        if self.config.enabled {
+            self.config.shares.clone()
+        } else {
+            BTreeMap::new()
+        }
+    }
+
+    /// Files in every share; missing shares are skipped
+    pub async fn list(&self) -> Vec<SharedFile> {
+        let mut files = Vec::new();
+        for (share, root) in &self.shares() {
+            let mut listed = Vec::new();
+            let mut pending = vec![(root.clone(), 0)];
+            while let Some((path, depth)) = pending.pop() {
+                // Links below the share may lead out of it and are skipped,
+                // as `resolve` refuses them
+                let metadata = if depth == 0 {
+                    tokio::fs::metadata(&path).await
+                } else {
+                    tokio::fs::symlink_metadata(&path).await
+                };
+                let Ok(metadata) = metadata else {
+                    continue;
+                };
+                if metadata.is_file() {
+                    listed.push(SharedFile {
+                        share: share.clone(),
+                        path: relative(root, &path),
+                        size: metadata.len(),
+                        modified: metadata
+                            .modified()
+                            .ok()
+                            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                            .map(|age| age.as_secs()),
+                    });
+                } else if metadata.is_dir() && depth < MAX_DEPTH {
+                    let Ok(mut entries) = tokio::fs::read_dir(&path).await else {
+                        continue;
+                    };
+                    while let Ok(Some(entry)) = entries.next_entry().await {
+                        pending.push((entry.path(), depth + 1));
+                    }
+                }
+                if listed.len() >= MAX_LISTED {
+                    debug!("Listing of share {} truncated", share);
+                    break;
+                }
+            }
+            listed.sort_by(|a, b| a.path.cmp(&b.path));
+            files.extend(listed);
+        }
+        files
+    }
+
+    /// File `path` names in `share`. Paths may not leave the share, also
+    /// not through symlinks.
+    pub async fn resolve(&self, share: &str, path: &str) -> Result<PathBuf> {
+        let shown = format!("{}/{}", share, path);
+        let root = self
+            .shares()
+            .remove(share)
+            .ok_or_else(|| TransferError::NotFound(shown.clone()))?;
+        let relative = Path::new(path);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(TransferError::InvalidName(path.to_string()).into());
+        }
+
+        let not_found = || TransferError::NotFound(shown.clone());
+        let root = tokio::fs::canonicalize(&root)
+            .await
+            .map_err(|_| not_found())?;
+        let file = tokio::fs::canonicalize(root.join(relative))
+            .await
+            .map_err(|_| not_found())?;
+        let is_file = tokio::fs::metadata(&file)
+            .await
+            .map(|metadata| metadata.is_file())
+            .unwrap_or(false);
+        if !file.starts_with(&root) || !is_file {
+            return Err(not_found().into());
+        }
+        Ok(file)
+    }
+
+    /// Write an upload to `dest`, through a hidden `.part` file that is
+    /// renamed once complete so scans never see half an ISO
+    pub async fn receive<S, B, E>(&self, body: S, dest: &Path, overwrite: bool) -> Result<u64>
+    where
+        S: Stream<Item = std::result::Result<B, E>> + Unpin,
+        B: AsRef<[u8]>,
+        E: Display,
+    {
+        let name = dest
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !overwrite && tokio::fs::try_exists(dest).await.unwrap_or(false) {
+            return Err(TransferError::AlreadyExists(name).into());
+        }
+        let part = dest.with_file_name(format!(".{}.part", name));
+        let received = match self.write_part(body, &part).await {
+            Ok(received) => received,
+            Err(e) => {
+                if let Err(e) = tokio::fs::remove_file(&part).await {
+                    warn!("Failed to remove {}: {}", part.display(), e);
+                }
+                return Err(e);
+            }
+        };
+        tokio::fs::rename(&part, dest)
+            .await
+            .map_err(|e| TransferError::IoError(format!("{}: {}", dest.display(), e)))?;
+        Ok(received)
+    }
+
+    async fn write_part<S, B, E>(&self, mut body: S, part: &Path) -> Result<u64>
+    where
+        S: Stream<Item = std::result::Result<B, E>> + Unpin,
+        B: AsRef<[u8]>,
+        E: Display,
+    {
+        let io_error =
+            |e: std::io::Error| TransferError::IoError(format!("{}: {}", part.display(), e));
+        let mut file = tokio::fs::File::create(part).await.map_err(io_error)?;
+        let limit = self.max_upload_bytes();
+        let mut received = 0u64;
+        while let Some(chunk) = body.next().await {
+            let chunk =
+                chunk.map_err(|e| TransferError::IoError(format!("Upload broke off: {}", e)))?;
+            let chunk = chunk.as_ref();
+            received += chunk.len() as u64;
+            if received > limit {
+                return Err(TransferError::TooLarge(self.config.max_upload_mb).into());
+            }
+            file.write_all(chunk).await.map_err(io_error)?;
+        }
+        file.sync_all().await.map_err(io_error)?;
+        Ok(received)
+    }
+}
+
+/// `name` if it is a plain `.iso` file name, nothing that could land
+/// outside the upload directory or be hidden from scans
+pub fn iso_file_name(name: &str) -> Result<&str> {
+    let plain = matches!(
+        Path::new(name).components().collect::<Vec<_>>().as_slice(),
+        [Component::Normal(_)]
+    );
+    if !plain || name.starts_with('.') || !name.to_ascii_lowercase().ends_with(".iso") {
+        return Err(TransferError::InvalidName(name.to_string()).into());
+    }
+    Ok(name)
+}
+
+/// A file read in chunks, for streaming responses
+pub fn read_chunks(
+    file: tokio::fs::File,
+) -> impl Stream<Item = std::io::Result<Vec<u8>>> + Send + 'static {
+    futures_util::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0; CHUNK_SIZE];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(read) => {
+                chunk.truncate(read);
+                Some((Ok(chunk), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn transfer(share: &Path, max_upload_mb: u64) -> FileTransfer {
+        FileTransfer::new(TransferConfig {
+            enabled: true,
+            max_upload_mb,
+            shares: [("logs".to_string(), share.to_path_buf())].into(),
+        })
+    }
+
+    fn body(
+        chunks: Vec<Vec<u8>>,
+    ) -> impl Stream<Item = std::result::Result<Vec<u8>, String>> + Unpin {
+        futures_util::stream::iter(chunks.into_iter().map(Ok))
+    }
+
+    #[test]
+    fn test_iso_file_name() {
+        assert_eq!(
+            iso_file_name("ubuntu-24.04.iso").unwrap(),
+            "ubuntu-24.04.iso"
+        );
+        assert!(iso_file_name("WIN11.ISO").is_ok());
+        for name in [
+            "../x.iso",
+            "dir/x.iso",
+            ".hidden.iso",
+            "notes.txt",
+            "/x.iso",
+            "",
+        ] {
+            assert!(
+                matches!(
+                    iso_file_name(name),
+                    Err(Error::Transfer(TransferError::InvalidName(_)))
+                ),
+                "{name}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive() {
+        let dir = tempfile::tempdir().unwrap();
+        let transfer = transfer(dir.path(), 1);
+        let dest = dir.path().join("tiny.iso");
+
+        let received = transfer
+            .receive(body(vec![b"abc".to_vec(), b"def".to_vec()]), &dest, false)
+            .await
+            .unwrap();
+        assert_eq!(received, 6);
+        assert_eq!(std::fs::read(&dest).unwrap(), b"abcdef");
+        assert!(matches!(
+            transfer
+                .receive(body(vec![b"x".to_vec()]), &dest, false)
+                .await,
+            Err(Error::Transfer(TransferError::AlreadyExists(_)))
+        ));
+        transfer
+            .receive(body(vec![b"x".to_vec()]), &dest, true)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"x");
+
+        let dest = dir.path().join("big.iso");
+        let chunks = vec![vec![0; 1024 * 1024], vec![0]];
+        assert!(matches!(
+            transfer.receive(body(chunks), &dest, false).await,
+            Err(Error::Transfer(TransferError::TooLarge(1)))
+        ));
+        assert!(!dest.exists());
+        assert!(!dir.path().join(".big.iso.part").exists());
+    }
+
+    #[tokio::test]
+    async fn test_shares() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("serial")).unwrap();
+        std::fs::write(dir.path().join("serial/rack1.log"), "login:").unwrap();
+        std::fs::write(dir.path().join("install.log"), "done").unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("shadow"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path().join("shadow"), dir.path().join("link")).unwrap();
+        let transfer = transfer(dir.path(), 1);
+
+        let listed: Vec<(String, u64)> = transfer
+            .list()
+            .await
+            .into_iter()
+            .map(|file| (file.path, file.size))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("install.log".to_string(), 4),
+                ("serial/rack1.log".to_string(), 6),
+            ]
+        );
+
+        let file = transfer.resolve("logs", "serial/rack1.log").await.unwrap();
+        assert!(file.ends_with("serial/rack1.log"));
+        assert!(matches!(
+            transfer.resolve("logs", "../etc/passwd").await,
+            Err(Error::Transfer(TransferError::InvalidName(_)))
+        ));
+        assert!(matches!(
+            transfer.resolve("logs", "link").await,
+            Err(Error::Transfer(TransferError::NotFound(_)))
+        ));
+        assert!(transfer.resolve("logs", "serial").await.is_err());
+        assert!(transfer.resolve("other", "install.log").await.is_err());
+    }
+}
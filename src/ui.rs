@@ -1,8 +1,27 @@
+pub mod access;
 pub mod installer_gui;
-
-use crate::config::UiConfig;
-use crate::error::{Result, UiError};
-use installer_gui::{GuiConfig, GuiEvent, GuiState, InstallProgress, InstallerGui};
+pub mod interface;
+pub mod messages;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "gui")]
+mod window;
+pub mod wizard;
+
+use crate::chaos::{self, FaultPoint};
+use crate::config::{UiConfig, UiMode};
+use crate::disk::inventory::DiskInventory;
+use crate::error::{Error, Result, UiError};
+use crate::iso::catalog::IsoCatalogEntry;
+use crate::job::install::{InstallJob, InstallJobStatus};
+use crate::monitoring::Alert;
+use crate::remote::RemoteServiceStatus;
+use access::AccessInfo;
+use installer_gui::{
+    AlertEntry, DeviceChoice, GuiConfig, GuiEvent, GuiState, ImageChoice, InstallProgress,
+    InstallerGui, JobEntry, ServiceEntry,
+};
+use interface::{Interface, InterfaceReport, InterfaceStatus};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
@@ -41,20 +60,18 @@ pub struct UiManager {
     message_tx: mpsc::Sender<UiMessage>,
     message_rx: Arc<RwLock<mpsc::Receiver<UiMessage>>>,
     backend_tx: Option<mpsc::Sender<HashMap<String, String>>>,
+    interface: InterfaceStatus,
+    #[cfg(feature = "tui")]
+    tui: Option<tui::ConsoleTui>,
 }
 
 impl UiManager {
     pub fn new(config: Arc<RwLock<UiConfig>>) -> Self {
         let (message_tx, message_rx) = mpsc::channel(1000);
 
-        let gui_config = GuiConfig {
-            window_title: "USB Installer Node".to_string(),
-            width: 800,
-            height: 600,
-            fullscreen: false,
-            theme: "dark".to_string(),
-            language: "en".to_string(),
-            auto_restart: true,
+        let gui_config = match config.try_read() {
+            Ok(ui) => gui_config(&ui, GuiConfig::default()),
+            Err(_) => GuiConfig::default(),
         };
 
         Self {
@@ -64,6 +81,9 @@ impl UiManager {
             message_tx,
             message_rx: Arc::new(RwLock::new(message_rx)),
             backend_tx: None,
+            interface: InterfaceStatus::default(),
+            #[cfg(feature = "tui")]
+            tui: None,
         }
     }
 
@@ -78,8 +98,28 @@ impl UiManager {
             return Ok(());
         }
 
-        self.gui.start().await?;
-        self.start_message_processor().await;
+        if !messages::is_supported(&config.language) {
+            warn!("No translations for {}, using English", config.language);
+        }
+        let gui = gui_config(&config, self.gui.get_config().await);
+        self.gui.update_config(gui).await?;
+
+        let report = interface::choose(
+            config.mode,
+            interface::detect_display().as_ref(),
+            interface::has_console(),
+        );
+        drop(config);
+        let report = self.start_interface(report).await;
+        match &report.fallback_reason {
+            Some(reason) => warn!("Using {:?} interface: {}", report.interface, reason),
+            None => info!("Using {:?} interface", report.interface),
+        }
+
+        let console = report.interface == Interface::Console;
+        let lines = console && !self.start_tui().await;
+        self.start_message_processor(lines).await;
+        self.interface.set(Some(report)).await;
 
         self.set_state(UiManagerState::Running).await;
         info!("UI manager started");
@@ -91,7 +131,12 @@ impl UiManager {
         info!("Stopping UI manager");
         self.set_state(UiManagerState::Stopping).await;
 
+        #[cfg(feature = "tui")]
+        if let Some(mut tui) = self.tui.take() {
+            tui.stop().await;
+        }
         self.gui.stop().await?;
+        self.interface.set(None).await;
 
         self.set_state(UiManagerState::Stopped).await;
         info!("UI manager stopped");
@@ -99,12 +144,61 @@ impl UiManager {
         Ok(())
     }
 
-    async fn start_message_processor(&self) {
+    /// Start the graphical interface if chosen; a failing GUI falls back to
+    /// the console or web-only interface rather than failing the UI
+    async fn start_interface(&self, report: InterfaceReport) -> InterfaceReport {
+        if report.interface != Interface::Graphical {
+            return report;
+        }
+        match self.gui.start().await {
+            Ok(()) => report,
+            Err(e) => {
+                let fallback = interface::choose(UiMode::Console, None, interface::has_console());
+                InterfaceReport {
+                    requested: report.requested,
+                    interface: fallback.interface,
+                    fallback_reason: Some(format!("graphical interface failed: {}", e)),
+                }
+            }
+        }
+    }
+
+    /// Draw the full-screen console interface; `false` leaves the console
+    /// to line output
+    #[cfg(feature = "tui")]
+    async fn start_tui(&mut self) -> bool {
+        match tui::ConsoleTui::start(self.gui.clone()) {
+            Ok(tui) => {
+                self.tui = Some(tui);
+                self.gui.set_state(GuiState::Ready).await;
+                true
+            }
+            Err(e) => {
+                warn!("Console TUI unavailable, printing lines instead: {}", e);
+                false
+            }
+        }
+    }
+
+    #[cfg(not(feature = "tui"))]
+    async fn start_tui(&mut self) -> bool {
+        false
+    }
+
+    /// Chosen interface, shared so the REST API can report it
+    pub fn interface_status(&self) -> InterfaceStatus {
+        self.interface.clone()
+    }
+
+    async fn start_message_processor(&self, console: bool) {
         let gui = self.gui.clone();
         let mut rx = self.message_rx.write().await;
 
         tokio::spawn(async move {
             while let Some(message) = rx.recv().await {
+                if console {
+                    print_console(&message);
+                }
                 match message.msg_type {
                     UiMessageType::Progress => {
                         if let (Some(step), Some(percentage)) =
@@ -150,6 +244,8 @@ impl UiManager {
     }
 
     pub async fn send_message(&self, message: UiMessage) -> Result<()> {
+        chaos::inject(FaultPoint::ChannelSend, "ui")?;
+
         self.message_tx
             .send(message)
             .await
@@ -223,6 +319,166 @@ impl UiManager {
         self.gui.handle_remote_input(event_type, data).await
     }
 
+    /// Refresh the device picker from a disk inventory
+    pub async fn refresh_devices(&self, disks: &[DiskInventory]) {
+        let choices = disks
+            .iter()
+            .map(|disk| {
+                let mut encryption = Vec::new();
+                for volume in &disk.encrypted {
+                    if !encryption.contains(&volume.kind) {
+                        encryption.push(volume.kind);
+                    }
+                }
+                let mut label = disk.display_name();
+                if !encryption.is_empty() {
+                    let kinds: Vec<String> = encryption.iter().map(|k| k.to_string()).collect();
+                    label.push_str(&format!(" - ENCRYPTED ({})", kinds.join(", ")));
+                }
+                DeviceChoice {
+                    path: disk.path.clone(),
+                    label,
+                    removable: disk.removable,
+                    in_use: disk.mounted,
+                    encryption,
+                }
+            })
+            .collect();
+        self.gui.set_devices(choices).await;
+    }
+
+    /// Refresh the image picker from the ISO catalog
+    pub async fn refresh_images(&self, entries: &[IsoCatalogEntry]) {
+        let choices = entries
+            .iter()
+            .map(|entry| ImageChoice {
+                path: entry.path.display().to_string(),
+                label: entry.display_name(),
+            })
+            .collect();
+        self.gui.set_images(choices).await;
+    }
+
+    /// Show the install job queue as running and waiting jobs
+    pub async fn refresh_jobs(&self, jobs: &[InstallJob]) {
+        let entries = jobs
+            .iter()
+            .map(|job| JobEntry {
+                id: job.id.clone(),
+                label: format!(
+                    "{} -> {}",
+                    job.iso
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    job.device
+                ),
+                status: match job.status {
+                    InstallJobStatus::Running => format!("{:?}", job.step).to_lowercase(),
+                    status => format!("{:?}", status).to_lowercase(),
+                },
+                priority: job.priority,
+            })
+            .collect();
+        self.gui.set_jobs(entries).await;
+    }
+
+    /// Show VNC, SSH and WebVNC with their state and port
+    pub async fn refresh_remote_services(&self, services: &[RemoteServiceStatus]) {
+        let entries = services
+            .iter()
+            .map(|service| ServiceEntry {
+                name: service.name.clone(),
+                label: match service.name.as_str() {
+                    "vnc" => "VNC".to_string(),
+                    "ssh" => "SSH".to_string(),
+                    "web_vnc" => "Web VNC".to_string(),
+                    other => other.to_string(),
+                },
+                running: service.running,
+                port: service.port,
+            })
+            .collect();
+        self.gui.set_services(entries).await;
+    }
+
+    /// Show where the node is reached from a phone. A change is logged
+    /// with the QR code too, for consoles that show no interface.
+    pub async fn set_access(&self, access: AccessInfo) {
+        if self.gui.get_access().await.as_ref() == Some(&access) {
+            return;
+        }
+        match access.to_unicode() {
+            Ok(code) => info!("Node access:\n{}\n{}", code, access.payload()),
+            Err(e) => warn!("Cannot show the node access as a QR code: {}", e),
+        }
+        self.gui.set_access(access).await;
+    }
+
+    /// Show the open alerts in the alerts panel
+    pub async fn refresh_alerts(&self, alerts: &[Alert]) {
+        let entries = alerts
+            .iter()
+            .filter(|alert| !alert.resolved)
+            .map(|alert| AlertEntry {
+                severity: alert.severity,
+                module: alert.module.clone(),
+                message: alert.message.clone(),
+            })
+            .collect();
+        self.gui.set_alerts(entries).await;
+    }
+
+    /// Show a banner above everything else, or remove it with `None`
+    pub async fn set_banner(&self, banner: Option<String>) {
+        self.gui.set_banner(banner).await;
+    }
+
+    pub async fn get_banner(&self) -> Option<String> {
+        self.gui.get_banner().await
+    }
+
+    pub async fn get_remote_services(&self) -> Vec<ServiceEntry> {
+        self.gui.get_services().await
+    }
+
+    pub async fn get_jobs(&self) -> Vec<JobEntry> {
+        self.gui.get_jobs().await
+    }
+
+    pub async fn get_images(&self) -> Vec<ImageChoice> {
+        self.gui.get_images().await
+    }
+
+    pub async fn select_image(&self, path: &str) -> Result<()> {
+        self.gui.select_image(path).await
+    }
+
+    pub async fn get_devices(&self) -> Vec<DeviceChoice> {
+        self.gui.get_devices().await
+    }
+
+    pub async fn select_device(&self, path: &str) -> Result<()> {
+        self.gui.select_device(path).await
+    }
+
+    pub async fn get_selected_device(&self) -> Option<String> {
+        self.gui.get_selected_device().await
+    }
+
+    /// The shared installer state, for frontends outside the UI module
+    pub fn installer_gui(&self) -> InstallerGui {
+        (*self.gui).clone()
+    }
+
+    pub async fn wizard_started(&self, job_id: String) {
+        self.gui.wizard_started(job_id).await
+    }
+
+    pub async fn wizard_finished(&self, job_id: Option<&str>, success: bool, message: String) {
+        self.gui.wizard_finished(job_id, success, message).await
+    }
+
     pub async fn get_gui_state(&self) -> GuiState {
         self.gui.get_state().await
     }
@@ -292,20 +548,64 @@ impl UiManager {
     }
 
     pub async fn get_localized_string(&self, key: &str) -> String {
-        let config = self.config.read().await;
-        let lang = &config.language;
-
-        match (lang.as_str(), key) {
-            ("en", "welcome") => "Welcome to USB Installer".to_string(),
-            ("en", "select_os") => "Select Operating System".to_string(),
-            ("en", "install") => "Install".to_string(),
-            ("en", "cancel") => "Cancel".to_string(),
-            ("en", "partitioning") => "Partitioning disk...".to_string(),
-            ("en", "installing") => "Installing OS...".to_string(),
-            ("en", "complete") => "Installation complete!".to_string(),
-            ("en", "error") => "An error occurred".to_string(),
-            _ => key.to_string(),
-        }
+        messages::lookup(&self.gui.language().await, key)
+    }
+
+    /// Switch the language until the next start, which goes back to
+    /// `[ui] language`
+    pub async fn set_language(&self, language: &str) -> Result<()> {
+        self.gui.set_language(language).await
+    }
+
+    /// Show an error in the configured language; the full detail goes to the log
+    pub async fn show_localized_error(&self, err: &Error) -> Result<()> {
+        error!("{}", err);
+
+        let message = err.user_message();
+        let content = messages::render_error(&self.gui.language().await, &message);
+
+        let mut data: HashMap<String, String> = message
+            .params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        data.insert("key".to_string(), message.key.to_string());
+
+        self.send_message(UiMessage {
+            msg_type: UiMessageType::Error,
+            content,
+            data,
+        })
+        .await
+    }
+}
+
+/// `base` with the theme, screen mode and language of `[ui]`
+fn gui_config(ui: &UiConfig, base: GuiConfig) -> GuiConfig {
+    GuiConfig {
+        theme: ui.theme.clone(),
+        fullscreen: ui.fullscreen,
+        language: ui.language.clone(),
+        ..base
+    }
+}
+
+/// Line-oriented output for the console interface
+fn print_console(message: &UiMessage) {
+    match message.msg_type {
+        UiMessageType::Progress => println!(
+            "[{:>3}%] {}",
+            message
+                .data
+                .get("percentage")
+                .map(String::as_str)
+                .unwrap_or("0"),
+            message.content
+        ),
+        UiMessageType::Error => eprintln!("Error: {}", message.content),
+        UiMessageType::Warning => eprintln!("Warning: {}", message.content),
+        UiMessageType::Info | UiMessageType::Success => println!("{}", message.content),
+        UiMessageType::Input => {}
     }
 }
 
@@ -346,6 +646,27 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_localized_error() {
+        let config = Arc::new(RwLock::new(UiConfig {
+            language: "de".to_string(),
+            ..UiConfig::default()
+        }));
+        let manager = UiManager::new(config);
+
+        let err: Error =
+            crate::error::DiskError::FormatFailed("mkfs.ntfs: exit 1".to_string()).into();
+        manager.show_localized_error(&err).await.unwrap();
+
+        let message = manager.message_rx.write().await.recv().await.unwrap();
+        assert_eq!(message.msg_type, UiMessageType::Error);
+        assert_eq!(message.content, "Formatierung fehlgeschlagen");
+        assert_eq!(
+            message.data.get("key").map(String::as_str),
+            Some("error.disk.format_failed")
+        );
+    }
+
     #[tokio::test]
     async fn test_localization() {
         let config = Arc::new(RwLock::new(UiConfig::default()));
@@ -356,5 +677,10 @@ mod tests {
 
         let unknown = manager.get_localized_string("unknown_key").await;
         assert_eq!(unknown, "unknown_key");
+
+        manager.set_language("de").await.unwrap();
+        let welcome = manager.get_localized_string("welcome").await;
+        assert_eq!(welcome, "Willkommen beim USB-Installer");
+        assert!(manager.set_language("xx").await.is_err());
     }
 }
@@ -0,0 +1,192 @@
+use crate::error::{Result, UiError};
+use crate::remote::RemoteServiceStatus;
+use qrcode::render::svg;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{EcLevel, QrCode};
+use serde::Serialize;
+
+/// Smallest edge of the SVG image, in pixels
+const SVG_SIZE: u32 = 256;
+
+/// Where a technician reaches the node from a phone or laptop, shown as a
+/// QR code so nobody has to type addresses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccessInfo {
+    /// REST API
+    pub node_url: String,
+    /// noVNC client, while WebVNC runs
+    pub web_vnc_url: Option<String>,
+    /// Command line, while SSH runs
+    pub ssh: Option<String>,
+    /// Of the SSH host key, to compare on the first connect
+    pub ssh_fingerprint: Option<String>,
+}
+
+/// Modules of a QR code row by row, `true` for dark, without quiet zone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrModules {
+    pub width: usize,
+    pub dark: Vec<bool>,
+}
+
+impl QrModules {
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+}
+
+impl AccessInfo {
+    /// For a node reached at `host`, with the API on `api_port`
+    pub fn new(host: &str, api_port: u16, services: &[RemoteServiceStatus]) -> Self {
+        let url_host = if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host.to_string()
+        };
+        let running = |name: &str| {
+            services
+                .iter()
+                .find(|service| service.name == name && service.running)
+        };
+
+        let web_vnc_url = running("web_vnc").map(|service| {
+            let https = service.details.get("https").map(String::as_str) == Some("true");
+            let scheme = if https { "https" } else { "http" };
+            format!("{}://{}:{}/vnc.html", scheme, url_host, service.port)
+        });
+        let ssh = running("ssh");
+        Self {
+            node_url: format!("http://{}:{}", url_host, api_port),
+            web_vnc_url,
+            ssh: ssh.map(|service| format!("ssh -p {} {}", service.port, host)),
+            ssh_fingerprint: ssh
+                .and_then(|service| service.details.get("host_key_fingerprint").cloned()),
+        }
+    }
+
+    /// What the QR code holds: one endpoint per line
+    pub fn payload(&self) -> String {
+        let mut lines = vec![self.node_url.clone()];
+        lines.extend(self.web_vnc_url.clone());
+        lines.extend(self.ssh.clone());
+        lines.extend(self.ssh_fingerprint.clone());
+        lines.join("\n")
+    }
+
+    /// For terminals, two modules per character. Dark modules are blank, so
+    /// the code reads on a dark console.
+    pub fn to_unicode(&self) -> Result<String> {
+        Ok(self
+            .qr()?
+            .render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build())
+    }
+
+    /// For browsers
+    pub fn to_svg(&self) -> Result<String> {
+        Ok(self
+            .qr()?
+            .render::<svg::Color>()
+            .min_dimensions(SVG_SIZE, SVG_SIZE)
+            .dark_color(svg::Color("#000000"))
+            .light_color(svg::Color("#ffffff"))
+            .build())
+    }
+
+    /// For frontends that paint the modules themselves
+    pub fn modules(&self) -> Result<QrModules> {
+        let code = self.qr()?;
+        Ok(QrModules {
+            width: code.width(),
+            dark: code
+                .to_colors()
+                .into_iter()
+                .map(|color| color == qrcode::Color::Dark)
+                .collect(),
+        })
+    }
+
+    /// Low error correction keeps the code small enough for a console
+    fn qr(&self) -> Result<QrCode> {
+        QrCode::with_error_correction_level(self.payload(), EcLevel::L)
+            .map_err(|e| UiError::RenderError(format!("QR code: {}", e)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn service(name: &str, port: u16, details: &[(&str, &str)]) -> RemoteServiceStatus {
+        RemoteServiceStatus {
+            name: name.to_string(),
+            enabled: true,
+            running: true,
+            port,
+            details: details
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let fingerprint = "SHA256:ohD8VZEXGWo6Ez8GSEJQ9WpafgLFsOfLOtGGQCQo6Og";
+        let mut vnc = service("vnc", 5900, &[]);
+        vnc.running = false;
+        let services = vec![
+            vnc,
+            service("ssh", 2222, &[("host_key_fingerprint", fingerprint)]),
+            service("web_vnc", 6080, &[("https", "true")]),
+        ];
+
+        let access = AccessInfo::new("192.168.1.40", 8080, &services);
+        assert_eq!(
+            access.payload(),
+            format!(
+                "http://192.168.1.40:8080\n\
+                 https://192.168.1.40:6080/vnc.html\n\
+                 ssh -p 2222 192.168.1.40\n{}",
+                fingerprint
+            )
+        );
+
+        let access = AccessInfo::new("fd00::40", 8080, &services[..1]);
+        assert_eq!(access.payload(), "http://[fd00::40]:8080");
+        assert_eq!(access.ssh_fingerprint, None);
+    }
+
+    #[test]
+    fn test_render() {
+        let services = vec![service(
+            "ssh",
+            22,
+            &[(
+                "host_key_fingerprint",
+                "SHA256:ohD8VZEXGWo6Ez8GSEJQ9WpafgLFsOfLOtGGQCQo6Og",
+            )],
+        )];
+        let access = AccessInfo::new("installer-node.local", 8080, &services);
+
+        let modules = access.modules().unwrap();
+        assert_eq!(modules.dark.len(), modules.width * modules.width);
+        // Finder pattern in the top left corner
+        assert!(modules.is_dark(0, 0));
+        assert!(!modules.is_dark(1, 1));
+
+        // Quiet zone of four modules on each side, two rows per line
+        let text = access.to_unicode().unwrap();
+        assert_eq!(text.lines().count(), (modules.width + 8).div_ceil(2));
+        assert!(text
+            .lines()
+            .all(|line| line.chars().count() == modules.width + 8));
+
+        let svg = access.to_svg().unwrap();
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains("#000000"));
+    }
+}
@@ -1,10 +1,27 @@
-use crate::error::{Result, UiError};
+use super::access::AccessInfo;
+use super::messages;
+#[cfg(feature = "gui")]
+use super::window::WindowResult;
+use super::wizard::{Wizard, WizardAction, WizardState};
+use crate::disk::encryption::EncryptionKind;
+use crate::error::{Error, Result, UiError};
+use crate::monitoring::AlertSeverity;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+#[cfg(feature = "gui")]
+use std::time::Duration;
 use std::time::SystemTime;
+#[cfg(feature = "gui")]
+use tokio::sync::oneshot;
 use tokio::sync::{mpsc, RwLock};
 use tracing::{debug, error, info, warn};
 
+/// Wait before reopening a window that closed or failed
+#[cfg(feature = "gui")]
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GuiState {
     Initializing,
@@ -42,6 +59,72 @@ pub enum GuiEventType {
     RemoteInput,
 }
 
+/// Entry in the target device picker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceChoice {
+    pub path: String,
+    pub label: String,
+    pub removable: bool,
+    /// Device has mounted partitions and cannot be selected
+    pub in_use: bool,
+    /// Encrypted data on the device, lost when it is overwritten
+    pub encryption: Vec<EncryptionKind>,
+}
+
+/// Entry in the installer image picker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageChoice {
+    pub path: String,
+    pub label: String,
+}
+
+/// Entry in the install job queue, running jobs first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobEntry {
+    pub id: String,
+    pub label: String,
+    /// Queued, or the step a running job is in
+    pub status: String,
+    pub priority: i32,
+}
+
+/// Entry in the remote access panel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceEntry {
+    pub name: String,
+    pub label: String,
+    pub running: bool,
+    pub port: u16,
+}
+
+/// Open alert in the alerts panel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlertEntry {
+    pub severity: AlertSeverity,
+    pub module: String,
+    pub message: String,
+}
+
+/// Everything a frontend draws, read at once
+#[derive(Debug, Clone)]
+pub struct GuiSnapshot {
+    pub state: GuiState,
+    pub progress: InstallProgress,
+    pub devices: Vec<DeviceChoice>,
+    pub selected_device: Option<String>,
+    pub images: Vec<ImageChoice>,
+    pub selected_image: Option<String>,
+    pub jobs: Vec<JobEntry>,
+    pub alerts: Vec<AlertEntry>,
+    /// Latest log lines, oldest first
+    pub logs: Vec<String>,
+    pub banner: Option<String>,
+    pub wizard: WizardState,
+    /// Language to draw in, switchable at runtime
+    pub language: String,
+    pub access: Option<AccessInfo>,
+}
+
 #[derive(Debug, Clone)]
 pub struct GuiConfig {
     pub window_title: String,
@@ -67,6 +150,7 @@ impl Default for GuiConfig {
     }
 }
 
+#[derive(Clone)]
 pub struct InstallerGui {
     config: Arc<RwLock<GuiConfig>>,
     state: Arc<RwLock<GuiState>>,
@@ -75,6 +159,20 @@ pub struct InstallerGui {
     event_rx: Arc<RwLock<mpsc::Receiver<GuiEvent>>>,
     logs: Arc<RwLock<Vec<String>>>,
     restart_count: Arc<RwLock<u32>>,
+    devices: Arc<RwLock<Vec<DeviceChoice>>>,
+    selected_device: Arc<RwLock<Option<String>>>,
+    images: Arc<RwLock<Vec<ImageChoice>>>,
+    selected_image: Arc<RwLock<Option<String>>>,
+    jobs: Arc<RwLock<Vec<JobEntry>>>,
+    services: Arc<RwLock<Vec<ServiceEntry>>>,
+    alerts: Arc<RwLock<Vec<AlertEntry>>>,
+    /// Shown above everything else until cleared, e.g. in safe mode
+    banner: Arc<RwLock<Option<String>>>,
+    wizard: Arc<RwLock<Wizard>>,
+    /// Where the node is reached from other devices, once the network is up
+    access: Arc<RwLock<Option<AccessInfo>>>,
+    /// Bumped on every start and stop; a window closes once it is behind
+    generation: Arc<AtomicU64>,
 }
 
 impl InstallerGui {
@@ -96,6 +194,17 @@ impl InstallerGui {
             event_rx: Arc::new(RwLock::new(event_rx)),
             logs: Arc::new(RwLock::new(Vec::new())),
             restart_count: Arc::new(RwLock::new(0)),
+            devices: Arc::new(RwLock::new(Vec::new())),
+            selected_device: Arc::new(RwLock::new(None)),
+            images: Arc::new(RwLock::new(Vec::new())),
+            selected_image: Arc::new(RwLock::new(None)),
+            jobs: Arc::new(RwLock::new(Vec::new())),
+            services: Arc::new(RwLock::new(Vec::new())),
+            alerts: Arc::new(RwLock::new(Vec::new())),
+            banner: Arc::new(RwLock::new(None)),
+            wizard: Arc::new(RwLock::new(Wizard::new())),
+            access: Arc::new(RwLock::new(None)),
+            generation: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -103,7 +212,7 @@ impl InstallerGui {
         info!("Starting installer GUI");
         self.set_state(GuiState::Initializing).await;
 
-        self.simulate_gui_thread().await?;
+        self.open_window().await?;
 
         self.set_state(GuiState::Ready).await;
         info!("Installer GUI ready");
@@ -113,39 +222,83 @@ impl InstallerGui {
 
     pub async fn stop(&self) -> Result<()> {
         info!("Stopping installer GUI");
+        // The window closes once it sees a newer generation
+        self.generation.fetch_add(1, Ordering::SeqCst);
         self.set_state(GuiState::Ready).await;
         Ok(())
     }
 
-    async fn simulate_gui_thread(&self) -> Result<()> {
-        let state = self.state.clone();
-        let config = self.config.clone();
-        let logs = self.logs.clone();
-        let restart_count = self.restart_count.clone();
+    /// Whether a window opened as `generation` is still wanted
+    pub(super) fn is_current(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) == generation
+    }
 
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    /// Open the installer window, failing when it cannot get on screen
+    #[cfg(feature = "gui")]
+    async fn open_window(&self) -> Result<()> {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let (opened_tx, opened_rx) = oneshot::channel();
+        let done = self.spawn_window(generation, Some(opened_tx)).await;
+        if opened_rx.await.is_err() {
+            let reason = match done.await {
+                Ok(Err(e)) => e,
+                _ => "window closed".to_string(),
+            };
+            return Err(UiError::InitFailed(reason).into());
+        }
 
-                let current_state = state.read().await.clone();
-                if current_state == GuiState::Crashed {
-                    let config = config.read().await;
-                    if config.auto_restart {
-                        warn!("GUI crashed, attempting restart");
-                        let mut count = restart_count.write().await;
-                        *count += 1;
+        let gui = self.clone();
+        tokio::spawn(async move { gui.supervise_window(generation, done).await });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "gui"))]
+    async fn open_window(&self) -> Result<()> {
+        Err(UiError::InitFailed("built without the gui feature".to_string()).into())
+    }
 
-                        *state.write().await = GuiState::Initializing;
-                        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-                        *state.write().await = GuiState::Ready;
+    #[cfg(feature = "gui")]
+    async fn spawn_window(
+        &self,
+        generation: u64,
+        opened: Option<oneshot::Sender<()>>,
+    ) -> oneshot::Receiver<WindowResult> {
+        let config = self.config.read().await.clone();
+        super::window::open(self.clone(), config, generation, opened)
+    }
 
-                        info!("GUI restarted successfully (attempt #{})", *count);
-                    }
-                }
+    /// Reopen the window whenever it closes or fails while it is wanted,
+    /// with `auto_restart`
+    #[cfg(feature = "gui")]
+    async fn supervise_window(&self, generation: u64, mut done: oneshot::Receiver<WindowResult>) {
+        loop {
+            let reason = match done.await {
+                Ok(Ok(())) => "closed".to_string(),
+                Ok(Err(e)) => e,
+                Err(_) => "window thread exited".to_string(),
+            };
+            if !self.is_current(generation) {
+                break;
+            }
+            self.set_state(GuiState::Crashed).await;
+            if !self.config.read().await.auto_restart {
+                error!("Installer window {}", reason);
+                break;
             }
-        });
 
-        Ok(())
+            let attempt = {
+                let mut count = self.restart_count.write().await;
+                *count += 1;
+                *count
+            };
+            warn!(
+                "Installer window {}, reopening (attempt #{})",
+                reason, attempt
+            );
+            tokio::time::sleep(RESTART_DELAY).await;
+            done = self.spawn_window(generation, None).await;
+            self.set_state(GuiState::Ready).await;
+        }
     }
 
     pub async fn display_progress(&self, progress: InstallProgress) -> Result<()> {
@@ -165,13 +318,23 @@ impl InstallerGui {
         &self,
         input_type: &str,
         data: HashMap<String, String>,
+    ) -> Result<()> {
+        let event_type = match input_type {
+            "click" => GuiEventType::Click,
+            "key" => GuiEventType::KeyPress,
+            _ => GuiEventType::RemoteInput,
+        };
+        self.send_event(event_type, data).await
+    }
+
+    /// Queue input from a local frontend for [`Self::process_events`]
+    pub async fn send_event(
+        &self,
+        event_type: GuiEventType,
+        data: HashMap<String, String>,
     ) -> Result<()> {
         let event = GuiEvent {
-            event_type: match input_type {
-                "click" => GuiEventType::Click,
-                "key" => GuiEventType::KeyPress,
-                _ => GuiEventType::RemoteInput,
-            },
+            event_type,
             data,
             timestamp: SystemTime::now(),
         };
@@ -188,7 +351,7 @@ impl InstallerGui {
         self.state.read().await.clone()
     }
 
-    async fn set_state(&self, state: GuiState) {
+    pub(super) async fn set_state(&self, state: GuiState) {
         *self.state.write().await = state;
     }
 
@@ -239,6 +402,29 @@ impl InstallerGui {
         Ok(())
     }
 
+    pub async fn get_config(&self) -> GuiConfig {
+        self.config.read().await.clone()
+    }
+
+    pub async fn language(&self) -> String {
+        self.config.read().await.language.clone()
+    }
+
+    /// Switch every frontend to `language`, which needs a bundle
+    pub async fn set_language(&self, language: &str) -> Result<()> {
+        if !messages::is_supported(language) {
+            return Err(UiError::UnsupportedLanguage(language.to_string()).into());
+        }
+        self.config.write().await.language = language.to_string();
+        self.add_log(format!("Language set to {}", language)).await;
+        Ok(())
+    }
+
+    /// `err` as the user sees it, in the current language
+    pub async fn localize_error(&self, err: &Error) -> String {
+        messages::render_error(&self.language().await, &err.user_message())
+    }
+
     pub async fn process_events(&self) -> Result<Vec<GuiEvent>> {
         let mut events = Vec::new();
         let mut rx = self.event_rx.write().await;
@@ -261,6 +447,210 @@ impl InstallerGui {
         self.set_state(GuiState::Completed).await;
     }
 
+    pub async fn set_devices(&self, devices: Vec<DeviceChoice>) {
+        let mut selected = self.selected_device.write().await;
+        if let Some(path) = selected.as_ref() {
+            if !devices.iter().any(|d| &d.path == path && !d.in_use) {
+                warn!("Selected device {} is no longer available", path);
+                *selected = None;
+            }
+        }
+        *self.devices.write().await = devices;
+    }
+
+    pub async fn get_devices(&self) -> Vec<DeviceChoice> {
+        self.devices.read().await.clone()
+    }
+
+    pub async fn select_device(&self, path: &str) -> Result<()> {
+        let devices = self.devices.read().await;
+        let device = devices
+            .iter()
+            .find(|d| d.path == path)
+            .ok_or_else(|| UiError::InputError(format!("Unknown device: {}", path)))?;
+
+        if device.in_use {
+            return Err(UiError::InputError(format!("Device {} is in use", path)).into());
+        }
+
+        *self.selected_device.write().await = Some(device.path.clone());
+        self.add_log(format!("Selected target device {}", device.label))
+            .await;
+        if !device.encryption.is_empty() {
+            let kinds: Vec<String> = device.encryption.iter().map(|k| k.to_string()).collect();
+            self.add_log(format!(
+                "WARNING: {} holds {} encrypted data, which will be destroyed",
+                device.label,
+                kinds.join(", ")
+            ))
+            .await;
+        }
+        Ok(())
+    }
+
+    pub async fn get_selected_device(&self) -> Option<String> {
+        self.selected_device.read().await.clone()
+    }
+
+    pub async fn set_images(&self, images: Vec<ImageChoice>) {
+        let mut selected = self.selected_image.write().await;
+        if let Some(path) = selected.as_ref() {
+            if !images.iter().any(|i| &i.path == path) {
+                warn!("Selected image {} is no longer available", path);
+                *selected = None;
+            }
+        }
+        *self.images.write().await = images;
+    }
+
+    pub async fn get_images(&self) -> Vec<ImageChoice> {
+        self.images.read().await.clone()
+    }
+
+    pub async fn select_image(&self, path: &str) -> Result<()> {
+        let images = self.images.read().await;
+        let image = images
+            .iter()
+            .find(|i| i.path == path)
+            .ok_or_else(|| UiError::InputError(format!("Unknown image: {}", path)))?;
+
+        *self.selected_image.write().await = Some(image.path.clone());
+        self.add_log(format!("Selected image {}", image.label))
+            .await;
+        Ok(())
+    }
+
+    pub async fn get_selected_image(&self) -> Option<String> {
+        self.selected_image.read().await.clone()
+    }
+
+    pub async fn set_jobs(&self, jobs: Vec<JobEntry>) {
+        *self.jobs.write().await = jobs;
+    }
+
+    pub async fn get_jobs(&self) -> Vec<JobEntry> {
+        self.jobs.read().await.clone()
+    }
+
+    pub async fn set_services(&self, services: Vec<ServiceEntry>) {
+        *self.services.write().await = services;
+    }
+
+    pub async fn get_services(&self) -> Vec<ServiceEntry> {
+        self.services.read().await.clone()
+    }
+
+    pub async fn set_alerts(&self, alerts: Vec<AlertEntry>) {
+        *self.alerts.write().await = alerts;
+    }
+
+    pub async fn get_alerts(&self) -> Vec<AlertEntry> {
+        self.alerts.read().await.clone()
+    }
+
+    pub async fn set_banner(&self, banner: Option<String>) {
+        if let Some(text) = &banner {
+            self.add_log(format!("BANNER: {}", text)).await;
+        }
+        *self.banner.write().await = banner;
+    }
+
+    pub async fn get_banner(&self) -> Option<String> {
+        self.banner.read().await.clone()
+    }
+
+    pub async fn set_access(&self, access: AccessInfo) {
+        *self.access.write().await = Some(access);
+    }
+
+    pub async fn get_access(&self) -> Option<AccessInfo> {
+        self.access.read().await.clone()
+    }
+
+    /// Current state with the last `log_limit` log lines
+    pub async fn snapshot(&self, log_limit: usize) -> GuiSnapshot {
+        GuiSnapshot {
+            state: self.get_state().await,
+            progress: self.get_progress().await,
+            devices: self.get_devices().await,
+            selected_device: self.get_selected_device().await,
+            images: self.get_images().await,
+            selected_image: self.get_selected_image().await,
+            jobs: self.get_jobs().await,
+            alerts: self.get_alerts().await,
+            logs: self.get_logs(Some(log_limit)).await,
+            banner: self.get_banner().await,
+            wizard: self.wizard_state().await,
+            language: self.language().await,
+            access: self.get_access().await,
+        }
+    }
+
+    pub async fn wizard_state(&self) -> WizardState {
+        let language = self.language().await;
+        self.wizard.read().await.state(&language)
+    }
+
+    /// Move the install wizard on. A confirmed install goes out as a
+    /// [`GuiEventType::Click`] event with `action=install`, like any other
+    /// frontend input.
+    pub async fn wizard_action(&self, action: WizardAction) -> Result<WizardState> {
+        let images = self.get_images().await;
+        let devices = self.get_devices().await;
+        let mut wizard = self.wizard.write().await;
+        let request = wizard.apply(action.clone(), &images, &devices)?;
+
+        let selection = match action {
+            WizardAction::SelectImage { path } => {
+                self.select_image(&path).await?;
+                Some(("image", path))
+            }
+            WizardAction::SelectDevice { path } => {
+                self.select_device(&path).await?;
+                Some(("device", path))
+            }
+            WizardAction::Reset => {
+                *self.selected_image.write().await = None;
+                *self.selected_device.write().await = None;
+                None
+            }
+            _ => None,
+        };
+        if let Some((field, path)) = selection {
+            let data = HashMap::from([(field.to_string(), path)]);
+            if let Err(e) = self.send_event(GuiEventType::SelectionChange, data).await {
+                debug!("Dropped selection: {}", e);
+            }
+        }
+
+        if let Some(request) = request {
+            self.add_log(format!(
+                "Confirmed erasing {} for {}",
+                request.device, request.image
+            ))
+            .await;
+            let data = HashMap::from([
+                ("action".to_string(), "install".to_string()),
+                ("image".to_string(), request.image),
+                ("device".to_string(), request.device),
+            ]);
+            if let Err(e) = self.send_event(GuiEventType::Click, data).await {
+                wizard.finished(None, false, e.to_string());
+            }
+        }
+        Ok(wizard.state(&self.language().await))
+    }
+
+    /// The install the wizard asked for was queued as `job_id`
+    pub async fn wizard_started(&self, job_id: String) {
+        self.wizard.write().await.started(job_id);
+    }
+
+    /// The wizard's install job ended, or with `None`, was not queued
+    pub async fn wizard_finished(&self, job_id: Option<&str>, success: bool, message: String) {
+        self.wizard.write().await.finished(job_id, success, message);
+    }
+
     pub async fn get_restart_count(&self) -> u32 {
         *self.restart_count.read().await
     }
@@ -287,6 +677,60 @@ mod tests {
         assert_eq!(gui.get_restart_count().await, 0);
     }
 
+    #[tokio::test]
+    async fn test_device_selection() {
+        let gui = InstallerGui::default();
+        gui.set_devices(vec![
+            DeviceChoice {
+                path: "/dev/sda".to_string(),
+                label: "System disk".to_string(),
+                removable: false,
+                in_use: true,
+                encryption: Vec::new(),
+            },
+            DeviceChoice {
+                path: "/dev/sdb".to_string(),
+                label: "Ultra (usb, 15.4 GB)".to_string(),
+                removable: true,
+                in_use: false,
+                encryption: vec![EncryptionKind::BitLocker],
+            },
+        ])
+        .await;
+
+        assert!(gui.select_device("/dev/sda").await.is_err());
+        assert!(gui.select_device("/dev/sdz").await.is_err());
+        gui.select_device("/dev/sdb").await.unwrap();
+        assert_eq!(gui.get_selected_device().await.as_deref(), Some("/dev/sdb"));
+        assert!(gui
+            .get_logs(None)
+            .await
+            .iter()
+            .any(|l| l.contains("WARNING: Ultra (usb, 15.4 GB) holds BitLocker encrypted data")));
+
+        gui.set_devices(Vec::new()).await;
+        assert!(gui.get_selected_device().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_image_selection() {
+        let gui = InstallerGui::default();
+        gui.set_images(vec![ImageChoice {
+            path: "/installers/ubuntu-24.04-live-server-amd64.iso".to_string(),
+            label: "Ubuntu 24.04 server (x86_64)".to_string(),
+        }])
+        .await;
+
+        assert!(gui.select_image("/installers/missing.iso").await.is_err());
+        gui.select_image("/installers/ubuntu-24.04-live-server-amd64.iso")
+            .await
+            .unwrap();
+        assert!(gui.get_selected_image().await.is_some());
+
+        gui.set_images(Vec::new()).await;
+        assert!(gui.get_selected_image().await.is_none());
+    }
+
     #[tokio::test]
     async fn test_progress_tracking() {
         let gui = InstallerGui::default();
@@ -337,4 +781,62 @@ mod tests {
         assert_eq!(events.len(), 1);
         assert_eq!(events[0].event_type, GuiEventType::Click);
     }
+
+    #[tokio::test]
+    async fn test_wizard_install_event() {
+        let gui = InstallerGui::default();
+        gui.set_images(vec![ImageChoice {
+            path: "/installers/debian-12.iso".to_string(),
+            label: "Debian 12 (x86_64)".to_string(),
+        }])
+        .await;
+        gui.set_devices(vec![DeviceChoice {
+            path: "/dev/sdb".to_string(),
+            label: "Ultra (usb, 15.4 GB)".to_string(),
+            removable: true,
+            in_use: false,
+            encryption: Vec::new(),
+        }])
+        .await;
+
+        for action in [
+            WizardAction::SelectImage {
+                path: "/installers/debian-12.iso".to_string(),
+            },
+            WizardAction::SelectDevice {
+                path: "/dev/sdb".to_string(),
+            },
+            WizardAction::Accept,
+            WizardAction::Confirm {
+                typed: "sdb".to_string(),
+            },
+        ] {
+            gui.wizard_action(action).await.unwrap();
+        }
+        assert_eq!(gui.get_selected_device().await.as_deref(), Some("/dev/sdb"));
+
+        let events = gui.process_events().await.unwrap();
+        let install = events.last().unwrap();
+        assert_eq!(install.event_type, GuiEventType::Click);
+        assert_eq!(install.data["device"], "/dev/sdb");
+
+        gui.wizard_started("job-1".to_string()).await;
+        gui.wizard_finished(Some("job-1"), false, "Write failed".to_string())
+            .await;
+        assert_eq!(gui.wizard_state().await.success, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_language_switch() {
+        let gui = InstallerGui::default();
+        assert!(gui.set_language("fr").await.is_err());
+        gui.set_language("de").await.unwrap();
+        assert_eq!(gui.snapshot(0).await.language, "de");
+
+        let err: Error = UiError::ConfirmationMismatch("sdb".to_string()).into();
+        assert_eq!(
+            gui.localize_error(&err).await,
+            "Geben Sie sdb ein, um das Löschen zu bestätigen"
+        );
+    }
 }
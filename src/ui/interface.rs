@@ -0,0 +1,197 @@
+use crate::config::UiMode;
+use serde::Serialize;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Where X servers put their sockets: `X0` serves display `:0`
+const X11_SOCKET_DIR: &str = "/tmp/.X11-unix";
+
+/// Interface actually presented on the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interface {
+    Graphical,
+    /// Text interface on the local terminal
+    Console,
+    /// Nothing local; the node is operated through the API and WebVNC only
+    WebOnly,
+}
+
+/// Display server found for the graphical interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayServer {
+    Wayland(PathBuf),
+    /// `DISPLAY` value, e.g. `:0`
+    X11(String),
+}
+
+/// The interface chosen at start and, when it is not the preferred one, why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InterfaceReport {
+    pub requested: UiMode,
+    pub interface: Interface,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_reason: Option<String>,
+}
+
+/// Chosen interface shared with the REST API; `None` while the UI is stopped
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceStatus {
+    report: Arc<RwLock<Option<InterfaceReport>>>,
+}
+
+impl InterfaceStatus {
+    pub async fn get(&self) -> Option<InterfaceReport> {
+        self.report.read().await.clone()
+    }
+
+    pub(super) async fn set(&self, report: Option<InterfaceReport>) {
+        *self.report.write().await = report;
+    }
+}
+
+/// Wayland or X11 display reachable from this process
+pub fn detect_display() -> Option<DisplayServer> {
+    detect_display_in(|name| std::env::var(name).ok(), Path::new(X11_SOCKET_DIR))
+}
+
+fn detect_display_in(
+    env: impl Fn(&str) -> Option<String>,
+    x11_dir: &Path,
+) -> Option<DisplayServer> {
+    if let Some(name) = env("WAYLAND_DISPLAY").filter(|n| !n.is_empty()) {
+        let socket = match env("XDG_RUNTIME_DIR") {
+            Some(runtime) if !Path::new(&name).is_absolute() => Path::new(&runtime).join(&name),
+            _ => PathBuf::from(&name),
+        };
+        if socket.exists() {
+            return Some(DisplayServer::Wayland(socket));
+        }
+    }
+
+    match env("DISPLAY").filter(|d| !d.is_empty()) {
+        // Local display: only usable if its server socket exists
+        Some(display) if display.starts_with(':') => {
+            let number = display[1..].split('.').next().unwrap_or_default();
+            x11_dir
+                .join(format!("X{}", number))
+                .exists()
+                .then_some(DisplayServer::X11(display))
+        }
+        // Remote display; nothing to check without connecting
+        Some(display) => Some(DisplayServer::X11(display)),
+        // Services started before the session have no DISPLAY, but the
+        // kiosk X server is usually :0
+        None => x11_dir
+            .join("X0")
+            .exists()
+            .then(|| DisplayServer::X11(":0".to_string())),
+    }
+}
+
+/// A terminal the console interface can draw on
+pub fn has_console() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+/// Best interface for `requested` given what the box has. Falls back from
+/// graphical to console to web-only instead of failing.
+pub fn choose(
+    requested: UiMode,
+    display: Option<&DisplayServer>,
+    console: bool,
+) -> InterfaceReport {
+    let (interface, fallback_reason) = match (requested, display, console) {
+        (UiMode::Web, _, _) => (Interface::WebOnly, None),
+        (UiMode::Auto | UiMode::Graphical, Some(_), _) => (Interface::Graphical, None),
+        (UiMode::Auto | UiMode::Graphical, None, true) => (
+            Interface::Console,
+            Some("no X11 or Wayland display".to_string()),
+        ),
+        (UiMode::Auto | UiMode::Graphical, None, false) => (
+            Interface::WebOnly,
+            Some("no X11 or Wayland display and no terminal".to_string()),
+        ),
+        (UiMode::Console, _, true) => (Interface::Console, None),
+        (UiMode::Console, _, false) => (Interface::WebOnly, Some("no terminal".to_string())),
+    };
+    InterfaceReport {
+        requested,
+        interface,
+        fallback_reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    fn detect(vars: &[(&str, &str)], x11_dir: &Path) -> Option<DisplayServer> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        detect_display_in(|name| vars.get(name).cloned(), x11_dir)
+    }
+
+    #[test]
+    fn test_detect_display() {
+        let dir = TempDir::new().unwrap();
+        let x11 = dir.path().join("x11");
+        std::fs::create_dir(&x11).unwrap();
+
+        assert_eq!(detect(&[], &x11), None);
+        assert_eq!(detect(&[("DISPLAY", ":0")], &x11), None);
+
+        std::fs::write(x11.join("X0"), "").unwrap();
+        assert_eq!(
+            detect(&[], &x11),
+            Some(DisplayServer::X11(":0".to_string()))
+        );
+        assert_eq!(
+            detect(&[("DISPLAY", ":0.1")], &x11),
+            Some(DisplayServer::X11(":0.1".to_string()))
+        );
+        assert_eq!(detect(&[("DISPLAY", ":1")], &x11), None);
+
+        let runtime = dir.path().to_str().unwrap();
+        std::fs::write(dir.path().join("wayland-1"), "").unwrap();
+        assert_eq!(
+            detect(
+                &[
+                    ("WAYLAND_DISPLAY", "wayland-1"),
+                    ("XDG_RUNTIME_DIR", runtime)
+                ],
+                &x11
+            ),
+            Some(DisplayServer::Wayland(dir.path().join("wayland-1")))
+        );
+    }
+
+    #[test]
+    fn test_choose_falls_back() {
+        let x11 = DisplayServer::X11(":0".to_string());
+
+        let report = choose(UiMode::Auto, Some(&x11), true);
+        assert_eq!(report.interface, Interface::Graphical);
+
+        let report = choose(UiMode::Graphical, None, true);
+        assert_eq!(report.interface, Interface::Console);
+        assert_eq!(
+            report.fallback_reason.as_deref(),
+            Some("no X11 or Wayland display")
+        );
+
+        let report = choose(UiMode::Auto, None, false);
+        assert_eq!(report.interface, Interface::WebOnly);
+        assert!(report.fallback_reason.is_some());
+
+        let report = choose(UiMode::Web, Some(&x11), true);
+        assert_eq!(report.interface, Interface::WebOnly);
+        assert_eq!(report.fallback_reason, None);
+    }
+}
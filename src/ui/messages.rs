@@ -0,0 +1,138 @@
+use crate::error::ErrorMessage;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use tracing::error;
+
+/// Language used when a key is missing in the configured language
+const FALLBACK_LANGUAGE: &str = "en";
+
+/// Translations embedded at build time, one JSON object of message key to
+/// template per language
+const BUNDLES: &[(&str, &str)] = &[
+    ("en", include_str!("locales/en.json")),
+    ("de", include_str!("locales/de.json")),
+];
+
+type Catalog = HashMap<String, String>;
+
+fn catalogs() -> &'static HashMap<&'static str, Catalog> {
+    static CATALOGS: OnceLock<HashMap<&'static str, Catalog>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        BUNDLES
+            .iter()
+            .map(|(language, json)| {
+                let catalog = serde_json::from_str(json).unwrap_or_else(|e| {
+                    error!("Ignoring the {} translations: {}", language, e);
+                    Catalog::new()
+                });
+                (*language, catalog)
+            })
+            .collect()
+    })
+}
+
+/// `de` for `de`, `de-AT` or `de_DE.UTF-8`
+fn primary(language: &str) -> &str {
+    language.split(['-', '_', '.']).next().unwrap_or(language)
+}
+
+/// Languages with a bundle, the fallback first
+pub fn languages() -> Vec<&'static str> {
+    BUNDLES.iter().map(|(language, _)| *language).collect()
+}
+
+pub fn is_supported(language: &str) -> bool {
+    catalogs().contains_key(primary(language))
+}
+
+fn find(language: &str, key: &str) -> Option<&'static str> {
+    catalogs()
+        .get(primary(language))?
+        .get(key)
+        .map(String::as_str)
+}
+
+/// Look up `key` in `language`, falling back to English and then to the key itself
+pub fn lookup(language: &str, key: &str) -> String {
+    find(language, key)
+        .or_else(|| find(FALLBACK_LANGUAGE, key))
+        .unwrap_or(key)
+        .to_string()
+}
+
+/// Render a message template, substituting `{name}` placeholders
+pub fn render(language: &str, key: &str, params: &[(&str, String)]) -> String {
+    params
+        .iter()
+        .fold(lookup(language, key), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+/// Render an error's user message in `language`
+pub fn render_error(language: &str, message: &ErrorMessage) -> String {
+    render(language, message.key, &message.params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{DiskError, Error};
+
+    #[test]
+    fn test_lookup_fallback() {
+        assert_eq!(lookup("de", "install"), "Installieren");
+        assert_eq!(
+            lookup("de", "error.api.failed"),
+            lookup("en", "error.api.failed")
+        );
+        assert_eq!(lookup("fr", "cancel"), "Cancel");
+        assert_eq!(lookup("en", "unknown.key"), "unknown.key");
+    }
+
+    #[test]
+    fn test_render_error() {
+        let err: Error = DiskError::DiskNotFound("/dev/sdz".to_string()).into();
+        assert_eq!(
+            render_error("en", &err.user_message()),
+            "Disk /dev/sdz was not found"
+        );
+        assert_eq!(
+            render_error("de", &err.user_message()),
+            "Datenträger /dev/sdz wurde nicht gefunden"
+        );
+    }
+
+    #[test]
+    fn test_bundles() {
+        let catalogs = catalogs();
+        assert_eq!(catalogs.len(), BUNDLES.len());
+        let english = &catalogs[FALLBACK_LANGUAGE];
+        let placeholders = |text: &str| {
+            let mut names: Vec<String> = text
+                .split('{')
+                .skip(1)
+                .filter_map(|rest| rest.split_once('}').map(|(name, _)| name.to_string()))
+                .collect();
+            names.sort();
+            names
+        };
+        for (language, catalog) in catalogs {
+            assert!(!catalog.is_empty(), "{} bundle is empty", language);
+            for (key, text) in catalog {
+                let Some(original) = english.get(key) else {
+                    panic!("{} has {}, which English lacks", language, key);
+                };
+                assert_eq!(placeholders(text), placeholders(original), "{}", key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_language_tags() {
+        assert!(is_supported("de_DE.UTF-8"));
+        assert!(!is_supported("fr"));
+        assert_eq!(lookup("de-AT", "cancel"), "Abbrechen");
+        assert_eq!(languages()[0], FALLBACK_LANGUAGE);
+    }
+}
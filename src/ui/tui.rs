@@ -0,0 +1,827 @@
+use super::installer_gui::{GuiEventType, GuiSnapshot, GuiState, InstallerGui};
+use super::messages;
+use super::wizard::{WizardAction, WizardState, WizardStep};
+use crate::monitoring::AlertSeverity;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::{cursor, execute};
+use nix::sys::signal::{raise, Signal};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+use std::collections::HashMap;
+use std::io::{self, Stdout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+/// Longest wait for a key before the screen is redrawn
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Log lines kept for the log panel, more than any terminal shows
+const LOG_LINES: usize = 200;
+
+type ConsoleTerminal = Terminal<CrosstermBackend<Stdout>>;
+
+/// Full-screen text interface on the local terminal, for nodes without a
+/// display. It draws the [`InstallerGui`] state and turns key presses into
+/// install wizard steps and [`GuiEventType::KeyPress`] events, like a
+/// graphical frontend would.
+pub struct ConsoleTui {
+    stop: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ConsoleTui {
+    /// Take over the terminal; fails when it cannot be put in raw mode
+    pub fn start(gui: Arc<InstallerGui>) -> io::Result<Self> {
+        let terminal = enter()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let runtime = Handle::current();
+        let task = tokio::task::spawn_blocking({
+            let stop = stop.clone();
+            move || {
+                let _restore = Restore;
+                if let Err(e) = run(terminal, &gui, &runtime, &stop) {
+                    error!("Console TUI stopped: {}", e);
+                }
+            }
+        });
+        Ok(Self {
+            stop,
+            task: Some(task),
+        })
+    }
+
+    /// Stop drawing and give the terminal back
+    pub async fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+fn enter() -> io::Result<ConsoleTerminal> {
+    enable_raw_mode()?;
+    if let Err(e) = execute!(io::stdout(), EnterAlternateScreen, cursor::Hide) {
+        let _ = disable_raw_mode();
+        return Err(e);
+    }
+    Terminal::new(CrosstermBackend::new(io::stdout()))
+}
+
+/// Restores the terminal however the draw loop ends, panics included
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+        let _ = disable_raw_mode();
+    }
+}
+
+fn run(
+    mut terminal: ConsoleTerminal,
+    gui: &InstallerGui,
+    runtime: &Handle,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let mut view = TuiView::default();
+    while !stop.load(Ordering::Relaxed) {
+        let snapshot = runtime.block_on(gui.snapshot(LOG_LINES));
+        terminal.draw(|frame| draw(frame, &snapshot, &mut view))?;
+
+        if !event::poll(REFRESH)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let data = HashMap::from([("key".to_string(), key_name(key.code))]);
+        if let Err(e) = runtime.block_on(gui.send_event(GuiEventType::KeyPress, data)) {
+            debug!("Dropped key press: {}", e);
+        }
+        match view.handle_key(key, &snapshot) {
+            // Raw mode swallows Ctrl-C; shut down as if it had not
+            Some(Action::Interrupt) => {
+                if let Err(e) = raise(Signal::SIGINT) {
+                    error!("Failed to interrupt the node: {}", e);
+                }
+            }
+            Some(action) => runtime.block_on(apply(gui, action)),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Wizard(WizardAction),
+    /// Switch to this language
+    Language(String),
+    Interrupt,
+}
+
+async fn apply(gui: &InstallerGui, action: Action) {
+    let result = match action {
+        Action::Wizard(action) => gui.wizard_action(action).await.map(|_| ()),
+        Action::Language(language) => gui.set_language(&language).await,
+        Action::Interrupt => return,
+    };
+    if let Err(e) = result {
+        gui.add_log(format!("ERROR: {}", gui.localize_error(&e).await))
+            .await;
+    }
+}
+
+/// The language after `current`, wrapping around
+fn next_language(current: &str) -> String {
+    let languages = messages::languages();
+    let index = languages.iter().position(|l| *l == current).unwrap_or(0);
+    languages[(index + 1) % languages.len()].to_string()
+}
+
+fn key_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::Char(c) => c.to_string(),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Panel {
+    #[default]
+    Images,
+    Devices,
+}
+
+/// Focus, cursors and the confirmation being typed; everything else is
+/// drawn from the snapshot
+#[derive(Debug, Default)]
+struct TuiView {
+    focus: Panel,
+    images: ListState,
+    devices: ListState,
+    typed: String,
+    /// The access QR code covers the screen
+    access: bool,
+}
+
+impl TuiView {
+    fn handle_key(&mut self, key: KeyEvent, snapshot: &GuiSnapshot) -> Option<Action> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Action::Interrupt);
+        }
+        if key.code == KeyCode::F(2) {
+            return Some(Action::Language(next_language(&snapshot.language)));
+        }
+        if key.code == KeyCode::F(3) || (self.access && key.code == KeyCode::Esc) {
+            self.access = !self.access;
+            return None;
+        }
+        if self.access {
+            return None;
+        }
+        let action = match (snapshot.wizard.step, key.code) {
+            (WizardStep::Review, KeyCode::Enter) => WizardAction::Accept,
+            (WizardStep::Review | WizardStep::Confirm, KeyCode::Esc) => {
+                self.typed.clear();
+                WizardAction::Back
+            }
+            (WizardStep::Confirm, KeyCode::Char(c)) => {
+                self.typed.push(c);
+                return None;
+            }
+            (WizardStep::Confirm, KeyCode::Backspace) => {
+                self.typed.pop();
+                return None;
+            }
+            (WizardStep::Confirm, KeyCode::Enter) => WizardAction::Confirm {
+                typed: std::mem::take(&mut self.typed),
+            },
+            (WizardStep::Review | WizardStep::Confirm | WizardStep::Progress, _) => return None,
+            (WizardStep::Result, KeyCode::Enter) => WizardAction::Reset,
+            (WizardStep::Result, _) => return None,
+            (_, KeyCode::Esc) => WizardAction::Back,
+            _ => return self.pick(key, snapshot),
+        };
+        Some(Action::Wizard(action))
+    }
+
+    /// Keys of the image and device pickers
+    fn pick(&mut self, key: KeyEvent, snapshot: &GuiSnapshot) -> Option<Action> {
+        let (list, len) = match self.focus {
+            Panel::Images => (&mut self.images, snapshot.images.len()),
+            Panel::Devices => (&mut self.devices, snapshot.devices.len()),
+        };
+        match key.code {
+            KeyCode::Tab | KeyCode::BackTab | KeyCode::Left | KeyCode::Right => {
+                self.focus = match self.focus {
+                    Panel::Images => Panel::Devices,
+                    Panel::Devices => Panel::Images,
+                };
+                None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                step(list, len, false);
+                None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                step(list, len, true);
+                None
+            }
+            KeyCode::Enter => {
+                let index = list.selected()?;
+                let action = match self.focus {
+                    Panel::Images => {
+                        let path = snapshot.images.get(index)?.path.clone();
+                        self.focus = Panel::Devices;
+                        WizardAction::SelectImage { path }
+                    }
+                    Panel::Devices => WizardAction::SelectDevice {
+                        path: snapshot.devices.get(index)?.path.clone(),
+                    },
+                };
+                Some(Action::Wizard(action))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn step(list: &mut ListState, len: usize, down: bool) {
+    if len == 0 {
+        list.select(None);
+        return;
+    }
+    let index = match (list.selected(), down) {
+        (None, _) => 0,
+        (Some(index), true) => (index + 1).min(len - 1),
+        (Some(index), false) => index.saturating_sub(1),
+    };
+    list.select(Some(index));
+}
+
+/// Keep the cursor on the list after it shrank
+fn clamp(list: &mut ListState, len: usize) {
+    match list.selected() {
+        Some(_) if len == 0 => list.select(None),
+        Some(index) if index >= len => list.select(Some(len - 1)),
+        None if len > 0 => list.select(Some(0)),
+        _ => {}
+    }
+}
+
+fn draw(frame: &mut Frame, snapshot: &GuiSnapshot, view: &mut TuiView) {
+    let banner_height = if snapshot.banner.is_some() { 3 } else { 0 };
+    let [banner, header, pickers, progress, bottom, help] = Layout::vertical([
+        Constraint::Length(banner_height),
+        Constraint::Length(1),
+        Constraint::Min(6),
+        Constraint::Length(3),
+        Constraint::Min(6),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    if let Some(text) = &snapshot.banner {
+        let style = Style::default()
+            .fg(Color::White)
+            .bg(Color::Red)
+            .add_modifier(Modifier::BOLD);
+        frame.render_widget(
+            Paragraph::new(text.as_str())
+                .style(style)
+                .block(Block::default().borders(Borders::ALL)),
+            banner,
+        );
+    }
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::styled(
+                "USB Installer Node",
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" - "),
+            Span::raw(state_text(&snapshot.state)),
+            Span::raw(" - "),
+            Span::raw(step_text(&snapshot.language, &snapshot.wizard)),
+        ])),
+        header,
+    );
+
+    let [images, devices] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(pickers);
+    draw_images(frame, images, snapshot, view);
+    draw_devices(frame, devices, snapshot, view);
+    draw_wizard(frame, pickers, snapshot, view);
+    draw_progress(frame, progress, snapshot);
+
+    let [logs, side] =
+        Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(bottom);
+    let [jobs, alerts] =
+        Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(side);
+    draw_logs(frame, logs, snapshot);
+    draw_jobs(frame, jobs, snapshot);
+    draw_alerts(frame, alerts, snapshot);
+
+    if view.access {
+        let area = frame.area();
+        let above_help = Rect {
+            height: area.height.saturating_sub(1),
+            ..area
+        };
+        draw_access(frame, above_help, snapshot);
+    }
+
+    let keys = match snapshot.wizard.step {
+        _ if view.access => "tui.keys.access",
+        WizardStep::SelectImage | WizardStep::SelectDevice => "tui.keys.pick",
+        WizardStep::Review => "tui.keys.review",
+        WizardStep::Confirm => "tui.keys.confirm",
+        WizardStep::Progress => "tui.keys.progress",
+        WizardStep::Result => "tui.keys.result",
+    };
+    frame.render_widget(
+        Paragraph::new(messages::lookup(&snapshot.language, keys))
+            .style(Style::default().fg(Color::DarkGray)),
+        help,
+    );
+}
+
+fn step_text(language: &str, wizard: &WizardState) -> String {
+    let device = wizard
+        .device
+        .as_ref()
+        .map(|device| device.path.clone())
+        .unwrap_or_default();
+    match wizard.step {
+        WizardStep::SelectImage => messages::lookup(language, "wizard.step.select_image"),
+        WizardStep::SelectDevice => messages::lookup(language, "wizard.step.select_device"),
+        WizardStep::Review | WizardStep::Confirm => {
+            messages::render(language, "wizard.step.review", &[("device", device)])
+        }
+        WizardStep::Progress => {
+            messages::render(language, "wizard.step.progress", &[("device", device)])
+        }
+        WizardStep::Result if wizard.success == Some(true) => {
+            messages::lookup(language, "wizard.result.success")
+        }
+        WizardStep::Result => messages::lookup(language, "wizard.result.failed"),
+    }
+}
+
+/// What the install erases over the pickers, until it is confirmed, and
+/// how it ended
+fn draw_wizard(frame: &mut Frame, area: Rect, snapshot: &GuiSnapshot, view: &TuiView) {
+    let (language, wizard) = (&snapshot.language, &snapshot.wizard);
+    let red = Style::default().fg(Color::Red);
+    let mut lines: Vec<Line> = Vec::new();
+    let title = match wizard.step {
+        WizardStep::Review | WizardStep::Confirm => {
+            lines.extend(
+                wizard
+                    .summary
+                    .iter()
+                    .map(|line| Line::styled(line.as_str(), red)),
+            );
+            if wizard.step == WizardStep::Confirm {
+                let name = wizard.confirm_name.clone().unwrap_or_default();
+                let prompt = messages::render(language, "wizard.step.confirm", &[("name", name)]);
+                lines.push(Line::raw(""));
+                lines.push(Line::from(vec![
+                    Span::raw(format!("{}: ", prompt)),
+                    Span::styled(
+                        format!("{}_", view.typed),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                ]));
+            }
+            step_text(language, wizard)
+        }
+        WizardStep::Result => {
+            let style = if wizard.success == Some(true) {
+                Style::default().fg(Color::Green)
+            } else {
+                red
+            };
+            let message = wizard.message.clone().unwrap_or_default();
+            lines.push(Line::styled(message, style));
+            step_text(language, wizard)
+        }
+        _ => return,
+    };
+
+    let [_, popup, _] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(lines.len() as u16 + 2),
+        Constraint::Fill(1),
+    ])
+    .areas(area);
+    let [_, popup, _] = Layout::horizontal([
+        Constraint::Percentage(10),
+        Constraint::Percentage(80),
+        Constraint::Percentage(10),
+    ])
+    .areas(popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(panel(&title, true)),
+        popup,
+    );
+}
+
+/// Where to connect from a phone, as a QR code and as text, over
+/// everything else
+fn draw_access(frame: &mut Frame, area: Rect, snapshot: &GuiSnapshot) {
+    let language = &snapshot.language;
+    let mut lines: Vec<Line> = Vec::new();
+    match &snapshot.access {
+        Some(access) => {
+            match access.to_unicode() {
+                Ok(code) => lines.extend(code.lines().map(|line| Line::raw(line.to_string()))),
+                Err(e) => lines.push(Line::styled(e.to_string(), Style::default().fg(Color::Red))),
+            }
+            lines.push(Line::raw(""));
+            lines.extend(
+                access
+                    .payload()
+                    .lines()
+                    .map(|line| Line::raw(line.to_string())),
+            );
+        }
+        None => lines.push(Line::raw(messages::lookup(language, "access.unknown"))),
+    }
+
+    let title = messages::lookup(language, "access.title");
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(Color::White).bg(Color::Black))
+            .block(panel(&title, true)),
+        area,
+    );
+}
+
+fn state_text(state: &GuiState) -> String {
+    match state {
+        GuiState::Initializing => "starting".to_string(),
+        GuiState::Ready => "ready".to_string(),
+        GuiState::Installing => "installing".to_string(),
+        GuiState::Completed => "completed".to_string(),
+        GuiState::Failed(reason) => format!("failed: {}", reason),
+        GuiState::Crashed => "crashed".to_string(),
+    }
+}
+
+fn panel(title: &str, focused: bool) -> Block<'_> {
+    let style = if focused {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
+    Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_style(style)
+}
+
+fn picker<'a>(items: Vec<ListItem<'a>>, block: Block<'a>) -> List<'a> {
+    List::new(items)
+        .block(block)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ")
+}
+
+fn draw_images(frame: &mut Frame, area: Rect, snapshot: &GuiSnapshot, view: &mut TuiView) {
+    clamp(&mut view.images, snapshot.images.len());
+    let items = snapshot
+        .images
+        .iter()
+        .map(|image| {
+            let chosen = snapshot.selected_image.as_ref() == Some(&image.path);
+            ListItem::new(format!(
+                "{} {}",
+                if chosen { "*" } else { " " },
+                image.label
+            ))
+        })
+        .collect();
+    let title = messages::lookup(&snapshot.language, "tui.panel.images");
+    let block = panel(&title, view.focus == Panel::Images);
+    frame.render_stateful_widget(picker(items, block), area, &mut view.images);
+}
+
+fn draw_devices(frame: &mut Frame, area: Rect, snapshot: &GuiSnapshot, view: &mut TuiView) {
+    clamp(&mut view.devices, snapshot.devices.len());
+    let items = snapshot
+        .devices
+        .iter()
+        .map(|device| {
+            let chosen = snapshot.selected_device.as_ref() == Some(&device.path);
+            let text = format!(
+                "{} {} {}",
+                if chosen { "*" } else { " " },
+                device.path,
+                device.label
+            );
+            if device.in_use {
+                ListItem::new(format!("{} (in use)", text))
+                    .style(Style::default().fg(Color::DarkGray))
+            } else if !device.encryption.is_empty() {
+                ListItem::new(text).style(Style::default().fg(Color::Red))
+            } else {
+                ListItem::new(text)
+            }
+        })
+        .collect();
+    let title = messages::lookup(&snapshot.language, "tui.panel.devices");
+    let block = panel(&title, view.focus == Panel::Devices);
+    frame.render_stateful_widget(picker(items, block), area, &mut view.devices);
+}
+
+fn draw_progress(frame: &mut Frame, area: Rect, snapshot: &GuiSnapshot) {
+    let progress = &snapshot.progress;
+    let label = if progress.message.is_empty() {
+        format!("{}%", progress.percentage)
+    } else {
+        format!("{}% {}", progress.percentage, progress.message)
+    };
+    let color = match snapshot.state {
+        GuiState::Failed(_) | GuiState::Crashed => Color::Red,
+        GuiState::Completed => Color::Green,
+        _ => Color::Cyan,
+    };
+    frame.render_widget(
+        Gauge::default()
+            .block(panel(&progress.current_step, false))
+            .gauge_style(Style::default().fg(color))
+            .percent(progress.percentage.min(100) as u16)
+            .label(label),
+        area,
+    );
+}
+
+fn draw_logs(frame: &mut Frame, area: Rect, snapshot: &GuiSnapshot) {
+    let visible = area.height.saturating_sub(2) as usize;
+    let skip = snapshot.logs.len().saturating_sub(visible);
+    let lines: Vec<Line> = snapshot.logs[skip..]
+        .iter()
+        .map(|line| {
+            let style = if line.contains("ERROR:") {
+                Style::default().fg(Color::Red)
+            } else if line.contains("WARNING:") || line.contains("BANNER:") {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+            Line::styled(line.as_str(), style)
+        })
+        .collect();
+    let title = messages::lookup(&snapshot.language, "tui.panel.log");
+    frame.render_widget(Paragraph::new(lines).block(panel(&title, false)), area);
+}
+
+fn draw_jobs(frame: &mut Frame, area: Rect, snapshot: &GuiSnapshot) {
+    let items: Vec<ListItem> = snapshot
+        .jobs
+        .iter()
+        .map(|job| ListItem::new(format!("{} [{}]", job.label, job.status)))
+        .collect();
+    let title = messages::lookup(&snapshot.language, "tui.panel.jobs");
+    frame.render_widget(List::new(items).block(panel(&title, false)), area);
+}
+
+fn draw_alerts(frame: &mut Frame, area: Rect, snapshot: &GuiSnapshot) {
+    let items: Vec<ListItem> = snapshot
+        .alerts
+        .iter()
+        .map(|alert| {
+            let style = match alert.severity {
+                AlertSeverity::Info => Style::default().fg(Color::Blue),
+                AlertSeverity::Warning => Style::default().fg(Color::Yellow),
+                AlertSeverity::Error => Style::default().fg(Color::Red),
+                AlertSeverity::Critical => {
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+                }
+            };
+            ListItem::new(format!("{}: {}", alert.module, alert.message)).style(style)
+        })
+        .collect();
+    let title = messages::lookup(&snapshot.language, "tui.panel.alerts");
+    frame.render_widget(List::new(items).block(panel(&title, false)), area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::access::AccessInfo;
+    use crate::ui::installer_gui::{AlertEntry, DeviceChoice, ImageChoice};
+    use ratatui::backend::TestBackend;
+
+    async fn snapshot() -> GuiSnapshot {
+        let gui = InstallerGui::default();
+        gui.set_images(vec![ImageChoice {
+            path: "/installers/debian-12.iso".to_string(),
+            label: "Debian 12 (x86_64)".to_string(),
+        }])
+        .await;
+        gui.set_devices(vec![
+            DeviceChoice {
+                path: "/dev/sda".to_string(),
+                label: "System disk".to_string(),
+                removable: false,
+                in_use: true,
+                encryption: Vec::new(),
+            },
+            DeviceChoice {
+                path: "/dev/sdb".to_string(),
+                label: "Ultra (usb, 15.4 GB)".to_string(),
+                removable: true,
+                in_use: false,
+                encryption: Vec::new(),
+            },
+        ])
+        .await;
+        gui.set_alerts(vec![AlertEntry {
+            severity: AlertSeverity::Warning,
+            module: "thermal".to_string(),
+            message: "SoC is throttled".to_string(),
+        }])
+        .await;
+        gui.set_banner(Some("Safe mode: vnc failed".to_string()))
+            .await;
+        gui.snapshot(LOG_LINES).await
+    }
+
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[tokio::test]
+    async fn test_keys_select() {
+        let snapshot = snapshot().await;
+        let mut view = TuiView::default();
+
+        assert_eq!(view.handle_key(press(KeyCode::Enter), &snapshot), None);
+        view.handle_key(press(KeyCode::Down), &snapshot);
+        assert_eq!(
+            view.handle_key(press(KeyCode::Enter), &snapshot),
+            Some(Action::Wizard(WizardAction::SelectImage {
+                path: "/installers/debian-12.iso".to_string()
+            }))
+        );
+
+        assert_eq!(view.focus, Panel::Devices);
+        view.handle_key(press(KeyCode::Down), &snapshot);
+        view.handle_key(press(KeyCode::Down), &snapshot);
+        view.handle_key(press(KeyCode::Down), &snapshot);
+        assert_eq!(
+            view.handle_key(press(KeyCode::Enter), &snapshot),
+            Some(Action::Wizard(WizardAction::SelectDevice {
+                path: "/dev/sdb".to_string()
+            }))
+        );
+
+        assert_eq!(
+            view.handle_key(press(KeyCode::F(2)), &snapshot),
+            Some(Action::Language("de".to_string()))
+        );
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(view.handle_key(ctrl_c, &snapshot), Some(Action::Interrupt));
+    }
+
+    #[tokio::test]
+    async fn test_draw() {
+        let snapshot = snapshot().await;
+        let mut view = TuiView::default();
+        let mut terminal = Terminal::new(TestBackend::new(100, 32)).unwrap();
+        terminal
+            .draw(|frame| draw(frame, &snapshot, &mut view))
+            .unwrap();
+
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        for text in [
+            "Safe mode: vnc failed",
+            "Debian 12 (x86_64)",
+            "/dev/sda System disk (in use)",
+            "thermal: SoC is throttled",
+        ] {
+            assert!(screen.contains(text), "{} not on screen", text);
+        }
+        assert_eq!(view.images.selected(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_access() {
+        let mut snapshot = snapshot().await;
+        snapshot.access = Some(AccessInfo {
+            node_url: "http://192.168.1.40:8080".to_string(),
+            web_vnc_url: Some("http://192.168.1.40:6080/vnc.html".to_string()),
+            ssh: None,
+            ssh_fingerprint: None,
+        });
+        let mut view = TuiView::default();
+        assert_eq!(view.handle_key(press(KeyCode::F(3)), &snapshot), None);
+        // The wizard gets no keys while the code is shown
+        assert_eq!(view.handle_key(press(KeyCode::Enter), &snapshot), None);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 40)).unwrap();
+        terminal
+            .draw(|frame| draw(frame, &snapshot, &mut view))
+            .unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("http://192.168.1.40:6080/vnc.html"));
+        assert!(screen.contains('▀'));
+        assert!(!screen.contains("Debian 12 (x86_64)"));
+
+        view.handle_key(press(KeyCode::Esc), &snapshot);
+        assert!(!view.access);
+    }
+
+    #[tokio::test]
+    async fn test_keys_confirm() {
+        let gui = InstallerGui::default();
+        gui.set_images(vec![ImageChoice {
+            path: "/installers/debian-12.iso".to_string(),
+            label: "Debian 12 (x86_64)".to_string(),
+        }])
+        .await;
+        gui.set_devices(vec![DeviceChoice {
+            path: "/dev/sdb".to_string(),
+            label: "Ultra (usb, 15.4 GB)".to_string(),
+            removable: true,
+            in_use: false,
+            encryption: Vec::new(),
+        }])
+        .await;
+        for action in [
+            WizardAction::SelectImage {
+                path: "/installers/debian-12.iso".to_string(),
+            },
+            WizardAction::SelectDevice {
+                path: "/dev/sdb".to_string(),
+            },
+            WizardAction::Accept,
+        ] {
+            gui.wizard_action(action).await.unwrap();
+        }
+        let snapshot = gui.snapshot(LOG_LINES).await;
+        let mut view = TuiView::default();
+
+        for c in "sdx".chars() {
+            assert_eq!(view.handle_key(press(KeyCode::Char(c)), &snapshot), None);
+        }
+        view.handle_key(press(KeyCode::Backspace), &snapshot);
+        view.handle_key(press(KeyCode::Char('b')), &snapshot);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 32)).unwrap();
+        terminal
+            .draw(|frame| draw(frame, &snapshot, &mut view))
+            .unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Type sdb to erase it: sdb_"));
+
+        assert_eq!(
+            view.handle_key(press(KeyCode::Enter), &snapshot),
+            Some(Action::Wizard(WizardAction::Confirm {
+                typed: "sdb".to_string()
+            }))
+        );
+        assert!(view.typed.is_empty());
+    }
+}
@@ -0,0 +1,436 @@
+use super::access::QrModules;
+use super::installer_gui::{DeviceChoice, GuiConfig, GuiSnapshot, InstallerGui};
+use super::messages;
+use super::wizard::{WizardAction, WizardState, WizardStep};
+use eframe::egui::{self, Color32, RichText};
+use std::panic::AssertUnwindSafe;
+use std::sync::{mpsc, OnceLock};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use winit::platform::x11::EventLoopBuilderExtX11;
+
+/// Longest time between redraws, so progress moves without input
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Height of buttons and list rows, big enough for a fingertip
+const TOUCH_ROW: f32 = 56.0;
+
+/// Log lines under the progress bar
+const LOG_LINES: usize = 8;
+
+/// Edge of the access QR code, quiet zone included
+const QR_SIZE: f32 = 320.0;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// How a window ended: closed, or the error it failed with
+pub(super) type WindowResult = std::result::Result<(), String>;
+
+/// Open the installer window. `opened` fires once it is on screen; the
+/// returned receiver gets how it ended.
+pub(super) fn open(
+    gui: InstallerGui,
+    config: GuiConfig,
+    generation: u64,
+    opened: Option<oneshot::Sender<()>>,
+) -> oneshot::Receiver<WindowResult> {
+    let runtime = Handle::current();
+    let (done_tx, done_rx) = oneshot::channel();
+    let job: Job = Box::new(move || {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            run(gui, config, generation, runtime, opened)
+        }))
+        .unwrap_or_else(|_| Err("window panicked".to_string()));
+        let _ = done_tx.send(result);
+    });
+    // A failed send drops the job and with it `done_tx`, which tells the
+    // receiver
+    let _ = window_thread().send(job);
+    done_rx
+}
+
+/// winit allows one event loop per process, which eframe keeps in a
+/// thread-local to reuse, so every window opens on this one thread
+fn window_thread() -> &'static mpsc::Sender<Job> {
+    static THREAD: OnceLock<mpsc::Sender<Job>> = OnceLock::new();
+    THREAD.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<Job>();
+        std::thread::Builder::new()
+            .name("installer-window".to_string())
+            .spawn(move || {
+                for job in rx {
+                    job();
+                }
+            })
+            .expect("failed to spawn the window thread");
+        tx
+    })
+}
+
+fn run(
+    gui: InstallerGui,
+    config: GuiConfig,
+    generation: u64,
+    runtime: Handle,
+    opened: Option<oneshot::Sender<()>>,
+) -> WindowResult {
+    let viewport = egui::ViewportBuilder::default()
+        .with_title(config.window_title.clone())
+        .with_inner_size([config.width as f32, config.height as f32])
+        .with_fullscreen(config.fullscreen);
+    let options = eframe::NativeOptions {
+        viewport,
+        // Not the main thread; on Wayland as well as X11
+        event_loop_builder: Some(Box::new(|builder| {
+            builder.with_any_thread(true);
+        })),
+        ..Default::default()
+    };
+    let title = config.window_title.clone();
+    eframe::run_native(
+        &title,
+        options,
+        Box::new(move |cc| {
+            if let Some(opened) = opened {
+                let _ = opened.send(());
+            }
+            Ok(Box::new(InstallerWindow::new(
+                cc, gui, &config, generation, runtime,
+            )))
+        }),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Install wizard for small touchscreens: pick an image, pick the target
+/// disk, review what is erased, type the device name, watch the progress
+struct InstallerWindow {
+    gui: InstallerGui,
+    runtime: Handle,
+    generation: u64,
+    /// Device name typed in the confirm step
+    typed: String,
+    error: Option<String>,
+    /// Of the latest snapshot
+    language: String,
+    /// The access QR code is shown instead of the wizard
+    show_access: bool,
+}
+
+impl InstallerWindow {
+    fn new(
+        cc: &eframe::CreationContext<'_>,
+        gui: InstallerGui,
+        config: &GuiConfig,
+        generation: u64,
+        runtime: Handle,
+    ) -> Self {
+        let ctx = &cc.egui_ctx;
+        ctx.set_visuals(if config.theme == "light" {
+            egui::Visuals::light()
+        } else {
+            egui::Visuals::dark()
+        });
+        let mut style = (*ctx.style()).clone();
+        for font in style.text_styles.values_mut() {
+            font.size *= 1.5;
+        }
+        style.spacing.button_padding = egui::vec2(16.0, 12.0);
+        style.spacing.item_spacing = egui::vec2(12.0, 12.0);
+        style.spacing.interact_size.y = TOUCH_ROW;
+        ctx.set_style(style);
+
+        Self {
+            gui,
+            runtime,
+            generation,
+            typed: String::new(),
+            error: None,
+            language: config.language.clone(),
+            show_access: false,
+        }
+    }
+
+    fn text(&self, key: &str) -> String {
+        messages::lookup(&self.language, key)
+    }
+
+    fn render(&self, key: &str, params: &[(&str, String)]) -> String {
+        messages::render(&self.language, key, params)
+    }
+
+    /// Move the wizard on, showing a refused action until the next one
+    /// succeeds
+    fn act(&mut self, action: WizardAction) {
+        let gui = &self.gui;
+        self.error = self.runtime.block_on(async {
+            match gui.wizard_action(action).await {
+                Ok(_) => None,
+                Err(e) => Some(gui.localize_error(&e).await),
+            }
+        });
+    }
+
+    fn back_button(&mut self, ui: &mut egui::Ui) {
+        let back = egui::Button::new(self.text("wizard.back"));
+        if ui.add_sized([TOUCH_ROW * 3.0, TOUCH_ROW], back).clicked() {
+            self.typed.clear();
+            self.act(WizardAction::Back);
+        }
+    }
+
+    fn image_step(&mut self, ui: &mut egui::Ui, snapshot: &GuiSnapshot) {
+        ui.heading(self.text("wizard.step.select_image"));
+        if snapshot.images.is_empty() {
+            ui.label(self.text("wizard.no_images"));
+        }
+        let mut chosen = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for image in &snapshot.images {
+                let button = egui::Button::new(&image.label)
+                    .selected(snapshot.selected_image.as_ref() == Some(&image.path));
+                if ui
+                    .add_sized([ui.available_width(), TOUCH_ROW], button)
+                    .clicked()
+                {
+                    chosen = Some(image.path.clone());
+                }
+            }
+        });
+        if let Some(path) = chosen {
+            self.act(WizardAction::SelectImage { path });
+        }
+    }
+
+    fn device_step(&mut self, ui: &mut egui::Ui, snapshot: &GuiSnapshot) {
+        ui.heading(self.text("wizard.step.select_device"));
+        if snapshot.devices.is_empty() {
+            ui.label(self.text("wizard.no_devices"));
+        }
+        let mut chosen = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for device in &snapshot.devices {
+                let text = format!("{} {}", device.path, device.label);
+                let clicked = ui
+                    .add_enabled_ui(!device.in_use, |ui| {
+                        ui.add_sized([ui.available_width(), TOUCH_ROW], egui::Button::new(text))
+                    })
+                    .inner
+                    .clicked();
+                self.warnings(ui, device);
+                if clicked {
+                    chosen = Some(device.path.clone());
+                }
+            }
+        });
+        self.back_button(ui);
+        if let Some(path) = chosen {
+            self.act(WizardAction::SelectDevice { path });
+        }
+    }
+
+    fn review_step(&mut self, ui: &mut egui::Ui, wizard: &WizardState) {
+        let path = wizard.device.as_ref().map(|d| d.path.clone());
+        ui.heading(self.render(
+            "wizard.step.review",
+            &[("device", path.unwrap_or_default())],
+        ));
+        for line in &wizard.summary {
+            ui.colored_label(Color32::RED, line);
+        }
+        ui.horizontal(|ui| {
+            self.back_button(ui);
+            let next = egui::Button::new(self.text("wizard.continue"));
+            if ui.add_sized([TOUCH_ROW * 3.0, TOUCH_ROW], next).clicked() {
+                self.act(WizardAction::Accept);
+            }
+        });
+    }
+
+    fn confirm_step(&mut self, ui: &mut egui::Ui, wizard: &WizardState) {
+        let name = wizard.confirm_name.clone().unwrap_or_default();
+        ui.heading(self.render("wizard.step.confirm", &[("name", name.clone())]));
+        for line in &wizard.summary {
+            ui.colored_label(Color32::RED, line);
+        }
+        ui.add_sized(
+            [ui.available_width(), TOUCH_ROW],
+            egui::TextEdit::singleline(&mut self.typed).hint_text(name.as_str()),
+        );
+
+        let mut install = false;
+        ui.horizontal(|ui| {
+            self.back_button(ui);
+            let label = RichText::new(self.text("wizard.erase")).color(Color32::WHITE);
+            let erase = egui::Button::new(label).fill(Color32::DARK_RED);
+            install = ui
+                .add_enabled_ui(!self.typed.trim().is_empty(), |ui| {
+                    ui.add_sized([TOUCH_ROW * 5.0, TOUCH_ROW], erase)
+                })
+                .inner
+                .clicked();
+        });
+        if install {
+            let typed = std::mem::take(&mut self.typed);
+            self.act(WizardAction::Confirm { typed });
+        }
+    }
+
+    fn progress_step(&self, ui: &mut egui::Ui, snapshot: &GuiSnapshot) {
+        let wizard = &snapshot.wizard;
+        let device = wizard.device.as_ref().map(|d| d.path.clone());
+        let job = wizard
+            .job_id
+            .as_ref()
+            .and_then(|id| snapshot.jobs.iter().find(|job| &job.id == id));
+        match job {
+            Some(job) => ui.heading(format!(
+                "{} ({})",
+                self.render(
+                    "wizard.step.progress",
+                    &[("device", device.unwrap_or_default())]
+                ),
+                job.status
+            )),
+            None => ui.heading(self.text("wizard.step.starting")),
+        };
+
+        let progress = &snapshot.progress;
+        ui.label(&progress.current_step);
+        ui.add(
+            egui::ProgressBar::new(progress.percentage.min(100) as f32 / 100.0)
+                .text(format!("{}% {}", progress.percentage, progress.message)),
+        );
+        for line in &snapshot.logs {
+            ui.monospace(line);
+        }
+    }
+
+    fn result_step(&mut self, ui: &mut egui::Ui, wizard: &WizardState) {
+        let message = wizard.message.as_deref().unwrap_or_default();
+        if wizard.success == Some(true) {
+            ui.heading(RichText::new(self.text("wizard.result.success")).color(Color32::GREEN));
+        } else {
+            ui.heading(RichText::new(self.text("wizard.result.failed")).color(Color32::RED));
+        }
+        ui.label(message);
+        let done = egui::Button::new(self.text("wizard.done"));
+        if ui.add_sized([TOUCH_ROW * 3.0, TOUCH_ROW], done).clicked() {
+            self.act(WizardAction::Reset);
+        }
+    }
+
+    /// What overwriting `device` puts at risk
+    fn warnings(&self, ui: &mut egui::Ui, device: &DeviceChoice) {
+        if device.in_use {
+            ui.colored_label(Color32::GRAY, self.text("wizard.device.in_use"));
+        }
+        if !device.removable {
+            ui.colored_label(Color32::YELLOW, self.text("wizard.device.not_removable"));
+        }
+        if !device.encryption.is_empty() {
+            let kinds: Vec<String> = device.encryption.iter().map(|k| k.to_string()).collect();
+            ui.colored_label(
+                Color32::RED,
+                self.render("wizard.device.encrypted", &[("kinds", kinds.join(", "))]),
+            );
+        }
+    }
+
+    /// Where to connect from a phone, as a QR code and as text
+    fn access_view(&self, ui: &mut egui::Ui, snapshot: &GuiSnapshot) {
+        ui.heading(self.text("access.title"));
+        let Some(access) = &snapshot.access else {
+            ui.label(self.text("access.unknown"));
+            return;
+        };
+        match access.modules() {
+            Ok(modules) => qr_code(ui, &modules),
+            Err(e) => {
+                ui.colored_label(Color32::RED, e.to_string());
+            }
+        }
+        for line in access.payload().lines() {
+            ui.monospace(line);
+        }
+    }
+
+    /// One button per language, the current one selected, and the access
+    /// QR code toggle
+    fn language_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let connect = egui::Button::new(self.text("access.show")).selected(self.show_access);
+            if ui
+                .add_sized([TOUCH_ROW * 3.0, TOUCH_ROW], connect)
+                .clicked()
+            {
+                self.show_access = !self.show_access;
+            }
+            ui.label(self.text("wizard.language"));
+            for language in messages::languages() {
+                let button = egui::Button::new(language).selected(self.language == language);
+                if ui.add_sized([TOUCH_ROW, TOUCH_ROW], button).clicked() {
+                    let result = self.runtime.block_on(self.gui.set_language(language));
+                    self.error = result.err().map(|e| e.to_string());
+                }
+            }
+        });
+    }
+}
+
+impl eframe::App for InstallerWindow {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if !self.gui.is_current(self.generation) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            return;
+        }
+        let snapshot = self.runtime.block_on(self.gui.snapshot(LOG_LINES));
+        self.language = snapshot.language.clone();
+
+        if let Some(banner) = &snapshot.banner {
+            let frame = egui::Frame::default()
+                .fill(Color32::DARK_RED)
+                .inner_margin(8.0);
+            egui::TopBottomPanel::top("banner")
+                .frame(frame)
+                .show(ctx, |ui| {
+                    ui.label(RichText::new(banner).color(Color32::WHITE).strong());
+                });
+        }
+        egui::TopBottomPanel::bottom("language").show(ctx, |ui| self.language_bar(ui));
+        if let Some(error) = self.error.clone() {
+            egui::TopBottomPanel::bottom("error").show(ctx, |ui| {
+                ui.colored_label(Color32::RED, error);
+            });
+        }
+        egui::CentralPanel::default().show(ctx, |ui| match snapshot.wizard.step {
+            _ if self.show_access => self.access_view(ui, &snapshot),
+            WizardStep::SelectImage => self.image_step(ui, &snapshot),
+            WizardStep::SelectDevice => self.device_step(ui, &snapshot),
+            WizardStep::Review => self.review_step(ui, &snapshot.wizard),
+            WizardStep::Confirm => self.confirm_step(ui, &snapshot.wizard),
+            WizardStep::Progress => self.progress_step(ui, &snapshot),
+            WizardStep::Result => self.result_step(ui, &snapshot.wizard),
+        });
+        ctx.request_repaint_after(REFRESH);
+    }
+}
+
+/// Dark modules on white, inside the quiet zone of four modules scanners
+/// look for
+fn qr_code(ui: &mut egui::Ui, modules: &QrModules) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(QR_SIZE, QR_SIZE), egui::Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 0.0, Color32::WHITE);
+    let unit = QR_SIZE / (modules.width + 8) as f32;
+    for y in 0..modules.width {
+        for x in 0..modules.width {
+            if modules.is_dark(x, y) {
+                let min = rect.min + egui::vec2((x + 4) as f32 * unit, (y + 4) as f32 * unit);
+                let module = egui::Rect::from_min_size(min, egui::vec2(unit, unit));
+                painter.rect_filled(module, 0.0, Color32::BLACK);
+            }
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use super::installer_gui::{DeviceChoice, ImageChoice};
+use super::messages;
+use crate::error::{Result, UiError};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Step of the install wizard
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WizardStep {
+    #[default]
+    SelectImage,
+    SelectDevice,
+    /// What the install destroys, to be accepted
+    Review,
+    /// The device name has to be typed
+    Confirm,
+    Progress,
+    Result,
+}
+
+/// What a frontend shows for the wizard
+#[derive(Debug, Clone, Serialize)]
+pub struct WizardState {
+    pub step: WizardStep,
+    pub image: Option<ImageChoice>,
+    pub device: Option<DeviceChoice>,
+    /// What the install destroys, once a device is chosen
+    pub summary: Vec<String>,
+    /// Name to type in the confirm step
+    pub confirm_name: Option<String>,
+    /// Install job of the progress and result steps
+    pub job_id: Option<String>,
+    /// Set in the result step
+    pub success: Option<bool>,
+    pub message: Option<String>,
+}
+
+/// Input from a frontend
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum WizardAction {
+    SelectImage {
+        path: String,
+    },
+    SelectDevice {
+        path: String,
+    },
+    /// Accept the review
+    Accept,
+    /// Confirm with the device name typed out
+    Confirm {
+        typed: String,
+    },
+    Back,
+    /// Start over; not while an install runs
+    Reset,
+}
+
+/// An install the user confirmed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallRequest {
+    pub image: String,
+    pub device: String,
+}
+
+/// Guided install flow: image, target disk, review of what is erased,
+/// the device name typed out, progress and result. The console, the
+/// touchscreen and the REST API all drive this one state machine, so each
+/// asks for the same confirmations before a disk is overwritten.
+#[derive(Debug, Clone, Default)]
+pub struct Wizard {
+    step: WizardStep,
+    image: Option<ImageChoice>,
+    device: Option<DeviceChoice>,
+    job_id: Option<String>,
+    result: Option<(bool, String)>,
+}
+
+impl Wizard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn step(&self) -> WizardStep {
+        self.step
+    }
+
+    /// What to show, with the summary in `language`
+    pub fn state(&self, language: &str) -> WizardState {
+        WizardState {
+            step: self.step,
+            image: self.image.clone(),
+            device: self.device.clone(),
+            summary: self
+                .device
+                .as_ref()
+                .map(|device| summary(language, self.image.as_ref(), device))
+                .unwrap_or_default(),
+            confirm_name: self.device.as_ref().map(|d| device_name(&d.path)),
+            job_id: self.job_id.clone(),
+            success: self.result.as_ref().map(|(success, _)| *success),
+            message: self.result.as_ref().map(|(_, message)| message.clone()),
+        }
+    }
+
+    /// Move on with `action`, checked against the current image and
+    /// device lists. Returns the install to queue once it is confirmed.
+    pub fn apply(
+        &mut self,
+        action: WizardAction,
+        images: &[ImageChoice],
+        devices: &[DeviceChoice],
+    ) -> Result<Option<InstallRequest>> {
+        use WizardStep::*;
+
+        match action {
+            WizardAction::SelectImage { path } => {
+                self.expect(&[SelectImage, SelectDevice, Review, Confirm])?;
+                let image = images
+                    .iter()
+                    .find(|image| image.path == path)
+                    .ok_or_else(|| UiError::InputError(format!("Unknown image: {}", path)))?;
+                self.image = Some(image.clone());
+                self.step = SelectDevice;
+            }
+            WizardAction::SelectDevice { path } => {
+                self.expect(&[SelectDevice, Review, Confirm])?;
+                self.device = Some(available(devices, &path)?.clone());
+                self.step = Review;
+            }
+            WizardAction::Accept => {
+                self.expect(&[Review])?;
+                self.step = Confirm;
+            }
+            WizardAction::Confirm { typed } => {
+                self.expect(&[Confirm])?;
+                let (Some(image), Some(device)) = (&self.image, &self.device) else {
+                    return Err(UiError::WrongWizardStep(format!("{:?}", self.step)).into());
+                };
+                // The disk may have been mounted or unplugged since
+                let device = match available(devices, &device.path) {
+                    Ok(device) => device.clone(),
+                    Err(e) => {
+                        self.device = None;
+                        self.step = SelectDevice;
+                        return Err(e);
+                    }
+                };
+                let typed = typed.trim();
+                if typed != device.path && typed != device_name(&device.path) {
+                    return Err(UiError::ConfirmationMismatch(device_name(&device.path)).into());
+                }
+                let request = InstallRequest {
+                    image: image.path.clone(),
+                    device: device.path.clone(),
+                };
+                self.device = Some(device);
+                self.step = Progress;
+                return Ok(Some(request));
+            }
+            WizardAction::Back => {
+                self.step = match self.step {
+                    SelectImage | SelectDevice => SelectImage,
+                    Review => SelectDevice,
+                    Confirm => Review,
+                    Progress => {
+                        return Err(UiError::WrongWizardStep(format!("{:?}", self.step)).into())
+                    }
+                    Result => {
+                        *self = Self::new();
+                        return Ok(None);
+                    }
+                };
+            }
+            WizardAction::Reset => {
+                self.expect(&[SelectImage, SelectDevice, Review, Confirm, Result])?;
+                *self = Self::new();
+            }
+        }
+        Ok(None)
+    }
+
+    /// The confirmed install was queued as `job_id`
+    pub fn started(&mut self, job_id: String) {
+        if self.step == WizardStep::Progress {
+            self.job_id = Some(job_id);
+        }
+    }
+
+    /// The install job `job_id` ended, or with `None`, could not be queued
+    pub fn finished(&mut self, job_id: Option<&str>, success: bool, message: String) {
+        if self.step == WizardStep::Progress && self.job_id.as_deref() == job_id {
+            self.result = Some((success, message));
+            self.step = WizardStep::Result;
+        }
+    }
+
+    fn expect(&self, steps: &[WizardStep]) -> Result<()> {
+        if steps.contains(&self.step) {
+            Ok(())
+        } else {
+            Err(UiError::WrongWizardStep(format!("{:?}", self.step)).into())
+        }
+    }
+}
+
+fn available<'a>(devices: &'a [DeviceChoice], path: &str) -> Result<&'a DeviceChoice> {
+    let device = devices
+        .iter()
+        .find(|device| device.path == path)
+        .ok_or_else(|| UiError::InputError(format!("Unknown device: {}", path)))?;
+    if device.in_use {
+        return Err(UiError::InputError(format!("Device {} is in use", path)).into());
+    }
+    Ok(device)
+}
+
+/// `sdb` for `/dev/sdb`
+fn device_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn summary(language: &str, image: Option<&ImageChoice>, device: &DeviceChoice) -> Vec<String> {
+    let path = ("device", device.path.clone());
+    let mut lines = vec![messages::render(
+        language,
+        "wizard.summary.erase",
+        &[("label", device.label.clone()), path.clone()],
+    )];
+    if !device.removable {
+        lines.push(messages::render(
+            language,
+            "wizard.summary.not_removable",
+            &[path.clone()],
+        ));
+    }
+    if !device.encryption.is_empty() {
+        let kinds: Vec<String> = device.encryption.iter().map(|k| k.to_string()).collect();
+        lines.push(messages::render(
+            language,
+            "wizard.summary.encrypted",
+            &[("kinds", kinds.join(", ")), path],
+        ));
+    }
+    if let Some(image) = image {
+        lines.push(messages::render(
+            language,
+            "wizard.summary.image",
+            &[("image", image.label.clone())],
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::encryption::EncryptionKind;
+
+    fn images() -> Vec<ImageChoice> {
+        vec![ImageChoice {
+            path: "/installers/debian-12.iso".to_string(),
+            label: "Debian 12 (x86_64)".to_string(),
+        }]
+    }
+
+    fn devices(in_use: bool) -> Vec<DeviceChoice> {
+        vec![DeviceChoice {
+            path: "/dev/sdb".to_string(),
+            label: "Ultra (usb, 15.4 GB)".to_string(),
+            removable: true,
+            in_use,
+            encryption: vec![EncryptionKind::BitLocker],
+        }]
+    }
+
+    fn select(wizard: &mut Wizard) {
+        let (images, devices) = (images(), devices(false));
+        let image = WizardAction::SelectImage {
+            path: images[0].path.clone(),
+        };
+        let device = WizardAction::SelectDevice {
+            path: "/dev/sdb".to_string(),
+        };
+        wizard.apply(image, &images, &devices).unwrap();
+        wizard.apply(device, &images, &devices).unwrap();
+    }
+
+    #[test]
+    fn test_confirmation_gates() {
+        let (images, devices) = (images(), devices(false));
+        let mut wizard = Wizard::new();
+        let early = WizardAction::Confirm {
+            typed: "sdb".to_string(),
+        };
+        assert!(wizard.apply(early, &images, &devices).is_err());
+
+        select(&mut wizard);
+        let state = wizard.state("en");
+        assert_eq!(state.step, WizardStep::Review);
+        assert_eq!(state.confirm_name.as_deref(), Some("sdb"));
+        assert!(state.summary[1].contains("BitLocker encrypted data"));
+        assert_eq!(
+            wizard.state("de").summary[2],
+            "Debian 12 (x86_64) wird darauf geschrieben"
+        );
+
+        wizard
+            .apply(WizardAction::Accept, &images, &devices)
+            .unwrap();
+        let wrong = WizardAction::Confirm {
+            typed: "sda".to_string(),
+        };
+        assert!(wizard.apply(wrong, &images, &devices).is_err());
+        assert_eq!(wizard.step(), WizardStep::Confirm);
+
+        let typed = WizardAction::Confirm {
+            typed: " sdb ".to_string(),
+        };
+        let request = wizard.apply(typed, &images, &devices).unwrap();
+        assert_eq!(
+            request,
+            Some(InstallRequest {
+                image: "/installers/debian-12.iso".to_string(),
+                device: "/dev/sdb".to_string(),
+            })
+        );
+        assert!(wizard
+            .apply(WizardAction::Reset, &images, &devices)
+            .is_err());
+
+        wizard.started("job-1".to_string());
+        wizard.finished(Some("job-2"), true, "Completed".to_string());
+        assert_eq!(wizard.step(), WizardStep::Progress);
+        wizard.finished(Some("job-1"), true, "Completed".to_string());
+        assert_eq!(wizard.state("en").success, Some(true));
+
+        wizard.apply(WizardAction::Back, &images, &devices).unwrap();
+        assert_eq!(wizard.step(), WizardStep::SelectImage);
+        assert!(wizard.state("en").image.is_none());
+    }
+
+    #[test]
+    fn test_device_gone_before_confirm() {
+        let images = images();
+        let mut wizard = Wizard::new();
+        select(&mut wizard);
+        wizard
+            .apply(WizardAction::Accept, &images, &devices(false))
+            .unwrap();
+
+        let typed = WizardAction::Confirm {
+            typed: "/dev/sdb".to_string(),
+        };
+        assert!(wizard.apply(typed, &images, &devices(true)).is_err());
+        assert_eq!(wizard.step(), WizardStep::SelectDevice);
+        assert!(wizard.state("en").device.is_none());
+    }
+}